@@ -1,19 +1,19 @@
-/// Benchmarks comparing AST interpreter vs Bytecode VM performance
-///
-/// This file contains benchmarks for various PohLang operations to measure
-/// the performance improvement gained by using the bytecode VM instead of
-/// the traditional AST-walking interpreter.
-///
-/// Expected Results:
-/// - Bytecode VM should be 5-15x faster than AST interpreter
-/// - Arithmetic operations: ~10x speedup
-/// - Loop execution: ~15x speedup  
-/// - Function calls: ~8x speedup
-/// - Variable access: ~12x speedup
+//! Benchmarks comparing AST interpreter vs Bytecode VM performance
+//!
+//! This file contains benchmarks for various PohLang operations to measure
+//! the performance improvement gained by using the bytecode VM instead of
+//! the traditional AST-walking interpreter.
+//!
+//! Expected Results:
+//! - Bytecode VM should be 5-15x faster than AST interpreter
+//! - Arithmetic operations: ~10x speedup
+//! - Loop execution: ~15x speedup  
+//! - Function calls: ~8x speedup
+//! - Variable access: ~12x speedup
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 use pohlang::bytecode::{BytecodeVM, Compiler, Value};
 use pohlang::parser::ast::{CmpOp, Expr, Stmt};
-use pohlang::{bytecode, parser, vm};
+use pohlang::vm;
 
 // ============================================================================
 // Helper Functions
@@ -41,7 +41,7 @@ fn run_ast(program: Vec<Stmt>) {
 fn create_arithmetic_program(iterations: usize) -> Vec<Stmt> {
     let mut stmts = Vec::new();
 
-    for i in 0..iterations {
+    for _ in 0..iterations {
         // ((5 * 3) + (10 / 2)) - 2 = (15 + 5) - 2 = 18
         stmts.push(Stmt::Write(Expr::Minus(
             Box::new(Expr::Plus(
@@ -213,15 +213,15 @@ fn create_string_program(iterations: usize) -> Vec<Stmt> {
 
     stmts.push(Stmt::Set {
         name: "text".to_string(),
-        value: Expr::StringLit("Hello ".to_string()),
+        value: Expr::Str("Hello ".to_string()),
     });
 
     for i in 0..iterations {
         stmts.push(Stmt::Set {
             name: format!("msg{}", i),
-            value: Expr::StringConcat(
+            value: Expr::Plus(
                 Box::new(Expr::Ident("text".to_string())),
-                Box::new(Expr::StringLit("World!".to_string())),
+                Box::new(Expr::Str("World!".to_string())),
             ),
         });
     }
@@ -265,7 +265,7 @@ fn create_mixed_program(iterations: usize) -> Vec<Stmt> {
     });
     stmts.push(Stmt::Set {
         name: "text".to_string(),
-        value: Expr::StringLit("Result: ".to_string()),
+        value: Expr::Str("Result: ".to_string()),
     });
 
     for i in 0..iterations {
@@ -331,6 +331,7 @@ criterion_group!(
     bench_arithmetic,
     bench_variables,
     bench_conditionals,
+    bench_strings,
     bench_mixed
 );
 
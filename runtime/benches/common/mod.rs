@@ -0,0 +1,144 @@
+//! Reproducible inputs for the benchmarks
+//!
+//! `ProgramGenerator` produces synthetic PohLang source from a seed, so the
+//! same seed always yields the same program and timings stay comparable
+//! across commits. Generated programs are meant to be parsed, not run: loop
+//! conditions are random and may never become false.
+
+/// Seeded generator for synthetic PohLang programs
+pub struct ProgramGenerator {
+    state: u64,
+}
+
+/// Variables every generated program defines up front
+const VARIABLES: usize = 8;
+
+impl ProgramGenerator {
+    pub fn new(seed: u64) -> Self {
+        // xorshift needs a non-zero state
+        ProgramGenerator {
+            state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        // xorshift64*
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn pick<'a>(&mut self, options: &[&'a str]) -> &'a str {
+        options[self.below(options.len() as u64) as usize]
+    }
+
+    fn variable(&mut self) -> String {
+        format!("v{}", self.below(VARIABLES as u64))
+    }
+
+    fn atom(&mut self) -> String {
+        match self.below(4) {
+            0 => self.below(1000).to_string(),
+            1 => format!("\"s{}\"", self.below(100)),
+            _ => self.variable(),
+        }
+    }
+
+    fn expr(&mut self, depth: u32) -> String {
+        if depth == 0 {
+            return self.atom();
+        }
+        match self.below(6) {
+            0 => format!("({})", self.expr(depth - 1)),
+            1 => format!(
+                "total of Make a list of {}, {}, {}",
+                self.atom(),
+                self.atom(),
+                self.atom()
+            ),
+            2 => format!("count of {}", self.variable()),
+            _ => {
+                let op = self.pick(&["plus", "minus", "times", "divided by", "+", "*"]);
+                format!("{} {} {}", self.expr(depth - 1), op, self.expr(depth - 1))
+            }
+        }
+    }
+
+    fn condition(&mut self) -> String {
+        let cmp = self.pick(&["is less than", "is greater than", "is equal to", ">=", "!="]);
+        let cond = format!("{} {} {}", self.expr(1), cmp, self.atom());
+        match self.below(3) {
+            0 => format!("{} and {} is not {}", cond, self.variable(), self.atom()),
+            1 => format!("{} or not {}", cond, self.variable()),
+            _ => cond,
+        }
+    }
+
+    fn statement(&mut self, out: &mut String, indent: usize, depth: u32) {
+        let pad = "    ".repeat(indent);
+        let nested = depth > 0;
+        match self.below(if nested { 6 } else { 3 }) {
+            0 | 1 => {
+                let name = self.variable();
+                let value = self.expr(3);
+                out.push_str(&format!("{}Set {} to {}\n", pad, name, value));
+            }
+            2 => {
+                let value = self.expr(2);
+                out.push_str(&format!("{}Write {}\n", pad, value));
+            }
+            3 => {
+                let cond = self.condition();
+                out.push_str(&format!("{}If {}\n", pad, cond));
+                self.block(out, indent + 1, depth - 1);
+                out.push_str(&format!("{}Otherwise\n", pad));
+                self.block(out, indent + 1, depth - 1);
+                out.push_str(&format!("{}End\n", pad));
+            }
+            4 => {
+                let cond = self.condition();
+                out.push_str(&format!("{}While {}\n", pad, cond));
+                self.block(out, indent + 1, depth - 1);
+                out.push_str(&format!("{}End\n", pad));
+            }
+            _ => {
+                let count = self.below(10) + 1;
+                out.push_str(&format!("{}Repeat {} times\n", pad, count));
+                self.block(out, indent + 1, depth - 1);
+                out.push_str(&format!("{}End\n", pad));
+            }
+        }
+    }
+
+    fn block(&mut self, out: &mut String, indent: usize, depth: u32) {
+        for _ in 0..self.below(3) + 1 {
+            self.statement(out, indent, depth);
+        }
+    }
+
+    /// A program with roughly `statements` top-level statements, one in ten
+    /// of them a function definition
+    pub fn program(&mut self, statements: usize) -> String {
+        let mut out = String::from("Start Program\n");
+        for i in 0..VARIABLES {
+            out.push_str(&format!("Set v{} to {}\n", i, i));
+        }
+        for i in 0..statements {
+            if i % 10 == 9 {
+                out.push_str(&format!("Make f{} with v0, v1\n", i));
+                self.block(&mut out, 1, 2);
+                let result = self.expr(2);
+                out.push_str(&format!("    Return {}\nEnd\n", result));
+            } else {
+                self.statement(&mut out, 0, 2);
+            }
+        }
+        out.push_str("End Program\n");
+        out
+    }
+}
@@ -0,0 +1,88 @@
+//! Benchmarks for per-request route handler setup
+//!
+//! Runs a real PohLang web server in a background thread and measures a round trip
+//! to a handler that calls a user-defined function. The program carries a large
+//! number of globals so that any per-request copying of the program scope shows up.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use pohlang::{parser, vm};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+// ============================================================================
+// Helper Functions
+// ============================================================================
+
+/// Build a server program with `globals` extra variables and one route
+fn server_program(port: u16, globals: usize) -> String {
+    let mut src = String::from("Start Program\n");
+    for i in 0..globals {
+        src.push_str(&format!("Set padding_{} to {}\n", i, i));
+    }
+    src.push_str(&format!(
+        "Set server to create web server on port {}\n",
+        port
+    ));
+    src.push_str("Add route \"/greet\" with method \"GET\" to server:\n");
+    src.push_str("    Write html response with greet(\"Ada\")\n");
+    src.push_str("Make greet with name Write \"Hello \" plus name\n");
+    src.push_str("Start server\n");
+    src.push_str("End Program\n");
+    src
+}
+
+/// Start the server in a background thread; it runs until the process exits
+fn start_server(port: u16, globals: usize) {
+    let program = parser::parse(&server_program(port, globals)).expect("Parse failed");
+    thread::spawn(move || {
+        let mut vm = vm::Vm::default();
+        let _ = vm.execute(&program);
+    });
+}
+
+fn get(port: u16, path: &str) -> String {
+    for _ in 0..100 {
+        if let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)) {
+            write!(
+                stream,
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                path
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            return response;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("server on port {} never came up", port);
+}
+
+// ============================================================================
+// Benchmark: Handler Calling a User Function
+// ============================================================================
+
+fn bench_handler(c: &mut Criterion) {
+    let mut group = c.benchmark_group("http_handler");
+
+    for (offset, globals) in [10usize, 1000, 10000].into_iter().enumerate() {
+        let port = 48200 + offset as u16;
+        start_server(port, globals);
+        assert!(get(port, "/greet").contains("Hello Ada"));
+
+        group.bench_with_input(BenchmarkId::new("globals", globals), &port, |b, &port| {
+            b.iter(|| get(port, "/greet"));
+        });
+    }
+
+    group.finish();
+}
+
+// ============================================================================
+// Benchmark Configuration
+// ============================================================================
+
+criterion_group!(benches, bench_handler);
+
+criterion_main!(benches);
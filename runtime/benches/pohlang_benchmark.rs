@@ -0,0 +1,236 @@
+//! End-to-end benchmarks for the parser, both VMs, JSON and templates
+//!
+//! Every input is either a fixed program or comes from the seeded generator in
+//! `common`, so numbers are comparable across commits. The bytecode compiler
+//! does not compile function bodies yet, so the bytecode group computes
+//! fib(25) with a loop; the AST group runs both forms for comparison.
+mod common;
+
+use common::ProgramGenerator;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pohlang::bytecode::{BytecodeChunk, BytecodeVM, Compiler};
+use pohlang::parser::{self, Program};
+#[cfg(feature = "web")]
+use pohlang::stdlib::template;
+use pohlang::vm;
+use serde_json::json;
+
+// ============================================================================
+// Programs
+// ============================================================================
+
+const FIB_RECURSIVE: &str = "Start Program
+Make fib with n
+    If n is less than 2
+        Return n
+    End
+    Return fib(n minus 1) plus fib(n minus 2)
+End
+Set result to fib(25)
+End Program
+";
+
+const FIB_ITERATIVE: &str = "Start Program
+Set a to 0
+Set b to 1
+Set i to 0
+While i is less than 25
+    Set next to a plus b
+    Set a to b
+    Set b to next
+    Set i to i plus 1
+End
+End Program
+";
+
+const LOOP_1M: &str = "Start Program
+Set i to 0
+Set total to 0
+While i is less than 1000000
+    Set total to total plus i
+    Set i to i plus 1
+End
+End Program
+";
+
+const EACH_IN_RANGE_1M: &str = "Start Program
+Set total to 0
+For each i in range(1000000):
+    Set total to total plus i
+End For
+End Program
+";
+
+// ============================================================================
+// Helper Functions
+// ============================================================================
+
+fn parse(src: &str) -> Program {
+    parser::parse(src).expect("Parse failed")
+}
+
+fn run_ast(program: &Program) {
+    let mut vm = vm::Vm::default();
+    vm.execute(program).expect("Execution failed");
+}
+
+fn compile(src: &str) -> BytecodeChunk {
+    Compiler::new()
+        .compile(parse(src))
+        .expect("Compilation failed")
+}
+
+fn run_bytecode(chunk: &BytecodeChunk) {
+    let mut vm = BytecodeVM::new();
+    vm.load(chunk.clone());
+    vm.run().expect("Execution failed");
+}
+
+/// A JSON document nested `depth` levels deep, each level carrying a few
+/// scalars and a small list
+fn deep_json(depth: usize) -> serde_json::Value {
+    let mut value = json!({ "leaf": true });
+    for level in (0..depth).rev() {
+        value = json!({
+            "level": level,
+            "name": format!("node {}", level),
+            "tags": ["a", "b", "c"],
+            "child": value,
+        });
+    }
+    value
+}
+
+/// A program that parses `text` as JSON and, if `stringify` is set, turns the
+/// result back into a string
+fn json_program(text: &str, stringify: bool) -> String {
+    let mut src = format!(
+        "Start Program\nSet text to '{}'\nSet data to parse json from text\n",
+        text
+    );
+    if stringify {
+        src.push_str("Set out to convert to json data\n");
+    }
+    src.push_str("End Program\n");
+    src
+}
+
+// ============================================================================
+// Benchmark 1: Parsing
+// ============================================================================
+
+fn bench_parser(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parser");
+
+    for statements in [100usize, 1000] {
+        let src = ProgramGenerator::new(42).program(statements);
+        parse(&src);
+        group.throughput(Throughput::Bytes(src.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("generated_program", statements),
+            &src,
+            |b, src| b.iter(|| parser::parse(black_box(src))),
+        );
+    }
+
+    group.finish();
+}
+
+// ============================================================================
+// Benchmark 2: AST Interpreter
+// ============================================================================
+
+fn bench_ast_vm(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ast_vm");
+    group.sample_size(10);
+
+    for (name, src) in [
+        ("fib_25_recursive", FIB_RECURSIVE),
+        ("fib_25_iterative", FIB_ITERATIVE),
+        ("loop_1m", LOOP_1M),
+        ("each_in_range_1m", EACH_IN_RANGE_1M),
+    ] {
+        let program = parse(src);
+        group.bench_function(name, |b| b.iter(|| run_ast(&program)));
+    }
+
+    group.finish();
+}
+
+// ============================================================================
+// Benchmark 3: Bytecode VM
+// ============================================================================
+
+fn bench_bytecode_vm(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bytecode_vm");
+    group.sample_size(10);
+
+    for (name, src) in [("fib_25_iterative", FIB_ITERATIVE), ("loop_1m", LOOP_1M)] {
+        let chunk = compile(src);
+        group.bench_function(name, |b| b.iter(|| run_bytecode(&chunk)));
+    }
+
+    group.finish();
+}
+
+// ============================================================================
+// Benchmark 4: JSON
+// ============================================================================
+
+fn bench_json(c: &mut Criterion) {
+    let mut group = c.benchmark_group("json");
+    let text = deep_json(64).to_string();
+
+    for (name, stringify) in [("parse", false), ("parse_and_stringify", true)] {
+        let program = parse(&json_program(&text, stringify));
+        group.bench_function(name, |b| b.iter(|| run_ast(&program)));
+    }
+
+    group.finish();
+}
+
+// ============================================================================
+// Benchmark 5: Templates
+// ============================================================================
+
+#[cfg(feature = "web")]
+fn bench_templates(c: &mut Criterion) {
+    let mut group = c.benchmark_group("templates");
+    let page = "<h1>{{title}}</h1>{{#if signed_in}}<p>Welcome back, {{user.name}}</p>{{/if}}\
+                <ul>{{#each items}}<li>{{name}}: {{price}}</li>{{/each}}</ul>";
+
+    for items in [10usize, 100] {
+        let data = json!({
+            "title": "Catalogue",
+            "signed_in": true,
+            "user": { "name": "Ada" },
+            "items": (0..items)
+                .map(|i| json!({ "name": format!("item {}", i), "price": i * 3 }))
+                .collect::<Vec<_>>(),
+        });
+        template::render_full(page, &data).expect("Render failed");
+        group.bench_with_input(BenchmarkId::new("render_full", items), &data, |b, data| {
+            b.iter(|| template::render_full(black_box(page), data))
+        });
+    }
+
+    group.finish();
+}
+
+// ============================================================================
+// Benchmark Configuration
+// ============================================================================
+
+#[cfg(feature = "web")]
+criterion_group!(
+    benches,
+    bench_parser,
+    bench_ast_vm,
+    bench_bytecode_vm,
+    bench_json,
+    bench_templates
+);
+#[cfg(not(feature = "web"))]
+criterion_group!(benches, bench_parser, bench_ast_vm, bench_bytecode_vm, bench_json);
+
+criterion_main!(benches);
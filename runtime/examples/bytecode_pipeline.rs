@@ -1,6 +1,6 @@
 /// Complete Bytecode Pipeline Demo
 /// This demonstrates: AST → Bytecode Compilation → VM Execution
-use pohlang::bytecode::{BytecodeVM, Compiler, Value};
+use pohlang::bytecode::{BytecodeVM, Compiler};
 use pohlang::parser::ast::{CmpOp, Expr, Stmt};
 
 fn main() {
@@ -155,7 +155,7 @@ fn main() {
     println!("  - Stage 6: Benchmarks (verify 10x+ speedup)");
 }
 
-fn execute_program(name: &str, program: Vec<Stmt>) {
+fn execute_program(_name: &str, program: Vec<Stmt>) {
     println!("   Compiling...");
 
     // Compile
@@ -1,7 +1,7 @@
 // Bytecode Compiler Demo
 // This example shows how to compile PohLang AST to bytecode
 
-use pohlang::bytecode::{Compiler, Instruction};
+use pohlang::bytecode::Compiler;
 use pohlang::parser::ast::{CmpOp, Expr, Stmt};
 
 fn main() {
@@ -58,7 +58,7 @@ End Program
     println!("\n=== Benchmark Complete ===");
 }
 
-fn run_benchmark(name: &str, source: &str) {
+fn run_benchmark(_name: &str, source: &str) {
     // Parse the program
     let program = match parser::parse(source) {
         Ok(prog) => prog,
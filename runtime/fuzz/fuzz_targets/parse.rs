@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Any text, as a whole program and as a single expression, must parse or
+// give an error; a panic or a hang is a bug
+fuzz_target!(|src: &str| {
+    let _ = pohlang::parser::parse(src);
+    let _ = pohlang::parser::parser::parse_expr(src);
+});
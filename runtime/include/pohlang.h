@@ -0,0 +1,71 @@
+/*
+ * PohLang C API
+ *
+ * Build the runtime with the capi feature to get these functions:
+ *
+ *     cargo build --release --features capi
+ *
+ * then link against the pohlang library in target/release (libpohlang.so,
+ * libpohlang.dylib or pohlang.dll).
+ *
+ * Strings passed in are NUL-terminated UTF-8. Strings handed back are the
+ * caller's, freed with poh_string_free. Functions returning int return 0
+ * on success and 1 on failure. A PohVm is not safe to use from two threads
+ * at once.
+ */
+
+#ifndef POHLANG_H
+#define POHLANG_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A VM, holding program-scope variables between runs */
+typedef struct PohVm PohVm;
+
+/* Called with each line a program writes, without its line ending */
+typedef void (*PohOutputFn)(const char *line, void *user_data);
+
+/* A new VM, or NULL if one could not be made */
+PohVm *poh_vm_new(void);
+
+/* Free a VM from poh_vm_new; NULL is ignored */
+void poh_vm_free(PohVm *vm);
+
+/* Free a string from this library; NULL is ignored */
+void poh_string_free(char *s);
+
+/*
+ * Send each line programs on vm write to callback with user_data, instead
+ * of standard output. A NULL callback goes back to standard output.
+ */
+int poh_vm_set_output_callback(PohVm *vm, PohOutputFn callback, void *user_data);
+
+/*
+ * Parse and run a whole program, from Start Program to End Program.
+ * Variables it sets at the top level stay on vm for the next call. On
+ * failure, if out_error is not NULL, *out_error is set to the message,
+ * to be freed with poh_string_free; otherwise it is set to NULL.
+ */
+int poh_vm_execute(PohVm *vm, const char *source, char **out_error);
+
+/*
+ * Set a program-scope variable for the next program run on vm. Fails if
+ * name is a constant. A whole number is set as one.
+ */
+int poh_vm_set_global_string(PohVm *vm, const char *name, const char *value);
+int poh_vm_set_global_number(PohVm *vm, const char *name, double value);
+
+/*
+ * A program-scope variable or constant as JSON text, to be freed with
+ * poh_string_free; NULL if there is no such variable or its value has no
+ * JSON form.
+ */
+char *poh_vm_get_global_as_json(PohVm *vm, const char *name);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* POHLANG_H */
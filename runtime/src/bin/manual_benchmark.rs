@@ -1,8 +1,8 @@
+//! Simple manual benchmarks to quickly measure AST vs Bytecode performance
+//! Run with: cargo run --release --bin manual_benchmark
 use pohlang::bytecode::{BytecodeVM, Compiler};
 use pohlang::parser::ast::{Expr, Stmt};
-/// Simple manual benchmarks to quickly measure AST vs Bytecode performance
-/// Run with: cargo run --release --bin manual_benchmark
-use pohlang::{bytecode, parser, vm};
+use pohlang::{parser, vm};
 use std::time::Instant;
 
 fn run_bytecode(program: Vec<Stmt>) {
@@ -1,5 +1,5 @@
 use super::{BytecodeChunk, Constant, DebugInfo, Instruction};
-use crate::parser::ast::{CmpOp, Expr, Program, Stmt};
+use crate::parser::ast::{AskType, CmpOp, Expr, Program, Stmt};
 use std::collections::HashMap;
 
 /// Compiler error types
@@ -78,8 +78,12 @@ impl CompilerContext {
 pub struct Compiler {
     chunk: BytecodeChunk,
     context: CompilerContext,
+    /// `Set constant` values, inlined wherever their name is used
+    constants: HashMap<String, Expr>,
     current_line: u32,
     line_numbers: Vec<u32>,
+    /// Fail on what would otherwise be skipped or compiled to a placeholder
+    strict: bool,
 }
 
 impl Compiler {
@@ -88,13 +92,42 @@ impl Compiler {
         Self {
             chunk: BytecodeChunk::new(1),
             context: CompilerContext::new(),
+            constants: HashMap::new(),
             current_line: 1,
             line_numbers: Vec::new(),
+            strict: false,
+        }
+    }
+
+    /// With `strict`, a function definition, route handler, middleware or
+    /// import is an error instead of being left out of the bytecode
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Going on without `what`, which only strict compilation refuses
+    fn skip(&self, what: &str) -> CompileResult<()> {
+        match self.strict {
+            true => Err(CompilerError::Other(format!("{} not yet supported; it would be left out", what))),
+            false => Ok(()),
         }
     }
 
     /// Compile a program (list of statements) to bytecode
     pub fn compile(mut self, program: Program) -> CompileResult<BytecodeChunk> {
+        // Constants are known before the program runs, so they are collected
+        // first and a use before the `Set constant` line is inlined too
+        for stmt in &program {
+            if let Stmt::GlobalConst { name, value } = stmt {
+                if self.constants.insert(name.clone(), value.clone()).is_some() {
+                    return Err(CompilerError::Other(format!(
+                        "Set constant {}: {} is already a constant",
+                        name, name
+                    )));
+                }
+            }
+        }
         for stmt in program {
             self.compile_stmt(stmt)?;
         }
@@ -120,6 +153,7 @@ impl Compiler {
     }
 
     /// Set the current line number for subsequent emissions
+    #[allow(dead_code)] // Statements don't carry source lines yet
     fn set_line(&mut self, line: u32) {
         self.current_line = line;
     }
@@ -139,6 +173,23 @@ impl Compiler {
         self.chunk.code.len()
     }
 
+    /// Replace the value on top of the stack with 1 or 0 for its truth, as
+    /// `and` and `or` give; each jump in `to_false` lands on the 0
+    fn emit_truth(&mut self, mut to_false: Vec<usize>) -> CompileResult<()> {
+        to_false.push(self.current_offset());
+        self.emit(Instruction::JumpIfFalse(0)); // Placeholder
+        let one = self.add_constant(Constant::Integer(1))?;
+        self.emit(Instruction::LoadConst(one));
+        let end_jump = self.current_offset();
+        self.emit(Instruction::Jump(0)); // Placeholder
+        for jump in to_false {
+            self.patch_jump(jump)?;
+        }
+        let zero = self.add_constant(Constant::Integer(0))?;
+        self.emit(Instruction::LoadConst(zero));
+        self.patch_jump(end_jump)
+    }
+
     /// Patch a jump instruction at the given offset with the current position
     fn patch_jump(&mut self, offset: usize) -> CompileResult<()> {
         let jump_distance = self.current_offset() - offset - 1;
@@ -167,13 +218,38 @@ impl Compiler {
                 self.emit(Instruction::Print);
             }
 
-            Stmt::AskFor { var_name } => {
-                self.emit(Instruction::Input);
+            Stmt::AskFor { var_name, expect } => {
+                self.emit(match expect {
+                    AskType::Any => Instruction::Input,
+                    expect => Instruction::InputAs(expect),
+                });
                 let local_idx = self.context.define_local(var_name)?;
                 self.emit(Instruction::StoreLocal(local_idx));
             }
 
+            Stmt::SetItem { .. } => {
+                return Err(CompilerError::Other(
+                    "Setting a list or dictionary item not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::SetLocal { .. } => {
+                return Err(CompilerError::Other(
+                    "SetLocal is only produced for AST function bodies".to_string(),
+                ));
+            }
+
+            Stmt::GlobalConst { .. } => {
+                // Inlined at each use
+            }
+
             Stmt::Set { name, value } => {
+                if self.constants.contains_key(&name) {
+                    return Err(CompilerError::Other(format!(
+                        "Cannot change {}: it was set with Set constant",
+                        name
+                    )));
+                }
                 self.compile_expr(value)?;
 
                 // Check if variable exists, if not create it
@@ -186,6 +262,24 @@ impl Compiler {
                 self.emit(Instruction::StoreLocal(local_idx));
             }
 
+            Stmt::Destructure { .. } => {
+                return Err(CompilerError::Other(
+                    "Destructuring Set not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::PutEnvInScope { .. } => {
+                return Err(CompilerError::Other(
+                    "Load env vars not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::PrintTable { .. } => {
+                return Err(CompilerError::Other(
+                    "Print table not yet supported".to_string(),
+                ));
+            }
+
             Stmt::Return(expr_opt) => {
                 if let Some(expr) = expr_opt {
                     self.compile_expr(expr)?;
@@ -300,6 +394,14 @@ impl Compiler {
                 self.patch_jump(exit_jump)?;
             }
 
+            // Lowered as the While Not it stands for
+            Stmt::RepeatUntil { cond, body } => {
+                self.compile_stmt(Stmt::WhileBlock {
+                    cond: Expr::Not(Box::new(cond)),
+                    body,
+                })?;
+            }
+
             Stmt::RepeatBlock { count, body } => {
                 // Compile count expression
                 self.compile_expr(count)?;
@@ -312,7 +414,7 @@ impl Compiler {
 
                 // Load counter and check if > 0
                 self.emit(Instruction::LoadLocal(counter_idx));
-                let zero_idx = self.add_constant(Constant::Number(0.0))?;
+                let zero_idx = self.add_constant(Constant::Integer(0))?;
                 self.emit(Instruction::LoadConst(zero_idx));
                 self.emit(Instruction::Greater);
 
@@ -329,7 +431,7 @@ impl Compiler {
 
                 // Decrement counter
                 self.emit(Instruction::LoadLocal(counter_idx));
-                let one_idx = self.add_constant(Constant::Number(1.0))?;
+                let one_idx = self.add_constant(Constant::Integer(1))?;
                 self.emit(Instruction::LoadConst(one_idx));
                 self.emit(Instruction::Subtract);
                 self.emit(Instruction::StoreLocal(counter_idx));
@@ -342,7 +444,7 @@ impl Compiler {
                 self.patch_jump(exit_jump)?;
             }
 
-            Stmt::Use { name, args } => {
+            Stmt::Use { name, args, result_var } => {
                 // Compile arguments
                 let arg_count = args.len() as u8;
                 for arg in args {
@@ -361,6 +463,23 @@ impl Compiler {
                 }
 
                 self.emit(Instruction::Call(arg_count));
+                match result_var {
+                    Some(var) => {
+                        if self.constants.contains_key(&var) {
+                            return Err(CompilerError::Other(format!(
+                                "Cannot change {}: it was set with Set constant",
+                                var
+                            )));
+                        }
+                        let local_idx = match self.context.get_local(&var) {
+                            Some(idx) => idx,
+                            None => self.context.define_local(var)?,
+                        };
+                        self.emit(Instruction::StoreLocal(local_idx));
+                    }
+                    // Use is a statement, so the return value is dropped
+                    None => self.emit(Instruction::Pop),
+                }
             }
 
             Stmt::TryCatch {
@@ -421,7 +540,8 @@ impl Compiler {
                 self.emit(Instruction::Throw);
             }
 
-            Stmt::FuncInline { name, params, body } => {
+            Stmt::FuncInline { name, params: _, body } => {
+                self.skip("Function definitions")?;
                 // For now, we'll store the function as a constant
                 // In a full implementation, we'd compile it to a separate chunk
                 let fn_idx = self.context.define_local(name)?;
@@ -431,7 +551,8 @@ impl Compiler {
                 self.emit(Instruction::StoreLocal(fn_idx));
             }
 
-            Stmt::FuncBlock { name, params, body } => {
+            Stmt::FuncBlock { name, params: _, body: _ } => {
+                self.skip("Function definitions")?;
                 // For now, we'll skip function compilation
                 // In a full implementation, we'd compile it to a separate chunk
                 let fn_idx = self.context.define_local(name)?;
@@ -444,8 +565,9 @@ impl Compiler {
             Stmt::AddRoute {
                 path,
                 method,
-                handler,
+                ..
             } => {
+                self.skip("Route handlers")?;
                 // Compile path and method
                 self.compile_expr(path)?;
                 self.compile_expr(method)?;
@@ -455,31 +577,176 @@ impl Compiler {
                 self.emit(Instruction::AddRoute);
             }
 
+            Stmt::Route { .. } => {
+                return Err(CompilerError::Other(
+                    "Route blocks not yet supported".to_string(),
+                ));
+            }
+
             Stmt::AddMiddleware {
-                middleware_type,
-                config,
+                middleware_type: _,
+                config: _,
             } => {
                 // Middleware statements are not yet supported in bytecode compilation
                 // They execute at server setup time, not at runtime
                 // Skip for now
+                self.skip("Middleware")?;
+            }
+
+            Stmt::Respond(_) => {
+                return Err(CompilerError::Other(
+                    "Respond with not yet supported".to_string(),
+                ));
             }
 
             Stmt::StartServer => {
                 self.emit(Instruction::StartServer);
             }
 
-            Stmt::ImportLocal { path } => {
+            Stmt::Evaluate(expr) => {
+                self.compile_expr(expr)?;
+                self.emit(Instruction::Pop);
+            }
+
+            Stmt::Line(_) => {}
+
+            // Type names are only checked by the linter and the AST interpreter
+            Stmt::TypeAlias { .. } | Stmt::DeclareType { .. } => {}
+
+            Stmt::Schema { .. } => {
+                return Err(CompilerError::Other(
+                    "Schemas not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::OnShutdown { .. } => {
+                return Err(CompilerError::Other(
+                    "On shutdown not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::EventEmitter { .. } | Stmt::On { .. } | Stmt::Emit { .. } => {
+                return Err(CompilerError::Other("Event emitters not yet supported".to_string()));
+            }
+
+            Stmt::ReadLineLoop { .. } => {
+                return Err(CompilerError::Other("Reading stdin line by line not yet supported".to_string()));
+            }
+
+            Stmt::ForEach { .. } => {
+                return Err(CompilerError::Other("For each loops not yet supported".to_string()));
+            }
+
+            Stmt::Require { .. } => {
+                return Err(CompilerError::Other("require ... to match not yet supported".to_string()));
+            }
+
+            Stmt::Assert { .. } => {
+                return Err(CompilerError::Other("Assert not yet supported".to_string()));
+            }
+
+            Stmt::BeforeEachRoute { .. } | Stmt::AfterEachRoute { .. } => {
+                return Err(CompilerError::Other(
+                    "Route hooks not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::RouteGroup { .. } | Stmt::GroupMiddleware { .. } => {
+                return Err(CompilerError::Other(
+                    "Route groups not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::Benchmark { .. } => {
+                return Err(CompilerError::Other(
+                    "Benchmark blocks not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::Measure { .. } => {
+                return Err(CompilerError::Other(
+                    "Measure blocks not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::DataBinding { .. } => {
+                return Err(CompilerError::Other("Bind blocks not yet supported".to_string()));
+            }
+
+            Stmt::Retry { .. } => {
+                return Err(CompilerError::Other(
+                    "Retry blocks not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::Parallel { .. } => {
+                return Err(CompilerError::Other(
+                    "Parallel blocks not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::SendEvent { .. } | Stmt::CloseStream(_) => {
+                return Err(CompilerError::Other("Event streams not yet supported".to_string()));
+            }
+
+            Stmt::SendMessage { .. } => {
+                return Err(CompilerError::Other("Websockets not yet supported".to_string()));
+            }
+
+            Stmt::Task { .. } | Stmt::Background { .. } | Stmt::AwaitAll { .. } | Stmt::AwaitAny { .. } => {
+                return Err(CompilerError::Other("Tasks not yet supported".to_string()));
+            }
+
+            Stmt::Checkpoint { .. } | Stmt::RestoreCheckpoint { .. } => {
+                return Err(CompilerError::Other("Checkpoints not yet supported".to_string()));
+            }
+
+            Stmt::Log { .. } | Stmt::LogMessage { .. } => {
+                return Err(CompilerError::Other("Log not yet supported".to_string()));
+            }
+
+            Stmt::MultiWrite { .. } => {
+                return Err(CompilerError::Other("Writing several values not yet supported".to_string()));
+            }
+
+            Stmt::EnableMetrics { .. } => {
+                return Err(CompilerError::Other(
+                    "Metrics not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::ServeStaticFiles(_) => {
+                return Err(CompilerError::Other(
+                    "Static files not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::TemplateDirectory(_) => {
+                return Err(CompilerError::Other(
+                    "Template directories not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::RateLimit { .. } => {
+                return Err(CompilerError::Other(
+                    "Rate limit not yet supported".to_string(),
+                ));
+            }
+
+            Stmt::ImportLocal { path: _ } | Stmt::ImportAs { path: _, alias: _ } | Stmt::ImportItems { .. } => {
                 // For now, we'll skip imports
                 // In a full implementation, we'd load the module
+                self.skip("Imports")?;
             }
 
             Stmt::ImportSystem {
-                name,
-                alias,
-                exposing,
+                name: _,
+                alias: _,
+                exposing: _,
             } => {
                 // For now, we'll skip imports
                 // In a full implementation, we'd load the system module
+                self.skip("Imports")?;
             }
         }
 
@@ -493,6 +760,11 @@ impl Compiler {
     fn compile_expr(&mut self, expr: Expr) -> CompileResult<()> {
         match expr {
             // Literals
+            Expr::Int(i) => {
+                let idx = self.add_constant(Constant::Integer(i))?;
+                self.emit(Instruction::LoadConst(idx));
+            }
+
             Expr::Num(n) => {
                 let idx = self.add_constant(Constant::Number(n))?;
                 self.emit(Instruction::LoadConst(idx));
@@ -515,7 +787,9 @@ impl Compiler {
 
             // Variables
             Expr::Ident(name) => {
-                if let Some(idx) = self.context.get_local(&name) {
+                if let Some(value) = self.constants.get(&name) {
+                    self.compile_expr(value.clone())?;
+                } else if let Some(idx) = self.context.get_local(&name) {
                     self.emit(Instruction::LoadLocal(idx));
                 } else {
                     return Err(CompilerError::UndefinedVariable(name));
@@ -548,16 +822,27 @@ impl Compiler {
             }
 
             // Logical operations
+            // The right side only runs when the left does not decide it
             Expr::And(left, right) => {
                 self.compile_expr(*left)?;
+                let left_false = self.current_offset();
+                self.emit(Instruction::JumpIfFalse(0)); // Placeholder
                 self.compile_expr(*right)?;
-                self.emit(Instruction::And);
+                self.emit_truth(vec![left_false])?;
             }
 
             Expr::Or(left, right) => {
                 self.compile_expr(*left)?;
+                let left_false = self.current_offset();
+                self.emit(Instruction::JumpIfFalse(0)); // Placeholder
+                let one = self.add_constant(Constant::Integer(1))?;
+                self.emit(Instruction::LoadConst(one));
+                let end_jump = self.current_offset();
+                self.emit(Instruction::Jump(0)); // Placeholder
+                self.patch_jump(left_false)?;
                 self.compile_expr(*right)?;
-                self.emit(Instruction::Or);
+                self.emit_truth(Vec::new())?;
+                self.patch_jump(end_jump)?;
             }
 
             Expr::Not(expr) => {
@@ -669,7 +954,20 @@ impl Compiler {
             }
 
             // Web framework operations
-            Expr::CreateWebServer(port) => {
+            Expr::CreateWebServer {
+                port,
+                body_limit,
+                request_timeout,
+                tls,
+            } => {
+                if body_limit.is_some() || request_timeout.is_some() {
+                    return Err(CompilerError::Other(
+                        "Web server body limits and request timeouts are not supported in bytecode yet".to_string(),
+                    ));
+                }
+                if tls.is_some() {
+                    return Err(CompilerError::Other("Secure web servers are not supported in bytecode yet".to_string()));
+                }
                 self.compile_expr(*port)?;
                 self.emit(Instruction::CreateWebServer);
             }
@@ -756,4 +1054,42 @@ mod tests {
         // Should have: LoadConst(42), StoreLocal(0), LoadLocal(0), Print, Return
         assert_eq!(chunk.instruction_count(), 5);
     }
+
+    #[test]
+    fn test_constants_are_inlined() {
+        let program = vec![
+            Stmt::Write(Expr::Ident("limit".to_string())),
+            Stmt::GlobalConst {
+                name: "limit".to_string(),
+                value: Expr::Int(100),
+            },
+            Stmt::Write(Expr::Ident("limit".to_string())),
+        ];
+        let chunk = Compiler::new().compile(program).unwrap();
+        // LoadConst, Print twice, then Return; no local is stored or loaded
+        assert_eq!(chunk.instruction_count(), 5);
+        assert!(chunk.code.iter().all(|i| !matches!(i, Instruction::LoadLocal(_) | Instruction::StoreLocal(_))));
+
+        let reassigned = vec![
+            Stmt::GlobalConst {
+                name: "limit".to_string(),
+                value: Expr::Int(100),
+            },
+            Stmt::Set {
+                name: "limit".to_string(),
+                value: Expr::Int(5),
+            },
+        ];
+        assert!(Compiler::new().compile(reassigned).is_err());
+    }
+
+    #[test]
+    fn test_strict_refuses_what_would_be_left_out() {
+        let program = vec![Stmt::ImportLocal {
+            path: "helpers.poh".to_string(),
+        }];
+        assert_eq!(Compiler::new().compile(program.clone()).unwrap().instruction_count(), 1);
+        let err = Compiler::new().strict(true).compile(program).unwrap_err();
+        assert_eq!(err.to_string(), "Imports not yet supported; it would be left out");
+    }
 }
@@ -8,6 +8,9 @@ use std::collections::HashMap;
 /// A constant value in the constant pool
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Constant {
+    /// Whole number
+    Integer(i64),
+
     /// Floating-point number
     Number(f64),
 
@@ -25,6 +28,7 @@ impl Constant {
     /// Returns the size in bytes when serialized
     pub fn size(&self) -> usize {
         match self {
+            Constant::Integer(_) => 9,          // 1 byte tag + 8 bytes i64
             Constant::Number(_) => 9,           // 1 byte tag + 8 bytes f64
             Constant::Boolean(_) => 2,          // 1 byte tag + 1 byte bool
             Constant::Null => 1,                // 1 byte tag only
@@ -55,6 +59,7 @@ impl ConstantPool {
     pub fn add_constant(&mut self, constant: Constant) -> u32 {
         // Create a key for deduplication
         let key = match &constant {
+            Constant::Integer(i) => format!("i:{}", i),
             Constant::Number(n) => format!("n:{}", n),
             Constant::String(s) => format!("s:{}", s),
             Constant::Boolean(b) => format!("b:{}", b),
@@ -105,7 +110,8 @@ impl ConstantPool {
         // Rebuild the map for deduplication
         for (idx, constant) in pool.constants.iter().enumerate() {
             let key = match constant {
-                Constant::Number(n) => format!("n:{}", n),
+                Constant::Integer(i) => format!("i:{}", i),
+            Constant::Number(n) => format!("n:{}", n),
                 Constant::String(s) => format!("s:{}", s),
                 Constant::Boolean(b) => format!("b:{}", b),
                 Constant::Null => "null".to_string(),
@@ -155,10 +161,10 @@ mod tests {
     fn test_get_constant() {
         let mut pool = ConstantPool::new();
 
-        let idx = pool.add_constant(Constant::Number(3.14));
+        let idx = pool.add_constant(Constant::Number(2.5));
         let constant = pool.get(idx).unwrap();
 
-        assert_eq!(*constant, Constant::Number(3.14));
+        assert_eq!(*constant, Constant::Number(2.5));
     }
 
     #[test]
@@ -0,0 +1,365 @@
+/// Bytecode Disassembler
+///
+/// Renders a `BytecodeChunk` as an annotated listing (optionally ANSI-colored)
+/// or as JSON for tooling. Instructions are grouped into preamble, body and
+/// cleanup sections based on where control flow starts and ends.
+use super::{BytecodeChunk, Constant, Instruction};
+use serde_json::{json, Value as JsonValue};
+use std::fmt::Write;
+use std::ops::Range;
+
+const BLUE: &str = "\x1b[34m";
+const YELLOW: &str = "\x1b[33m";
+const GREEN: &str = "\x1b[32m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Width of the opcode column
+const OPCODE_WIDTH: usize = 20;
+
+/// Width of the operand column
+const OPERAND_WIDTH: usize = 10;
+
+/// A contiguous run of instructions in the listing
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub name: &'static str,
+    pub range: Range<usize>,
+}
+
+/// Absolute jump target of a control flow instruction at `offset`
+///
+/// Offsets are relative to the instruction after the jump, matching the VM.
+pub fn jump_target(offset: usize, instruction: &Instruction) -> Option<usize> {
+    let next = offset as i64 + 1;
+    let target = match instruction {
+        Instruction::Jump(d) | Instruction::JumpIfFalse(d) | Instruction::JumpIfTrue(d) => {
+            next + *d as i64
+        }
+        Instruction::PushTryHandler(d) => next + *d as i64,
+        Instruction::Loop(d) => next - *d as i64,
+        _ => return None,
+    };
+    usize::try_from(target).ok()
+}
+
+/// Split the code into preamble, body and cleanup sections
+///
+/// The body spans from the first to the last instruction involved in control
+/// flow (a jump or a jump target). Straight-line code before it is the preamble
+/// and code after it is the cleanup. Without any control flow, everything but a
+/// trailing `Halt` is body. Empty sections are omitted.
+pub fn sections(code: &[Instruction]) -> Vec<Section> {
+    let len = code.len();
+    let mut flow_points = Vec::new();
+    for (i, instruction) in code.iter().enumerate() {
+        if let Some(target) = jump_target(i, instruction) {
+            flow_points.push(i);
+            flow_points.push(target.min(len.saturating_sub(1)));
+        }
+    }
+
+    let (body_start, body_end) = match (flow_points.iter().min(), flow_points.iter().max()) {
+        (Some(&first), Some(&last)) => (first, last + 1),
+        _ => {
+            let end = if code.last() == Some(&Instruction::Halt) {
+                len - 1
+            } else {
+                len
+            };
+            (0, end)
+        }
+    };
+
+    [
+        ("preamble", 0..body_start),
+        ("body", body_start..body_end),
+        ("cleanup", body_end..len),
+    ]
+    .into_iter()
+    .filter(|(_, range)| !range.is_empty())
+    .map(|(name, range)| Section { name, range })
+    .collect()
+}
+
+fn constant_type(constant: &Constant) -> &'static str {
+    match constant {
+        Constant::Integer(_) => "Integer",
+        Constant::Number(_) => "Number",
+        Constant::String(_) => "String",
+        Constant::Boolean(_) => "Boolean",
+        Constant::Null => "Null",
+    }
+}
+
+fn constant_value(constant: &Constant) -> String {
+    match constant {
+        Constant::Integer(i) => i.to_string(),
+        Constant::Number(n) => n.to_string(),
+        Constant::String(s) => format!("{:?}", s),
+        Constant::Boolean(b) => b.to_string(),
+        Constant::Null => "null".to_string(),
+    }
+}
+
+/// The operand of an instruction as shown in the listing, if it has one
+fn operand(instruction: &Instruction) -> Option<String> {
+    use Instruction::*;
+    match instruction {
+        LoadConst(n) | LoadLocal(n) | StoreLocal(n) | BuildList(n) | BuildDict(n)
+        | PushTryHandler(n) => Some(n.to_string()),
+        Jump(d) | JumpIfFalse(d) | JumpIfTrue(d) | Loop(d) => Some(d.to_string()),
+        Call(argc) => Some(argc.to_string()),
+        InputAs(expect) => Some(expect.phrase().to_string()),
+        LoadGlobal(name) | StoreGlobal(name) => Some(format!("{:?}", name)),
+        _ => None,
+    }
+}
+
+/// Extra context printed after the operand: constant values and jump targets
+fn annotation(chunk: &BytecodeChunk, offset: usize, instruction: &Instruction) -> Option<String> {
+    match instruction {
+        Instruction::LoadConst(idx) => chunk
+            .constants
+            .get(*idx as usize)
+            .map(constant_value)
+            .or_else(|| Some("<invalid constant>".to_string())),
+        Instruction::LoadLocal(idx) | Instruction::StoreLocal(idx) => chunk
+            .debug_info
+            .as_ref()
+            .and_then(|d| d.variable_names.get(*idx as usize))
+            .cloned(),
+        _ => jump_target(offset, instruction).map(|t| format!("-> {:04}", t)),
+    }
+}
+
+fn source_line(chunk: &BytecodeChunk, offset: usize) -> Option<u32> {
+    chunk
+        .debug_info
+        .as_ref()
+        .and_then(|d| d.line_numbers.get(offset))
+        .copied()
+}
+
+fn paint(text: &str, code: &str, color: bool) -> String {
+    if color {
+        format!("{}{}{}", code, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Render a human-readable listing of the chunk
+pub fn disassemble(chunk: &BytecodeChunk, color: bool) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "{}", paint("=== Bytecode Disassembly ===", BOLD, color));
+    let _ = writeln!(out, "Version: {}", chunk.version);
+    if let Some(debug_info) = &chunk.debug_info {
+        let _ = writeln!(out, "Source: {}", debug_info.source_file);
+    }
+
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "{}",
+        paint(&format!("--- Constants ({}) ---", chunk.constants.len()), BOLD, color)
+    );
+    for (i, constant) in chunk.constants.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "  [{:>3}] {:<8} {}",
+            i,
+            constant_type(constant),
+            paint(&constant_value(constant), YELLOW, color)
+        );
+    }
+
+    for section in sections(&chunk.code) {
+        let _ = writeln!(out);
+        let _ = writeln!(
+            out,
+            "{}",
+            paint(
+                &format!("--- {} ({} instructions) ---", section.name, section.range.len()),
+                BOLD,
+                color
+            )
+        );
+        for offset in section.range {
+            let instruction = &chunk.code[offset];
+            let _ = write!(out, "  {:04}  ", offset);
+            match source_line(chunk, offset) {
+                Some(line) => {
+                    let _ = write!(out, "{}  ", paint(&format!("{:>5}", line), GREEN, color));
+                }
+                None if chunk.debug_info.is_some() => out.push_str("    -  "),
+                None => {}
+            }
+            let note = annotation(chunk, offset, instruction);
+            let Some(operand_text) = operand(instruction) else {
+                // Nothing follows the opcode, so skip the padding
+                let _ = writeln!(out, "{}", paint(instruction.name(), BLUE, color));
+                continue;
+            };
+            let opcode = format!("{:<width$}", instruction.name(), width = OPCODE_WIDTH);
+            let operand_text = format!("{:>width$}", operand_text, width = OPERAND_WIDTH);
+            let _ = write!(
+                out,
+                "{} {}",
+                paint(&opcode, BLUE, color),
+                paint(&operand_text, YELLOW, color)
+            );
+            if let Some(note) = note {
+                let _ = write!(out, "  ; {}", note);
+            }
+            out.push('\n');
+        }
+    }
+
+    if let Some(debug_info) = &chunk.debug_info {
+        let _ = writeln!(out);
+        let _ = writeln!(out, "{}", paint("--- Debug Info ---", BOLD, color));
+        let _ = writeln!(out, "  Line numbers: {} entries", debug_info.line_numbers.len());
+        let _ = writeln!(out, "  Variables: {} entries", debug_info.variable_names.len());
+    }
+
+    out
+}
+
+/// Machine-readable form of the listing
+pub fn disassemble_json(chunk: &BytecodeChunk) -> JsonValue {
+    let sections = sections(&chunk.code);
+    let section_of = |offset: usize| {
+        sections
+            .iter()
+            .find(|s| s.range.contains(&offset))
+            .map(|s| s.name)
+    };
+
+    let constants: Vec<JsonValue> = chunk
+        .constants
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let value = match c {
+                Constant::Integer(i) => json!(i),
+                Constant::Number(n) => json!(n),
+                Constant::String(s) => json!(s),
+                Constant::Boolean(b) => json!(b),
+                Constant::Null => JsonValue::Null,
+            };
+            json!({ "index": i, "type": constant_type(c), "value": value })
+        })
+        .collect();
+
+    let instructions: Vec<JsonValue> = chunk
+        .code
+        .iter()
+        .enumerate()
+        .map(|(i, instruction)| {
+            json!({
+                "offset": i,
+                "opcode": instruction.name(),
+                "operand": operand(instruction),
+                "target": jump_target(i, instruction),
+                "line": source_line(chunk, i),
+                "section": section_of(i),
+            })
+        })
+        .collect();
+
+    let sections: Vec<JsonValue> = sections
+        .iter()
+        .map(|s| json!({ "name": s.name, "start": s.range.start, "end": s.range.end }))
+        .collect();
+
+    json!({
+        "version": chunk.version,
+        "source_file": chunk.debug_info.as_ref().map(|d| d.source_file.clone()),
+        "constants": constants,
+        "sections": sections,
+        "instructions": instructions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::DebugInfo;
+
+    fn loop_chunk() -> BytecodeChunk {
+        let mut chunk = BytecodeChunk::new(1);
+        chunk.constants.push(Constant::Number(0.0));
+        chunk.constants.push(Constant::String("done".to_string()));
+        chunk.code = vec![
+            Instruction::LoadConst(0),
+            Instruction::StoreGlobal("i".to_string()),
+            Instruction::LoadTrue,
+            Instruction::JumpIfFalse(1),
+            Instruction::Loop(3),
+            Instruction::LoadConst(1),
+            Instruction::Print,
+            Instruction::Halt,
+        ];
+        chunk
+    }
+
+    #[test]
+    fn test_jump_targets() {
+        assert_eq!(jump_target(3, &Instruction::JumpIfFalse(1)), Some(5));
+        assert_eq!(jump_target(4, &Instruction::Loop(3)), Some(2));
+        assert_eq!(jump_target(0, &Instruction::Add), None);
+    }
+
+    #[test]
+    fn test_sections_follow_control_flow() {
+        let names: Vec<_> = sections(&loop_chunk().code)
+            .into_iter()
+            .map(|s| (s.name, s.range))
+            .collect();
+        assert_eq!(
+            names,
+            vec![("preamble", 0..2), ("body", 2..6), ("cleanup", 6..8)]
+        );
+
+        let straight = vec![Instruction::LoadTrue, Instruction::Print, Instruction::Halt];
+        let names: Vec<_> = sections(&straight).into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["body", "cleanup"]);
+    }
+
+    #[test]
+    fn test_plain_listing_is_aligned() {
+        let mut chunk = loop_chunk();
+        chunk.debug_info = Some(DebugInfo {
+            source_file: "loop.poh".to_string(),
+            line_numbers: vec![1, 1, 2, 2, 3, 4, 4, 5],
+            variable_names: Vec::new(),
+        });
+        let text = disassemble(&chunk, false);
+        assert!(!text.contains('\x1b'));
+        assert!(text.contains("[  1] String   \"done\""));
+        assert!(text.contains("--- preamble (2 instructions) ---"));
+        assert!(text.contains(&format!(
+            "  0003      2  {:<20} {:>10}  ; -> 0005",
+            "JumpIfFalse", "1"
+        )));
+    }
+
+    #[test]
+    fn test_colored_listing() {
+        let text = disassemble(&loop_chunk(), true);
+        assert!(text.contains(&format!("{}{:<20}{}", BLUE, "LoadConst", RESET)));
+        assert!(text.contains(&format!("{}Print{}\n", BLUE, RESET)));
+    }
+
+    #[test]
+    fn test_json_listing() {
+        let value = disassemble_json(&loop_chunk());
+        assert_eq!(value["constants"][1]["type"], "String");
+        assert_eq!(value["instructions"][4]["opcode"], "Loop");
+        assert_eq!(value["instructions"][4]["target"], 2);
+        assert_eq!(value["instructions"][7]["section"], "cleanup");
+        assert_eq!(value["sections"].as_array().unwrap().len(), 3);
+    }
+}
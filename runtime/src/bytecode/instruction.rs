@@ -2,6 +2,7 @@
 ///
 /// Stack-based bytecode instructions for the PohLang virtual machine.
 /// Designed for simplicity, portability, and performance.
+use crate::core::io::AskType;
 use std::fmt;
 
 /// Bytecode instruction enum
@@ -172,6 +173,10 @@ pub enum Instruction {
     /// Stack: [...] -> [... input_string]
     Input,
 
+    /// Read input until it is of the given kind, re-prompting on bad answers
+    /// Stack: [...] -> [... answer]
+    InputAs(AskType),
+
     /// Write to file
     /// Stack: [... content filepath] -> [...]
     WriteFile,
@@ -231,7 +236,7 @@ impl Instruction {
             | StartServer | HtmlResponse | JsonResponse | Pop | Duplicate | Swap | Halt => 1,
 
             // Instructions with u8 operand: 2 bytes
-            Call(_) => 2,
+            Call(_) | InputAs(_) => 2,
 
             // Instructions with u32 operand: 5 bytes
             LoadConst(_) | LoadLocal(_) | StoreLocal(_) | BuildList(_) | BuildDict(_)
@@ -295,6 +300,7 @@ impl Instruction {
             Throw => "Throw",
             Print => "Print",
             Input => "Input",
+            InputAs(_) => "InputAs",
             WriteFile => "WriteFile",
             ReadFile => "ReadFile",
             CreateWebServer => "CreateWebServer",
@@ -324,6 +330,7 @@ impl fmt::Display for Instruction {
             JumpIfTrue(offset) => write!(f, "JumpIfTrue {}", offset),
             Loop(offset) => write!(f, "Loop {}", offset),
             Call(argc) => write!(f, "Call {}", argc),
+            InputAs(expect) => write!(f, "InputAs {}", expect.phrase()),
             BuildList(count) => write!(f, "BuildList {}", count),
             BuildDict(count) => write!(f, "BuildDict {}", count),
             PushTryHandler(offset) => write!(f, "PushTryHandler {}", offset),
@@ -1,5 +1,6 @@
 pub mod compiler;
 pub mod constant;
+pub mod disassembler;
 /// PohLang Bytecode Module
 ///
 /// This module contains the bytecode compiler, VM, and related utilities
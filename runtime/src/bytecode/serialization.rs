@@ -2,6 +2,7 @@
 ///
 /// Serialization and deserialization for bytecode chunks
 use super::{BytecodeChunk, Constant, DebugInfo, Instruction};
+use crate::core::io::AskType;
 use std::fs::File;
 use std::io::{self, Read, Write};
 use std::path::Path;
@@ -105,6 +106,10 @@ impl BytecodeSerializer {
                 Constant::Null => {
                     buf.push(3); // Type tag
                 }
+                Constant::Integer(i) => {
+                    buf.push(4); // Type tag
+                    buf.write_all(&i.to_le_bytes())?;
+                }
             }
         }
 
@@ -191,6 +196,14 @@ impl BytecodeSerializer {
             Instruction::Pop => buf.push(42),
             Instruction::Print => buf.push(50),
             Instruction::Input => buf.push(51),
+            Instruction::InputAs(expect) => {
+                buf.push(52);
+                buf.push(match expect {
+                    AskType::Number => 1,
+                    AskType::YesNo => 2,
+                    _ => 3,
+                });
+            }
             Instruction::Halt => buf.push(99),
             _ => {
                 // For unimplemented instructions, use opcode 255
@@ -336,6 +349,27 @@ impl BytecodeDeserializer {
         Ok(value)
     }
 
+    /// Read i64 from bytes
+    fn read_i64(bytes: &[u8], cursor: &mut usize) -> SerializationResult<i64> {
+        if *cursor + 8 > bytes.len() {
+            return Err(SerializationError::InvalidData(
+                "Unexpected end of data".to_string(),
+            ));
+        }
+        let value = i64::from_le_bytes([
+            bytes[*cursor],
+            bytes[*cursor + 1],
+            bytes[*cursor + 2],
+            bytes[*cursor + 3],
+            bytes[*cursor + 4],
+            bytes[*cursor + 5],
+            bytes[*cursor + 6],
+            bytes[*cursor + 7],
+        ]);
+        *cursor += 8;
+        Ok(value)
+    }
+
     /// Read string from bytes
     fn read_string(bytes: &[u8], cursor: &mut usize) -> SerializationResult<String> {
         let len = Self::read_u32(bytes, cursor)? as usize;
@@ -384,6 +418,10 @@ impl BytecodeDeserializer {
                     Constant::Boolean(b)
                 }
                 3 => Constant::Null,
+                4 => {
+                    let i = Self::read_i64(bytes, cursor)?;
+                    Constant::Integer(i)
+                }
                 _ => {
                     return Err(SerializationError::InvalidData(format!(
                         "Unknown constant type: {}",
@@ -462,6 +500,20 @@ impl BytecodeDeserializer {
             42 => Instruction::Pop,
             50 => Instruction::Print,
             51 => Instruction::Input,
+            52 => {
+                let expect = match bytes.get(*cursor) {
+                    Some(1) => AskType::Number,
+                    Some(2) => AskType::YesNo,
+                    Some(3) => AskType::Text,
+                    _ => {
+                        return Err(SerializationError::InvalidData(
+                            "InputAs kind missing".to_string(),
+                        ))
+                    }
+                };
+                *cursor += 1;
+                Instruction::InputAs(expect)
+            }
             99 => Instruction::Halt,
             255 => Instruction::Halt, // Fallback for unsupported instructions
             _ => {
@@ -535,20 +587,22 @@ mod tests {
     #[test]
     fn test_all_constant_types() {
         let mut chunk = BytecodeChunk::new(1);
-        chunk.constants.push(Constant::Number(3.14));
+        chunk.constants.push(Constant::Number(2.5));
         chunk.constants.push(Constant::String("hello".to_string()));
         chunk.constants.push(Constant::Boolean(true));
         chunk.constants.push(Constant::Null);
+        chunk.constants.push(Constant::Integer(9007199254740993));
         chunk.code.push(Instruction::Halt);
 
         let bytes = BytecodeSerializer::serialize(&chunk).unwrap();
         let deserialized = BytecodeDeserializer::deserialize(&bytes).unwrap();
 
-        assert_eq!(deserialized.constants.len(), 4);
+        assert_eq!(deserialized.constants.len(), 5);
         assert!(matches!(deserialized.constants[0], Constant::Number(_)));
         assert!(matches!(deserialized.constants[1], Constant::String(_)));
         assert!(matches!(deserialized.constants[2], Constant::Boolean(true)));
         assert!(matches!(deserialized.constants[3], Constant::Null));
+        assert_eq!(deserialized.constants[4], Constant::Integer(9007199254740993));
     }
 
     #[test]
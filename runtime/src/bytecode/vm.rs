@@ -2,6 +2,10 @@
 ///
 /// Stack-based VM for executing bytecode instructions
 use super::{BytecodeChunk, Constant, Instruction};
+use crate::core::equality::{typed_eq, HasShape, Shape};
+use crate::core::io::{self as core_io, Answer, AskType};
+use crate::core::number::{self, Number};
+use std::cmp::Ordering;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fmt;
@@ -11,6 +15,7 @@ use std::time::{Duration, Instant};
 /// Runtime value representation
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
+    Integer(i64),
     Number(f64),
     String(String),
     Boolean(bool),
@@ -20,10 +25,12 @@ pub enum Value {
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Value::Number(n) => write!(f, "{}", n),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Number(n) => write!(f, "{}", number::format_float(*n)),
             Value::String(s) => write!(f, "{}", s),
-            Value::Boolean(b) => write!(f, "{}", b),
-            Value::Null => write!(f, "null"),
+            Value::Boolean(true) => write!(f, "True"),
+            Value::Boolean(false) => write!(f, "False"),
+            Value::Null => write!(f, "None"),
         }
     }
 }
@@ -34,6 +41,7 @@ impl Value {
         match self {
             Value::Boolean(b) => *b,
             Value::Null => false,
+            Value::Integer(i) => *i != 0,
             Value::Number(n) => *n != 0.0,
             Value::String(s) => !s.is_empty(),
         }
@@ -42,12 +50,57 @@ impl Value {
     /// Convert constant to value
     pub fn from_constant(constant: &Constant) -> Self {
         match constant {
+            Constant::Integer(i) => Value::Integer(*i),
             Constant::Number(n) => Value::Number(*n),
             Constant::String(s) => Value::String(s.clone()),
             Constant::Boolean(b) => Value::Boolean(*b),
             Constant::Null => Value::Null,
         }
     }
+
+    fn as_number(&self) -> Option<Number> {
+        match self {
+            Value::Integer(i) => Some(Number::Int(*i)),
+            Value::Number(n) => Some(Number::Float(*n)),
+            _ => None,
+        }
+    }
+}
+
+impl HasShape for Value {
+    fn shape(&self) -> Shape<'_, Self> {
+        match self {
+            Value::Integer(i) => Shape::Number(Number::Int(*i)),
+            Value::Number(n) => Shape::Number(Number::Float(*n)),
+            Value::String(s) => Shape::Str(s),
+            Value::Boolean(b) => Shape::Bool(*b),
+            Value::Null => Shape::Null,
+        }
+    }
+}
+
+impl From<Number> for Value {
+    fn from(n: Number) -> Self {
+        match n {
+            Number::Int(i) => Value::Integer(i),
+            Number::Float(f) => Value::Number(f),
+        }
+    }
+}
+
+/// What a comparison or logic operator gives: 1 or 0, as in the AST interpreter
+fn truth(b: bool) -> Value {
+    Value::Integer(b as i64)
+}
+
+/// An answer read by `Input`/`InputAs`; bad input becomes a ValidationError
+fn answer_value(answer: Result<Answer, String>) -> Result<Value, VMError> {
+    match answer {
+        Ok(Answer::Number(n)) => Ok(n.into()),
+        Ok(Answer::Bool(b)) => Ok(Value::Boolean(b)),
+        Ok(Answer::Text(s)) => Ok(Value::String(s)),
+        Err(msg) => Err(VMError::Other(format!("[ValidationError] {}", msg))),
+    }
 }
 
 /// VM runtime errors
@@ -85,6 +138,7 @@ impl std::error::Error for VMError {}
 pub type VMResult<T> = Result<T, VMError>;
 
 /// Call frame for function calls
+#[allow(dead_code)] // Reserved for Call/Return support
 #[derive(Debug, Clone)]
 struct CallFrame {
     return_ip: usize,
@@ -155,7 +209,7 @@ impl VMStats {
     pub fn format_report(&self) -> String {
         let mut report = String::new();
 
-        report.push_str(&format!("=== VM Execution Statistics ===\n"));
+        report.push_str("=== VM Execution Statistics ===\n");
         report.push_str(&format!(
             "Total Instructions: {}\n",
             self.total_instructions
@@ -167,20 +221,20 @@ impl VMStats {
             report.push_str(&format!("Instructions/sec: {:.0}\n", ips));
         }
 
-        report.push_str(&format!("\nStack:\n"));
+        report.push_str("\nStack:\n");
         report.push_str(&format!("  Max Depth: {}\n", self.max_stack_depth));
 
-        report.push_str(&format!("\nCache:\n"));
+        report.push_str("\nCache:\n");
         let total_cache_ops = self.cache_hits + self.cache_misses;
         if total_cache_ops > 0 {
             let hit_rate = (self.cache_hits as f64 / total_cache_ops as f64) * 100.0;
             report.push_str(&format!("  Hits: {} ({:.1}%)\n", self.cache_hits, hit_rate));
             report.push_str(&format!("  Misses: {}\n", self.cache_misses));
         } else {
-            report.push_str(&format!("  No cache operations\n"));
+            report.push_str("  No cache operations\n");
         }
 
-        report.push_str(&format!("\nTop Instructions:\n"));
+        report.push_str("\nTop Instructions:\n");
         let mut sorted_instructions: Vec<_> = self.instruction_counts.iter().collect();
         sorted_instructions.sort_by(|a, b| b.1.cmp(a.1));
         for (name, count) in sorted_instructions.iter().take(10) {
@@ -193,6 +247,8 @@ impl VMStats {
 }
 
 /// Bytecode Virtual Machine
+///
+/// Like the AST `Vm`, this is `Send + Sync` and can run on any thread.
 pub struct BytecodeVM {
     /// Value stack
     stack: Vec<Value>,
@@ -207,6 +263,7 @@ pub struct BytecodeVM {
     global_cache: Vec<Option<CacheEntry>>,
 
     /// Call frames
+    #[allow(dead_code)]
     call_stack: Vec<CallFrame>,
 
     /// Instruction pointer
@@ -311,7 +368,7 @@ impl BytecodeVM {
             // Record instruction in stats
             if let Some(stats) = &mut self.stats {
                 stats.record_instruction(
-                    &format!("{:?}", instruction)
+                    format!("{:?}", instruction)
                         .split('(')
                         .next()
                         .unwrap_or("Unknown"),
@@ -387,14 +444,10 @@ impl BytecodeVM {
             Instruction::Add => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                let result = match (a, b) {
-                    (Value::Number(x), Value::Number(y)) => Value::Number(x + y),
-                    (Value::String(x), Value::String(y)) => Value::String(format!("{}{}", x, y)),
-                    _ => {
-                        return Err(VMError::TypeError(
-                            "Add requires numbers or strings".to_string(),
-                        ))
-                    }
+                // Anything that is not two numbers is joined as text
+                let result = match (a.as_number(), b.as_number()) {
+                    (Some(x), Some(y)) => (x + y).into(),
+                    _ => Value::String(format!("{}{}", a, b)),
                 };
                 self.push(result)?;
             }
@@ -402,8 +455,8 @@ impl BytecodeVM {
             Instruction::Subtract => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                match (a, b) {
-                    (Value::Number(x), Value::Number(y)) => self.push(Value::Number(x - y))?,
+                match (a.as_number(), b.as_number()) {
+                    (Some(x), Some(y)) => self.push((x - y).into())?,
                     _ => return Err(VMError::TypeError("Subtract requires numbers".to_string())),
                 }
             }
@@ -411,8 +464,8 @@ impl BytecodeVM {
             Instruction::Multiply => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                match (a, b) {
-                    (Value::Number(x), Value::Number(y)) => self.push(Value::Number(x * y))?,
+                match (a.as_number(), b.as_number()) {
+                    (Some(x), Some(y)) => self.push((x * y).into())?,
                     _ => return Err(VMError::TypeError("Multiply requires numbers".to_string())),
                 }
             }
@@ -420,12 +473,12 @@ impl BytecodeVM {
             Instruction::Divide => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                match (a, b) {
-                    (Value::Number(x), Value::Number(y)) => {
-                        if y == 0.0 {
+                match (a.as_number(), b.as_number()) {
+                    (Some(x), Some(y)) => {
+                        if y.is_zero() {
                             return Err(VMError::DivisionByZero);
                         }
-                        self.push(Value::Number(x / y))?
+                        self.push((x / y).into())?
                     }
                     _ => return Err(VMError::TypeError("Divide requires numbers".to_string())),
                 }
@@ -433,8 +486,8 @@ impl BytecodeVM {
 
             Instruction::Negate => {
                 let value = self.pop()?;
-                match value {
-                    Value::Number(n) => self.push(Value::Number(-n))?,
+                match value.as_number() {
+                    Some(n) => self.push((-n).into())?,
                     _ => return Err(VMError::TypeError("Negate requires a number".to_string())),
                 }
             }
@@ -443,71 +496,36 @@ impl BytecodeVM {
             Instruction::Equal => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                self.push(Value::Boolean(a == b))?;
+                self.push(truth(typed_eq(&a, &b)))?;
             }
 
             Instruction::NotEqual => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                self.push(Value::Boolean(a != b))?;
-            }
-
-            Instruction::Less => {
-                let b = self.pop()?;
-                let a = self.pop()?;
-                match (a, b) {
-                    (Value::Number(x), Value::Number(y)) => self.push(Value::Boolean(x < y))?,
-                    _ => return Err(VMError::TypeError("Less requires numbers".to_string())),
-                }
+                self.push(truth(!typed_eq(&a, &b)))?;
             }
 
-            Instruction::LessEqual => {
-                let b = self.pop()?;
-                let a = self.pop()?;
-                match (a, b) {
-                    (Value::Number(x), Value::Number(y)) => self.push(Value::Boolean(x <= y))?,
-                    _ => return Err(VMError::TypeError("LessEqual requires numbers".to_string())),
-                }
-            }
-
-            Instruction::Greater => {
-                let b = self.pop()?;
-                let a = self.pop()?;
-                match (a, b) {
-                    (Value::Number(x), Value::Number(y)) => self.push(Value::Boolean(x > y))?,
-                    _ => return Err(VMError::TypeError("Greater requires numbers".to_string())),
-                }
-            }
-
-            Instruction::GreaterEqual => {
-                let b = self.pop()?;
-                let a = self.pop()?;
-                match (a, b) {
-                    (Value::Number(x), Value::Number(y)) => self.push(Value::Boolean(x >= y))?,
-                    _ => {
-                        return Err(VMError::TypeError(
-                            "GreaterEqual requires numbers".to_string(),
-                        ))
-                    }
-                }
-            }
+            Instruction::Less => self.compare_sizes(Ordering::is_lt)?,
+            Instruction::LessEqual => self.compare_sizes(Ordering::is_le)?,
+            Instruction::Greater => self.compare_sizes(Ordering::is_gt)?,
+            Instruction::GreaterEqual => self.compare_sizes(Ordering::is_ge)?,
 
             // === Logical Operations ===
             Instruction::Not => {
                 let value = self.pop()?;
-                self.push(Value::Boolean(!value.is_truthy()))?;
+                self.push(truth(!value.is_truthy()))?;
             }
 
             Instruction::And => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                self.push(Value::Boolean(a.is_truthy() && b.is_truthy()))?;
+                self.push(truth(a.is_truthy() && b.is_truthy()))?;
             }
 
             Instruction::Or => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                self.push(Value::Boolean(a.is_truthy() || b.is_truthy()))?;
+                self.push(truth(a.is_truthy() || b.is_truthy()))?;
             }
 
             // === Control Flow ===
@@ -559,8 +577,13 @@ impl BytecodeVM {
             }
 
             Instruction::Input => {
-                // For now, we'll push null (in real implementation, would read from stdin)
-                self.push(Value::Null)?;
+                let answer = core_io::ask_as(AskType::Any);
+                self.push(answer_value(answer)?)?;
+            }
+
+            Instruction::InputAs(expect) => {
+                let answer = core_io::ask_as(*expect);
+                self.push(answer_value(answer)?)?;
             }
 
             // === Other instructions (not yet implemented) ===
@@ -580,6 +603,18 @@ impl BytecodeVM {
     }
 
     /// Push a value onto the stack
+    /// `is less than` and the others: only numbers have a size, so anything
+    /// else compares false, as in the AST interpreter without `--strict`
+    fn compare_sizes(&mut self, holds: fn(Ordering) -> bool) -> VMResult<()> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        let result = match (a.as_number(), b.as_number()) {
+            (Some(x), Some(y)) => x.compare(y).is_some_and(holds),
+            _ => false,
+        };
+        self.push(truth(result))
+    }
+
     fn push(&mut self, value: Value) -> VMResult<()> {
         if self.stack.len() >= 1024 {
             return Err(VMError::StackOverflow);
@@ -702,6 +737,14 @@ impl Default for BytecodeVM {
 mod tests {
     use super::*;
 
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_vm_is_send_sync() {
+        assert_send_sync::<BytecodeVM>();
+        assert_send_sync::<Value>();
+    }
+
     #[test]
     fn test_vm_push_pop() {
         let mut vm = BytecodeVM::new();
@@ -773,7 +816,7 @@ mod tests {
         vm.load(chunk);
         let result = vm.run().unwrap();
 
-        assert_eq!(result, Value::Boolean(true));
+        assert_eq!(result, Value::Integer(1));
     }
 
     #[test]
@@ -832,7 +875,7 @@ mod tests {
 
     #[test]
     fn test_vm_error_with_line_numbers() {
-        use super::DebugInfo;
+        use crate::bytecode::DebugInfo;
 
         let mut chunk = BytecodeChunk::new(1);
         chunk.constants.push(Constant::Number(10.0));
@@ -889,9 +932,9 @@ mod tests {
                                                  // Execution time can be 0ns on very fast runners; don't assert > 0 to avoid flakiness
         assert!(stats.max_stack_depth > 0); // Stack was used
 
-        // Check cache statistics: one LoadGlobal executed => first access is a miss, no hits yet
-        assert_eq!(stats.cache_hits, 0);
-        assert_eq!(stats.cache_misses, 1);
+        // Check cache statistics: StoreGlobal writes through the cache, so the LoadGlobal is a hit
+        assert_eq!(stats.cache_hits, 1);
+        assert_eq!(stats.cache_misses, 0);
 
         // Print report for manual inspection
         if let Some(report) = vm.stats_report() {
@@ -0,0 +1,232 @@
+//! `extern "C"` functions for running PohLang from C and C++, declared in
+//! `include/pohlang.h`.
+//!
+//! A `PohVm` is an opaque handle from `poh_vm_new`, freed with
+//! `poh_vm_free`. Strings handed back to the caller are theirs, freed with
+//! `poh_string_free`. No panic unwinds out of these functions: one that
+//! panics fails as it would on any other error.
+
+use crate::core::io;
+use crate::parser;
+use crate::vm::Vm;
+use anyhow::{anyhow, bail, Result};
+use std::any::Any;
+use std::ffi::{c_char, c_double, c_int, c_void, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// Called with each line a program writes, without its line ending
+pub type PohOutputFn = extern "C" fn(line: *const c_char, user_data: *mut c_void);
+
+/// A VM and where its programs' output goes
+pub struct PohVm {
+    vm: Vm,
+    output: Option<(PohOutputFn, *mut c_void)>,
+}
+
+/// `f`'s result, with a panic turned into an error
+fn guarded<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|panic| Err(anyhow!("PohLang panicked: {}", panic_message(&*panic))))
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message")
+}
+
+/// 0 for success, 1 for failure
+fn status(result: Result<()>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
+/// # Safety
+/// `vm` is NULL or live
+unsafe fn vm_mut<'a>(vm: *mut PohVm) -> Result<&'a mut PohVm> {
+    vm.as_mut().ok_or_else(|| anyhow!("the VM is NULL"))
+}
+
+/// # Safety
+/// `s` is NULL or NUL-terminated
+unsafe fn text<'a>(s: *const c_char, what: &str) -> Result<&'a str> {
+    if s.is_null() {
+        bail!("{} is NULL", what);
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| anyhow!("{} is not UTF-8", what))
+}
+
+/// `s` for the caller to free with `poh_string_free`
+fn owned(s: String) -> *mut c_char {
+    // A NUL inside the text would end it early on the C side
+    CString::new(s.replace('\0', "\\0")).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// A new VM, or NULL if one could not be made
+#[no_mangle]
+pub extern "C" fn poh_vm_new() -> *mut PohVm {
+    guarded(|| {
+        Ok(Box::into_raw(Box::new(PohVm {
+            vm: Vm::default(),
+            output: None,
+        })))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Free a VM from `poh_vm_new`; NULL is ignored
+///
+/// # Safety
+/// `vm` is NULL or from `poh_vm_new`, and is not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn poh_vm_free(vm: *mut PohVm) {
+    if !vm.is_null() {
+        let _ = guarded(|| {
+            drop(Box::from_raw(vm));
+            Ok(())
+        });
+    }
+}
+
+/// Free a string from this library; NULL is ignored
+///
+/// # Safety
+/// `s` is NULL or was returned by this library, and is not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn poh_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Send each line programs on `vm` write to `callback` with `user_data`,
+/// instead of standard output. A NULL `callback` goes back to standard
+/// output. Returns 0, or 1 if `vm` is NULL.
+///
+/// # Safety
+/// `vm` is NULL or live, and `callback` stays callable with `user_data`
+/// while `vm` runs programs
+#[no_mangle]
+pub unsafe extern "C" fn poh_vm_set_output_callback(
+    vm: *mut PohVm,
+    callback: Option<PohOutputFn>,
+    user_data: *mut c_void,
+) -> c_int {
+    status(guarded(|| {
+        vm_mut(vm)?.output = callback.map(|callback| (callback, user_data));
+        Ok(())
+    }))
+}
+
+/// Parse and run a whole program, from `Start Program` to `End Program`.
+/// Variables it sets at the top level stay on `vm` for the next call.
+/// Returns 0 if it ran. Otherwise returns 1 and, if `out_error` is not
+/// NULL, sets `*out_error` to the message, to be freed with
+/// `poh_string_free`.
+///
+/// # Safety
+/// `vm` is NULL or live, `source` is NULL or NUL-terminated, and
+/// `out_error` is NULL or writable
+#[no_mangle]
+pub unsafe extern "C" fn poh_vm_execute(
+    vm: *mut PohVm,
+    source: *const c_char,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    if !out_error.is_null() {
+        *out_error = ptr::null_mut();
+    }
+    let result = guarded(|| {
+        let vm = vm_mut(vm)?;
+        let program = parser::parse(text(source, "the source")?)?;
+        match vm.output {
+            Some((callback, user_data)) => io::with_output_sink(
+                move |line| {
+                    if let Ok(line) = CString::new(line.replace('\0', "\\0")) {
+                        callback(line.as_ptr(), user_data);
+                    }
+                },
+                || vm.vm.execute(&program),
+            ),
+            None => vm.vm.execute(&program),
+        }
+    });
+    if let Err(e) = &result {
+        if !out_error.is_null() {
+            *out_error = owned(format!("{:#}", e));
+        }
+    }
+    status(result)
+}
+
+/// Set a program-scope variable to text, for the next program run on `vm`.
+/// Returns 0, or 1 if an argument is NULL or not UTF-8 or `name` is a
+/// constant.
+///
+/// # Safety
+/// `vm` is NULL or live, and `name` and `value` are NULL or NUL-terminated
+#[no_mangle]
+pub unsafe extern "C" fn poh_vm_set_global_string(
+    vm: *mut PohVm,
+    name: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    status(guarded(|| {
+        let vm = vm_mut(vm)?;
+        vm.vm
+            .set_global(text(name, "the name")?, text(value, "the value")?.into())
+    }))
+}
+
+/// Set a program-scope variable to a number, for the next program run on
+/// `vm`. A whole number is set as one. Returns 0, or 1 if an argument is
+/// NULL or not UTF-8 or `name` is a constant.
+///
+/// # Safety
+/// `vm` is NULL or live, and `name` is NULL or NUL-terminated
+#[no_mangle]
+pub unsafe extern "C" fn poh_vm_set_global_number(
+    vm: *mut PohVm,
+    name: *const c_char,
+    value: c_double,
+) -> c_int {
+    status(guarded(|| {
+        let vm = vm_mut(vm)?;
+        let value = if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
+            (value as i64).into()
+        } else {
+            value.into()
+        };
+        vm.vm.set_global(text(name, "the name")?, value)
+    }))
+}
+
+/// A program-scope variable or constant as JSON text, to be freed with
+/// `poh_string_free`; NULL if there is no such variable or its value has
+/// no JSON form
+///
+/// # Safety
+/// `vm` is NULL or live, and `name` is NULL or NUL-terminated
+#[no_mangle]
+pub unsafe extern "C" fn poh_vm_get_global_as_json(
+    vm: *mut PohVm,
+    name: *const c_char,
+) -> *mut c_char {
+    guarded(|| {
+        let vm = vm_mut(vm)?;
+        let name = text(name, "the name")?;
+        let value = vm
+            .vm
+            .global(name)
+            .ok_or_else(|| anyhow!("{} is not defined", name))?;
+        Ok(owned(vm.vm.to_json(&value)?))
+    })
+    .unwrap_or(ptr::null_mut())
+}
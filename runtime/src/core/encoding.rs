@@ -0,0 +1,126 @@
+//! Decoding of source files saved in encodings other than UTF-8
+//!
+//! Only file reading is affected: once decoded, source text and runtime
+//! strings are UTF-8 as usual. A UTF-8 byte order mark is stripped and, like
+//! browsers do, takes precedence over the requested encoding.
+
+use anyhow::{anyhow, Context, Result};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Windows-1252 code points for bytes 0x80..=0x9F; the rest of the range
+/// matches Latin-1. The five unassigned bytes map to the C1 control with the
+/// same value, as in the WHATWG encoding standard.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SourceEncoding {
+    #[default]
+    Utf8,
+    Latin1,
+    Windows1252,
+}
+
+impl SourceEncoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            SourceEncoding::Utf8 => "utf-8",
+            SourceEncoding::Latin1 => "latin-1",
+            SourceEncoding::Windows1252 => "windows-1252",
+        }
+    }
+
+    /// Decode raw file bytes into a string
+    pub fn decode(self, bytes: &[u8]) -> Result<String> {
+        if let Some(rest) = bytes.strip_prefix(UTF8_BOM) {
+            return decode_utf8(rest);
+        }
+        match self {
+            SourceEncoding::Utf8 => decode_utf8(bytes),
+            SourceEncoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+            SourceEncoding::Windows1252 => Ok(bytes
+                .iter()
+                .map(|&b| match b {
+                    0x80..=0x9F => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+                    _ => b as char,
+                })
+                .collect()),
+        }
+    }
+}
+
+fn decode_utf8(bytes: &[u8]) -> Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|e| {
+        anyhow!(
+            "invalid UTF-8 at byte {}; use --input-encoding latin-1 or windows-1252 for files saved in those encodings",
+            e.utf8_error().valid_up_to()
+        )
+    })
+}
+
+impl FromStr for SourceEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(SourceEncoding::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" => Ok(SourceEncoding::Latin1),
+            "windows-1252" | "cp1252" => Ok(SourceEncoding::Windows1252),
+            _ => Err(format!(
+                "unsupported encoding '{}' (expected utf-8, latin-1 or windows-1252)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SourceEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Read a source file and decode it with `encoding`
+pub fn read_source(path: &Path, encoding: SourceEncoding) -> Result<String> {
+    let bytes = std::fs::read(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    encoding
+        .decode(&bytes)
+        .with_context(|| format!("Cannot decode {} as {}", path.display(), encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_each_encoding() {
+        let bytes = b"caf\xE9 \x80";
+        assert_eq!(SourceEncoding::Latin1.decode(bytes).unwrap(), "café \u{80}");
+        assert_eq!(SourceEncoding::Windows1252.decode(bytes).unwrap(), "café €");
+        assert!(SourceEncoding::Utf8.decode(bytes).is_err());
+        assert_eq!(SourceEncoding::Utf8.decode("café".as_bytes()).unwrap(), "café");
+    }
+
+    #[test]
+    fn test_bom_is_stripped_and_wins() {
+        let bytes = b"\xEF\xBB\xBFcaf\xC3\xA9";
+        assert_eq!(SourceEncoding::Utf8.decode(bytes).unwrap(), "café");
+        assert_eq!(SourceEncoding::Latin1.decode(bytes).unwrap(), "café");
+    }
+
+    #[test]
+    fn test_parse_names() {
+        assert_eq!("UTF8".parse(), Ok(SourceEncoding::Utf8));
+        assert_eq!("iso-8859-1".parse(), Ok(SourceEncoding::Latin1));
+        assert_eq!("cp1252".parse(), Ok(SourceEncoding::Windows1252));
+        assert!("shift-jis".parse::<SourceEncoding>().is_err());
+    }
+}
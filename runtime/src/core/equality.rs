@@ -0,0 +1,137 @@
+//! Typed equality shared by the AST and bytecode VMs
+//!
+//! `is equal to` compares values of the same kind only:
+//! - numbers compare by value, so `1` equals `1.0`; NaN equals nothing,
+//!   not even itself
+//! - strings, booleans and nothing compare as themselves
+//! - lists, tuples and dictionaries compare item by item
+//! - sets compare by their members, in any order
+//! - values of different kinds are never equal, so `"1"` is not `1`
+//!
+//! Functions, servers and other runtime handles are never equal. Each VM
+//! describes its values through [`Shape`] and calls [`typed_eq`].
+
+use super::number::Number;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+/// How a runtime value looks to the equality rules
+pub enum Shape<'a, V> {
+    Number(Number),
+    Str(&'a str),
+    Bool(bool),
+    Null,
+    List(&'a [V]),
+    Tuple(&'a [V]),
+    Dict(&'a HashMap<String, V>),
+    Set(&'a HashSet<OrdValue<V>>),
+    Other,
+}
+
+pub trait HasShape: Sized {
+    fn shape(&self) -> Shape<'_, Self>;
+}
+
+pub fn typed_eq<V: HasShape>(a: &V, b: &V) -> bool {
+    match (a.shape(), b.shape()) {
+        (Shape::Number(x), Shape::Number(y)) => x.num_eq(y),
+        (Shape::Str(x), Shape::Str(y)) => x == y,
+        (Shape::Bool(x), Shape::Bool(y)) => x == y,
+        (Shape::Null, Shape::Null) => true,
+        (Shape::List(xs), Shape::List(ys)) | (Shape::Tuple(xs), Shape::Tuple(ys)) => {
+            xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| typed_eq(x, y))
+        }
+        (Shape::Dict(xs), Shape::Dict(ys)) => {
+            xs.len() == ys.len()
+                && xs
+                    .iter()
+                    .all(|(k, x)| ys.get(k).is_some_and(|y| typed_eq(x, y)))
+        }
+        (Shape::Set(xs), Shape::Set(ys)) => xs == ys,
+        _ => false,
+    }
+}
+
+/// A member of a set: a number, string, boolean or nothing, hashed and
+/// compared by the rules above, so `2` and `2.0` are the same member.
+///
+/// Only those kinds hash by what they hold, and NaN is not equal to itself;
+/// the VM checks both before wrapping a value.
+#[derive(Clone, Debug)]
+pub struct OrdValue<V>(pub V);
+
+impl<V: HasShape> PartialEq for OrdValue<V> {
+    fn eq(&self, other: &Self) -> bool {
+        typed_eq(&self.0, &other.0)
+    }
+}
+
+impl<V: HasShape> Eq for OrdValue<V> {}
+
+impl<V: HasShape> Hash for OrdValue<V> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.0.shape() {
+            Shape::Number(n) => match n {
+                Number::Int(i) => (0u8, i).hash(state),
+                Number::Float(f) => match Number::from_whole(f) {
+                    Number::Int(i) => (0u8, i).hash(state),
+                    Number::Float(f) => (1u8, f.to_bits()).hash(state),
+                },
+            },
+            Shape::Str(s) => (2u8, s).hash(state),
+            Shape::Bool(b) => (3u8, b).hash(state),
+            Shape::Null => 4u8.hash(state),
+            _ => 5u8.hash(state),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum V {
+        N(Number),
+        S(&'static str),
+        L(Vec<V>),
+    }
+
+    impl HasShape for V {
+        fn shape(&self) -> Shape<'_, Self> {
+            match self {
+                V::N(n) => Shape::Number(*n),
+                V::S(s) => Shape::Str(s),
+                V::L(xs) => Shape::List(xs),
+            }
+        }
+    }
+
+    #[test]
+    fn test_numbers_compare_by_value_and_nan_never_matches() {
+        assert!(typed_eq(&V::N(Number::Int(1)), &V::N(Number::Float(1.0))));
+        assert!(!typed_eq(&V::N(Number::Float(0.1 + 0.2)), &V::N(Number::Float(0.3))));
+        let nan = V::N(Number::Float(f64::NAN));
+        assert!(!typed_eq(&nan, &nan));
+    }
+
+    #[test]
+    fn test_kinds_do_not_mix_and_lists_compare_deeply() {
+        assert!(!typed_eq(&V::S("1"), &V::N(Number::Int(1))));
+        let a = V::L(vec![V::N(Number::Int(1)), V::L(vec![V::S("x")])]);
+        let b = V::L(vec![V::N(Number::Float(1.0)), V::L(vec![V::S("x")])]);
+        let c = V::L(vec![V::N(Number::Int(1))]);
+        assert!(typed_eq(&a, &b));
+        assert!(!typed_eq(&a, &c));
+    }
+
+    #[test]
+    fn test_set_members_hash_numbers_by_value() {
+        let set: HashSet<_> = [V::N(Number::Int(2)), V::N(Number::Float(2.0)), V::S("2"), V::N(Number::Float(2.5))]
+            .into_iter()
+            .map(OrdValue)
+            .collect();
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(&OrdValue(V::N(Number::Float(2.0)))));
+        assert!(!set.contains(&OrdValue(V::S("2.5"))));
+    }
+}
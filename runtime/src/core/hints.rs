@@ -0,0 +1,163 @@
+//! Hints for error messages
+//! One list for the parser and the VM: what to look for in a message, the
+//! hint to add below it and, optionally, a code for the hint. Embedders and
+//! modules add their own with `register` or `Vm::register_hint`.
+
+use regex::Regex;
+use std::sync::RwLock;
+
+/// What a hint looks for in an error message
+#[derive(Debug, Clone)]
+pub enum HintPattern {
+    /// Text the message contains
+    Contains(String),
+    /// A regular expression that matches part of the message
+    Matches(Regex),
+}
+
+impl HintPattern {
+    /// How much of `msg` the pattern covers, or `None` when it does not match
+    fn matched_len(&self, msg: &str) -> Option<usize> {
+        match self {
+            HintPattern::Contains(text) => msg.contains(text.as_str()).then_some(text.len()),
+            HintPattern::Matches(re) => re.find_iter(msg).map(|m| m.len()).max(),
+        }
+    }
+}
+
+impl From<&str> for HintPattern {
+    fn from(text: &str) -> Self {
+        HintPattern::Contains(text.to_string())
+    }
+}
+
+impl From<Regex> for HintPattern {
+    fn from(re: Regex) -> Self {
+        HintPattern::Matches(re)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hint {
+    pub pattern: HintPattern,
+    pub text: String,
+    pub code: Option<String>,
+}
+
+impl Hint {
+    pub fn new(pattern: impl Into<HintPattern>, text: impl Into<String>) -> Self {
+        Hint { pattern: pattern.into(), text: text.into(), code: None }
+    }
+
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+}
+
+/// The hints that come with PohLang, as text each message may contain
+const BUILT_IN: &[(&str, &str)] = &[
+    ("Expected 'with'", "Function definitions use 'Define function name with parameter as expression'"),
+    ("Expected function name", "Function name must be a valid identifier (letters, numbers, underscore)"),
+    ("Expected 'as <expr>'", "Inline functions need 'as' followed by an expression"),
+    ("Expected variable name", "Variable names must start with a letter or underscore"),
+    ("Could not parse expression", "Check for unmatched brackets [], braces {}, or parentheses ()"),
+    ("Empty expression", "Expressions cannot be empty. Provide a value, variable, or operation"),
+    ("Unsupported statement", "Valid statements: Write, Set, Ask for, If, Repeat, While, Make, Use, Import"),
+    ("out of range", "Check array bounds. Use negative indexing (-1) for last element"),
+    ("not found", "Verify the key exists in the dictionary or check for typos"),
+    ("division by zero", "Ensure denominator is not zero before dividing"),
+    ("not defined", "Make sure the function is defined before calling it"),
+];
+
+/// Hints added while the process runs, oldest first
+static REGISTERED: RwLock<Vec<Hint>> = RwLock::new(Vec::new());
+
+/// Add a hint for every parser and VM error message from now on
+pub fn register(hint: Hint) {
+    REGISTERED.write().unwrap().push(hint);
+}
+
+/// The hint for `msg`: the one whose pattern covers the most of it. A
+/// registered hint wins a tie with a built-in one, and a later one with an
+/// earlier one, so a hint can be replaced by registering it again.
+pub fn best_match(msg: &str) -> Option<Hint> {
+    let built_in = BUILT_IN.iter().map(|(pattern, text)| Hint::new(*pattern, *text));
+    let registered = REGISTERED.read().unwrap().clone();
+    let mut best: Option<(usize, Hint)> = None;
+    for hint in built_in.chain(registered) {
+        if let Some(len) = hint.pattern.matched_len(msg) {
+            if best.as_ref().is_none_or(|(most, _)| len >= *most) {
+                best = Some((len, hint));
+            }
+        }
+    }
+    best.map(|(_, hint)| hint)
+}
+
+/// `msg` with its hint on the line below, or `msg` as it is without one
+pub fn with_hint(msg: &str) -> String {
+    match best_match(msg) {
+        Some(Hint { text, code: Some(code), .. }) => format!("{}.\nHint [{}]: {}", msg, code, text),
+        Some(hint) => format!("{}.\nHint: {}", msg, hint.text),
+        None => msg.to_string(),
+    }
+}
+
+/// An error message of one or more lines with the hint for its first line
+/// put below that line, unless the message already has a hint
+pub fn add_to(msg: &str) -> Option<String> {
+    if msg.contains("\nHint") {
+        return None;
+    }
+    let (first, rest) = msg.split_once('\n').map_or((msg, None), |(first, rest)| (first, Some(rest)));
+    best_match(first)?;
+    Some(match rest {
+        Some(rest) => format!("{}\n{}", with_hint(first), rest),
+        None => with_hint(first),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_built_in_hint_matches_its_own_pattern() {
+        for (pattern, text) in BUILT_IN {
+            let msg = format!("Error: {} here", pattern);
+            assert_eq!(with_hint(&msg), format!("{}.\nHint: {}", msg, text));
+        }
+    }
+
+    #[test]
+    fn test_the_longest_match_wins() {
+        let msg = "Could not parse expression: key not found";
+        assert!(with_hint(msg).ends_with("Hint: Check for unmatched brackets [], braces {}, or parentheses ()"));
+        assert_eq!(with_hint("all is well"), "all is well");
+    }
+
+    #[test]
+    fn test_hints_go_below_the_first_line_once() {
+        assert_eq!(
+            add_to("Key 'x' not found\n  in file: a.poh").as_deref(),
+            Some("Key 'x' not found.\nHint: Verify the key exists in the dictionary or check for typos\n  in file: a.poh")
+        );
+        assert_eq!(add_to("x out of range.\nHint: Check array bounds"), None);
+        assert_eq!(add_to("all is well\n  in file: a.poh"), None);
+    }
+
+    #[test]
+    fn test_registered_hints_match_text_and_patterns() {
+        register(Hint::new("no such hint table", "Create the table first"));
+        register(Hint::new(Regex::new(r"hint column \w+ is missing").unwrap(), "Check the column names").with_code("SQL1"));
+        assert_eq!(with_hint("no such hint table: users"), "no such hint table: users.\nHint: Create the table first");
+        assert_eq!(
+            with_hint("hint column email is missing"),
+            "hint column email is missing.\nHint [SQL1]: Check the column names"
+        );
+        // A later hint for the same text replaces the earlier one
+        register(Hint::new("no such hint table", "Run the migrations"));
+        assert!(with_hint("no such hint table").ends_with("Hint: Run the migrations"));
+    }
+}
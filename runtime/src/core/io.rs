@@ -1,13 +1,213 @@
-use std::io::{self, Write};
+use super::number::{self, Number};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read, Write};
 
+thread_local! {
+    /// Set while `capture_output` runs on this thread
+    static CAPTURE: RefCell<Option<String>> = const { RefCell::new(None) };
+    /// Set while `with_input` runs on this thread
+    static INPUT: RefCell<Option<VecDeque<String>>> = const { RefCell::new(None) };
+    /// Set while `with_output_sink` runs on this thread
+    static SINK: RefCell<Option<Sink>> = const { RefCell::new(None) };
+}
+
+/// Where `with_output_sink` sends each line
+type Sink = Box<dyn FnMut(&str)>;
+
+/// How many times `ask_as` asks again after an answer of the wrong kind
+pub const ASK_RETRIES: usize = 3;
+
+/// What `Ask for <name> [as ...]` accepts
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AskType {
+    /// A number if it reads as one, otherwise text
+    #[default]
+    Any,
+    Number,
+    /// `yes`/`no`, also `y`/`n` and `true`/`false`, in any case
+    YesNo,
+    /// Always text, even `42`
+    Text,
+}
+
+impl AskType {
+    /// The phrase after `as`, for messages and listings
+    pub fn phrase(self) -> &'static str {
+        match self {
+            AskType::Any => "",
+            AskType::Number => "number",
+            AskType::YesNo => "yes or no",
+            AskType::Text => "text",
+        }
+    }
+
+    /// The typed kinds by their phrase, any case
+    pub fn from_phrase(phrase: &str) -> Option<AskType> {
+        [AskType::Number, AskType::YesNo, AskType::Text]
+            .into_iter()
+            .find(|k| phrase.eq_ignore_ascii_case(k.phrase()))
+    }
+}
+
+/// An answer `ask_as` accepted
+#[derive(Clone, Debug, PartialEq)]
+pub enum Answer {
+    Number(Number),
+    Bool(bool),
+    Text(String),
+}
+
+/// The output sink: every line a program writes goes through here
 pub fn write(value: &str) {
-    println!("{}", value);
+    write_ending(value, "\n")
 }
 
-pub fn ask(prompt: &str) -> String {
+/// Write `value` followed by `ending` instead of a newline. A sink from
+/// `with_output_sink` gets the text without any trailing newline.
+pub fn write_ending(value: &str, ending: &str) {
+    let captured = CAPTURE.with(|c| match c.borrow_mut().as_mut() {
+        Some(buf) => {
+            buf.push_str(value);
+            buf.push_str(ending);
+            true
+        }
+        None => false,
+    });
+    if captured {
+        return;
+    }
+    let sunk = SINK.with(|s| match s.borrow_mut().as_mut() {
+        Some(sink) => {
+            let text = format!("{}{}", value, ending);
+            sink(text.strip_suffix('\n').unwrap_or(&text));
+            true
+        }
+        None => false,
+    });
+    if !sunk {
+        let mut out = io::stdout().lock();
+        let _ = write!(out, "{}{}", value, ending);
+        let _ = out.flush();
+    }
+}
+
+/// Run `f`, handing each line it writes on this thread to `sink` as it is
+/// written instead of printing it. `capture_output` inside `f` still wins.
+pub fn with_output_sink<T>(sink: impl FnMut(&str) + 'static, f: impl FnOnce() -> T) -> T {
+    let previous = SINK.with(|s| s.borrow_mut().replace(Box::new(sink)));
+    let result = f();
+    SINK.with(|s| *s.borrow_mut() = previous);
+    result
+}
+
+/// Run `f`, collecting what it writes on this thread instead of printing it
+pub fn capture_output<T>(f: impl FnOnce() -> T) -> (T, String) {
+    let previous = CAPTURE.with(|c| c.borrow_mut().replace(String::new()));
+    let result = f();
+    let output = CAPTURE.with(|c| std::mem::replace(&mut *c.borrow_mut(), previous));
+    (result, output.unwrap_or_default())
+}
+
+/// Run `f`, answering any input it reads on this thread from `lines`
+/// instead of stdin; once they run out, input is at its end
+pub fn with_input<T>(lines: impl IntoIterator<Item = impl Into<String>>, f: impl FnOnce() -> T) -> T {
+    let lines = lines.into_iter().map(Into::into).collect();
+    let previous = INPUT.with(|i| i.borrow_mut().replace(lines));
+    let result = f();
+    INPUT.with(|i| *i.borrow_mut() = previous);
+    result
+}
+
+/// The next line of input, trimmed; `None` at the end of input
+pub fn read_line(prompt: &str) -> Option<String> {
+    let scripted = INPUT.with(|i| i.borrow_mut().as_mut().map(VecDeque::pop_front));
+    if let Some(line) = scripted {
+        return line.map(|l| l.trim().to_string());
+    }
     print!("{}", prompt);
     let _ = io::stdout().flush();
     let mut input = String::new();
-    let _ = io::stdin().read_line(&mut input);
-    input.trim().to_string()
+    match io::stdin().read_line(&mut input) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(input.trim().to_string()),
+    }
+}
+
+/// The next line of input as it was, without its line ending; `None` at
+/// the end of input. Bytes that are not UTF-8 are replaced.
+pub fn read_raw_line() -> Option<String> {
+    let scripted = INPUT.with(|i| i.borrow_mut().as_mut().map(VecDeque::pop_front));
+    if let Some(line) = scripted {
+        return line;
+    }
+    let mut bytes = Vec::new();
+    match io::stdin().lock().read_until(b'\n', &mut bytes) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => {
+            let line = String::from_utf8_lossy(&bytes);
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            Some(line.strip_suffix('\r').unwrap_or(line).to_string())
+        }
+    }
+}
+
+/// The rest of the input, up to its end; empty if there is none
+pub fn read_to_end() -> String {
+    let scripted = INPUT.with(|i| i.borrow_mut().as_mut().map(|lines| lines.drain(..).collect::<Vec<_>>()));
+    if let Some(lines) = scripted {
+        return lines.iter().map(|l| format!("{}\n", l)).collect();
+    }
+    let mut bytes = Vec::new();
+    let _ = io::stdin().lock().read_to_end(&mut bytes);
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+pub fn ask(prompt: &str) -> String {
+    read_line(prompt).unwrap_or_default()
+}
+
+/// Read an answer of the kind `expect` names. A wrong answer is reported
+/// and asked for again, up to `ASK_RETRIES` times; the error is the message
+/// for a ValidationError once input ends or the retries run out. A plain
+/// `Ask for` takes the end of input as empty text, as it always has.
+pub fn ask_as(expect: AskType) -> Result<Answer, String> {
+    for attempt in 0..=ASK_RETRIES {
+        let line = match read_line("") {
+            Some(line) => line,
+            None if expect == AskType::Any => String::new(),
+            None => return Err(format!("input ended before {} was given", describe(expect))),
+        };
+        match parse_answer(&line, expect) {
+            Some(answer) => return Ok(answer),
+            None if attempt < ASK_RETRIES => {
+                write(&format!("'{}' is not {}. Please try again:", line, describe(expect)))
+            }
+            None => {}
+        }
+    }
+    Err(format!("no valid answer after {} tries: expected {}", ASK_RETRIES + 1, describe(expect)))
+}
+
+fn describe(expect: AskType) -> &'static str {
+    match expect {
+        AskType::Number => "a number",
+        AskType::YesNo => "yes or no",
+        AskType::Any | AskType::Text => "an answer",
+    }
+}
+
+fn parse_answer(line: &str, expect: AskType) -> Option<Answer> {
+    match expect {
+        AskType::Any => Some(
+            number::parse_number(line).map_or_else(|| Answer::Text(line.to_string()), Answer::Number),
+        ),
+        AskType::Number => number::parse_number(line).map(Answer::Number),
+        AskType::YesNo => match line.to_ascii_lowercase().as_str() {
+            "yes" | "y" | "true" => Some(Answer::Bool(true)),
+            "no" | "n" | "false" => Some(Answer::Bool(false)),
+            _ => None,
+        },
+        AskType::Text => Some(Answer::Text(line.to_string())),
+    }
 }
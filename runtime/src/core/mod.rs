@@ -1,4 +1,9 @@
 pub mod control;
+pub mod encoding;
+pub mod equality;
 pub mod functions;
+pub mod hints;
 pub mod io;
 pub mod math;
+pub mod number;
+pub mod table;
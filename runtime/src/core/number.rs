@@ -0,0 +1,248 @@
+//! Integer-aware arithmetic shared by the AST and bytecode VMs
+//!
+//! Whole-number literals are kept as `i64` so they stay exact; anything with
+//! a fractional part is an `f64`. Integer operations that overflow, and
+//! divisions that do not come out even, fall back to floats instead of
+//! wrapping or truncating. Mixing the two kinds always gives a float.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+/// Floats are printed with at most this many significant digits, which hides
+/// the representation error in results like `0.1 + 0.2`
+const FLOAT_DIGITS: usize = 15;
+
+impl Number {
+    /// A float result that is a whole number within range becomes an integer
+    pub fn from_whole(n: f64) -> Number {
+        if n.is_finite() && n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+            Number::Int(n as i64)
+        } else {
+            Number::Float(n)
+        }
+    }
+
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Number::Int(i) => i as f64,
+            Number::Float(f) => f,
+        }
+    }
+
+    pub fn is_zero(self) -> bool {
+        match self {
+            Number::Int(i) => i == 0,
+            Number::Float(f) => f == 0.0,
+        }
+    }
+
+    fn int_op(
+        self,
+        other: Number,
+        checked: fn(i64, i64) -> Option<i64>,
+        float: fn(f64, f64) -> f64,
+    ) -> Number {
+        if let (Number::Int(a), Number::Int(b)) = (self, other) {
+            if let Some(n) = checked(a, b) {
+                return Number::Int(n);
+            }
+        }
+        Number::Float(float(self.as_f64(), other.as_f64()))
+    }
+
+    pub fn abs(self) -> Number {
+        match self {
+            Number::Int(i) => i.checked_abs().map_or(Number::Float((i as f64).abs()), Number::Int),
+            Number::Float(f) => Number::Float(f.abs()),
+        }
+    }
+
+    pub fn round(self) -> Number {
+        self.whole(f64::round)
+    }
+
+    pub fn floor(self) -> Number {
+        self.whole(f64::floor)
+    }
+
+    pub fn ceil(self) -> Number {
+        self.whole(f64::ceil)
+    }
+
+    fn whole(self, f: fn(f64) -> f64) -> Number {
+        match self {
+            Number::Int(_) => self,
+            Number::Float(n) => Number::from_whole(f(n)),
+        }
+    }
+
+    /// Exact comparison, including between an integer and a float
+    pub fn compare(self, other: Number) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => Some(a.cmp(&b)),
+            (Number::Float(a), Number::Float(b)) => a.partial_cmp(&b),
+            (Number::Int(a), Number::Float(b)) => cmp_int_float(a, b),
+            (Number::Float(a), Number::Int(b)) => cmp_int_float(b, a).map(Ordering::reverse),
+        }
+    }
+
+    pub fn num_eq(self, other: Number) -> bool {
+        self.compare(other) == Some(Ordering::Equal)
+    }
+}
+
+impl Add for Number {
+    type Output = Number;
+
+    fn add(self, other: Number) -> Number {
+        self.int_op(other, i64::checked_add, |a, b| a + b)
+    }
+}
+
+impl Sub for Number {
+    type Output = Number;
+
+    fn sub(self, other: Number) -> Number {
+        self.int_op(other, i64::checked_sub, |a, b| a - b)
+    }
+}
+
+impl Mul for Number {
+    type Output = Number;
+
+    fn mul(self, other: Number) -> Number {
+        self.int_op(other, i64::checked_mul, |a, b| a * b)
+    }
+}
+
+impl Div for Number {
+    type Output = Number;
+
+    /// Division stays an integer only when it is exact; callers check for a
+    /// zero divisor first
+    fn div(self, other: Number) -> Number {
+        self.int_op(
+            other,
+            |a, b| match a.checked_rem(b) {
+                Some(0) => a.checked_div(b),
+                _ => None,
+            },
+            |a, b| a / b,
+        )
+    }
+}
+
+impl Neg for Number {
+    type Output = Number;
+
+    fn neg(self) -> Number {
+        match self {
+            Number::Int(i) => i.checked_neg().map_or(Number::Float(-(i as f64)), Number::Int),
+            Number::Float(f) => Number::Float(-f),
+        }
+    }
+}
+
+fn cmp_int_float(i: i64, f: f64) -> Option<Ordering> {
+    if f.is_nan() {
+        return None;
+    }
+    // i64::MAX as f64 rounds up to 2^63, which no i64 reaches
+    if f >= i64::MAX as f64 {
+        return Some(Ordering::Less);
+    }
+    if f < i64::MIN as f64 {
+        return Some(Ordering::Greater);
+    }
+    let whole = f.trunc();
+    Some(i.cmp(&(whole as i64)).then_with(|| {
+        let frac = f - whole;
+        if frac > 0.0 {
+            Ordering::Less
+        } else if frac < 0.0 {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }))
+}
+
+/// Parse user text such as `42`, `-7` or `2.5`, keeping whole numbers exact
+pub fn parse_number(s: &str) -> Option<Number> {
+    let s = s.trim();
+    if let Ok(i) = s.parse::<i64>() {
+        return Some(Number::Int(i));
+    }
+    s.parse::<f64>().ok().map(Number::Float)
+}
+
+/// Print a float with up to 15 significant digits and no trailing zeros
+pub fn format_float(n: f64) -> String {
+    if !n.is_finite() {
+        return n.to_string();
+    }
+    let rounded: f64 = format!("{:.*e}", FLOAT_DIGITS - 1, n)
+        .parse()
+        .unwrap_or(n);
+    if rounded == 0.0 {
+        // Avoid printing "-0"
+        return "0".to_string();
+    }
+    rounded.to_string()
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number::Int(i) => write!(f, "{}", i),
+            Number::Float(n) => f.write_str(&format_float(*n)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_int_arithmetic_promotes_on_overflow_and_inexact_division() {
+        assert_eq!(Number::Int(2) + Number::Int(3), Number::Int(5));
+        assert_eq!(Number::Int(i64::MAX) + Number::Int(1), Number::Float(i64::MAX as f64 + 1.0));
+        assert_eq!(-Number::Int(i64::MIN), Number::Float(-(i64::MIN as f64)));
+        assert_eq!(Number::Int(6) / Number::Int(3), Number::Int(2));
+        assert_eq!(Number::Int(7) / Number::Int(2), Number::Float(3.5));
+        assert_eq!(Number::Int(i64::MIN) / Number::Int(-1), Number::Float(-(i64::MIN as f64)));
+        assert_eq!(Number::Int(1) + Number::Float(0.5), Number::Float(1.5));
+    }
+
+    #[test]
+    fn test_compare_int_and_float_exactly() {
+        let big = Number::Int(9007199254740993);
+        assert_eq!(big.compare(Number::Float(9007199254740992.0)), Some(Ordering::Greater));
+        assert!(Number::Int(2).num_eq(Number::Float(2.0)));
+        assert_eq!(Number::Int(2).compare(Number::Float(2.5)), Some(Ordering::Less));
+        assert_eq!(Number::Float(-2.5).compare(Number::Int(-2)), Some(Ordering::Less));
+        assert_eq!(Number::Int(1).compare(Number::Float(f64::NAN)), None);
+    }
+
+    #[test]
+    fn test_formatting_and_parsing() {
+        assert_eq!(Number::Float(0.1 + 0.2).to_string(), "0.3");
+        assert_eq!(Number::Float(2.5).to_string(), "2.5");
+        assert_eq!(Number::Float(3.0).to_string(), "3");
+        assert_eq!(Number::Float(-0.0).to_string(), "0");
+        assert_eq!(Number::Int(9007199254740993).to_string(), "9007199254740993");
+        assert_eq!(parse_number(" 42 "), Some(Number::Int(42)));
+        assert_eq!(parse_number("2.5"), Some(Number::Float(2.5)));
+        assert_eq!(parse_number("abc"), None);
+        assert_eq!(Number::Float(2.5).round(), Number::Int(3));
+        assert_eq!(Number::Int(7).floor(), Number::Int(7));
+    }
+}
@@ -0,0 +1,162 @@
+//! Table layout for `Print table with headers ... and rows ...`
+//!
+//! Cells arrive already converted to text. Each column is as wide as its
+//! header or its widest cell, whichever is wider. A cell containing line
+//! breaks takes up several lines, and the rest of its row is padded to
+//! keep the columns lined up.
+
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TableStyle {
+    /// Borders drawn with box-drawing characters
+    #[default]
+    Box,
+    /// Space-aligned columns with a dashed line under the headers
+    Plain,
+    /// Comma-separated values, quoted where needed
+    Csv,
+}
+
+impl FromStr for TableStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "box" => Ok(TableStyle::Box),
+            "plain" => Ok(TableStyle::Plain),
+            "csv" => Ok(TableStyle::Csv),
+            _ => Err(format!(
+                "unsupported table style '{}' (expected plain, box or csv)",
+                s
+            )),
+        }
+    }
+}
+
+/// Lay out a table; every row must have at most `headers.len()` cells
+pub fn format_table(headers: &[String], rows: &[Vec<String>], style: TableStyle) -> String {
+    if style == TableStyle::Csv {
+        return std::iter::once(headers)
+            .chain(rows.iter().map(Vec::as_slice))
+            .map(|cells| csv_line(cells, headers.len()))
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+
+    let widths: Vec<usize> = (0..headers.len())
+        .map(|c| {
+            std::iter::once(&headers[c])
+                .chain(rows.iter().filter_map(|r| r.get(c)))
+                .flat_map(|cell| cell.lines())
+                .map(|line| line.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let mut out = Vec::new();
+    match style {
+        TableStyle::Box => {
+            out.push(border(&widths, '┌', '┬', '┐'));
+            push_row(&mut out, headers, &widths, style);
+            out.push(border(&widths, '├', '┼', '┤'));
+            for row in rows {
+                push_row(&mut out, row, &widths, style);
+            }
+            out.push(border(&widths, '└', '┴', '┘'));
+        }
+        _ => {
+            push_row(&mut out, headers, &widths, style);
+            let dashes: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+            out.push(dashes.join("  "));
+            for row in rows {
+                push_row(&mut out, row, &widths, style);
+            }
+        }
+    }
+    out.join("\n")
+}
+
+fn border(widths: &[usize], left: char, mid: char, right: char) -> String {
+    let parts: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+    format!("{}{}{}", left, parts.join(&mid.to_string()), right)
+}
+
+fn push_row(out: &mut Vec<String>, row: &[String], widths: &[usize], style: TableStyle) {
+    let cells: Vec<Vec<&str>> = (0..widths.len())
+        .map(|c| row.get(c).map_or_else(Vec::new, |cell| cell.lines().collect()))
+        .collect();
+    let height = cells.iter().map(Vec::len).max().unwrap_or(0).max(1);
+    for line in 0..height {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, w)| {
+                let text = cell.get(line).copied().unwrap_or("");
+                format!("{}{}", text, " ".repeat(w - text.chars().count()))
+            })
+            .collect();
+        if style == TableStyle::Box {
+            out.push(format!("│ {} │", padded.join(" │ ")));
+        } else {
+            out.push(padded.join("  ").trim_end().to_string());
+        }
+    }
+}
+
+/// Short rows are padded with empty fields so every line has `columns` fields
+fn csv_line(cells: &[String], columns: usize) -> String {
+    (0..columns)
+        .map(|c| {
+            let cell = cells.get(c).map_or("", String::as_str);
+            if cell.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", cell.replace('"', "\"\""))
+            } else {
+                cell.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(cells: &[&str]) -> Vec<String> {
+        cells.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn test_box_table_aligns_columns_and_wraps_multiline_cells() {
+        let headers = strings(&["Name", "Notes"]);
+        let rows = vec![strings(&["Ada", "first\nprogrammer"]), strings(&["Grace"])];
+        assert_eq!(
+            format_table(&headers, &rows, TableStyle::Box),
+            "┌───────┬────────────┐\n\
+             │ Name  │ Notes      │\n\
+             ├───────┼────────────┤\n\
+             │ Ada   │ first      │\n\
+             │       │ programmer │\n\
+             │ Grace │            │\n\
+             └───────┴────────────┘"
+        );
+    }
+
+    #[test]
+    fn test_plain_and_csv_styles() {
+        let headers = strings(&["Item", "Qty"]);
+        let rows = vec![strings(&["tea, green", "2"]), strings(&["say \"hi\""])];
+        assert_eq!(
+            format_table(&headers, &rows, TableStyle::Plain),
+            "Item        Qty\n----------  ---\ntea, green  2\nsay \"hi\""
+        );
+        assert_eq!(
+            format_table(&headers, &rows, TableStyle::Csv),
+            "Item,Qty\n\"tea, green\",2\n\"say \"\"hi\"\"\","
+        );
+        assert_eq!("CSV".parse::<TableStyle>(), Ok(TableStyle::Csv));
+        assert!("fancy".parse::<TableStyle>().is_err());
+    }
+}
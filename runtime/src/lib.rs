@@ -1,11 +1,16 @@
 pub mod bytecode;
+#[cfg(feature = "capi")]
+pub mod capi;
 pub mod core;
 pub mod parser;
 pub mod stdlib;
+pub mod transpile;
 pub mod vm;
 
 // Optional: expose a simple runtime API
-pub fn execute_program(program: &parser::Program) -> anyhow::Result<()> {
+/// Run a program in a fresh VM; a top-level `Return` ends it and its value
+/// comes back here
+pub fn execute_program(program: &parser::Program) -> anyhow::Result<Option<vm::ProgramValue>> {
     let mut v = vm::Vm::default();
-    v.execute(program)
+    v.run(program)
 }
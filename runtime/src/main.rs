@@ -1,6 +1,11 @@
+use pohlang::core::encoding::{self, SourceEncoding};
+use pohlang::core::table::TableStyle;
 use pohlang::{bytecode, parser, vm};
 use std::fs;
-use std::path::PathBuf;
+use std::io::IsTerminal;
+use std::panic::{self, AssertUnwindSafe, PanicHookInfo};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 #[derive(clap::Parser, Debug)]
 #[command(name = "pohlang", version, about = "PohLang compiler/runtime")]
@@ -29,24 +34,175 @@ struct Args {
     #[arg(long)]
     disassemble: bool,
 
-    /// Show execution statistics (instruction counts, timing, cache stats)
+    /// Disassemble .pbc file as JSON
+    #[arg(long)]
+    disassemble_json: bool,
+
+    /// Show execution statistics (instruction counts, timing, cache stats);
+    /// with --run, a read/parse/execute breakdown on stderr
     #[arg(long)]
     stats: bool,
 
+    /// Run the program N times and report min/avg/max timings
+    /// (AST interpreter, or bytecode VM with --bytecode)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    bench: Option<u32>,
+
+    /// Record the calls made inside Benchmark blocks and write a flame graph
+    /// of them to flamegraph.svg after each block (with --run or --bench)
+    #[arg(long)]
+    bench_flamegraph: bool,
+
     /// Ahead-of-time compile to a native executable (stub)
     #[arg(long)]
     aot: bool,
 
+    /// Encoding of .poh source files: utf-8, latin-1 or windows-1252
+    #[arg(long, value_name = "NAME", default_value = "utf-8")]
+    input_encoding: SourceEncoding,
+
+    /// Use a whole-number top-level Return value as the process exit code
+    #[arg(long)]
+    exit_with_return: bool,
+
+    /// Print each statement to stderr as it runs (with --run)
+    #[arg(long)]
+    trace: bool,
+
+    /// Only trace inside calls of this function; implies --trace
+    #[arg(long, value_name = "FUNCTION")]
+    trace_filter: Option<String>,
+
+    /// Stop before the first statement and read debugger commands from stdin
+    /// (with --run). Needs a terminal, unless POHLANG_DEBUG_STDIN=1 is set to
+    /// read scripted commands from a pipe
+    #[arg(long)]
+    debug: bool,
+
+    /// Report internal errors (Rust panics), including ones on server
+    /// threads, as a short message asking for a bug report. The Rust
+    /// backtrace is only shown with --debug or RUST_BACKTRACE=1
+    #[arg(long)]
+    reformat_errors: bool,
+
+    /// Make assigning a value of the wrong type to a variable declared with
+    /// 'Set x as Type to ...' a runtime TypeError (with --run)
+    #[arg(long)]
+    strict_types: bool,
+
+    /// Print something about the source instead of running it. "tokens"
+    /// prints each token's byte range, kind and text, one per line;
+    /// "normalized" prints the parsed program back as PohLang, with
+    /// shorthands written out as what they mean
+    #[arg(long, value_name = "WHAT")]
+    emit: Option<Emit>,
+
+    /// Print the program translated into another language instead of
+    /// running it, or write it to --out. "js" is JavaScript for Node or a
+    /// browser, with a small runtime of its own ahead of the program
+    #[arg(long, value_name = "TARGET")]
+    transpile: Option<Target>,
+
+    /// Make soft failures errors: undefined variables, ordering values that
+    /// are not numbers and non-number Repeat counts (with --run), and what
+    /// the bytecode compiler would leave out (with --bytecode or --compile).
+    /// Implies --strict-types and --report-const-candidates
+    #[arg(long)]
+    strict: bool,
+
+    /// Make 'Restore checkpoint' ignore the checkpoints saved by earlier
+    /// runs, so the program starts over (with --run)
+    #[arg(long)]
+    no_resume: bool,
+
+    /// Stop the program with an uncatchable MemoryLimitExceeded error when
+    /// it builds a value larger than about this many bytes (with --run)
+    #[arg(long, value_name = "BYTES")]
+    memory_limit: Option<usize>,
+
+    /// Before running, hint at each variable set once to a literal and never
+    /// changed, which could be a 'Set constant' (with --run)
+    #[arg(long)]
+    report_const_candidates: bool,
+
+    /// Print the files the program imports, and the files they import, as a
+    /// tree instead of running it. Nothing is run to find them
+    #[arg(long)]
+    tree: bool,
+
+    /// Layout for Print table output: box, plain or csv
+    #[arg(long, value_name = "STYLE", default_value = "box")]
+    table_style: TableStyle,
+
     /// Input .poh or .pbc file
     input: PathBuf,
 
-    /// Output path (for --compile or --aot)
+    /// Output path (for --compile, --aot or --transpile)
     #[arg(short, long)]
     out: Option<PathBuf>,
 }
 
+/// What --emit prints
+#[derive(Clone, Copy, Debug)]
+enum Emit {
+    Tokens,
+    Normalized,
+}
+
+impl std::str::FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tokens" => Ok(Emit::Tokens),
+            "normalized" => Ok(Emit::Normalized),
+            _ => Err(format!("unsupported --emit '{}' (expected tokens or normalized)", s)),
+        }
+    }
+}
+
+/// What --transpile translates into
+#[derive(Clone, Copy, Debug)]
+enum Target {
+    Js,
+}
+
+impl std::str::FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "js" | "javascript" => Ok(Target::Js),
+            _ => Err(format!("unsupported --transpile '{}' (expected js)", s)),
+        }
+    }
+}
+
+/// Where --bench-flamegraph writes, in the current directory
+const FLAMEGRAPH_FILE: &str = "flamegraph.svg";
+
 fn main() -> anyhow::Result<()> {
     let args = <Args as clap::Parser>::parse();
+    if !args.reformat_errors {
+        return run(args);
+    }
+    let backtrace = args.debug || std::env::var("RUST_BACKTRACE").is_ok_and(|v| v == "1" || v == "full");
+    // The hook reports panics on every thread as they happen; catching the
+    // unwind here only keeps the main thread's from being reported twice
+    panic::set_hook(Box::new(move |info| eprint!("{}", panic_report(info, backtrace))));
+    match panic::catch_unwind(AssertUnwindSafe(|| run(args))) {
+        Ok(result) => result,
+        Err(_) => std::process::exit(101),
+    }
+}
+
+fn run(args: Args) -> anyhow::Result<()> {
+    // Lets tests check how internal errors are reported; debug builds only,
+    // so a released binary cannot be made to panic from its environment
+    #[cfg(debug_assertions)]
+    if let Some(message) = std::env::var_os("POHLANG_TEST_PANIC") {
+        panic!("{}", message.to_string_lossy());
+    }
 
     // Handle --run-bytecode: Execute pre-compiled .pbc file
     if args.run_bytecode {
@@ -54,7 +210,12 @@ fn main() -> anyhow::Result<()> {
         let chunk = bytecode::BytecodeDeserializer::deserialize(&bytes)?;
         let mut vm = bytecode::BytecodeVM::new();
         vm.load(chunk);
-        let _result = vm.run()?;
+        let result = vm.run()?;
+        if args.exit_with_return {
+            if let bytecode::Value::Integer(code) = result {
+                exit_with(code);
+            }
+        }
 
         // Show statistics if requested
         if args.stats {
@@ -67,32 +228,115 @@ fn main() -> anyhow::Result<()> {
     if args.disassemble {
         let bytes = fs::read(&args.input)?;
         let chunk = bytecode::BytecodeDeserializer::deserialize(&bytes)?;
-        println!("=== Bytecode Disassembly ===");
-        println!("Version: {}", chunk.version);
-        println!("Constants: {} entries", chunk.constants.len());
-        for (i, constant) in chunk.constants.iter().enumerate() {
-            println!("  [{}] {:?}", i, constant);
-        }
-        println!("\nCode: {} instructions", chunk.code.len());
-        for (i, instruction) in chunk.code.iter().enumerate() {
-            println!("  {:04} {:?}", i, instruction);
-        }
-        if let Some(debug_info) = &chunk.debug_info {
-            println!("\nDebug Info:");
-            println!("  Source: {}", debug_info.source_file);
-            println!("  Line numbers: {} entries", debug_info.line_numbers.len());
-            println!("  Variables: {} entries", debug_info.variable_names.len());
-        }
+        let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+        print!("{}", bytecode::disassembler::disassemble(&chunk, color));
+        return Ok(());
+    }
+
+    // Handle --disassemble-json: Machine-readable disassembly
+    if args.disassemble_json {
+        let bytes = fs::read(&args.input)?;
+        let chunk = bytecode::BytecodeDeserializer::deserialize(&bytes)?;
+        let listing = bytecode::disassembler::disassemble_json(&chunk);
+        println!("{}", serde_json::to_string_pretty(&listing)?);
+        return Ok(());
+    }
+
+    if args.debug && !std::io::stdin().is_terminal() && std::env::var_os("POHLANG_DEBUG_STDIN").is_none() {
+        anyhow::bail!(
+            "--debug reads commands from a terminal, but stdin is not one. Set POHLANG_DEBUG_STDIN=1 to read them from a pipe"
+        );
+    }
+
+    if args.tree {
+        print!("{}", vm::dependency::import_tree(&args.input, args.input_encoding)?);
         return Ok(());
     }
 
     // For all other modes, we need to parse the source
-    let src = fs::read_to_string(&args.input)?;
-    let program = parser::parse(&src)?;
+    let read_start = Instant::now();
+    let src = encoding::read_source(&args.input, args.input_encoding)?;
+    let read_time = read_start.elapsed();
+    if let Some(Emit::Tokens) = args.emit {
+        for token in parser::tokens::tokenize(&src) {
+            println!(
+                "{}..{} {} {:?}",
+                token.span.start,
+                token.span.end,
+                token.kind.name(),
+                token.text(&src)
+            );
+        }
+        return Ok(());
+    }
+    if let Some(Emit::Normalized) = args.emit {
+        print!("{}", vm::vm::dump_program(&parser::parse(&src)?));
+        return Ok(());
+    }
+    if let Some(Target::Js) = args.transpile {
+        // Lines let unsupported statements be reported where they are
+        let js = pohlang::transpile::js::transpile(&parser::parse_with_lines(&src)?)?;
+        match &args.out {
+            Some(path) => fs::write(path, js)?,
+            None => print!("{}", js),
+        }
+        return Ok(());
+    }
+    let parse_start = Instant::now();
+    let tracing = args.trace || args.trace_filter.is_some();
+    let program = if tracing || args.debug {
+        parser::parse_with_lines(&src)?
+    } else {
+        parser::parse(&src)?
+    };
+    let parse_time = parse_start.elapsed();
+    let hints = match args.report_const_candidates || args.strict {
+        true => vm::lint::const_candidates(&program),
+        false => Vec::new(),
+    };
+    for warning in vm::lint::discarded_results(&program)
+        .into_iter()
+        .chain(vm::lint::type_mismatches(&program))
+        .chain(hints)
+    {
+        eprintln!("{}", warning);
+    }
+
+    // Handle --bench: Time repeated runs of the program
+    if let Some(runs) = args.bench {
+        let mut times = Vec::with_capacity(runs as usize);
+        if args.bytecode {
+            let chunk = bytecode::Compiler::new().strict(args.strict).compile(program)?;
+            for _ in 0..runs {
+                let start = Instant::now();
+                let mut vm = bytecode::BytecodeVM::new();
+                vm.load(chunk.clone());
+                vm.run()?;
+                times.push(start.elapsed());
+            }
+        } else {
+            let base_dir = args.input.parent().unwrap_or_else(|| Path::new("."));
+            for _ in 0..runs {
+                let start = Instant::now();
+                let mut vm = vm::Vm::with_base_dir(base_dir.to_path_buf());
+                vm.set_current_file(args.input.display().to_string());
+                vm.set_source_encoding(args.input_encoding);
+                vm.set_table_style(args.table_style);
+                if args.bench_flamegraph {
+                    vm.enable_flamegraph(PathBuf::from(FLAMEGRAPH_FILE));
+                }
+                vm.execute(&program)?;
+                times.push(start.elapsed());
+            }
+        }
+        // Timings go to stderr so stdout stays the program's own output
+        eprintln!("{}", bench_summary(&times));
+        return Ok(());
+    }
 
     // Handle --compile: Compile .poh to .pbc
     if args.compile {
-        let compiler = bytecode::Compiler::new();
+        let compiler = bytecode::Compiler::new().strict(args.strict);
         let chunk = compiler.compile(program)?;
         let bc_path = args.out.unwrap_or_else(|| args.input.with_extension("pbc"));
         bytecode::BytecodeSerializer::save_to_file(&chunk, &bc_path)?;
@@ -107,11 +351,16 @@ fn main() -> anyhow::Result<()> {
 
     // Handle --bytecode: Compile and run with bytecode VM
     if args.bytecode {
-        let compiler = bytecode::Compiler::new();
+        let compiler = bytecode::Compiler::new().strict(args.strict);
         let chunk = compiler.compile(program)?;
         let mut vm = bytecode::BytecodeVM::new();
         vm.load(chunk);
-        let _result = vm.run()?;
+        let result = vm.run()?;
+        if args.exit_with_return {
+            if let bytecode::Value::Integer(code) = result {
+                exit_with(code);
+            }
+        }
 
         // Show statistics if requested
         if args.stats {
@@ -125,22 +374,66 @@ fn main() -> anyhow::Result<()> {
         let base_dir = args
             .input
             .parent()
-            .unwrap_or_else(|| std::path::Path::new("."))
+            .unwrap_or_else(|| Path::new("."))
             .to_path_buf();
 
         let mut vm = vm::Vm::with_base_dir(base_dir.clone());
 
         // Set the current file being executed
         vm.set_current_file(args.input.display().to_string());
+        vm.set_source_encoding(args.input_encoding);
+        vm.set_table_style(args.table_style);
 
         // Enable hot reload if --watch flag is set
+        #[cfg(not(feature = "web"))]
+        if args.watch {
+            anyhow::bail!("--watch reloads the pages of a web server, and web support is not compiled in");
+        }
+        #[cfg(feature = "web")]
         if args.watch {
             vm.enable_hot_reload(vec![base_dir.clone()]);
             println!("🔥 Hot reload enabled! Changes will be detected automatically.");
             println!("💡 Watching: {}", base_dir.clone().display());
         }
 
-        vm.execute(&program)?;
+        if args.stats {
+            vm.enable_stats();
+        }
+        if args.bench_flamegraph {
+            vm.enable_flamegraph(PathBuf::from(FLAMEGRAPH_FILE));
+        }
+        if args.strict {
+            vm.enable_strict();
+        } else if args.strict_types {
+            vm.enable_strict_types();
+        }
+        if args.no_resume {
+            vm.disable_resume();
+        }
+        if let Some(bytes) = args.memory_limit {
+            vm.set_memory_limit(bytes);
+        }
+        if tracing {
+            vm.enable_trace(args.trace_filter.clone());
+        }
+        if args.debug {
+            vm.enable_debug(Box::new(debug_prompt));
+        }
+        let exec_start = Instant::now();
+        let returned = vm.run(&program)?;
+        if args.stats {
+            let times = PhaseTimes {
+                read: read_time,
+                parse: parse_time,
+                execute: exec_start.elapsed(),
+            };
+            eprintln!("{}", stats_report(&times, &program, &vm.stats()));
+        }
+        if args.exit_with_return {
+            if let Some(code) = returned.as_ref().and_then(vm::ProgramValue::as_i64) {
+                exit_with(code);
+            }
+        }
         return Ok(());
     }
 
@@ -157,5 +450,138 @@ fn main() -> anyhow::Result<()> {
     eprintln!("  --compile       Compile to .pbc file");
     eprintln!("  --run-bytecode  Execute .pbc file");
     eprintln!("  --disassemble   Show bytecode instructions");
+    eprintln!("  --disassemble-json  Show bytecode instructions as JSON");
+    eprintln!("  --bench N       Time N runs and report min/avg/max");
     Ok(())
 }
+
+/// The --debug command loop, run at each stop until a command resumes the
+/// program. Everything it writes goes to stderr, apart from program output.
+fn debug_prompt(stop: &mut vm::debug::Stop<'_>) -> vm::debug::Resume {
+    use vm::debug::Resume;
+    let line = stop.line.map_or_else(|| "?".to_string(), |n| n.to_string());
+    eprintln!("[debug] line {}: {}", line, stop.statement);
+    loop {
+        eprint!("(debug) ");
+        let mut input = String::new();
+        // End of input runs the rest of the program without stopping
+        if std::io::stdin().read_line(&mut input).unwrap_or(0) == 0 {
+            eprintln!();
+            stop.breakpoints.clear();
+            return Resume::Continue;
+        }
+        let input = input.trim();
+        let (command, rest) = input.split_once(' ').map_or((input, ""), |(c, r)| (c, r.trim()));
+        match command {
+            "step" | "s" => return Resume::Step,
+            "next" | "n" => return Resume::Next,
+            "continue" | "c" => return Resume::Continue,
+            "print" | "p" if !rest.is_empty() => match stop.eval(rest) {
+                Ok(value) => eprintln!("{}", value),
+                Err(e) => eprintln!("error: {}", e),
+            },
+            "vars" => {
+                let locals = stop.locals();
+                if !locals.is_empty() {
+                    eprintln!("locals:");
+                    for (name, value) in locals {
+                        eprintln!("  {} = {}", name, value);
+                    }
+                }
+                eprintln!("globals:");
+                for (name, value) in stop.globals() {
+                    eprintln!("  {} = {}", name, value);
+                }
+            }
+            "break" | "b" => match rest.parse::<usize>() {
+                Ok(n) if n > 0 => {
+                    stop.breakpoints.insert(n);
+                    eprintln!("Breakpoint at line {}", n);
+                }
+                _ => eprintln!("usage: break <line>"),
+            },
+            "" => {}
+            _ => eprintln!("commands: step, next, continue, print <expr>, vars, break <line>"),
+        }
+    }
+}
+
+/// What --reformat-errors prints for a panic
+fn panic_report(info: &PanicHookInfo<'_>, backtrace: bool) -> String {
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message");
+    let mut report = String::from("Oops! PohLang encountered an internal error. Please report this bug.\n");
+    report.push_str(&format!("  {}", message));
+    if let Some(location) = info.location() {
+        report.push_str(&format!(" (at {}:{})", location.file(), location.line()));
+    }
+    if let Some(name) = std::thread::current().name().filter(|&name| name != "main") {
+        report.push_str(&format!(" in thread '{}'", name));
+    }
+    report.push_str(
+        "\nPlease open an issue at https://github.com/AlhaqGH/PohLang/issues and include the program that caused it.\n",
+    );
+    if backtrace {
+        report.push_str(&format!("{}\n", std::backtrace::Backtrace::force_capture()));
+    }
+    report
+}
+
+/// Exit codes outside the i32 range are reported as a plain failure
+fn exit_with(code: i64) -> ! {
+    std::process::exit(i32::try_from(code).unwrap_or(1))
+}
+
+struct PhaseTimes {
+    read: Duration,
+    parse: Duration,
+    execute: Duration,
+}
+
+fn stats_report(times: &PhaseTimes, program: &parser::Program, stats: &vm::ExecutionStats) -> String {
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    let (statements, functions) = vm::stats::parsed_counts(program);
+    let mut lines = vec![
+        "--- Execution statistics ---".to_string(),
+        format!("Read file:       {:.3} ms", ms(times.read)),
+        format!(
+            "Parse:           {:.3} ms ({} statements, {} functions)",
+            ms(times.parse),
+            statements,
+            functions
+        ),
+        format!("Execute:         {:.3} ms", ms(times.execute)),
+        format!("Statements run:  {}", stats.statements_executed),
+        format!("Function calls:  {}", stats.function_calls),
+    ];
+    let top = stats.top_builtins(10);
+    if top.is_empty() {
+        lines.push("Built-in calls:  none".to_string());
+    } else {
+        lines.push("Built-in calls:".to_string());
+        let width = top.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        for (name, count) in top {
+            lines.push(format!("  {:<width$}  {}", name, count, width = width));
+        }
+    }
+    lines.join("\n")
+}
+
+fn bench_summary(times: &[Duration]) -> String {
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    let min = times.iter().min().copied().unwrap_or_default();
+    let max = times.iter().max().copied().unwrap_or_default();
+    let total: Duration = times.iter().sum();
+    let avg = total / times.len().max(1) as u32;
+    format!(
+        "{} runs: min {:.3} ms, avg {:.3} ms, max {:.3} ms",
+        times.len(),
+        ms(min),
+        ms(avg),
+        ms(max)
+    )
+}
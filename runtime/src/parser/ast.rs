@@ -1,10 +1,14 @@
 #[derive(Debug, Clone)]
 pub enum Expr {
     Str(String),
+    RegexLit(String), // r"pattern", compiled when evaluated
+    Int(i64),
     Num(f64),
     Bool(bool),
     Null,
     Ident(String),
+    Local(u16),     // function local resolved to a frame slot
+    Global(String), // name known not to be a function local
     Plus(Box<Expr>, Box<Expr>),
     Minus(Box<Expr>, Box<Expr>),
     Times(Box<Expr>, Box<Expr>),
@@ -16,18 +20,31 @@ pub enum Expr {
     And(Box<Expr>, Box<Expr>),
     Or(Box<Expr>, Box<Expr>),
     Not(Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>), // A if cond else B, as (cond, A, B)
     Cmp(CmpOp, Box<Expr>, Box<Expr>),
     ListLit(Vec<Expr>),
+    TupleLit(Vec<Expr>), // tuple of X, Y, Z
     DictLit(Vec<(String, Expr)>),
     Index(Box<Expr>, Box<Expr>), // collection[index]
+    Memoize(Box<Expr>),          // function wrapped with a result cache
+    TimedCall(String, Vec<Expr>), // time calling f with args: {"result", "milliseconds"}
+    ApplyInParallel(String, Box<Expr>, Option<Box<Expr>>), // apply f to each item in list in parallel [with n workers]
     // Phrasal built-in expressions
     TotalOf(Box<Expr>),             // total of list
     SmallestIn(Box<Expr>),          // smallest in list
     LargestIn(Box<Expr>),           // largest in list
+    AverageOf(Box<Expr>),           // average of list
+    MedianOf(Box<Expr>),            // median of list
+    VarianceOf(Box<Expr>),          // variance of list
+    StdDevOf(Box<Expr>),            // standard deviation of list
     AbsoluteValueOf(Box<Expr>),     // absolute value of number
     Round(Box<Expr>),               // round number
     RoundDown(Box<Expr>),           // round down number
     RoundUp(Box<Expr>),             // round up number
+    Lerp(Box<Expr>, Box<Expr>, Box<Expr>),  // interpolate from a to b at t
+    Clamp(Box<Expr>, Box<Expr>, Box<Expr>), // clamp x between low and high
+    // map x from range a to b into range c to d
+    MapRange(Box<Expr>, Box<Expr>, Box<Expr>, Box<Expr>, Box<Expr>),
     MakeUppercase(Box<Expr>),       // make uppercase string
     MakeLowercase(Box<Expr>),       // make lowercase string
     TrimSpaces(Box<Expr>),          // trim spaces from string
@@ -35,6 +52,9 @@ pub enum Expr {
     LastIn(Box<Expr>),              // last in list/string
     ReverseOf(Box<Expr>),           // reverse of list/string
     CountOf(Box<Expr>),             // count of list/string/dict
+    TypeOf(Box<Expr>),              // type of value: "number", "list", ...
+    WaitFor(Box<Expr>),             // wait for task: what its body returned
+    MakeChannel,                    // make a channel
     JoinWith(Box<Expr>, Box<Expr>), // join list with separator
     SplitBy(Box<Expr>, Box<Expr>),  // split string by separator
     // Additional collection operations
@@ -42,6 +62,14 @@ pub enum Expr {
     Remove(Box<Expr>, Box<Expr>),   // remove item from list
     Append(Box<Expr>, Box<Expr>),   // append item to list
     InsertAt(Box<Expr>, Box<Expr>, Box<Expr>), // insert item at index in list
+    // Sets
+    SetLit(Vec<Expr>),                      // set containing X, Y, Z; empty set
+    SetUnion(Box<Expr>, Box<Expr>),         // union of A and B
+    SetIntersection(Box<Expr>, Box<Expr>),  // intersection of A and B
+    SetDifference(Box<Expr>, Box<Expr>),    // difference of A and B: members of A not in B
+    SetContains(Box<Expr>, Box<Expr>),      // set S contains X
+    SetToList(Box<Expr>),                   // list of set, its members sorted
+    ListToSet(Box<Expr>),                   // set of list
     // File I/O operations
     ReadFile(Box<Expr>),              // read file at path
     WriteFile(Box<Expr>, Box<Expr>),  // write content to file at path
@@ -58,11 +86,33 @@ pub enum Expr {
     ToJson(Box<Expr>),                        // convert to json string
     ToJsonPretty(Box<Expr>),                  // convert to pretty json string
     JsonGet(Box<Expr>, Box<Expr>),            // get key from json object
+    JsonQuery(Box<Expr>, Box<Expr>),          // query json X with path "$.a[*].b"
     JsonSet(Box<Expr>, Box<Expr>, Box<Expr>), // set key in json object to value
     NewJsonObject,                            // new json object
     NewJsonArray,                             // new json array
     JsonPush(Box<Expr>, Box<Expr>),           // push item to json array
     JsonLength(Box<Expr>),                    // json length of array/object
+    Pick(Box<Expr>, Vec<String>),             // pick "a", "b" from dict
+    Omit(Box<Expr>, Vec<String>),             // omit "a", "b" from dict
+    FindFirst(Box<Expr>, Box<Expr>),          // find first item in list where predicate
+    FindAll(Box<Expr>, Box<Expr>),            // find all items in list where predicate
+    FindIndex(Box<Expr>, Box<Expr>),          // find index of first item in list where predicate
+    GroupConcat(Box<Expr>, Box<Expr>),        // collect field "name" from each item in list
+    Pluck(Box<Expr>, Box<Expr>),              // collect fields "name", "email" from each item in list
+    Aggregate(Box<Expr>, Vec<AggField>),      // aggregate list X computing "total" as sum of "price"
+    Validate(Box<Expr>, String),              // validate X as Schema: the field errors, empty if none
+    Coerce(Box<Expr>, String),                // coerce X as Schema: the schema's fields, defaults filled in
+    ValidateAgainst(Box<Expr>, Box<Expr>),    // validate X against S: nothing, or a list of problems
+    ReadFromStdin,                            // read from stdin: all of the input, up to its end
+    // URL operations
+    ParseUrl(Box<Expr>),  // parse url X
+    BuildUrl(Box<Expr>),  // build url from dict
+    UrlEncode(Box<Expr>), // url encode text
+    UrlDecode(Box<Expr>), // url decode text
+    IndentBy(Box<Expr>, Box<Expr>), // indent text by N spaces
+    Dedent(Box<Expr>),              // remove common indent from text
+    WrapText(Box<Expr>, Box<Expr>), // wrap text at N characters
+    Pretty(Box<Expr>),    // Write X nicely: indented, one entry per line
     // Error handling operations
     ErrorMessage(Box<Expr>), // error message of error
     ErrorType(Box<Expr>),    // error type of error
@@ -71,21 +121,95 @@ pub enum Expr {
         message: Box<Expr>,
     }, // error of type X with message Y
     // Web server operations
-    CreateWebServer(Box<Expr>),               // create web server on port
+    // create [secure] web server on port P [with certificate C and key K]
+    //     [with body limit N bytes] [with request timeout S seconds]
+    CreateWebServer {
+        port: Box<Expr>,
+        body_limit: Option<Box<Expr>>,      // in bytes
+        request_timeout: Option<Box<Expr>>, // in seconds
+        tls: Option<(Box<Expr>, Box<Expr>)>, // certificate and key files
+    },
     HtmlResponse(Box<Expr>),                  // html response with content
     JsonResponse(Box<Expr>),                  // json response with data
     JsonResponseStatus(Box<Expr>, Box<Expr>), // json response with data and status
     RenderTemplate(Box<Expr>, Box<Expr>),     // render template with data
+    RenderTemplateFile(Box<Expr>, Box<Expr>), // render template file "users.html" with data
     ErrorResponse(Box<Expr>, Box<Expr>),      // error response with status and message
     RequestField(Box<Expr>, Box<Expr>),       // request["field"]
     GetPathParam(Box<Expr>),                  // get path parameter by name
+    GetRequestHeader(Box<Expr>),              // get request header by name
+    SetResponseHeaderExpr(Box<Expr>, Box<Expr>), // set response header X to Y
+    WithEtag(Box<Expr>),                      // response with etag
+    WithCache(Box<Expr>, Box<Expr>),          // response with cache for S seconds
+    GetRequestMethod,                         // request method
+    GetRequestPath,                           // request path
+    GetRemoteAddr,                            // client address
+    PreferredResponseType,                    // preferred response type of request
+    // start event stream [with heartbeat every S seconds]
+    StartEventStream {
+        heartbeat: Option<Box<Expr>>, // in seconds
+    },
+    // receive message from S [with timeout T seconds]
+    ReceiveMessage {
+        socket: Box<Expr>,
+        timeout: Option<Box<Expr>>, // in seconds
+    },
+    // receive from C [within T seconds]
+    ReceiveFrom {
+        channel: Box<Expr>,
+        timeout: Option<Box<Expr>>, // in seconds
+    },
 }
 
+impl Expr {
+    /// A number, text, boolean or nothing, or a list or dictionary of those:
+    /// what `Set constant` accepts, known before the program runs
+    pub fn is_literal(&self) -> bool {
+        match self {
+            Expr::Str(_) | Expr::Int(_) | Expr::Num(_) | Expr::Bool(_) | Expr::Null => true,
+            // A negative number is parsed as `0 minus <number>`
+            Expr::Minus(zero, n) => {
+                matches!(**zero, Expr::Int(0)) && matches!(**n, Expr::Int(_) | Expr::Num(_))
+            }
+            Expr::ListLit(items) => items.iter().all(Expr::is_literal),
+            Expr::DictLit(pairs) => pairs.iter().all(|(_, v)| v.is_literal()),
+            _ => false,
+        }
+    }
+
+    /// A chain of `plus`, `minus`, `times` and `divided by` as its leftmost
+    /// term and each operator with its right operand, innermost first.
+    /// `a plus b plus c` nests to the left one level a term, so walkers take
+    /// chains apart with this instead of recursing down a long sum.
+    pub fn arithmetic_chain(&self) -> (&Expr, Vec<(&Expr, &Expr)>) {
+        let mut terms = Vec::new();
+        let mut left = self;
+        while let Expr::Plus(a, b) | Expr::Minus(a, b) | Expr::Times(a, b) | Expr::DividedBy(a, b) = left {
+            terms.push((left, b.as_ref()));
+            left = a;
+        }
+        terms.reverse();
+        (left, terms)
+    }
+}
+
+/// The method `Add websocket route` keeps its routes under
+pub const WEBSOCKET_METHOD: &str = "WEBSOCKET";
+
 #[derive(Debug, Clone)]
 pub enum Stmt {
     Write(Expr),
+    /// `Write a, b, c [separated by sep] [ending with end]`: one line of
+    /// several values, joined by `sep` (a space if not given) and ended by
+    /// `end` (a newline if not given)
+    MultiWrite {
+        exprs: Vec<Expr>,
+        sep: Option<Expr>,
+        end: Option<Expr>,
+    },
     AskFor {
         var_name: String,
+        expect: AskType,
     },
     IfInline {
         cond: Expr,
@@ -115,9 +239,21 @@ pub enum Stmt {
         count: Expr,
         body: Program,
     },
+    RepeatUntil {
+        cond: Expr,
+        body: Program,
+    }, // checked before each pass, like While Not cond
     ImportLocal {
         path: String,
     },
+    ImportAs {
+        path: String,
+        alias: String,
+    },
+    ImportItems {
+        path: String,
+        items: Vec<String>,
+    }, // Import a, b from "file": only the named functions and variables
     ImportSystem {
         name: String,
         alias: Option<String>,
@@ -126,11 +262,38 @@ pub enum Stmt {
     Use {
         name: String,
         args: Vec<Expr>,
+        /// Set by `storing result in`: where the returned value goes
+        result_var: Option<String>,
     },
     Set {
         name: String,
         value: Expr,
     },
+    SetLocal {
+        slot: u16,
+        value: Expr,
+    }, // Set resolved to a frame slot
+    GlobalConst {
+        name: String,
+        value: Expr,
+    }, // Set constant name to a literal; fixed before the program starts
+    SetItem {
+        name: String,
+        key: Expr,
+        value: Expr,
+    }, // Set name[key] to value; also Increase/Decrease of an item
+    Destructure {
+        names: Vec<String>,
+        value: Expr,
+    }, // Set a, b to tuple/list
+    PutEnvInScope {
+        prefix: String,
+        strip_prefix: bool,
+    }, // Load env vars starting with "APP_" [stripping prefix]
+    PrintTable {
+        headers: Expr,
+        rows: Expr,
+    }, // Print table with headers X and rows Y
     Return(Option<Expr>),
     TryCatch {
         try_block: Program,
@@ -138,17 +301,162 @@ pub enum Stmt {
         finally_block: Option<Program>,
     },
     Throw(Expr),
+    Log {
+        to_file: Option<Expr>, // None: stderr only
+        rotate_size: Option<Expr>, // bytes
+    },
+    LogMessage {
+        level: LogLevel,
+        message: Expr,
+    },
     // Web server statements
     AddRoute {
         path: Expr,
         method: Expr,
         handler: Program,
+        line: usize, // 1-based line of the statement, for error messages
     },
+    Route {
+        method: String,
+        path: String,
+        server: Option<String>, // the variable holding the server; `server` if not named
+        handler: Program,
+        line: usize,
+    }, // Route GET "/users" [on server api]: ... End Route
+    RouteGroup {
+        prefix: Expr,
+        body: Program,
+    }, // Add route group "/api" to server: ... End group
+    GroupMiddleware {
+        body: Program,
+    }, // Add middleware to group: ... End middleware
     AddMiddleware {
         middleware_type: String,
         config: Vec<(String, Expr)>, // Configuration key-value pairs
     },
+    RateLimit {
+        max_requests: Expr,
+        per_seconds: Expr,
+        key_by: Option<Expr>, // function from the request to its key; client address if absent
+    },
+    EnableMetrics {
+        path: Expr,
+        line: usize,
+    }, // Enable metrics on server at "/metrics"
+    ServeStaticFiles(Expr), // Serve static files from "public"
+    TemplateDirectory(Expr), // Set template directory to "templates"
+    Respond(Expr), // Respond with value: html, json or text, as the request's Accept header prefers
     StartServer,
+    Evaluate(Expr), // an expression run for its effect, e.g. set response header X to Y
+    OnShutdown {
+        body: Program,
+    }, // On shutdown: ... End On Shutdown
+    EventEmitter {
+        name: String,
+    }, // Create event emitter X
+    On {
+        emitter: String,
+        event: String,
+        body: Program,
+    }, // On event "click" from emitter X: ... End On
+    Emit {
+        emitter: String,
+        event: String,
+        data: Expr,
+    }, // Emit "click" from X with data Y
+    DataBinding {
+        var: String,
+        to: Expr,
+        on_change: Program,
+    }, // Bind X to Y and on change run: ... End Bind
+    Require {
+        value: Expr,
+        schema: Expr,
+    }, // require X to match S: a ValidationError listing the problems
+    Assert {
+        deep_equal: bool,
+        left: Expr,
+        right: Expr,
+    }, // Assert X equals Y, or Assert X deep equals Y to list each difference
+    ReadLineLoop {
+        var_name: String,
+        body: Program,
+    }, // For each line in stdin as X: ... End For
+    ForEach {
+        var_name: String,
+        items: Expr,
+        body: Program,
+    }, // For each X in <list>: ... End For
+    BeforeEachRoute {
+        body: Program,
+    }, // Before each route: ... End Before
+    AfterEachRoute {
+        body: Program,
+    }, // After each route: ... End After
+    Parallel {
+        branches: Vec<Program>,
+    }, // Parallel: Branch: ... Branch: ... End Parallel
+    SendEvent {
+        data: Expr,
+        stream: Expr,
+    }, // Send event X to S
+    SendMessage {
+        message: Expr,
+        socket: Expr,
+    }, // Send X to S
+    CloseStream(Expr), // Close S, an event stream or a websocket
+    Task {
+        name: String,
+        body: Program,
+    }, // Start task named X: ... End Task
+    Background {
+        name: Option<String>,
+        body: Program,
+    }, // Run in background [as X]: ... End
+    AwaitAll {
+        names: Vec<String>,
+    }, // Wait for all tasks X, Y, Z
+    AwaitAny {
+        names: Vec<String>,
+    }, // Wait for any task X or Y
+    Checkpoint {
+        name: String,
+        state: Expr,
+    }, // Checkpoint X with state Y
+    RestoreCheckpoint {
+        name: String,
+        into_var: String,
+    }, // Restore checkpoint X into variable Y
+    Retry {
+        max_attempts: Expr,
+        delay: Expr,
+        backoff: bool,
+        jitter: bool,
+        body: Program,
+        on_failure: Option<(Option<String>, Program)>,
+    }, // Retry X times with delay Y seconds: ... On all failures as e: ... End Retry
+    Benchmark {
+        name: Expr,
+        body: Program,
+    }, // Benchmark "name": ... End Benchmark
+    Measure {
+        name: Expr,
+        store_in: Option<String>,
+        body: Program,
+    }, // Measure time of "name": ... End Measure, optionally storing result in a variable
+    TypeAlias {
+        name: String,
+        base_type: String,
+    }, // Type Age is number
+    Schema {
+        name: String,
+        fields: Vec<SchemaField>,
+    }, // Define schema User with name as required string, age as optional number
+    DeclareType {
+        name: String,
+        type_name: String,
+    }, // from Set age as Age to ..., ahead of the Set itself
+    Line(usize), // source line of the statement after it; only from parse_with_lines
 }
 
 #[derive(Debug, Clone)]
@@ -164,8 +472,72 @@ pub struct CatchHandler {
     pub block: Program,
 }
 
+/// One `"alias" as <function> of <field>` of an `aggregate list`
+#[derive(Debug, Clone)]
+pub struct AggField {
+    pub alias: String,
+    pub function: AggFunc,
+    pub field: Box<Expr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggFunc {
+    Sum,
+    Count,
+    Min,
+    Max,
+    Avg,
+    First,
+    Last,
+}
+
+impl AggFunc {
+    /// The words naming it in `aggregate list`, the first the usual one
+    pub fn words(self) -> &'static [&'static str] {
+        match self {
+            AggFunc::Sum => &["sum"],
+            AggFunc::Count => &["count"],
+            AggFunc::Min => &["min", "minimum"],
+            AggFunc::Max => &["max", "maximum"],
+            AggFunc::Avg => &["average", "avg"],
+            AggFunc::First => &["first"],
+            AggFunc::Last => &["last"],
+        }
+    }
+}
+
+/// One `<name> as [required|optional] <type> [defaulting to <value>]` of
+/// a `Define schema`
+#[derive(Debug, Clone)]
+pub struct SchemaField {
+    pub name: String,
+    pub type_name: String,
+    /// A field is required unless it is marked optional or has a default
+    pub required: bool,
+    pub default: Option<Expr>,
+}
+
 pub type Program = Vec<Stmt>;
 
+pub use crate::core::io::AskType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    pub fn label(self) -> &'static str {
+        match self {
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum CmpOp {
     Lt,
@@ -0,0 +1,1402 @@
+// Expression parser.
+// The lexer turns an expression into tokens and this module parses them by
+// precedence climbing. From loosest to tightest: `or`, `and`, `not`,
+// comparisons, `plus`/`minus`, `times`/`divided by`, then indexing. Phrases
+// such as `total of` take one operand at the indexing level, so
+// `total of nums plus 1` adds one to the total.
+
+use crate::parser::ast::{AggField, AggFunc, CmpOp, Expr};
+use crate::parser::lexer::{tokenize, Spanned, Token};
+use crate::parser::parser::suggest_fix;
+use crate::parser::phrases as P;
+use anyhow::{anyhow, Result};
+
+// Binding powers
+const OR: u8 = 1;
+const AND: u8 = 2;
+const CMP: u8 = 3;
+const ADD: u8 = 4;
+const MUL: u8 = 5;
+
+/// How deeply brackets, phrases and `Not` may nest in one expression
+const MAX_NESTING: usize = 256;
+
+// Phrasal comparisons, longest first so `is not equal to` wins over `is not`
+pub(super) const COMPARISONS: &[(&str, CmpOp)] = &[
+    ("is greater than or equal to", CmpOp::Ge),
+    ("is less than or equal to", CmpOp::Le),
+    ("is not equal to", CmpOp::Ne),
+    ("is equal to", CmpOp::Eq),
+    ("is greater than", CmpOp::Gt),
+    ("is less than", CmpOp::Lt),
+    ("greater or equal", CmpOp::Ge),
+    ("less or equal", CmpOp::Le),
+    ("greater than", CmpOp::Gt),
+    ("less than", CmpOp::Lt),
+    ("not equals", CmpOp::Ne),
+    ("equals", CmpOp::Eq),
+    ("is not", CmpOp::Ne),
+    ("is", CmpOp::Eq),
+];
+
+const SYMBOL_COMPARISONS: &[(&str, CmpOp)] = &[
+    (">=", CmpOp::Ge),
+    ("<=", CmpOp::Le),
+    ("!=", CmpOp::Ne),
+    ("==", CmpOp::Eq),
+    (">", CmpOp::Gt),
+    ("<", CmpOp::Lt),
+    ("=", CmpOp::Eq),
+];
+
+type Build = fn(Box<Expr>) -> Expr;
+
+// Phrases that take a single operand. Where one phrase is a prefix of another
+// the longer one comes first.
+pub(super) const UNARY_PHRASES: &[(&str, Build)] = &[
+    (P::P_COUNT_OF, Expr::CountOf),
+    (P::P_TYPE_OF, Expr::TypeOf),
+    (P::P_WAIT_FOR, Expr::WaitFor),
+    (P::P_TOTAL_OF, Expr::TotalOf),
+    (P::P_SMALLEST_IN, Expr::SmallestIn),
+    (P::P_LARGEST_IN, Expr::LargestIn),
+    (P::P_AVERAGE_OF, Expr::AverageOf),
+    (P::P_MEDIAN_OF, Expr::MedianOf),
+    (P::P_VARIANCE_OF, Expr::VarianceOf),
+    (P::P_STD_DEV_OF, Expr::StdDevOf),
+    (P::P_ABS_OF, Expr::AbsoluteValueOf),
+    (P::P_ROUND_DOWN, Expr::RoundDown),
+    (P::P_ROUND_UP, Expr::RoundUp),
+    (P::P_ROUND, Expr::Round),
+    (P::P_MAKE_UPPER, Expr::MakeUppercase),
+    (P::P_MAKE_LOWER, Expr::MakeLowercase),
+    (P::P_TRIM_FROM, Expr::TrimSpaces),
+    (P::P_FIRST_IN, Expr::FirstIn),
+    (P::P_LAST_IN, Expr::LastIn),
+    (P::P_REVERSE_OF, Expr::ReverseOf),
+    (P::P_REVERSE_ALIAS, Expr::ReverseOf),
+    (P::P_CLEAN_ALIAS, Expr::TrimSpaces),
+    (P::P_SIZE_OF, Expr::CountOf),
+    (P::P_READ_FILE, Expr::ReadFile),
+    (P::P_READ_LINES, Expr::ReadLines),
+    (P::P_READ_LINES_ALT, Expr::ReadLines),
+    (P::P_FILE_EXISTS, Expr::FileExists),
+    (P::P_DELETE_FILE, Expr::DeleteFile),
+    (P::P_CREATE_DIR, Expr::CreateDir),
+    (P::P_LIST_DIR_ALT, Expr::ListDir),
+    (P::P_LIST_DIR, Expr::ListDir),
+    (P::P_PARSE_JSON, Expr::ParseJson),
+    (P::P_TO_JSON, Expr::ToJson),
+    (P::P_JSON_PRETTY, Expr::ToJsonPretty),
+    (P::P_JSON_LENGTH, Expr::JsonLength),
+    (P::P_PARSE_URL, Expr::ParseUrl),
+    (P::P_BUILD_URL, Expr::BuildUrl),
+    (P::P_URL_ENCODE, Expr::UrlEncode),
+    (P::P_URL_DECODE, Expr::UrlDecode),
+    (P::P_DEDENT, Expr::Dedent),
+    (P::P_ERROR_MESSAGE, Expr::ErrorMessage),
+    (P::P_ERROR_TYPE, Expr::ErrorType),
+    ("html response with", Expr::HtmlResponse),
+    (P::P_GET_PATH_PARAM, Expr::GetPathParam),
+    (P::P_GET_REQUEST_HEADER, Expr::GetRequestHeader),
+    (P::P_SET_OF, Expr::ListToSet),
+    (P::P_LIST_OF, Expr::SetToList),
+];
+
+#[derive(Clone)]
+enum BinOp {
+    Or,
+    And,
+    Cmp(CmpOp),
+    Plus,
+    Minus,
+    Times,
+    DividedBy,
+}
+
+impl BinOp {
+    fn build(self, lhs: Expr, rhs: Expr) -> Expr {
+        let (a, b) = (Box::new(lhs), Box::new(rhs));
+        match self {
+            BinOp::Or => Expr::Or(a, b),
+            BinOp::And => Expr::And(a, b),
+            BinOp::Cmp(op) => Expr::Cmp(op, a, b),
+            BinOp::Plus => Expr::Plus(a, b),
+            BinOp::Minus => Expr::Minus(a, b),
+            BinOp::Times => Expr::Times(a, b),
+            BinOp::DividedBy => Expr::DividedBy(a, b),
+        }
+    }
+}
+
+/// Where an expression sits, which changes how a few words are read
+#[derive(Clone, Copy, Default)]
+struct Ctx {
+    // `and` separates list items and call arguments instead of joining conditions
+    and_separates: bool,
+    // The operand is followed by a `with`, so `name with ...` is not a call
+    no_with_call: bool,
+}
+
+impl Ctx {
+    fn items(self) -> Ctx {
+        Ctx {
+            and_separates: true,
+            ..self
+        }
+    }
+}
+
+struct ExprParser<'a> {
+    src: &'a str,
+    tokens: Vec<Spanned>,
+    pos: usize,
+    depth: usize,
+}
+
+pub fn parse_expr(s: &str) -> Result<Expr> {
+    let mut p = ExprParser::new(s)?;
+    if p.tokens.is_empty() {
+        return Err(anyhow!("Empty expression"));
+    }
+    let expr = p.expr(OR, Ctx::default())?;
+    p.finish()?;
+    Ok(expr)
+}
+
+/// Parse comma separated arguments, also split on `and` when `allow_and` is set
+pub fn parse_args(s: &str, allow_and: bool) -> Result<Vec<Expr>> {
+    let mut p = ExprParser::new(s)?;
+    let ctx = Ctx {
+        and_separates: allow_and,
+        no_with_call: false,
+    };
+    let args = p.items(ctx)?;
+    p.finish()?;
+    Ok(args)
+}
+
+/// Whether evaluating `e` twice is the same as evaluating it once, so the
+/// middle of a comparison chain can be repeated
+fn repeatable(e: &Expr) -> bool {
+    match e {
+        Expr::Str(_) | Expr::Int(_) | Expr::Num(_) | Expr::Bool(_) | Expr::Null | Expr::Ident(_) => true,
+        Expr::Plus(..) | Expr::Minus(..) | Expr::Times(..) | Expr::DividedBy(..) => {
+            let (left, terms) = e.arithmetic_chain();
+            repeatable(left) && terms.iter().all(|(_, b)| repeatable(b))
+        }
+        Expr::Index(a, b) => repeatable(a) && repeatable(b),
+        _ => false,
+    }
+}
+
+impl<'a> ExprParser<'a> {
+    fn new(src: &'a str) -> Result<Self> {
+        let tokens = tokenize(src).map_err(|e| {
+            let msg = format!("Could not parse expression: {} ({})", src.trim(), e);
+            anyhow!("{}", suggest_fix(&msg, src))
+        })?;
+        Ok(ExprParser {
+            src,
+            tokens,
+            pos: 0,
+            depth: 0,
+        })
+    }
+
+    fn error(&self) -> anyhow::Error {
+        let msg = format!("Could not parse expression: {}", self.src.trim());
+        anyhow!("{}", suggest_fix(&msg, self.src))
+    }
+
+    fn finish(&self) -> Result<()> {
+        if self.pos < self.tokens.len() {
+            return Err(self.error());
+        }
+        Ok(())
+    }
+
+    fn peek(&self, ahead: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + ahead).map(|t| &t.token)
+    }
+
+    fn is_word(&self, ahead: usize, word: &str) -> bool {
+        matches!(self.peek(ahead), Some(Token::Ident(w)) if w.eq_ignore_ascii_case(word))
+    }
+
+    fn is_symbol(&self, ahead: usize, sym: &str) -> bool {
+        matches!(self.peek(ahead), Some(Token::Symbol(s)) if *s == sym)
+    }
+
+    /// Number of words in `phrase` if the upcoming tokens spell it
+    fn match_words(&self, phrase: &str) -> Option<usize> {
+        let mut n = 0;
+        for word in phrase.split_whitespace() {
+            if !self.is_word(n, word) {
+                return None;
+            }
+            n += 1;
+        }
+        Some(n)
+    }
+
+    fn eat_words(&mut self, phrase: &str) -> bool {
+        match self.match_words(phrase) {
+            Some(n) => {
+                self.pos += n;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn eat_symbol(&mut self, sym: &str) -> bool {
+        if self.is_symbol(0, sym) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_symbol(&mut self, sym: &str) -> Result<()> {
+        if self.eat_symbol(sym) {
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    /// The binary operator at the current position, its binding power and
+    /// how many tokens it spans
+    fn infix(&self, ctx: Ctx) -> Option<(BinOp, u8, usize)> {
+        match self.peek(0)? {
+            Token::Symbol(s) => {
+                let op = match *s {
+                    "+" => (BinOp::Plus, ADD),
+                    "-" => (BinOp::Minus, ADD),
+                    "*" => (BinOp::Times, MUL),
+                    "/" => (BinOp::DividedBy, MUL),
+                    _ => {
+                        let (_, op) = SYMBOL_COMPARISONS.iter().find(|(sym, _)| sym == s)?;
+                        (BinOp::Cmp(op.clone()), CMP)
+                    }
+                };
+                Some((op.0, op.1, 1))
+            }
+            Token::Ident(w) => {
+                let simple = match w.to_ascii_lowercase().as_str() {
+                    "or" => Some((BinOp::Or, OR)),
+                    "and" if !ctx.and_separates => Some((BinOp::And, AND)),
+                    "plus" => Some((BinOp::Plus, ADD)),
+                    "minus" => Some((BinOp::Minus, ADD)),
+                    "times" => Some((BinOp::Times, MUL)),
+                    _ => None,
+                };
+                if let Some((op, bp)) = simple {
+                    return Some((op, bp, 1));
+                }
+                if let Some(n) = self.match_words("divided by") {
+                    return Some((BinOp::DividedBy, MUL, n));
+                }
+                COMPARISONS.iter().find_map(|(phrase, op)| {
+                    self.match_words(phrase)
+                        .map(|n| (BinOp::Cmp(op.clone()), CMP, n))
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the current token can begin an operand
+    fn starts_operand(&self) -> bool {
+        match self.peek(0) {
+            None => false,
+            Some(Token::Symbol(s)) => matches!(*s, "(" | "[" | "{" | "-"),
+            Some(Token::Ident(w)) => {
+                w.eq_ignore_ascii_case("not") || self.infix(Ctx::default()).is_none()
+            }
+            Some(_) => true,
+        }
+    }
+
+    fn expr(&mut self, min_bp: u8, ctx: Ctx) -> Result<Expr> {
+        let mut lhs = if self.is_word(0, "not") && self.match_words("not equals").is_none() {
+            self.pos += 1;
+            Expr::Not(Box::new(self.nested(|p| p.expr(CMP, ctx))?))
+        } else {
+            self.unary(ctx)?
+        };
+
+        // The right side of the comparison just built, for chains such as
+        // `1 < x < 10`
+        let mut last_compared: Option<Expr> = None;
+        while let Some((op, bp, width)) = self.infix(ctx) {
+            if bp < min_bp {
+                break;
+            }
+            self.pos += width;
+            // Operators are left-associative: the right side only takes
+            // tighter-binding operators
+            let rhs = self.expr(bp + 1, ctx)?;
+            lhs = match (op, last_compared.take()) {
+                (BinOp::Cmp(op), Some(middle)) => {
+                    if !repeatable(&middle) {
+                        return Err(anyhow!(
+                            "{}: chained comparisons are not supported here; write `x is greater than 1 And x is less than 10`",
+                            self.error()
+                        ));
+                    }
+                    // `a < b < c` is `a < b And b < c`
+                    last_compared = Some(rhs.clone());
+                    let next = Expr::Cmp(op, Box::new(middle), Box::new(rhs));
+                    Expr::And(Box::new(lhs), Box::new(next))
+                }
+                (op, _) => {
+                    if matches!(op, BinOp::Cmp(_)) {
+                        last_compared = Some(rhs.clone());
+                    }
+                    op.build(lhs, rhs)
+                }
+            };
+        }
+        if min_bp == OR {
+            // A if cond else B
+            if self.eat_words("if") {
+                let cond = self.expr(OR, ctx)?;
+                if !self.eat_words("else") {
+                    return Err(anyhow!("Expected 'else' after the condition of 'A if condition else B'"));
+                }
+                let otherwise = self.expr(OR, ctx)?;
+                lhs = Expr::Conditional(Box::new(cond), Box::new(lhs), Box::new(otherwise));
+            }
+            lhs = self.response_modifiers(lhs, ctx)?;
+        }
+        Ok(lhs)
+    }
+
+    /// `with etag` and `with cache for <n> seconds` after a response, in
+    /// any order
+    fn response_modifiers(&mut self, mut response: Expr, ctx: Ctx) -> Result<Expr> {
+        let ctx = Ctx {
+            no_with_call: true,
+            ..ctx
+        };
+        loop {
+            if self.eat_words(P::P_WITH_ETAG) {
+                response = Expr::WithEtag(Box::new(response));
+            } else if self.eat_words(P::P_WITH_CACHE_FOR) {
+                let secs = self.last_operand(ctx)?;
+                if !self.eat_words("seconds") && !self.eat_words("second") {
+                    return Err(anyhow!(
+                        "with cache for must give a number of seconds, e.g. 'with cache for 3600 seconds'"
+                    ));
+                }
+                response = Expr::WithCache(Box::new(response), secs);
+            } else {
+                return Ok(response);
+            }
+        }
+    }
+
+    fn at_response_modifier(&self) -> bool {
+        self.match_words(P::P_WITH_ETAG).is_some() || self.match_words(P::P_WITH_CACHE_FOR).is_some()
+    }
+
+    /// A primary expression followed by any number of `[index]` suffixes
+    fn unary(&mut self, ctx: Ctx) -> Result<Expr> {
+        self.nested(|p| {
+            let mut expr = p.primary(ctx)?;
+            while p.eat_symbol("[") {
+                let index = p.expr(OR, Ctx::default())?;
+                p.expect_symbol("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            }
+            Ok(expr)
+        })
+    }
+
+    /// Parse one level deeper. Brackets, phrases and `Not` all recurse, so a
+    /// generated expression nested thousands deep would otherwise overflow
+    /// the stack here or when it runs.
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Result<Expr>) -> Result<Expr> {
+        if self.depth == MAX_NESTING {
+            return Err(anyhow!("expression too deeply nested (limit {})", MAX_NESTING));
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    /// Expressions separated by commas, and by `and` when the context says so
+    fn items(&mut self, ctx: Ctx) -> Result<Vec<Expr>> {
+        let mut items = Vec::new();
+        while self.starts_operand() {
+            items.push(self.expr(OR, ctx)?);
+            let before = self.pos;
+            let separated = self.eat_symbol(",")
+                || (ctx.and_separates && self.eat_words("and"));
+            if !separated {
+                break;
+            }
+            // In a dictionary literal, `"key" as` starts the next pair
+            if self.at_dict_key() {
+                self.pos = before;
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    fn at_dict_key(&self) -> bool {
+        matches!(self.peek(0), Some(Token::String(_)))
+            && (self.is_word(1, "as") || (self.is_word(1, "set") && self.is_word(2, "to")))
+    }
+
+    fn primary(&mut self, ctx: Ctx) -> Result<Expr> {
+        let Some(token) = self.peek(0).cloned() else {
+            return Err(self.error());
+        };
+        match token {
+            Token::Int(i) => {
+                self.pos += 1;
+                Ok(Expr::Int(i))
+            }
+            Token::Number(n) => {
+                self.pos += 1;
+                Ok(Expr::Num(n))
+            }
+            Token::String(s) => {
+                self.pos += 1;
+                Ok(Expr::Str(s))
+            }
+            Token::Regex(p) => {
+                self.pos += 1;
+                Ok(Expr::RegexLit(p))
+            }
+            Token::Symbol("(") => {
+                self.pos += 1;
+                if self.eat_symbol(")") {
+                    return Err(anyhow!("Empty parentheses () are not allowed"));
+                }
+                let inner = self.expr(OR, Ctx::default())?;
+                self.expect_symbol(")")?;
+                Ok(inner)
+            }
+            Token::Symbol("[") => Err(anyhow!(
+                "Bracket list literals '[]' are not supported. Use: Make a list of ..."
+            )),
+            Token::Symbol("{") => Err(anyhow!(
+                "Brace dictionary literals '{{}}' are not supported. Use: Make a dictionary with ..."
+            )),
+            Token::Symbol("-") => match self.peek(1) {
+                Some(Token::Int(i)) => {
+                    let i = *i;
+                    self.pos += 2;
+                    Ok(Expr::Int(-i))
+                }
+                Some(Token::Number(n)) => {
+                    let n = *n;
+                    self.pos += 2;
+                    Ok(Expr::Num(-n))
+                }
+                // Unary minus, as `0 minus operand`; it binds like indexing,
+                // so `-x times 2` is `(-x) times 2`
+                Some(_) => {
+                    self.pos += 1;
+                    if !self.starts_operand() {
+                        return Err(self.error());
+                    }
+                    Ok(Expr::Minus(Box::new(Expr::Int(0)), Box::new(self.unary(ctx)?)))
+                }
+                None => Err(self.error()),
+            },
+            Token::Ident(word) => self.word(word, ctx),
+            Token::Symbol(_) => Err(self.error()),
+        }
+    }
+
+    fn word(&mut self, word: String, ctx: Ctx) -> Result<Expr> {
+        // Call form: name(args)
+        if self.is_symbol(1, "(") {
+            self.pos += 2;
+            let mut args = Vec::new();
+            if !self.eat_symbol(")") {
+                loop {
+                    args.push(self.expr(OR, Ctx::default())?);
+                    if !self.eat_symbol(",") {
+                        break;
+                    }
+                }
+                self.expect_symbol(")")?;
+            }
+            return Ok(Expr::Call { name: word, args });
+        }
+
+        if let Some(expr) = self.phrase(ctx)? {
+            return Ok(expr);
+        }
+
+        // Legacy collection syntax is NOT supported
+        if self.match_words("list contains").is_some() {
+            return Err(anyhow!("Legacy 'List contains' is not supported. Use: Make a list of ..."));
+        }
+        if self.match_words("dictionary contains").is_some() {
+            return Err(anyhow!(
+                "Legacy 'Dictionary contains' is not supported. Use: Make a dictionary with ..."
+            ));
+        }
+
+        self.pos += 1;
+        if word.eq_ignore_ascii_case("True") {
+            return Ok(Expr::Bool(true));
+        }
+        if word.eq_ignore_ascii_case("False") {
+            return Ok(Expr::Bool(false));
+        }
+        if word.eq_ignore_ascii_case("Null") || word.eq_ignore_ascii_case("Nothing") || word == "None"
+        {
+            return Ok(Expr::Null);
+        }
+
+        // Phrasal call: name with args
+        if !ctx.no_with_call && !self.at_response_modifier() && self.eat_words("with") {
+            let args = self.items(ctx.items())?;
+            return Ok(Expr::Call { name: word, args });
+        }
+
+        Ok(Expr::Ident(word))
+    }
+
+    /// Built-in phrases starting at the current word, if any match
+    fn phrase(&mut self, ctx: Ctx) -> Result<Option<Expr>> {
+        let start = self.pos;
+
+        // Phrasal list literals (immutable/mutable): Make a (mutable) list of 1, 2 and 3
+        // TODO: track mutability; for now, same ListLit representation.
+        if self.eat_words("Make a mutable list of") || self.eat_words("Make a list of") {
+            return Ok(Some(Expr::ListLit(self.items(ctx.items())?)));
+        }
+        if self.eat_words(P::P_MAKE_A_CHANNEL) {
+            return Ok(Some(Expr::MakeChannel));
+        }
+
+        // Tuple literals: tuple of 1, "two" and 3
+        if self.eat_words("tuple of") {
+            return Ok(Some(Expr::TupleLit(self.items(ctx.items())?)));
+        }
+
+        // Set literals: set containing 1, "two" and 3
+        if self.eat_words(P::P_SET_CONTAINING) {
+            return Ok(Some(Expr::SetLit(self.items(ctx.items())?)));
+        }
+        if self.eat_words(P::P_EMPTY_SET) {
+            return Ok(Some(Expr::SetLit(Vec::new())));
+        }
+
+        // smallest of 3, 7 and 2: min and max over their items, with lists
+        // among them flattened
+        for (prefix, name) in [(P::P_SMALLEST_OF, "min"), (P::P_LARGEST_OF, "max")] {
+            if self.eat_words(prefix) {
+                let args = self.items(ctx.items())?;
+                if !args.is_empty() {
+                    return Ok(Some(Expr::Call { name: name.to_string(), args }));
+                }
+                self.pos = start;
+            }
+        }
+
+        // Phrasal dictionary literals: Make a (mutable) dictionary with "a" as 1 and "b" as 2
+        if self.eat_words("Make a mutable dictionary with") || self.eat_words("Make a dictionary with")
+        {
+            return self.dict_items(ctx.items()).map(Some);
+        }
+
+        if self.eat_words(P::P_CREATE_WEB_SERVER) {
+            return self.create_web_server(ctx, false).map(Some);
+        }
+        if self.eat_words(P::P_CREATE_SECURE_WEB_SERVER) {
+            return self.create_web_server(ctx, true).map(Some);
+        }
+
+        for (prefix, build) in UNARY_PHRASES {
+            if self.eat_words(prefix) {
+                if self.starts_operand() {
+                    return Ok(Some(build(Box::new(self.unary(ctx)?))));
+                }
+                self.pos = start;
+            }
+        }
+
+        if self.eat_words(P::P_NEW_JSON_OBJECT) {
+            return Ok(Some(Expr::NewJsonObject));
+        }
+        if self.eat_words(P::P_NEW_JSON_ARRAY) {
+            return Ok(Some(Expr::NewJsonArray));
+        }
+        if self.eat_words(P::P_REQUEST_METHOD) {
+            return Ok(Some(Expr::GetRequestMethod));
+        }
+        if self.eat_words(P::P_REQUEST_PATH) {
+            return Ok(Some(Expr::GetRequestPath));
+        }
+        if self.eat_words(P::P_CLIENT_ADDRESS) {
+            return Ok(Some(Expr::GetRemoteAddr));
+        }
+        if self.eat_words(P::P_PREFERRED_RESPONSE_TYPE) {
+            return Ok(Some(Expr::PreferredResponseType));
+        }
+        if self.eat_words(P::P_START_EVENT_STREAM) {
+            return self.start_event_stream(ctx).map(Some);
+        }
+        if self.eat_words(P::P_RECEIVE_MESSAGE) {
+            return self.receive_message(ctx).map(Some);
+        }
+        if self.eat_words(P::P_RECEIVE_FROM) {
+            return self.receive_from(ctx).map(Some);
+        }
+        if self.eat_words(P::P_READ_FROM_STDIN) {
+            return Ok(Some(Expr::ReadFromStdin));
+        }
+
+        // Phrases whose operands are separated by keywords. When one of the
+        // keywords is missing this is not the phrase, so rewind and let the
+        // words be read some other way.
+        let expr = if self.eat_words(P::P_JOIN) {
+            self.binary(ctx, P::P_JOIN_WITH, Expr::JoinWith)?
+        } else if self.eat_words(P::P_SPLIT) || self.eat_words(P::P_SEPARATE) {
+            self.binary(ctx, P::P_SPLIT_BY, Expr::SplitBy)?
+        } else if self.eat_words(P::P_CONTAINS) {
+            self.binary(ctx, P::P_CONTAINS_IN, Expr::Contains)?
+        } else if self.eat_words(P::P_UNION_OF) {
+            self.binary(ctx, P::P_SET_AND, Expr::SetUnion)?
+        } else if self.eat_words(P::P_INTERSECTION_OF) {
+            self.binary(ctx, P::P_SET_AND, Expr::SetIntersection)?
+        } else if self.eat_words(P::P_DIFFERENCE_OF) {
+            self.binary(ctx, P::P_SET_AND, Expr::SetDifference)?
+        } else if self.eat_words(P::P_FIND_FIRST) {
+            self.binary(ctx, P::P_FIND_WHERE, Expr::FindFirst)?
+        } else if self.eat_words(P::P_FIND_ALL) {
+            self.binary(ctx, P::P_FIND_WHERE, Expr::FindAll)?
+        } else if self.eat_words(P::P_FIND_INDEX) {
+            self.binary(ctx, P::P_FIND_WHERE, Expr::FindIndex)?
+        } else if self.eat_words(P::P_INDENT) {
+            // Only a phrase when an operand follows, so `indent` can still name a variable
+            if self.starts_operand() {
+                self.layout_width(ctx, P::P_INDENT_BY, "spaces", Expr::IndentBy)?
+            } else {
+                None
+            }
+        } else if self.eat_words(P::P_WRAP) {
+            if self.starts_operand() {
+                self.layout_width(ctx, P::P_WRAP_AT, "characters", Expr::WrapText)?
+            } else {
+                None
+            }
+        } else if self.eat_words(P::P_REMOVE) {
+            self.binary(ctx, P::P_REMOVE_FROM, Expr::Remove)?
+        } else if self.eat_words(P::P_APPEND) {
+            // append <item> to <list> or append <content> into file at <path>
+            let item = Box::new(self.expr(CMP, ctx)?);
+            if self.eat_words(P::P_APPEND_TO) {
+                Some(Expr::Append(item, self.last_operand(ctx)?))
+            } else if self.eat_words(P::P_APPEND_TO_FILE) {
+                Some(Expr::AppendFile(item, self.last_operand(ctx)?))
+            } else {
+                None
+            }
+        } else if self.eat_words(P::P_INSERT) {
+            self.ternary(ctx, P::P_INSERT_AT, P::P_INSERT_IN, Expr::InsertAt)?
+        } else if self.eat_words(P::P_INTERPOLATE) {
+            self.ternary(ctx, P::P_INTERPOLATE_TO, P::P_INTERPOLATE_AT, Expr::Lerp)?
+        } else if self.eat_words(P::P_CLAMP) {
+            // Only a phrase when an operand follows, so `clamp` can still name a variable
+            if self.starts_operand() {
+                self.ternary(ctx, P::P_CLAMP_BETWEEN, P::P_CLAMP_AND, Expr::Clamp)?
+            } else {
+                None
+            }
+        } else if self.eat_words(P::P_MAP) {
+            if self.starts_operand() {
+                self.map_range(ctx)?
+            } else {
+                None
+            }
+        } else if self.eat_words(P::P_WRITE_FILE) {
+            self.binary(ctx, P::P_WRITE_TO_FILE, Expr::WriteFile)?
+        } else if self.eat_words(P::P_COPY_FILE) {
+            self.binary(ctx, P::P_COPY_TO, Expr::CopyFile)?
+        } else if self.eat_words(P::P_MOVE_FILE) {
+            self.binary(ctx, P::P_COPY_TO, Expr::MoveFile)?
+        } else if self.eat_words(P::P_JSON_GET) {
+            self.binary(ctx, P::P_JSON_FROM, |key, json| Expr::JsonGet(json, key))?
+        } else if self.eat_words(P::P_TIME_CALLING) {
+            self.timed_call(ctx)?
+        } else if self.eat_words(P::P_APPLY) {
+            self.apply_in_parallel(ctx)?
+        } else if self.eat_words(P::P_QUERY_JSON) {
+            self.binary(ctx, P::P_WITH_PATH, Expr::JsonQuery)?
+        } else if self.eat_words(P::P_PICK) {
+            self.fields_from(ctx, Expr::Pick)?
+        } else if self.eat_words(P::P_OMIT) {
+            self.fields_from(ctx, Expr::Omit)?
+        } else if self.eat_words(P::P_COLLECT_FIELD) {
+            self.binary(ctx, P::P_FROM_EACH, Expr::GroupConcat)?
+        } else if self.eat_words(P::P_COLLECT_FIELDS) {
+            self.collect_fields(ctx)?
+        } else if self.eat_words(P::P_AGGREGATE) {
+            self.aggregate(ctx)?
+        } else if self.eat_words(P::P_VALIDATE) {
+            let value = self.pos;
+            match self.binary(ctx, P::P_AGAINST, Expr::ValidateAgainst)? {
+                Some(expr) => Some(expr),
+                None => {
+                    self.pos = value;
+                    self.schema_check(ctx, Expr::Validate)?
+                }
+            }
+        } else if self.eat_words(P::P_COERCE) {
+            self.schema_check(ctx, Expr::Coerce)?
+        } else if self.eat_words(P::P_SET_RESPONSE_HEADER) {
+            self.binary(ctx, P::P_RESPONSE_HEADER_TO, Expr::SetResponseHeaderExpr)?
+        } else if self.eat_words(P::P_JSON_SET) {
+            let operand = self.pos;
+            match self.ternary(ctx, P::P_JSON_IN, P::P_JSON_TO, |key, json, value| {
+                Expr::JsonSet(json, key, value)
+            })? {
+                Some(expr) => Some(expr),
+                None => {
+                    self.pos = operand;
+                    self.binary(ctx, P::P_SET_CONTAINS, Expr::SetContains)?
+                }
+            }
+        } else if self.eat_words(P::P_JSON_PUSH) {
+            self.binary(ctx, P::P_JSON_PUSH_TO, |item, json| Expr::JsonPush(json, item))?
+        } else if self.eat_words("error of type") {
+            self.new_error(ctx)?
+        } else if self.eat_words("json response with") {
+            let data = self.last_operand(ctx)?;
+            if self.eat_words("and status") {
+                Some(Expr::JsonResponseStatus(data, self.last_operand(ctx)?))
+            } else {
+                Some(Expr::JsonResponse(data))
+            }
+        } else if self.eat_words(P::P_RENDER_TEMPLATE_FILE) {
+            self.binary(ctx, " with ", Expr::RenderTemplateFile)?
+        } else if self.eat_words("render template") {
+            self.binary(ctx, " with ", Expr::RenderTemplate)?
+        } else if self.eat_words("error response with status") {
+            self.binary(ctx, " and message ", Expr::ErrorResponse)?
+        } else {
+            None
+        };
+
+        if expr.is_none() {
+            self.pos = start;
+        }
+        Ok(expr)
+    }
+
+    /// An operand followed by the keyword(s) `delim`, or `None` if they are missing
+    fn operand_then(&mut self, ctx: Ctx, delim: &str) -> Result<Option<Box<Expr>>> {
+        let ctx = Ctx {
+            no_with_call: ctx.no_with_call || delim.trim_start().starts_with("with"),
+            ..ctx
+        };
+        let operand = self.expr(CMP, ctx)?;
+        Ok(self.eat_words(delim).then(|| Box::new(operand)))
+    }
+
+    /// The operand that ends a phrase. Like a single-operand phrase it stops
+    /// at the first binary operator.
+    fn last_operand(&mut self, ctx: Ctx) -> Result<Box<Expr>> {
+        Ok(Box::new(self.unary(ctx)?))
+    }
+
+    fn binary(
+        &mut self,
+        ctx: Ctx,
+        delim: &str,
+        build: impl FnOnce(Box<Expr>, Box<Expr>) -> Expr,
+    ) -> Result<Option<Expr>> {
+        match self.operand_then(ctx, delim)? {
+            Some(first) => Ok(Some(build(first, self.last_operand(ctx)?))),
+            None => Ok(None),
+        }
+    }
+
+    fn ternary(
+        &mut self,
+        ctx: Ctx,
+        first_delim: &str,
+        second_delim: &str,
+        build: impl FnOnce(Box<Expr>, Box<Expr>, Box<Expr>) -> Expr,
+    ) -> Result<Option<Expr>> {
+        let Some(first) = self.operand_then(ctx, first_delim)? else {
+            return Ok(None);
+        };
+        let Some(second) = self.operand_then(ctx, second_delim)? else {
+            return Ok(None);
+        };
+        Ok(Some(build(first, second, self.last_operand(ctx)?)))
+    }
+
+    /// `indent <text> by <n> spaces` and `wrap <text> at <n> characters`:
+    /// the phrase once `delim` is found, after which the unit must follow
+    fn layout_width(
+        &mut self,
+        ctx: Ctx,
+        delim: &str,
+        unit: &str,
+        build: impl FnOnce(Box<Expr>, Box<Expr>) -> Expr,
+    ) -> Result<Option<Expr>> {
+        let Some(expr) = self.binary(ctx, delim, build)? else {
+            return Ok(None);
+        };
+        if !self.eat_words(unit) && !self.eat_words(unit.trim_end_matches('s')) {
+            let example = match &expr {
+                Expr::IndentBy(..) => "indent text by 4 spaces",
+                _ => "wrap text at 72 characters",
+            };
+            return Err(anyhow!("Expected '{}' after the number, e.g. '{}'", unit, example));
+        }
+        Ok(Some(expr))
+    }
+
+    /// `"key" as value` pairs of a dictionary literal
+    fn dict_items(&mut self, ctx: Ctx) -> Result<Expr> {
+        let mut pairs = Vec::new();
+        while self.starts_operand() {
+            let Some(Token::String(key)) = self.peek(0).cloned() else {
+                return Err(anyhow!("Expected quoted key in dictionary literal"));
+            };
+            self.pos += 1;
+            // Legacy ' set to ' is still accepted in place of ' as '
+            if !self.eat_words("as") && !self.eat_words("set to") {
+                return Err(anyhow!("Expected 'as' in dictionary literal item"));
+            }
+            pairs.push((key, self.expr(OR, ctx)?));
+            if !self.eat_symbol(",") && !self.eat_words("and") {
+                break;
+            }
+        }
+        Ok(Expr::DictLit(pairs))
+    }
+
+    /// `"a", "b" and "c" from <dict>` after `pick` or `omit`
+    fn fields_from(
+        &mut self,
+        ctx: Ctx,
+        build: fn(Box<Expr>, Vec<String>) -> Expr,
+    ) -> Result<Option<Expr>> {
+        let mut keys = Vec::new();
+        while let Some(Token::String(key)) = self.peek(0).cloned() {
+            self.pos += 1;
+            keys.push(key);
+            if !self.eat_symbol(",") && !self.eat_words("and") {
+                break;
+            }
+        }
+        if keys.is_empty() || !self.eat_words(P::P_FIELDS_FROM) {
+            return Ok(None);
+        }
+        Ok(Some(build(self.last_operand(ctx)?, keys)))
+    }
+
+    /// collect fields "a", "b" from each item in <list>
+    ///
+    /// Several keys are gathered into a list literal; a single one can also
+    /// be a list held in a variable.
+    fn collect_fields(&mut self, ctx: Ctx) -> Result<Option<Expr>> {
+        let ctx = Ctx {
+            no_with_call: true,
+            ..ctx
+        };
+        let mut keys = self.items(ctx.items())?;
+        if keys.is_empty() || !self.eat_words(P::P_FROM_EACH) {
+            return Ok(None);
+        }
+        let keys = if keys.len() == 1 { keys.remove(0) } else { Expr::ListLit(keys) };
+        Ok(Some(Expr::Pluck(Box::new(keys), self.last_operand(ctx)?)))
+    }
+
+    /// aggregate list <list> computing "total" as sum of "price", "n" as count of "id"
+    fn aggregate(&mut self, ctx: Ctx) -> Result<Option<Expr>> {
+        let Some(list) = self.operand_then(ctx, P::P_COMPUTING)? else {
+            return Ok(None);
+        };
+        const FUNCTIONS: [AggFunc; 7] = [
+            AggFunc::Sum,
+            AggFunc::Count,
+            AggFunc::Min,
+            AggFunc::Max,
+            AggFunc::Avg,
+            AggFunc::First,
+            AggFunc::Last,
+        ];
+        let mut fields = Vec::new();
+        loop {
+            let Some(Token::String(alias)) = self.peek(0).cloned() else {
+                return Err(anyhow!(
+                    "Expected '\"name\" as <sum|count|min|max|average|first|last> of <field>' after 'computing'"
+                ));
+            };
+            self.pos += 1;
+            let function = match self.eat_words("as") {
+                true => FUNCTIONS.into_iter().find(|f| f.words().iter().any(|w| self.eat_words(w))),
+                false => None,
+            };
+            let Some(function) = function.filter(|_| self.eat_words("of")) else {
+                return Err(anyhow!(
+                    "Expected 'as <sum|count|min|max|average|first|last> of <field>' after \"{}\" in aggregate list",
+                    alias
+                ));
+            };
+            let field = Box::new(self.expr(OR, ctx.items())?);
+            fields.push(AggField { alias, function, field });
+            if !self.eat_symbol(",") && !self.eat_words("and") {
+                break;
+            }
+        }
+        Ok(Some(Expr::Aggregate(list, fields)))
+    }
+
+    /// time calling <function> [with <args>]
+    fn timed_call(&mut self, ctx: Ctx) -> Result<Option<Expr>> {
+        let Some(Token::Ident(name)) = self.peek(0).cloned() else {
+            return Ok(None);
+        };
+        self.pos += 1;
+        let args = if self.eat_words("with") { self.items(ctx.items())? } else { Vec::new() };
+        Ok(Some(Expr::TimedCall(name, args)))
+    }
+
+    /// apply <function> to each item in <list> in parallel [with <n> workers]
+    fn apply_in_parallel(&mut self, ctx: Ctx) -> Result<Option<Expr>> {
+        let Some(Token::Ident(name)) = self.peek(0).cloned() else {
+            return Ok(None);
+        };
+        self.pos += 1;
+        if !self.eat_words(P::P_TO_EACH_ITEM_IN) {
+            return Ok(None);
+        }
+        let Some(items) = self.operand_then(ctx, P::P_IN_PARALLEL)? else {
+            return Err(anyhow!("Expected 'in parallel' after 'apply {} to each item in <list>'", name));
+        };
+        let workers = if self.eat_words(P::P_WITH_WORKERS) {
+            let n = self.last_operand(ctx)?;
+            if !self.eat_words("workers") && !self.eat_words("worker") {
+                return Err(anyhow!("Expected 'workers' after the number, e.g. 'apply {} to each item in list in parallel with 4 workers'", name));
+            }
+            Some(n)
+        } else {
+            None
+        };
+        Ok(Some(Expr::ApplyInParallel(name, items, workers)))
+    }
+
+    /// validate|coerce <value> as <schema name>
+    fn schema_check(&mut self, ctx: Ctx, build: fn(Box<Expr>, String) -> Expr) -> Result<Option<Expr>> {
+        let Some(value) = self.operand_then(ctx, P::P_AS_SCHEMA)? else {
+            return Ok(None);
+        };
+        match self.peek(0).cloned() {
+            Some(Token::Ident(schema)) => {
+                self.pos += 1;
+                Ok(Some(build(value, schema)))
+            }
+            _ => Err(anyhow!("Expected a schema name after 'as'")),
+        }
+    }
+
+    /// create [secure] web server on port <port>, then for a secure server
+    /// `with certificate <file> and key <file>`, then in any order
+    /// `with body limit <n> [bytes|KB|MB|GB]` and
+    /// `with request timeout <n> seconds`
+    fn create_web_server(&mut self, ctx: Ctx, secure: bool) -> Result<Expr> {
+        let ctx = Ctx {
+            no_with_call: true,
+            ..ctx
+        };
+        let port = self.last_operand(ctx)?;
+        let mut tls = None;
+        if secure {
+            if !self.eat_words(P::P_WITH_CERTIFICATE) {
+                return Err(anyhow!(
+                    "create secure web server needs its files, e.g. 'with certificate \"cert.pem\" and key \"key.pem\"'"
+                ));
+            }
+            let certificate = self.last_operand(ctx)?;
+            if !self.eat_words(P::P_AND_KEY) {
+                return Err(anyhow!("Expected 'and key <file>' after the certificate of a secure web server"));
+            }
+            tls = Some((certificate, self.last_operand(ctx)?));
+        }
+        let (mut body_limit, mut request_timeout) = (None, None);
+        loop {
+            if self.eat_words(P::P_WITH_BODY_LIMIT) {
+                let size = self.last_operand(ctx)?;
+                let unit = [("bytes", 1), ("KB", 1 << 10), ("MB", 1 << 20), ("GB", 1 << 30)]
+                    .into_iter()
+                    .find(|(word, _)| self.eat_words(word));
+                body_limit = Some(match unit {
+                    Some((_, n)) if n > 1 => Box::new(Expr::Times(size, Box::new(Expr::Int(n)))),
+                    _ => size,
+                });
+            } else if self.eat_words(P::P_WITH_REQUEST_TIMEOUT) {
+                let secs = self.last_operand(ctx)?;
+                if !self.eat_words("seconds") && !self.eat_words("second") {
+                    return Err(anyhow!(
+                        "with request timeout must give a number of seconds, e.g. 'with request timeout 30 seconds'"
+                    ));
+                }
+                request_timeout = Some(secs);
+            } else {
+                break;
+            }
+        }
+        Ok(Expr::CreateWebServer {
+            port,
+            body_limit,
+            request_timeout,
+            tls,
+        })
+    }
+
+    /// map <x> from range <a> to <b> into range <c> to <d>
+    fn map_range(&mut self, ctx: Ctx) -> Result<Option<Expr>> {
+        let mut operands = Vec::new();
+        for delim in [P::P_MAP_FROM, P::P_MAP_TO, P::P_MAP_INTO, P::P_MAP_TO] {
+            match self.operand_then(ctx, delim)? {
+                Some(operand) => operands.push(operand),
+                None => return Ok(None),
+            }
+        }
+        let mut operands = operands.into_iter();
+        let mut next = || operands.next().unwrap();
+        Ok(Some(Expr::MapRange(next(), next(), next(), next(), self.last_operand(ctx)?)))
+    }
+
+    /// start event stream [with heartbeat every <n> seconds]
+    fn start_event_stream(&mut self, ctx: Ctx) -> Result<Expr> {
+        let ctx = Ctx {
+            no_with_call: true,
+            ..ctx
+        };
+        let mut heartbeat = None;
+        if self.eat_words(P::P_WITH_HEARTBEAT) {
+            let secs = self.last_operand(ctx)?;
+            if !self.eat_words("seconds") && !self.eat_words("second") {
+                return Err(anyhow!(
+                    "with heartbeat every must give a number of seconds, e.g. 'with heartbeat every 15 seconds'"
+                ));
+            }
+            heartbeat = Some(secs);
+        }
+        Ok(Expr::StartEventStream { heartbeat })
+    }
+
+    /// receive message from <socket> [with timeout <seconds> seconds]
+    fn receive_message(&mut self, ctx: Ctx) -> Result<Expr> {
+        let ctx = Ctx {
+            no_with_call: true,
+            ..ctx
+        };
+        let socket = self.last_operand(ctx)?;
+        let mut timeout = None;
+        if self.eat_words(P::P_WITH_TIMEOUT) {
+            let secs = self.last_operand(ctx)?;
+            if !self.eat_words("seconds") && !self.eat_words("second") {
+                return Err(anyhow!(
+                    "with timeout must give a number of seconds, e.g. 'with timeout 30 seconds'"
+                ));
+            }
+            timeout = Some(secs);
+        }
+        Ok(Expr::ReceiveMessage { socket, timeout })
+    }
+
+    /// receive from <channel> [within <seconds> seconds]
+    fn receive_from(&mut self, ctx: Ctx) -> Result<Expr> {
+        let ctx = Ctx {
+            no_with_call: true,
+            ..ctx
+        };
+        let channel = self.last_operand(ctx)?;
+        let mut timeout = None;
+        if self.eat_words(P::P_WITHIN) {
+            let secs = self.last_operand(ctx)?;
+            if !self.eat_words("seconds") && !self.eat_words("second") {
+                return Err(anyhow!("within must give a number of seconds, e.g. 'within 5 seconds'"));
+            }
+            timeout = Some(secs);
+        }
+        Ok(Expr::ReceiveFrom { channel, timeout })
+    }
+
+    /// error of type <type> with message <message>
+    ///
+    /// The type is taken as written, with surrounding quotes removed.
+    fn new_error(&mut self, ctx: Ctx) -> Result<Option<Expr>> {
+        let type_start = self.pos;
+        let mut delim = None;
+        while self.pos < self.tokens.len() {
+            delim = self.match_words(P::P_WITH_MESSAGE);
+            if delim.is_some() {
+                break;
+            }
+            self.pos += 1;
+        }
+        let (Some(delim_len), true) = (delim, self.pos > type_start) else {
+            return Ok(None);
+        };
+        let type_tokens = &self.tokens[type_start..self.pos];
+        let error_type = match type_tokens {
+            [Spanned {
+                token: Token::String(s),
+                ..
+            }] => s.clone(),
+            _ => {
+                let (first, last) = (&type_tokens[0], &type_tokens[type_tokens.len() - 1]);
+                self.src[first.start..last.end].to_string()
+            }
+        };
+        self.pos += delim_len;
+        Ok(Some(Expr::NewError {
+            error_type,
+            message: self.last_operand(ctx)?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn dump(s: &str) -> String {
+        format!("{:?}", parse_expr(s).unwrap())
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(dump("1 plus 2 times 3"), dump("1 plus (2 times 3)"));
+        assert_eq!(dump("10 minus 4 minus 3"), dump("(10 minus 4) minus 3"));
+        assert_eq!(dump("a or b and not c is 1"), dump("a or (b and (not (c is 1)))"));
+        assert_eq!(
+            dump("age is less than or equal to 12 or student is True"),
+            dump("(age is less than or equal to 12) or (student is True)")
+        );
+        assert_eq!(dump("total of nums plus 1"), dump("(total of nums) plus 1"));
+        assert_eq!(dump("x*2+y/4 >= 3"), dump("((x times 2) plus (y divided by 4)) >= 3"));
+    }
+
+    #[test]
+    fn test_unary_minus() {
+        assert!(matches!(parse_expr("-5").unwrap(), Expr::Int(-5)));
+        assert_eq!(dump("-x"), dump("0 minus x"));
+        assert_eq!(dump("-(1 plus 2)"), dump("0 minus (1 plus 2)"));
+        assert_eq!(dump("3 - -2"), dump("3 minus (-2)"));
+        assert_eq!(dump("-x times 2"), dump("(0 minus x) times 2"));
+        assert_eq!(dump("-nums[0]"), dump("0 minus (nums[0])"));
+        match parse_expr("Make a list of -1, -x and 3").unwrap() {
+            Expr::ListLit(items) => assert_eq!(items.len(), 3),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(parse_expr("1 plus -").is_err());
+    }
+
+    #[test]
+    fn test_comparison_chains() {
+        assert_eq!(dump("1 < x < 10"), dump("(1 < x) and (x < 10)"));
+        assert_eq!(dump("1 < x <= y < 10"), dump("((1 < x) and (x <= y)) and (y < 10)"));
+        assert_eq!(
+            dump("1 is less than x is less than 10"),
+            dump("(1 is less than x) and (x is less than 10)")
+        );
+        assert_eq!(
+            dump("0 is less than x plus 1 is less than or equal to n is less than 100"),
+            dump("((0 < (x plus 1)) and ((x plus 1) <= n)) and (n < 100)")
+        );
+        // A comparison chain stops at `and`
+        assert_eq!(dump("1 < x and x < 10"), dump("(1 < x) and (x < 10)"));
+        let err = parse_expr("0 < count of xs < 3").unwrap_err().to_string();
+        assert!(err.contains("chained comparisons are not supported"), "{}", err);
+    }
+
+    #[test]
+    fn test_words_are_whole_tokens() {
+        // `and`, `or` and `times` inside identifiers are not operators
+        assert_eq!(dump("Brand plus ordinal"), dump("(Brand) plus (ordinal)"));
+        assert!(matches!(parse_expr("sometimes").unwrap(), Expr::Ident(_)));
+        assert!(matches!(parse_expr("round").unwrap(), Expr::Ident(_)));
+    }
+
+    #[test]
+    fn test_phrases() {
+        assert!(matches!(
+            parse_expr("join words with \", \"").unwrap(),
+            Expr::JoinWith(..)
+        ));
+        assert!(matches!(
+            parse_expr("insert 9 at 0 in nums").unwrap(),
+            Expr::InsertAt(..)
+        ));
+        assert!(matches!(
+            parse_expr("json response with data and status 201").unwrap(),
+            Expr::JsonResponseStatus(..)
+        ));
+        assert!(matches!(parse_expr("set seen contains x").unwrap(), Expr::SetContains(..)));
+        assert!(matches!(parse_expr("set \"a\" in json doc to 1").unwrap(), Expr::JsonSet(..)));
+        assert_eq!(dump("union of a and b plus c"), dump("(union of a and b) plus c"));
+        assert_eq!(dump("a plus 1 if b else c"), dump("(a plus 1) if b else c"));
+        assert_eq!(dump("a if b else c if d else e"), dump("a if b else (c if d else e)"));
+        assert!(parse_expr("a if b").is_err());
+        assert_eq!(
+            dump("json response with data with etag with cache for 60 seconds"),
+            dump("(json response with data) with etag with cache for 60 seconds")
+        );
+        match parse_expr("html response with page with etag").unwrap() {
+            Expr::WithEtag(response) => assert!(matches!(*response, Expr::HtmlResponse(_))),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_expr("Make a list of 1, 2 plus 3 and f with 4, 5").unwrap() {
+            Expr::ListLit(items) => assert_eq!(items.len(), 3),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_expr("Make a dictionary with \"xs\" as Make a list of 1, 2 and \"n\" as 3").unwrap() {
+            Expr::DictLit(pairs) => assert!(matches!(&pairs[..], [(_, Expr::ListLit(xs)), _] if xs.len() == 2)),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_expr("tuple of 1, \"two\" and 3").unwrap() {
+            Expr::TupleLit(items) => assert_eq!(items.len(), 3),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_expr("error of type ValueError with message \"bad\"").unwrap() {
+            Expr::NewError { error_type, .. } => assert_eq!(error_type, "ValueError"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_expr("pick \"name\", \"age\" and \"email\" from user").unwrap() {
+            Expr::Pick(_, keys) => assert_eq!(keys, ["name", "age", "email"]),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(parse_expr("omit \"password\" from user").unwrap(), Expr::Omit(..)));
+        assert_eq!(
+            dump("find index of first item in scores plus extra where is_high plus 1"),
+            dump("(find index of first item in (scores plus extra) where is_high) plus 1")
+        );
+        assert!(matches!(parse_expr("find all items in xs where odd").unwrap(), Expr::FindAll(..)));
+        match parse_expr("join collect field \"name\" from each item in people with \", \"").unwrap() {
+            Expr::JoinWith(list, _) => assert!(matches!(*list, Expr::GroupConcat(..))),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_expr("collect fields \"name\", \"email\" from each item in users").unwrap() {
+            Expr::Pluck(keys, _) => assert!(matches!(*keys, Expr::ListLit(ref k) if k.len() == 2)),
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_expr("aggregate list orders computing \"total\" as sum of \"price\" and \"n\" as count of \"id\"")
+            .unwrap()
+        {
+            Expr::Aggregate(_, fields) => {
+                let functions: Vec<_> = fields.iter().map(|f| (f.alias.as_str(), f.function)).collect();
+                assert_eq!(functions, [("total", AggFunc::Sum), ("n", AggFunc::Count)]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(parse_expr("aggregate list orders computing \"total\" as median of \"price\"").is_err());
+        // Without quoted keys and `from`, pick is just a name
+        assert_eq!(dump("pick plus 1"), dump("(pick) plus 1"));
+        assert_eq!(
+            dump("clamp x plus 1 between low and high times 2"),
+            dump("(clamp x plus 1 between low and high) times 2")
+        );
+        assert!(matches!(parse_expr("interpolate from a to b at t").unwrap(), Expr::Lerp(..)));
+        assert!(matches!(parse_expr("validate body against user_schema").unwrap(), Expr::ValidateAgainst(..)));
+        assert!(matches!(parse_expr("validate body as User").unwrap(), Expr::Validate(..)));
+        assert!(matches!(
+            parse_expr("receive message from socket with timeout 5 seconds").unwrap(),
+            Expr::ReceiveMessage { timeout: Some(_), .. }
+        ));
+        assert!(parse_expr("receive message from socket with timeout 5").is_err());
+        assert!(matches!(
+            parse_expr("map reading from range 0 to 1023 into range 0 to 255").unwrap(),
+            Expr::MapRange(..)
+        ));
+        // A variable can still be called clamp or map
+        assert_eq!(dump("clamp plus map"), dump("(clamp) plus (map)"));
+        assert!(matches!(parse_expr("map(xs)").unwrap(), Expr::Call { .. }));
+    }
+
+    #[test]
+    fn test_errors() {
+        for (src, msg) in [
+            ("", "Empty expression"),
+            ("[1, 2]", "Bracket list literals"),
+            ("()", "Empty parentheses"),
+            ("(1 plus 2", "Could not parse expression"),
+            ("line x", "Could not parse expression: line x"),
+        ] {
+            let err = parse_expr(src).unwrap_err().to_string();
+            assert!(err.contains(msg), "{:?} gave {:?}", src, err);
+        }
+    }
+
+    #[test]
+    fn test_web_server_limits_come_in_any_order() {
+        let e = parse_expr("create web server on port 8080 with request timeout 30 seconds with body limit 2 MB").unwrap();
+        let Expr::CreateWebServer {
+            port,
+            body_limit: Some(bytes),
+            request_timeout: Some(secs),
+            tls: None,
+        } = e
+        else {
+            panic!("{:?}", e);
+        };
+        assert!(matches!(*port, Expr::Int(8080)));
+        assert!(matches!(*bytes, Expr::Times(ref n, ref unit) if matches!(**n, Expr::Int(2)) && matches!(**unit, Expr::Int(1048576))), "{:?}", bytes);
+        assert!(matches!(*secs, Expr::Int(30)));
+        assert!(matches!(
+            parse_expr("create web server on port p with body limit limit").unwrap(),
+            Expr::CreateWebServer { body_limit: Some(_), request_timeout: None, .. }
+        ));
+        assert!(parse_expr("create web server on port 80 with request timeout 5").is_err());
+        assert!(matches!(
+            parse_expr("create secure web server on port 443 with certificate \"cert.pem\" and key \"key.pem\" with body limit 1 MB").unwrap(),
+            Expr::CreateWebServer { tls: Some(_), body_limit: Some(_), .. }
+        ));
+        assert!(parse_expr("create secure web server on port 443").is_err());
+        assert!(parse_expr("create secure web server on port 443 with certificate \"cert.pem\"").is_err());
+    }
+
+    #[test]
+    fn test_long_operator_chains_parse_in_linear_time() {
+        fn chain(n: usize) -> String {
+            let ops = ["plus", "times", "minus", "*", "+", "divided by"];
+            let mut s = String::from("x");
+            for i in 0..n {
+                s.push_str(&format!(" {} {}", ops[i % ops.len()], i));
+            }
+            s
+        }
+        let time = |n: usize| {
+            let src = chain(n);
+            let start = Instant::now();
+            parse_expr(&src).unwrap();
+            start.elapsed()
+        };
+        time(500);
+        let small = time(500);
+        let large = time(5000);
+        assert!(small < Duration::from_millis(100), "500 operators took {:?}", small);
+        // Ten times the input should cost nowhere near a hundred times the time
+        assert!(
+            large < small * 40 + Duration::from_millis(20),
+            "500 operators: {:?}, 5000 operators: {:?}",
+            small,
+            large
+        );
+    }
+
+}
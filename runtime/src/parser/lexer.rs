@@ -1,10 +1,255 @@
-// Placeholder for a future lexer implementation.
-// Current parser operates on lines/strings directly.
+// Tokenizer for expressions.
+// Statements are still recognised line by line; the text of each expression is
+// turned into tokens here and handed to the expression parser.
+
+use anyhow::{anyhow, Result};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    // Minimal placeholder to allow future migration
+    // A word: identifiers, keywords and phrase words alike. Qualified names
+    // such as `utils::greet` are a single token.
     Ident(String),
+    // Whole numbers that fit in an i64; everything else is a `Number`
+    Int(i64),
     Number(f64),
     String(String),
+    // `r"pattern"`: a regular expression, its backslashes kept as written
+    Regex(String),
+    // Operators, brackets and commas
+    Symbol(&'static str),
+}
+
+/// A token with the byte range it was read from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned {
+    pub token: Token,
+    pub start: usize,
+    pub end: usize,
+}
+
+// Longer symbols first so `>=` is not read as `>` followed by `=`
+pub(super) const SYMBOLS: &[&str] = &[
+    ">=", "<=", "==", "!=", "(", ")", "[", "]", "{", "}", ",", "+", "-", "*", "/", ">", "<", "=",
+];
+
+pub(super) fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+pub fn tokenize(src: &str) -> Result<Vec<Spanned>> {
+    let mut tokens = Vec::new();
+    let mut chars = src.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        if c == '"' || c == '\'' {
+            chars.next();
+            let (text, end) = read_string(&mut chars, c)
+                .ok_or_else(|| anyhow!("Unterminated string literal"))?;
+            tokens.push(Spanned {
+                token: Token::String(text),
+                start,
+                end,
+            });
+            continue;
+        }
+
+        if c == 'r' && src[start + 1..].starts_with('"') {
+            let (text, end) = read_pattern(src, start + 2)
+                .ok_or_else(|| anyhow!("Unterminated pattern literal"))?;
+            while chars.peek().is_some_and(|&(i, _)| i < end) {
+                chars.next();
+            }
+            tokens.push(Spanned {
+                token: Token::Regex(text),
+                start,
+                end,
+            });
+            continue;
+        }
+
+        if is_word_char(c) {
+            let end = word_end(src, start);
+            while chars.peek().is_some_and(|&(i, _)| i < end) {
+                chars.next();
+            }
+            let text = &src[start..end];
+            let token = if is_number(text) {
+                match text.parse::<i64>() {
+                    Ok(i) => Token::Int(i),
+                    Err(_) => Token::Number(
+                        text.parse().map_err(|_| anyhow!("Invalid number '{}'", text))?,
+                    ),
+                }
+            } else {
+                Token::Ident(text.to_string())
+            };
+            tokens.push(Spanned { token, start, end });
+            continue;
+        }
+
+        let rest = &src[start..];
+        let Some(sym) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) else {
+            return Err(anyhow!("Unexpected character '{}'", c));
+        };
+        for _ in 0..sym.len() {
+            chars.next();
+        }
+        tokens.push(Spanned {
+            token: Token::Symbol(sym),
+            start,
+            end: start + sym.len(),
+        });
+    }
+
+    Ok(tokens)
+}
+
+/// End of the word starting at `start`: word characters, a decimal point
+/// between digits, and `::` joining the parts of a qualified name
+pub(super) fn word_end(src: &str, start: usize) -> usize {
+    let bytes = src.as_bytes();
+    let mut end = start;
+    loop {
+        while let Some(c) = src[end..].chars().next().filter(|c| is_word_char(*c)) {
+            end += c.len_utf8();
+        }
+        let next_is_word = |at: usize| src[at..].chars().next().is_some_and(is_word_char);
+        let next_is_digit = |at: usize| bytes.get(at).is_some_and(u8::is_ascii_digit);
+        if bytes.get(end) == Some(&b'.') && is_number(&src[start..end]) && next_is_digit(end + 1) {
+            end += 1;
+        } else if src[end..].starts_with("::") && next_is_word(end + 2) {
+            end += 2;
+        } else {
+            return end;
+        }
+    }
+}
+
+pub(super) fn is_number(text: &str) -> bool {
+    let mut parts = text.splitn(2, '.');
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    parts.next().is_some_and(digits) && parts.next().is_none_or(digits)
+}
+
+/// Read a string body up to the closing `quote`, handling backslash escapes.
+/// Unknown escapes such as `\d` keep their backslash.
+fn read_string(
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+    quote: char,
+) -> Option<(String, usize)> {
+    let mut text = String::new();
+    while let Some((i, c)) = chars.next() {
+        if c == quote {
+            return Some((text, i + c.len_utf8()));
+        }
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.peek().map(|&(_, e)| e) {
+            Some('n') => text.push('\n'),
+            Some('t') => text.push('\t'),
+            Some('r') => text.push('\r'),
+            Some(e @ ('\\' | '"' | '\'')) => text.push(e),
+            _ => {
+                text.push('\\');
+                continue;
+            }
+        }
+        chars.next();
+    }
+    None
+}
+
+/// Read a pattern body starting at `start` up to the closing `"`. Nothing
+/// is unescaped: `\d` and `\"` reach the regex engine as written.
+pub(super) fn read_pattern(src: &str, start: usize) -> Option<(String, usize)> {
+    let mut chars = src[start..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((src[start..start + i].to_string(), start + i + 1)),
+            '\\' => {
+                chars.next()?;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<Token> {
+        tokenize(src).unwrap().into_iter().map(|t| t.token).collect()
+    }
+
+    fn ident(s: &str) -> Token {
+        Token::Ident(s.to_string())
+    }
+
+    #[test]
+    fn test_words_numbers_and_symbols() {
+        assert_eq!(
+            kinds("total of nums >= 2.5, (x-1)"),
+            vec![
+                ident("total"),
+                ident("of"),
+                ident("nums"),
+                Token::Symbol(">="),
+                Token::Number(2.5),
+                Token::Symbol(","),
+                Token::Symbol("("),
+                ident("x"),
+                Token::Symbol("-"),
+                Token::Int(1),
+                Token::Symbol(")"),
+            ]
+        );
+        assert_eq!(kinds("utils::greet(x)")[0], ident("utils::greet"));
+        assert_eq!(kinds("item2 3rd"), vec![ident("item2"), ident("3rd")]);
+        // Too big for an i64, so it can only be a float
+        assert_eq!(kinds("99999999999999999999"), vec![Token::Number(1e20)]);
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        assert_eq!(
+            kinds(r#""say \"hi\"\n" 'it\'s' "C:\temp\x""#),
+            vec![
+                Token::String("say \"hi\"\n".to_string()),
+                Token::String("it's".to_string()),
+                Token::String("C:\temp\\x".to_string()),
+            ]
+        );
+        let spans = tokenize("x plus \"a b\"").unwrap();
+        assert_eq!((spans[2].start, spans[2].end), (7, 12));
+    }
+
+    #[test]
+    fn test_patterns_keep_their_backslashes() {
+        assert_eq!(
+            kinds(r#"r"\d+\.\"" r "x" ar"y""#),
+            vec![
+                Token::Regex(r#"\d+\.\""#.to_string()),
+                ident("r"),
+                Token::String("x".to_string()),
+                ident("ar"),
+                Token::String("y".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(tokenize("\"open").is_err());
+        assert!(tokenize("r\"open").is_err());
+        assert!(tokenize("x ? y").is_err());
+    }
 }
@@ -1,8 +1,10 @@
 pub mod ast;
+mod expr;
 pub mod lexer;
 #[allow(clippy::module_inception)]
 pub mod parser;
 pub mod phrases;
+pub mod tokens;
 
 pub use ast::{CmpOp, Expr, Param, Program, Stmt};
-pub use parser::parse;
+pub use parser::{parse, parse_with_lines};
@@ -1,7 +1,24 @@
-use crate::parser::ast::{CatchHandler, CmpOp, Expr, Param, Program, Stmt};
+use crate::core::hints;
+use crate::parser::ast::{AskType, CatchHandler, Expr, LogLevel, Param, Program, SchemaField, Stmt};
+use crate::parser::expr;
 use crate::parser::phrases as P;
 use crate::parser::phrases::strip_prefix_ci;
 use anyhow::{anyhow, Result};
+use std::cell::Cell;
+
+thread_local! {
+    /// Set while `parse_with_lines` runs
+    static LINE_MARKERS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Like `parse`, but each statement is preceded by a `Stmt::Line` with its
+/// 1-based line number, for tracing
+pub fn parse_with_lines(src: &str) -> Result<Program> {
+    LINE_MARKERS.with(|m| m.set(true));
+    let prog = parse(src);
+    LINE_MARKERS.with(|m| m.set(false));
+    prog
+}
 
 // Helper to check if a line starts with any of the given prefixes (case-insensitive)
 fn line_starts_with_any(line: &str, prefixes: &[&str]) -> bool {
@@ -13,54 +30,11 @@ fn line_starts_with_any(line: &str, prefixes: &[&str]) -> bool {
     false
 }
 
-fn suggest_fix(error_msg: &str, context: &str) -> String {
-    let suggestions = vec![
-        (
-            "Expected 'with'",
-            "Hint: Function definitions use 'Define function name with parameter as expression'",
-        ),
-        (
-            "Expected function name",
-            "Hint: Function name must be a valid identifier (letters, numbers, underscore)",
-        ),
-        (
-            "Expected 'as <expr>'",
-            "Hint: Inline functions need 'as' followed by an expression",
-        ),
-        (
-            "Expected variable name",
-            "Hint: Variable names must start with a letter or underscore",
-        ),
-        (
-            "Could not parse expression",
-            "Hint: Check for unmatched brackets [], braces {}, or parentheses ()",
-        ),
-        (
-            "Empty expression",
-            "Hint: Expressions cannot be empty. Provide a value, variable, or operation",
-        ),
-        (
-            "Unsupported statement",
-            "Hint: Valid statements: Write, Set, Ask for, If, Repeat, While, Make, Use, Import",
-        ),
-        (
-            "out of range",
-            "Hint: Check array bounds. Use negative indexing (-1) for last element",
-        ),
-        (
-            "not found",
-            "Hint: Verify the key exists in the dictionary or check for typos",
-        ),
-        (
-            "division by zero",
-            "Hint: Ensure denominator is not zero before dividing",
-        ),
-    ];
-
-    for (pattern, suggestion) in suggestions {
-        if error_msg.contains(pattern) {
-            return format!("{}.\n{}", error_msg, suggestion);
-        }
+/// The error with its hint from the registry, or failing that one about
+/// the line it came from
+pub(crate) fn suggest_fix(error_msg: &str, context: &str) -> String {
+    if hints::best_match(error_msg).is_some() {
+        return hints::with_hint(error_msg);
     }
 
     // If context is provided and looks like incomplete syntax, add context-specific hint
@@ -71,9 +45,9 @@ fn suggest_fix(error_msg: &str, context: &str) -> String {
                 error_msg
             );
         }
-        if context.contains("If ") && !context.contains(" Write ") {
+        if context.contains("If ") && !context.contains(" Write ") && !context.contains(" then ") {
             return format!(
-                "{}.\nHint: Inline If needs: If condition Write expression Otherwise expression",
+                "{}.\nHint: Inline If needs: If condition then statement else statement",
                 error_msg
             );
         }
@@ -159,6 +133,21 @@ fn parse_until_keywords(lines: &[&str], i: &mut usize, stops: &[&str]) -> Result
             continue;
         }
 
+        if LINE_MARKERS.with(Cell::get) {
+            out.push(Stmt::Line(*i + 1));
+        }
+
+        // Define schema User with name as required string, age as optional number, ...
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_DEFINE_SCHEMA) {
+            let (name, after) = split_ident(rest).ok_or_else(|| anyhow!("Expected a schema name after 'Define schema'"))?;
+            let Some(fields) = after.trim_start().strip_prefix("with ") else {
+                return Err(anyhow!("Expected 'with <field> as <type>, ...' after 'Define schema {}'", name));
+            };
+            let fields = schema_fields(&name, fields)?;
+            out.push(Stmt::Schema { name, fields });
+            *i += 1;
+            continue;
+        }
         // Define function (inline)
         if let Some(rest) = t.strip_prefix("Define function ") {
             let (name, after_name) =
@@ -179,6 +168,12 @@ fn parse_until_keywords(lines: &[&str], i: &mut usize, stops: &[&str]) -> Result
                 return Err(anyhow!("Expected 'as <expr>'"));
             }
         }
+        // Respond with <value>
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_RESPOND_WITH) {
+            out.push(Stmt::Respond(parse_expr(rest.trim())?));
+            *i += 1;
+            continue;
+        }
         // Write statement - check for "to file" pattern first
         if let Some(rest) = t.strip_prefix("Write ") {
             // Check if it's "Write <content> to file at <path>"
@@ -191,6 +186,23 @@ fn parse_until_keywords(lines: &[&str], i: &mut usize, stops: &[&str]) -> Result
                 *i += 1;
                 continue;
             }
+            // Write <expr> nicely
+            let cut = rest.len().saturating_sub(P::P_NICELY.len());
+            if let Some(value) = rest
+                .get(cut..)
+                .filter(|tail| tail.eq_ignore_ascii_case(P::P_NICELY))
+                .and_then(|_| rest.get(..cut))
+            {
+                out.push(Stmt::Write(Expr::Pretty(Box::new(parse_expr(value.trim())?))));
+                *i += 1;
+                continue;
+            }
+            // Write a, b, c [separated by ...] [ending with ...]
+            if let Some(multi) = multi_write(rest)? {
+                out.push(multi);
+                *i += 1;
+                continue;
+            }
             // Regular Write statement
             let expr = parse_expr(rest)?;
             out.push(Stmt::Write(expr));
@@ -219,35 +231,158 @@ fn parse_until_keywords(lines: &[&str], i: &mut usize, stops: &[&str]) -> Result
             *i += 1;
             continue;
         }
-        // Ask for
+        // Ask for <name> [as number | as yes or no | as text]
         if let Some(rest) = t.strip_prefix("Ask for ") {
             if let Some((var_name, rest_after)) = split_ident(rest) {
-                if rest_after.trim().is_empty() {
-                    out.push(Stmt::AskFor { var_name });
+                let rest_after = rest_after.trim();
+                let expect = if rest_after.is_empty() {
+                    Some(AskType::Any)
+                } else {
+                    strip_prefix_ci(rest_after, P::P_ASK_AS)
+                        .and_then(|kind| AskType::from_phrase(kind.trim()))
+                };
+                if let Some(expect) = expect {
+                    out.push(Stmt::AskFor { var_name, expect });
                     *i += 1;
                     continue;
                 }
+                return Err(anyhow!(
+                    "Expected 'as number', 'as yes or no' or 'as text' after 'Ask for {}', got: {}",
+                    var_name,
+                    rest_after
+                ));
             }
             return Err(anyhow!("Expected variable name after 'Ask for'"));
         }
+        // Load env vars starting with "PREFIX" [stripping prefix]
+        if let Some(rest) = t.strip_prefix("Load env vars starting with ") {
+            let rest = rest.trim();
+            let (prefix_part, strip_prefix) = match rest.strip_suffix(" stripping prefix") {
+                Some(p) => (p.trim_end(), true),
+                None => (rest, false),
+            };
+            let Expr::Str(prefix) = parse_expr(prefix_part)? else {
+                return Err(anyhow!(
+                    "Expected a quoted prefix, e.g. Load env vars starting with \"APP_\""
+                ));
+            };
+            out.push(Stmt::PutEnvInScope {
+                prefix,
+                strip_prefix,
+            });
+            *i += 1;
+            continue;
+        }
+        // Print table with headers <list> and rows <list of lists>
+        if let Some(rest) = t.strip_prefix("Print table with headers ") {
+            let (headers_part, rows_part) = split_once_word(rest, " and rows ").ok_or_else(|| {
+                anyhow!("Expected 'Print table with headers <list> and rows <list>'")
+            })?;
+            out.push(Stmt::PrintTable {
+                headers: parse_expr(headers_part.trim())?,
+                rows: parse_expr(rows_part.trim())?,
+            });
+            *i += 1;
+            continue;
+        }
         // Call statement (alias of Use)
         if let Some(rest) = t.strip_prefix("Call ") {
-            let (name, after_name) =
-                split_ident(rest).ok_or_else(|| anyhow!("Expected function name"))?;
-            let after_with = after_name.trim_start().strip_prefix("with ").unwrap_or("");
-            let args = if after_with.is_empty() {
-                vec![]
-            } else {
-                parse_arg_list_multi(after_with, true)?
+            out.push(use_call(rest)?);
+            *i += 1;
+            continue;
+        }
+        // Send event <data> to <stream>; the last ' to ' ends the data
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_SEND_EVENT) {
+            let Some((data, stream)) = rest.rsplit_once(P::P_SEND_EVENT_TO) else {
+                return Err(anyhow!("Expected 'to <stream>' in 'Send event {}'", rest.trim()));
+            };
+            out.push(Stmt::SendEvent {
+                data: parse_expr(data.trim())?,
+                stream: parse_expr(stream.trim())?,
+            });
+            *i += 1;
+            continue;
+        }
+        // Send <message> to <socket>, after 'Send event'
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_SEND) {
+            let Some((message, socket)) = rest.rsplit_once(P::P_SEND_TO) else {
+                return Err(anyhow!("Expected 'to <socket>' in 'Send {}'", rest.trim()));
             };
-            out.push(Stmt::Use { name, args });
+            out.push(Stmt::SendMessage {
+                message: parse_expr(message.trim())?,
+                socket: parse_expr(socket.trim())?,
+            });
+            *i += 1;
+            continue;
+        }
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_CLOSE) {
+            out.push(Stmt::CloseStream(parse_expr(rest.trim())?));
+            *i += 1;
+            continue;
+        }
+        // Set template directory to <dir> - also before Set
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_SET_TEMPLATE_DIR) {
+            out.push(Stmt::TemplateDirectory(parse_expr(rest.trim())?));
+            *i += 1;
+            continue;
+        }
+        // Set response header X to Y - checked before Set, which would
+        // otherwise read it as assigning to a variable named 'response'
+        if P::strip_prefix_ci(t, P::P_SET_RESPONSE_HEADER).is_some() {
+            out.push(Stmt::Evaluate(parse_expr(t)?));
             *i += 1;
             continue;
         }
         // Set
         if let Some(rest) = t.strip_prefix("Set ") {
-            if let Some((name, after)) = split_ident(rest) {
+            // Set constant limit to 100; a variable named constant is still
+            // set with Set constant to ...
+            if let Some((name, after)) = rest.strip_prefix("constant ").and_then(split_ident) {
+                if let Some(after) = after.trim_start().strip_prefix("to ") {
+                    if stops != ["End Program"] {
+                        return Err(anyhow!(
+                            "Set constant {}: constants are set at the top level of a program, not inside a block",
+                            name
+                        ));
+                    }
+                    let value = parse_expr(after)?;
+                    if !value.is_literal() {
+                        return Err(anyhow!(
+                            "Set constant {}: the value must be a number, text, True, False, None, or a list or dictionary of those",
+                            name
+                        ));
+                    }
+                    out.push(Stmt::GlobalConst { name, value });
+                    *i += 1;
+                    continue;
+                }
+            }
+            if let Some((name, key, after)) = split_item_target(rest) {
                 let after = after.trim_start();
+                let value = parse_expr(after.strip_prefix("to ").unwrap_or(after))?;
+                let key = parse_expr(key.trim())?;
+                out.push(Stmt::SetItem { name, key, value });
+                *i += 1;
+                continue;
+            }
+            if let Some((names, after)) = split_destructure_targets(rest) {
+                let value = parse_expr(after)?;
+                out.push(Stmt::Destructure { names, value });
+                *i += 1;
+                continue;
+            }
+            if let Some((name, after)) = split_ident(rest) {
+                let mut after = after.trim_start();
+                // Set age as Age to 30 declares the type of age
+                if let Some(typed) = after.strip_prefix("as ") {
+                    let (type_name, rest) = split_ident(typed.trim_start())
+                        .ok_or_else(|| anyhow!("Expected a type name after 'Set {} as'", name))?;
+                    out.push(Stmt::DeclareType {
+                        name: name.clone(),
+                        type_name,
+                    });
+                    after = rest.trim_start();
+                }
                 let after = after.strip_prefix("to ").unwrap_or(after);
                 let expr = parse_expr(after)?;
                 out.push(Stmt::Set { name, value: expr });
@@ -255,26 +390,40 @@ fn parse_until_keywords(lines: &[&str], i: &mut usize, stops: &[&str]) -> Result
                 continue;
             }
         }
-        // Increase name by expr -> desugar to Set name to name plus expr
+        // Type Age is number
+        if let Some(rest) = t.strip_prefix("Type ") {
+            let (name, after) = split_ident(rest).ok_or_else(|| anyhow!("Expected a type name after 'Type'"))?;
+            let Some(base) = after.trim_start().strip_prefix("is ") else {
+                return Err(anyhow!("Expected 'is' after 'Type {}'", name));
+            };
+            let base = base.trim().trim_matches('"');
+            match split_ident(base) {
+                Some((base_type, "")) => out.push(Stmt::TypeAlias { name, base_type }),
+                _ => return Err(anyhow!("Expected one type name after 'Type {} is', got: {}", name, base)),
+            }
+            *i += 1;
+            continue;
+        }
+        // Increase name [by expr] -> desugar to Set name to name plus expr (1 without by)
         if let Some(rest) = t.strip_prefix("Increase ") {
-            if let Some((name, after)) = split_ident(rest) {
-                let after = after.trim_start();
-                let amount_str = after.strip_prefix("by ").unwrap_or(after);
-                let amount = parse_expr(amount_str)?;
-                let value = Expr::Plus(Box::new(Expr::Ident(name.clone())), Box::new(amount));
-                out.push(Stmt::Set { name, value });
+            if let Some(stmt) = parse_step("Increase", rest, Expr::Plus)? {
+                out.push(stmt);
                 *i += 1;
                 continue;
             }
         }
-        // Decrease name by expr -> desugar to Set name to name minus expr
+        // Decrease name [by expr] -> desugar to Set name to name minus expr (1 without by)
         if let Some(rest) = t.strip_prefix("Decrease ") {
-            if let Some((name, after)) = split_ident(rest) {
-                let after = after.trim_start();
-                let amount_str = after.strip_prefix("by ").unwrap_or(after);
-                let amount = parse_expr(amount_str)?;
-                let value = Expr::Minus(Box::new(Expr::Ident(name.clone())), Box::new(amount));
-                out.push(Stmt::Set { name, value });
+            if let Some(stmt) = parse_step("Decrease", rest, Expr::Minus)? {
+                out.push(stmt);
+                *i += 1;
+                continue;
+            }
+        }
+        // If <cond> then <stmt> [else <stmt>], as an If block of one statement
+        if let Some(rest) = t.strip_prefix("If ") {
+            if let Some(stmt) = parse_if_then(rest)? {
+                out.push(stmt);
                 *i += 1;
                 continue;
             }
@@ -361,7 +510,7 @@ fn parse_until_keywords(lines: &[&str], i: &mut usize, stops: &[&str]) -> Result
             }
 
             if let Some(rest_expose) = remainder.strip_prefix("exposing ") {
-                exposing = parse_exposing_list(rest_expose)?;
+                exposing = parse_symbol_list(rest_expose, "exposing list")?;
                 remainder = "";
             }
 
@@ -377,14 +526,43 @@ fn parse_until_keywords(lines: &[&str], i: &mut usize, stops: &[&str]) -> Result
             *i += 1;
             continue;
         }
+        // Import a, b from "file"
+        if let Some((names, from)) = t
+            .strip_prefix("Import ")
+            .filter(|rest| !rest.trim_start().starts_with('"'))
+            .and_then(|rest| rest.rsplit_once(" from "))
+        {
+            let (path, remainder) =
+                extract_quoted_and_rest(from.trim()).ok_or_else(|| anyhow!("Expected quoted path after 'from'"))?;
+            if !remainder.trim().is_empty() {
+                return Err(anyhow!("Unexpected trailing content in import"));
+            }
+            out.push(Stmt::ImportItems {
+                path,
+                items: parse_symbol_list(names, "import list")?,
+            });
+            *i += 1;
+            continue;
+        }
         if let Some(rest) = t.strip_prefix("Import ") {
-            if let Some(p) = extract_quoted(rest.trim()) {
+            let (p, remainder) =
+                extract_quoted_and_rest(rest.trim()).ok_or_else(|| anyhow!("Expected quoted path"))?;
+            let remainder = remainder.trim();
+            if remainder.is_empty() {
                 out.push(Stmt::ImportLocal { path: p });
-                *i += 1;
-                continue;
+            } else if let Some(alias_part) = remainder.strip_prefix("as ") {
+                match split_ident(alias_part.trim_start()) {
+                    Some((alias, after)) if after.trim().is_empty() => {
+                        out.push(Stmt::ImportAs { path: p, alias });
+                    }
+                    Some(_) => return Err(anyhow!("Unexpected content after alias in import")),
+                    None => return Err(anyhow!("Expected alias name after 'as'")),
+                }
             } else {
-                return Err(anyhow!("Expected quoted path"));
+                return Err(anyhow!("Unexpected trailing content in import"));
             }
+            *i += 1;
+            continue;
         }
         // While block
         if let Some(rest) = t.strip_prefix("While ") {
@@ -402,6 +580,22 @@ fn parse_until_keywords(lines: &[&str], i: &mut usize, stops: &[&str]) -> Result
             });
             continue;
         }
+        // Repeat until block
+        if let Some(rest) = t.strip_prefix("Repeat until ") {
+            let cond_expr = parse_expr(rest.trim())?;
+            *i += 1;
+            let body = parse_until_keywords(lines, i, &["End"])?;
+            if *i < lines.len() && lines[*i].trim() == "End" {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End' after Repeat until"));
+            }
+            out.push(Stmt::RepeatUntil {
+                cond: cond_expr,
+                body,
+            });
+            continue;
+        }
         // Repeat block
         if let Some(rest) = t.strip_prefix("Repeat ") {
             let mut r = rest.trim().to_string();
@@ -454,175 +648,649 @@ fn parse_until_keywords(lines: &[&str], i: &mut usize, stops: &[&str]) -> Result
         }
         // Use
         if let Some(rest) = t.strip_prefix("Use ") {
-            let (name, after_name) =
-                split_ident(rest).ok_or_else(|| anyhow!("Expected function name"))?;
-            let after_with = after_name.trim_start().strip_prefix("with ").unwrap_or("");
-            let args = if after_with.is_empty() {
-                vec![]
+            out.push(use_call(rest)?);
+            *i += 1;
+            continue;
+        }
+        // On shutdown: ... End On Shutdown
+        if P::strip_prefix_ci(t, P::P_ON_SHUTDOWN).is_some() {
+            *i += 1;
+            let body = parse_until_keywords(lines, i, &[P::P_END_ON_SHUTDOWN])?;
+            if *i < lines.len() && P::strip_prefix_ci(lines[*i].trim(), P::P_END_ON_SHUTDOWN).is_some() {
+                *i += 1;
             } else {
-                parse_arg_list_multi(after_with, true)?
+                return Err(anyhow!("Expected 'End On Shutdown' after 'On shutdown:' block"));
+            }
+            out.push(Stmt::OnShutdown { body });
+            continue;
+        }
+        // Create event emitter X
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_CREATE_EVENT_EMITTER) {
+            let name = match split_ident(rest.trim()) {
+                Some((name, "")) => name,
+                _ => return Err(anyhow!("Expected a name after 'Create event emitter', got '{}'", rest.trim())),
             };
-            out.push(Stmt::Use { name, args });
+            out.push(Stmt::EventEmitter { name });
             *i += 1;
             continue;
         }
-        // Try-Catch
-        if P::strip_prefix_ci(t, P::P_TRY).is_some() {
+        // On event "click" from emitter X: ... End On
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_ON_EVENT) {
+            let (event, after) = extract_quoted_and_rest(rest)
+                .ok_or_else(|| anyhow!("Expected the event name in quotes after 'On event'"))?;
+            let emitter = P::strip_prefix_ci(after.trim_start(), P::P_FROM_EMITTER)
+                .and_then(|e| e.trim_end().strip_suffix(':'))
+                .and_then(|e| match split_ident(e.trim()) {
+                    Some((name, "")) => Some(name),
+                    _ => None,
+                })
+                .ok_or_else(|| anyhow!("Expected 'from emitter <name>:' after 'On event \"{}\"'", event))?;
             *i += 1;
-            // Parse try block
-            let try_block = parse_until_keywords(
-                lines,
-                i,
-                &[P::P_IF_ERROR, P::P_FINALLY, P::P_END_TRY, "End"],
-            )?;
-
-            let mut catch_handlers = Vec::new();
-            let mut finally_block = None;
-
-            // Parse catch handlers
-            while *i < lines.len() {
-                let line = lines[*i].trim();
-
-                // Check for "if error" catch clause
-                if let Some(rest) = P::strip_prefix_ci(line, P::P_IF_ERROR) {
-                    *i += 1;
-
-                    let rest = rest.trim();
-                    let mut error_type = None;
-                    let mut var_name = None;
-
-                    // Check for "of type X"
-                    if let Some(after_of_type) = strip_prefix_ci(rest, "of type ") {
-                        // Split on " as " to get type and variable name
-                        if let Some((type_part, var_part)) = split_once_word(after_of_type, " as ")
-                        {
-                            error_type = Some(type_part.trim().trim_matches('"').to_string());
-                            var_name = Some(var_part.trim().to_string());
-                        } else {
-                            // Just type, no variable
-                            error_type = Some(after_of_type.trim().trim_matches('"').to_string());
-                        }
-                    } else if let Some(rest_as) = strip_prefix_ci(rest, "as ") {
-                        // Just "if error as var_name" - catch all errors
-                        var_name = Some(rest_as.trim().to_string());
-                    }
-                    // else: just "if error" - catch all without binding
-
-                    // Parse catch body
-                    let block = parse_until_keywords(
-                        lines,
-                        i,
-                        &[P::P_IF_ERROR, P::P_FINALLY, P::P_END_TRY, "End"],
-                    )?;
-
-                    catch_handlers.push(CatchHandler {
-                        error_type,
-                        var_name,
-                        block,
-                    });
-                    continue;
-                }
-
-                // Check for "finally"
-                if P::strip_prefix_ci(line, P::P_FINALLY).is_some() {
-                    *i += 1;
-                    finally_block = Some(parse_until_keywords(lines, i, &[P::P_END_TRY, "End"])?);
-                    continue;
-                }
-
-                // Check for "end try"
-                if P::strip_prefix_ci(line, P::P_END_TRY).is_some() || line == "End" {
-                    *i += 1;
-                    break;
-                }
-
-                return Err(anyhow!(
-                    "Expected 'if error', 'finally', or 'end try', found '{}'",
-                    line
-                ));
+            let body = parse_until_keywords(lines, i, &[P::P_END_ON])?;
+            if *i < lines.len() && lines[*i].trim().eq_ignore_ascii_case(P::P_END_ON) {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End On' after 'On event \"{}\"' block", event));
             }
-
-            out.push(Stmt::TryCatch {
-                try_block,
-                catch_handlers,
-                finally_block,
-            });
+            out.push(Stmt::On { emitter, event, body });
             continue;
         }
-        // Return
-        if let Some(rest) = t.strip_prefix("Return") {
-            let r = rest.trim();
-            let expr = if r.is_empty() {
-                None
-            } else {
-                Some(parse_expr(r)?)
+        // Emit "click" from X with data Y; without 'with data' the data is nothing
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_EMIT) {
+            let (event, after) = extract_quoted_and_rest(rest)
+                .ok_or_else(|| anyhow!("Expected the event name in quotes after 'Emit'"))?;
+            let (emitter, after) = P::strip_prefix_ci(after.trim_start(), P::P_EMIT_FROM)
+                .and_then(|e| split_ident(e.trim_start()))
+                .ok_or_else(|| anyhow!("Expected 'from <emitter>' after 'Emit \"{}\"'", event))?;
+            let data = match after.trim() {
+                "" => Expr::Null,
+                after => match P::strip_prefix_ci(after, P::P_WITH_DATA) {
+                    Some(data) => parse_expr(data.trim())?,
+                    None => return Err(anyhow!("Expected 'with data <value>' after 'Emit \"{}\" from {}'", event, emitter)),
+                },
             };
-            out.push(Stmt::Return(expr));
+            out.push(Stmt::Emit { emitter, event, data });
             *i += 1;
             continue;
         }
-        // Throw
-        if let Some(rest) = P::strip_prefix_ci(t, P::P_THROW) {
-            let expr = parse_expr(rest.trim())?;
-            out.push(Stmt::Throw(expr));
+        // For each line in stdin as X: ... End For
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_FOR_EACH_LINE_IN_STDIN) {
+            let var_name = match rest.trim_end().strip_suffix(':').map(|r| split_ident(r.trim())) {
+                Some(Some((name, ""))) => name,
+                _ => return Err(anyhow!("Expected 'For each line in stdin as <name>:'")),
+            };
             *i += 1;
+            let body = parse_until_keywords(lines, i, &[P::P_END_FOR])?;
+            if *i < lines.len() && P::strip_prefix_ci(lines[*i].trim(), P::P_END_FOR).is_some() {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End For' after 'For each line in stdin' block"));
+            }
+            out.push(Stmt::ReadLineLoop { var_name, body });
             continue;
         }
-
-        // Web Framework Statements
-        // Add route <path> with method <method> to server:
-        if let Some(rest) = P::strip_prefix_ci(t, "add route ") {
-            if let Some((path_and_method, _)) = rest.split_once(" to server:") {
-                if let Some((path_part, method_part)) = path_and_method.split_once(" with method ")
-                {
-                    let path_expr = parse_expr(path_part.trim())?;
-                    let method_expr = parse_expr(method_part.trim())?;
-
-                    // Parse handler block (indented lines following)
-                    *i += 1;
-                    let handler_start = *i;
-
-                    // Find indented handler lines
-                    while *i < lines.len() {
-                        let line = lines[*i];
-                        if line.trim().is_empty() {
-                            *i += 1;
-                            continue;
-                        }
-                        // If line starts with whitespace, it's part of the handler
-                        if line.starts_with(' ') || line.starts_with('\t') {
-                            *i += 1;
-                        } else {
-                            break;
-                        }
-                    }
-
-                    // Parse handler as a program
-                    let handler_lines: Vec<&str> = lines[handler_start..*i]
-                        .iter()
-                        .map(|l| l.trim_start())
-                        .collect();
-                    let mut handler_i = 0;
-                    let handler_prog = parse_until_keywords(&handler_lines, &mut handler_i, &[])?;
-
-                    out.push(Stmt::AddRoute {
-                        path: path_expr,
-                        method: method_expr,
-                        handler: handler_prog,
-                    });
-                    continue;
-                }
+        // For each X in <list>: ... End For
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_FOR_EACH) {
+            let header = rest.trim_end().strip_suffix(':').and_then(|r| split_ident(r.trim_start()));
+            let (var_name, items) = match header {
+                Some((name, after)) => match P::strip_prefix_ci(after, P::P_FOR_EACH_IN) {
+                    Some(items) if !items.trim().is_empty() => (name, parse_expr(items.trim())?),
+                    _ => return Err(anyhow!("Expected 'For each <name> in <list>:'")),
+                },
+                None => return Err(anyhow!("Expected 'For each <name> in <list>:'")),
+            };
+            *i += 1;
+            let body = parse_until_keywords(lines, i, &[P::P_END_FOR])?;
+            if *i < lines.len() && P::strip_prefix_ci(lines[*i].trim(), P::P_END_FOR).is_some() {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End For' after 'For each {}' block", var_name));
             }
+            out.push(Stmt::ForEach { var_name, items, body });
+            continue;
         }
-
-        // Add middleware "<type>" to server (with optional config)
-        // Examples:
-        //   Add middleware "cors" to server
-        //   Add middleware "logging" to server
-        //   Add middleware "auth" with token "secret123" to server
-        if let Some(rest) = P::strip_prefix_ci(t, "add middleware ") {
-            if let Some((type_and_config, _)) = rest.split_once(" to server") {
-                // Extract middleware type (quoted string)
+        // require <value> to match <schema>
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_REQUIRE) {
+            let Some(&at) = top_level_matches(rest, P::P_TO_MATCH).first() else {
+                return Err(anyhow!("Expected 'to match <schema>' in 'require {}'", rest.trim()));
+            };
+            out.push(Stmt::Require {
+                value: parse_expr(rest[..at].trim())?,
+                schema: parse_expr(rest[at + P::P_TO_MATCH.len()..].trim())?,
+            });
+            *i += 1;
+            continue;
+        }
+        // Assert <actual> equals <expected>, or deep equals for a diff
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_ASSERT) {
+            let (deep_equal, phrase) = if top_level_matches(rest, P::P_DEEP_EQUALS).is_empty() {
+                (false, P::P_EQUALS)
+            } else {
+                (true, P::P_DEEP_EQUALS)
+            };
+            let Some(&at) = top_level_matches(rest, phrase).first() else {
+                return Err(anyhow!("Expected 'equals <value>' in 'Assert {}'", rest.trim()));
+            };
+            out.push(Stmt::Assert {
+                deep_equal,
+                left: parse_expr(rest[..at].trim())?,
+                right: parse_expr(rest[at + phrase.len()..].trim())?,
+            });
+            *i += 1;
+            continue;
+        }
+        // Before each route: ... End Before, and After each route: ... End After
+        if P::strip_prefix_ci(t, P::P_BEFORE_EACH_ROUTE).is_some() {
+            *i += 1;
+            let body = parse_until_keywords(lines, i, &[P::P_END_BEFORE])?;
+            if *i < lines.len() && P::strip_prefix_ci(lines[*i].trim(), P::P_END_BEFORE).is_some() {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End Before' after 'Before each route:' block"));
+            }
+            out.push(Stmt::BeforeEachRoute { body });
+            continue;
+        }
+        if P::strip_prefix_ci(t, P::P_AFTER_EACH_ROUTE).is_some() {
+            *i += 1;
+            let body = parse_until_keywords(lines, i, &[P::P_END_AFTER])?;
+            if *i < lines.len() && P::strip_prefix_ci(lines[*i].trim(), P::P_END_AFTER).is_some() {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End After' after 'After each route:' block"));
+            }
+            out.push(Stmt::AfterEachRoute { body });
+            continue;
+        }
+        // Benchmark "name": ... End Benchmark
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_BENCHMARK) {
+            let Some(name) = rest.trim_end().strip_suffix(':') else {
+                return Err(anyhow!("Expected ':' after the name in 'Benchmark {}'", rest.trim()));
+            };
+            let name = parse_expr(name.trim())?;
+            *i += 1;
+            let body = parse_until_keywords(lines, i, &[P::P_END_BENCHMARK])?;
+            if *i < lines.len() && P::strip_prefix_ci(lines[*i].trim(), P::P_END_BENCHMARK).is_some() {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End Benchmark' after 'Benchmark' block"));
+            }
+            out.push(Stmt::Benchmark { name, body });
+            continue;
+        }
+        // Measure time of "name": ... End Measure, or with
+        // `storing result in elapsed:` to keep the milliseconds instead
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_MEASURE) {
+            let Some(head) = rest.trim_end().strip_suffix(':') else {
+                return Err(anyhow!("Expected ':' after the name in 'Measure time of {}'", rest.trim()));
+            };
+            let (name, store_in) = match top_level_matches(head, P::P_STORING_RESULT_IN).last() {
+                Some(&at) => match split_ident(head[at + P::P_STORING_RESULT_IN.len()..].trim()) {
+                    Some((var, "")) => (&head[..at], Some(var)),
+                    _ => {
+                        return Err(anyhow!(
+                            "Expected a variable name after 'storing result in' in 'Measure time of {}'",
+                            head.trim()
+                        ))
+                    }
+                },
+                None => (head, None),
+            };
+            let name = parse_expr(name.trim())?;
+            *i += 1;
+            let body = parse_until_keywords(lines, i, &[P::P_END_MEASURE])?;
+            if *i < lines.len() && P::strip_prefix_ci(lines[*i].trim(), P::P_END_MEASURE).is_some() {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End Measure' after 'Measure time of' block"));
+            }
+            out.push(Stmt::Measure { name, store_in, body });
+            continue;
+        }
+        // Bind name to <value> and on change run: ... End Bind
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_BIND) {
+            let usage = || anyhow!("Expected 'Bind <name> to <value> and on change run:', got 'Bind {}'", rest.trim());
+            let (var, after) = split_ident(rest.trim()).ok_or_else(usage)?;
+            let head = P::strip_prefix_ci(after.trim_start(), P::P_BIND_TO)
+                .and_then(|head| head.trim_end().strip_suffix(':'))
+                .ok_or_else(usage)?;
+            let Some(&at) = top_level_matches(head, P::P_ON_CHANGE_RUN).last() else {
+                return Err(usage());
+            };
+            if !head[at + P::P_ON_CHANGE_RUN.len()..].trim().is_empty() {
+                return Err(usage());
+            }
+            let to = parse_expr(head[..at].trim())?;
+            *i += 1;
+            let on_change = parse_until_keywords(lines, i, &[P::P_END_BIND])?;
+            if *i < lines.len() && P::strip_prefix_ci(lines[*i].trim(), P::P_END_BIND).is_some() {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End Bind' after 'Bind {}' block", var));
+            }
+            out.push(Stmt::DataBinding { var, to, on_change });
+            continue;
+        }
+        // Parallel: Branch: ... Branch: ... End Parallel
+        if P::strip_prefix_ci(t, P::P_PARALLEL).is_some() {
+            *i += 1;
+            let mut branches = Vec::new();
+            loop {
+                while *i < lines.len() && lines[*i].trim().is_empty() {
+                    *i += 1;
+                }
+                let Some(line) = lines.get(*i).map(|l| l.trim()) else {
+                    return Err(anyhow!("Expected 'End Parallel' after 'Parallel:' block"));
+                };
+                if P::strip_prefix_ci(line, P::P_END_PARALLEL).is_some() {
+                    *i += 1;
+                    break;
+                }
+                if P::strip_prefix_ci(line, P::P_BRANCH).is_none() {
+                    return Err(anyhow!(
+                        "Expected 'Branch:' or 'End Parallel' in 'Parallel:' block, found '{}'",
+                        line
+                    ));
+                }
+                *i += 1;
+                branches.push(parse_until_keywords(lines, i, &[P::P_BRANCH, P::P_END_PARALLEL])?);
+            }
+            out.push(Stmt::Parallel { branches });
+            continue;
+        }
+        // Start task named fetch: ... End Task
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_START_TASK) {
+            let name = match rest.trim_end().strip_suffix(':').and_then(|n| split_ident(n.trim())) {
+                Some((name, "")) => name,
+                _ => return Err(anyhow!("Expected a name and ':' in 'Start task named {}'", rest.trim())),
+            };
+            *i += 1;
+            let body = parse_until_keywords(lines, i, &[P::P_END_TASK])?;
+            if *i < lines.len() && P::strip_prefix_ci(lines[*i].trim(), P::P_END_TASK).is_some() {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End Task' after 'Start task named {}' block", name));
+            }
+            out.push(Stmt::Task { name, body });
+            continue;
+        }
+        // Wait for all tasks a, b and c / Wait for any task a or b
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_WAIT_FOR_ALL_TASKS) {
+            let names = task_names(rest, " and ", "Wait for all tasks")?;
+            out.push(Stmt::AwaitAll { names });
+            *i += 1;
+            continue;
+        }
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_WAIT_FOR_ANY_TASK) {
+            let names = task_names(rest, " or ", "Wait for any task")?;
+            out.push(Stmt::AwaitAny { names });
+            *i += 1;
+            continue;
+        }
+        // Wait for job: as 'Wait for all tasks job'
+        if let Some(Some((name, ""))) = P::strip_prefix_ci(t, P::P_WAIT_FOR).map(|rest| split_ident(rest.trim())) {
+            out.push(Stmt::AwaitAll { names: vec![name] });
+            *i += 1;
+            continue;
+        }
+        // Run in background: ... End / Run in background as job: ... End
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_RUN_IN_BACKGROUND) {
+            let name = match rest.trim_end().strip_suffix(':').map(str::trim) {
+                Some("") => None,
+                Some(named) => match P::strip_prefix_ci(named, "as ").and_then(|n| split_ident(n.trim())) {
+                    Some((name, "")) => Some(name),
+                    _ => return Err(anyhow!("Expected 'Run in background:' or 'Run in background as <name>:'")),
+                },
+                None => return Err(anyhow!("Expected ':' after 'Run in background'")),
+            };
+            *i += 1;
+            let body = parse_until_keywords(lines, i, &["End"])?;
+            if *i < lines.len() && lines[*i].trim() == "End" {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End' after 'Run in background' block"));
+            }
+            out.push(Stmt::Background { name, body });
+            continue;
+        }
+        // Checkpoint import with state row / Restore checkpoint import into variable row
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_CHECKPOINT) {
+            let Some((name, state)) = rest.split_once(P::P_WITH_STATE) else {
+                return Err(anyhow!("Expected 'with state <value>' in 'Checkpoint {}'", rest.trim()));
+            };
+            out.push(Stmt::Checkpoint {
+                name: checkpoint_name(name)?,
+                state: parse_expr(state.trim())?,
+            });
+            *i += 1;
+            continue;
+        }
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_RESTORE_CHECKPOINT) {
+            let Some((name, var)) = rest.split_once(P::P_INTO_VARIABLE) else {
+                return Err(anyhow!("Expected 'into variable <name>' in 'Restore checkpoint {}'", rest.trim()));
+            };
+            let into_var = match split_ident(var.trim()) {
+                Some((var, "")) => var,
+                _ => return Err(anyhow!("'{}' is not a variable name to restore checkpoint into", var.trim())),
+            };
+            out.push(Stmt::RestoreCheckpoint {
+                name: checkpoint_name(name)?,
+                into_var,
+            });
+            *i += 1;
+            continue;
+        }
+        // Retry 3 times with delay 2 seconds with backoff: ... On all failures as e: ... End Retry
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_RETRY) {
+            let Some(mut head) = rest.trim_end().strip_suffix(':') else {
+                return Err(anyhow!("Expected ':' at the end of 'Retry {}'", rest.trim()));
+            };
+            let (mut backoff, mut jitter) = (false, false);
+            loop {
+                if let Some(h) = head.trim_end().strip_suffix(P::P_WITH_BACKOFF) {
+                    (head, backoff) = (h, true);
+                } else if let Some(h) = head.trim_end().strip_suffix(P::P_WITH_JITTER) {
+                    (head, jitter) = (h, true);
+                } else {
+                    break;
+                }
+            }
+            let parts = split_once_word(head, P::P_TIMES_WITH_DELAY).and_then(|(count, delay)| {
+                let delay = delay.trim_end();
+                let delay = delay.strip_suffix(" seconds").or_else(|| delay.strip_suffix(" second"))?;
+                Some((count, delay))
+            });
+            let Some((count, delay)) = parts else {
+                return Err(anyhow!(
+                    "Expected 'Retry <count> times with delay <seconds> seconds:', found '{}'",
+                    t
+                ));
+            };
+            *i += 1;
+            let body = parse_until_keywords(lines, i, &[P::P_ON_ALL_FAILURES, P::P_END_RETRY])?;
+            let mut on_failure = None;
+            if let Some(rest) = lines.get(*i).and_then(|l| P::strip_prefix_ci(l.trim(), P::P_ON_ALL_FAILURES)) {
+                let var = match rest.trim_end().strip_suffix(':').map(str::trim) {
+                    Some("") => None,
+                    Some(r) => match strip_prefix_ci(r, "as ").and_then(|v| split_ident(v.trim())) {
+                        Some((var, "")) => Some(var),
+                        _ => return Err(anyhow!("Expected 'On all failures:' or 'On all failures as <name>:'")),
+                    },
+                    None => return Err(anyhow!("Expected ':' after 'On all failures'")),
+                };
+                *i += 1;
+                on_failure = Some((var, parse_until_keywords(lines, i, &[P::P_END_RETRY])?));
+            }
+            if *i < lines.len() && P::strip_prefix_ci(lines[*i].trim(), P::P_END_RETRY).is_some() {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End Retry' after 'Retry' block"));
+            }
+            out.push(Stmt::Retry {
+                max_attempts: parse_expr(count.trim())?,
+                delay: parse_expr(delay.trim())?,
+                backoff,
+                jitter,
+                body,
+                on_failure,
+            });
+            continue;
+        }
+        // Try-Catch
+        if P::strip_prefix_ci(t, P::P_TRY).is_some() {
+            *i += 1;
+            // Parse try block
+            let try_block = parse_until_keywords(
+                lines,
+                i,
+                &[P::P_IF_ERROR, P::P_FINALLY, P::P_END_TRY, "End"],
+            )?;
+
+            let mut catch_handlers = Vec::new();
+            let mut finally_block = None;
+
+            // Parse catch handlers
+            while *i < lines.len() {
+                let line = lines[*i].trim();
+
+                // Check for "if error" catch clause
+                if let Some(rest) = P::strip_prefix_ci(line, P::P_IF_ERROR) {
+                    *i += 1;
+
+                    let rest = rest.trim();
+                    let mut error_type = None;
+                    let mut var_name = None;
+
+                    // Check for "of type X"
+                    if let Some(after_of_type) = strip_prefix_ci(rest, "of type ") {
+                        // Split on " as " to get type and variable name
+                        if let Some((type_part, var_part)) = split_once_word(after_of_type, " as ")
+                        {
+                            error_type = Some(type_part.trim().trim_matches('"').to_string());
+                            var_name = Some(var_part.trim().to_string());
+                        } else {
+                            // Just type, no variable
+                            error_type = Some(after_of_type.trim().trim_matches('"').to_string());
+                        }
+                    } else if let Some(rest_as) = strip_prefix_ci(rest, "as ") {
+                        // Just "if error as var_name" - catch all errors
+                        var_name = Some(rest_as.trim().to_string());
+                    }
+                    // else: just "if error" - catch all without binding
+
+                    // Parse catch body
+                    let block = parse_until_keywords(
+                        lines,
+                        i,
+                        &[P::P_IF_ERROR, P::P_FINALLY, P::P_END_TRY, "End"],
+                    )?;
+
+                    catch_handlers.push(CatchHandler {
+                        error_type,
+                        var_name,
+                        block,
+                    });
+                    continue;
+                }
+
+                // Check for "finally"
+                if P::strip_prefix_ci(line, P::P_FINALLY).is_some() {
+                    *i += 1;
+                    finally_block = Some(parse_until_keywords(lines, i, &[P::P_END_TRY, "End"])?);
+                    continue;
+                }
+
+                // Check for "end try"
+                if P::strip_prefix_ci(line, P::P_END_TRY).is_some() || line == "End" {
+                    *i += 1;
+                    break;
+                }
+
+                return Err(anyhow!(
+                    "Expected 'if error', 'finally', or 'end try', found '{}'",
+                    line
+                ));
+            }
+
+            out.push(Stmt::TryCatch {
+                try_block,
+                catch_handlers,
+                finally_block,
+            });
+            continue;
+        }
+        // Return
+        if let Some(rest) = t.strip_prefix("Return") {
+            let r = rest.trim();
+            let expr = if r.is_empty() {
+                None
+            } else {
+                Some(parse_expr(r)?)
+            };
+            out.push(Stmt::Return(expr));
+            *i += 1;
+            continue;
+        }
+        // Throw
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_THROW) {
+            let expr = parse_expr(rest.trim())?;
+            out.push(Stmt::Throw(expr));
+            *i += 1;
+            continue;
+        }
+
+        // Log to file <path> [rotating at <size>], or back to stderr only
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_LOG_TO_FILE) {
+            let (path, rotate_size) = match split_once_word(rest, P::P_ROTATING_AT) {
+                Some((path, size)) => (path, Some(parse_byte_size(size.trim())?)),
+                None => (rest, None),
+            };
+            out.push(Stmt::Log {
+                to_file: Some(parse_expr(path.trim())?),
+                rotate_size,
+            });
+            *i += 1;
+            continue;
+        }
+        if t.eq_ignore_ascii_case(P::P_LOG_TO_STDERR) {
+            out.push(Stmt::Log {
+                to_file: None,
+                rotate_size: None,
+            });
+            *i += 1;
+            continue;
+        }
+        // Log info/warn/error <message>
+        let log_level = [
+            (P::P_LOG_INFO, LogLevel::Info),
+            (P::P_LOG_WARN, LogLevel::Warn),
+            (P::P_LOG_WARNING, LogLevel::Warn),
+            (P::P_LOG_ERROR, LogLevel::Error),
+        ]
+        .into_iter()
+        .find_map(|(phrase, level)| P::strip_prefix_ci(t, phrase).map(|rest| (level, rest)));
+        if let Some((level, rest)) = log_level {
+            out.push(Stmt::LogMessage {
+                level,
+                message: parse_expr(rest.trim())?,
+            });
+            *i += 1;
+            continue;
+        }
+
+        // Web Framework Statements
+        // Add route group <prefix> to server: ... End group
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_ADD_ROUTE_GROUP) {
+            let Some(prefix) = rest.trim_end().strip_suffix(" to server:") else {
+                return Err(anyhow!("Expected 'Add route group <prefix> to server:'"));
+            };
+            *i += 1;
+            let body = parse_until_keywords(lines, i, &[P::P_END_GROUP])?;
+            if *i < lines.len() && P::strip_prefix_ci(lines[*i].trim(), P::P_END_GROUP).is_some() {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End group' after 'Add route group' block"));
+            }
+            out.push(Stmt::RouteGroup {
+                prefix: parse_expr(prefix.trim())?,
+                body,
+            });
+            continue;
+        }
+        // Add middleware to group: ... End middleware
+        if P::strip_prefix_ci(t, P::P_ADD_GROUP_MIDDLEWARE).is_some() {
+            *i += 1;
+            let body = parse_until_keywords(lines, i, &[P::P_END_MIDDLEWARE])?;
+            if *i < lines.len() && P::strip_prefix_ci(lines[*i].trim(), P::P_END_MIDDLEWARE).is_some() {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End middleware' after 'Add middleware to group:' block"));
+            }
+            out.push(Stmt::GroupMiddleware { body });
+            continue;
+        }
+        // Route <METHOD> "<path>" [on server <name>]: ... End Route
+        if let Some(head) = P::strip_prefix_ci(t, P::P_ROUTE).and_then(|r| r.trim_end().strip_suffix(':')) {
+            let line = *i + 1;
+            let (method, target) = head.trim().split_once(' ').unwrap_or((head.trim(), ""));
+            if method.is_empty() || !method.chars().all(|c| c.is_ascii_alphabetic()) {
+                return Err(anyhow!("Expected 'Route <METHOD> \"<path>\":' such as 'Route GET \"/users\":'"));
+            }
+            let (path, server) = match target.rsplit_once(P::P_ON_SERVER) {
+                Some((path, name)) => match split_ident(name.trim()) {
+                    Some((name, "")) => (path, Some(name)),
+                    _ => return Err(anyhow!("Expected a variable name after 'on server', got '{}'", name.trim())),
+                },
+                None => (target, None),
+            };
+            let path = path.trim();
+            let Some(path) = path
+                .strip_prefix('"')
+                .and_then(|p| p.strip_suffix('"'))
+                .filter(|p| p.starts_with('/') && !p.contains('"'))
+            else {
+                return Err(anyhow!("Route {}: the path must be text in quotes starting with '/', got {}", method, path));
+            };
+            *i += 1;
+            let handler = parse_until_keywords(lines, i, &[P::P_END_ROUTE])?;
+            if *i < lines.len() && P::strip_prefix_ci(lines[*i].trim(), P::P_END_ROUTE).is_some() {
+                *i += 1;
+            } else {
+                return Err(anyhow!("Expected 'End Route' after 'Route {} \"{}\":' block", method, path));
+            }
+            out.push(Stmt::Route {
+                method: method.to_uppercase(),
+                path: path.to_string(),
+                server,
+                handler,
+                line,
+            });
+            continue;
+        }
+        // Add route <path> with method <method> to server:
+        if let Some(rest) = P::strip_prefix_ci(t, "add route ") {
+            if let Some((path_and_method, _)) = rest.split_once(" to server:") {
+                if let Some((path_part, method_part)) = path_and_method.split_once(" with method ")
+                {
+                    let path_expr = parse_expr(path_part.trim())?;
+                    let method_expr = parse_expr(method_part.trim())?;
+                    let line = *i + 1;
+                    *i += 1;
+                    out.push(Stmt::AddRoute {
+                        path: path_expr,
+                        method: method_expr,
+                        handler: route_handler(lines, i)?,
+                        line,
+                    });
+                    continue;
+                }
+            }
+        }
+        // Add websocket route <path> to server:
+        // Kept as a route with the method websocket routes are looked up by
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_ADD_WEBSOCKET_ROUTE) {
+            let Some((path, _)) = rest.split_once(" to server:") else {
+                return Err(anyhow!("Expected 'Add websocket route <path> to server:'"));
+            };
+            let line = *i + 1;
+            *i += 1;
+            out.push(Stmt::AddRoute {
+                path: parse_expr(path.trim())?,
+                method: Expr::Str(crate::parser::ast::WEBSOCKET_METHOD.to_string()),
+                handler: route_handler(lines, i)?,
+                line,
+            });
+            continue;
+        }
+
+        // Add middleware "<type>" to server (with optional config)
+        // Examples:
+        //   Add middleware "cors" to server
+        //   Add middleware "logging" to server
+        //   Add middleware "auth" with token "secret123" to server
+        if let Some(rest) = P::strip_prefix_ci(t, "add middleware ") {
+            if let Some((type_and_config, _)) = rest.split_once(" to server") {
+                // Extract middleware type (quoted string)
                 if let Some(type_str) = type_and_config.split('"').nth(1) {
                     let middleware_type = type_str.to_string();
                     let mut config = Vec::new();
@@ -651,6 +1319,47 @@ fn parse_until_keywords(lines: &[&str], i: &mut usize, stops: &[&str]) -> Result
             }
         }
 
+        // Enable metrics on server at <path>
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_ENABLE_METRICS) {
+            out.push(Stmt::EnableMetrics {
+                path: parse_expr(rest.trim())?,
+                line: *i + 1,
+            });
+            *i += 1;
+            continue;
+        }
+        // Serve static files from <dir>
+        if let Some(rest) = P::strip_prefix_ci(t, P::P_SERVE_STATIC_FILES) {
+            out.push(Stmt::ServeStaticFiles(parse_expr(rest.trim())?));
+            *i += 1;
+            continue;
+        }
+        // Limit to <n> requests per <secs> seconds [grouped by <function>]
+        if let Some(rest) = P::strip_prefix_ci(t, "limit to ") {
+            if let Some((max, after)) = split_once_word(rest, " requests per ")
+                .or_else(|| split_once_word(rest, " request per "))
+            {
+                let (window, key_by) = match split_once_word(after, " grouped by ") {
+                    Some((w, k)) => (w, Some(parse_expr(k.trim())?)),
+                    None => (after, None),
+                };
+                let window = window.trim();
+                let secs = window
+                    .strip_suffix(" seconds")
+                    .or_else(|| window.strip_suffix(" second"))
+                    .ok_or_else(|| {
+                        anyhow!("Limit to ... requests per ... must end in 'seconds': {}", t)
+                    })?;
+                out.push(Stmt::RateLimit {
+                    max_requests: parse_expr(max.trim())?,
+                    per_seconds: parse_expr(secs.trim())?,
+                    key_by,
+                });
+                *i += 1;
+                continue;
+            }
+        }
+
         // Start server
         if P::strip_prefix_ci(t, "start server").is_some() {
             out.push(Stmt::StartServer);
@@ -663,559 +1372,461 @@ fn parse_until_keywords(lines: &[&str], i: &mut usize, stops: &[&str]) -> Result
     Ok(out)
 }
 
-fn split_once_word<'a>(s: &'a str, pat: &str) -> Option<(&'a str, &'a str)> {
-    if let Some(idx) = s.find(pat) {
-        let (a, b) = s.split_at(idx);
-        let b = &b[pat.len()..];
-        Some((a, b))
-    } else {
-        None
+/// `10MB`, `512 KB`, `100 bytes`, or any expression giving a number of bytes
+/// `<cond> then <stmt> [else <stmt>]` after `If`; None without a `then`
+/// followed by a statement. A statement may hold an `A if c else B`, so
+/// each `else` is tried from the last: the first split where both
+/// statements parse wins.
+fn parse_if_then(rest: &str) -> Result<Option<Stmt>> {
+    let Some(&then_at) = top_level_matches(rest, P::P_THEN).first() else {
+        return Ok(None);
+    };
+    let (cond, stmts) = (&rest[..then_at], &rest[then_at + P::P_THEN.len()..]);
+    if stmts.trim().is_empty() {
+        return Ok(None);
+    }
+    let cond = parse_expr(cond.trim())?;
+    let mut first_error = None;
+    for else_at in top_level_matches(stmts, P::P_ELSE).into_iter().rev() {
+        let (then_stmt, else_stmt) = (&stmts[..else_at], &stmts[else_at + P::P_ELSE.len()..]);
+        match single_statement(then_stmt).and_then(|t| Ok((t, single_statement(else_stmt)?))) {
+            Ok((then_stmt, else_stmt)) => {
+                return Ok(Some(Stmt::IfBlock {
+                    cond,
+                    then_body: vec![then_stmt],
+                    otherwise_body: Some(vec![else_stmt]),
+                }))
+            }
+            Err(e) => first_error = Some(e),
+        }
+    }
+    match single_statement(stmts) {
+        Ok(stmt) => Ok(Some(Stmt::IfBlock {
+            cond,
+            then_body: vec![stmt],
+            otherwise_body: None,
+        })),
+        Err(e) => Err(first_error.unwrap_or(e)),
     }
 }
 
-fn split_ident(s: &str) -> Option<(String, &str)> {
-    let mut chars = s.chars();
+/// The one statement written in `text`, for `If ... then ... else ...`
+fn single_statement(text: &str) -> Result<Stmt> {
+    let text = text.trim();
     let mut i = 0;
-    for c in chars.by_ref() {
-        if c.is_alphanumeric() || c == '_' {
-            i += c.len_utf8();
-        } else {
-            break;
-        }
+    let mut stmts: Vec<Stmt> = parse_until_keywords(&[text], &mut i, &[])?
+        .into_iter()
+        .filter(|s| !matches!(s, Stmt::Line(_)))
+        .collect();
+    match stmts.pop() {
+        Some(stmt) if stmts.is_empty() && i == 1 => Ok(stmt),
+        _ => Err(anyhow!("Expected a single statement after 'then' or 'else', got: {}", text)),
     }
-    if i == 0 {
-        return None;
-    }
-    Some((s[..i].to_string(), &s[i..]))
 }
 
-fn parse_params(s: &str) -> Result<Vec<Param>> {
-    let mut out = Vec::new();
-    let mut depth = 0i32;
-    let mut in_str = false;
-    let mut buf = String::new();
-    for ch in s.chars() {
+/// Where `pat` starts in `s`, ignoring case, outside quotes and brackets
+fn top_level_matches(s: &str, pat: &str) -> Vec<usize> {
+    let mut found = Vec::new();
+    let (mut in_str, mut depth) = (false, 0i32);
+    for (at, ch) in s.char_indices() {
         match ch {
-            '"' => {
-                in_str = !in_str;
-                buf.push(ch);
-            }
-            '(' if !in_str => {
-                depth += 1;
-                buf.push(ch);
-            }
-            ')' if !in_str => {
-                depth -= 1;
-                buf.push(ch);
-            }
-            ',' if !in_str && depth == 0 => {
-                let item = buf.trim().to_string();
-                if !item.is_empty() {
-                    out.push(parse_param_item(&item)?);
-                }
-                buf.clear();
+            '"' => in_str = !in_str,
+            '(' | '[' | '{' if !in_str => depth += 1,
+            ')' | ']' | '}' if !in_str => depth -= 1,
+            _ if !in_str && depth == 0 && s.get(at..at + pat.len()).is_some_and(|w| w.eq_ignore_ascii_case(pat)) => {
+                found.push(at)
             }
-            _ => buf.push(ch),
+            _ => {}
         }
     }
-    if !buf.trim().is_empty() {
-        out.push(parse_param_item(buf.trim())?);
+    found
+}
+
+fn parse_byte_size(s: &str) -> Result<Expr> {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let unit = match s[digits..].trim().to_ascii_lowercase().as_str() {
+        "b" | "bytes" => Some(1),
+        "kb" => Some(1 << 10),
+        "mb" => Some(1 << 20),
+        "gb" => Some(1 << 30),
+        _ => None,
+    };
+    match (s[..digits].parse::<i64>(), unit) {
+        (Ok(n), Some(unit)) => Ok(Expr::Int(n.saturating_mul(unit))),
+        _ => parse_expr(s),
     }
-    Ok(out)
 }
 
-fn parse_param_item(s: &str) -> Result<Param> {
-    let s = s.trim();
-    if let Some(idx) = s.find(" set to ") {
-        let name = s[..idx].trim();
-        let expr_str = &s[idx + " set to ".len()..];
-        if let Some((n, rest)) = split_ident(name) {
-            if rest.trim().is_empty() {
-                let def = parse_expr(expr_str.trim())?;
-                return Ok(Param {
-                    name: n,
-                    default: Some(def),
-                });
-            }
+/// The lines from `i` on indented more than the `Add route` before them,
+/// its handler; the route itself is indented inside a route group
+fn route_handler(lines: &[&str], i: &mut usize) -> Result<Program> {
+    let start = *i;
+    let indent = |line: &str| line.len() - line.trim_start().len();
+    let route_indent = indent(lines[start - 1]);
+    while *i < lines.len() {
+        let line = lines[*i];
+        // Blank lines and lines indented more than the route are part of it
+        if line.trim().is_empty() || indent(line) > route_indent {
+            *i += 1;
+        } else {
+            break;
         }
-        return Err(anyhow!("Invalid parameter default: {}", s));
     }
-    if let Some(idx) = s.find(" defaulting to ") {
-        let name = s[..idx].trim();
-        let expr_str = &s[idx + " defaulting to ".len()..];
-        if let Some((n, rest)) = split_ident(name) {
-            if rest.trim().is_empty() {
-                let def = parse_expr(expr_str.trim())?;
-                return Ok(Param {
-                    name: n,
-                    default: Some(def),
-                });
+    let handler_lines: Vec<&str> = lines[start..*i].iter().map(|l| l.trim_start()).collect();
+    parse_until_keywords(&handler_lines, &mut 0, &[])
+}
+
+/// The fields of `Define schema <schema> with ...`. A comma starts the next
+/// field only when `<name> as` follows it, so a default may hold commas.
+fn schema_fields(schema: &str, text: &str) -> Result<Vec<SchemaField>> {
+    let starts_field = |piece: &str| {
+        split_ident(piece.trim_start()).is_some_and(|(_, rest)| rest.trim_start().starts_with("as "))
+    };
+    let mut pieces: Vec<String> = Vec::new();
+    for piece in text.split(',') {
+        match pieces.last_mut() {
+            Some(last) if !starts_field(piece) => {
+                last.push(',');
+                last.push_str(piece);
             }
+            _ => pieces.push(piece.to_string()),
         }
-        return Err(anyhow!("Invalid parameter default: {}", s));
     }
-    if let Some((n, rest)) = split_ident(s) {
-        if rest.trim().is_empty() {
-            return Ok(Param {
-                name: n,
-                default: None,
-            });
+    let mut fields: Vec<SchemaField> = Vec::new();
+    for piece in &pieces {
+        let piece = piece.trim();
+        let parsed = split_ident(piece).and_then(|(name, rest)| Some((name, rest.trim_start().strip_prefix("as ")?)));
+        let Some((name, rest)) = parsed else {
+            return Err(anyhow!(
+                "Define schema {}: expected '<field> as [required|optional] <type>', got '{}'",
+                schema,
+                piece
+            ));
+        };
+        let rest = rest.trim_start();
+        let (marker, rest) = match (strip_prefix_ci(rest, "required "), strip_prefix_ci(rest, "optional ")) {
+            (Some(rest), _) => (Some(true), rest),
+            (_, Some(rest)) => (Some(false), rest),
+            _ => (None, rest),
+        };
+        let Some((type_name, rest)) = split_ident(rest.trim_start()) else {
+            return Err(anyhow!("Define schema {}: expected a type after '{} as'", schema, name));
+        };
+        let rest = rest.trim();
+        let default = match strip_prefix_ci(rest, P::P_DEFAULTING_TO) {
+            Some(value) => Some(parse_expr(value.trim())?),
+            None if rest.is_empty() => None,
+            None => {
+                return Err(anyhow!(
+                    "Define schema {}: expected ',' or 'defaulting to <value>' after '{} as {}', got '{}'",
+                    schema,
+                    name,
+                    type_name,
+                    rest
+                ))
+            }
+        };
+        if marker == Some(true) && default.is_some() {
+            return Err(anyhow!("Define schema {}: {} is required, so it cannot have a default", schema, name));
+        }
+        if fields.iter().any(|f| f.name == name) {
+            return Err(anyhow!("Define schema {}: {} is listed twice", schema, name));
         }
+        fields.push(SchemaField {
+            required: marker.unwrap_or(default.is_none()),
+            name,
+            type_name,
+            default,
+        });
     }
-    Err(anyhow!("Invalid parameter: {}", s))
-}
-
-fn parse_arg_list(s: &str) -> Result<Vec<Expr>> {
-    parse_arg_list_multi(s, false)
+    Ok(fields)
 }
 
-fn parse_arg_list_multi(s: &str, allow_and: bool) -> Result<Vec<Expr>> {
-    // Split by commas (always) and optionally by " and " at top level
-    let mut parts: Vec<String> = split_top_level(s, ",");
-    if allow_and {
-        let mut expanded = Vec::new();
-        for p in parts.into_iter() {
-            let sub = split_top_level(&p, " and ");
-            for item in sub {
-                if !item.trim().is_empty() {
-                    expanded.push(item);
-                }
-            }
+/// A checkpoint's name, written as a name or in quotes
+fn checkpoint_name(text: &str) -> Result<String> {
+    let text = text.trim();
+    if let Some(quoted) = text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+        if !quoted.is_empty() && !quoted.contains('"') {
+            return Ok(quoted.to_string());
         }
-        parts = expanded;
     }
-    let mut args = Vec::new();
-    for p in parts {
-        let t = p.trim();
-        if t.is_empty() {
-            continue;
-        }
-        args.push(parse_expr(t)?);
+    match split_ident(text) {
+        Some((name, "")) => Ok(name),
+        _ => Err(anyhow!("'{}' is not a checkpoint name; use a name or text in quotes", text)),
     }
-    Ok(args)
 }
 
-pub fn parse_expr(s: &str) -> Result<Expr> {
-    parse_or(s)
+/// `Write` of several values, or None when `rest` is a single value with
+/// neither `separated by` nor `ending with`. Commas only split the values
+/// when `rest` does not already read as one expression, so
+/// `Write Make a list of 1, 2 and 3` is still a single list.
+fn multi_write(rest: &str) -> Result<Option<Stmt>> {
+    let mut values = rest;
+    let mut suffix = |phrase: &str| -> Result<Option<Expr>> {
+        match top_level_matches(values, phrase).last() {
+            Some(&at) => {
+                let e = parse_expr(values[at + phrase.len()..].trim())?;
+                values = &values[..at];
+                Ok(Some(e))
+            }
+            None => Ok(None),
+        }
+    };
+    let end = suffix(P::P_ENDING_WITH)?;
+    let sep = suffix(P::P_SEPARATED_BY)?;
+    let values = values.trim();
+    let parts = split_top_level_multi(values, &[","]);
+    let exprs = match parse_expr(values) {
+        Ok(_) if sep.is_none() && end.is_none() => return Ok(None),
+        Ok(e) => vec![e],
+        Err(_) if parts.len() > 1 => parts.iter().map(|p| parse_expr(p.trim())).collect::<Result<_>>()?,
+        Err(e) => return Err(e),
+    };
+    Ok(Some(Stmt::MultiWrite { exprs, sep, end }))
 }
 
-fn try_parse_phrasal_call(s: &str) -> Option<Expr> {
-    let st = s.trim();
-    if let Some((name, after)) = split_ident(st) {
-        let after = after.trim_start();
-        if let Some(rest) = strip_prefix_ci(after, "with ") {
-            // Don't treat "error of type X with message Y" as a phrasal call
-            // Check if the name is "error" and after starts with "of type"
-            if name == "error" && after.trim_start().starts_with("of type") {
-                return None;
-            }
-            if let Ok(args) = parse_arg_list_multi(rest, true) {
-                return Some(Expr::Call { name, args });
-            }
-        }
-    }
-    None
+/// `greet with "Ada" storing result in greeting`, after `Use` or `Call`
+fn use_call(rest: &str) -> Result<Stmt> {
+    let (call, result_var) = match top_level_matches(rest, P::P_STORING_RESULT_IN).last() {
+        Some(&at) => match split_ident(rest[at + P::P_STORING_RESULT_IN.len()..].trim()) {
+            Some((var, "")) => (&rest[..at], Some(var)),
+            _ => return Err(anyhow!("Expected a variable name after 'storing result in' in 'Use {}'", rest)),
+        },
+        None => (rest, None),
+    };
+    let (name, after_name) = split_ident(call).ok_or_else(|| anyhow!("Expected function name"))?;
+    let after_with = after_name.trim_start().strip_prefix("with ").unwrap_or("");
+    let args = if after_with.is_empty() {
+        vec![]
+    } else {
+        parse_arg_list_multi(after_with, true)?
+    };
+    Ok(Stmt::Use { name, args, result_var })
 }
 
-fn parse_or(s: &str) -> Result<Expr> {
-    if let Some(call) = try_parse_phrasal_call(s) {
-        return Ok(call);
-    }
-    let parts = split_top_level_multi(s, &[" Or ", " or "]);
-    if parts.len() > 1 {
-        let mut it = parts.into_iter();
-        let mut e = parse_and(it.next().unwrap().trim())?;
-        for p in it {
-            let r = parse_and(p.trim())?;
-            e = Expr::Or(Box::new(e), Box::new(r));
-        }
-        return Ok(e);
-    }
-    parse_and(s)
+/// The names in `a, b and c`, where `last` joins the final two
+fn task_names(list: &str, last: &str, phrase: &str) -> Result<Vec<String>> {
+    list.replace(last, ",")
+        .split(',')
+        .map(|name| match split_ident(name.trim()) {
+            Some((name, "")) => Ok(name),
+            _ => Err(anyhow!("Expected task names in '{} {}'", phrase, list.trim())),
+        })
+        .collect()
 }
 
-fn parse_and(s: &str) -> Result<Expr> {
-    if let Some(call) = try_parse_phrasal_call(s) {
-        return Ok(call);
-    }
-    let parts = split_top_level_multi(s, &[" And ", " and "]);
-    if parts.len() > 1 {
-        let mut it = parts.into_iter();
-        let mut e = parse_not(it.next().unwrap().trim())?;
-        for p in it {
-            let r = parse_not(p.trim())?;
-            e = Expr::And(Box::new(e), Box::new(r));
-        }
-        return Ok(e);
+fn split_once_word<'a>(s: &'a str, pat: &str) -> Option<(&'a str, &'a str)> {
+    if let Some(idx) = s.find(pat) {
+        let (a, b) = s.split_at(idx);
+        let b = &b[pat.len()..];
+        Some((a, b))
+    } else {
+        None
     }
-    parse_not(s)
 }
 
-fn parse_not(s: &str) -> Result<Expr> {
-    let st = s.trim_start();
-    if let Some(rest) = st.strip_prefix("Not ") {
-        return Ok(Expr::Not(Box::new(parse_not(rest)?)));
+fn split_ident(s: &str) -> Option<(String, &str)> {
+    let mut chars = s.chars();
+    let mut i = 0;
+    for c in chars.by_ref() {
+        if c.is_alphanumeric() || c == '_' {
+            i += c.len_utf8();
+        } else {
+            break;
+        }
     }
-    if let Some(rest) = st.strip_prefix("not ") {
-        return Ok(Expr::Not(Box::new(parse_not(rest)?)));
+    if i == 0 {
+        return None;
     }
-    parse_cmp(st)
+    Some((s[..i].to_string(), &s[i..]))
 }
 
-fn parse_cmp(s: &str) -> Result<Expr> {
-    // Recognize comparisons at top-level, not inside strings or parens; prefer longest match
-    // Order matters: check longer patterns first to avoid premature matching
-    let cmps = [
-        // Full phrasal forms with "is" (longest patterns first)
-        (" is greater than or equal to ", CmpOp::Ge),
-        (" is less than or equal to ", CmpOp::Le),
-        (" Is Greater Than Or Equal To ", CmpOp::Ge),
-        (" Is Less Than Or Equal To ", CmpOp::Le),
-        (" is not equal to ", CmpOp::Ne),
-        (" Is Not Equal To ", CmpOp::Ne),
-        (" is equal to ", CmpOp::Eq),
-        (" Is Equal To ", CmpOp::Eq),
-        (" is greater than ", CmpOp::Gt),
-        (" is less than ", CmpOp::Lt),
-        (" Is Greater Than ", CmpOp::Gt),
-        (" Is Less Than ", CmpOp::Lt),
-        // Shorter forms without "is"
-        (" Greater Or Equal ", CmpOp::Ge),
-        (" Less Or Equal ", CmpOp::Le),
-        (" greater or equal ", CmpOp::Ge),
-        (" less or equal ", CmpOp::Le),
-        (" Greater Than ", CmpOp::Gt),
-        (" Less Than ", CmpOp::Lt),
-        (" greater than ", CmpOp::Gt),
-        (" less than ", CmpOp::Lt),
-        (" Not Equals ", CmpOp::Ne),
-        (" not equals ", CmpOp::Ne),
-        (" Equals ", CmpOp::Eq),
-        (" equals ", CmpOp::Eq),
-        // Basic "is" (check before symbolic to prefer phrasal)
-        (" is not ", CmpOp::Ne),
-        (" Is Not ", CmpOp::Ne),
-        (" is ", CmpOp::Eq),
-        (" Is ", CmpOp::Eq),
-        // Symbolic operators (check after phrasal forms)
-        (" >= ", CmpOp::Ge),
-        (" <= ", CmpOp::Le),
-        (" != ", CmpOp::Ne),
-        (" == ", CmpOp::Eq),
-        (" > ", CmpOp::Gt),
-        (" < ", CmpOp::Lt),
-        (" = ", CmpOp::Eq),
-    ];
-    for (pat, op) in cmps.iter() {
-        if let Some((l, r)) = split_once_top_level(s, pat) {
-            let le = parse_add(l.trim())?;
-            let re = parse_add(r.trim())?;
-            return Ok(Expr::Cmp(op.clone(), Box::new(le), Box::new(re)));
+/// An item target `name[key]`: the name, the key's source and the text
+/// after the closing bracket
+fn split_item_target(s: &str) -> Option<(String, &str, &str)> {
+    let (name, after) = split_ident(s)?;
+    let inner = after.strip_prefix('[')?;
+    let mut depth = 0;
+    let mut in_str = false;
+    for (i, ch) in inner.char_indices() {
+        match ch {
+            '"' => in_str = !in_str,
+            '[' | '(' if !in_str => depth += 1,
+            ']' | ')' if !in_str && depth > 0 => depth -= 1,
+            ']' if !in_str => return Some((name, &inner[..i], &inner[i + 1..])),
+            _ => {}
         }
     }
-    parse_add(s)
+    None
 }
 
-fn parse_add(s: &str) -> Result<Expr> {
-    // Handle addition and subtraction (left-to-right)
-    // Support both phrasal (plus/minus) and symbolic (+/-)
-    let plus_parts = split_top_level(s, " plus ");
-    let minus_parts = split_top_level(s, " minus ");
-    let sym_plus_parts = split_top_level(s, " + ");
-    let sym_minus_parts = split_top_level(s, " - ");
-
-    // If we have any operators, process them in order
-    if plus_parts.len() > 1
-        || minus_parts.len() > 1
-        || sym_plus_parts.len() > 1
-        || sym_minus_parts.len() > 1
-    {
-        // Find which operator comes first
-        let mut tokens: Vec<(usize, bool, String)> = Vec::new(); // (position, is_plus, text)
-        let mut pos = 0;
-        let bytes = s.as_bytes();
-        let mut in_str = false;
-        let mut depth = 0;
-        let mut buf = String::new();
-
-        let mut i = 0;
-        while i < bytes.len() {
-            if bytes[i] == b'"' {
-                in_str = !in_str;
-                buf.push('"');
-                i += 1;
-                continue;
-            }
-            if !in_str {
-                if bytes[i] == b'(' || bytes[i] == b'[' || bytes[i] == b'{' {
-                    depth += 1;
-                    buf.push(bytes[i] as char);
-                    i += 1;
-                    continue;
-                }
-                if bytes[i] == b')' || bytes[i] == b']' || bytes[i] == b'}' {
-                    depth -= 1;
-                    buf.push(bytes[i] as char);
-                    i += 1;
-                    continue;
-                }
-                if depth == 0 {
-                    // Check phrasal operators first (longer patterns)
-                    if s[i..].starts_with(" plus ") {
-                        if !buf.trim().is_empty() {
-                            tokens.push((pos, true, buf.trim().to_string()));
-                        }
-                        buf.clear();
-                        i += " plus ".len();
-                        pos = tokens.len();
-                        continue;
-                    }
-                    if s[i..].starts_with(" minus ") {
-                        if !buf.trim().is_empty() {
-                            tokens.push((pos, false, buf.trim().to_string()));
-                        }
-                        buf.clear();
-                        i += " minus ".len();
-                        pos = tokens.len();
-                        continue;
-                    }
-                    // Check symbolic operators
-                    if s[i..].starts_with(" + ") {
-                        if !buf.trim().is_empty() {
-                            tokens.push((pos, true, buf.trim().to_string()));
-                        }
-                        buf.clear();
-                        i += " + ".len();
-                        pos = tokens.len();
-                        continue;
-                    }
-                    if s[i..].starts_with(" - ") {
-                        if !buf.trim().is_empty() {
-                            tokens.push((pos, false, buf.trim().to_string()));
-                        }
-                        buf.clear();
-                        i += " - ".len();
-                        pos = tokens.len();
-                        continue;
-                    }
+/// The rest of an Increase/Decrease line, as a Set of the target to itself
+/// combined with the amount by `op`. The target is a name, an item
+/// `scores[0]`, or a key in a dictionary `"wins" in stats`; an item is read
+/// and written back through `Stmt::SetItem`. The amount is 1 unless
+/// `by <expr>` follows the target.
+fn parse_step(verb: &str, rest: &str, op: fn(Box<Expr>, Box<Expr>) -> Expr) -> Result<Option<Stmt>> {
+    // `after` is what follows the target, which is the rest of `rest`
+    let amount = |after: &str| -> Result<Expr> {
+        let target = rest[..rest.len() - after.len()].trim();
+        match after.trim() {
+            "" => Ok(Expr::Int(1)),
+            "by" => Err(anyhow!("Expected an amount after '{} {} by'", verb, target)),
+            after => match after.strip_prefix("by ") {
+                Some(amount) => parse_expr(amount),
+                None => {
+                    let example = after.split_once(' ').map_or("1", |(_, amount)| amount.trim());
+                    Err(anyhow!(
+                        "Expected 'by <amount>' after '{} {}', got: {}.\nHint: Give the amount with 'by': {} {} by {}",
+                        verb,
+                        target,
+                        after,
+                        verb,
+                        target,
+                        example
+                    ))
                 }
-            }
-            buf.push(s[i..].chars().next().unwrap());
-            i += s[i..].chars().next().unwrap().len_utf8();
-        }
-        if !buf.trim().is_empty() {
-            tokens.push((pos, true, buf.trim().to_string())); // Last token, operator doesn't matter
-        }
+            },
+        }
+    };
+    let item = |name: String, key: Expr, amount: Expr| {
+        let current = Expr::Index(Box::new(Expr::Ident(name.clone())), Box::new(key.clone()));
+        let value = op(Box::new(current), Box::new(amount));
+        Some(Stmt::SetItem { name, key, value })
+    };
+    if let Some((name, key, after)) = split_item_target(rest) {
+        return Ok(item(name, parse_expr(key.trim())?, amount(after)?));
+    }
+    if let Some((name, after)) = split_ident(rest) {
+        if !after.trim_start().starts_with("in ") {
+            let value = op(Box::new(Expr::Ident(name.clone())), Box::new(amount(after)?));
+            return Ok(Some(Stmt::Set { name, value }));
+        }
+    }
+    // <key> in <dictionary> [by <amount>]
+    let (key, in_rest) = match extract_quoted_and_rest(rest) {
+        Some((text, after)) => (Expr::Str(text), after.trim_start().strip_prefix("in ")),
+        None => match split_once_word(rest, " in ") {
+            Some((key, after)) => (parse_expr(key.trim())?, Some(after)),
+            None => return Ok(None),
+        },
+    };
+    let Some((name, after)) = in_rest.and_then(|r| split_ident(r.trim_start())) else {
+        return Ok(None);
+    };
+    Ok(item(name, key, amount(after)?))
+}
 
-        if tokens.len() > 1 {
-            let mut expr = parse_mult(tokens[0].2.trim())?;
-            for i in 1..tokens.len() {
-                let rhs = parse_mult(tokens[i].2.trim())?;
-                if tokens[i - 1].1 {
-                    // Previous token marked as plus
-                    expr = Expr::Plus(Box::new(expr), Box::new(rhs));
-                } else {
-                    expr = Expr::Minus(Box::new(expr), Box::new(rhs));
-                }
+/// Targets of a destructuring Set: `a, b, c to ...` or `[a, b] to ...`.
+/// Returns the names and the text after `to`.
+fn split_destructure_targets(s: &str) -> Option<(Vec<String>, &str)> {
+    let (targets, rest) = match s.strip_prefix('[') {
+        Some(inner) => {
+            let (targets, rest) = inner.split_once(']')?;
+            (targets, rest.trim_start().strip_prefix("to ")?)
+        }
+        None => {
+            let (first, _) = split_ident(s)?;
+            if !s[first.len()..].trim_start().starts_with(',') {
+                return None;
             }
-            return Ok(expr);
-        }
-    }
-
-    parse_mult(s)
+            s.split_once(" to ")?
+        }
+    };
+    let names = targets
+        .split(',')
+        .map(|n| {
+            let n = n.trim();
+            let valid = split_ident(n).is_some_and(|(name, tail)| tail.is_empty() && name == n);
+            valid.then(|| n.to_string())
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some((names, rest))
 }
 
-fn parse_mult(s: &str) -> Result<Expr> {
-    // Handle multiplication and division (left-to-right, higher precedence than +/-)
-    // Support both phrasal (times/divided by) and symbolic (*/)
-    let times_parts = split_top_level(s, " times ");
-    let div_parts = split_top_level(s, " divided by ");
-    let sym_mult_parts = split_top_level(s, " * ");
-    let sym_div_parts = split_top_level(s, " / ");
-
-    if times_parts.len() > 1
-        || div_parts.len() > 1
-        || sym_mult_parts.len() > 1
-        || sym_div_parts.len() > 1
-    {
-        // Find which operator comes first
-        let mut tokens: Vec<(usize, bool, String)> = Vec::new(); // (position, is_times, text)
-        let mut pos = 0;
-        let bytes = s.as_bytes();
-        let mut in_str = false;
-        let mut depth = 0;
-        let mut buf = String::new();
-
-        let mut i = 0;
-        while i < bytes.len() {
-            if bytes[i] == b'"' {
+fn parse_params(s: &str) -> Result<Vec<Param>> {
+    let mut out = Vec::new();
+    let mut depth = 0i32;
+    let mut in_str = false;
+    let mut buf = String::new();
+    for ch in s.chars() {
+        match ch {
+            '"' => {
                 in_str = !in_str;
-                buf.push('"');
-                i += 1;
-                continue;
+                buf.push(ch);
             }
-            if !in_str {
-                if bytes[i] == b'(' || bytes[i] == b'[' || bytes[i] == b'{' {
-                    depth += 1;
-                    buf.push(bytes[i] as char);
-                    i += 1;
-                    continue;
-                }
-                if bytes[i] == b')' || bytes[i] == b']' || bytes[i] == b'}' {
-                    depth -= 1;
-                    buf.push(bytes[i] as char);
-                    i += 1;
-                    continue;
-                }
-                if depth == 0 {
-                    // Check phrasal operators first (longer patterns)
-                    if s[i..].starts_with(" divided by ") {
-                        if !buf.trim().is_empty() {
-                            tokens.push((pos, false, buf.trim().to_string()));
-                        }
-                        buf.clear();
-                        i += " divided by ".len();
-                        pos = tokens.len();
-                        continue;
-                    }
-                    if s[i..].starts_with(" times ") {
-                        if !buf.trim().is_empty() {
-                            tokens.push((pos, true, buf.trim().to_string()));
-                        }
-                        buf.clear();
-                        i += " times ".len();
-                        pos = tokens.len();
-                        continue;
-                    }
-                    // Check symbolic operators
-                    if s[i..].starts_with(" * ") {
-                        if !buf.trim().is_empty() {
-                            tokens.push((pos, true, buf.trim().to_string()));
-                        }
-                        buf.clear();
-                        i += " * ".len();
-                        pos = tokens.len();
-                        continue;
-                    }
-                    if s[i..].starts_with(" / ") {
-                        if !buf.trim().is_empty() {
-                            tokens.push((pos, false, buf.trim().to_string()));
-                        }
-                        buf.clear();
-                        i += " / ".len();
-                        pos = tokens.len();
-                        continue;
-                    }
-                }
+            '(' if !in_str => {
+                depth += 1;
+                buf.push(ch);
             }
-            buf.push(s[i..].chars().next().unwrap());
-            i += s[i..].chars().next().unwrap().len_utf8();
-        }
-        if !buf.trim().is_empty() {
-            tokens.push((pos, true, buf.trim().to_string())); // Last token, operator doesn't matter
-        }
-
-        if tokens.len() > 1 {
-            let mut expr = parse_postfix(tokens[0].2.trim())?;
-            for i in 1..tokens.len() {
-                let rhs = parse_postfix(tokens[i].2.trim())?;
-                if tokens[i - 1].1 {
-                    // Previous token marked as times
-                    expr = Expr::Times(Box::new(expr), Box::new(rhs));
-                } else {
-                    expr = Expr::DividedBy(Box::new(expr), Box::new(rhs));
-                }
+            ')' if !in_str => {
+                depth -= 1;
+                buf.push(ch);
             }
-            return Ok(expr);
-        }
-    }
-
-    parse_postfix(s)
-}
-
-fn parse_postfix(s: &str) -> Result<Expr> {
-    let s = s.trim();
-    // Check for indexing: expr[index]
-    // Find the rightmost '[' at depth 0 (not inside strings or nested brackets/parens)
-    let mut in_str = false;
-    let mut depth = 0i32;
-    let mut last_bracket = None;
-
-    for (i, ch) in s.char_indices() {
-        if ch == '"' {
-            in_str = !in_str;
-            continue;
-        }
-        if !in_str {
-            if ch == '(' || ch == '[' || ch == '{' {
-                if depth == 0 && ch == '[' {
-                    last_bracket = Some(i);
+            ',' if !in_str && depth == 0 => {
+                let item = buf.trim().to_string();
+                if !item.is_empty() {
+                    out.push(parse_param_item(&item)?);
                 }
-                depth += 1;
-            } else if ch == ')' || ch == ']' || ch == '}' {
-                depth -= 1;
+                buf.clear();
             }
+            _ => buf.push(ch),
         }
     }
-
-    // If we found a '[' at top level and the expression ends with ']'
-    if let Some(bracket_pos) = last_bracket {
-        if s.ends_with(']') && bracket_pos > 0 {
-            let base = &s[..bracket_pos];
-            let index_expr = &s[bracket_pos + 1..s.len() - 1];
-
-            // Recursively parse both parts
-            let base_expr = parse_postfix(base.trim())?;
-            let index = parse_expr(index_expr.trim())?;
-
-            return Ok(Expr::Index(Box::new(base_expr), Box::new(index)));
-        }
+    if !buf.trim().is_empty() {
+        out.push(parse_param_item(buf.trim())?);
     }
-
-    parse_term(s)
+    Ok(out)
 }
 
-fn split_top_level(s: &str, delim: &str) -> Vec<String> {
-    let mut out = Vec::new();
-    let mut buf = String::new();
-    let mut in_str = false;
-    let mut depth = 0i32;
-    let bytes = s.as_bytes();
-    let mut i = 0;
-    while i < bytes.len() {
-        if bytes[i] == b'"' {
-            in_str = !in_str;
-            buf.push('"');
-            i += 1;
-            continue;
-        }
-        if !in_str {
-            if bytes[i] == b'(' || bytes[i] == b'[' || bytes[i] == b'{' {
-                depth += 1;
-                buf.push(bytes[i] as char);
-                i += 1;
-                continue;
-            }
-            if bytes[i] == b')' || bytes[i] == b']' || bytes[i] == b'}' {
-                depth -= 1;
-                buf.push(bytes[i] as char);
-                i += 1;
-                continue;
+fn parse_param_item(s: &str) -> Result<Param> {
+    let s = s.trim();
+    if let Some(idx) = s.find(" set to ") {
+        let name = s[..idx].trim();
+        let expr_str = &s[idx + " set to ".len()..];
+        if let Some((n, rest)) = split_ident(name) {
+            if rest.trim().is_empty() {
+                let def = parse_expr(expr_str.trim())?;
+                return Ok(Param {
+                    name: n,
+                    default: Some(def),
+                });
             }
-            if depth == 0 && s[i..].starts_with(delim) {
-                out.push(buf.trim().to_string());
-                buf.clear();
-                i += delim.len();
-                continue;
+        }
+        return Err(anyhow!("Invalid parameter default: {}", s));
+    }
+    if let Some(idx) = s.find(" defaulting to ") {
+        let name = s[..idx].trim();
+        let expr_str = &s[idx + " defaulting to ".len()..];
+        if let Some((n, rest)) = split_ident(name) {
+            if rest.trim().is_empty() {
+                let def = parse_expr(expr_str.trim())?;
+                return Ok(Param {
+                    name: n,
+                    default: Some(def),
+                });
             }
         }
-        buf.push(s[i..].chars().next().unwrap());
-        i += s[i..].chars().next().unwrap().len_utf8();
+        return Err(anyhow!("Invalid parameter default: {}", s));
     }
-    if !buf.trim().is_empty() {
-        out.push(buf.trim().to_string());
+    if let Some((n, rest)) = split_ident(s) {
+        if rest.trim().is_empty() {
+            return Ok(Param {
+                name: n,
+                default: None,
+            });
+        }
     }
-    out
+    Err(anyhow!("Invalid parameter: {}", s))
+}
+
+fn parse_arg_list_multi(s: &str, allow_and: bool) -> Result<Vec<Expr>> {
+    // Split by commas (always) and optionally by " and " at top level
+    expr::parse_args(s, allow_and)
+}
+
+pub fn parse_expr(s: &str) -> Result<Expr> {
+    expr::parse_expr(s)
 }
 
 fn split_top_level_multi(s: &str, delims: &[&str]) -> Vec<String> {
@@ -1270,572 +1881,6 @@ fn split_top_level_multi(s: &str, delims: &[&str]) -> Vec<String> {
     out
 }
 
-fn split_once_top_level<'a>(s: &'a str, pat: &str) -> Option<(&'a str, &'a str)> {
-    let mut in_str = false;
-    let mut depth = 0i32;
-    let bytes = s.as_bytes();
-    let mut i = 0;
-    while i + pat.len() <= bytes.len() {
-        let ch = s[i..].chars().next().unwrap();
-        if ch == '"' {
-            in_str = !in_str;
-            i += ch.len_utf8();
-            continue;
-        }
-        if !in_str {
-            if ch == '(' || ch == '[' || ch == '{' {
-                depth += 1;
-                i += 1;
-                continue;
-            }
-            if ch == ')' || ch == ']' || ch == '}' {
-                depth -= 1;
-                i += 1;
-                continue;
-            }
-            if depth == 0 && s[i..].starts_with(pat) {
-                let (l, r) = s.split_at(i);
-                let r = &r[pat.len()..];
-                return Some((l, r));
-            }
-        }
-        i += ch.len_utf8();
-    }
-    None
-}
-
-// CmpOp provided by ast
-
-// Helper: parse a comma/" and " separated list of expressions at top level.
-fn parse_items_comma_or_and(s: &str) -> Result<Vec<Expr>> {
-    let parts = split_top_level_multi(s, &[",", " and "]);
-    let mut out = Vec::new();
-    for p in parts {
-        let t = p.trim();
-        if t.is_empty() {
-            continue;
-        }
-        out.push(parse_expr(t)?);
-    }
-    Ok(out)
-}
-
-fn parse_term(s: &str) -> Result<Expr> {
-    let s = s.trim();
-    if s.is_empty() {
-        return Err(anyhow!("Empty expression"));
-    }
-
-    // Phrasal list literals (immutable/mutable): Make a (mutable) list of 1, 2 and 3
-    if let Some(rest) = strip_prefix_ci(s, "Make a mutable list of ") {
-        let items = if rest.trim().is_empty() {
-            vec![]
-        } else {
-            parse_items_comma_or_and(rest)?
-        };
-        // TODO: track mutability; for now, same ListLit representation.
-        return Ok(Expr::ListLit(items));
-    }
-    if let Some(rest) = strip_prefix_ci(s, "Make a list of ") {
-        let items = if rest.trim().is_empty() {
-            vec![]
-        } else {
-            parse_items_comma_or_and(rest)?
-        };
-        return Ok(Expr::ListLit(items));
-    }
-
-    // Phrasal dictionary literals (immutable/mutable): Make a (mutable) dictionary with "a" as 1 and "b" as 2
-    if let Some(rest) = strip_prefix_ci(s, "Make a mutable dictionary with ") {
-        let mut pairs = Vec::new();
-        let mut r = rest.trim();
-        while !r.is_empty() {
-            // split key and remainder by ' as ' (or legacy ' set to ')
-            let (kpart, after_key) = if let Some((k, a)) = split_once_top_level(r, " as ") {
-                (k, a)
-            } else if let Some((k, a)) = split_once_top_level(r, " set to ") {
-                (k, a)
-            } else {
-                return Err(anyhow!("Expected 'as' in dictionary literal item"));
-            };
-            let kstr = extract_quoted(kpart.trim())
-                .ok_or_else(|| anyhow!("Expected quoted key in dictionary literal"))?;
-            // find next delimiter (either ' and ' or ',') at top level to terminate the value expression
-            let (vpart, rest_after_val) =
-                if let Some((v, after)) = split_once_top_level(after_key, " and ") {
-                    (v.trim(), Some(after))
-                } else if let Some((v, after)) = split_once_top_level(after_key, ",") {
-                    (v.trim(), Some(after))
-                } else {
-                    (after_key.trim(), None)
-                };
-            let vexpr = parse_expr(vpart)?;
-            pairs.push((kstr, vexpr));
-            r = match rest_after_val {
-                Some(a) => a.trim(),
-                None => "",
-            };
-        }
-        // TODO: track mutability; for now, same DictLit representation.
-        return Ok(Expr::DictLit(pairs));
-    }
-    if let Some(rest) = strip_prefix_ci(s, "Make a dictionary with ") {
-        let mut pairs = Vec::new();
-        let mut r = rest.trim();
-        while !r.is_empty() {
-            let (kpart, after_key) = if let Some((k, a)) = split_once_top_level(r, " as ") {
-                (k, a)
-            } else if let Some((k, a)) = split_once_top_level(r, " set to ") {
-                (k, a)
-            } else {
-                return Err(anyhow!("Expected 'as' in dictionary literal item"));
-            };
-            let kstr = extract_quoted(kpart.trim())
-                .ok_or_else(|| anyhow!("Expected quoted key in dictionary literal"))?;
-            let (vpart, rest_after_val) =
-                if let Some((v, after)) = split_once_top_level(after_key, " and ") {
-                    (v.trim(), Some(after))
-                } else if let Some((v, after)) = split_once_top_level(after_key, ",") {
-                    (v.trim(), Some(after))
-                } else {
-                    (after_key.trim(), None)
-                };
-            let vexpr = parse_expr(vpart)?;
-            pairs.push((kstr, vexpr));
-            r = match rest_after_val {
-                Some(a) => a.trim(),
-                None => "",
-            };
-        }
-        return Ok(Expr::DictLit(pairs));
-    }
-
-    // Bracket list literals are NOT supported - use phrasal syntax
-    if s.starts_with('[') && s.ends_with(']') {
-        return Err(anyhow!("Bracket list literals '[]' are not supported. Use: Make a list of ..."));
-    }
-
-    // Brace dict literals are NOT supported - use phrasal syntax
-    if s.starts_with('{') && s.ends_with('}') {
-        return Err(anyhow!("Brace dictionary literals '{{}}' are not supported. Use: Make a dictionary with ..."));
-    }
-
-    // Phrasal built-in expressions (case-insensitive)
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_COUNT_OF) {
-        return Ok(Expr::CountOf(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_TOTAL_OF) {
-        return Ok(Expr::TotalOf(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_SMALLEST_IN) {
-        return Ok(Expr::SmallestIn(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_LARGEST_IN) {
-        return Ok(Expr::LargestIn(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_ABS_OF) {
-        return Ok(Expr::AbsoluteValueOf(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_ROUND_DOWN) {
-        return Ok(Expr::RoundDown(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_ROUND_UP) {
-        return Ok(Expr::RoundUp(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_ROUND) {
-        return Ok(Expr::Round(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_MAKE_UPPER) {
-        return Ok(Expr::MakeUppercase(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_MAKE_LOWER) {
-        return Ok(Expr::MakeLowercase(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_TRIM_FROM) {
-        return Ok(Expr::TrimSpaces(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_FIRST_IN) {
-        return Ok(Expr::FirstIn(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_LAST_IN) {
-        return Ok(Expr::LastIn(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_REVERSE_OF) {
-        return Ok(Expr::ReverseOf(Box::new(parse_expr(rest)?)));
-    }
-    // Aliases for friendliness
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_REVERSE_ALIAS) {
-        // alias of "reverse of"
-        return Ok(Expr::ReverseOf(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_CLEAN_ALIAS) {
-        // alias of "trim spaces from"
-        return Ok(Expr::TrimSpaces(Box::new(parse_expr(rest)?)));
-    }
-    // New aliases
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_SIZE_OF) {
-        return Ok(Expr::CountOf(Box::new(parse_expr(rest)?)));
-    }
-
-    // Phrasal binary built-ins: join <list> with <sep>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_JOIN) {
-        if let Some((lhs, rhs)) = split_once_top_level(rest, P::P_JOIN_WITH) {
-            let a = parse_expr(lhs.trim())?;
-            let b = parse_expr(rhs.trim())?;
-            return Ok(Expr::JoinWith(Box::new(a), Box::new(b)));
-        }
-    }
-    // split <text> by <sep>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_SPLIT) {
-        if let Some((lhs, rhs)) = split_once_top_level(rest, P::P_SPLIT_BY) {
-            let a = parse_expr(lhs.trim())?;
-            let b = parse_expr(rhs.trim())?;
-            return Ok(Expr::SplitBy(Box::new(a), Box::new(b)));
-        }
-    }
-    // separate <text> by <sep> (alias of split)
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_SEPARATE) {
-        if let Some((lhs, rhs)) = split_once_top_level(rest, P::P_SPLIT_BY) {
-            let a = parse_expr(lhs.trim())?;
-            let b = parse_expr(rhs.trim())?;
-            return Ok(Expr::SplitBy(Box::new(a), Box::new(b)));
-        }
-    }
-
-    // Collection operations: contains <item> in <collection>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_CONTAINS) {
-        if let Some((item, collection)) = split_once_top_level(rest, P::P_CONTAINS_IN) {
-            let item_expr = parse_expr(item.trim())?;
-            let coll_expr = parse_expr(collection.trim())?;
-            return Ok(Expr::Contains(Box::new(item_expr), Box::new(coll_expr)));
-        }
-    }
-    // remove <item> from <list>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_REMOVE) {
-        if let Some((item, list)) = split_once_top_level(rest, P::P_REMOVE_FROM) {
-            let item_expr = parse_expr(item.trim())?;
-            let list_expr = parse_expr(list.trim())?;
-            return Ok(Expr::Remove(Box::new(item_expr), Box::new(list_expr)));
-        }
-    }
-    // append <item> to <list>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_APPEND) {
-        if let Some((item, list)) = split_once_top_level(rest, P::P_APPEND_TO) {
-            let item_expr = parse_expr(item.trim())?;
-            let list_expr = parse_expr(list.trim())?;
-            return Ok(Expr::Append(Box::new(item_expr), Box::new(list_expr)));
-        }
-    }
-    // insert <item> at <index> in <list>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_INSERT) {
-        // First split by " at " to get item and "index in list"
-        if let Some((item, rest2)) = split_once_top_level(rest, P::P_INSERT_AT) {
-            // Then split by " in " to get index and list
-            if let Some((index, list)) = split_once_top_level(rest2, P::P_INSERT_IN) {
-                let item_expr = parse_expr(item.trim())?;
-                let index_expr = parse_expr(index.trim())?;
-                let list_expr = parse_expr(list.trim())?;
-                return Ok(Expr::InsertAt(
-                    Box::new(item_expr),
-                    Box::new(index_expr),
-                    Box::new(list_expr),
-                ));
-            }
-        }
-    }
-
-    // File I/O operations
-    // read file at <path>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_READ_FILE) {
-        return Ok(Expr::ReadFile(Box::new(parse_expr(rest)?)));
-    }
-    // read lines from file at <path> or read lines from <path>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_READ_LINES) {
-        return Ok(Expr::ReadLines(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_READ_LINES_ALT) {
-        return Ok(Expr::ReadLines(Box::new(parse_expr(rest)?)));
-    }
-    // file exists at <path>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_FILE_EXISTS) {
-        return Ok(Expr::FileExists(Box::new(parse_expr(rest)?)));
-    }
-    // delete file at <path>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_DELETE_FILE) {
-        return Ok(Expr::DeleteFile(Box::new(parse_expr(rest)?)));
-    }
-    // create directory at <path>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_CREATE_DIR) {
-        return Ok(Expr::CreateDir(Box::new(parse_expr(rest)?)));
-    }
-    // list files in directory at <path> or list files in <path>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_LIST_DIR_ALT) {
-        return Ok(Expr::ListDir(Box::new(parse_expr(rest)?)));
-    }
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_LIST_DIR) {
-        return Ok(Expr::ListDir(Box::new(parse_expr(rest)?)));
-    }
-    // write <content> to file at <path>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_WRITE_FILE) {
-        if let Some((content, path)) = split_once_top_level(rest, P::P_WRITE_TO_FILE) {
-            let content_expr = parse_expr(content.trim())?;
-            let path_expr = parse_expr(path.trim())?;
-            return Ok(Expr::WriteFile(Box::new(content_expr), Box::new(path_expr)));
-        }
-    }
-    // append <content> to file at <path>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_APPEND_FILE) {
-        if let Some((content, path)) = split_once_top_level(rest, P::P_APPEND_TO_FILE) {
-            let content_expr = parse_expr(content.trim())?;
-            let path_expr = parse_expr(path.trim())?;
-            return Ok(Expr::AppendFile(
-                Box::new(content_expr),
-                Box::new(path_expr),
-            ));
-        }
-    }
-    // copy file from <source> to <dest>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_COPY_FILE) {
-        if let Some((source, dest)) = split_once_top_level(rest, P::P_COPY_TO) {
-            let source_expr = parse_expr(source.trim())?;
-            let dest_expr = parse_expr(dest.trim())?;
-            return Ok(Expr::CopyFile(Box::new(source_expr), Box::new(dest_expr)));
-        }
-    }
-    // move file from <source> to <dest>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_MOVE_FILE) {
-        if let Some((source, dest)) = split_once_top_level(rest, P::P_COPY_TO) {
-            let source_expr = parse_expr(source.trim())?;
-            let dest_expr = parse_expr(dest.trim())?;
-            return Ok(Expr::MoveFile(Box::new(source_expr), Box::new(dest_expr)));
-        }
-    }
-
-    // JSON operations
-    // parse json from <string>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_PARSE_JSON) {
-        return Ok(Expr::ParseJson(Box::new(parse_expr(rest)?)));
-    }
-    // convert to json <value>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_TO_JSON) {
-        return Ok(Expr::ToJson(Box::new(parse_expr(rest)?)));
-    }
-    // convert to pretty json <value>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_JSON_PRETTY) {
-        return Ok(Expr::ToJsonPretty(Box::new(parse_expr(rest)?)));
-    }
-    // json length of <value>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_JSON_LENGTH) {
-        return Ok(Expr::JsonLength(Box::new(parse_expr(rest)?)));
-    }
-    // new json object
-    if s.eq_ignore_ascii_case(P::P_NEW_JSON_OBJECT) {
-        return Ok(Expr::NewJsonObject);
-    }
-    // new json array
-    if s.eq_ignore_ascii_case(P::P_NEW_JSON_ARRAY) {
-        return Ok(Expr::NewJsonArray);
-    }
-    // get <key> from json <object>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_JSON_GET) {
-        if let Some((key, json)) = split_once_top_level(rest, P::P_JSON_FROM) {
-            let key_expr = parse_expr(key.trim())?;
-            let json_expr = parse_expr(json.trim())?;
-            return Ok(Expr::JsonGet(Box::new(json_expr), Box::new(key_expr)));
-        }
-    }
-    // set <key> in json <object> to <value>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_JSON_SET) {
-        if let Some((key_part, rest2)) = split_once_top_level(rest, P::P_JSON_IN) {
-            if let Some((json_part, value_part)) = split_once_top_level(rest2, P::P_JSON_TO) {
-                let key_expr = parse_expr(key_part.trim())?;
-                let json_expr = parse_expr(json_part.trim())?;
-                let value_expr = parse_expr(value_part.trim())?;
-                return Ok(Expr::JsonSet(
-                    Box::new(json_expr),
-                    Box::new(key_expr),
-                    Box::new(value_expr),
-                ));
-            }
-        }
-    }
-    // push <item> to json <array>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_JSON_PUSH) {
-        if let Some((item, json)) = split_once_top_level(rest, P::P_JSON_PUSH_TO) {
-            let item_expr = parse_expr(item.trim())?;
-            let json_expr = parse_expr(json.trim())?;
-            return Ok(Expr::JsonPush(Box::new(json_expr), Box::new(item_expr)));
-        }
-    }
-
-    // Error operations
-    // error message of <error>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_ERROR_MESSAGE) {
-        return Ok(Expr::ErrorMessage(Box::new(parse_expr(rest)?)));
-    }
-    // error type of <error>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_ERROR_TYPE) {
-        return Ok(Expr::ErrorType(Box::new(parse_expr(rest)?)));
-    }
-    // error of type <type> with message <message>
-    if let Some(rest) = P::strip_prefix_ci(s, "error of type ") {
-        if let Some((type_part, message_part)) = split_once_top_level(rest, P::P_WITH_MESSAGE) {
-            let error_type = type_part.trim();
-            // Remove quotes from type if present
-            let error_type_clean = if (error_type.starts_with('"') && error_type.ends_with('"'))
-                || (error_type.starts_with('\'') && error_type.ends_with('\''))
-            {
-                &error_type[1..error_type.len() - 1]
-            } else {
-                error_type
-            };
-            let message_expr = parse_expr(message_part.trim())?;
-            return Ok(Expr::NewError {
-                error_type: error_type_clean.to_string(),
-                message: Box::new(message_expr),
-            });
-        }
-    }
-
-    // Web Framework Expressions
-    // create web server on port <port>
-    if let Some(rest) = P::strip_prefix_ci(s, "create web server on port ") {
-        return Ok(Expr::CreateWebServer(Box::new(parse_expr(rest)?)));
-    }
-    // html response with <content>
-    if let Some(rest) = P::strip_prefix_ci(s, "html response with ") {
-        return Ok(Expr::HtmlResponse(Box::new(parse_expr(rest)?)));
-    }
-    // json response with <data>
-    if let Some(rest) = P::strip_prefix_ci(s, "json response with ") {
-        // Check if it has "and status"
-        if let Some((data_part, status_part)) = split_once_top_level(rest, " and status ") {
-            let data_expr = parse_expr(data_part.trim())?;
-            let status_expr = parse_expr(status_part.trim())?;
-            return Ok(Expr::JsonResponseStatus(
-                Box::new(data_expr),
-                Box::new(status_expr),
-            ));
-        }
-        return Ok(Expr::JsonResponse(Box::new(parse_expr(rest)?)));
-    }
-    // render template <template> with <data>
-    if let Some(rest) = P::strip_prefix_ci(s, "render template ") {
-        if let Some((template_part, data_part)) = split_once_top_level(rest, " with ") {
-            let template_expr = parse_expr(template_part.trim())?;
-            let data_expr = parse_expr(data_part.trim())?;
-            return Ok(Expr::RenderTemplate(
-                Box::new(template_expr),
-                Box::new(data_expr),
-            ));
-        }
-    }
-    // get path parameter <name>
-    if let Some(rest) = P::strip_prefix_ci(s, P::P_GET_PATH_PARAM) {
-        let param_expr = parse_expr(rest.trim())?;
-        return Ok(Expr::GetPathParam(Box::new(param_expr)));
-    }
-    // error response with status <status> and message <message>
-    if let Some(rest) = P::strip_prefix_ci(s, "error response with status ") {
-        if let Some((status_part, message_part)) = split_once_top_level(rest, " and message ") {
-            let status_expr = parse_expr(status_part.trim())?;
-            let message_expr = parse_expr(message_part.trim())?;
-            return Ok(Expr::ErrorResponse(
-                Box::new(status_expr),
-                Box::new(message_expr),
-            ));
-        }
-    }
-
-    // String literal
-    if (s.starts_with('"') && s.ends_with('"') && s.len() >= 2)
-        || (s.starts_with('\'') && s.ends_with('\'') && s.len() >= 2)
-    {
-        return Ok(Expr::Str(s[1..s.len() - 1].to_string()));
-    }
-    // Booleans
-    if s.eq_ignore_ascii_case("True") {
-        return Ok(Expr::Bool(true));
-    }
-    if s.eq_ignore_ascii_case("False") {
-        return Ok(Expr::Bool(false));
-    }
-    // Null/None
-    if s.eq_ignore_ascii_case("Null") || s.eq_ignore_ascii_case("Nothing") || s == "None" {
-        return Ok(Expr::Null);
-    }
-    // Legacy list syntax is NOT supported
-    if let Some(_rest) = strip_prefix_ci(s, "List contains ") {
-        return Err(anyhow!("Legacy 'List contains' is not supported. Use: Make a list of ..."));
-    }
-    // Legacy dictionary syntax is NOT supported
-    if let Some(_rest) = strip_prefix_ci(s, "Dictionary contains ") {
-        return Err(anyhow!("Legacy 'Dictionary contains' is not supported. Use: Make a dictionary with ..."));
-    }
-    // Number
-    if let Ok(n) = s.parse::<f64>() {
-        return Ok(Expr::Num(n));
-    }
-    // Phrasal call: name with args
-    if let Some((name, after)) = split_ident(s) {
-        let after = after.trim_start();
-        if let Some(rest) = after.strip_prefix("with ") {
-            let args = if rest.trim().is_empty() {
-                vec![]
-            } else {
-                parse_arg_list_multi(rest, true)?
-            };
-            return Ok(Expr::Call { name, args });
-        }
-    }
-    // Call form: name(args) OR grouping: (expr)
-    if let Some(idx) = s.find('(') {
-        if s.ends_with(')') {
-            let name = s[..idx].trim();
-            let args_str = &s[idx + 1..s.len() - 1];
-            
-            // If name is empty, this is a grouping expression: (expr)
-            if name.is_empty() {
-                let inner = args_str.trim();
-                if inner.is_empty() {
-                    return Err(anyhow!("Empty parentheses () are not allowed"));
-                }
-                // Parse the grouped expression recursively
-                return parse_expr(inner);
-            }
-            
-            // Otherwise, it's a function call: name(args)
-            let args = if args_str.trim().is_empty() {
-                vec![]
-            } else {
-                parse_arg_list(args_str)?
-            };
-            return Ok(Expr::Call {
-                name: name.to_string(),
-                args,
-            });
-        }
-    }
-    // Identifier
-    if let Some((id, rest)) = split_ident(s) {
-        if rest.trim().is_empty() {
-            return Ok(Expr::Ident(id));
-        }
-    }
-    let error_msg = format!("Could not parse expression: {}", s);
-    Err(anyhow!("{}", suggest_fix(&error_msg, s)))
-}
-
-fn extract_quoted(s: &str) -> Option<String> {
-    let st = s.trim();
-    if let Some(stripped) = st.strip_prefix('"') {
-        if let Some(pos) = stripped.find('"') {
-            return Some(stripped[..pos].to_string());
-        }
-    }
-    None
-}
-
 fn extract_quoted_and_rest(s: &str) -> Option<(String, &str)> {
     let st = s.trim_start();
     if !st.starts_with('"') {
@@ -1851,11 +1896,12 @@ fn extract_quoted_and_rest(s: &str) -> Option<(String, &str)> {
     }
 }
 
-fn parse_exposing_list(s: &str) -> Result<Vec<String>> {
+/// The names of an `exposing` or import list, each once
+fn parse_symbol_list(s: &str, list: &str) -> Result<Vec<String>> {
     let mut names = Vec::new();
     let trimmed = s.trim();
     if trimmed.is_empty() {
-        return Err(anyhow!("Expected at least one symbol after 'exposing'"));
+        return Err(anyhow!("Expected at least one symbol in {}", list));
     }
 
     // Allow comma separated list with optional 'and'
@@ -1867,17 +1913,17 @@ fn parse_exposing_list(s: &str) -> Result<Vec<String>> {
         }
         if let Some((name, rest)) = split_ident(trimmed) {
             if !rest.trim().is_empty() {
-                return Err(anyhow!("Invalid symbol name in exposing list"));
+                return Err(anyhow!("Invalid symbol name in {}", list));
             }
             if !names.contains(&name) {
                 names.push(name);
             }
         } else {
-            return Err(anyhow!("Invalid symbol name in exposing list"));
+            return Err(anyhow!("Invalid symbol name in {}", list));
         }
     }
     if names.is_empty() {
-        return Err(anyhow!("Expected at least one symbol after 'exposing'"));
+        return Err(anyhow!("Expected at least one symbol in {}", list));
     }
     Ok(names)
 }
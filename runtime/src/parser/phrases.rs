@@ -4,10 +4,26 @@
 pub const P_TOTAL_OF: &str = "total of ";
 pub const P_SMALLEST_IN: &str = "smallest in ";
 pub const P_LARGEST_IN: &str = "largest in ";
+pub const P_SMALLEST_OF: &str = "smallest of "; // items separated by commas or 'and'
+pub const P_LARGEST_OF: &str = "largest of ";
+pub const P_AVERAGE_OF: &str = "average of ";
+pub const P_MEDIAN_OF: &str = "median of ";
+pub const P_VARIANCE_OF: &str = "variance of ";
+pub const P_STD_DEV_OF: &str = "standard deviation of ";
 pub const P_ABS_OF: &str = "absolute value of ";
 pub const P_ROUND: &str = "round ";
 pub const P_ROUND_DOWN: &str = "round down ";
 pub const P_ROUND_UP: &str = "round up ";
+pub const P_INTERPOLATE: &str = "interpolate from "; // needs ' to ' and ' at '
+pub const P_INTERPOLATE_TO: &str = " to ";
+pub const P_INTERPOLATE_AT: &str = " at ";
+pub const P_CLAMP: &str = "clamp "; // needs ' between ' and ' and '
+pub const P_CLAMP_BETWEEN: &str = " between ";
+pub const P_CLAMP_AND: &str = " and ";
+pub const P_MAP: &str = "map "; // needs ' from range ', ' to ', ' into range ' and ' to '
+pub const P_MAP_FROM: &str = " from range ";
+pub const P_MAP_INTO: &str = " into range ";
+pub const P_MAP_TO: &str = " to ";
 pub const P_MAKE_UPPER: &str = "make uppercase ";
 pub const P_MAKE_LOWER: &str = "make lowercase ";
 pub const P_TRIM_FROM: &str = "trim spaces from ";
@@ -20,6 +36,7 @@ pub const P_CLEAN_ALIAS: &str = "clean spaces from ";
 
 // New phrases
 pub const P_COUNT_OF: &str = "count of ";
+pub const P_TYPE_OF: &str = "type of ";
 pub const P_JOIN: &str = "join "; // needs ' with '
 pub const P_JOIN_WITH: &str = " with ";
 pub const P_SPLIT: &str = "split "; // needs ' by '
@@ -38,6 +55,10 @@ pub const P_APPEND_TO: &str = " to ";
 pub const P_INSERT: &str = "insert "; // needs ' at ' and ' in '
 pub const P_INSERT_AT: &str = " at ";
 pub const P_INSERT_IN: &str = " in ";
+pub const P_FIND_FIRST: &str = "find first item in "; // needs ' where '
+pub const P_FIND_ALL: &str = "find all items in "; // needs ' where '
+pub const P_FIND_INDEX: &str = "find index of first item in "; // needs ' where '
+pub const P_FIND_WHERE: &str = " where ";
 
 // File I/O operation phrases
 pub const P_READ_FILE: &str = "read file at ";
@@ -61,8 +82,18 @@ pub const P_PARSE_JSON: &str = "parse json from ";
 pub const P_TO_JSON: &str = "convert to json ";
 pub const P_JSON_PRETTY: &str = "convert to pretty json ";
 pub const P_JSON_GET: &str = "get "; // needs ' from json '
+pub const P_PICK: &str = "pick "; // quoted keys, then ' from '
+pub const P_OMIT: &str = "omit "; // quoted keys, then ' from '
+pub const P_FIELDS_FROM: &str = " from ";
+pub const P_COLLECT_FIELD: &str = "collect field "; // needs ' from each item in '
+pub const P_COLLECT_FIELDS: &str = "collect fields "; // keys, then ' from each item in '
+pub const P_FROM_EACH: &str = " from each item in ";
+pub const P_AGGREGATE: &str = "aggregate list "; // needs ' computing '
+pub const P_COMPUTING: &str = " computing ";
 pub const P_JSON_FROM: &str = " from json ";
-pub const P_JSON_SET: &str = "set "; // needs ' in json ' and ' to '
+pub const P_QUERY_JSON: &str = "query json "; // needs ' with path '
+pub const P_WITH_PATH: &str = " with path ";
+pub const P_JSON_SET: &str = "set "; // needs ' in json ' and ' to ', or ' contains ' for sets
 pub const P_JSON_IN: &str = " in json ";
 pub const P_JSON_TO: &str = " to ";
 pub const P_NEW_JSON_OBJECT: &str = "new json object";
@@ -71,6 +102,25 @@ pub const P_JSON_PUSH: &str = "push "; // needs ' to json '
 pub const P_JSON_PUSH_TO: &str = " to json ";
 pub const P_JSON_LENGTH: &str = "json length of ";
 
+// Input phrases
+pub const P_ASK_AS: &str = "as "; // Ask for <name> as number / yes or no / text
+
+// Output phrases
+pub const P_NICELY: &str = " nicely"; // Write X nicely
+
+// URL phrases
+pub const P_PARSE_URL: &str = "parse url ";
+pub const P_BUILD_URL: &str = "build url from ";
+pub const P_URL_ENCODE: &str = "url encode ";
+pub const P_URL_DECODE: &str = "url decode ";
+
+// Text layout phrases
+pub const P_INDENT: &str = "indent "; // needs ' by ' and ' spaces'
+pub const P_INDENT_BY: &str = " by ";
+pub const P_DEDENT: &str = "remove common indent from ";
+pub const P_WRAP: &str = "wrap "; // needs ' at ' and ' characters'
+pub const P_WRAP_AT: &str = " at ";
+
 // Error handling phrases
 pub const P_TRY: &str = "try this:";
 pub const P_IF_ERROR: &str = "if error";
@@ -84,14 +134,138 @@ pub const P_ERROR_MESSAGE: &str = "error message of ";
 pub const P_ERROR_TYPE: &str = "error type of ";
 pub const P_WITH_MESSAGE: &str = " with message ";
 
+// Logging phrases
+pub const P_LOG_TO_FILE: &str = "log to file ";
+pub const P_ROTATING_AT: &str = " rotating at ";
+pub const P_LOG_TO_STDERR: &str = "log to stderr";
+pub const P_LOG_INFO: &str = "log info ";
+pub const P_LOG_WARN: &str = "log warn ";
+pub const P_LOG_WARNING: &str = "log warning ";
+pub const P_LOG_ERROR: &str = "log error ";
+
 // Web server phrases
+pub const P_CREATE_WEB_SERVER: &str = "create web server on port ";
+pub const P_CREATE_SECURE_WEB_SERVER: &str = "create secure web server on port ";
+pub const P_WITH_CERTIFICATE: &str = " with certificate ";
+pub const P_AND_KEY: &str = " and key ";
+pub const P_WITH_BODY_LIMIT: &str = " with body limit ";
+pub const P_WITH_REQUEST_TIMEOUT: &str = " with request timeout ";
 pub const P_GET_PATH_PARAM: &str = "get path parameter ";
+pub const P_GET_REQUEST_HEADER: &str = "get request header ";
+pub const P_SET_RESPONSE_HEADER: &str = "set response header ";
+pub const P_RESPONSE_HEADER_TO: &str = " to ";
+pub const P_REQUEST_METHOD: &str = "request method";
+pub const P_REQUEST_PATH: &str = "request path";
+pub const P_CLIENT_ADDRESS: &str = "client address";
+pub const P_PREFERRED_RESPONSE_TYPE: &str = "preferred response type of request";
+pub const P_RESPOND_WITH: &str = "respond with ";
+pub const P_START_EVENT_STREAM: &str = "start event stream";
+pub const P_WITH_HEARTBEAT: &str = " with heartbeat every ";
+pub const P_SEND_EVENT: &str = "send event ";
+pub const P_SEND_EVENT_TO: &str = " to ";
+pub const P_CLOSE: &str = "close ";
+pub const P_ADD_WEBSOCKET_ROUTE: &str = "add websocket route ";
+pub const P_ROUTE: &str = "route ";
+pub const P_ON_SERVER: &str = " on server ";
+pub const P_END_ROUTE: &str = "end route";
+pub const P_ADD_ROUTE_GROUP: &str = "add route group ";
+pub const P_END_GROUP: &str = "end group";
+pub const P_ADD_GROUP_MIDDLEWARE: &str = "add middleware to group:";
+pub const P_END_MIDDLEWARE: &str = "end middleware";
+pub const P_ENABLE_METRICS: &str = "enable metrics on server at ";
+pub const P_SERVE_STATIC_FILES: &str = "serve static files from ";
+pub const P_WITH_ETAG: &str = "with etag";
+pub const P_WITH_CACHE_FOR: &str = "with cache for ";
+pub const P_RECEIVE_MESSAGE: &str = "receive message from ";
+pub const P_WITH_TIMEOUT: &str = " with timeout ";
+pub const P_SEND: &str = "send ";
+pub const P_SEND_TO: &str = " to ";
+pub const P_ON_SHUTDOWN: &str = "on shutdown:";
+pub const P_END_ON_SHUTDOWN: &str = "end on shutdown";
+pub const P_BEFORE_EACH_ROUTE: &str = "before each route:";
+pub const P_END_BEFORE: &str = "end before";
+pub const P_AFTER_EACH_ROUTE: &str = "after each route:";
+pub const P_END_AFTER: &str = "end after";
+pub const P_PARALLEL: &str = "parallel:";
+pub const P_BRANCH: &str = "branch:";
+pub const P_END_PARALLEL: &str = "end parallel";
+pub const P_APPLY: &str = "apply "; // needs ' to each item in ' and ' in parallel'
+pub const P_TO_EACH_ITEM_IN: &str = " to each item in ";
+pub const P_IN_PARALLEL: &str = " in parallel";
+pub const P_WITH_WORKERS: &str = " with "; // then a number and 'workers'
+pub const P_BENCHMARK: &str = "benchmark ";
+pub const P_END_BENCHMARK: &str = "end benchmark";
+pub const P_MEASURE: &str = "measure time of ";
+pub const P_END_MEASURE: &str = "end measure";
+pub const P_TIME_CALLING: &str = "time calling ";
+pub const P_BIND: &str = "bind ";
+pub const P_BIND_TO: &str = "to ";
+pub const P_ON_CHANGE_RUN: &str = " and on change run";
+pub const P_END_BIND: &str = "end bind";
+pub const P_START_TASK: &str = "start task named ";
+pub const P_END_TASK: &str = "end task";
+pub const P_RUN_IN_BACKGROUND: &str = "run in background";
+pub const P_WAIT_FOR: &str = "wait for ";
+pub const P_WAIT_FOR_ALL_TASKS: &str = "wait for all tasks ";
+pub const P_WAIT_FOR_ANY_TASK: &str = "wait for any task ";
+pub const P_MAKE_A_CHANNEL: &str = "make a channel";
+pub const P_RECEIVE_FROM: &str = "receive from ";
+pub const P_WITHIN: &str = " within "; // then a number and 'seconds'
+pub const P_CHECKPOINT: &str = "checkpoint ";
+pub const P_WITH_STATE: &str = " with state ";
+pub const P_RESTORE_CHECKPOINT: &str = "restore checkpoint ";
+pub const P_INTO_VARIABLE: &str = " into variable ";
+pub const P_DEFINE_SCHEMA: &str = "define schema ";
+pub const P_DEFAULTING_TO: &str = "defaulting to ";
+pub const P_VALIDATE: &str = "validate ";
+pub const P_COERCE: &str = "coerce ";
+pub const P_AS_SCHEMA: &str = " as ";
+pub const P_SET_TEMPLATE_DIR: &str = "set template directory to ";
+pub const P_RENDER_TEMPLATE_FILE: &str = "render template file ";
+pub const P_THEN: &str = " then ";
+pub const P_ELSE: &str = " else ";
+pub const P_CREATE_EVENT_EMITTER: &str = "create event emitter ";
+pub const P_ON_EVENT: &str = "on event ";
+pub const P_FROM_EMITTER: &str = "from emitter ";
+pub const P_END_ON: &str = "end on";
+pub const P_EMIT: &str = "emit ";
+pub const P_EMIT_FROM: &str = "from ";
+pub const P_WITH_DATA: &str = "with data ";
+pub const P_AGAINST: &str = " against ";
+pub const P_REQUIRE: &str = "require ";
+pub const P_TO_MATCH: &str = " to match ";
+pub const P_ASSERT: &str = "assert ";
+pub const P_DEEP_EQUALS: &str = " deep equals ";
+pub const P_EQUALS: &str = " equals ";
+pub const P_READ_FROM_STDIN: &str = "read from stdin";
+pub const P_FOR_EACH_LINE_IN_STDIN: &str = "for each line in stdin as ";
+pub const P_END_FOR: &str = "end for";
+pub const P_FOR_EACH: &str = "for each ";
+pub const P_FOR_EACH_IN: &str = " in ";
+pub const P_RETRY: &str = "retry ";
+pub const P_TIMES_WITH_DELAY: &str = " times with delay ";
+pub const P_WITH_BACKOFF: &str = " with backoff";
+pub const P_WITH_JITTER: &str = " with jitter";
+pub const P_ON_ALL_FAILURES: &str = "on all failures";
+pub const P_END_RETRY: &str = "end retry";
+pub const P_SET_CONTAINING: &str = "set containing "; // items separated by commas or 'and'
+pub const P_EMPTY_SET: &str = "empty set";
+pub const P_SET_OF: &str = "set of ";
+pub const P_LIST_OF: &str = "list of ";
+pub const P_UNION_OF: &str = "union of "; // needs ' and '
+pub const P_INTERSECTION_OF: &str = "intersection of "; // needs ' and '
+pub const P_DIFFERENCE_OF: &str = "difference of "; // needs ' and '
+pub const P_SET_AND: &str = " and ";
+pub const P_SET_CONTAINS: &str = " contains ";
+pub const P_STORING_RESULT_IN: &str = " storing result in ";
+pub const P_SEPARATED_BY: &str = " separated by ";
+pub const P_ENDING_WITH: &str = " ending with ";
 
 pub fn strip_prefix_ci<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
-    if s.len() < prefix.len() {
+    // A prefix that ends inside a character of `s` does not match it
+    let (Some(head), Some(tail)) = (s.get(..prefix.len()), s.get(prefix.len()..)) else {
         return None;
-    }
-    let (head, tail) = s.split_at(prefix.len());
+    };
     if head.eq_ignore_ascii_case(prefix) {
         Some(tail)
     } else {
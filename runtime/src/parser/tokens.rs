@@ -0,0 +1,489 @@
+//! Tokens for editors and syntax highlighting.
+//!
+//! Unlike the expression lexer this never fails and drops nothing: the spans
+//! of the tokens returned cover the source from start to end, whitespace
+//! included, so joining their text gives back the source exactly. Text it
+//! cannot read, such as an unterminated string, becomes an `Error` token.
+//! Phrases like `Make a list of` and `is greater than` are one `Keyword`
+//! token each.
+
+use super::expr::{COMPARISONS, UNARY_PHRASES};
+use super::lexer::{is_number, word_end, SYMBOLS};
+use super::phrases as P;
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    /// A statement word or phrase, possibly several words long
+    Keyword,
+    Identifier,
+    String,
+    Number,
+    /// Operators, brackets, commas and colons
+    Symbol,
+    /// A line starting with `#` or `//`, up to but not including the newline
+    Comment,
+    Whitespace,
+    /// Text that is not part of the language, or a string left open
+    Error,
+}
+
+impl TokenKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenKind::Keyword => "keyword",
+            TokenKind::Identifier => "identifier",
+            TokenKind::String => "string",
+            TokenKind::Number => "number",
+            TokenKind::Symbol => "symbol",
+            TokenKind::Comment => "comment",
+            TokenKind::Whitespace => "whitespace",
+            TokenKind::Error => "error",
+        }
+    }
+}
+
+/// A token and the byte range of the source it covers
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Range<usize>,
+}
+
+impl Token {
+    pub fn text<'a>(&self, src: &'a str) -> &'a str {
+        &src[self.span.clone()]
+    }
+}
+
+// Statement words and phrases the expression parser does not list itself
+const STATEMENT_KEYWORDS: &[&str] = &[
+    "Start Program",
+    "End Program",
+    "Write",
+    "Print table with headers",
+    "and rows",
+    "Ask for",
+    "yes or no",
+    "Set",
+    "Type",
+    "Increase",
+    "Decrease",
+    "by",
+    "Import system",
+    "Import",
+    "exposing",
+    "Load env vars starting with",
+    "stripping prefix",
+    "If",
+    "Otherwise",
+    "End If",
+    "While",
+    "End While",
+    "Repeat until",
+    "Repeat",
+    "times",
+    "End Repeat",
+    "End",
+    "Make",
+    "Define function",
+    "with",
+    "parameters",
+    "parameter",
+    "set to",
+    "Use",
+    "Call",
+    "Return",
+    "as",
+    "to",
+    "from",
+    "in",
+    "and",
+    "or",
+    "not",
+    "plus",
+    "minus",
+    "divided by",
+    "True",
+    "False",
+    "None",
+    "Null",
+    "Nothing",
+    "Make a list of",
+    "Make a mutable list of",
+    "Make a dictionary with",
+    "Make a mutable dictionary with",
+    "tuple of",
+    "list contains",
+    "dictionary contains",
+    "error of type",
+    "json response with",
+    "and status",
+    "render template",
+    "error response with status",
+    "add route",
+    "add middleware",
+    "start server",
+    "limit to",
+];
+
+// Phrases shared with the parser
+const PHRASES: &[&str] = &[
+    P::P_JOIN,
+    P::P_SPLIT,
+    P::P_SEPARATE,
+    P::P_CONTAINS,
+    P::P_REMOVE,
+    P::P_APPEND,
+    P::P_INSERT,
+    P::P_FIND_FIRST,
+    P::P_FIND_ALL,
+    P::P_FIND_INDEX,
+    P::P_FIND_WHERE,
+    P::P_INTERPOLATE,
+    P::P_CLAMP_BETWEEN,
+    P::P_MAP_FROM,
+    P::P_MAP_INTO,
+    P::P_DEDENT,
+    P::P_SMALLEST_OF,
+    P::P_LARGEST_OF,
+    P::P_WRITE_TO_FILE,
+    P::P_COPY_FILE,
+    P::P_MOVE_FILE,
+    P::P_PICK,
+    P::P_OMIT,
+    P::P_COLLECT_FIELD,
+    P::P_COLLECT_FIELDS,
+    P::P_AGGREGATE,
+    P::P_COMPUTING,
+    P::P_FROM_EACH,
+    P::P_JSON_FROM,
+    P::P_QUERY_JSON,
+    P::P_WITH_PATH,
+    P::P_JSON_IN,
+    P::P_NEW_JSON_OBJECT,
+    P::P_NEW_JSON_ARRAY,
+    P::P_JSON_PUSH,
+    P::P_JSON_PUSH_TO,
+    P::P_NICELY,
+    P::P_TRY,
+    P::P_IF_ERROR,
+    P::P_OF_TYPE,
+    P::P_FINALLY,
+    P::P_END_TRY,
+    P::P_THROW,
+    P::P_WITH_MESSAGE,
+    P::P_LOG_TO_FILE,
+    P::P_ROTATING_AT,
+    P::P_LOG_TO_STDERR,
+    P::P_LOG_INFO,
+    P::P_LOG_WARN,
+    P::P_LOG_WARNING,
+    P::P_LOG_ERROR,
+    P::P_CREATE_WEB_SERVER,
+    P::P_CREATE_SECURE_WEB_SERVER,
+    P::P_WITH_CERTIFICATE,
+    P::P_WITH_BODY_LIMIT,
+    P::P_WITH_REQUEST_TIMEOUT,
+    P::P_SET_RESPONSE_HEADER,
+    P::P_REQUEST_METHOD,
+    P::P_REQUEST_PATH,
+    P::P_CLIENT_ADDRESS,
+    P::P_PREFERRED_RESPONSE_TYPE,
+    P::P_RESPOND_WITH,
+    P::P_START_EVENT_STREAM,
+    P::P_WITH_HEARTBEAT,
+    P::P_SEND_EVENT,
+    P::P_CLOSE,
+    P::P_ADD_WEBSOCKET_ROUTE,
+    P::P_ROUTE,
+    P::P_ON_SERVER,
+    P::P_END_ROUTE,
+    P::P_ADD_ROUTE_GROUP,
+    P::P_END_GROUP,
+    P::P_ADD_GROUP_MIDDLEWARE,
+    P::P_END_MIDDLEWARE,
+    P::P_ENABLE_METRICS,
+    P::P_SERVE_STATIC_FILES,
+    P::P_WITH_ETAG,
+    P::P_WITH_CACHE_FOR,
+    P::P_RECEIVE_MESSAGE,
+    P::P_WITH_TIMEOUT,
+    P::P_SEND,
+    P::P_ON_SHUTDOWN,
+    P::P_END_ON_SHUTDOWN,
+    P::P_BEFORE_EACH_ROUTE,
+    P::P_END_BEFORE,
+    P::P_AFTER_EACH_ROUTE,
+    P::P_END_AFTER,
+    P::P_PARALLEL,
+    P::P_BRANCH,
+    P::P_END_PARALLEL,
+    P::P_APPLY,
+    P::P_TO_EACH_ITEM_IN,
+    P::P_IN_PARALLEL,
+    P::P_BENCHMARK,
+    P::P_END_BENCHMARK,
+    P::P_MEASURE,
+    P::P_END_MEASURE,
+    P::P_TIME_CALLING,
+    P::P_ON_CHANGE_RUN,
+    P::P_END_BIND,
+    P::P_START_TASK,
+    P::P_END_TASK,
+    P::P_RUN_IN_BACKGROUND,
+    P::P_WAIT_FOR_ALL_TASKS,
+    P::P_WAIT_FOR_ANY_TASK,
+    P::P_MAKE_A_CHANNEL,
+    P::P_RECEIVE_FROM,
+    P::P_CHECKPOINT,
+    P::P_WITH_STATE,
+    P::P_RESTORE_CHECKPOINT,
+    P::P_INTO_VARIABLE,
+    P::P_DEFINE_SCHEMA,
+    P::P_DEFAULTING_TO,
+    P::P_VALIDATE,
+    P::P_COERCE,
+    P::P_SET_TEMPLATE_DIR,
+    P::P_RENDER_TEMPLATE_FILE,
+    P::P_THEN,
+    P::P_ELSE,
+    P::P_CREATE_EVENT_EMITTER,
+    P::P_ON_EVENT,
+    P::P_FROM_EMITTER,
+    P::P_END_ON,
+    P::P_EMIT,
+    P::P_EMIT_FROM,
+    P::P_WITH_DATA,
+    P::P_AGAINST,
+    P::P_REQUIRE,
+    P::P_TO_MATCH,
+    P::P_DEEP_EQUALS,
+    P::P_READ_FROM_STDIN,
+    P::P_FOR_EACH_LINE_IN_STDIN,
+    P::P_END_FOR,
+    P::P_FOR_EACH,
+    P::P_RETRY,
+    P::P_TIMES_WITH_DELAY,
+    P::P_WITH_BACKOFF,
+    P::P_WITH_JITTER,
+    P::P_ON_ALL_FAILURES,
+    P::P_END_RETRY,
+    P::P_SET_CONTAINING,
+    P::P_EMPTY_SET,
+    P::P_UNION_OF,
+    P::P_INTERSECTION_OF,
+    P::P_DIFFERENCE_OF,
+];
+
+/// Every keyword phrase, as its words
+fn keywords() -> impl Iterator<Item = &'static str> {
+    STATEMENT_KEYWORDS
+        .iter()
+        .copied()
+        .chain(PHRASES.iter().copied())
+        .chain(COMPARISONS.iter().map(|(phrase, _)| *phrase))
+        .chain(UNARY_PHRASES.iter().map(|(phrase, _)| *phrase))
+        .map(|phrase| phrase.trim().trim_end_matches(':'))
+}
+
+/// End of `phrase` if the source spells it out from `start`, ignoring case,
+/// with spaces or tabs between its words
+fn phrase_end(src: &str, start: usize, phrase: &str) -> Option<usize> {
+    let mut end = start;
+    for (i, word) in phrase.split(' ').enumerate() {
+        if i > 0 {
+            let gap = src[end..].len() - src[end..].trim_start_matches([' ', '\t']).len();
+            if gap == 0 {
+                return None;
+            }
+            end += gap;
+        }
+        let candidate = src.get(end..end + word.len())?;
+        if !candidate.eq_ignore_ascii_case(word) {
+            return None;
+        }
+        end += word.len();
+    }
+    // A whole word, so `tomorrow` is not `to` followed by `morrow`
+    (word_end(src, end) == end).then_some(end)
+}
+
+/// The source as tokens. Never fails; see the module docs.
+pub fn tokenize(src: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    // Only whitespace so far on this line, so `#` and `//` start a comment
+    let mut line_start = true;
+
+    while let Some(c) = src[pos..].chars().next() {
+        let start = pos;
+        let kind = if c.is_whitespace() {
+            pos += src[pos..].len() - src[pos..].trim_start().len();
+            TokenKind::Whitespace
+        } else if line_start && (c == '#' || src[pos..].starts_with("//")) {
+            pos += src[pos..].find('\n').unwrap_or(src.len() - pos);
+            TokenKind::Comment
+        } else if c == '"' || c == '\'' || (c == 'r' && src[pos + 1..].starts_with('"')) {
+            // `r"pattern"` is a string too, its `r` included
+            let (quote_at, quote) = if c == 'r' { (pos + 1, '"') } else { (pos, c) };
+            let (end, closed) = string_end(src, quote_at, quote);
+            pos = end;
+            if closed {
+                TokenKind::String
+            } else {
+                TokenKind::Error
+            }
+        } else if super::lexer::is_word_char(c) {
+            let word = word_end(src, pos);
+            if is_number(&src[pos..word]) {
+                pos = word;
+                TokenKind::Number
+            } else if let Some(end) = keywords().filter_map(|k| phrase_end(src, pos, k)).max() {
+                pos = end;
+                TokenKind::Keyword
+            } else {
+                pos = word;
+                TokenKind::Identifier
+            }
+        } else if let Some(sym) = SYMBOLS.iter().chain([&":"]).find(|s| src[pos..].starts_with(**s)) {
+            pos += sym.len();
+            TokenKind::Symbol
+        } else {
+            pos += c.len_utf8();
+            TokenKind::Error
+        };
+        let text = &src[start..pos];
+        line_start = if kind == TokenKind::Whitespace {
+            line_start || text.contains('\n')
+        } else {
+            false
+        };
+        tokens.push(Token {
+            kind,
+            span: start..pos,
+        });
+    }
+    tokens
+}
+
+/// End of the string opened by the `quote` at `start`, and whether it was
+/// closed. A string left open stops at the end of its line.
+fn string_end(src: &str, start: usize, quote: char) -> (usize, bool) {
+    let mut chars = src[start..].char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\n' => return (start + i, false),
+            '\\' => {
+                if let Some((_, '\n')) | None = chars.next() {
+                    // Leave the newline for the next token
+                    return (start + i + 1, false);
+                }
+            }
+            c if c == quote => return (start + i + c.len_utf8(), true),
+            _ => {}
+        }
+    }
+    (src.len(), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Kind and text of each token, leaving out whitespace
+    fn shown(src: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(src)
+            .into_iter()
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .map(|t| (t.kind, t.text(src)))
+            .collect()
+    }
+
+    fn rebuilt(src: &str) -> String {
+        tokenize(src).iter().map(|t| t.text(src)).collect()
+    }
+
+    #[test]
+    fn test_phrases_are_single_keywords() {
+        use TokenKind::*;
+        assert_eq!(
+            shown("Set nums to Make a list of 1, 2.5\nIf total of nums is  greater than 3"),
+            vec![
+                (Keyword, "Set"),
+                (Identifier, "nums"),
+                (Keyword, "to"),
+                (Keyword, "Make a list of"),
+                (Number, "1"),
+                (Symbol, ","),
+                (Number, "2.5"),
+                (Keyword, "If"),
+                (Keyword, "total of"),
+                (Identifier, "nums"),
+                (Keyword, "is  greater than"),
+                (Number, "3"),
+            ]
+        );
+        // Keywords are whole words only
+        assert_eq!(shown("tomorrow"), vec![(Identifier, "tomorrow")]);
+        assert_eq!(shown("try this:"), vec![(Keyword, "try this"), (Symbol, ":")]);
+    }
+
+    #[test]
+    fn test_comments_strings_and_errors() {
+        use TokenKind::*;
+        assert_eq!(
+            shown("# note\n  // also\nWrite \"a \\\" b\" # not a comment"),
+            vec![
+                (Comment, "# note"),
+                (Comment, "// also"),
+                (Keyword, "Write"),
+                (String, "\"a \\\" b\""),
+                (Error, "#"),
+                (Keyword, "not"),
+                (Identifier, "a"),
+                (Identifier, "comment"),
+            ]
+        );
+        assert_eq!(
+            shown("Write \"open\nWrite 'ünïcode' ? x"),
+            vec![
+                (Keyword, "Write"),
+                (Error, "\"open"),
+                (Keyword, "Write"),
+                (String, "'ünïcode'"),
+                (Error, "?"),
+                (Identifier, "x"),
+            ]
+        );
+        assert_eq!(
+            shown(r#"Set re to r"\d+" plus r"#),
+            vec![
+                (Keyword, "Set"),
+                (Identifier, "re"),
+                (Keyword, "to"),
+                (String, r#"r"\d+""#),
+                (Keyword, "plus"),
+                (Identifier, "r"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans_rebuild_the_source() {
+        let sources = [
+            "Start Program\n\tWrite \"hi\" plus 1\r\nEnd Program\n",
+            "Make greet with name\n    Return \"Hello \" plus name\nEnd\n",
+            "\"unterminated \\",
+            "x ? y ¿ ∑ 3.x 'a\\'b' a::b(1)>=2 // still code",
+            "",
+            "   \n\n",
+        ];
+        for src in sources {
+            assert_eq!(rebuilt(src), src);
+            let tokens = tokenize(src);
+            assert!(tokens.windows(2).all(|w| w[0].span.end == w[1].span.start));
+            assert!(tokens.iter().all(|t| !t.span.is_empty()));
+        }
+    }
+}
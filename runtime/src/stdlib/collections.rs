@@ -0,0 +1,212 @@
+/// Collections Module for PohLang
+///
+/// Data structures behind `Import system "collections"`:
+/// - `PriorityQueue`: highest priority first, ties in insertion order
+/// - `OrderedDict`: a string-keyed map that iterates in insertion order
+///
+/// Stacks and queues need nothing beyond `Vec` and `VecDeque`, so only these
+/// two live here. Both are generic so they can be tested without the VM.
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+#[derive(Debug)]
+struct Entry<T> {
+    priority: f64,
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    // Max-heap on priority; for equal priorities the earlier push wins
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .total_cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+#[derive(Debug)]
+pub struct PriorityQueue<T> {
+    heap: BinaryHeap<Entry<T>>,
+    next_seq: u64,
+}
+
+impl<T> Default for PriorityQueue<T> {
+    fn default() -> Self {
+        PriorityQueue {
+            heap: BinaryHeap::new(),
+            next_seq: 0,
+        }
+    }
+}
+
+impl<T> PriorityQueue<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, item: T, priority: f64) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.heap.push(Entry {
+            priority,
+            seq,
+            item,
+        });
+    }
+
+    /// Remove and return the item with the highest priority
+    pub fn pop_highest(&mut self) -> Option<T> {
+        self.heap.pop().map(|e| e.item)
+    }
+
+    pub fn peek(&self) -> Option<&T> {
+        self.heap.peek().map(|e| &e.item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Items in the order `pop_highest` would return them
+    pub fn items_by_priority(&self) -> Vec<&T> {
+        let mut entries: Vec<&Entry<T>> = self.heap.iter().collect();
+        entries.sort_by(|a, b| b.cmp(a));
+        entries.into_iter().map(|e| &e.item).collect()
+    }
+
+    /// A queue of `f` of each item, with the same priorities and order
+    pub fn map<U>(&self, mut f: impl FnMut(&T) -> U) -> PriorityQueue<U> {
+        PriorityQueue {
+            heap: self.heap.iter().map(|e| Entry { priority: e.priority, seq: e.seq, item: f(&e.item) }).collect(),
+            next_seq: self.next_seq,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OrderedDict<T> {
+    entries: Vec<(String, T)>,
+    index: HashMap<String, usize>,
+}
+
+impl<T> Default for OrderedDict<T> {
+    fn default() -> Self {
+        OrderedDict {
+            entries: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+}
+
+impl<T> OrderedDict<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert or update a key. Updating keeps the key's original position.
+    pub fn insert(&mut self, key: String, value: T) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&T> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<T> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for slot in self.index.values_mut() {
+            if *slot > i {
+                *slot -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &T)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    /// A dictionary of `f` of each value, with the keys in the same order
+    pub fn map<U>(&self, mut f: impl FnMut(&T) -> U) -> OrderedDict<U> {
+        OrderedDict {
+            entries: self.entries.iter().map(|(k, v)| (k.clone(), f(v))).collect(),
+            index: self.index.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_queue_order() {
+        let mut pq = PriorityQueue::new();
+        pq.push("low", 1.0);
+        pq.push("high", 5.0);
+        pq.push("also high", 5.0);
+        pq.push("mid", 3.0);
+        assert_eq!(pq.items_by_priority(), vec![&"high", &"also high", &"mid", &"low"]);
+        assert_eq!(pq.peek(), Some(&"high"));
+        assert_eq!(pq.pop_highest(), Some("high"));
+        assert_eq!(pq.pop_highest(), Some("also high"));
+        assert_eq!(pq.len(), 2);
+        assert_eq!(pq.pop_highest(), Some("mid"));
+        assert_eq!(pq.pop_highest(), Some("low"));
+        assert!(pq.pop_highest().is_none());
+    }
+
+    #[test]
+    fn test_ordered_dict_keeps_insertion_order() {
+        let mut od = OrderedDict::new();
+        od.insert("zebra".to_string(), 1);
+        od.insert("apple".to_string(), 2);
+        od.insert("mango".to_string(), 3);
+        od.insert("zebra".to_string(), 10);
+        let keys: Vec<&String> = od.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["zebra", "apple", "mango"]);
+        assert_eq!(od.get("zebra"), Some(&10));
+
+        assert_eq!(od.remove("apple"), Some(2));
+        assert_eq!(od.get("mango"), Some(&3));
+        od.insert("kiwi".to_string(), 4);
+        let keys: Vec<&String> = od.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["zebra", "mango", "kiwi"]);
+        assert_eq!(od.len(), 3);
+    }
+}
@@ -0,0 +1,484 @@
+//! Reading HTTP/1.1 requests from client connections and writing responses
+//!
+//! Each connection is served on its own thread, one request after another
+//! for as long as the client keeps it open. Three limits protect the server
+//! from clients that send too much, too slowly or too often:
+//! - A body over the limit is answered 413 Payload Too Large. The
+//!   `Content-Length` is checked before any of the body is read; a chunked
+//!   body is cut off as soon as it passes the limit.
+//! - A client gets the request timeout to send each request in full, from
+//!   its first byte to the end of its body. One too slow is answered 408
+//!   Request Timeout and disconnected, so trickling headers or stalling
+//!   part way through a body cannot hold a thread forever.
+//! - At most the connection limit are served at once. A client past it is
+//!   answered 503 Service Unavailable and disconnected, so a flood of
+//!   connections cannot start a thread each.
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::time::{Duration, Instant};
+
+use super::socket::Socket;
+
+/// Largest request body accepted unless the program sets its own limit
+pub const DEFAULT_BODY_LIMIT: usize = 10 * 1024 * 1024;
+
+/// How long a client may take to send a request unless the program says
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many connections are served at once, each on a thread of its own
+pub const DEFAULT_CONNECTION_LIMIT: usize = 1024;
+
+/// The request line and headers together may be no longer than this
+const MAX_HEAD: usize = 64 * 1024;
+
+/// How much a client may send, how slowly, and how many may be connected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    pub body_bytes: usize,
+    pub timeout: Duration,
+    pub connections: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            body_bytes: DEFAULT_BODY_LIMIT,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            connections: DEFAULT_CONNECTION_LIMIT,
+        }
+    }
+}
+
+/// A request as the client sent it
+#[derive(Debug)]
+pub struct RawRequest {
+    pub method: String,
+    /// Path and query string
+    pub target: String,
+    /// In the order sent, names as the client wrote them
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Whether the client wants the connection kept open afterwards
+    pub keep_alive: bool,
+}
+
+impl RawRequest {
+    /// The first header called `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Why no request was read
+#[derive(Debug)]
+pub enum ReadError {
+    /// The client closed the connection, or went quiet, between requests
+    Closed,
+    /// The request broke a rule; answer with this status and disconnect
+    Reject(u16),
+    Io(io::Error),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+/// A client connection
+pub struct Connection {
+    reader: BufReader<Socket>,
+    writer: Socket,
+    /// When the request being read must be in; `None` between requests
+    deadline: Option<Instant>,
+}
+
+impl Connection {
+    pub fn new(stream: Socket) -> io::Result<Self> {
+        Ok(Self {
+            writer: stream.try_clone()?,
+            reader: BufReader::new(stream),
+            deadline: None,
+        })
+    }
+
+    /// Another handle on the socket, for writing to it directly
+    pub fn socket(&self) -> io::Result<Socket> {
+        self.writer.try_clone()
+    }
+
+    /// What has been read from the socket past the last request
+    pub fn buffered(&self) -> Vec<u8> {
+        self.reader.buffer().to_vec()
+    }
+
+    /// Read the next request, holding it to `limits`
+    pub fn read_request(&mut self, limits: Limits) -> Result<RawRequest, ReadError> {
+        self.deadline = None;
+        let mut head = MAX_HEAD;
+        // Blank lines before a request are allowed and skipped
+        let mut request_line = String::new();
+        while request_line.is_empty() {
+            request_line = self.read_line(limits, &mut head)?;
+        }
+        let mut parts = request_line.split_whitespace();
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ReadError::Reject(400));
+        };
+        let http_10 = match version {
+            "HTTP/1.1" => false,
+            "HTTP/1.0" => true,
+            _ => return Err(ReadError::Reject(505)),
+        };
+
+        let mut headers = Vec::new();
+        loop {
+            let line = self.read_line(limits, &mut head)?;
+            if line.is_empty() {
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                return Err(ReadError::Reject(400));
+            };
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        let mut request = RawRequest {
+            method: method.to_string(),
+            target: target.to_string(),
+            headers,
+            body: Vec::new(),
+            keep_alive: false,
+        };
+        let connection = request.header("Connection").map(str::to_ascii_lowercase);
+        request.keep_alive = match connection.as_deref() {
+            Some("close") => false,
+            Some("keep-alive") => true,
+            _ => !http_10,
+        };
+        request.body = self.read_body(&request, limits)?;
+        self.deadline = None;
+        Ok(request)
+    }
+
+    fn read_body(&mut self, request: &RawRequest, limits: Limits) -> Result<Vec<u8>, ReadError> {
+        let chunked = request
+            .header("Transfer-Encoding")
+            .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"));
+        let length = match request.header("Content-Length") {
+            Some(n) => Some(n.parse::<usize>().map_err(|_| ReadError::Reject(400))?),
+            None => None,
+        };
+        if !chunked && length.unwrap_or(0) == 0 {
+            return Ok(Vec::new());
+        }
+        if length.is_some_and(|n| n > limits.body_bytes) {
+            return Err(ReadError::Reject(413));
+        }
+        let expects_continue = request
+            .header("Expect")
+            .is_some_and(|e| e.eq_ignore_ascii_case("100-continue"));
+        if expects_continue {
+            self.writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        }
+
+        let mut body = Vec::new();
+        if !chunked {
+            self.read_exact_into(&mut body, length.unwrap_or(0), limits)?;
+            return Ok(body);
+        }
+        // Chunk size lines are bounded too, so a body cannot hide in
+        // endless chunk extensions
+        let mut head = MAX_HEAD.saturating_add(limits.body_bytes);
+        loop {
+            let line = self.read_line(limits, &mut head)?;
+            let size = line.split(';').next().unwrap_or("").trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| ReadError::Reject(400))?;
+            if size == 0 {
+                // Trailers are read and dropped
+                while !self.read_line(limits, &mut head)?.is_empty() {}
+                return Ok(body);
+            }
+            if body.len().saturating_add(size) > limits.body_bytes {
+                return Err(ReadError::Reject(413));
+            }
+            self.read_exact_into(&mut body, size, limits)?;
+            if !self.read_line(limits, &mut head)?.is_empty() {
+                return Err(ReadError::Reject(400));
+            }
+        }
+    }
+
+    /// More of the request, waiting no later than its deadline. The
+    /// deadline starts with the first byte, so a connection left open
+    /// between requests is only closed, not answered.
+    fn fill(&mut self, limits: Limits) -> Result<&[u8], ReadError> {
+        let remaining = match self.deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => limits.timeout,
+        };
+        if remaining.is_zero() {
+            return Err(ReadError::Reject(408));
+        }
+        self.reader.get_ref().set_read_timeout(Some(remaining))?;
+        let waiting = self.deadline.is_none();
+        match self.reader.fill_buf() {
+            Ok([]) if waiting => Err(ReadError::Closed),
+            Ok([]) => Err(ReadError::Reject(400)),
+            Ok(_) => {
+                self.deadline.get_or_insert_with(|| Instant::now() + remaining);
+                Ok(self.reader.buffer())
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if waiting {
+                    Err(ReadError::Closed)
+                } else {
+                    Err(ReadError::Reject(408))
+                }
+            }
+            Err(e) => Err(ReadError::Io(e)),
+        }
+    }
+
+    /// A line without its line ending, taking its length from `budget`
+    fn read_line(&mut self, limits: Limits, budget: &mut usize) -> Result<String, ReadError> {
+        let mut line = Vec::new();
+        loop {
+            let buf = self.fill(limits)?;
+            let (taken, done) = match buf.iter().position(|&b| b == b'\n') {
+                Some(i) => (i + 1, true),
+                None => (buf.len(), false),
+            };
+            if taken > *budget {
+                return Err(ReadError::Reject(431));
+            }
+            *budget -= taken;
+            line.extend_from_slice(&buf[..taken]);
+            self.reader.consume(taken);
+            if done {
+                break;
+            }
+        }
+        while matches!(line.last(), Some(b'\n' | b'\r')) {
+            line.pop();
+        }
+        String::from_utf8(line).map_err(|_| ReadError::Reject(400))
+    }
+
+    fn read_exact_into(&mut self, body: &mut Vec<u8>, mut n: usize, limits: Limits) -> Result<(), ReadError> {
+        while n > 0 {
+            let buf = self.fill(limits)?;
+            let taken = buf.len().min(n);
+            body.extend_from_slice(&buf[..taken]);
+            self.reader.consume(taken);
+            n -= taken;
+        }
+        Ok(())
+    }
+
+    /// Send a response. A `Content-Length` is always added; for a HEAD
+    /// request the body is left out. A header with a line break in it is
+    /// refused with `InvalidInput` before anything is sent.
+    pub fn respond(
+        &mut self,
+        status: u16,
+        headers: &[(String, String)],
+        body: &[u8],
+        head_only: bool,
+        keep_alive: bool,
+    ) -> io::Result<()> {
+        if let Some((name, _)) = headers.iter().find(|(k, v)| k.contains(['\r', '\n']) || v.contains(['\r', '\n'])) {
+            let msg = format!("header {:?} cannot contain a line break", name);
+            return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+        }
+        let mut out = format!("HTTP/1.1 {} {}\r\n", status, reason(status));
+        for (name, value) in headers {
+            let framing = ["Content-Length", "Transfer-Encoding", "Connection"]
+                .iter()
+                .any(|h| h.eq_ignore_ascii_case(name));
+            if !framing {
+                out.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        // A 304 has no body, and the length of the one it stands for is not known here
+        if status != 304 {
+            out.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        if !keep_alive {
+            out.push_str("Connection: close\r\n");
+        }
+        out.push_str("\r\n");
+        self.writer.write_all(out.as_bytes())?;
+        if !head_only {
+            self.writer.write_all(body)?;
+        }
+        self.writer.flush()
+    }
+}
+
+/// The reason phrase for a status code
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// A connection to a client thread that sends `parts` with `pause`
+    /// between them, and the text the client receives back
+    fn client(parts: &[&str], pause: Duration) -> (Connection, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let parts: Vec<String> = parts.iter().map(|p| p.to_string()).collect();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            for part in parts {
+                // The server may already have given up and closed
+                if stream.write_all(part.as_bytes()).is_err() {
+                    break;
+                }
+                thread::sleep(pause);
+            }
+            let _ = stream.shutdown(std::net::Shutdown::Write);
+            let mut received = String::new();
+            let _ = stream.read_to_string(&mut received);
+            received
+        });
+        let (stream, _) = listener.accept().unwrap();
+        (Connection::new(stream.into()).unwrap(), client)
+    }
+
+    fn limits(body_bytes: usize, timeout_ms: u64) -> Limits {
+        Limits {
+            body_bytes,
+            timeout: Duration::from_millis(timeout_ms),
+            ..Limits::default()
+        }
+    }
+
+    #[test]
+    fn test_requests_are_read_one_after_another() {
+        let (mut conn, client) = client(
+            &[
+                "GET /a?x=1 HTTP/1.1\r\nHost: h\r\nContent-Length: 5\r\n\r\nhello",
+                "POST /b HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n3\r\nabc\r\n2;ext\r\nde\r\n0\r\n\r\n",
+            ],
+            Duration::from_millis(20),
+        );
+        let first = conn.read_request(Limits::default()).unwrap();
+        assert_eq!((first.method.as_str(), first.target.as_str()), ("GET", "/a?x=1"));
+        assert_eq!(first.header("host"), Some("h"));
+        assert_eq!(first.body, b"hello");
+        assert!(first.keep_alive);
+        let second = conn.read_request(Limits::default()).unwrap();
+        assert_eq!(second.body, b"abcde");
+        assert!(!second.keep_alive);
+        // Nothing of a response with a line break in a header is sent
+        let split = conn.respond(200, &[("X-A".into(), "1\r\nSet-Cookie: a=b".into())], b"", false, true);
+        assert_eq!(split.unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(conn.respond(200, &[("X-A\nB".into(), "1".into())], b"", false, true).is_err());
+        conn.respond(201, &[("X-A".into(), "1".into())], b"done", false, false).unwrap();
+        drop(conn);
+        assert_eq!(
+            client.join().unwrap(),
+            "HTTP/1.1 201 Created\r\nX-A: 1\r\nContent-Length: 4\r\nConnection: close\r\n\r\ndone"
+        );
+    }
+
+    #[test]
+    fn test_bodies_over_the_limit_are_refused_before_they_are_read() {
+        // Only the headers are ever sent, so a 413 cannot have waited for the body
+        let (mut conn, _client) = client(
+            &["POST /up HTTP/1.1\r\nContent-Length: 1048577\r\n\r\n"],
+            Duration::from_millis(500),
+        );
+        let started = Instant::now();
+        assert!(matches!(conn.read_request(limits(1 << 20, 5_000)), Err(ReadError::Reject(413))));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let (mut conn, _client) = client(
+            &["POST /up HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nabcd\r\n4\r\nefgh\r\n"],
+            Duration::ZERO,
+        );
+        assert!(matches!(conn.read_request(limits(6, 5_000)), Err(ReadError::Reject(413))));
+    }
+
+    #[test]
+    fn test_slow_requests_time_out() {
+        // Headers trickled in one piece at a time, each in good time, still
+        // run out the clock for the request as a whole
+        let (mut conn, _client) = client(
+            &["GET / HTTP/1.1\r\n", "Host: h\r\n", "X-A: 1\r\n", "X-B: 2\r\n", "X-C: 3\r\n"],
+            Duration::from_millis(80),
+        );
+        let started = Instant::now();
+        assert!(matches!(conn.read_request(limits(100, 200)), Err(ReadError::Reject(408))));
+        assert!(started.elapsed() < Duration::from_millis(1_000));
+
+        // A body that stops part way
+        let (mut conn, _client) = client(
+            &["POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc", ""],
+            Duration::from_millis(500),
+        );
+        assert!(matches!(conn.read_request(limits(100, 200)), Err(ReadError::Reject(408))));
+
+        // A quiet keep-alive connection is closed without an answer
+        let (mut conn, _client) = client(&[""], Duration::from_millis(500));
+        assert!(matches!(conn.read_request(limits(100, 100)), Err(ReadError::Closed)));
+    }
+
+    #[test]
+    fn test_malformed_requests_are_rejected() {
+        for (request, status) in [
+            ("NONSENSE\r\n\r\n", 400),
+            ("GET / HTTP/2.0\r\n\r\n", 505),
+            ("GET / HTTP/1.1\r\nno colon\r\n\r\n", 400),
+            ("POST / HTTP/1.1\r\nContent-Length: many\r\n\r\n", 400),
+        ] {
+            let (mut conn, _client) = client(&[request], Duration::ZERO);
+            match conn.read_request(Limits::default()) {
+                Err(ReadError::Reject(s)) => assert_eq!(s, status, "{:?}", request),
+                other => panic!("{:?} gave {:?}", request, other.map(|r| r.method)),
+            }
+        }
+    }
+}
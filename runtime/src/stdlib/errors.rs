@@ -121,10 +121,15 @@ impl PohError {
         if !self.stack_trace.is_empty() {
             output.push_str("\nCall stack:");
             for frame in &self.stack_trace {
-                output.push_str(&format!(
-                    "\n  in {} at {}:{}",
-                    frame.function, frame.file, frame.line
-                ));
+                // Line 0 means the line was not recorded
+                if frame.line == 0 {
+                    output.push_str(&format!("\n  in {} at {}", frame.function, frame.file));
+                } else {
+                    output.push_str(&format!(
+                        "\n  in {} at {}:{}",
+                        frame.function, frame.file, frame.line
+                    ));
+                }
             }
         }
 
@@ -213,7 +218,7 @@ mod tests {
         assert_eq!(ErrorKind::from_string("FileError"), ErrorKind::FileError);
         assert_eq!(
             ErrorKind::from_string("CustomError"),
-            ErrorKind::Custom("customerror".to_string())
+            ErrorKind::Custom("CustomError".to_string())
         );
     }
 
@@ -226,17 +231,19 @@ mod tests {
         let error = PohError::with_stack_trace(ErrorKind::RuntimeError, "Test error", trace);
 
         let formatted = error.format_with_trace();
-        assert!(formatted.contains("RuntimeError: Test error"));
-        assert!(formatted.contains("Stack trace:"));
-        assert!(formatted.contains("at main (main.poh:10)"));
-        assert!(formatted.contains("at process (lib.poh:25)"));
+        assert!(formatted.starts_with("[RuntimeError]"));
+        assert!(formatted.contains("Test error"));
+        assert!(formatted.contains("Call stack:"));
+        assert!(formatted.contains("in main at main.poh:10"));
+        assert!(formatted.contains("in process at lib.poh:25"));
     }
 
     #[test]
     fn test_error_display() {
         let error = PohError::new(ErrorKind::TypeError, "Type mismatch");
         let display = format!("{}", error);
-        assert!(display.contains("TypeError: Type mismatch"));
+        assert!(display.contains("[TypeError]"));
+        assert!(display.contains("Type mismatch"));
     }
 
     #[test]
@@ -0,0 +1,226 @@
+//! `start event stream`: a server-sent events response, written while the
+//! route handler runs instead of after it.
+//!
+//! The connection thread offers its socket before calling a handler.
+//! Starting a stream takes it and sends the response head at once, with no
+//! length, and each `Send event` writes one event. When the handler ends
+//! the stream is closed, and the connection with it. A thread per stream
+//! watches for the client going away and writes a comment when nothing has
+//! been sent for a heartbeat, so proxies do not drop the connection as idle.
+
+use anyhow::{bail, Result};
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::socket::Socket;
+
+pub const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(15);
+/// A client that takes longer than this to accept a write counts as gone
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+thread_local! {
+    /// The socket of the request handled on this thread, until a stream takes it
+    static OFFERED: RefCell<Option<Socket>> = const { RefCell::new(None) };
+    /// The stream started for that request, if one was
+    static STARTED: RefCell<Option<EventStream>> = const { RefCell::new(None) };
+}
+
+/// Let the handler about to run on this thread start a stream on `socket`
+pub fn offer(socket: Socket) {
+    OFFERED.with(|o| *o.borrow_mut() = Some(socket));
+    STARTED.with(|s| s.borrow_mut().take());
+}
+
+/// After the handler: the stream it started, if it did. The offer ends.
+pub fn finish() -> Option<EventStream> {
+    OFFERED.with(|o| o.borrow_mut().take());
+    STARTED.with(|s| s.borrow_mut().take())
+}
+
+/// An open event stream; copies share it
+#[derive(Clone, Debug)]
+pub struct EventStream(Arc<Shared>);
+
+#[derive(Debug)]
+struct Shared {
+    writer: Mutex<Writer>,
+    open: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Writer {
+    socket: Socket,
+    last_write: Instant,
+}
+
+impl EventStream {
+    /// Answer the request handled on this thread with the head of an event
+    /// stream. `headers` are those set with `set response header`.
+    pub fn start(headers: &[(String, String)], heartbeat: Duration) -> Result<EventStream> {
+        let Some(mut socket) = OFFERED.with(|o| o.borrow_mut().take()) else {
+            bail!("start event stream can only be used once in a route handler");
+        };
+        let mut head = String::from(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n",
+        );
+        for (name, value) in headers {
+            let fixed = ["Content-Type", "Content-Length", "Transfer-Encoding", "Connection", "Cache-Control"]
+                .iter()
+                .any(|h| h.eq_ignore_ascii_case(name));
+            if !fixed {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str("\r\n");
+        socket.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        socket.write_all(head.as_bytes())?;
+        let watched = socket.try_clone()?;
+        let stream = EventStream(Arc::new(Shared {
+            writer: Mutex::new(Writer {
+                socket,
+                last_write: Instant::now(),
+            }),
+            open: AtomicBool::new(true),
+        }));
+        let watcher = stream.clone();
+        thread::spawn(move || watcher.watch(watched, heartbeat));
+        STARTED.with(|s| *s.borrow_mut() = Some(stream.clone()));
+        Ok(stream)
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.0.open.load(Ordering::SeqCst)
+    }
+
+    /// Send `data` as one event, a `data:` line for each of its lines
+    pub fn send(&self, data: &str) -> Result<()> {
+        let mut event: String = data
+            .split('\n')
+            .map(|line| format!("data: {}\n", line.trim_end_matches('\r')))
+            .collect();
+        event.push('\n');
+        if self.write(event.as_bytes()).is_err() {
+            bail!("[StreamClosed] The event stream is closed; the client may have gone away");
+        }
+        Ok(())
+    }
+
+    /// End the stream. Closing it again does nothing.
+    pub fn close(&self) {
+        if self.0.open.swap(false, Ordering::SeqCst) {
+            let writer = self.0.writer.lock().unwrap();
+            let _ = writer.socket.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn write(&self, bytes: &[u8]) -> io::Result<()> {
+        if !self.is_open() {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        let mut writer = self.0.writer.lock().unwrap();
+        let written = writer.socket.write_all(bytes).and_then(|_| writer.socket.flush());
+        drop(writer);
+        match written {
+            Ok(()) => {
+                self.0.writer.lock().unwrap().last_write = Instant::now();
+                Ok(())
+            }
+            Err(e) => {
+                self.close();
+                Err(e)
+            }
+        }
+    }
+
+    /// Until the stream closes: close it when the client hangs up, and send
+    /// a comment whenever nothing else has been sent for `heartbeat`
+    fn watch(&self, mut socket: Socket, heartbeat: Duration) {
+        let mut buf = [0u8; 512];
+        while self.is_open() {
+            let idle = self.0.writer.lock().unwrap().last_write.elapsed();
+            if idle >= heartbeat {
+                if self.write(b": heartbeat\n\n").is_err() {
+                    break;
+                }
+                continue;
+            }
+            let _ = socket.set_read_timeout(Some(heartbeat - idle));
+            match socket.read(&mut buf) {
+                // Anything the client sends after its request is ignored
+                Ok(n) if n > 0 => {}
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => {}
+                _ => break,
+            }
+        }
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+
+    /// A stream started on the server end of a fresh connection, and the client end
+    fn started(heartbeat: Duration) -> (EventStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        offer(server.into());
+        let headers = [("X-Room".to_string(), "lobby".to_string()), ("Content-Type".to_string(), "text/html".to_string())];
+        let stream = EventStream::start(&headers, heartbeat).unwrap();
+        (stream, client)
+    }
+
+    #[test]
+    fn test_events_follow_the_head_and_end_with_the_stream() {
+        let (stream, mut client) = started(DEFAULT_HEARTBEAT);
+        assert!(EventStream::start(&[], DEFAULT_HEARTBEAT).is_err());
+        stream.send("hello").unwrap();
+        stream.send("two\nlines").unwrap();
+        assert!(finish().is_some());
+        stream.close();
+        assert!(stream.send("late").is_err());
+
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        let (head, body) = received.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(head.contains("Content-Type: text/event-stream\r\n"), "{}", head);
+        assert!(head.contains("X-Room: lobby"), "{}", head);
+        assert!(!head.contains("text/html") && !head.contains("Content-Length"), "{}", head);
+        assert_eq!(body, "data: hello\n\ndata: two\ndata: lines\n\n");
+    }
+
+    #[test]
+    fn test_idle_streams_send_heartbeats() {
+        let (stream, mut client) = started(Duration::from_millis(50));
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0u8; 256];
+        while !String::from_utf8_lossy(&received).contains(": heartbeat\n\n") {
+            let n = client.read(&mut buf).unwrap();
+            assert!(n > 0, "stream ended without a heartbeat");
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert!(stream.is_open());
+        stream.close();
+    }
+
+    #[test]
+    fn test_a_client_hanging_up_closes_the_stream() {
+        let (stream, client) = started(DEFAULT_HEARTBEAT);
+        drop(client);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while stream.is_open() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!stream.is_open());
+        assert!(stream.send("anyone?").is_err());
+    }
+}
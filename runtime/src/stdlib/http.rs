@@ -1,15 +1,28 @@
-use anyhow::{anyhow, Result};
+use anyhow::{bail, Result};
 use serde_json::{json, Value as JsonValue};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs;
-use std::io::Read;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
-use tiny_http::{Header, Request, Response, Server, StatusCode};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
+use crate::parser::ast::Program;
+
+use super::connection::{self, Connection, Limits, RawRequest, ReadError};
+use super::event_stream;
+use super::metrics::Metrics;
 use super::router::{Router, RoutePattern};
 use super::middleware::MiddlewareChain;
+use super::rate_limit::RateLimiter;
+use super::shutdown;
+use super::socket::Socket;
+use super::tls::{TlsConfig, TlsFiles};
+use super::websocket;
 
 /// Represents an HTTP request for PohLang
 #[derive(Debug, Clone)]
@@ -20,6 +33,17 @@ pub struct HttpRequest {
     pub headers: HashMap<String, String>,
     pub body: String,
     pub path_params: HashMap<String, String>, // Added for path parameters
+    pub remote_addr: String,
+}
+
+impl HttpRequest {
+    /// Header names are case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
 }
 
 /// Represents an HTTP response for PohLang
@@ -40,6 +64,44 @@ impl Default for HttpResponse {
     }
 }
 
+thread_local! {
+    /// Headers set with `set response header` while a handler runs; each
+    /// request is handled on its own thread
+    static RESPONSE_HEADERS: RefCell<Vec<(String, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Queue a header for the response of the request handled on this thread
+pub fn set_response_header(name: String, value: String) -> Result<()> {
+    check_header(&name, &value)?;
+    RESPONSE_HEADERS.with(|h| h.borrow_mut().push((name, value)));
+    Ok(())
+}
+
+/// A line break would end the header early and start one the program did
+/// not write
+fn check_header(name: &str, value: &str) -> Result<()> {
+    if name.contains(['\r', '\n']) || value.contains(['\r', '\n']) {
+        bail!("header {:?} cannot contain a line break", name);
+    }
+    Ok(())
+}
+
+/// Take the headers queued on this thread, in the order they were set
+pub fn take_response_headers() -> Vec<(String, String)> {
+    RESPONSE_HEADERS.with(|h| h.take())
+}
+
+/// Take the queued headers; later ones replace earlier ones with the same
+/// name, ignoring case
+fn apply_response_headers(response: &mut HttpResponse) -> Result<()> {
+    for (name, value) in take_response_headers() {
+        check_header(&name, &value)?;
+        response.headers.retain(|k, _| !k.eq_ignore_ascii_case(&name));
+        response.headers.insert(name, value);
+    }
+    Ok(())
+}
+
 /// Route handler type
 pub type RouteHandler = Arc<dyn Fn(HttpRequest) -> Result<HttpResponse> + Send + Sync>;
 
@@ -49,6 +111,8 @@ pub struct Route {
     pub path: String,
     pub method: String,
     pub handler: RouteHandler,
+    /// Line of the `Add route` statement; 0 for routes the runtime adds
+    pub line: usize,
 }
 
 impl std::fmt::Debug for Route {
@@ -61,37 +125,105 @@ impl std::fmt::Debug for Route {
     }
 }
 
+/// `Before each route` and `After each route` blocks, in the order they
+/// were added. Route handlers run them around every matched request.
+#[derive(Debug, Default)]
+pub struct RouteHooks {
+    pub before: Vec<Program>,
+    pub after: Vec<Program>,
+}
+
 /// Web server instance
 #[derive(Debug)]
 pub struct WebServer {
     port: u16,
     routes: Arc<Mutex<Vec<Route>>>,
+    /// Shared with the handlers, so hooks added after a route still run for it
+    hooks: Arc<RwLock<RouteHooks>>,
+    #[allow(dead_code)] // Not wired into request dispatch yet
     router: Arc<Mutex<Router>>, // Added for advanced routing
     middleware: Arc<Mutex<MiddlewareChain>>, // Added for middleware support
+    rate_limit: RateLimit,
+    limits: Limits,
+    /// Set for `create secure web server`
+    tls: Option<TlsConfig>,
+    metrics: Arc<Metrics>,
+    /// Set by `Serve static files from`; requests no route matches are
+    /// answered from it
+    static_dir: Option<Arc<PathBuf>>,
 }
 
+/// Shared by the server and its request threads; `None` means no limit
+type RateLimit = Arc<Mutex<Option<RateLimiter>>>;
+
 impl WebServer {
     /// Creates a new web server
     pub fn new(port: u16) -> Self {
         Self {
             port,
             routes: Arc::new(Mutex::new(Vec::new())),
+            hooks: Arc::new(RwLock::new(RouteHooks::default())),
             router: Arc::new(Mutex::new(Router::new())),
             middleware: Arc::new(Mutex::new(MiddlewareChain::new())),
+            rate_limit: Arc::new(Mutex::new(None)),
+            limits: Limits::default(),
+            tls: None,
+            metrics: Arc::new(Metrics::default()),
+            static_dir: None,
         }
     }
 
-    /// Adds a route to the server
-    pub fn add_route(&mut self, path: String, method: String, handler: RouteHandler) {
-        let route = Route {
+    /// What the server has served, for `Enable metrics`
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Serve HTTPS with this certificate and key instead of plain HTTP;
+    /// an error when they cannot be served with, or in a build without the
+    /// `tls` feature
+    pub fn set_tls(&mut self, files: TlsFiles) -> Result<()> {
+        self.tls = Some(files.config()?);
+        Ok(())
+    }
+
+    /// Answer GET and HEAD requests no route matches with the files in
+    /// `dir`; replaces any earlier directory
+    pub fn set_static_dir(&mut self, dir: PathBuf) {
+        self.static_dir = Some(Arc::new(dir));
+    }
+
+    /// Limit how often each client may call the server; replaces any
+    /// earlier limit
+    pub fn set_rate_limit(&self, limiter: RateLimiter) {
+        *self.rate_limit.lock().unwrap() = Some(limiter);
+    }
+
+    /// Adds a route to the server. A second route for the same method and
+    /// path is an error, since only the first would ever be used.
+    pub fn add_route(&mut self, path: String, method: String, line: usize, handler: RouteHandler) -> Result<()> {
+        let method = method.to_uppercase();
+        let mut routes = self.routes.lock().unwrap();
+        if let Some(first) = routes.iter().find(|r| r.path == path && r.method == method) {
+            bail!(
+                "add route: {} {} on line {} is already handled by the route on line {}",
+                method,
+                path,
+                line,
+                first.line
+            );
+        }
+        routes.push(Route {
             path,
-            method: method.to_uppercase(),
+            method,
             handler,
-        };
+            line,
+        });
+        Ok(())
+    }
 
-        if let Ok(mut routes) = self.routes.lock() {
-            routes.push(route);
-        }
+    /// The hooks route handlers run around each request
+    pub fn route_hooks(&self) -> Arc<RwLock<RouteHooks>> {
+        Arc::clone(&self.hooks)
     }
 
     /// Add a route directly (for internal use, can work with Arc)
@@ -115,38 +247,34 @@ impl WebServer {
         }
     }
 
+    /// Refuse request bodies over `bytes` with 413 Payload Too Large
+    pub fn set_body_limit(&mut self, bytes: usize) {
+        self.limits.body_bytes = bytes;
+    }
+
+    /// Disconnect clients that take longer than `timeout` to send a
+    /// request, with 408 Request Timeout
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.limits.timeout = timeout;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
     /// Starts the web server (blocking)
     pub fn start(&self) -> Result<()> {
-        let addr = format!("127.0.0.1:{}", self.port);
-        let server = Server::http(&addr).map_err(|e| anyhow!("Failed to start server: {}", e))?;
+        let (connections, addr) = listen("127.0.0.1", self.port)?;
+        let site = Site {
+            routes: self.routes.clone(),
+            static_dir: self.static_dir.clone(),
+            tls: self.tls.clone(),
+        };
 
-        println!("🚀 Server listening on http://{}", addr);
+        println!("🚀 Server listening on {}://{}", site.scheme(), addr);
         eprintln!("[DEBUG] Entering request loop...");
 
-        let routes = self.routes.clone();
-        let mut request_count = 0;
-
-        loop {
-            eprintln!("[DEBUG] Waiting for request #{}...", request_count + 1);
-            let request = match server.recv() {
-                Ok(req) => {
-                    request_count += 1;
-                    eprintln!("[DEBUG] Received request #{}", request_count);
-                    req
-                }
-                Err(e) => {
-                    eprintln!("[ERROR] Error receiving request: {}", e);
-                    continue;
-                }
-            };
-
-            let routes = routes.clone();
-            thread::spawn(move || {
-                if let Err(e) = handle_request(request, &routes) {
-                    eprintln!("Error handling request: {}", e);
-                }
-            });
-        }
+        serve(connections, site, self.rate_limit.clone(), self.limits, self.metrics())
     }
 }
 
@@ -154,156 +282,417 @@ impl WebServer {
 /// This is the proper way to start a server that's shared via Arc<Mutex<WebServer>>
 pub fn start_server_from_arc(server_arc: Arc<Mutex<WebServer>>) -> Result<()> {
     // Extract what we need without holding the lock
-    let (addr, routes) = {
+    let (port, site, rate_limit, limits, metrics) = {
         let server = server_arc.lock().unwrap();
-        let addr = format!("0.0.0.0:{}", server.port);
-        let routes = server.routes.clone();
-        (addr, routes)
+        let site = Site {
+            routes: server.routes.clone(),
+            static_dir: server.static_dir.clone(),
+            tls: server.tls.clone(),
+        };
+        (server.port, site, server.rate_limit.clone(), server.limits, server.metrics())
     }; // Lock released here
 
-    // Now start the server without holding any locks
-    let http_server = Server::http(&addr).map_err(|e| anyhow!("Failed to start server: {}", e))?;
+    // Now start the server without holding any locks; on port 0 the system
+    // picks a free one, and `addr` has the port it picked
+    let (connections, addr) = listen("0.0.0.0", port)?;
 
-    eprintln!("[DEBUG] Server::http() succeeded, server object created");
     eprintln!("[DEBUG] Server address: {}", addr);
-    eprintln!(
-        "[DEBUG] Server type: {:?}",
-        std::any::type_name_of_val(&http_server)
-    );
 
-    println!("🚀 Server listening on http://{}", addr);
+    println!("🚀 Server listening on {}://{}", site.scheme(), addr);
     eprintln!("[DEBUG] Entering request loop...");
 
-    let mut request_count = 0;
+    serve(connections, site, rate_limit, limits, metrics)
+}
 
-    loop {
-        eprintln!(
-            "[DEBUG] Loop iteration {}, about to call server.recv()...",
-            request_count + 1
-        );
-        eprintln!("[DEBUG] Right before recv() call...");
-        let request = match http_server.recv() {
-            Ok(req) => {
-                request_count += 1;
-                eprintln!("[DEBUG] Received request #{}", request_count);
-                req
-            }
-            Err(e) => {
-                eprintln!("[ERROR] Error receiving request: {}", e);
-                eprintln!("[ERROR] Error details: {:?}", e);
-                return Err(anyhow!("Server recv() failed: {}", e));
+/// What the request threads answer from: the routes, then the static
+/// files, over TLS for a secure server
+#[derive(Clone)]
+struct Site {
+    routes: Arc<Mutex<Vec<Route>>>,
+    static_dir: Option<Arc<PathBuf>>,
+    tls: Option<TlsConfig>,
+}
+
+impl Site {
+    fn scheme(&self) -> &'static str {
+        if self.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        }
+    }
+}
+
+/// Why `Start server` could not listen on its port; a NetworkError in the
+/// program, so it can try another port
+#[derive(Debug)]
+pub struct BindError {
+    pub port: u16,
+    pub source: std::io::Error,
+}
+
+impl std::fmt::Display for BindError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.source.kind() {
+            std::io::ErrorKind::AddrInUse => write!(
+                f,
+                "port {} is already in use — choose a different port or stop the other process",
+                self.port
+            ),
+            std::io::ErrorKind::PermissionDenied => write!(
+                f,
+                "not allowed to listen on port {}; ports below 1024 usually need extra privileges, so choose a higher one",
+                self.port
+            ),
+            _ => write!(f, "cannot listen on port {}: {}", self.port, self.source),
+        }
+    }
+}
+
+impl std::error::Error for BindError {}
+
+/// Accept connections on `host:port` on a thread of their own, and the
+/// address listened on. Once the receiver is dropped, connections are
+/// closed as soon as they are accepted.
+fn listen(host: &str, port: u16) -> Result<(mpsc::Receiver<TcpStream>, SocketAddr)> {
+    let listener = TcpListener::bind((host, port)).map_err(|source| BindError { port, source })?;
+    let local_addr = listener.local_addr()?;
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if sender.send(stream).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => eprintln!("[ERROR] Error accepting connection: {}", e),
             }
+        }
+    });
+    Ok((receiver, local_addr))
+}
+
+/// Serve each connection on its own thread until shutdown is requested,
+/// up to `limits.connections` at once
+fn serve(
+    connections: mpsc::Receiver<TcpStream>,
+    site: Site,
+    rate_limit: RateLimit,
+    limits: Limits,
+    metrics: Arc<Metrics>,
+) -> Result<()> {
+    let serving = shutdown::serving();
+    metrics.start();
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let open = Arc::new(AtomicUsize::new(0));
+
+    // Poll with a timeout so a shutdown signal is noticed between connections
+    while !shutdown::requested() {
+        let stream = match connections.recv_timeout(shutdown::POLL_INTERVAL) {
+            Ok(stream) => stream,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => bail!("Server stopped accepting connections"),
         };
+        if open.load(Ordering::SeqCst) >= limits.connections {
+            metrics.record(503, None);
+            refuse(stream, &site);
+            continue;
+        }
+        let slot = OpenConnection::new(&open);
 
-        let routes_clone = routes.clone();
+        let (site, rate_limit, in_flight) = (site.clone(), rate_limit.clone(), Arc::clone(&in_flight));
+        let metrics = Arc::clone(&metrics);
         thread::spawn(move || {
-            if let Err(e) = handle_request(request, &routes_clone) {
-                eprintln!("Error handling request: {}", e);
-            }
+            serve_connection(stream, &site, &rate_limit, limits, &in_flight, &metrics);
+            drop(slot);
         });
     }
+    drain(connections, &in_flight, serving)
 }
 
-/// Handles an incoming HTTP request
-pub fn handle_request_external(request: Request, routes: &Arc<Mutex<Vec<Route>>>) -> Result<()> {
-    handle_request(request, routes)
+/// A connection's place under the connection limit, given up when its
+/// thread is done with it
+struct OpenConnection(Arc<AtomicUsize>);
+
+impl OpenConnection {
+    fn new(open: &Arc<AtomicUsize>) -> Self {
+        open.fetch_add(1, Ordering::SeqCst);
+        OpenConnection(Arc::clone(open))
+    }
 }
 
-/// Handles an incoming HTTP request (internal)
-fn handle_request(mut request: Request, routes: &Arc<Mutex<Vec<Route>>>) -> Result<()> {
-    // Extract request information
-    let method = request.method().to_string();
-    let path = request.url().to_string();
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
-    // Parse query string
-    let query = parse_query_string(&path);
+/// Turn away a connection past the limit without giving it a thread. The
+/// short answer fits in the new socket's send buffer, so writing it does not
+/// block; a secure server just closes the connection.
+fn refuse(stream: TcpStream, site: &Site) {
+    if site.tls.is_some() || stream.set_nonblocking(true).is_err() {
+        return;
+    }
+    if let Ok(mut conn) = Connection::new(Socket::from(stream)) {
+        let response = error_response(503, connection::reason(503).to_string());
+        if send_response(&mut conn, response, false, false).is_ok() {
+            // Closing with the request unread would reset the connection
+            // before the client reads the answer
+            let _ = conn.read_request(Limits::default());
+        }
+    }
+}
 
-    // Extract headers
-    let mut headers = HashMap::new();
-    for header in request.headers() {
-        headers.insert(
-            header.field.as_str().to_string(),
-            header.value.as_str().to_string(),
-        );
+/// Answer the requests of one connection until the client is done with it.
+/// Requests count in `in_flight` from when they are read until answered.
+fn serve_connection(
+    stream: TcpStream,
+    site: &Site,
+    rate_limit: &RateLimit,
+    limits: Limits,
+    in_flight: &AtomicUsize,
+    metrics: &Metrics,
+) {
+    let remote_addr = stream
+        .peer_addr()
+        .map(|a| a.ip().to_string())
+        .unwrap_or_default();
+    // A client that cannot finish the handshake in time is dropped unanswered
+    let socket = match &site.tls {
+        Some(tls) => match tls.accept(stream, limits.timeout) {
+            Ok(socket) => socket,
+            Err(_) => return,
+        },
+        None => Socket::from(stream),
+    };
+    let Ok(mut conn) = Connection::new(socket) else {
+        return;
+    };
+    let _active = metrics.connection();
+    loop {
+        let raw = match conn.read_request(limits) {
+            Ok(raw) => raw,
+            Err(ReadError::Reject(status)) => {
+                metrics.record(status, None);
+                let response = error_response(status, connection::reason(status).to_string());
+                let _ = send_response(&mut conn, response, false, false);
+                return;
+            }
+            // The client went away, either way
+            Err(ReadError::Closed) | Err(ReadError::Io(_)) => return,
+        };
+        in_flight.fetch_add(1, Ordering::SeqCst);
+        let head_only = raw.method == "HEAD";
+        // No more requests on this connection once the server is stopping
+        let keep_alive = raw.keep_alive && !shutdown::requested();
+        if let Ok(socket) = conn.socket() {
+            event_stream::offer(socket);
+        }
+        let upgrade = websocket::upgrade_key(&raw.method, &raw.headers);
+        let mut request = to_http_request(raw, &remote_addr);
+        if let Some(key) = upgrade {
+            if let Ok(socket) = conn.socket() {
+                websocket::offer(socket, conn.buffered(), key);
+            }
+            request.method = websocket::METHOD.to_string();
+        }
+        let started = Instant::now();
+        let response = handle_request(request, site, rate_limit);
+        // A handler that started an event stream or accepted a websocket
+        // has answered already
+        let (stream, socket) = (event_stream::finish(), websocket::finish());
+        if stream.is_some() || socket.is_some() {
+            metrics.record(if socket.is_some() { 101 } else { 200 }, None);
+            if let Some(stream) = stream {
+                stream.close();
+            }
+            if let Some(socket) = socket {
+                socket.close();
+            }
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            return;
+        }
+        metrics.record(response.status, Some(started.elapsed()));
+        let sent = match send_response(&mut conn, response, head_only, keep_alive) {
+            // Nothing was sent of a response the handler gave a broken header
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => {
+                let response = error_response(500, format!("Handler error: {}", e));
+                send_response(&mut conn, response, head_only, false).and(Err(e))
+            }
+            sent => sent,
+        };
+        in_flight.fetch_sub(1, Ordering::SeqCst);
+        if sent.is_err() || !keep_alive {
+            return;
+        }
     }
+}
 
-    // Read body
-    let mut body_string = String::new();
-    if let Err(e) = request.as_reader().read_to_string(&mut body_string) {
-        eprintln!("Error reading body: {}", e);
+/// Stop accepting connections, give in-flight requests up to
+/// `shutdown::DRAIN_TIMEOUT` to finish, then wait for the shutdown thread
+/// to run the `On shutdown` hooks and exit the process
+fn drain(
+    connections: mpsc::Receiver<TcpStream>,
+    in_flight: &AtomicUsize,
+    serving: shutdown::ServerGuard,
+) -> Result<()> {
+    drop(connections);
+    println!("🛑 Shutting down, finishing in-flight requests...");
+    let deadline = Instant::now() + shutdown::DRAIN_TIMEOUT;
+    while in_flight.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        thread::sleep(shutdown::POLL_INTERVAL);
+    }
+    let abandoned = in_flight.load(Ordering::SeqCst);
+    if abandoned > 0 {
+        eprintln!("[WARN] {} request(s) still running after the drain timeout", abandoned);
+    }
+    drop(serving);
+    loop {
+        thread::park();
     }
+}
 
-    // Create PohLang request object
-    let poh_request = HttpRequest {
-        method: method.clone(),
-        path: path.split('?').next().unwrap_or(&path).to_string(),
+fn to_http_request(raw: RawRequest, remote_addr: &str) -> HttpRequest {
+    let query = parse_query_string(&raw.target);
+    HttpRequest {
+        method: raw.method,
+        path: raw.target.split('?').next().unwrap_or(&raw.target).to_string(),
         query,
-        headers,
-        body: body_string,
+        headers: raw.headers.into_iter().collect(),
+        body: String::from_utf8_lossy(&raw.body).into_owned(),
         path_params: HashMap::new(), // Will be filled by router if matched
-    };
+        remote_addr: remote_addr.to_string(),
+    }
+}
 
-    // Find matching route - try exact match first, then pattern matching
-    let routes_guard = routes.lock().unwrap();
-    
-    // Try exact match first (for backwards compatibility)
-    let exact_match = routes_guard
-        .iter()
-        .find(|r| r.path == poh_request.path && r.method == method);
+/// The response to a request: from its route or a static file, or 404 or
+/// 429. A response whose ETag the client already has is sent as 304.
+fn handle_request(poh_request: HttpRequest, site: &Site, rate_limit: &RateLimit) -> HttpResponse {
+    RESPONSE_HEADERS.with(|h| h.borrow_mut().clear());
 
-    let response = if let Some(route) = exact_match {
-        // Exact route match
-        match (route.handler)(poh_request.clone()) {
+    if let Some(retry_after) = over_rate_limit(rate_limit, &poh_request) {
+        let mut response = error_response(429, "Too Many Requests".to_string());
+        response
+            .headers
+            .insert("Retry-After".to_string(), retry_after.to_string());
+        return response;
+    }
+    let method = poh_request.method.clone();
+    // Find the handler and the request to give it, trying exact paths
+    // first, then patterns with path parameters. It is called once the
+    // routes are unlocked: an event stream or a websocket keeps its handler
+    // running, and other requests must not wait for it to end.
+    let matched = {
+        let routes = site.routes.lock().unwrap();
+        let exact_match = routes
+            .iter()
+            .find(|r| r.path == poh_request.path && r.method == method)
+            .map(|route| (Arc::clone(&route.handler), poh_request.clone()));
+        exact_match.or_else(|| {
+            routes
+                .iter()
+                .filter(|r| r.path.contains(':') && r.method == method)
+                .find_map(|route| {
+                    let params = RoutePattern::new(&route.path).ok()?.matches(&poh_request.path)?;
+                    let mut req_with_params = poh_request.clone();
+                    req_with_params.path_params = params;
+                    Some((Arc::clone(&route.handler), req_with_params))
+                })
+        })
+    };
+
+    let mut response = match matched {
+        Some((handler, request)) => match handler(request) {
             Ok(resp) => resp,
             Err(e) => error_response(500, format!("Handler error: {}", e)),
-        }
-    } else {
-        // Try pattern matching for path parameters
-        let mut matched = false;
-        let mut final_response = error_response(404, "Not Found".to_string());
-        
-        for route in routes_guard.iter() {
-            // Check if this route has path parameters (contains ':')
-            if route.path.contains(':') && route.method == method {
-                if let Ok(pattern) = RoutePattern::new(&route.path) {
-                    if let Some(params) = pattern.matches(&poh_request.path) {
-                        // Found a match! Create request with path params
-                        let mut req_with_params = poh_request.clone();
-                        req_with_params.path_params = params;
-                        
-                        final_response = match (route.handler)(req_with_params) {
-                            Ok(resp) => resp,
-                            Err(e) => error_response(500, format!("Handler error: {}", e)),
-                        };
-                        matched = true;
-                        break;
-                    }
-                }
-            }
-        }
-        
-        final_response
+        },
+        None => static_file(site, &poh_request).unwrap_or_else(|| error_response(404, "Not Found".to_string())),
     };
+    if let Err(e) = apply_response_headers(&mut response) {
+        response = error_response(500, format!("Handler error: {}", e));
+    }
+    if not_modified(&poh_request, &response) {
+        // The client's copy is current: only the headers go back
+        response.status = 304;
+        response.body.clear();
+    }
+    response
+}
 
-    // Build tiny_http response
-    let status_code = StatusCode::from(response.status);
-    let mut tiny_response = Response::from_string(response.body);
+/// The file a GET or HEAD request asks for, when the server serves static
+/// files and has it
+fn static_file(site: &Site, request: &HttpRequest) -> Option<HttpResponse> {
+    let dir = site.static_dir.as_ref()?;
+    if request.method != "GET" && request.method != "HEAD" {
+        return None;
+    }
+    serve_static_file(dir, &request.path)
+}
 
-    // Add headers
-    for (key, value) in response.headers {
-        if let Ok(header) = Header::from_bytes(key.as_bytes(), value.as_bytes()) {
-            tiny_response = tiny_response.with_header(header);
-        }
+/// Whether `If-None-Match` says the client already has this response: it
+/// lists the response's ETag, or is `*` and there is something to send.
+/// Only GET and HEAD requests for successful responses are answered 304.
+fn not_modified(request: &HttpRequest, response: &HttpResponse) -> bool {
+    if !matches!(request.method.as_str(), "GET" | "HEAD") || !(200..300).contains(&response.status) {
+        return false;
     }
+    let Some(if_none_match) = request.header("If-None-Match") else {
+        return false;
+    };
+    if if_none_match.trim() == "*" {
+        return true;
+    }
+    let etag = response
+        .headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("ETag"))
+        .map(|(_, v)| v.as_str());
+    etag.is_some_and(|etag| etag_listed(if_none_match, etag))
+}
+
+/// Whether the comma-separated ETags of `list` include `etag`. Comparison
+/// is weak, as `If-None-Match` asks: `W/"a"` and `"a"` are the same tag.
+fn etag_listed(list: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| {
+        let tag = tag.trim();
+        tag.strip_prefix("W/").unwrap_or(tag).to_string()
+    };
+    let etag = opaque(etag);
+    list.split(',').any(|tag| opaque(tag) == etag)
+}
 
-    tiny_response = tiny_response.with_status_code(status_code);
+/// A weak ETag for `content`: its length and a 64-bit FNV-1a hash, which
+/// stays the same across runs so clients can keep their copies
+pub fn weak_etag(content: &[u8]) -> String {
+    let hash = content
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100_0000_01b3));
+    format!("W/\"{:x}-{:016x}\"", content.len(), hash)
+}
 
-    // Send response
-    request
-        .respond(tiny_response)
-        .map_err(|e| anyhow!("Failed to send response: {}", e))?;
+/// Seconds until the client may try again, when it is over the limit
+fn over_rate_limit(rate_limit: &RateLimit, request: &HttpRequest) -> Option<u64> {
+    // The key function runs program code, so it is called without the lock
+    let key_by = rate_limit.lock().unwrap().as_ref()?.key_by();
+    let key = RateLimiter::key_for(key_by.as_ref(), request);
+    let mut guard = rate_limit.lock().unwrap();
+    guard.as_mut()?.check(key, Instant::now()).err()
+}
 
-    Ok(())
+/// Write a response; text without a `Content-Type` is sent as plain text
+fn send_response(
+    conn: &mut Connection,
+    response: HttpResponse,
+    head_only: bool,
+    keep_alive: bool,
+) -> std::io::Result<()> {
+    let mut headers: Vec<(String, String)> = response.headers.into_iter().collect();
+    if !headers.iter().any(|(k, _)| k.eq_ignore_ascii_case("Content-Type")) {
+        headers.push(("Content-Type".to_string(), "text/plain; charset=UTF-8".to_string()));
+    }
+    conn.respond(response.status, &headers, response.body.as_bytes(), head_only, keep_alive)
 }
 
 /// Parses query string from URL
@@ -410,6 +799,79 @@ pub fn error_response(status: u16, message: String) -> HttpResponse {
     }
 }
 
+/// Helper function to create a plain text response
+pub fn text_response(text: String) -> HttpResponse {
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type".to_string(), "text/plain; charset=utf-8".to_string());
+
+    HttpResponse {
+        status: 200,
+        headers,
+        body: text,
+    }
+}
+
+/// The media types each answer of `preferred response type of request`
+/// stands for, in the order ties are broken
+const RESPONSE_TYPES: [(&str, &[&str]); 3] = [
+    ("html", &["text/html", "application/xhtml+xml"]),
+    ("json", &["application/json"]),
+    ("text", &["text/plain"]),
+];
+
+/// What the client would rather have back going by its `Accept` header:
+/// "html", "json" or "text". Each takes the q-value of the most specific
+/// range that covers it; the highest q wins, then the more specific range,
+/// then the order of `RESPONSE_TYPES`. A missing header, or one with nothing
+/// readable in it, is taken to be a browser's, so html.
+pub fn preferred_response_type(accept: Option<&str>) -> &'static str {
+    let ranges: Vec<(String, f64)> = accept.unwrap_or_default().split(',').filter_map(media_range).collect();
+    let mut best: Option<(&str, f64, u8)> = None;
+    for (name, types) in RESPONSE_TYPES {
+        let covers = |range: &str| {
+            if range == "*/*" {
+                Some(0)
+            } else if types.contains(&range) || (name == "json" && range.ends_with("+json")) {
+                Some(2)
+            } else {
+                let main = range.strip_suffix("/*")?;
+                types.iter().any(|t| t.split('/').next() == Some(main)).then_some(1)
+            }
+        };
+        let Some((specificity, q)) = ranges
+            .iter()
+            .filter_map(|(range, q)| Some((covers(range)?, *q)))
+            .max_by_key(|(specificity, _)| *specificity)
+        else {
+            continue;
+        };
+        if q > 0.0 && best.is_none_or(|(_, best_q, best_specificity)| (q, specificity) > (best_q, best_specificity)) {
+            best = Some((name, q, specificity));
+        }
+    }
+    best.map_or("html", |(name, ..)| name)
+}
+
+/// One entry of an Accept header as its lowercased media range and q-value;
+/// None if it cannot be read
+fn media_range(entry: &str) -> Option<(String, f64)> {
+    let mut parts = entry.split(';');
+    let range = parts.next()?.trim().to_ascii_lowercase();
+    let (main, sub) = range.split_once('/')?;
+    if main.is_empty() || sub.is_empty() || (main == "*" && sub != "*") {
+        return None;
+    }
+    let mut q = 1.0;
+    for param in parts {
+        if let Some((key, value)) = param.split_once('=') {
+            if key.trim().eq_ignore_ascii_case("q") {
+                q = value.trim().parse().ok().filter(|q| (0.0..=1.0).contains(q))?;
+            }
+        }
+    }
+    Some((range, q))
+}
+
 /// Converts HttpRequest to JSON for PohLang
 pub fn request_to_json(request: &HttpRequest) -> JsonValue {
     json!({
@@ -472,6 +934,7 @@ pub fn serve_static_file(root_dir: &Path, request_path: &str) -> Option<HttpResp
             let mime_type = guess_mime_type(&file_path);
             let mut headers = HashMap::new();
             headers.insert("Content-Type".to_string(), mime_type);
+            headers.insert("ETag".to_string(), weak_etag(&content));
 
             // Add cache control for static assets
             if is_cacheable_asset(&file_path) {
@@ -563,4 +1026,168 @@ mod tests {
         );
         assert!(response.body.contains("success"));
     }
+
+    #[test]
+    fn test_if_none_match_lists_and_star() {
+        let request = |method: &str, if_none_match: Option<&str>| HttpRequest {
+            method: method.to_string(),
+            path: "/".to_string(),
+            query: HashMap::new(),
+            headers: if_none_match
+                .map(|v| HashMap::from([("if-none-match".to_string(), v.to_string())]))
+                .unwrap_or_default(),
+            body: String::new(),
+            path_params: HashMap::new(),
+            remote_addr: String::new(),
+        };
+        let mut response = text_response("hello".to_string());
+        let etag = weak_etag(response.body.as_bytes());
+        assert_eq!(etag, weak_etag(b"hello"));
+        assert_ne!(etag, weak_etag(b"hellp"));
+        assert!(etag.starts_with("W/\"5-"), "{}", etag);
+        response.headers.insert("ETag".to_string(), etag.clone());
+
+        assert!(not_modified(&request("GET", Some(&etag)), &response));
+        assert!(not_modified(&request("HEAD", Some(&etag)), &response));
+        // Any of several tags, and the strong form of a weak one
+        let strong = etag.trim_start_matches("W/");
+        assert!(not_modified(&request("GET", Some(&format!("\"other\", {}", strong))), &response));
+        assert!(not_modified(&request("GET", Some("*")), &response));
+        assert!(!not_modified(&request("GET", Some("\"other\", W/\"x\"")), &response));
+        assert!(!not_modified(&request("GET", None), &response));
+        assert!(!not_modified(&request("POST", Some(&etag)), &response));
+        response.status = 404;
+        assert!(!not_modified(&request("GET", Some("*")), &response));
+    }
+
+    #[test]
+    fn test_static_files_have_etags() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.html"), "<h1>Hi</h1>").unwrap();
+        fs::write(dir.path().join("app.css"), "body {}").unwrap();
+        let index = serve_static_file(dir.path(), "/").unwrap();
+        assert_eq!(index.body, "<h1>Hi</h1>");
+        assert_eq!(index.headers["ETag"], weak_etag(b"<h1>Hi</h1>"));
+        let css = serve_static_file(dir.path(), "/app.css").unwrap();
+        assert_eq!(css.headers["Cache-Control"], "public, max-age=3600");
+        assert!(serve_static_file(dir.path(), "/missing.js").is_none());
+        assert_eq!(serve_static_file(dir.path(), "/../secret").unwrap().status, 403);
+    }
+
+    #[test]
+    fn test_connections_past_the_limit_are_turned_away() {
+        use std::io::{Read, Write};
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, connections) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                sender.send(stream.unwrap()).unwrap();
+            }
+        });
+        let site = Site {
+            routes: Arc::new(Mutex::new(Vec::new())),
+            static_dir: None,
+            tls: None,
+        };
+        let limits = Limits {
+            connections: 1,
+            ..Limits::default()
+        };
+        let rate_limit: RateLimit = Arc::new(Mutex::new(None));
+        thread::spawn(move || serve(connections, site, rate_limit, limits, Arc::new(Metrics::default())));
+        let get = || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            stream.write_all(b"GET / HTTP/1.1\r\nHost: h\r\nConnection: close\r\n\r\n").unwrap();
+            let mut response = String::new();
+            let _ = stream.read_to_string(&mut response);
+            response
+        };
+
+        // An idle client holds the only place
+        let idle = TcpStream::connect(addr).unwrap();
+        let mut refused = TcpStream::connect(addr).unwrap();
+        refused.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let mut response = String::new();
+        refused.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"), "{}", response);
+        // Once it hangs up, the next client is served
+        drop(idle);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let response = loop {
+            let response = get();
+            if response.starts_with("HTTP/1.1 404") || Instant::now() > deadline {
+                break response;
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"), "{}", response);
+    }
+
+    #[test]
+    fn test_rate_limit_keys_are_found_without_the_lock() {
+        let rate_limit: RateLimit = Arc::new(Mutex::new(None));
+        let shared = Arc::clone(&rate_limit);
+        let key_by: crate::stdlib::rate_limit::KeyFn = Arc::new(move |_: &HttpRequest| {
+            // What the program's key function does may reach the server again
+            Some(if shared.try_lock().is_ok() { "unlocked" } else { "locked" }.to_string())
+        });
+        *rate_limit.lock().unwrap() = Some(RateLimiter::new(1, Duration::from_secs(60), Some(key_by)));
+        let request = HttpRequest {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            query: HashMap::new(),
+            headers: HashMap::new(),
+            body: String::new(),
+            path_params: HashMap::new(),
+            remote_addr: "127.0.0.1:5000".to_string(),
+        };
+        assert_eq!(over_rate_limit(&rate_limit, &request), None);
+        assert!(over_rate_limit(&rate_limit, &request).is_some());
+        let mut limiter = rate_limit.lock().unwrap().take().unwrap();
+        // Both requests were counted under the key found while unlocked
+        assert!(limiter.check("unlocked".to_string(), Instant::now()).is_err());
+        assert!(limiter.check("locked".to_string(), Instant::now()).is_ok());
+    }
+
+    #[test]
+    fn test_response_headers_cannot_contain_line_breaks() {
+        set_response_header("X-Feed".to_string(), "prices".to_string()).unwrap();
+        assert!(set_response_header("X-Feed".to_string(), "a\r\nSet-Cookie: x=1".to_string()).is_err());
+        assert!(set_response_header("X-Feed\nSet-Cookie".to_string(), "x=1".to_string()).is_err());
+        let mut response = text_response("hello".to_string());
+        apply_response_headers(&mut response).unwrap();
+        assert_eq!(response.headers["X-Feed"], "prices");
+
+        RESPONSE_HEADERS.with(|h| h.borrow_mut().push(("X-Feed".to_string(), "a\rb".to_string())));
+        assert!(apply_response_headers(&mut response).is_err());
+    }
+
+    #[test]
+    fn test_preferred_response_type() {
+        let preferred = |accept| preferred_response_type(Some(accept));
+        // Browsers, curl and fetch
+        assert_eq!(
+            preferred("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+            "html"
+        );
+        assert_eq!(preferred("*/*"), "html");
+        assert_eq!(preferred_response_type(None), "html");
+        // The named type beats a wildcard of the same q
+        assert_eq!(preferred("application/json"), "json");
+        assert_eq!(preferred("*/*, application/json"), "json");
+        assert_eq!(preferred("application/problem+json"), "json");
+        assert_eq!(preferred("text/plain"), "text");
+        assert_eq!(preferred("text/*"), "html");
+        // q-values order the types, whatever order they are listed in
+        assert_eq!(preferred("text/html;q=0.5, application/json;q=0.9"), "json");
+        assert_eq!(preferred("text/plain; q=1, application/json; q=0.2"), "text");
+        assert_eq!(preferred("*/*;q=0.1, text/html;q=0"), "json");
+        // What cannot be read is left out
+        assert_eq!(preferred("application/json;q=high, text/plain"), "text");
+        assert_eq!(preferred("nonsense, ;;, */json"), "html");
+        assert_eq!(preferred(""), "html");
+        assert_eq!(preferred("image/png"), "html");
+    }
 }
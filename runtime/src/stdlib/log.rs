@@ -0,0 +1,144 @@
+//! Logging for PohLang programs
+//! `Log info/warn/error` lines go to stderr, and to a file once
+//! `Log to file` has set one up. The file can rotate: past a size limit it
+//! becomes `app.log.1`, older ones shift up to `app.log.5`, and a fresh
+//! `app.log` is started.
+
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many rotated files are kept next to the live one
+pub const KEEP_ROTATED: usize = 5;
+
+/// A log file, appended to and rotated by size
+pub struct LogFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    size: u64,
+    rotate_at: Option<u64>,
+}
+
+impl LogFile {
+    /// Open `path` for appending, creating it if needed
+    pub fn open(path: impl Into<PathBuf>, rotate_at: Option<u64>) -> Result<Self> {
+        let path = path.into();
+        let file = open_append(&path)?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(LogFile {
+            path,
+            writer: BufWriter::new(file),
+            size,
+            rotate_at,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one line, rotating first if it would take the file past the
+    /// limit. Lines are flushed as they are written.
+    pub fn write_line(&mut self, line: &str) -> Result<()> {
+        let len = line.len() as u64 + 1;
+        if let Some(limit) = self.rotate_at {
+            if self.size > 0 && self.size + len > limit {
+                self.rotate()?;
+            }
+        }
+        writeln!(self.writer, "{}", line)
+            .and_then(|_| self.writer.flush())
+            .with_context(|| format!("Failed to write log file '{}'", self.path.display()))?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        let _ = fs::remove_file(rotated(KEEP_ROTATED));
+        for n in (1..KEEP_ROTATED).rev() {
+            if rotated(n).exists() {
+                fs::rename(rotated(n), rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated(1))
+            .with_context(|| format!("Failed to rotate log file '{}'", self.path.display()))?;
+        self.writer = BufWriter::new(open_append(&self.path)?);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log file '{}'", path.display()))
+}
+
+/// `2026-10-14T09:30:00Z INFO message`
+pub fn format_line(level: &str, message: &str, at: SystemTime) -> String {
+    format!("{} {} {}", utc_timestamp(at), level, message)
+}
+
+/// Seconds-precision UTC time in ISO 8601 form
+pub fn utc_timestamp(at: SystemTime) -> String {
+    let secs = at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3_600,
+        rest % 3_600 / 60,
+        rest % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_utc_timestamp() {
+        assert_eq!(utc_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let at = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(utc_timestamp(at), "2024-02-29T12:34:56Z");
+    }
+
+    #[test]
+    fn test_rotation_keeps_five_files() {
+        let dir = std::env::temp_dir().join(format!(
+            "pohlang_log_rotation_{}",
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        let mut log = LogFile::open(&path, Some(10)).unwrap();
+        for n in 0..8 {
+            log.write_line(&format!("line {}", n)).unwrap();
+        }
+        let read = |p: PathBuf| fs::read_to_string(p).unwrap();
+        assert_eq!(read(path.clone()), "line 7\n");
+        assert_eq!(read(dir.join("app.log.1")), "line 6\n");
+        assert_eq!(read(dir.join("app.log.5")), "line 2\n");
+        assert!(!dir.join("app.log.6").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
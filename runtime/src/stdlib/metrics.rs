@@ -0,0 +1,255 @@
+//! `Enable metrics on server at "/metrics"`: what a web server has served,
+//! as JSON or in the Prometheus text format.
+//!
+//! Every server keeps these counts whether or not the endpoint is enabled;
+//! each request costs a few atomic adds. Latency percentiles come from the
+//! last `RECENT` requests, kept in a ring that is only sorted when the
+//! metrics are read.
+
+use serde_json::{json, Map, Value as JsonValue};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use super::http::{json_response, HttpRequest, HttpResponse};
+use crate::vm::ExecutionStats;
+
+/// How many of the latest request latencies the percentiles are taken over
+const RECENT: usize = 1024;
+
+const CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+/// Counts kept by a web server as it answers requests
+#[derive(Debug)]
+pub struct Metrics {
+    started: OnceLock<Instant>,
+    requests: AtomicU64,
+    by_class: [AtomicU64; 5],
+    active_connections: AtomicUsize,
+    /// Requests whose latency was recorded, and the sum of those latencies
+    timed: AtomicU64,
+    total_micros: AtomicU64,
+    /// Microseconds; slot `n % RECENT` holds the latency of timed request `n`
+    recent: Box<[AtomicU64]>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            started: OnceLock::new(),
+            requests: AtomicU64::new(0),
+            by_class: Default::default(),
+            active_connections: AtomicUsize::new(0),
+            timed: AtomicU64::new(0),
+            total_micros: AtomicU64::new(0),
+            recent: (0..RECENT).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+}
+
+/// Counts a connection as active until it is dropped
+pub struct ActiveConnection<'a>(&'a Metrics);
+
+impl Drop for ActiveConnection<'_> {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// Uptime counts from the first call
+    pub fn start(&self) {
+        self.started.get_or_init(Instant::now);
+    }
+
+    pub fn connection(&self) -> ActiveConnection<'_> {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ActiveConnection(self)
+    }
+
+    /// Count an answered request; `latency` is `None` for event streams and
+    /// websockets, which stay open for as long as the client wants
+    pub fn record(&self, status: u16, latency: Option<Duration>) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if let Some(class) = (status / 100).checked_sub(1).and_then(|c| self.by_class.get(c as usize)) {
+            class.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(latency) = latency {
+            let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+            let n = self.timed.fetch_add(1, Ordering::Relaxed);
+            self.total_micros.fetch_add(micros, Ordering::Relaxed);
+            self.recent[n as usize % RECENT].store(micros, Ordering::Relaxed);
+        }
+    }
+
+    fn uptime(&self) -> f64 {
+        self.started.get().map_or(0.0, |t| t.elapsed().as_secs_f64())
+    }
+
+    /// Average latency over every timed request, then the 50th, 90th and
+    /// 99th percentiles of the recent ones, in seconds
+    fn latency(&self) -> (f64, [(f64, f64); 3]) {
+        let timed = self.timed.load(Ordering::Relaxed);
+        let mut recent: Vec<u64> = self.recent[..(timed as usize).min(RECENT)]
+            .iter()
+            .map(|slot| slot.load(Ordering::Relaxed))
+            .collect();
+        recent.sort_unstable();
+        let seconds = |micros: u64| micros as f64 / 1e6;
+        let percentile = |p: f64| match recent.len() {
+            0 => 0.0,
+            n => seconds(recent[((n as f64 * p).ceil() as usize).clamp(1, n) - 1]),
+        };
+        let average = match timed {
+            0 => 0.0,
+            n => seconds(self.total_micros.load(Ordering::Relaxed)) / n as f64,
+        };
+        (average, [0.5, 0.9, 0.99].map(|p| (p, percentile(p))))
+    }
+
+    /// The answer of the metrics route: the Prometheus format for a client
+    /// that asks for text, as Prometheus does, or `?format=prometheus`;
+    /// JSON otherwise
+    pub fn response(&self, request: &HttpRequest, execution: &ExecutionStats) -> HttpResponse {
+        let accept = request.header("Accept").unwrap_or_default();
+        let wants_text = accept.contains("text/plain") || accept.contains("openmetrics");
+        if !wants_text && request.query.get("format").map(String::as_str) != Some("prometheus") {
+            return json_response(self.json(execution));
+        }
+        let mut response = HttpResponse {
+            body: self.prometheus(execution),
+            ..HttpResponse::default()
+        };
+        response
+            .headers
+            .insert("Content-Type".to_string(), "text/plain; version=0.0.4; charset=utf-8".to_string());
+        response
+    }
+
+    pub fn json(&self, execution: &ExecutionStats) -> JsonValue {
+        let by_status: Map<String, JsonValue> = CLASSES
+            .iter()
+            .zip(&self.by_class)
+            .map(|(class, n)| (class.to_string(), json!(n.load(Ordering::Relaxed))))
+            .collect();
+        let (average, [p50, p90, p99]) = self.latency();
+        let ms = |secs: f64| secs * 1000.0;
+        json!({
+            "uptime_seconds": self.uptime(),
+            "requests_total": self.requests.load(Ordering::Relaxed),
+            "requests_by_status": by_status,
+            "active_connections": self.active_connections.load(Ordering::Relaxed),
+            "latency_ms": {
+                "average": ms(average),
+                "p50": ms(p50.1),
+                "p90": ms(p90.1),
+                "p99": ms(p99.1),
+            },
+            "execution": {
+                "statements_executed": execution.statements_executed,
+                "function_calls": execution.function_calls,
+                "builtin_calls": execution.builtin_calls,
+            },
+        })
+    }
+
+    /// The same figures in the Prometheus text exposition format
+    pub fn prometheus(&self, execution: &ExecutionStats) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            let _ = writeln!(out, "# HELP pohlang_{} {}\n# TYPE pohlang_{} {}", name, help, name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "pohlang_{}{} {}", name, labels, value);
+            }
+        };
+        let one = |value: String| [(String::new(), value)];
+        metric("uptime_seconds", "gauge", "Seconds since the server started", &one(self.uptime().to_string()));
+        let by_status: Vec<(String, String)> = CLASSES
+            .iter()
+            .zip(&self.by_class)
+            .map(|(class, n)| (format!("{{class=\"{}\"}}", class), n.load(Ordering::Relaxed).to_string()))
+            .collect();
+        metric("requests_total", "counter", "Requests answered, by status class", &by_status);
+        metric(
+            "active_connections",
+            "gauge",
+            "Connections open now",
+            &one(self.active_connections.load(Ordering::Relaxed).to_string()),
+        );
+        let (_, quantiles) = self.latency();
+        let mut latency: Vec<(String, String)> = quantiles
+            .iter()
+            .map(|(p, secs)| (format!("{{quantile=\"{}\"}}", p), secs.to_string()))
+            .collect();
+        let total = self.total_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        latency.push(("_sum".to_string(), total.to_string()));
+        latency.push(("_count".to_string(), self.timed.load(Ordering::Relaxed).to_string()));
+        metric("request_duration_seconds", "summary", "Time to answer a request", &latency);
+        metric(
+            "statements_executed_total",
+            "counter",
+            "Statements run by the program and its route handlers",
+            &one(execution.statements_executed.to_string()),
+        );
+        metric(
+            "function_calls_total",
+            "counter",
+            "Calls of user-defined functions",
+            &one(execution.function_calls.to_string()),
+        );
+        let mut builtins: Vec<_> = execution.builtin_calls.iter().collect();
+        builtins.sort();
+        let builtins: Vec<(String, String)> = builtins
+            .into_iter()
+            .map(|(name, n)| (format!("{{name=\"{}\"}}", name), n.to_string()))
+            .collect();
+        metric("builtin_calls_total", "counter", "Calls of built-in functions, by name", &builtins);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests_are_counted_by_class_with_percentiles() {
+        let metrics = Metrics::default();
+        for ms in 1..=10 {
+            metrics.record(200, Some(Duration::from_millis(ms)));
+        }
+        metrics.record(404, Some(Duration::from_millis(100)));
+        metrics.record(101, None);
+        let connection = metrics.connection();
+        let json = metrics.json(&ExecutionStats::default());
+        assert_eq!(json["requests_total"], 12);
+        assert_eq!(json["requests_by_status"]["2xx"], 10);
+        assert_eq!(json["requests_by_status"]["4xx"], 1);
+        assert_eq!(json["requests_by_status"]["1xx"], 1);
+        assert_eq!(json["active_connections"], 1);
+        assert_eq!(json["latency_ms"]["p50"], 6.0);
+        assert_eq!(json["latency_ms"]["p99"], 100.0);
+        drop(connection);
+
+        let text = metrics.prometheus(&ExecutionStats::default());
+        assert!(text.contains("pohlang_requests_total{class=\"2xx\"} 10\n"), "{}", text);
+        assert!(text.contains("pohlang_active_connections 0\n"), "{}", text);
+        assert!(text.contains("pohlang_request_duration_seconds_count 11\n"), "{}", text);
+        assert!(text.contains("# TYPE pohlang_request_duration_seconds summary\n"), "{}", text);
+    }
+
+    #[test]
+    fn test_percentiles_cover_only_recent_requests() {
+        let metrics = Metrics::default();
+        for _ in 0..RECENT {
+            metrics.record(200, Some(Duration::from_secs(1)));
+        }
+        for _ in 0..RECENT {
+            metrics.record(200, Some(Duration::from_millis(2)));
+        }
+        let (average, [(_, p50), _, (_, p99)]) = metrics.latency();
+        assert_eq!((p50, p99), (0.002, 0.002));
+        assert!((average - 0.501).abs() < 1e-9, "{}", average);
+    }
+}
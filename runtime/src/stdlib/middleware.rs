@@ -1,7 +1,7 @@
-/// Middleware system for PohLang web framework
-/// Provides request/response pipeline with before/after hooks
+//! Middleware system for PohLang web framework
+//! Provides request/response pipeline with before/after hooks
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
@@ -109,7 +109,7 @@ impl Default for MiddlewareChain {
     }
 }
 
-/// Built-in middleware functions
+// ---- Built-in middleware functions ----
 
 /// CORS middleware - adds CORS headers
 pub fn cors_middleware(
@@ -199,7 +199,7 @@ impl RateLimiter {
             let now = Instant::now();
             
             // Get or create request history
-            let history = requests_map.entry(client_ip.clone()).or_insert_with(Vec::new);
+            let history = requests_map.entry(client_ip.clone()).or_default();
             
             // Remove old requests outside window
             history.retain(|&time| now.duration_since(time).as_secs() < window_secs);
@@ -284,8 +284,11 @@ mod tests {
         let mut req = HttpRequest {
             method: "GET".to_string(),
             path: "/test".to_string(),
+            query: HashMap::new(),
             headers: HashMap::new(),
             body: String::new(),
+            path_params: HashMap::new(),
+            remote_addr: String::new(),
         };
         
         let mut ctx = MiddlewareContext::new();
@@ -316,8 +319,11 @@ mod tests {
         let mut req = HttpRequest {
             method: "GET".to_string(),
             path: "/test".to_string(),
+            query: HashMap::new(),
             headers: HashMap::new(),
             body: String::new(),
+            path_params: HashMap::new(),
+            remote_addr: String::new(),
         };
         
         let mut ctx = MiddlewareContext::new();
@@ -335,8 +341,11 @@ mod tests {
         let mut req = HttpRequest {
             method: "GET".to_string(),
             path: "/test".to_string(),
+            query: HashMap::new(),
             headers: HashMap::new(),
             body: String::new(),
+            path_params: HashMap::new(),
+            remote_addr: String::new(),
         };
         
         let mut ctx = MiddlewareContext::new();
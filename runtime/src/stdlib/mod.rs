@@ -1,13 +1,40 @@
 // Standard Library Modules
 // This module contains all standard library functionality for PohLang
+// The web server and what it serves are behind the `web` feature
 
+pub mod collections;
+#[cfg(feature = "web")]
+pub mod connection;
 pub mod errors;
+#[cfg(feature = "web")]
+pub mod event_stream;
 pub mod file;
+#[cfg(feature = "web")]
 pub mod http;
+#[cfg(feature = "web")]
 pub mod livereload;
+pub mod log;
+#[cfg(feature = "web")]
+pub mod metrics;
+#[cfg(feature = "web")]
 pub mod middleware;
 pub mod network;
+#[cfg(feature = "web")]
+pub mod rate_limit;
+#[cfg(feature = "web")]
 pub mod router;
+pub mod shutdown;
+#[cfg(feature = "web")]
+pub mod socket;
+#[cfg(feature = "web")]
 pub mod static_files;
+#[cfg(feature = "web")]
 pub mod template;
+pub mod text;
+#[cfg(feature = "web")]
+pub mod tls;
+#[cfg(feature = "web")]
 pub mod upload;
+pub mod url;
+#[cfg(feature = "web")]
+pub mod websocket;
@@ -11,7 +11,7 @@ use serde_json::{Map, Value as JsonValue};
 /// * `Err` - If the JSON is invalid
 ///
 /// # Example
-/// ```
+/// ```ignore
 /// let json = parse_json(r#"{"name": "Alice", "age": 25}"#)?;
 /// ```
 pub fn parse_json(json_str: &str) -> Result<JsonValue> {
@@ -28,7 +28,7 @@ pub fn parse_json(json_str: &str) -> Result<JsonValue> {
 /// * `Err` - If serialization fails
 ///
 /// # Example
-/// ```
+/// ```ignore
 /// let json_str = json_stringify(&json_value)?;
 /// ```
 pub fn json_stringify(value: &JsonValue) -> Result<String> {
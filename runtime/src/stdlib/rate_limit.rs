@@ -0,0 +1,105 @@
+//! Rate limiting for the PohLang web server
+//! Counts requests per key (the client address unless the program groups
+//! them some other way) in fixed windows; over the limit, the server answers
+//! 429 Too Many Requests with a Retry-After header
+
+use super::http::HttpRequest;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Picks the key a request is counted under; `None` falls back to the
+/// client address
+pub type KeyFn = Arc<dyn Fn(&HttpRequest) -> Option<String> + Send + Sync>;
+
+/// Windows are dropped once this many keys are tracked and they have ended
+const PRUNE_AT: usize = 10_000;
+
+pub struct RateLimiter {
+    max_requests: u64,
+    window: Duration,
+    key_by: Option<KeyFn>,
+    /// key → requests counted since the window started, and when it started
+    rate_limiter: HashMap<String, (u64, Instant)>,
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("max_requests", &self.max_requests)
+            .field("window", &self.window)
+            .field("grouped", &self.key_by.is_some())
+            .field("keys", &self.rate_limiter.len())
+            .finish()
+    }
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: u64, window: Duration, key_by: Option<KeyFn>) -> Self {
+        Self {
+            max_requests,
+            window,
+            key_by,
+            rate_limiter: HashMap::new(),
+        }
+    }
+
+    /// The function that groups requests, for calling once the limiter is
+    /// no longer locked
+    pub fn key_by(&self) -> Option<KeyFn> {
+        self.key_by.clone()
+    }
+
+    /// The key a request is counted under: what `key_by` gives, or the
+    /// client's address
+    pub fn key_for(key_by: Option<&KeyFn>, request: &HttpRequest) -> String {
+        key_by
+            .and_then(|key_by| key_by(request))
+            .unwrap_or_else(|| request.remote_addr.clone())
+    }
+
+    /// Count a request under `key`. Over the limit, returns how many whole
+    /// seconds remain until the key's window ends.
+    pub fn check(&mut self, key: String, now: Instant) -> Result<(), u64> {
+        if self.rate_limiter.len() >= PRUNE_AT {
+            let window = self.window;
+            self.rate_limiter
+                .retain(|_, (_, start)| now.duration_since(*start) < window);
+        }
+        let (count, start) = self.rate_limiter.entry(key).or_insert((0, now));
+        let elapsed = now.duration_since(*start);
+        if elapsed >= self.window {
+            *count = 0;
+            *start = now;
+        } else if *count >= self.max_requests {
+            let left = self.window - elapsed;
+            let secs = left.as_secs() + u64::from(left.subsec_nanos() > 0);
+            return Err(secs.max(1));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_resets_when_the_window_ends() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(10), None);
+        let start = Instant::now();
+        assert_eq!(limiter.check("a".to_string(), start), Ok(()));
+        assert_eq!(limiter.check("a".to_string(), start), Ok(()));
+        assert_eq!(
+            limiter.check("a".to_string(), start + Duration::from_millis(2500)),
+            Err(8)
+        );
+        // Other keys have their own windows
+        assert_eq!(limiter.check("b".to_string(), start), Ok(()));
+        assert_eq!(
+            limiter.check("a".to_string(), start + Duration::from_secs(10)),
+            Ok(())
+        );
+    }
+}
@@ -1,5 +1,5 @@
-/// Advanced routing system for PohLang web framework
-/// Supports path parameters, query strings, route groups, and middleware
+//! Advanced routing system for PohLang web framework
+//! Supports path parameters, query strings, route groups, and middleware
 
 use anyhow::{anyhow, Result};
 use regex::Regex;
@@ -30,10 +30,9 @@ impl RoutePattern {
                 regex_pattern.push('/');
             }
             
-            if part.starts_with(':') {
+            if let Some(param_name) = part.strip_prefix(':') {
                 // Path parameter like :id
-                let param_name = part[1..].to_string();
-                param_names.push(param_name);
+                param_names.push(param_name.to_string());
                 regex_pattern.push_str(r"([^/]+)");
             } else if *part == "*" {
                 // Wildcard match
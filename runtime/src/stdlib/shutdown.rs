@@ -0,0 +1,98 @@
+/// Graceful Shutdown Module for PohLang
+///
+/// Catches SIGINT and SIGTERM so a running program can stop cleanly:
+/// - The signal handler only sets a flag; a watcher thread does the work
+/// - Running web servers stop accepting connections and finish in-flight
+///   requests (up to `DRAIN_TIMEOUT`)
+/// - `On shutdown:` hooks then run on the watcher thread, and the process
+///   exits with code 0
+///
+/// The handler is installed with `libc::signal` on Unix. Elsewhere signals
+/// keep their default behaviour and hooks do not run.
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a stopping server waits for in-flight requests
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the watcher and the server loop look at the shutdown flag
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub type ShutdownHook = Box<dyn FnOnce() + Send>;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static ACTIVE_SERVERS: AtomicUsize = AtomicUsize::new(0);
+static HOOKS: Mutex<Vec<ShutdownHook>> = Mutex::new(Vec::new());
+static INSTALL: Once = Once::new();
+
+/// True once SIGINT or SIGTERM has arrived
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Register code to run when the process is asked to stop
+pub fn add_hook(hook: ShutdownHook) {
+    install();
+    HOOKS.lock().unwrap().push(hook);
+}
+
+/// Marks a web server as running until the guard is dropped; shutdown waits
+/// for every server to finish draining before running hooks
+pub fn serving() -> ServerGuard {
+    install();
+    ACTIVE_SERVERS.fetch_add(1, Ordering::SeqCst);
+    ServerGuard(())
+}
+
+pub struct ServerGuard(());
+
+impl Drop for ServerGuard {
+    fn drop(&mut self) {
+        ACTIVE_SERVERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Install the signal handlers and the watcher thread, once per process
+fn install() {
+    INSTALL.call_once(|| {
+        install_signal_handlers();
+        thread::spawn(watch);
+    });
+}
+
+fn watch() {
+    while !requested() {
+        thread::sleep(POLL_INTERVAL);
+    }
+    // Servers drain for at most DRAIN_TIMEOUT; allow a little slack on top
+    let deadline = Instant::now() + DRAIN_TIMEOUT + Duration::from_secs(1);
+    while ACTIVE_SERVERS.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        thread::sleep(POLL_INTERVAL);
+    }
+    let hooks = std::mem::take(&mut *HOOKS.lock().unwrap());
+    for hook in hooks {
+        hook();
+    }
+    std::process::exit(0);
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(_: libc::c_int) {
+    // Only async-signal-safe work here: set the flag and return
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+fn install_signal_handlers() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: `on_signal` only performs an atomic store
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+#[cfg(not(unix))]
+fn install_signal_handlers() {}
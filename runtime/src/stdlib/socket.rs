@@ -0,0 +1,90 @@
+//! The client side of a server connection: a plain TCP stream, or with the
+//! `tls` feature a stream whose bytes go through a TLS session.
+//!
+//! Copies of a socket share the connection, the way `TcpStream::try_clone`
+//! does, so an event stream or a websocket can read on one thread and write
+//! on another.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::time::Duration;
+
+#[cfg(feature = "tls")]
+use super::tls::TlsSocket;
+
+#[derive(Debug)]
+pub enum Socket {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(TlsSocket),
+}
+
+impl From<TcpStream> for Socket {
+    fn from(stream: TcpStream) -> Self {
+        Socket::Plain(stream)
+    }
+}
+
+impl Socket {
+    /// Another handle on the same connection
+    pub fn try_clone(&self) -> io::Result<Socket> {
+        match self {
+            Socket::Plain(stream) => stream.try_clone().map(Socket::Plain),
+            #[cfg(feature = "tls")]
+            Socket::Tls(tls) => tls.try_clone().map(Socket::Tls),
+        }
+    }
+
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Socket::Plain(stream) => stream,
+            #[cfg(feature = "tls")]
+            Socket::Tls(tls) => tls.tcp(),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp().set_read_timeout(timeout)
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp().set_write_timeout(timeout)
+    }
+
+    /// Close the connection; a TLS session tells the client first
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        #[cfg(feature = "tls")]
+        if let Socket::Tls(tls) = self {
+            tls.close_notify();
+        }
+        self.tcp().shutdown(how)
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Socket::Plain(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Socket::Tls(tls) => tls.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Socket::Tls(tls) => tls.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Socket::Tls(tls) => tls.flush(),
+        }
+    }
+}
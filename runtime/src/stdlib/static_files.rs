@@ -1,7 +1,7 @@
-/// Static file serving for PohLang web framework
-/// Handles serving files with proper MIME types, caching, and security
+//! Static file serving for PohLang web framework
+//! Handles serving files with proper MIME types, caching, and security
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::Read;
@@ -235,20 +235,18 @@ impl StaticFileServer {
         html.push_str("<style>body{font-family:monospace;padding:20px;}a{display:block;padding:5px;}</style>");
         html.push_str("</head><body><h1>Directory Listing</h1><ul>");
         
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let name = entry.file_name().to_string_lossy().to_string();
-                let is_dir = entry.path().is_dir();
-                let display_name = if is_dir {
-                    format!("{}/", name)
-                } else {
-                    name.clone()
-                };
-                html.push_str(&format!(
-                    "<li><a href=\"{}\">{}</a></li>",
-                    name, display_name
-                ));
-            }
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let is_dir = entry.path().is_dir();
+            let display_name = if is_dir {
+                format!("{}/", name)
+            } else {
+                name.clone()
+            };
+            html.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>",
+                name, display_name
+            ));
         }
         
         html.push_str("</ul></body></html>");
@@ -1,14 +1,111 @@
+//! Simple template engine for HTML templating
+//! Supports {{variable}} syntax for variable substitution
+//!
+//! `render template file` reads templates from the template directory
+//! through a `TemplateCache`, which keeps each file until its modification
+//! time changes or hot reload sees a change.
+//!
+//! # Example Template
+//! ```html
+//! <h1>{{title}}</h1>
+//! <p>{{content}}</p>
+//! ```
+
 use anyhow::{anyhow, Result};
 use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+/// Template files read once and kept, keyed by path and modification time.
+/// Shared by a program and its route handlers.
+#[derive(Debug, Default)]
+pub struct TemplateCache {
+    /// Set by `Set template directory to`; the program's directory otherwise
+    dir: RwLock<Option<PathBuf>>,
+    files: Mutex<HashMap<PathBuf, (SystemTime, Arc<str>)>>,
+    /// Files read from disk, for tests
+    reads: AtomicUsize,
+}
 
-/// Simple template engine for HTML templating
-/// Supports {{variable}} syntax for variable substitution
-///
-/// # Example Template
-/// ```html
-/// <h1>{{title}}</h1>
-/// <p>{{content}}</p>
-/// ```
+impl TemplateCache {
+    pub fn set_dir(&self, dir: PathBuf) {
+        *self.dir.write().unwrap() = Some(dir);
+    }
+
+    /// The template `name` in the template directory, or else in `base`.
+    /// A file is read again only once its modification time has changed;
+    /// it is checked when read, so a broken one is reported at each render.
+    pub fn load(&self, base: &Path, name: &str) -> Result<Arc<str>> {
+        let path = match self.dir.read().unwrap().as_ref() {
+            Some(dir) => dir.join(name),
+            None => base.join(name),
+        };
+        let modified = fs::metadata(&path)
+            .and_then(|m| m.modified())
+            .map_err(|e| anyhow!("Cannot read template {}: {}", path.display(), e))?;
+        if let Some((at, text)) = self.files.lock().unwrap().get(&path) {
+            if *at == modified {
+                return Ok(Arc::clone(text));
+            }
+        }
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        let text = fs::read_to_string(&path).map_err(|e| anyhow!("Cannot read template {}: {}", path.display(), e))?;
+        check(&text).map_err(|e| anyhow!("Template {} {}", path.display(), e))?;
+        let text: Arc<str> = text.into();
+        self.files.lock().unwrap().insert(path, (modified, Arc::clone(&text)));
+        Ok(text)
+    }
+
+    /// Forget every file, for when hot reload sees a change
+    pub fn clear(&self) {
+        self.files.lock().unwrap().clear();
+    }
+
+    pub fn reads(&self) -> usize {
+        self.reads.load(Ordering::Relaxed)
+    }
+}
+
+/// Check that every `{{` is closed and every `{{#each}}` and `{{#if}}`
+/// block is ended; the error gives the line the problem starts on
+pub fn check(template: &str) -> Result<(), String> {
+    let line_of = |at: usize| template[..at].matches('\n').count() + 1;
+    let mut open: Vec<(&str, &str, usize)> = Vec::new();
+    let mut rest = 0;
+    while let Some(found) = template[rest..].find("{{") {
+        let start = rest + found;
+        let Some(len) = template[start + 2..].find("}}") else {
+            return Err(format!("line {}: {{{{ is never closed with }}}}", line_of(start)));
+        };
+        let tag = template[start + 2..start + 2 + len].trim();
+        rest = start + 2 + len + 2;
+        if let Some(block) = ["each", "if"].into_iter().find(|b| tag.starts_with(&format!("#{} ", b))) {
+            open.push((block, tag, line_of(start)));
+        } else if let Some(block) = tag.strip_prefix('/') {
+            match open.pop() {
+                Some((opened, ..)) if opened == block => {}
+                Some((_, tag, line)) => {
+                    return Err(format!(
+                        "line {}: {{{{/{}}}}} ends the {{{{{}}}}} from line {}",
+                        line_of(start),
+                        block,
+                        tag,
+                        line
+                    ))
+                }
+                None => return Err(format!("line {}: {{{{/{}}}}} ends no block", line_of(start), block)),
+            }
+        }
+    }
+    match open.pop() {
+        Some((block, tag, line)) => Err(format!("line {}: {{{{{}}}}} has no {{{{/{}}}}}", line, tag, block)),
+        None => Ok(()),
+    }
+}
 
 /// Renders a template with variable substitution
 ///
@@ -21,7 +118,7 @@ use serde_json::Value as JsonValue;
 /// * `Err` - If rendering fails
 ///
 /// # Example
-/// ```
+/// ```ignore
 /// let template = "<h1>{{title}}</h1>";
 /// let data = json!({"title": "Hello World"});
 /// let rendered = render_template(template, &data)?;
@@ -112,7 +209,7 @@ fn get_nested_value(data: &JsonValue, path: &str) -> String {
 /// * `data` - JSON object with array data
 ///
 /// # Example
-/// ```
+/// ```ignore
 /// let template = "{{#each users}}<p>{{name}}</p>{{/each}}";
 /// let data = json!({"users": [{"name": "Alice"}, {"name": "Bob"}]});
 /// ```
@@ -160,7 +257,7 @@ pub fn render_template_with_loops(template: &str, data: &JsonValue) -> Result<St
 /// Renders a template with conditionals
 ///
 /// # Example
-/// ```
+/// ```ignore
 /// let template = "{{#if show}}<p>Visible</p>{{/if}}";
 /// let data = json!({"show": true});
 /// ```
@@ -271,6 +368,48 @@ mod tests {
         assert_eq!(result, "<p>Count: 42</p>");
     }
 
+    #[test]
+    fn test_template_files_are_read_once_until_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.html");
+        fs::write(&path, "<p>{{name}}</p>").unwrap();
+        let cache = TemplateCache::default();
+        assert_eq!(&*cache.load(dir.path(), "users.html").unwrap(), "<p>{{name}}</p>");
+        assert_eq!(&*cache.load(dir.path(), "users.html").unwrap(), "<p>{{name}}</p>");
+        assert_eq!(cache.reads(), 1);
+
+        fs::write(&path, "<b>{{name}}</b>").unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        assert_eq!(&*cache.load(dir.path(), "users.html").unwrap(), "<b>{{name}}</b>");
+        assert_eq!(cache.reads(), 2);
+        cache.clear();
+        cache.load(dir.path(), "users.html").unwrap();
+        assert_eq!(cache.reads(), 3);
+
+        fs::create_dir(dir.path().join("templates")).unwrap();
+        cache.set_dir(dir.path().join("templates"));
+        let err = cache.load(dir.path(), "users.html").unwrap_err().to_string();
+        assert!(err.contains("Cannot read template") && err.contains("templates"), "{}", err);
+    }
+
+    #[test]
+    fn test_template_check_names_the_line() {
+        assert_eq!(check("<ul>{{#each users}}<li>{{#if admin}}*{{/if}}{{name}}</li>{{/each}}</ul>"), Ok(()));
+        assert_eq!(check("a\n{{#each users}}\nb"), Err("line 2: {{#each users}} has no {{/each}}".to_string()));
+        assert_eq!(check("a\n\n{{name"), Err("line 3: {{ is never closed with }}".to_string()));
+        assert_eq!(check("{{/if}}"), Err("line 1: {{/if}} ends no block".to_string()));
+        assert_eq!(
+            check("{{#each xs}}\n{{/if}}"),
+            Err("line 2: {{/if}} ends the {{#each xs}} from line 1".to_string())
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("page.html"), "<h1>\n{{#if show}}\n</h1>").unwrap();
+        let err = TemplateCache::default().load(dir.path(), "page.html").unwrap_err().to_string();
+        assert!(err.ends_with("page.html line 2: {{#if show}} has no {{/if}}"), "{}", err);
+    }
+
     #[test]
     fn test_boolean_rendering() {
         let template = "<p>Active: {{active}}</p>";
@@ -0,0 +1,109 @@
+//! Text layout for PohLang
+//! Indents, dedents and word-wraps multi-line text, for programs that write
+//! out code, configuration files and reports
+
+/// Put `spaces` spaces in front of every line that has text on it. Blank
+/// lines stay empty so the output has no trailing whitespace.
+pub fn indent(text: &str, spaces: usize) -> String {
+    let pad = " ".repeat(spaces);
+    text.split_inclusive('\n')
+        .map(|line| {
+            if line.trim().is_empty() {
+                line.to_string()
+            } else {
+                format!("{}{}", pad, line)
+            }
+        })
+        .collect()
+}
+
+/// Take off the leading whitespace every non-blank line shares, so an
+/// indented block moves back to the left margin. Lines that are only
+/// whitespace become empty.
+pub fn dedent(text: &str) -> String {
+    let mut common: Option<&str> = None;
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        let lead = &line[..line.len() - line.trim_start().len()];
+        common = Some(match common {
+            None => lead,
+            Some(c) => {
+                // Whole characters only: U+2002 and U+2003 share their first two bytes
+                let shared: usize = c
+                    .chars()
+                    .zip(lead.chars())
+                    .take_while(|(a, b)| a == b)
+                    .map(|(a, _)| a.len_utf8())
+                    .sum();
+                &c[..shared]
+            }
+        });
+    }
+    let common = common.unwrap_or("");
+    text.split_inclusive('\n')
+        .map(|line| {
+            let (body, newline) = match line.strip_suffix('\n') {
+                Some(body) => (body, "\n"),
+                None => (line, ""),
+            };
+            if body.trim().is_empty() {
+                newline.to_string()
+            } else {
+                format!("{}{}", &body[common.len()..], newline)
+            }
+        })
+        .collect()
+}
+
+/// Break each line of `text` between words so no line is longer than
+/// `width` characters. A word longer than the width is kept whole on a
+/// line of its own; existing line breaks are kept.
+pub fn wrap(text: &str, width: usize) -> String {
+    text.split('\n')
+        .map(|line| {
+            let mut lines: Vec<String> = Vec::new();
+            let mut current = String::new();
+            for word in line.split_whitespace() {
+                if current.is_empty() {
+                    current.push_str(word);
+                } else if current.chars().count() + 1 + word.chars().count() <= width {
+                    current.push(' ');
+                    current.push_str(word);
+                } else {
+                    lines.push(std::mem::take(&mut current));
+                    current.push_str(word);
+                }
+            }
+            lines.push(current);
+            lines.join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_indent_skips_blank_lines() {
+        assert_eq!(indent("a\n\n  b\n", 2), "  a\n\n    b\n");
+        assert_eq!(indent("", 4), "");
+    }
+
+    #[test]
+    fn test_dedent_removes_the_shared_indent() {
+        assert_eq!(dedent("    if x:\n        y\n\n    z"), "if x:\n    y\n\nz");
+        assert_eq!(dedent("  a\n b\n"), " a\nb\n");
+        assert_eq!(dedent("\ta\n  b"), "\ta\n  b");
+        assert_eq!(dedent("   \n  a\n"), "\na\n");
+        assert_eq!(dedent("\u{2002}a\n\u{2003}b"), "\u{2002}a\n\u{2003}b");
+        assert_eq!(dedent("\u{2003}\u{2002}a\n\u{2003}b"), "\u{2002}a\nb");
+    }
+
+    #[test]
+    fn test_wrap_breaks_between_words() {
+        assert_eq!(wrap("the quick brown fox jumps", 10), "the quick\nbrown fox\njumps");
+        assert_eq!(wrap("a extraordinarily b", 5), "a\nextraordinarily\nb");
+        assert_eq!(wrap("one two\n\nthree four", 7), "one two\n\nthree\nfour");
+    }
+}
@@ -1,10 +1,10 @@
-/// File upload handling for PohLang web framework
-/// Supports multipart/form-data parsing and file storage
+//! File upload handling for PohLang web framework
+//! Supports multipart/form-data parsing and file storage
 
 use anyhow::{anyhow, Result};
 use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
 /// Represents an uploaded file
@@ -133,14 +133,13 @@ impl UploadConfig {
             }
         }
         
-        if !self.allowed_types.is_empty() {
-            if !self.allowed_types.contains(&file.content_type) {
+        if !self.allowed_types.is_empty()
+            && !self.allowed_types.contains(&file.content_type) {
                 return Err(anyhow!(
                     "File type '{}' not allowed",
                     file.content_type
                 ));
             }
-        }
         
         Ok(())
     }
@@ -233,9 +232,9 @@ fn extract_param(header: &str, param: &str) -> Option<String> {
         let rest = &header[value_start..];
         
         // Handle quoted values
-        if rest.starts_with('"') {
-            if let Some(end) = rest[1..].find('"') {
-                return Some(rest[1..=end].to_string());
+        if let Some(quoted) = rest.strip_prefix('"') {
+            if let Some(end) = quoted.find('"') {
+                return Some(quoted[..end].to_string());
             }
         } else {
             // Handle unquoted values (until semicolon or end)
@@ -252,8 +251,9 @@ pub fn parse_form_urlencoded(body: &str) -> HashMap<String, String> {
     
     for pair in body.split('&') {
         if let Some((key, value)) = pair.split_once('=') {
-            let key = urlencoding::decode(key).unwrap_or_default().to_string();
-            let value = urlencoding::decode(value).unwrap_or_default().to_string();
+            // '+' encodes a space in form bodies; urlencoding only handles %XX
+            let key = urlencoding::decode(&key.replace('+', " ")).unwrap_or_default().to_string();
+            let value = urlencoding::decode(&value.replace('+', " ")).unwrap_or_default().to_string();
             result.insert(key, value);
         }
     }
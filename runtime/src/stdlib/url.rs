@@ -0,0 +1,185 @@
+//! URL parsing and building for PohLang
+//! Splits absolute URLs into scheme, host, port, path, query and fragment,
+//! puts them back together, and percent-encodes text for URLs
+
+use anyhow::{anyhow, bail, Result};
+
+/// The pieces of an absolute URL. Absent parts are `None`; the scheme and
+/// host are lowercased, everything else is kept as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlParts {
+    pub scheme: String,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub path: String,
+    pub query: Option<String>,
+    pub fragment: Option<String>,
+}
+
+/// Schemes that always have a host, and whose empty path means "/"
+const HOST_SCHEMES: &[&str] = &["http", "https", "ws", "wss", "ftp"];
+
+/// Parse an absolute URL such as `https://example.com:8080/a?b=1#top`
+pub fn parse_url(input: &str) -> Result<UrlParts> {
+    let invalid = |why: &str| anyhow!("'{}' is not a valid URL: {}", input, why);
+    let text = input.trim();
+    if text.chars().any(char::is_whitespace) {
+        return Err(invalid("it contains spaces"));
+    }
+
+    let (scheme, rest) = text.split_once(':').ok_or_else(|| invalid("missing scheme, like 'https://'"))?;
+    let mut chars = scheme.chars();
+    let scheme_ok = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    if !scheme_ok {
+        return Err(invalid("missing scheme, like 'https://'"));
+    }
+    let scheme = scheme.to_ascii_lowercase();
+
+    let (rest, fragment) = match rest.split_once('#') {
+        Some((r, f)) => (r, Some(f.to_string())),
+        None => (rest, None),
+    };
+    let (rest, query) = match rest.split_once('?') {
+        Some((r, q)) => (r, Some(q.to_string())),
+        None => (rest, None),
+    };
+
+    let (host, port, path) = match rest.strip_prefix("//") {
+        Some(after) => {
+            let (authority, path) = match after.find('/') {
+                Some(at) => after.split_at(at),
+                None => (after, ""),
+            };
+            let (host, port) = split_authority(authority).map_err(|why| invalid(&why))?;
+            (Some(host), port, path)
+        }
+        None => (None, None, rest),
+    };
+
+    let needs_host = HOST_SCHEMES.contains(&scheme.as_str());
+    if needs_host && host.as_deref().is_none_or(str::is_empty) {
+        return Err(invalid("missing host"));
+    }
+    let path = if needs_host && path.is_empty() {
+        "/".to_string()
+    } else {
+        path.to_string()
+    };
+
+    Ok(UrlParts {
+        scheme,
+        host,
+        port,
+        path,
+        query,
+        fragment,
+    })
+}
+
+fn split_authority(authority: &str) -> std::result::Result<(String, Option<u16>), String> {
+    if authority.contains('@') {
+        return Err("user names and passwords in URLs are not supported".to_string());
+    }
+    // An IPv6 host is bracketed and has colons of its own
+    let port_at = match authority.rfind(':') {
+        Some(at) if !authority[at..].contains(']') => Some(at),
+        _ => None,
+    };
+    let (host, port) = match port_at {
+        Some(at) => {
+            let digits = &authority[at + 1..];
+            let port = digits
+                .parse::<u16>()
+                .map_err(|_| format!("invalid port '{}'", digits))?;
+            (&authority[..at], Some(port))
+        }
+        None => (authority, None),
+    };
+    Ok((host.to_ascii_lowercase(), port))
+}
+
+/// Put a URL back together from its pieces
+pub fn build_url(parts: &UrlParts) -> Result<String> {
+    if parts.scheme.is_empty() {
+        bail!("a URL needs a scheme, like \"https\"");
+    }
+    let mut url = format!("{}:", parts.scheme);
+    if let Some(host) = &parts.host {
+        url.push_str("//");
+        url.push_str(host);
+        if let Some(port) = parts.port {
+            url.push_str(&format!(":{}", port));
+        }
+        if !parts.path.is_empty() && !parts.path.starts_with('/') {
+            url.push('/');
+        }
+    } else if parts.port.is_some() {
+        bail!("a URL with a port needs a host");
+    }
+    url.push_str(&parts.path);
+    if let Some(query) = &parts.query {
+        url.push('?');
+        url.push_str(query);
+    }
+    if let Some(fragment) = &parts.fragment {
+        url.push('#');
+        url.push_str(fragment);
+    }
+    Ok(url)
+}
+
+/// Percent-encode everything except letters, digits and `-_.~`
+pub fn url_encode(text: &str) -> String {
+    urlencoding::encode(text).into_owned()
+}
+
+/// Undo percent-encoding; `+` is left alone
+pub fn url_decode(text: &str) -> Result<String> {
+    urlencoding::decode(text)
+        .map(|s| s.into_owned())
+        .map_err(|_| anyhow!("'{}' does not decode to valid UTF-8 text", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_rebuild_round_trip() {
+        let parts = parse_url("HTTPS://API.Example.com:8443/v1/hooks?id=7&x=y#top").unwrap();
+        assert_eq!(parts.scheme, "https");
+        assert_eq!(parts.host.as_deref(), Some("api.example.com"));
+        assert_eq!(parts.port, Some(8443));
+        assert_eq!(parts.path, "/v1/hooks");
+        assert_eq!(parts.query.as_deref(), Some("id=7&x=y"));
+        assert_eq!(parts.fragment.as_deref(), Some("top"));
+        assert_eq!(
+            build_url(&parts).unwrap(),
+            "https://api.example.com:8443/v1/hooks?id=7&x=y#top"
+        );
+
+        let parts = parse_url("http://[::1]:3000").unwrap();
+        assert_eq!(parts.host.as_deref(), Some("[::1]"));
+        assert_eq!(parts.path, "/");
+
+        let parts = parse_url("mailto:ada@example.com").unwrap();
+        assert_eq!((parts.host, parts.path.as_str()), (None, "ada@example.com"));
+    }
+
+    #[test]
+    fn test_invalid_urls_say_why() {
+        let err = |s: &str| parse_url(s).unwrap_err().to_string();
+        assert!(err("example.com/path").contains("missing scheme"));
+        assert!(err("https:///path").contains("missing host"));
+        assert!(err("http://example.com:99999/").contains("invalid port '99999'"));
+        assert!(err("http://exa mple.com").contains("spaces"));
+    }
+
+    #[test]
+    fn test_percent_encoding() {
+        assert_eq!(url_encode("a b&c=d/é"), "a%20b%26c%3Dd%2F%C3%A9");
+        assert_eq!(url_decode("a%20b%26c%3Dd%2F%C3%A9").unwrap(), "a b&c=d/é");
+        assert!(url_decode("%FF").is_err());
+    }
+}
@@ -0,0 +1,478 @@
+//! `Add websocket route`: connections that upgrade from HTTP to WebSocket
+//! (RFC 6455) and stay open while the handler talks to the client.
+//!
+//! Websocket routes are kept with the other routes under the method
+//! `WEBSOCKET`, and a GET asking to upgrade is looked up under it. As for
+//! event streams, the connection thread offers its socket before calling
+//! the handler. Accepting it answers 101 Switching Protocols; from then on
+//! the two ends exchange frames until one of them closes. Text and binary
+//! messages, split over continuation frames or not, are read whole. Pings
+//! are answered as they arrive, and a frame that breaks the protocol closes
+//! the connection with the matching status code.
+
+use anyhow::{bail, Result};
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::socket::Socket;
+
+/// The method websocket routes are kept under
+pub const METHOD: &str = crate::parser::ast::WEBSOCKET_METHOD;
+/// Appended to the client's key to make the accept header
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Largest message taken from a client, across all of its frames
+const MAX_MESSAGE: usize = 16 * 1024 * 1024;
+/// A client that takes longer than this to accept a write counts as gone
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CONTINUATION: u8 = 0x0;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// Close status codes
+const NORMAL: u16 = 1000;
+const PROTOCOL_ERROR: u16 = 1002;
+const INVALID_DATA: u16 = 1007;
+const TOO_BIG: u16 = 1009;
+
+thread_local! {
+    /// The socket of the upgrade request handled on this thread, until a
+    /// handler accepts it
+    static OFFERED: RefCell<Option<Offer>> = const { RefCell::new(None) };
+    /// The websocket accepted for that request, if one was
+    static ACCEPTED: RefCell<Option<WebSocket>> = const { RefCell::new(None) };
+}
+
+struct Offer {
+    socket: Socket,
+    /// Read from the socket past the end of the request
+    buffered: Vec<u8>,
+    key: String,
+}
+
+/// The `Sec-WebSocket-Key` of a request asking to upgrade to a websocket
+pub fn upgrade_key(method: &str, headers: &[(String, String)]) -> Option<String> {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    };
+    let upgrade = header("Upgrade")?.split(',').any(|t| t.trim().eq_ignore_ascii_case("websocket"));
+    let connection = header("Connection")?.split(',').any(|t| t.trim().eq_ignore_ascii_case("upgrade"));
+    let key = header("Sec-WebSocket-Key")?.trim();
+    (method == "GET" && upgrade && connection && !key.is_empty()).then(|| key.to_string())
+}
+
+/// Let the handler about to run on this thread accept the upgrade request
+/// `key` came with
+pub fn offer(socket: Socket, buffered: Vec<u8>, key: String) {
+    OFFERED.with(|o| *o.borrow_mut() = Some(Offer { socket, buffered, key }));
+    ACCEPTED.with(|a| a.borrow_mut().take());
+}
+
+/// After the handler: the websocket it accepted, if it did. The offer ends.
+pub fn finish() -> Option<WebSocket> {
+    OFFERED.with(|o| o.borrow_mut().take());
+    ACCEPTED.with(|a| a.borrow_mut().take())
+}
+
+/// The `Sec-WebSocket-Accept` answer to a client's key
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+/// An open websocket connection; copies share it
+#[derive(Clone, Debug)]
+pub struct WebSocket(Arc<Shared>);
+
+#[derive(Debug)]
+struct Shared {
+    reader: Mutex<Reader>,
+    writer: Mutex<Socket>,
+    open: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Reader {
+    socket: Socket,
+    /// Received but not yet a whole frame
+    pending: Vec<u8>,
+    /// The opcode and frames so far of a message sent in pieces
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+/// One frame as sent by a client, unmasked
+#[derive(Debug, PartialEq)]
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+impl WebSocket {
+    /// Accept the upgrade request handled on this thread. `headers` are
+    /// those set with `set response header`.
+    pub fn accept(headers: &[(String, String)]) -> Result<WebSocket> {
+        let Some(Offer { mut socket, buffered, key }) = OFFERED.with(|o| o.borrow_mut().take()) else {
+            bail!("the websocket can only be accepted once, in a websocket route");
+        };
+        let mut head = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n",
+            accept_key(&key)
+        );
+        for (name, value) in headers {
+            let fixed = ["Upgrade", "Connection", "Sec-WebSocket-Accept", "Content-Length", "Transfer-Encoding"]
+                .iter()
+                .any(|h| h.eq_ignore_ascii_case(name));
+            if !fixed {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str("\r\n");
+        socket.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        socket.write_all(head.as_bytes())?;
+        let socket = WebSocket(Arc::new(Shared {
+            reader: Mutex::new(Reader {
+                socket: socket.try_clone()?,
+                pending: buffered,
+                partial: None,
+            }),
+            writer: Mutex::new(socket),
+            open: AtomicBool::new(true),
+        }));
+        ACCEPTED.with(|a| *a.borrow_mut() = Some(socket.clone()));
+        Ok(socket)
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.0.open.load(Ordering::SeqCst)
+    }
+
+    /// The next message from the client, waiting at most `timeout` if one
+    /// is given. `None` when the time is up or the connection has closed.
+    pub fn receive(&self, timeout: Option<Duration>) -> Result<Option<String>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut reader = self.0.reader.lock().unwrap();
+        let mut buf = [0u8; 4096];
+        while self.is_open() {
+            let frame = match decode(&reader.pending) {
+                Ok(Some((frame, used))) => {
+                    reader.pending.drain(..used);
+                    frame
+                }
+                Ok(None) => {
+                    let wait = match deadline {
+                        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                            Some(left) if !left.is_zero() => Some(left),
+                            _ => return Ok(None),
+                        },
+                        None => None,
+                    };
+                    reader.socket.set_read_timeout(wait)?;
+                    match reader.socket.read(&mut buf) {
+                        Ok(0) => self.close_with(NORMAL),
+                        Ok(n) => reader.pending.extend_from_slice(&buf[..n]),
+                        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(_) => self.close_with(NORMAL),
+                    }
+                    continue;
+                }
+                Err(code) => {
+                    self.close_with(code);
+                    break;
+                }
+            };
+            match frame.opcode {
+                TEXT | BINARY if reader.partial.is_none() => {
+                    if frame.fin {
+                        return Ok(self.message(frame.opcode, frame.payload));
+                    }
+                    reader.partial = Some((frame.opcode, frame.payload));
+                }
+                CONTINUATION if reader.partial.is_some() => {
+                    let (opcode, mut message) = reader.partial.take().unwrap();
+                    if message.len() + frame.payload.len() > MAX_MESSAGE {
+                        self.close_with(TOO_BIG);
+                        break;
+                    }
+                    message.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        return Ok(self.message(opcode, message));
+                    }
+                    reader.partial = Some((opcode, message));
+                }
+                PING => {
+                    let _ = self.write_frame(PONG, &frame.payload);
+                }
+                PONG => {}
+                CLOSE => {
+                    // Answer with the client's own code, as the protocol asks
+                    let code = match frame.payload[..] {
+                        [a, b, ..] => u16::from_be_bytes([a, b]),
+                        _ => NORMAL,
+                    };
+                    self.close_with(code);
+                }
+                _ => self.close_with(PROTOCOL_ERROR),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Send `text` as one text message
+    pub fn send(&self, text: &str) -> Result<()> {
+        if self.write_frame(TEXT, text.as_bytes()).is_err() {
+            bail!("[SocketClosed] The websocket is closed; the client may have gone away");
+        }
+        Ok(())
+    }
+
+    /// End the connection. Closing it again does nothing.
+    pub fn close(&self) {
+        self.close_with(NORMAL);
+    }
+
+    /// A whole message; text that is not UTF-8 closes the connection
+    fn message(&self, opcode: u8, payload: Vec<u8>) -> Option<String> {
+        match (opcode, String::from_utf8(payload)) {
+            (_, Ok(text)) => Some(text),
+            (BINARY, Err(e)) => Some(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+            (_, Err(_)) => {
+                self.close_with(INVALID_DATA);
+                None
+            }
+        }
+    }
+
+    fn close_with(&self, code: u16) {
+        if self.0.open.swap(false, Ordering::SeqCst) {
+            let mut writer = self.0.writer.lock().unwrap();
+            let _ = writer.write_all(&encode(CLOSE, &code.to_be_bytes()));
+            let _ = writer.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn write_frame(&self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        if !self.is_open() {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        let mut writer = self.0.writer.lock().unwrap();
+        let written = writer.write_all(&encode(opcode, payload)).and_then(|_| writer.flush());
+        drop(writer);
+        if written.is_err() {
+            self.close_with(NORMAL);
+        }
+        written
+    }
+}
+
+/// The first frame in `buf` and how many bytes it took, or `None` if it
+/// has not all arrived. A frame that breaks the protocol is the close code
+/// to answer it with.
+fn decode(buf: &[u8]) -> Result<Option<(Frame, usize)>, u16> {
+    let [first, second, ..] = *buf else {
+        return Ok(None);
+    };
+    let (fin, opcode) = (first & 0x80 != 0, first & 0x0F);
+    // No extensions are agreed, so the reserved bits must be clear, and
+    // every frame from a client is masked
+    if first & 0x70 != 0 || second & 0x80 == 0 {
+        return Err(PROTOCOL_ERROR);
+    }
+    let (len, at) = match second & 0x7F {
+        126 if buf.len() >= 4 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+        127 if buf.len() >= 10 => (u64::from_be_bytes(buf[2..10].try_into().unwrap()), 10),
+        126 | 127 => return Ok(None),
+        n => (n as u64, 2),
+    };
+    if opcode >= CLOSE && (!fin || len > 125) {
+        return Err(PROTOCOL_ERROR);
+    }
+    if len > MAX_MESSAGE as u64 {
+        return Err(TOO_BIG);
+    }
+    let end = at + 4 + len as usize;
+    if buf.len() < end {
+        return Ok(None);
+    }
+    let mask = &buf[at..at + 4];
+    let payload = buf[at + 4..end]
+        .iter()
+        .enumerate()
+        .map(|(n, b)| b ^ mask[n % 4])
+        .collect();
+    Ok(Some((Frame { fin, opcode, payload }, end)))
+}
+
+/// A whole, unmasked frame, as the server sends them
+fn encode(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        n if n < 126 => frame.push(n as u8),
+        n if n <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        n => {
+            frame.push(127);
+            frame.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// SHA-1 of `data`; the handshake is its only use
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (n, word) in block.chunks(4).enumerate() {
+            w[n] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for n in 16..80 {
+            w[n] = (w[n - 3] ^ w[n - 8] ^ w[n - 14] ^ w[n - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (n, word) in w.iter().enumerate() {
+            let (f, k) = match n {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, t);
+        }
+        for (x, y) in h.iter_mut().zip([a, b, c, d, e]) {
+            *x = x.wrapping_add(y);
+        }
+    }
+    let mut digest = [0u8; 20];
+    for (out, x) in digest.chunks_mut(4).zip(h) {
+        out.copy_from_slice(&x.to_be_bytes());
+    }
+    digest
+}
+
+/// Standard base64, padded
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// A frame as a client sends it, masked
+    fn masked(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![first, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(n, b)| b ^ mask[n % 4]));
+        frame
+    }
+
+    #[test]
+    fn test_handshake_answers_the_client_key() {
+        // The example from RFC 6455, section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+        let headers = [
+            ("Upgrade".to_string(), "websocket".to_string()),
+            ("connection".to_string(), "keep-alive, Upgrade".to_string()),
+            ("Sec-WebSocket-Key".to_string(), "abc".to_string()),
+        ];
+        assert_eq!(upgrade_key("GET", &headers), Some("abc".to_string()));
+        assert_eq!(upgrade_key("POST", &headers), None);
+        assert_eq!(upgrade_key("GET", &headers[1..]), None);
+    }
+
+    #[test]
+    fn test_frames_are_unmasked_and_checked() {
+        let frame = masked(0x81, b"Hello");
+        assert_eq!(
+            decode(&frame),
+            Ok(Some((Frame { fin: true, opcode: TEXT, payload: b"Hello".to_vec() }, frame.len())))
+        );
+        assert_eq!(decode(&frame[..4]), Ok(None));
+        // Unmasked, a fragmented ping, and a reserved bit set
+        assert_eq!(decode(&encode(TEXT, b"Hello")), Err(PROTOCOL_ERROR));
+        assert_eq!(decode(&masked(0x09, b"")), Err(PROTOCOL_ERROR));
+        assert_eq!(decode(&masked(0xC1, b"x")), Err(PROTOCOL_ERROR));
+        assert_eq!(encode(TEXT, &[b'x'; 200])[..4], [0x81, 126, 0, 200]);
+    }
+
+    #[test]
+    fn test_messages_pings_and_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        offer(server.into(), masked(0x01, b"Hel"), "dGhlIHNhbXBsZSBub25jZQ==".to_string());
+        let socket = WebSocket::accept(&[]).unwrap();
+        assert!(WebSocket::accept(&[]).is_err());
+        assert!(finish().is_some());
+
+        let mut rest = masked(0x89, b"hi");
+        rest.extend(masked(0x80, b"lo"));
+        client.write_all(&rest).unwrap();
+        assert_eq!(socket.receive(None).unwrap(), Some("Hello".to_string()));
+        assert_eq!(socket.receive(Some(Duration::from_millis(20))).unwrap(), None);
+        socket.send("back").unwrap();
+
+        let reader = socket.clone();
+        let closed = thread::spawn(move || reader.receive(None).unwrap());
+        client.write_all(&masked(0x88, &1001u16.to_be_bytes())).unwrap();
+        assert_eq!(closed.join().unwrap(), None);
+        assert!(!socket.is_open());
+        assert!(socket.send("late").is_err());
+
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        let head_end = received.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8_lossy(&received[..head_end]);
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"), "{}", head);
+        let mut frames = encode(PONG, b"hi");
+        frames.extend(encode(TEXT, b"back"));
+        frames.extend(encode(CLOSE, &1001u16.to_be_bytes()));
+        assert_eq!(received[head_end..], frames[..]);
+    }
+}
@@ -0,0 +1,576 @@
+//! `--transpile js`: a program as readable JavaScript
+//!
+//! Statements become the JavaScript statements closest to them. Operations
+//! on values become calls into the prelude (`prelude.js`, copied ahead of
+//! the program), which keeps the interpreter's rules for numbers, text,
+//! truth and equality. Statements that need the web server, files, tasks
+//! or other services of the runtime have no JavaScript form and are
+//! reported with their line.
+
+use crate::parser::ast::{AskType, CatchHandler, CmpOp, Expr, LogLevel, Param, Program, Stmt};
+use crate::vm::vm::{dump_expr, dump_stmt, BUILTIN_FUNCTIONS};
+use anyhow::{bail, Result};
+
+const PRELUDE: &str = include_str!("prelude.js");
+
+/// The built-in functions the prelude has, by their PohLang name
+const PRELUDE_FUNCTIONS: &[(&str, &str)] = &[
+    ("range", "range"),
+    ("join", "join"),
+    ("split", "split"),
+    ("length", "length"),
+    ("len", "length"),
+    ("sum", "sum"),
+    ("min", "min"),
+    ("max", "max"),
+    ("abs", "abs"),
+    ("round", "round"),
+    ("floor", "floor"),
+    ("ceil", "ceil"),
+    ("lerp", "lerp"),
+    ("clamp", "clamp"),
+    ("map_range", "map_range"),
+    ("uppercase", "uppercase"),
+    ("lowercase", "lowercase"),
+    ("trim", "trim"),
+    ("first", "first"),
+    ("last", "last"),
+    ("reverse", "reverse"),
+];
+
+/// Words JavaScript keeps for itself; a PohLang name that is one gets a `_`
+const RESERVED: &[&str] = &[
+    "arguments", "await", "break", "case", "catch", "class", "const", "continue", "debugger", "default",
+    "delete", "do", "else", "enum", "eval", "export", "extends", "false", "finally", "for", "function", "if",
+    "implements", "import", "in", "Infinity", "instanceof", "interface", "let", "NaN", "new", "null",
+    "package", "private", "protected", "public", "return", "static", "super", "switch", "this", "throw",
+    "true", "try", "typeof", "undefined", "var", "void", "while", "with", "yield",
+];
+
+/// The JavaScript for `prog`: the prelude, then the program
+pub fn transpile(prog: &Program) -> Result<String> {
+    let mut js = Js {
+        out: format!("{}\n// The program\n", PRELUDE),
+        ..Js::default()
+    };
+    // Constants are set before the program starts, as in the interpreter
+    for s in prog {
+        if let Stmt::GlobalConst { name, value } = s {
+            let value = js.expr(value)?;
+            js.emit(&format!("const {} = {};", ident(name), value));
+        }
+    }
+    js.declare(&assigned(prog, &[]));
+    js.stmts(prog, true)?;
+    Ok(js.out)
+}
+
+#[derive(Default)]
+struct Js {
+    out: String,
+    indent: usize,
+    /// The source line of the statement being translated, when known
+    line: Option<usize>,
+    /// How many functions the statement is inside
+    functions: usize,
+    /// How many `Repeat` loops the statement is inside
+    loops: usize,
+}
+
+impl Js {
+    fn emit(&mut self, line: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    /// `let` for the variables a program or function sets
+    fn declare(&mut self, names: &[String]) {
+        if !names.is_empty() {
+            let names: Vec<String> = names.iter().map(|n| format!("{} = null", ident(n))).collect();
+            self.emit(&format!("let {};", names.join(", ")));
+        }
+    }
+
+    fn unsupported<T>(&self, what: String) -> Result<T> {
+        match self.line {
+            Some(line) => bail!("line {}: {}, not supported in the JS target", line, what),
+            None => bail!("{}, not supported in the JS target", what),
+        }
+    }
+
+    /// The statements of a block. `top` is set for the body of a program or
+    /// function, where a function can be declared; elsewhere it is assigned
+    fn stmts(&mut self, body: &[Stmt], top: bool) -> Result<()> {
+        for s in body {
+            self.stmt(s, top)?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, body: &[Stmt]) -> Result<()> {
+        self.indent += 1;
+        let result = self.stmts(body, false);
+        self.indent -= 1;
+        result
+    }
+
+    fn stmt(&mut self, s: &Stmt, top: bool) -> Result<()> {
+        match s {
+            Stmt::Line(line) => self.line = Some(*line),
+            Stmt::Write(e) => {
+                let e = self.expr(e)?;
+                self.emit(&format!("$poh.write({});", e));
+            }
+            Stmt::MultiWrite { exprs, sep, end } => {
+                let values = exprs.iter().map(|e| self.expr(e)).collect::<Result<Vec<_>>>()?;
+                let sep = sep.as_ref().map_or(Ok("\" \"".to_string()), |e| self.expr(e))?;
+                let end = end.as_ref().map_or(Ok("\"\\n\"".to_string()), |e| self.expr(e))?;
+                self.emit(&format!("$poh.writeAll([{}], {}, {});", values.join(", "), sep, end));
+            }
+            Stmt::AskFor { var_name, expect } => {
+                let expect = match expect {
+                    AskType::Any => "any",
+                    AskType::Number => "number",
+                    AskType::YesNo => "yes/no",
+                    AskType::Text => "text",
+                };
+                self.emit(&format!("{} = $poh.ask({}, \"{}\");", ident(var_name), string(var_name), expect));
+            }
+            Stmt::IfInline {
+                cond,
+                then_write,
+                otherwise_write,
+            } => {
+                let (cond, then_write) = (self.expr(cond)?, self.expr(then_write)?);
+                self.emit(&format!("if ($poh.truthy({})) $poh.write({});", cond, then_write));
+                if let Some(o) = otherwise_write {
+                    let o = self.expr(o)?;
+                    self.emit(&format!("else $poh.write({});", o));
+                }
+            }
+            Stmt::IfBlock { .. } => self.if_chain(s, "if")?,
+            Stmt::FuncInline { name, params, body } => {
+                let body = vec![Stmt::Return(Some(body.clone()))];
+                self.function(name, params, &body, top)?;
+            }
+            Stmt::FuncBlock { name, params, body } => self.function(name, params, body, top)?,
+            Stmt::WhileBlock { cond, body } => {
+                let cond = self.expr(cond)?;
+                self.emit(&format!("while ($poh.truthy({})) {{", cond));
+                self.block(body)?;
+                self.emit("}");
+            }
+            Stmt::RepeatUntil { cond, body } => {
+                let cond = self.expr(cond)?;
+                self.emit(&format!("while (!$poh.truthy({})) {{", cond));
+                self.block(body)?;
+                self.emit("}");
+            }
+            Stmt::RepeatBlock { count, body } => {
+                let count = self.expr(count)?;
+                self.loops += 1;
+                let (i, n) = (format!("$i{}", self.loops), format!("$n{}", self.loops));
+                self.emit(&format!("for (let {i} = 0, {n} = $poh.repeatCount({count}); {i} < {n}; {i}++) {{"));
+                let result = self.block(body);
+                self.loops -= 1;
+                result?;
+                self.emit("}");
+            }
+            Stmt::Use { name, args, result_var } => {
+                let call = self.call(name, args)?;
+                match result_var {
+                    Some(var) => self.emit(&format!("{} = {};", ident(var), call)),
+                    None => self.emit(&format!("{};", call)),
+                }
+            }
+            Stmt::Set { name, value } => {
+                let value = self.expr(value)?;
+                self.emit(&format!("{} = {};", ident(name), value));
+            }
+            // Declared with the other constants, ahead of the program
+            Stmt::GlobalConst { .. } => {}
+            Stmt::SetItem { name, key, value } => {
+                let (key, value) = (self.expr(key)?, self.expr(value)?);
+                let name = ident(name);
+                self.emit(&format!("{name} = $poh.setItem({name}, {key}, {value});"));
+            }
+            Stmt::Destructure { names, value } => {
+                let value = self.expr(value)?;
+                let targets: Vec<String> = names.iter().map(|n| ident(n)).collect();
+                self.emit(&format!("[{}] = $poh.unpack({}, {});", targets.join(", "), value, names.len()));
+            }
+            Stmt::Return(_) if self.functions == 0 => {
+                return self.unsupported(format!("'{}' outside a function", dump_stmt(s)));
+            }
+            Stmt::Return(None) => self.emit("return null;"),
+            Stmt::Return(Some(e)) => {
+                let e = self.expr(e)?;
+                self.emit(&format!("return {};", e));
+            }
+            Stmt::TryCatch {
+                try_block,
+                catch_handlers,
+                finally_block,
+            } => self.try_catch(try_block, catch_handlers, finally_block.as_ref())?,
+            Stmt::Throw(e) => {
+                let e = self.expr(e)?;
+                self.emit(&format!("throw $poh.raise({});", e));
+            }
+            Stmt::LogMessage { level, message } => {
+                let level = match level {
+                    LogLevel::Info => "INFO",
+                    LogLevel::Warn => "WARN",
+                    LogLevel::Error => "ERROR",
+                };
+                let message = self.expr(message)?;
+                self.emit(&format!("$poh.log(\"{}\", {});", level, message));
+            }
+            Stmt::Evaluate(e) => {
+                let e = self.expr(e)?;
+                self.emit(&format!("{};", e));
+            }
+            // Types are only checked with --strict-types, which has no JavaScript form
+            Stmt::TypeAlias { .. } | Stmt::DeclareType { .. } => {}
+            Stmt::AddRoute { .. }
+            | Stmt::Route { .. }
+            | Stmt::RouteGroup { .. }
+            | Stmt::GroupMiddleware { .. }
+            | Stmt::AddMiddleware { .. }
+            | Stmt::RateLimit { .. }
+            | Stmt::EnableMetrics { .. }
+            | Stmt::ServeStaticFiles(_)
+            | Stmt::TemplateDirectory(_)
+            | Stmt::Respond(_)
+            | Stmt::StartServer
+            | Stmt::BeforeEachRoute { .. }
+            | Stmt::AfterEachRoute { .. }
+            | Stmt::SendEvent { .. }
+            | Stmt::SendMessage { .. }
+            | Stmt::CloseStream(_) => {
+                return self.unsupported(format!("'{}' is a web server statement", dump_stmt(s)));
+            }
+            _ => return self.unsupported(format!("'{}'", dump_stmt(s))),
+        }
+        Ok(())
+    }
+
+    /// `if`, then `else if` for each `Otherwise` holding only another `If`
+    fn if_chain(&mut self, s: &Stmt, keyword: &str) -> Result<()> {
+        let Stmt::IfBlock {
+            cond,
+            then_body,
+            otherwise_body,
+        } = s
+        else {
+            unreachable!("if_chain takes an If block");
+        };
+        let cond = self.expr(cond)?;
+        self.emit(&format!("{} ($poh.truthy({})) {{", keyword, cond));
+        self.block(then_body)?;
+        match otherwise_body.as_deref() {
+            Some([next @ Stmt::IfBlock { .. }]) => self.if_chain(next, "} else if")?,
+            Some([Stmt::Line(line), next @ Stmt::IfBlock { .. }]) => {
+                self.line = Some(*line);
+                self.if_chain(next, "} else if")?
+            }
+            Some(otherwise) => {
+                self.emit("} else {");
+                self.block(otherwise)?;
+                self.emit("}");
+            }
+            None => self.emit("}"),
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, name: &str, params: &[Param], body: &Program, top: bool) -> Result<()> {
+        let mut list = Vec::new();
+        for p in params {
+            match &p.default {
+                Some(d) => list.push(format!("{} = {}", ident(&p.name), self.expr(d)?)),
+                None => list.push(ident(&p.name)),
+            }
+        }
+        let (name, list) = (ident(name), list.join(", "));
+        match top {
+            true => self.emit(&format!("function {}({}) {{", name, list)),
+            false => self.emit(&format!("{} = function {}({}) {{", name, name, list)),
+        }
+        // Each call has its own variables, as in the interpreter
+        let param_names: Vec<&str> = params.iter().map(|p| p.name.as_str()).collect();
+        self.indent += 1;
+        self.functions += 1;
+        self.declare(&assigned(body, &param_names));
+        let result = self.stmts(body, true);
+        self.functions -= 1;
+        self.indent -= 1;
+        result?;
+        self.emit(if top { "}" } else { "};" });
+        Ok(())
+    }
+
+    fn try_catch(&mut self, body: &Program, handlers: &[CatchHandler], finally: Option<&Program>) -> Result<()> {
+        if handlers.is_empty() && finally.is_none() {
+            self.emit("{");
+            self.block(body)?;
+            self.emit("}");
+            return Ok(());
+        }
+        self.emit("try {");
+        self.block(body)?;
+        if !handlers.is_empty() {
+            self.emit("} catch ($e) {");
+            self.indent += 1;
+            match handlers {
+                [only @ CatchHandler { error_type: None, .. }] => self.handler(only)?,
+                _ => {
+                    let mut caught_all = false;
+                    for (i, h) in handlers.iter().enumerate() {
+                        let keyword = if i == 0 { "if" } else { "} else if" };
+                        match &h.error_type {
+                            Some(t) => self.emit(&format!("{} ($poh.caughtAs($e, {})) {{", keyword, string(t))),
+                            None if i == 0 => self.emit("{"),
+                            None => self.emit("} else {"),
+                        }
+                        self.indent += 1;
+                        self.handler(h)?;
+                        self.indent -= 1;
+                        if h.error_type.is_none() {
+                            caught_all = true;
+                            break;
+                        }
+                    }
+                    if !caught_all {
+                        self.emit("} else {");
+                        self.emit("  throw $e;");
+                    }
+                    self.emit("}");
+                }
+            }
+            self.indent -= 1;
+        }
+        if let Some(finally) = finally {
+            self.emit("} finally {");
+            self.block(finally)?;
+        }
+        self.emit("}");
+        Ok(())
+    }
+
+    fn handler(&mut self, h: &CatchHandler) -> Result<()> {
+        if let Some(var) = &h.var_name {
+            self.emit(&format!("{} = $poh.caught($e);", ident(var)));
+        }
+        self.stmts(&h.block, false)
+    }
+
+    fn call(&self, name: &str, args: &[Expr]) -> Result<String> {
+        let args = self.exprs(args)?;
+        if let Some((_, js)) = PRELUDE_FUNCTIONS.iter().find(|(poh, _)| *poh == name) {
+            return Ok(format!("$poh.{}({})", js, args));
+        }
+        if BUILTIN_FUNCTIONS.contains(&name) {
+            return self.unsupported(format!("the built-in function {}", name));
+        }
+        if name.contains("::") {
+            return self.unsupported(format!("calling {} from an imported module", name));
+        }
+        Ok(format!("{}({})", ident(name), args))
+    }
+
+    fn exprs(&self, es: &[Expr]) -> Result<String> {
+        Ok(es.iter().map(|e| self.expr(e)).collect::<Result<Vec<_>>>()?.join(", "))
+    }
+
+    /// A prelude function applied to each of `args`
+    fn prelude(&self, function: &str, args: &[&Expr]) -> Result<String> {
+        let args: Vec<String> = args.iter().map(|e| self.expr(e)).collect::<Result<_>>()?;
+        Ok(format!("$poh.{}({})", function, args.join(", ")))
+    }
+
+    fn expr(&self, e: &Expr) -> Result<String> {
+        Ok(match e {
+            Expr::Str(s) => string(s),
+            Expr::Int(i) => i.to_string(),
+            Expr::Num(n) if n.is_nan() => "NaN".to_string(),
+            Expr::Num(n) if n.is_infinite() => format!("{}Infinity", if *n < 0.0 { "-" } else { "" }),
+            Expr::Num(n) => format!("{:?}", n),
+            Expr::Bool(b) => b.to_string(),
+            Expr::Null => "null".to_string(),
+            Expr::Ident(name) | Expr::Global(name) => ident(name),
+            // A negative number is parsed as `0 minus <number>`
+            Expr::Minus(zero, n) if matches!(**zero, Expr::Int(0)) && matches!(**n, Expr::Int(_) | Expr::Num(_)) => {
+                format!("-{}", self.expr(n)?)
+            }
+            Expr::Plus(a, b) => self.prelude("add", &[a, b])?,
+            Expr::Minus(a, b) => self.prelude("sub", &[a, b])?,
+            Expr::Times(a, b) => self.prelude("mul", &[a, b])?,
+            Expr::DividedBy(a, b) => self.prelude("div", &[a, b])?,
+            // The right side is only worked out when it decides the answer
+            Expr::And(a, b) => format!("$poh.and({}, () => {})", self.expr(a)?, self.expr(b)?),
+            Expr::Or(a, b) => format!("$poh.or({}, () => {})", self.expr(a)?, self.expr(b)?),
+            Expr::Not(a) => self.prelude("not", &[a])?,
+            Expr::Conditional(cond, a, b) => {
+                format!("($poh.truthy({}) ? {} : {})", self.expr(cond)?, self.expr(a)?, self.expr(b)?)
+            }
+            Expr::Cmp(op, a, b) => {
+                let op = match op {
+                    CmpOp::Lt => "lt",
+                    CmpOp::Le => "le",
+                    CmpOp::Gt => "gt",
+                    CmpOp::Ge => "ge",
+                    CmpOp::Eq => "eq",
+                    CmpOp::Ne => "ne",
+                };
+                self.prelude(op, &[a, b])?
+            }
+            Expr::Call { name, args } => self.call(name, args)?,
+            Expr::ListLit(items) => format!("[{}]", self.exprs(items)?),
+            Expr::TupleLit(items) => format!("$poh.tuple([{}])", self.exprs(items)?),
+            Expr::DictLit(pairs) => {
+                let pairs: Vec<String> = pairs
+                    .iter()
+                    .map(|(k, v)| Ok(format!("{}: {}", string(k), self.expr(v)?)))
+                    .collect::<Result<_>>()?;
+                format!("{{ {} }}", pairs.join(", "))
+            }
+            Expr::Index(base, i) => self.prelude("index", &[base, i])?,
+            Expr::TotalOf(x) => self.prelude("sum", &[x])?,
+            Expr::SmallestIn(x) => self.prelude("min", &[x])?,
+            Expr::LargestIn(x) => self.prelude("max", &[x])?,
+            Expr::AverageOf(x) => self.prelude("average", &[x])?,
+            Expr::MedianOf(x) => self.prelude("median", &[x])?,
+            Expr::AbsoluteValueOf(x) => self.prelude("abs", &[x])?,
+            Expr::Round(x) => self.prelude("round", &[x])?,
+            Expr::RoundDown(x) => self.prelude("floor", &[x])?,
+            Expr::RoundUp(x) => self.prelude("ceil", &[x])?,
+            Expr::Lerp(a, b, t) => self.prelude("lerp", &[a, b, t])?,
+            Expr::Clamp(x, low, high) => self.prelude("clamp", &[x, low, high])?,
+            Expr::MapRange(x, a, b, c, d) => self.prelude("map_range", &[x, a, b, c, d])?,
+            Expr::MakeUppercase(x) => self.prelude("uppercase", &[x])?,
+            Expr::MakeLowercase(x) => self.prelude("lowercase", &[x])?,
+            Expr::TrimSpaces(x) => self.prelude("trim", &[x])?,
+            Expr::FirstIn(x) => self.prelude("first", &[x])?,
+            Expr::LastIn(x) => self.prelude("last", &[x])?,
+            Expr::ReverseOf(x) => self.prelude("reverse", &[x])?,
+            Expr::CountOf(x) => self.prelude("length", &[x])?,
+            Expr::JoinWith(list, sep) => self.prelude("join", &[list, sep])?,
+            Expr::SplitBy(s, sep) => self.prelude("split", &[s, sep])?,
+            Expr::Contains(item, coll) => self.prelude("contains", &[item, coll])?,
+            Expr::Remove(item, list) => self.prelude("remove", &[item, list])?,
+            Expr::Append(item, list) => self.prelude("append", &[item, list])?,
+            Expr::InsertAt(item, i, list) => self.prelude("insert_at", &[item, i, list])?,
+            Expr::ToJson(x) => self.prelude("toJson", &[x])?,
+            Expr::ToJsonPretty(x) => format!("$poh.toJson({}, 2)", self.expr(x)?),
+            Expr::ParseJson(x) => self.prelude("parseJson", &[x])?,
+            Expr::NewError { error_type, message } => {
+                format!("$poh.error({}, {})", string(error_type), self.expr(message)?)
+            }
+            Expr::ErrorMessage(x) => self.prelude("errorMessage", &[x])?,
+            Expr::CreateWebServer { .. }
+            | Expr::HtmlResponse(_)
+            | Expr::JsonResponse(_)
+            | Expr::JsonResponseStatus(..)
+            | Expr::RenderTemplate(..)
+            | Expr::RenderTemplateFile(..)
+            | Expr::ErrorResponse(..)
+            | Expr::RequestField(..)
+            | Expr::GetPathParam(_)
+            | Expr::GetRequestHeader(_)
+            | Expr::SetResponseHeaderExpr(..)
+            | Expr::WithEtag(_)
+            | Expr::WithCache(..)
+            | Expr::GetRequestMethod
+            | Expr::GetRequestPath
+            | Expr::GetRemoteAddr
+            | Expr::PreferredResponseType
+            | Expr::StartEventStream { .. }
+            | Expr::ReceiveMessage { .. } => {
+                return self.unsupported(format!("'{}' is a web server expression", dump_expr(e)));
+            }
+            _ => return self.unsupported(format!("'{}'", dump_expr(e))),
+        })
+    }
+}
+
+/// `s` as a JavaScript string literal
+fn string(s: &str) -> String {
+    serde_json::to_string(s).expect("strings always serialize")
+}
+
+fn ident(name: &str) -> String {
+    let name = if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    };
+    match name.starts_with(|c: char| c.is_ascii_digit()) {
+        true => format!("_{}", name),
+        false => name,
+    }
+}
+
+/// The variables `body` sets, in the order it first sets them, without
+/// those in `params` or in the functions it defines. Functions defined
+/// inside its blocks are assigned, so they count too.
+fn assigned(body: &Program, params: &[&str]) -> Vec<String> {
+    fn walk<'a>(body: &'a Program, top: bool, out: &mut Vec<&'a str>) {
+        for s in body {
+            match s {
+                Stmt::Set { name, .. } | Stmt::AskFor { var_name: name, .. } => out.push(name),
+                Stmt::Use {
+                    result_var: Some(name), ..
+                } => out.push(name),
+                Stmt::Destructure { names, .. } => out.extend(names.iter().map(String::as_str)),
+                Stmt::FuncInline { name, .. } | Stmt::FuncBlock { name, .. } if !top => out.push(name),
+                Stmt::IfBlock {
+                    then_body,
+                    otherwise_body,
+                    ..
+                } => {
+                    walk(then_body, false, out);
+                    if let Some(o) = otherwise_body {
+                        walk(o, false, out);
+                    }
+                }
+                Stmt::WhileBlock { body, .. } | Stmt::RepeatBlock { body, .. } | Stmt::RepeatUntil { body, .. } => {
+                    walk(body, false, out)
+                }
+                Stmt::TryCatch {
+                    try_block,
+                    catch_handlers,
+                    finally_block,
+                } => {
+                    walk(try_block, false, out);
+                    for h in catch_handlers {
+                        out.extend(h.var_name.as_deref());
+                        walk(&h.block, false, out);
+                    }
+                    if let Some(f) = finally_block {
+                        walk(f, false, out);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    let constants: Vec<&str> = body
+        .iter()
+        .filter_map(|s| match s {
+            Stmt::GlobalConst { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let mut names = Vec::new();
+    walk(body, true, &mut names);
+    let mut out: Vec<String> = Vec::new();
+    for name in names {
+        if !params.contains(&name) && !constants.contains(&name) && !out.iter().any(|n| n == name) {
+            out.push(name.to_string());
+        }
+    }
+    out
+}
@@ -0,0 +1,3 @@
+//! Programs translated into other languages, for `--transpile`
+
+pub mod js;
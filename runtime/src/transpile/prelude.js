@@ -0,0 +1,357 @@
+// The PohLang runtime the transpiled program below runs on. Values keep
+// the interpreter's rules: numbers only add to numbers, comparisons give
+// 1 or 0, and text is printed the way `Write` prints it.
+"use strict";
+const $poh = (() => {
+  class PohError extends Error {
+    constructor(type, message) {
+      super(message);
+      this.type = type;
+    }
+  }
+  const TUPLE = Symbol("tuple");
+  const isNum = (v) => typeof v === "number";
+  const isList = Array.isArray;
+  const isDict = (v) =>
+    v !== null && typeof v === "object" && !isList(v) && !(v instanceof PohError);
+  const fail = (message) => {
+    throw new PohError("RuntimeError", message);
+  };
+
+  // Rust prints floats without exponents; so does this
+  function plain(n) {
+    let s = String(n);
+    const m = /^(-?)(\d)(?:\.(\d+))?e([+-]\d+)$/.exec(s);
+    if (!m) return s;
+    const [, sign, lead, rest = "", exp] = m;
+    const digits = lead + rest;
+    const point = 1 + Number(exp);
+    if (point <= 0) return sign + "0." + "0".repeat(-point) + digits;
+    if (point >= digits.length) return sign + digits + "0".repeat(point - digits.length);
+    return sign + digits.slice(0, point) + "." + digits.slice(point);
+  }
+  function num(n) {
+    if (!Number.isFinite(n)) return Number.isNaN(n) ? "NaN" : n > 0 ? "inf" : "-inf";
+    if (Number.isSafeInteger(n)) return String(n);
+    const rounded = Number(n.toPrecision(15));
+    return rounded === 0 ? "0" : plain(rounded);
+  }
+  function str(v) {
+    if (v === undefined || v === null) return "None";
+    if (typeof v === "string") return v;
+    if (isNum(v)) return num(v);
+    if (typeof v === "boolean") return v ? "True" : "False";
+    if (typeof v === "function") return `<function ${v.name}>`;
+    if (v instanceof PohError) return `${v.type} occurred: ${v.message}`;
+    if (isList(v)) {
+      const items = v.map(str).join(", ");
+      return v[TUPLE] ? `(${items})` : `[${items}]`;
+    }
+    const keys = Object.keys(v).sort();
+    return `{${keys.map((k) => `${JSON.stringify(k)}: ${str(v[k])}`).join(", ")}}`;
+  }
+  function truthy(v) {
+    if (v === undefined || v === null) return false;
+    if (isNum(v)) return v !== 0;
+    if (typeof v === "string") return v.length > 0;
+    if (typeof v === "boolean") return v;
+    if (isList(v)) return v.length > 0;
+    if (isDict(v)) return Object.keys(v).length > 0;
+    return true;
+  }
+  function eq(a, b) {
+    a = a ?? null;
+    b = b ?? null;
+    if (isList(a) && isList(b)) {
+      return !a[TUPLE] === !b[TUPLE] && a.length === b.length && a.every((x, i) => eq(x, b[i]));
+    }
+    if (isDict(a) && isDict(b)) {
+      const keys = Object.keys(a);
+      return keys.length === Object.keys(b).length && keys.every((k) => k in b && eq(a[k], b[k]));
+    }
+    return typeof a === typeof b && a === b && (typeof a !== "object" || a === null);
+  }
+  const bit = (b) => (b ? 1 : 0);
+  function numbers(phrase, a, b) {
+    if (!isNum(a) || !isNum(b)) fail(`Cannot ${phrase} non-numeric values`);
+  }
+  function numberList(phrase, args) {
+    const items = args.length === 1 && isList(args[0]) ? args[0] : args.flat();
+    items.forEach((v, i) => {
+      if (!isNum(v)) fail(`${phrase} expects numbers; item ${i} was ${str(v)}`);
+    });
+    return items;
+  }
+  function position(items, i, what) {
+    const at = Math.trunc(i) < 0 ? items.length + Math.trunc(i) : Math.trunc(i);
+    if (at < 0 || at >= items.length) {
+      fail(`${what} index out of range: ${Math.trunc(i)} (${what.toLowerCase()} length: ${items.length})`);
+    }
+    return at;
+  }
+  // Prefers a line from `$poh.prompt`, then stdin under Node, then the
+  // browser's prompt(); null means the input has ended
+  function readLine() {
+    if (api.prompt) return api.prompt();
+    if (typeof process !== "undefined" && process.stdin) {
+      const fs = require("fs");
+      const byte = Buffer.alloc(1);
+      let line = "";
+      for (;;) {
+        let n;
+        try {
+          n = fs.readSync(0, byte, 0, 1, null);
+        } catch (e) {
+          if (e.code === "EAGAIN") continue;
+          if (e.code === "EOF") n = 0;
+          else throw e;
+        }
+        if (n === 0) return line === "" ? null : line;
+        const c = byte.toString("latin1");
+        if (c === "\n") return Buffer.from(line, "latin1").toString("utf8").replace(/\r$/, "");
+        line += c;
+      }
+    }
+    return typeof prompt === "function" ? prompt() : null;
+  }
+  function answer(line, expect) {
+    const n = /^\s*[+-]?(\d+\.?\d*|\.\d+)([eE][+-]?\d+)?\s*$/.test(line) ? Number(line) : null;
+    switch (expect) {
+      case "number":
+        return n === null ? undefined : n;
+      case "yes/no": {
+        const word = line.toLowerCase();
+        if (["yes", "y", "true"].includes(word)) return true;
+        if (["no", "n", "false"].includes(word)) return false;
+        return undefined;
+      }
+      case "text":
+        return line;
+      default:
+        return n === null ? line : n;
+    }
+  }
+
+  const defaultOutput = (line) => console.log(line);
+
+  const api = {
+    PohError,
+    /** Where `Write` sends each line */
+    output: defaultOutput,
+    /** Set to a function returning the next line of input, or null at its end */
+    prompt: null,
+    str,
+    truthy,
+    write(v) {
+      api.output(str(v));
+    },
+    /** `Write a, b, c separated by sep ending with end` */
+    writeAll(values, sep, end) {
+      const text = values.map(str).join(str(sep)) + str(end);
+      if (text.endsWith("\n")) api.output(text.slice(0, -1));
+      else if (api.output === defaultOutput && typeof process !== "undefined") process.stdout.write(text);
+      else api.output(text);
+    },
+    log(level, v) {
+      console.error(`${new Date().toISOString().slice(0, 19)}Z ${level} ${str(v)}`);
+    },
+    ask(name, expect) {
+      const describe = { number: "a number", "yes/no": "yes or no" }[expect] || "an answer";
+      for (let attempt = 0; attempt <= 3; attempt++) {
+        const line = readLine();
+        if (line === null) {
+          if (expect === "any") return "";
+          throw new PohError("ValidationError", `Ask for ${name}: input ended before ${describe} was given`);
+        }
+        const value = answer(line, expect);
+        if (value !== undefined) return value;
+        if (attempt < 3) api.write(`'${line}' is not ${describe}. Please try again:`);
+      }
+      throw new PohError("ValidationError", `Ask for ${name}: no valid answer after 4 tries: expected ${describe}`);
+    },
+    add(a, b) {
+      return isNum(a) && isNum(b) ? a + b : str(a) + str(b);
+    },
+    sub(a, b) {
+      numbers("subtract", a, b);
+      return a - b;
+    },
+    mul(a, b) {
+      numbers("multiply", a, b);
+      return a * b;
+    },
+    div(a, b) {
+      numbers("divide", a, b);
+      if (b === 0) fail("Division by zero");
+      return a / b;
+    },
+    and: (a, b) => bit(truthy(a) && truthy(b())),
+    or: (a, b) => bit(truthy(a) || truthy(b())),
+    not: (a) => bit(!truthy(a)),
+    eq: (a, b) => bit(eq(a, b)),
+    ne: (a, b) => bit(!eq(a, b)),
+    lt: (a, b) => bit(isNum(a) && isNum(b) && a < b),
+    le: (a, b) => bit(isNum(a) && isNum(b) && a <= b),
+    gt: (a, b) => bit(isNum(a) && isNum(b) && a > b),
+    ge: (a, b) => bit(isNum(a) && isNum(b) && a >= b),
+    tuple(items) {
+      items[TUPLE] = true;
+      return Object.freeze(items);
+    },
+    index(base, i) {
+      if (isList(base) && isNum(i)) return base[position(base, i, "List")];
+      if (typeof base === "string" && isNum(i)) {
+        const chars = [...base];
+        return chars[position(chars, i, "String")];
+      }
+      if (isDict(base) && typeof i === "string") {
+        if (!(i in base)) fail(`Key not found in dictionary: "${i}"`);
+        return base[i];
+      }
+      fail(`Cannot index ${str(base)} with ${str(i)}`);
+    },
+    /** A copy of `base` with the item at `key` set: values are never shared */
+    setItem(base, key, value) {
+      if (isList(base) && !base[TUPLE] && isNum(key)) {
+        const copy = base.slice();
+        copy[position(copy, key, "List")] = value;
+        return copy;
+      }
+      if (isDict(base) && typeof key === "string") return { ...base, [key]: value };
+      fail(`Cannot set item ${str(key)} of ${str(base)}`);
+    },
+    unpack(v, count) {
+      if (!isList(v)) fail(`Cannot unpack ${str(v)} into ${count} names`);
+      if (v.length !== count) fail(`Cannot unpack ${v.length} values into ${count} names`);
+      return v;
+    },
+    repeatCount: (n) => (isNum(n) ? Math.max(0, Math.trunc(n)) : 0),
+    // Errors
+    error: (type, message) => new PohError(type, str(message)),
+    raise: (v) => (v instanceof PohError ? v : new PohError("RuntimeError", str(v))),
+    /** Whether a caught error is of `type`: by its type, or else by its message */
+    caughtAs(e, type) {
+      if (e instanceof PohError && e.type.toLowerCase() === type.toLowerCase()) return true;
+      return String(e && e.message).toLowerCase().includes(type.toLowerCase());
+    },
+    /** What `if error as e` binds: the error's message */
+    caught: (e) => String(e && e.message !== undefined ? e.message : e),
+    errorMessage: (e) => (e instanceof PohError ? e.message : str(e)),
+    // Built-in functions and phrases
+    range(...args) {
+      if (!args.every(isNum)) return [];
+      const [start, end, step] =
+        args.length === 1 ? [0, args[0], 1] : args.length === 2 ? [args[0], args[1], 1] : args;
+      const out = [];
+      if (step > 0) for (let i = start; i < end; i += step) out.push(i);
+      if (step < 0) for (let i = start; i > end; i += step) out.push(i);
+      return out;
+    },
+    length(v) {
+      if (isList(v)) return v.length;
+      if (typeof v === "string") return [...v].length;
+      if (isDict(v)) return Object.keys(v).length;
+      return 0;
+    },
+    join: (v, sep = "") => (isList(v) ? v.map(str).join(str(sep)) : str(v)),
+    split(s, sep) {
+      s = str(s);
+      sep = str(sep);
+      return sep === "" ? [...s] : s.split(sep);
+    },
+    sum: (...args) => numberList("total of", args).reduce((t, n) => t + n, 0),
+    min(...args) {
+      const xs = numberList("smallest in", args);
+      if (xs.length === 0) fail("smallest in an empty list is undefined");
+      return Math.min(...xs);
+    },
+    max(...args) {
+      const xs = numberList("largest in", args);
+      if (xs.length === 0) fail("largest in an empty list is undefined");
+      return Math.max(...xs);
+    },
+    average(...args) {
+      const xs = numberList("average of", args);
+      return xs.reduce((t, n) => t + n, 0) / xs.length;
+    },
+    median(...args) {
+      const xs = numberList("median of", args).slice().sort((a, b) => a - b);
+      const mid = xs.length >> 1;
+      return xs.length % 2 === 0 ? (xs[mid - 1] + xs[mid]) / 2 : xs[mid];
+    },
+    abs: (n) => (numbers("take the absolute value of", n, 0), Math.abs(n)),
+    // Halves round away from zero, as in Rust
+    round: (n) => (numbers("round", n, 0), Math.sign(n) * Math.round(Math.abs(n))),
+    floor: (n) => (numbers("round down", n, 0), Math.floor(n)),
+    ceil: (n) => (numbers("round up", n, 0), Math.ceil(n)),
+    lerp: (a, b, t) => (numberList("lerp", [a, b, t]), a + (b - a) * t),
+    clamp(x, low, high) {
+      numberList("clamp", [x, low, high]);
+      if (low > high) fail(`clamp: the low end ${num(low)} is above the high end ${num(high)}`);
+      return Math.min(Math.max(x, low), high);
+    },
+    map_range(x, a, b, c, d) {
+      numberList("map_range", [x, a, b, c, d]);
+      if (b - a === 0) fail(`map_range: the range ${num(a)} to ${num(b)} is empty`);
+      return c + ((x - a) * (d - c)) / (b - a);
+    },
+    uppercase: (s) => str(s).toUpperCase(),
+    lowercase: (s) => str(s).toLowerCase(),
+    trim: (s) => str(s).trim(),
+    first(v) {
+      const items = typeof v === "string" ? [...v] : v;
+      if (!isList(items)) fail(`first requires a list or string, got ${str(v)}`);
+      if (items.length === 0) fail(`first called on empty ${isList(v) ? "list" : "string"}`);
+      return items[0];
+    },
+    last(v) {
+      const items = typeof v === "string" ? [...v] : v;
+      if (!isList(items)) fail(`last requires a list or string, got ${str(v)}`);
+      if (items.length === 0) fail(`last called on empty ${isList(v) ? "list" : "string"}`);
+      return items[items.length - 1];
+    },
+    reverse(v) {
+      if (typeof v === "string") return [...v].reverse().join("");
+      if (!isList(v)) fail(`reverse requires a list or string, got ${str(v)}`);
+      return v.slice().reverse();
+    },
+    contains(item, coll) {
+      if (isList(coll)) return coll.some((x) => eq(item, x));
+      if (typeof coll === "string") return coll.includes(str(item));
+      if (isDict(coll)) return typeof item === "string" && item in coll;
+      return false;
+    },
+    remove(item, list) {
+      if (!isList(list) || list[TUPLE]) fail(`remove expects a list as second argument, got ${str(list)}`);
+      const at = list.findIndex((x) => eq(item, x));
+      return at < 0 ? list.slice() : list.filter((_, i) => i !== at);
+    },
+    append(item, list) {
+      if (!isList(list) || list[TUPLE]) fail(`append expects a list as second argument, got ${str(list)}`);
+      return [...list, item];
+    },
+    insert_at(item, index, list) {
+      if (!isList(list) || list[TUPLE]) fail(`insert at expects a list as third argument, got ${str(list)}`);
+      const i = Math.trunc(index);
+      const at = i < 0 ? Math.max(list.length + i, 0) : Math.min(i, list.length);
+      return [...list.slice(0, at), item, ...list.slice(at)];
+    },
+    toJson(v, indent) {
+      const sorted = (x) =>
+        isList(x)
+          ? x.map(sorted)
+          : isDict(x)
+            ? Object.fromEntries(Object.keys(x).sort().map((k) => [k, sorted(x[k])]))
+            : (x ?? null);
+      return JSON.stringify(sorted(v), null, indent);
+    },
+    parseJson(s) {
+      try {
+        return JSON.parse(str(s));
+      } catch (e) {
+        throw new PohError("RuntimeError", `Invalid JSON: ${e.message}`);
+      }
+    },
+  };
+  return api;
+})();
@@ -0,0 +1,112 @@
+//! `make a channel` and the `Send`, `receive from` and `Close` that use it.
+//!
+//! A channel carries values from one part of a program to another, usually
+//! from a `Run in background` block to the code that started it. Every copy
+//! of a channel's handle is the same channel, so any of them can send or
+//! receive. Values arrive in the order they were sent, each one once.
+
+use anyhow::{bail, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub(super) struct Channel<T> {
+    /// `None` once the channel is closed. Each value goes with the size
+    /// it was sent with.
+    sender: Mutex<Option<Sender<(T, usize)>>>,
+    receiver: Mutex<Receiver<(T, usize)>>,
+    /// Total size of the values sent and not yet received
+    queued: AtomicUsize,
+}
+
+impl<T: Send> Channel<T> {
+    pub(super) fn new() -> Arc<Self> {
+        let (sender, receiver) = mpsc::channel();
+        Arc::new(Channel {
+            sender: Mutex::new(Some(sender)),
+            receiver: Mutex::new(receiver),
+            queued: AtomicUsize::new(0),
+        })
+    }
+
+    /// Send `value`, which counts as `size` in `queued_size` until it is
+    /// received
+    pub(super) fn send(&self, value: T, size: usize) -> Result<()> {
+        match &*self.sender.lock().unwrap() {
+            Some(sender) => {
+                self.queued.fetch_add(size, Ordering::SeqCst);
+                // The receiver lives as long as the channel, so this cannot fail
+                let _ = sender.send((value, size));
+                Ok(())
+            }
+            None => bail!("Cannot send to a channel that has been closed"),
+        }
+    }
+
+    /// The next value, waiting up to `timeout` for one, or for ever without
+    /// a timeout. `None` when the time runs out, or when the channel is
+    /// closed and every value sent before that has been received.
+    pub(super) fn receive(&self, timeout: Option<Duration>) -> Option<T> {
+        let receiver = self.receiver.lock().unwrap();
+        let (value, size) = match timeout {
+            None => receiver.recv().ok(),
+            Some(timeout) => receiver.recv_timeout(timeout).ok(),
+        }?;
+        self.queued.fetch_sub(size, Ordering::SeqCst);
+        Some(value)
+    }
+
+    /// The sizes given for the values waiting to be received, added up
+    pub(super) fn queued_size(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Stop further sends; values already sent can still be received
+    pub(super) fn close(&self) {
+        self.sender.lock().unwrap().take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_values_arrive_in_order_across_threads() {
+        let channel = Channel::new();
+        let sending = Arc::clone(&channel);
+        let producer = thread::spawn(move || {
+            for n in 1..=3 {
+                sending.send(n, 1).unwrap();
+            }
+            sending.close();
+        });
+        let received: Vec<i32> = std::iter::from_fn(|| channel.receive(None)).collect();
+        producer.join().unwrap();
+        assert_eq!(received, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_receive_gives_up_after_the_timeout_or_the_close() {
+        let channel: Arc<Channel<i32>> = Channel::new();
+        assert_eq!(channel.receive(Some(Duration::from_millis(10))), None);
+        channel.send(7, 1).unwrap();
+        channel.close();
+        assert!(channel.send(8, 1).is_err());
+        assert_eq!(channel.receive(None), Some(7));
+        assert_eq!(channel.receive(None), None);
+    }
+
+    #[test]
+    fn test_queued_size_counts_what_has_not_been_received() {
+        let channel = Channel::new();
+        channel.send("a", 10).unwrap();
+        channel.send("b", 5).unwrap();
+        assert_eq!(channel.queued_size(), 15);
+        assert_eq!(channel.receive(None), Some("a"));
+        assert_eq!(channel.queued_size(), 5);
+    }
+}
@@ -0,0 +1,80 @@
+//! `Checkpoint X with state Y` and `Restore checkpoint X into variable Y`.
+//!
+//! Checkpoints are kept in one JSON object in `.poh_checkpoint` beside the
+//! program, keyed by name, so a long script that is stopped part way can
+//! pick up from its last checkpoint on the next run. Saving writes the
+//! whole file to a temporary one and renames it over the old, so a script
+//! killed while saving leaves the previous checkpoints as they were.
+
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value as JsonValue};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+const FILE_NAME: &str = ".poh_checkpoint";
+
+fn file_in(dir: &Path) -> PathBuf {
+    dir.join(FILE_NAME)
+}
+
+/// Every checkpoint saved in `dir`; none if there is no file yet
+fn read_all(dir: &Path) -> Result<Map<String, JsonValue>> {
+    let path = file_in(dir);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Map::new()),
+        Err(e) => return Err(anyhow!("Cannot read checkpoints from {}: {}", path.display(), e)),
+    };
+    match serde_json::from_str(&text) {
+        Ok(JsonValue::Object(all)) => Ok(all),
+        _ => Err(anyhow!(
+            "{} is not a checkpoint file; delete it or run with --no-resume",
+            path.display()
+        )),
+    }
+}
+
+/// Save `state` as the checkpoint `name`, keeping the others
+pub(super) fn save(dir: &Path, name: &str, state: JsonValue) -> Result<()> {
+    let mut all = read_all(dir)?;
+    all.insert(name.to_string(), state);
+    let path = file_in(dir);
+    let temp = dir.join(format!("{}.tmp", FILE_NAME));
+    let text = serde_json::to_string_pretty(&JsonValue::Object(all))?;
+    fs::write(&temp, text)
+        .and_then(|_| fs::rename(&temp, &path))
+        .map_err(|e| anyhow!("Cannot save checkpoint '{}' to {}: {}", name, path.display(), e))
+}
+
+/// The state last saved as the checkpoint `name`, if there is one
+pub(super) fn load(dir: &Path, name: &str) -> Result<Option<JsonValue>> {
+    Ok(read_all(dir)?.remove(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_checkpoints_are_kept_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(load(dir.path(), "import").unwrap(), None);
+        save(dir.path(), "import", json!({"row": 10})).unwrap();
+        save(dir.path(), "scrape", json!(["a", "b"])).unwrap();
+        save(dir.path(), "import", json!({"row": 20})).unwrap();
+        assert_eq!(load(dir.path(), "import").unwrap(), Some(json!({"row": 20})));
+        assert_eq!(load(dir.path(), "scrape").unwrap(), Some(json!(["a", "b"])));
+        assert!(!dir.path().join(".poh_checkpoint.tmp").exists());
+    }
+
+    #[test]
+    fn test_a_damaged_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(FILE_NAME), "not json").unwrap();
+        let err = load(dir.path(), "import").unwrap_err().to_string();
+        assert!(err.contains("--no-resume"), "{}", err);
+        assert!(save(dir.path(), "import", json!(1)).is_err());
+    }
+}
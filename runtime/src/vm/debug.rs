@@ -0,0 +1,144 @@
+//! `--debug`: stop before statements and let a command loop look around.
+//!
+//! The `Vm` asks the debugger before each statement whether to stop. After
+//! `Resume::Step` it stops at the next statement, after `Resume::Next` at the
+//! next one that is not inside a deeper function call, and after
+//! `Resume::Continue` only at breakpoints. A new debugger stops before the
+//! first statement. Route handlers and `On shutdown:` blocks run in their own
+//! `Vm`s and never stop.
+
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// How to carry on from a stop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resume {
+    Step,
+    Next,
+    Continue,
+}
+
+/// The command loop run at each stop, from `Vm::enable_debug`
+pub type Prompt = Box<dyn FnMut(&mut Stop<'_>) -> Resume + Send>;
+
+/// What the program looks like where it stopped
+pub struct Stop<'a> {
+    /// `None` for programs parsed without line markers
+    pub line: Option<usize>,
+    /// The statement about to run, as it was written
+    pub statement: String,
+    /// Lines to stop at; changes are kept for the rest of the run
+    pub breakpoints: &'a mut BTreeSet<usize>,
+    scope: &'a dyn Inspect,
+}
+
+impl Stop<'_> {
+    /// Evaluate an expression in the scope the statement runs in
+    pub fn eval(&self, src: &str) -> Result<String> {
+        self.scope.eval(src)
+    }
+
+    /// Locals of the function running, by name; empty at the top level
+    pub fn locals(&self) -> Vec<(String, String)> {
+        self.scope.locals()
+    }
+
+    /// Variables of the program scope, by name
+    pub fn globals(&self) -> Vec<(String, String)> {
+        self.scope.globals()
+    }
+}
+
+/// The scope a stop can see, provided by the `Vm`
+pub(super) trait Inspect {
+    fn eval(&self, src: &str) -> Result<String>;
+    fn locals(&self) -> Vec<(String, String)>;
+    fn globals(&self) -> Vec<(String, String)>;
+}
+
+pub(super) struct Debugger {
+    state: Mutex<State>,
+    /// Shared with `Call` guards, so holding one does not borrow the `Vm`
+    calls: Arc<AtomicUsize>,
+}
+
+struct State {
+    resume: Resume,
+    /// Call depth of the last stop, for `Resume::Next`
+    depth: usize,
+    breakpoints: BTreeSet<usize>,
+    /// Taken while the prompt runs, so a `print` that calls a function does
+    /// not stop inside it
+    prompt: Option<Prompt>,
+}
+
+impl Debugger {
+    pub(super) fn new(prompt: Prompt) -> Self {
+        Debugger {
+            state: Mutex::new(State {
+                resume: Resume::Step,
+                depth: 0,
+                breakpoints: BTreeSet::new(),
+                prompt: Some(prompt),
+            }),
+            calls: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Count a function call until the guard is dropped
+    pub(super) fn call(&self) -> Call {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        Call(Arc::clone(&self.calls))
+    }
+
+    /// Run the prompt if the statement on `line` (0 if unknown) should stop
+    pub(super) fn before(&self, line: usize, statement: impl FnOnce() -> String, scope: &dyn Inspect) {
+        let depth = self.calls.load(Ordering::Relaxed);
+        let (mut prompt, mut breakpoints) = {
+            let mut state = self.state.lock().unwrap();
+            let stop = match state.resume {
+                Resume::Step => true,
+                Resume::Next => depth <= state.depth,
+                Resume::Continue => false,
+            } || state.breakpoints.contains(&line);
+            if !stop {
+                return;
+            }
+            let Some(prompt) = state.prompt.take() else {
+                return;
+            };
+            (prompt, std::mem::take(&mut state.breakpoints))
+        };
+        let resume = prompt(&mut Stop {
+            line: (line > 0).then_some(line),
+            statement: statement(),
+            breakpoints: &mut breakpoints,
+            scope,
+        });
+        let mut state = self.state.lock().unwrap();
+        state.resume = resume;
+        state.depth = depth;
+        state.breakpoints = breakpoints;
+        state.prompt = Some(prompt);
+    }
+}
+
+impl std::fmt::Debug for Debugger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("Debugger")
+            .field("resume", &state.resume)
+            .field("breakpoints", &state.breakpoints)
+            .finish_non_exhaustive()
+    }
+}
+
+pub(super) struct Call(Arc<AtomicUsize>);
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
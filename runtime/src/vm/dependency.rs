@@ -0,0 +1,153 @@
+//! `--tree`: the files a program imports, and the files those import, drawn
+//! as a tree.
+//!
+//! Imports are read from the parsed programs without running anything, so
+//! an import inside a function or an `If` is shown whether or not it would
+//! run. Files are looked for where the VM looks for them. A file imported
+//! again while it is still being followed is marked `[circular]` instead of
+//! being followed again, and one that cannot be read or parsed is marked
+//! too, so the rest of the tree is still shown.
+
+use super::lint::walk;
+use super::vm::{local_module_file, stdlib_module_file};
+use crate::core::encoding::{self, SourceEncoding};
+use crate::parser::ast::{Program, Stmt};
+use anyhow::Result;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A file or system module and what it imports, in the order first imported
+#[derive(Debug, PartialEq)]
+pub struct Module {
+    pub label: String,
+    pub imports: Vec<Module>,
+}
+
+impl Module {
+    fn leaf(label: String) -> Module {
+        Module {
+            label,
+            imports: Vec::new(),
+        }
+    }
+
+    fn fmt_imports(&self, f: &mut fmt::Formatter<'_>, indent: &str) -> fmt::Result {
+        for (n, import) in self.imports.iter().enumerate() {
+            let last = n + 1 == self.imports.len();
+            writeln!(f, "{}{}{}", indent, if last { "└── " } else { "├── " }, import.label)?;
+            import.fmt_imports(f, &format!("{}{}", indent, if last { "    " } else { "│   " }))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.label)?;
+        self.fmt_imports(f, "")
+    }
+}
+
+/// The import tree of the program in `path`. Only the program itself has
+/// to be readable and parse; problems below it are marked in the tree.
+pub fn import_tree(path: &Path, encoding: SourceEncoding) -> Result<Module> {
+    let program = crate::parser::parse(&encoding::read_source(path, encoding)?)?;
+    let canon = canonical(path);
+    let mut following = vec![canon.clone()];
+    Ok(Module {
+        label: path.display().to_string(),
+        imports: imports_of(&program, directory_of(&canon), encoding, &mut following),
+    })
+}
+
+fn imports_of(program: &Program, dir: &Path, encoding: SourceEncoding, following: &mut Vec<PathBuf>) -> Vec<Module> {
+    let mut found: Vec<(String, Option<PathBuf>)> = Vec::new();
+    walk(program, &mut |s| {
+        let import = match s {
+            Stmt::ImportLocal { path } | Stmt::ImportAs { path, .. } | Stmt::ImportItems { path, .. } => {
+                let file = local_module_file(dir, path);
+                let label = local_module_file(Path::new(""), path).display().to_string();
+                (label, Some(file))
+            }
+            Stmt::ImportSystem { name, .. } => {
+                (format!("[system] {}", name), stdlib_module_file(dir, &format!("{}.poh", name)))
+            }
+            _ => return,
+        };
+        if !found.iter().any(|(label, _)| *label == import.0) {
+            found.push(import);
+        }
+    });
+    found
+        .into_iter()
+        .map(|(label, file)| match file {
+            Some(file) => follow(label, &file, encoding, following),
+            // A system module with no .poh file is built in, or empty
+            None => Module::leaf(label),
+        })
+        .collect()
+}
+
+fn follow(label: String, file: &Path, encoding: SourceEncoding, following: &mut Vec<PathBuf>) -> Module {
+    let canon = canonical(file);
+    if following.contains(&canon) {
+        return Module::leaf(format!("{} [circular]", label));
+    }
+    let src = match encoding::read_source(&canon, encoding) {
+        Ok(src) => src,
+        Err(_) if !canon.exists() => return Module::leaf(format!("{} [not found]", label)),
+        Err(_) => return Module::leaf(format!("{} [cannot read]", label)),
+    };
+    let Ok(program) = crate::parser::parse(&src) else {
+        return Module::leaf(format!("{} [does not parse]", label));
+    };
+    following.push(canon.clone());
+    let imports = imports_of(&program, directory_of(&canon), encoding, following);
+    following.pop();
+    Module { label, imports }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn directory_of(file: &Path) -> &Path {
+    file.parent().unwrap_or(Path::new("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_imports_are_followed_and_drawn() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, body: &str| {
+            fs::write(dir.path().join(name), format!("Start Program\n{}End Program\n", body)).unwrap()
+        };
+        write(
+            "main.poh",
+            "Import \"utils\"\nImport \"math.poh\" as M\nMake greet with name\n    Import system \"regex\"\nEnd\nImport \"utils\"\n",
+        );
+        write("utils.poh", "Import square from \"lib/helpers\"\n");
+        fs::create_dir(dir.path().join("lib")).unwrap();
+        write("lib/helpers.poh", "Import \"../utils\"\nImport \"gone\"\n");
+        fs::write(dir.path().join("math.poh"), "Write 1\n").unwrap();
+
+        let tree = import_tree(&dir.path().join("main.poh"), SourceEncoding::Utf8).unwrap();
+        let text = tree.to_string();
+        let drawing: Vec<&str> = text.lines().skip(1).collect();
+        assert_eq!(
+            drawing,
+            [
+                "├── utils.poh",
+                "│   └── lib/helpers.poh",
+                "│       ├── ../utils.poh [circular]",
+                "│       └── gone.poh [not found]",
+                "├── math.poh [does not parse]",
+                "└── [system] regex",
+            ]
+        );
+    }
+}
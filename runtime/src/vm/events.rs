@@ -0,0 +1,97 @@
+//! `Create event emitter X`, the `On event "name" from emitter X:` blocks
+//! that listen to it and the `Emit "name" from X with data Y` that calls them.
+//!
+//! An emitter is a handle: copies share its handlers, so one passed to a
+//! function or kept in a list is the same emitter. Emitting calls the
+//! handlers of that event one after another, in the order they were added,
+//! before the next statement runs. The list is copied out first, so a
+//! handler may add handlers or emit again without waiting on itself.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// What an `On event` block runs with the data of one emit
+pub(super) type Handler<T> = Arc<dyn Fn(T) -> Result<()> + Send + Sync>;
+
+pub(super) struct EventEmitter<T> {
+    handlers: Arc<Mutex<HashMap<String, Vec<Handler<T>>>>>,
+}
+
+impl<T> Clone for EventEmitter<T> {
+    fn clone(&self) -> Self {
+        EventEmitter {
+            handlers: Arc::clone(&self.handlers),
+        }
+    }
+}
+
+impl<T> Default for EventEmitter<T> {
+    fn default() -> Self {
+        EventEmitter {
+            handlers: Arc::default(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for EventEmitter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EventEmitter({} events)", self.handlers.lock().unwrap().len())
+    }
+}
+
+impl<T: Clone> EventEmitter<T> {
+    pub(super) fn on(&self, event: &str, handler: Handler<T>) {
+        self.handlers
+            .lock()
+            .unwrap()
+            .entry(event.to_string())
+            .or_default()
+            .push(handler);
+    }
+
+    /// Call every handler of `event` with `data`; the first that fails stops
+    /// the rest. Returns how many ran.
+    pub(super) fn emit(&self, event: &str, data: T) -> Result<usize> {
+        let handlers = self.handlers.lock().unwrap().get(event).cloned().unwrap_or_default();
+        for (n, handler) in handlers.iter().enumerate() {
+            handler(data.clone()).map_err(|e| anyhow!("In handler {} of event \"{}\": {}", n + 1, event, e))?;
+        }
+        Ok(handlers.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    #[test]
+    fn test_handlers_run_in_order_and_copies_share_them() {
+        let emitter = EventEmitter::<i64>::default();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        for tag in 1..=2 {
+            let seen = Arc::clone(&seen);
+            emitter.clone().on("click", Arc::new(move |data| {
+                seen.lock().unwrap().push((tag, data));
+                Ok(())
+            }));
+        }
+        assert_eq!(emitter.emit("click", 7).unwrap(), 2);
+        assert_eq!(emitter.emit("other", 8).unwrap(), 0);
+        assert_eq!(*seen.lock().unwrap(), [(1, 7), (2, 7)]);
+    }
+
+    #[test]
+    fn test_a_handler_can_emit_and_errors_stop_the_rest() {
+        let emitter = EventEmitter::<i64>::default();
+        let inner = emitter.clone();
+        emitter.on("outer", Arc::new(move |data| inner.emit("inner", data).map(drop)));
+        emitter.on("inner", Arc::new(|data| if data > 1 { bail!("too big") } else { Ok(()) }));
+        emitter.on("inner", Arc::new(|_| panic!("not reached")));
+        let err = emitter.emit("outer", 2).unwrap_err().to_string();
+        assert!(err.contains("handler 1 of event \"outer\""), "{}", err);
+        assert!(err.contains("too big"), "{}", err);
+    }
+}
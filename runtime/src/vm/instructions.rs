@@ -1,5 +1,7 @@
 // Minimal bytecode instruction set and (very) simple encoding.
 
+use crate::core::io::AskType;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Instruction {
     PushNum(f64),
@@ -21,7 +23,7 @@ pub enum Instruction {
     Jump(usize),
     JumpIfFalse(usize),
     WriteTop,
-    AskVar(String),
+    AskVar(String, AskType),
 }
 
 impl Instruction {
@@ -46,7 +48,8 @@ impl Instruction {
             Instruction::Jump(t) => format!("JMP\t{}", t),
             Instruction::JumpIfFalse(t) => format!("JMPF\t{}", t),
             Instruction::WriteTop => "WRITE_TOP".to_string(),
-            Instruction::AskVar(n) => format!("ASK_VAR\t{}", n),
+            Instruction::AskVar(n, AskType::Any) => format!("ASK_VAR\t{}", n),
+            Instruction::AskVar(n, expect) => format!("ASK_VAR\t{}\t{}", n, expect.phrase()),
         }
     }
 
@@ -80,7 +83,11 @@ impl Instruction {
             "JMP" => arg.parse::<usize>().ok().map(Instruction::Jump),
             "JMPF" => arg.parse::<usize>().ok().map(Instruction::JumpIfFalse),
             "WRITE_TOP" => Some(Instruction::WriteTop),
-            "ASK_VAR" => Some(Instruction::AskVar(arg.to_string())),
+            "ASK_VAR" => {
+                let (name, expect) = arg.split_once('\t').unwrap_or((arg, ""));
+                let expect = AskType::from_phrase(expect).unwrap_or(AskType::Any);
+                Some(Instruction::AskVar(name.to_string(), expect))
+            }
             _ => None,
         }
     }
@@ -0,0 +1,216 @@
+//! Warnings about programs that run but probably don't do what was meant.
+//!
+//! They go to stderr before the program starts and never stop it running.
+
+use super::types::{BaseType, Types};
+use super::vm::{dump_expr, BUILTIN_FUNCTIONS};
+use crate::parser::ast::{Expr, Program, Stmt};
+use std::collections::{HashMap, HashSet};
+
+/// Warn about each bare `Use`/`Call` of a built-in that returns a value.
+/// Since 0.7 those statements discard the result instead of printing it.
+pub fn discarded_results(prog: &Program) -> Vec<String> {
+    let mut defined = HashSet::new();
+    walk(prog, &mut |s| {
+        if let Stmt::FuncInline { name, .. } | Stmt::FuncBlock { name, .. } = s {
+            defined.insert(name.clone());
+        }
+    });
+    let mut warnings = Vec::new();
+    walk(prog, &mut |s| {
+        if let Stmt::Use {
+            name, result_var: None, ..
+        } = s
+        {
+            if BUILTIN_FUNCTIONS.contains(&name.as_str()) && !defined.contains(name) {
+                warnings.push(format!(
+                    "Warning: 'Use {}' discards the value {} returns; write 'Write {} with ...' to print it",
+                    name, name, name
+                ));
+            }
+        }
+    });
+    warnings
+}
+
+/// Warn about `Type` aliases and declarations whose type does not exist, and
+/// about each `Set` of a declared variable to a literal of another type
+pub fn type_mismatches(prog: &Program) -> Vec<String> {
+    let mut types = Types::default();
+    walk(prog, &mut |s| match s {
+        Stmt::TypeAlias { name, base_type } => types.alias(name, base_type),
+        Stmt::DeclareType { name, type_name } => types.declare(name, type_name),
+        _ => {}
+    });
+    let mut warnings: Vec<String> = types.unknown().into_iter().map(|p| format!("Warning: {}", p)).collect();
+    walk(prog, &mut |s| {
+        if let Stmt::Set { name, value } = s {
+            if let Some(problem) = literal_type(value).and_then(|found| types.mismatch(name, found)) {
+                warnings.push(format!("Warning: {}", problem));
+            }
+        }
+    });
+    warnings
+}
+
+/// With `--report-const-candidates`, a hint for each `Set` at the top of
+/// the program to a literal when nothing else in the program assigns that
+/// variable, so it could be a `Set constant`
+pub fn const_candidates(prog: &Program) -> Vec<String> {
+    let mut assignments: HashMap<String, usize> = HashMap::new();
+    walk(prog, &mut |s| {
+        for name in assigned_names(s) {
+            *assignments.entry(name.to_string()).or_default() += 1;
+        }
+    });
+    prog.iter()
+        .filter_map(|s| match s {
+            Stmt::Set { name, value } if value.is_literal() && assignments[name] == 1 => Some(format!(
+                "Hint: {} is set once and never changed; 'Set constant {} to {}' saves looking it up at each use",
+                name,
+                name,
+                dump_expr(value)
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The variables a statement assigns, not counting those in its body
+fn assigned_names(s: &Stmt) -> Vec<&str> {
+    match s {
+        Stmt::Set { name, .. }
+        | Stmt::SetItem { name, .. }
+        | Stmt::GlobalConst { name, .. }
+        | Stmt::FuncInline { name, .. }
+        | Stmt::FuncBlock { name, .. }
+        | Stmt::EventEmitter { name, .. }
+        | Stmt::Task { name, .. }
+        | Stmt::AskFor { var_name: name, .. }
+        | Stmt::ReadLineLoop { var_name: name, .. }
+        | Stmt::ForEach { var_name: name, .. }
+        | Stmt::RestoreCheckpoint { into_var: name, .. } => vec![name],
+        Stmt::Measure {
+            store_in: Some(name), ..
+        }
+        | Stmt::Background { name: Some(name), .. } => vec![name],
+        Stmt::Destructure { names, .. } => names.iter().map(String::as_str).collect(),
+        Stmt::Use {
+            result_var: Some(name), ..
+        } => vec![name],
+        Stmt::TryCatch { catch_handlers, .. } => catch_handlers.iter().filter_map(|h| h.var_name.as_deref()).collect(),
+        Stmt::Retry {
+            on_failure: Some((Some(name), _)),
+            ..
+        } => vec![name],
+        _ => Vec::new(),
+    }
+}
+
+/// Warn about each `get path parameter "name"` in a route handler when the
+/// route's path has no `:name` segment, so the value would always be missing
+#[cfg(feature = "web")]
+pub(super) fn unknown_path_parameters(path: &str, method: &str, line: usize, handler: &Program) -> Vec<String> {
+    let params: Vec<&str> = path.split('/').filter_map(|part| part.strip_prefix(':')).collect();
+    let has = if params.is_empty() {
+        "has none".to_string()
+    } else {
+        let quoted: Vec<String> = params.iter().map(|p| format!("'{}'", p)).collect();
+        format!("only has {}", quoted.join(", "))
+    };
+    let mut seen = HashSet::new();
+    path_parameters_used(handler)
+        .into_iter()
+        .filter(|name| !params.contains(&name.as_str()) && seen.insert(name.clone()))
+        .map(|name| {
+            format!(
+                "Warning: line {}: the handler for {} {} asks for path parameter '{}', but the path {}",
+                line, method, path, name, has
+            )
+        })
+        .collect()
+}
+
+/// Names passed as literals to `get path parameter` anywhere in `prog`. The
+/// derived Debug text reaches every nested expression, so it is searched
+/// instead of matching each kind of expression that could hold one.
+#[cfg(feature = "web")]
+fn path_parameters_used(prog: &Program) -> Vec<String> {
+    format!("{:?}", prog)
+        .split("GetPathParam(Str(\"")
+        .skip(1)
+        .filter_map(|rest| rest.split_once("\")").map(|(name, _)| name.to_string()))
+        .collect()
+}
+
+fn literal_type(e: &Expr) -> Option<BaseType> {
+    match e {
+        Expr::Int(_) | Expr::Num(_) => Some(BaseType::Number),
+        Expr::Str(_) => Some(BaseType::String),
+        Expr::Bool(_) => Some(BaseType::Boolean),
+        Expr::ListLit(_) | Expr::TupleLit(_) => Some(BaseType::List),
+        Expr::DictLit(_) => Some(BaseType::Dict),
+        _ => None,
+    }
+}
+
+pub(super) fn walk(prog: &Program, f: &mut impl FnMut(&Stmt)) {
+    for s in prog {
+        f(s);
+        match s {
+            Stmt::IfBlock {
+                then_body,
+                otherwise_body,
+                ..
+            } => {
+                walk(then_body, f);
+                if let Some(b) = otherwise_body {
+                    walk(b, f);
+                }
+            }
+            Stmt::FuncBlock { body, .. }
+            | Stmt::WhileBlock { body, .. }
+            | Stmt::RepeatBlock { body, .. }
+            | Stmt::ReadLineLoop { body, .. }
+            | Stmt::ForEach { body, .. }
+            | Stmt::RepeatUntil { body, .. }
+            | Stmt::AddRoute { handler: body, .. }
+            | Stmt::Route { handler: body, .. }
+            | Stmt::OnShutdown { body }
+            | Stmt::On { body, .. }
+            | Stmt::BeforeEachRoute { body }
+            | Stmt::RouteGroup { body, .. }
+            | Stmt::GroupMiddleware { body }
+            | Stmt::AfterEachRoute { body }
+            | Stmt::Benchmark { body, .. }
+            | Stmt::Measure { body, .. }
+            | Stmt::Task { body, .. }
+            | Stmt::Background { body, .. } => walk(body, f),
+            Stmt::Parallel { branches } => {
+                for b in branches {
+                    walk(b, f);
+                }
+            }
+            Stmt::TryCatch {
+                try_block,
+                catch_handlers,
+                finally_block,
+            } => {
+                walk(try_block, f);
+                for h in catch_handlers {
+                    walk(&h.block, f);
+                }
+                if let Some(b) = finally_block {
+                    walk(b, f);
+                }
+            }
+            Stmt::Retry { body, on_failure, .. } => {
+                walk(body, f);
+                if let Some((_, b)) = on_failure {
+                    walk(b, f);
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,5 +1,18 @@
+mod channel;
+mod checkpoint;
+pub mod debug;
+pub mod dependency;
+mod events;
 pub mod instructions;
+pub mod lint;
+mod profile;
+mod resolve;
+pub mod stats;
+mod task;
+mod trace;
+mod types;
 #[allow(clippy::module_inception)]
 pub mod vm;
 
-pub use vm::{compile, Vm};
+pub use stats::ExecutionStats;
+pub use vm::{compile, compile_strict, ProgramValue, Vm};
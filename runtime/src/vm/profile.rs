@@ -0,0 +1,296 @@
+//! `Benchmark "name":` blocks, and the call tree behind `--bench-flamegraph`.
+//!
+//! While a benchmark runs with profiling on, each call of a user-defined or
+//! built-in function adds its time to a node under the function that called
+//! it. Calls of the same function from the same caller share a node. The
+//! tree of every benchmark so far is drawn as a flame graph: one box per
+//! node, as wide as its share of the time, stacked on its caller.
+
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const WIDTH: f64 = 1200.0;
+const ROW: f64 = 16.0;
+/// Boxes narrower than this many pixels are left out
+const MIN_WIDTH: f64 = 0.1;
+
+#[derive(Default)]
+pub(super) struct Profiler {
+    /// Where to write the flame graph; `None` when profiling is off
+    output: Option<PathBuf>,
+    /// Whether a benchmark is running, so calls outside one cost a load
+    active: AtomicBool,
+    /// Shared with `Span` guards, so holding one does not borrow the `Vm`
+    tree: Arc<Mutex<Tree>>,
+}
+
+#[derive(Debug)]
+struct Node {
+    name: String,
+    time: Duration,
+    children: Vec<usize>,
+}
+
+/// Node 0 is the root, standing for all benchmarks
+#[derive(Debug)]
+struct Tree {
+    nodes: Vec<Node>,
+    /// Nodes of the calls running, innermost last
+    open: Vec<usize>,
+}
+
+impl Default for Tree {
+    fn default() -> Self {
+        Tree {
+            nodes: vec![Node {
+                name: "all".to_string(),
+                time: Duration::ZERO,
+                children: Vec::new(),
+            }],
+            open: Vec::new(),
+        }
+    }
+}
+
+impl Tree {
+    /// The child of the innermost open node called `name`, made if needed
+    fn enter(&mut self, name: &str) -> usize {
+        let parent = self.open.last().copied().unwrap_or(0);
+        let found = self.nodes[parent]
+            .children
+            .iter()
+            .copied()
+            .find(|&c| self.nodes[c].name == name);
+        let node = found.unwrap_or_else(|| {
+            self.nodes.push(Node {
+                name: name.to_string(),
+                time: Duration::ZERO,
+                children: Vec::new(),
+            });
+            let id = self.nodes.len() - 1;
+            self.nodes[parent].children.push(id);
+            id
+        });
+        self.open.push(node);
+        node
+    }
+
+    fn leave(&mut self, node: usize, elapsed: Duration) {
+        self.nodes[node].time += elapsed;
+        if self.open.last() == Some(&node) {
+            self.open.pop();
+        }
+        if self.open.is_empty() {
+            self.nodes[0].time += elapsed;
+        }
+    }
+
+    fn depth(&self, node: usize) -> usize {
+        1 + self.nodes[node]
+            .children
+            .iter()
+            .map(|&c| self.depth(c))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl Profiler {
+    pub(super) fn new(output: PathBuf) -> Self {
+        Profiler {
+            output: Some(output),
+            ..Profiler::default()
+        }
+    }
+
+    pub(super) fn enabled(&self) -> bool {
+        self.output.is_some()
+    }
+
+    /// Whether calls are being recorded, that is a benchmark is running
+    pub(super) fn recording(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Start timing a benchmark block; its calls are recorded under it
+    pub(super) fn benchmark(&self, name: &str) -> Option<Span> {
+        self.enabled().then(|| {
+            self.active.store(true, Ordering::Relaxed);
+            self.open(name)
+        })
+    }
+
+    /// Start timing a call, if a benchmark is running
+    pub(super) fn call(&self, name: &str) -> Option<Span> {
+        self.recording().then(|| self.open(name))
+    }
+
+    fn open(&self, name: &str) -> Span {
+        let node = self.tree.lock().unwrap().enter(name);
+        Span {
+            tree: Arc::clone(&self.tree),
+            node,
+            start: Instant::now(),
+        }
+    }
+
+    /// After a benchmark block: stop recording and write the flame graph
+    /// of every benchmark so far
+    pub(super) fn finish(&self) -> Result<Option<&Path>> {
+        let Some(path) = &self.output else {
+            return Ok(None);
+        };
+        let tree = self.tree.lock().unwrap();
+        if tree.open.is_empty() {
+            self.active.store(false, Ordering::Relaxed);
+        }
+        std::fs::write(path, flame_graph(&tree))
+            .with_context(|| format!("Failed to write flame graph '{}'", path.display()))?;
+        Ok(Some(path))
+    }
+}
+
+/// A running call or benchmark; its time is added to the tree when dropped
+pub(super) struct Span {
+    tree: Arc<Mutex<Tree>>,
+    node: usize,
+    start: Instant,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        self.tree.lock().unwrap().leave(self.node, elapsed);
+    }
+}
+
+/// The tree as an SVG flame graph, callers below the functions they call
+fn flame_graph(tree: &Tree) -> String {
+    let depth = tree.depth(0);
+    let height = depth as f64 * ROW + 2.0 * ROW;
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<?xml version=\"1.0\" standalone=\"no\"?>\n\
+         <svg version=\"1.1\" width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\" \
+         font-family=\"Verdana\" font-size=\"12\">",
+        WIDTH, height
+    );
+    let _ = writeln!(
+        svg,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\" font-size=\"16\">Flame Graph</text>",
+        WIDTH / 2.0,
+        ROW
+    );
+    let total = tree.nodes[0].time.as_secs_f64();
+    let scale = if total > 0.0 { WIDTH / total } else { 0.0 };
+    draw(tree, 0, 0.0, 0, scale, total, height, &mut svg);
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw(tree: &Tree, node: usize, x: f64, level: usize, scale: f64, total: f64, height: f64, svg: &mut String) {
+    let n = &tree.nodes[node];
+    let secs = n.time.as_secs_f64();
+    let width = secs * scale;
+    if width < MIN_WIDTH && node != 0 {
+        return;
+    }
+    let y = height - (level as f64 + 1.0) * ROW;
+    let share = if total > 0.0 { secs / total * 100.0 } else { 100.0 };
+    let name = escape(&n.name);
+    let _ = writeln!(
+        svg,
+        "<g><title>{} ({:.3} ms, {:.2}%)</title>\
+         <rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\" rx=\"2\"/>\
+         <text x=\"{:.1}\" y=\"{:.1}\">{}</text></g>",
+        name,
+        secs * 1000.0,
+        share,
+        x,
+        y,
+        width.max(MIN_WIDTH),
+        ROW - 1.0,
+        color(&n.name),
+        x + 3.0,
+        y + ROW - 4.0,
+        escape(&fit(&n.name, width))
+    );
+    let mut child_x = x;
+    for &c in &n.children {
+        draw(tree, c, child_x, level + 1, scale, total, height, svg);
+        child_x += tree.nodes[c].time.as_secs_f64() * scale;
+    }
+}
+
+/// A warm colour that stays the same for a name across runs
+fn color(name: &str) -> String {
+    let hash = name
+        .bytes()
+        .fold(2166136261u32, |h, b| (h ^ b as u32).wrapping_mul(16777619));
+    let r = 205 + (hash % 50);
+    let g = (hash >> 8) % 230;
+    let b = (hash >> 16) % 55;
+    format!("rgb({},{},{})", r, g, b)
+}
+
+/// As much of the label as fits in the box, at about 7 pixels a letter
+fn fit(label: &str, width: f64) -> String {
+    let room = ((width - 6.0) / 7.0).max(0.0) as usize;
+    if label.chars().count() <= room {
+        label.to_string()
+    } else if room > 2 {
+        let kept: String = label.chars().take(room - 2).collect();
+        format!("{}..", kept)
+    } else {
+        String::new()
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calls_from_the_same_caller_share_a_node() {
+        let mut tree = Tree::default();
+        let bench = tree.enter("hot loop");
+        for _ in 0..3 {
+            let f = tree.enter("f");
+            let len = tree.enter("length");
+            tree.leave(len, Duration::from_millis(1));
+            tree.leave(f, Duration::from_millis(2));
+        }
+        tree.leave(bench, Duration::from_millis(10));
+
+        assert_eq!(tree.nodes.len(), 4);
+        assert_eq!(tree.nodes[0].time, Duration::from_millis(10));
+        assert_eq!(tree.nodes[2].time, Duration::from_millis(6));
+        assert_eq!(tree.nodes[3].time, Duration::from_millis(3));
+        assert_eq!(tree.depth(0), 4);
+
+        let svg = flame_graph(&tree);
+        assert_eq!(svg.matches("<rect").count(), 4);
+        assert!(svg.contains("<title>f (6.000 ms, 60.00%)</title>"), "{}", svg);
+    }
+
+    #[test]
+    fn test_labels_are_escaped_and_cut_to_fit() {
+        assert_eq!(escape("a<b & \"c\""), "a&lt;b &amp; &quot;c&quot;");
+        assert_eq!(fit("length", 100.0), "length");
+        assert_eq!(fit("a long function name", 50.0), "a lo..");
+        assert_eq!(fit("f", 5.0), "");
+    }
+}
@@ -0,0 +1,391 @@
+//! Slot resolution for `Make ... End` function bodies.
+//!
+//! Parameters and every name a body assigns get a numeric slot, so the VM can
+//! read and write them by index instead of hashing into scope maps. Any other
+//! identifier is rewritten to `Expr::Global`. Bodies that define functions or
+//! event handlers of their own are left alone: those capture the frame's scope
+//! by name.
+
+use crate::parser::ast::{AggField, Expr, Param, Program, Stmt};
+use std::collections::HashMap;
+
+/// Slot assignment for one function body
+#[derive(Debug, Default)]
+pub struct SlotLayout {
+    names: Vec<String>,
+    index: HashMap<String, u16>,
+}
+
+impl SlotLayout {
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn slot(&self, name: &str) -> Option<u16> {
+        self.index.get(name).copied()
+    }
+
+    pub fn name(&self, slot: u16) -> &str {
+        &self.names[slot as usize]
+    }
+
+    fn add(&mut self, name: &str) {
+        if !self.index.contains_key(name) {
+            self.index.insert(name.to_string(), self.names.len() as u16);
+            self.names.push(name.to_string());
+        }
+    }
+}
+
+/// Rewrite a function body to use slots, or `None` if it has to stay dynamic
+pub fn resolve_body(params: &[Param], body: &Program) -> Option<(Program, SlotLayout)> {
+    if defines_functions(body) {
+        return None;
+    }
+    let mut layout = SlotLayout::default();
+    for p in params {
+        layout.add(&p.name);
+    }
+    collect_assigned(body, &mut layout);
+    if layout.len() > u16::MAX as usize {
+        return None;
+    }
+    let body = body.iter().map(|s| resolve_stmt(s, &layout)).collect();
+    Some((body, layout))
+}
+
+fn defines_functions(body: &Program) -> bool {
+    body.iter().any(|s| match s {
+        Stmt::FuncInline { .. } | Stmt::FuncBlock { .. } | Stmt::On { .. } => true,
+        Stmt::IfBlock {
+            then_body,
+            otherwise_body,
+            ..
+        } => defines_functions(then_body) || otherwise_body.as_ref().is_some_and(defines_functions),
+        Stmt::WhileBlock { body, .. }
+        | Stmt::RepeatBlock { body, .. }
+        | Stmt::RepeatUntil { body, .. }
+        | Stmt::ReadLineLoop { body, .. }
+        | Stmt::ForEach { body, .. }
+        | Stmt::Benchmark { body, .. }
+        | Stmt::Measure { body, .. } => defines_functions(body),
+        _ => false,
+    })
+}
+
+fn collect_assigned(body: &Program, layout: &mut SlotLayout) {
+    for s in body {
+        match s {
+            Stmt::Set { name, .. } => layout.add(name),
+            Stmt::Destructure { names, .. } => names.iter().for_each(|n| layout.add(n)),
+            Stmt::AskFor { var_name, .. } => layout.add(var_name),
+            Stmt::Use {
+                result_var: Some(var), ..
+            } => layout.add(var),
+            Stmt::ReadLineLoop { var_name, body } | Stmt::ForEach { var_name, body, .. } => {
+                layout.add(var_name);
+                collect_assigned(body, layout);
+            }
+            Stmt::IfBlock {
+                then_body,
+                otherwise_body,
+                ..
+            } => {
+                collect_assigned(then_body, layout);
+                if let Some(eb) = otherwise_body {
+                    collect_assigned(eb, layout);
+                }
+            }
+            Stmt::WhileBlock { body, .. }
+            | Stmt::RepeatBlock { body, .. }
+            | Stmt::RepeatUntil { body, .. }
+            | Stmt::Benchmark { body, .. } => collect_assigned(body, layout),
+            Stmt::Measure { store_in, body, .. } => {
+                if let Some(var) = store_in {
+                    layout.add(var);
+                }
+                collect_assigned(body, layout);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn resolve_block(body: &Program, layout: &SlotLayout) -> Program {
+    body.iter().map(|s| resolve_stmt(s, layout)).collect()
+}
+
+fn resolve_stmt(s: &Stmt, layout: &SlotLayout) -> Stmt {
+    let r = |e: &Expr| resolve_expr(e, layout);
+    match s {
+        Stmt::Write(e) => Stmt::Write(r(e)),
+        Stmt::MultiWrite { exprs, sep, end } => Stmt::MultiWrite {
+            exprs: exprs.iter().map(r).collect(),
+            sep: sep.as_ref().map(r),
+            end: end.as_ref().map(r),
+        },
+        Stmt::Respond(e) => Stmt::Respond(r(e)),
+        Stmt::TemplateDirectory(e) => Stmt::TemplateDirectory(r(e)),
+        Stmt::Set { name, value } => match layout.slot(name) {
+            Some(slot) => Stmt::SetLocal {
+                slot,
+                value: r(value),
+            },
+            None => s.clone(),
+        },
+        Stmt::SetItem { name, key, value } => Stmt::SetItem {
+            name: name.clone(),
+            key: r(key),
+            value: r(value),
+        },
+        Stmt::Destructure { names, value } => Stmt::Destructure {
+            names: names.clone(),
+            value: r(value),
+        },
+        Stmt::IfInline {
+            cond,
+            then_write,
+            otherwise_write,
+        } => Stmt::IfInline {
+            cond: r(cond),
+            then_write: r(then_write),
+            otherwise_write: otherwise_write.as_ref().map(r),
+        },
+        Stmt::IfBlock {
+            cond,
+            then_body,
+            otherwise_body,
+        } => Stmt::IfBlock {
+            cond: r(cond),
+            then_body: resolve_block(then_body, layout),
+            otherwise_body: otherwise_body.as_ref().map(|b| resolve_block(b, layout)),
+        },
+        Stmt::WhileBlock { cond, body } => Stmt::WhileBlock {
+            cond: r(cond),
+            body: resolve_block(body, layout),
+        },
+        Stmt::RepeatBlock { count, body } => Stmt::RepeatBlock {
+            count: r(count),
+            body: resolve_block(body, layout),
+        },
+        Stmt::ReadLineLoop { var_name, body } => Stmt::ReadLineLoop {
+            var_name: var_name.clone(),
+            body: resolve_block(body, layout),
+        },
+        Stmt::ForEach { var_name, items, body } => Stmt::ForEach {
+            var_name: var_name.clone(),
+            items: r(items),
+            body: resolve_block(body, layout),
+        },
+        Stmt::RepeatUntil { cond, body } => Stmt::RepeatUntil {
+            cond: r(cond),
+            body: resolve_block(body, layout),
+        },
+        Stmt::Benchmark { name, body } => Stmt::Benchmark {
+            name: r(name),
+            body: resolve_block(body, layout),
+        },
+        Stmt::Measure { name, store_in, body } => Stmt::Measure {
+            name: r(name),
+            store_in: store_in.clone(),
+            body: resolve_block(body, layout),
+        },
+        Stmt::PrintTable { headers, rows } => Stmt::PrintTable {
+            headers: r(headers),
+            rows: r(rows),
+        },
+        Stmt::Use { name, args, result_var } => Stmt::Use {
+            name: name.clone(),
+            args: args.iter().map(r).collect(),
+            result_var: result_var.clone(),
+        },
+        Stmt::Return(e) => Stmt::Return(e.as_ref().map(r)),
+        Stmt::Throw(e) => Stmt::Throw(r(e)),
+        Stmt::LogMessage { level, message } => Stmt::LogMessage {
+            level: *level,
+            message: r(message),
+        },
+        Stmt::Evaluate(e) => Stmt::Evaluate(r(e)),
+        Stmt::Require { value, schema } => Stmt::Require {
+            value: r(value),
+            schema: r(schema),
+        },
+        Stmt::Assert { deep_equal, left, right } => Stmt::Assert {
+            deep_equal: *deep_equal,
+            left: r(left),
+            right: r(right),
+        },
+        Stmt::Emit { emitter, event, data } => Stmt::Emit {
+            emitter: emitter.clone(),
+            event: event.clone(),
+            data: r(data),
+        },
+        _ => s.clone(),
+    }
+}
+
+/// Rewrite identifiers in the expressions the frame evaluator walks itself.
+/// Everything else is kept as written and still resolves by name.
+fn resolve_expr(e: &Expr, layout: &SlotLayout) -> Expr {
+    let r = |e: &Expr| Box::new(resolve_expr(e, layout));
+    match e {
+        Expr::Ident(name) => match layout.slot(name) {
+            Some(slot) => Expr::Local(slot),
+            None => Expr::Global(name.clone()),
+        },
+        Expr::Plus(..) | Expr::Minus(..) | Expr::Times(..) | Expr::DividedBy(..) => {
+            let (left, terms) = e.arithmetic_chain();
+            terms.into_iter().fold(resolve_expr(left, layout), |a, (op, b)| {
+                let (a, b) = (Box::new(a), r(b));
+                match op {
+                    Expr::Plus(..) => Expr::Plus(a, b),
+                    Expr::Minus(..) => Expr::Minus(a, b),
+                    Expr::Times(..) => Expr::Times(a, b),
+                    _ => Expr::DividedBy(a, b),
+                }
+            })
+        }
+        Expr::And(a, b) => Expr::And(r(a), r(b)),
+        Expr::Or(a, b) => Expr::Or(r(a), r(b)),
+        Expr::Conditional(c, a, b) => Expr::Conditional(r(c), r(a), r(b)),
+        Expr::Not(a) => Expr::Not(r(a)),
+        Expr::Cmp(op, a, b) => Expr::Cmp(op.clone(), r(a), r(b)),
+        Expr::Call { name, args } => Expr::Call {
+            name: name.clone(),
+            args: args.iter().map(|a| resolve_expr(a, layout)).collect(),
+        },
+        Expr::ApplyInParallel(name, items, workers) => {
+            Expr::ApplyInParallel(name.clone(), r(items), workers.as_ref().map(|n| r(n)))
+        }
+        Expr::TimedCall(name, args) => {
+            Expr::TimedCall(name.clone(), args.iter().map(|a| resolve_expr(a, layout)).collect())
+        }
+        Expr::ListLit(items) => {
+            Expr::ListLit(items.iter().map(|i| resolve_expr(i, layout)).collect())
+        }
+        Expr::TupleLit(items) => {
+            Expr::TupleLit(items.iter().map(|i| resolve_expr(i, layout)).collect())
+        }
+        Expr::DictLit(pairs) => Expr::DictLit(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.clone(), resolve_expr(v, layout)))
+                .collect(),
+        ),
+        Expr::Index(a, b) => Expr::Index(r(a), r(b)),
+        Expr::TotalOf(a) => Expr::TotalOf(r(a)),
+        Expr::SmallestIn(a) => Expr::SmallestIn(r(a)),
+        Expr::LargestIn(a) => Expr::LargestIn(r(a)),
+        Expr::AverageOf(a) => Expr::AverageOf(r(a)),
+        Expr::MedianOf(a) => Expr::MedianOf(r(a)),
+        Expr::VarianceOf(a) => Expr::VarianceOf(r(a)),
+        Expr::StdDevOf(a) => Expr::StdDevOf(r(a)),
+        Expr::AbsoluteValueOf(a) => Expr::AbsoluteValueOf(r(a)),
+        Expr::Round(a) => Expr::Round(r(a)),
+        Expr::RoundDown(a) => Expr::RoundDown(r(a)),
+        Expr::RoundUp(a) => Expr::RoundUp(r(a)),
+        Expr::Lerp(a, b, c) => Expr::Lerp(r(a), r(b), r(c)),
+        Expr::Clamp(a, b, c) => Expr::Clamp(r(a), r(b), r(c)),
+        Expr::MapRange(a, b, c, d, e) => Expr::MapRange(r(a), r(b), r(c), r(d), r(e)),
+        Expr::ReceiveMessage { socket, timeout } => Expr::ReceiveMessage {
+            socket: r(socket),
+            timeout: timeout.as_deref().map(r),
+        },
+        Expr::ReceiveFrom { channel, timeout } => Expr::ReceiveFrom {
+            channel: r(channel),
+            timeout: timeout.as_deref().map(r),
+        },
+        Expr::MakeUppercase(a) => Expr::MakeUppercase(r(a)),
+        Expr::MakeLowercase(a) => Expr::MakeLowercase(r(a)),
+        Expr::TrimSpaces(a) => Expr::TrimSpaces(r(a)),
+        Expr::FirstIn(a) => Expr::FirstIn(r(a)),
+        Expr::LastIn(a) => Expr::LastIn(r(a)),
+        Expr::ReverseOf(a) => Expr::ReverseOf(r(a)),
+        Expr::CountOf(a) => Expr::CountOf(r(a)),
+        Expr::TypeOf(a) => Expr::TypeOf(r(a)),
+        Expr::WaitFor(a) => Expr::WaitFor(r(a)),
+        Expr::JoinWith(a, b) => Expr::JoinWith(r(a), r(b)),
+        Expr::SplitBy(a, b) => Expr::SplitBy(r(a), r(b)),
+        Expr::IndentBy(a, b) => Expr::IndentBy(r(a), r(b)),
+        Expr::JsonQuery(a, b) => Expr::JsonQuery(r(a), r(b)),
+        Expr::Dedent(a) => Expr::Dedent(r(a)),
+        Expr::WrapText(a, b) => Expr::WrapText(r(a), r(b)),
+        Expr::Contains(a, b) => Expr::Contains(r(a), r(b)),
+        Expr::Remove(a, b) => Expr::Remove(r(a), r(b)),
+        Expr::Append(a, b) => Expr::Append(r(a), r(b)),
+        Expr::InsertAt(a, b, c) => Expr::InsertAt(r(a), r(b), r(c)),
+        Expr::SetLit(items) => Expr::SetLit(items.iter().map(|i| resolve_expr(i, layout)).collect()),
+        Expr::SetUnion(a, b) => Expr::SetUnion(r(a), r(b)),
+        Expr::SetIntersection(a, b) => Expr::SetIntersection(r(a), r(b)),
+        Expr::SetDifference(a, b) => Expr::SetDifference(r(a), r(b)),
+        Expr::SetContains(a, b) => Expr::SetContains(r(a), r(b)),
+        Expr::SetToList(a) => Expr::SetToList(r(a)),
+        Expr::ListToSet(a) => Expr::ListToSet(r(a)),
+        Expr::Pick(a, keys) => Expr::Pick(r(a), keys.clone()),
+        Expr::Omit(a, keys) => Expr::Omit(r(a), keys.clone()),
+        Expr::FindFirst(a, b) => Expr::FindFirst(r(a), r(b)),
+        Expr::FindAll(a, b) => Expr::FindAll(r(a), r(b)),
+        Expr::FindIndex(a, b) => Expr::FindIndex(r(a), r(b)),
+        Expr::GroupConcat(a, b) => Expr::GroupConcat(r(a), r(b)),
+        Expr::Pluck(a, b) => Expr::Pluck(r(a), r(b)),
+        Expr::Aggregate(a, fields) => Expr::Aggregate(
+            r(a),
+            fields
+                .iter()
+                .map(|f| AggField {
+                    field: r(&f.field),
+                    ..f.clone()
+                })
+                .collect(),
+        ),
+        Expr::Validate(a, schema) => Expr::Validate(r(a), schema.clone()),
+        Expr::Coerce(a, schema) => Expr::Coerce(r(a), schema.clone()),
+        Expr::ValidateAgainst(a, b) => Expr::ValidateAgainst(r(a), r(b)),
+        Expr::GetRequestHeader(a) => Expr::GetRequestHeader(r(a)),
+        Expr::SetResponseHeaderExpr(a, b) => Expr::SetResponseHeaderExpr(r(a), r(b)),
+        Expr::WithEtag(a) => Expr::WithEtag(r(a)),
+        Expr::RenderTemplateFile(a, b) => Expr::RenderTemplateFile(r(a), r(b)),
+        Expr::WithCache(a, b) => Expr::WithCache(r(a), r(b)),
+        _ => e.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn make_block(src: &str) -> (Vec<Param>, Program) {
+        let prog = parse(&format!("Start Program\n{}\nEnd Program\n", src)).unwrap();
+        match prog.into_iter().next() {
+            Some(Stmt::FuncBlock { params, body, .. }) => (params, body),
+            other => panic!("expected a function block, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn params_and_assigned_names_get_slots() {
+        let (params, body) = make_block(
+            "Make total_to with n\n    Set acc to 0\n    If n is greater than 0\n        Set extra to n\n    End\n    Return acc plus limit\nEnd",
+        );
+        let (resolved, layout) = resolve_body(&params, &body).unwrap();
+        assert_eq!(layout.slot("n"), Some(0));
+        assert_eq!(layout.slot("acc"), Some(1));
+        assert_eq!(layout.slot("extra"), Some(2));
+        assert!(matches!(resolved[0], Stmt::SetLocal { slot: 1, .. }));
+        match &resolved[2] {
+            Stmt::Return(Some(Expr::Plus(a, b))) => {
+                assert!(matches!(**a, Expr::Local(1)));
+                assert!(matches!(&**b, Expr::Global(name) if name == "limit"));
+            }
+            other => panic!("unexpected return: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nested_function_definitions_stay_dynamic() {
+        let (params, body) =
+            make_block("Make outer with x\n    Make inner with y Write x plus y\n    Return inner(1)\nEnd");
+        assert!(resolve_body(&params, &body).is_none());
+    }
+}
@@ -0,0 +1,96 @@
+//! Counters behind `--stats` for the AST interpreter.
+//!
+//! A `Vm` only counts once `Vm::enable_stats` has been called; until then
+//! each counting site is a single branch on a `bool`. Route handlers count
+//! into the counters of the program that added the route, by `Enable
+//! metrics`.
+
+use super::lint::walk;
+use crate::parser::ast::{Program, Stmt};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// What a program did while it ran, from `Vm::stats`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExecutionStats {
+    /// Statements run, counting each pass through a loop body
+    pub statements_executed: u64,
+    /// Calls of user-defined and module functions
+    pub function_calls: u64,
+    /// Calls of each built-in function, by name
+    pub builtin_calls: HashMap<String, u64>,
+}
+
+impl ExecutionStats {
+    /// The `n` most called built-ins, most calls first, ties by name
+    pub fn top_builtins(&self, n: usize) -> Vec<(&str, u64)> {
+        let mut calls: Vec<(&str, u64)> = self
+            .builtin_calls
+            .iter()
+            .map(|(name, count)| (name.as_str(), *count))
+            .collect();
+        calls.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        calls.truncate(n);
+        calls
+    }
+}
+
+#[derive(Default)]
+pub(super) struct Counters {
+    /// Whether the `Vm`s sharing these count, read when a handler starts
+    enabled: AtomicBool,
+    statements: AtomicU64,
+    calls: AtomicU64,
+    builtins: Mutex<HashMap<String, u64>>,
+}
+
+impl Counters {
+    pub(super) fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub(super) fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn statement(&self) {
+        self.statements.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn call(&self) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn builtin(&self, name: &str) {
+        *self
+            .builtins
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default() += 1;
+    }
+
+    pub(super) fn snapshot(&self) -> ExecutionStats {
+        ExecutionStats {
+            statements_executed: self.statements.load(Ordering::Relaxed),
+            function_calls: self.calls.load(Ordering::Relaxed),
+            builtin_calls: self.builtins.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Statements and function definitions in a parsed program, including
+/// those nested in blocks
+pub fn parsed_counts(prog: &Program) -> (usize, usize) {
+    let (mut statements, mut functions) = (0, 0);
+    walk(prog, &mut |s| match s {
+        Stmt::Line(_) => {}
+        Stmt::FuncInline { .. } | Stmt::FuncBlock { .. } => {
+            statements += 1;
+            functions += 1;
+        }
+        _ => statements += 1,
+    });
+    (statements, functions)
+}
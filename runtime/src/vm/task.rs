@@ -0,0 +1,127 @@
+//! `Start task named X:` blocks and the `Wait for ... tasks` that join them.
+//!
+//! A task's body runs on a thread of its own with a copy of the `Vm`, like a
+//! `Parallel` branch, while the program goes on. What it returns comes back
+//! over a channel. Once it has arrived it is kept, so a task can be waited
+//! for more than once and every copy of its handle sees the same result.
+
+use anyhow::{anyhow, Error, Result};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How often `first_finished` looks at tasks still running
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Debug)]
+pub(super) struct Task<T> {
+    name: String,
+    receiver: Mutex<Receiver<Result<T>>>,
+    /// The result once it has arrived; an error is shared by every wait, and
+    /// keeps its type for the waits that look at it
+    outcome: Mutex<Option<Result<T, Arc<Error>>>>,
+}
+
+impl<T: Clone + Send + 'static> Task<T> {
+    /// Start `run` on a new thread
+    pub(super) fn spawn(name: &str, run: impl FnOnce() -> Result<T> + Send + 'static) -> Arc<Self> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(run());
+        });
+        Arc::new(Task {
+            name: name.to_string(),
+            receiver: Mutex::new(receiver),
+            outcome: Mutex::new(None),
+        })
+    }
+
+    pub(super) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The result, waiting for the task to finish if it has not
+    pub(super) fn wait(&self) -> Result<T, Arc<Error>> {
+        let mut outcome = self.outcome.lock().unwrap();
+        if outcome.is_none() {
+            let received = self.receiver.lock().unwrap().recv();
+            *outcome = Some(Self::received(received.ok()));
+        }
+        outcome.clone().unwrap()
+    }
+
+    /// The result if the task has finished
+    pub(super) fn poll(&self) -> Option<Result<T, Arc<Error>>> {
+        let mut outcome = self.outcome.lock().unwrap();
+        if outcome.is_none() {
+            match self.receiver.lock().unwrap().try_recv() {
+                Ok(result) => *outcome = Some(Self::received(Some(result))),
+                Err(TryRecvError::Disconnected) => *outcome = Some(Self::received(None)),
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        outcome.clone()
+    }
+
+    /// `None` if the thread ended without sending, that is it panicked
+    fn received(result: Option<Result<T>>) -> Result<T, Arc<Error>> {
+        match result {
+            Some(Ok(v)) => Ok(v),
+            Some(Err(e)) => Err(Arc::new(e)),
+            None => Err(Arc::new(anyhow!("stopped by an internal error"))),
+        }
+    }
+}
+
+/// Index of the first of `tasks` to finish and its result. Tasks that had
+/// already finished count first, in the order given.
+pub(super) fn first_finished<T: Clone + Send + 'static>(tasks: &[Arc<Task<T>>]) -> (usize, Result<T, Arc<Error>>) {
+    loop {
+        for (n, task) in tasks.iter().enumerate() {
+            if let Some(outcome) = task.poll() {
+                return (n, outcome);
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_results_are_kept_once_they_arrive() {
+        let ok = Task::spawn("ok", || Ok(7));
+        assert_eq!(ok.wait().unwrap(), 7);
+        assert_eq!(ok.poll().unwrap().unwrap(), 7);
+        assert_eq!(ok.wait().unwrap(), 7);
+
+        let failed: Arc<Task<i32>> = Task::spawn("failed", || Err(anyhow!("no route")));
+        assert_eq!(failed.wait().unwrap_err().to_string(), "no route");
+        // Every wait sees the same error, with its type
+        assert!(Arc::ptr_eq(&failed.wait().unwrap_err(), &failed.poll().unwrap().unwrap_err()));
+        let typed: Arc<Task<i32>> = Task::spawn("typed", || Err(std::fmt::Error.into()));
+        assert!(typed.wait().unwrap_err().is::<std::fmt::Error>());
+
+        let panicked: Arc<Task<i32>> = Task::spawn("panicked", || panic!("boom"));
+        assert_eq!(panicked.wait().unwrap_err().to_string(), "stopped by an internal error");
+    }
+
+    #[test]
+    fn test_the_first_task_to_finish_wins() {
+        let (release, gate) = channel::<()>();
+        let slow = Task::spawn("slow", move || {
+            let _ = gate.recv();
+            Ok("slow")
+        });
+        let fast = Task::spawn("fast", || Ok("fast"));
+        let tasks = [slow, fast];
+        let (winner, outcome) = first_finished(&tasks);
+        assert_eq!((tasks[winner].name(), outcome.unwrap()), ("fast", "fast"));
+        release.send(()).unwrap();
+        assert_eq!(tasks[0].wait().unwrap(), "slow");
+    }
+}
@@ -0,0 +1,107 @@
+//! `--trace`: each statement is written to stderr as it runs.
+//!
+//! Lines look like `[trace]   12  Set total to total plus x → 15`; the
+//! statement is indented two spaces per enclosing block or function call.
+//! Line numbers come from the `Stmt::Line` markers `parse_with_lines` adds,
+//! and show as `?` for programs parsed without them. With a filter, only
+//! what runs inside calls of the named function is shown.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Default)]
+pub(super) struct Tracer {
+    enabled: bool,
+    filter: Option<String>,
+    /// Shared with `Nested` guards, so holding one does not borrow the `Vm`
+    depth: Arc<AtomicUsize>,
+    line: AtomicUsize,
+    /// How many calls of the filtered function are running
+    inside: AtomicUsize,
+}
+
+impl Tracer {
+    pub(super) fn new(filter: Option<String>) -> Self {
+        Tracer {
+            enabled: true,
+            filter,
+            ..Tracer::default()
+        }
+    }
+
+    pub(super) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// `None` when tracing is off, otherwise the filter; for handing the
+    /// same settings to another `Vm`
+    pub(super) fn settings(&self) -> Option<Option<String>> {
+        self.enabled.then(|| self.filter.clone())
+    }
+
+    pub(super) fn set_line(&self, line: usize) {
+        self.line.store(line, Ordering::Relaxed);
+    }
+
+    /// Line of the statement running, 0 if unknown
+    pub(super) fn line(&self) -> usize {
+        self.line.load(Ordering::Relaxed)
+    }
+
+    /// Indent what runs until the guard is dropped one level deeper
+    pub(super) fn nested(&self) -> Nested {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        Nested(Arc::clone(&self.depth))
+    }
+
+    pub(super) fn emit(&self, text: &str) {
+        if self.filter.is_some() && self.inside.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let line = match self.line.load(Ordering::Relaxed) {
+            0 => "?".to_string(),
+            n => n.to_string(),
+        };
+        let depth = self.depth.load(Ordering::Relaxed).saturating_sub(1);
+        eprintln!("[trace] {:>4}  {}{}", line, "  ".repeat(depth), text);
+    }
+
+    /// Wrap a function call in entry and exit lines. The line number is put
+    /// back afterwards so the caller's statement keeps its own.
+    pub(super) fn call<T, E>(
+        &self,
+        name: &str,
+        entry: String,
+        run: impl FnOnce() -> Result<T, E>,
+        show: impl Fn(&T) -> String,
+    ) -> Result<T, E> {
+        let watched = self.filter.as_deref() == Some(name);
+        if watched {
+            self.inside.fetch_add(1, Ordering::Relaxed);
+        }
+        let line = self.line.load(Ordering::Relaxed);
+        let result = {
+            let _body = self.nested();
+            self.emit(&entry);
+            let result = run();
+            match &result {
+                Ok(v) => self.emit(&format!("{} returned {}", name, show(v))),
+                Err(_) => self.emit(&format!("{} failed", name)),
+            }
+            result
+        };
+        self.set_line(line);
+        if watched {
+            self.inside.fetch_sub(1, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+pub(super) struct Nested(Arc<AtomicUsize>);
+
+impl Drop for Nested {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
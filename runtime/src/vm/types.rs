@@ -0,0 +1,135 @@
+//! `Type Age is number` aliases and `Set age as Age to 30` declarations.
+//!
+//! Neither changes what a program does. The linter warns when a declared
+//! variable is set to a literal of another type, and with `--strict-types`
+//! the `Vm` checks every `Set` of a declared variable as it runs.
+
+use std::collections::HashMap;
+
+/// The types an alias can finally stand for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum BaseType {
+    Number,
+    String,
+    List,
+    Dict,
+    Boolean,
+}
+
+impl BaseType {
+    pub(super) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "number" => Some(BaseType::Number),
+            "string" => Some(BaseType::String),
+            "list" => Some(BaseType::List),
+            "dict" => Some(BaseType::Dict),
+            "boolean" => Some(BaseType::Boolean),
+            _ => None,
+        }
+    }
+
+    pub(super) fn name(self) -> &'static str {
+        match self {
+            BaseType::Number => "number",
+            BaseType::String => "string",
+            BaseType::List => "list",
+            BaseType::Dict => "dict",
+            BaseType::Boolean => "boolean",
+        }
+    }
+}
+
+/// Aliases and declared variables seen so far
+#[derive(Clone, Debug, Default)]
+pub(super) struct Types {
+    aliases: HashMap<String, String>,
+    declared: HashMap<String, String>,
+}
+
+impl Types {
+    pub(super) fn alias(&mut self, name: &str, base: &str) {
+        self.aliases.insert(name.to_string(), base.to_string());
+    }
+
+    pub(super) fn declare(&mut self, var: &str, type_name: &str) {
+        self.declared.insert(var.to_string(), type_name.to_string());
+    }
+
+    /// The base type `type_name` stands for, following aliases of aliases.
+    /// `None` for unknown names and aliases that lead back to themselves.
+    pub(super) fn resolve(&self, type_name: &str) -> Option<BaseType> {
+        let mut name = type_name;
+        for _ in 0..=self.aliases.len() {
+            if let Some(base) = BaseType::from_name(name) {
+                return Some(base);
+            }
+            name = self.aliases.get(name)?;
+        }
+        None
+    }
+
+    /// The type a variable was declared with, if it was
+    pub(super) fn declared(&self, var: &str) -> Option<&str> {
+        self.declared.get(var).map(String::as_str)
+    }
+
+    /// Why setting `var` to a value of type `found` breaks its declaration,
+    /// if it does
+    pub(super) fn mismatch(&self, var: &str, found: BaseType) -> Option<String> {
+        let type_name = self.declared(var)?;
+        let expected = self.resolve(type_name)?;
+        (expected != found).then(|| {
+            format!(
+                "'{}' is declared as {} ({}) but is set to a {}",
+                var,
+                type_name,
+                expected.name(),
+                found.name()
+            )
+        })
+    }
+
+    /// Aliases and declarations naming types that do not exist, in name order
+    pub(super) fn unknown(&self) -> Vec<String> {
+        let mut problems: Vec<String> = self
+            .aliases
+            .iter()
+            .filter(|(name, _)| self.resolve(name).is_none())
+            .map(|(name, base)| format!("'Type {} is {}' does not lead to a known type", name, base))
+            .chain(
+                self.declared
+                    .iter()
+                    .filter(|(_, t)| self.resolve(t).is_none())
+                    .map(|(var, t)| format!("'{}' is declared as {}, which is not a known type", var, t)),
+            )
+            .collect();
+        problems.sort();
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aliases_resolve_through_other_aliases() {
+        let mut types = Types::default();
+        types.alias("Years", "number");
+        types.alias("Age", "Years");
+        types.alias("Loop", "Knot");
+        types.alias("Knot", "Loop");
+        types.declare("age", "Age");
+        types.declare("name", "Name");
+
+        assert_eq!(types.resolve("Age"), Some(BaseType::Number));
+        assert_eq!(types.resolve("Loop"), None);
+        assert_eq!(types.mismatch("age", BaseType::Number), None);
+        assert_eq!(
+            types.mismatch("age", BaseType::String).unwrap(),
+            "'age' is declared as Age (number) but is set to a string"
+        );
+        assert_eq!(types.mismatch("other", BaseType::String), None);
+        assert_eq!(types.unknown().len(), 3);
+    }
+}
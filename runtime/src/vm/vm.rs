@@ -1,63 +1,115 @@
+use super::debug::{Debugger, Inspect, Prompt};
+use super::events::{EventEmitter, Handler};
 use super::instructions::Instruction;
-use crate::core::io as core_io;
-use crate::parser::ast::{CmpOp, Expr, Param, Program, Stmt};
+#[cfg(feature = "web")]
+use super::lint;
+use super::profile::Profiler;
+use super::resolve::{resolve_body, SlotLayout};
+use super::stats::{Counters, ExecutionStats};
+use super::channel::Channel;
+use super::checkpoint;
+use super::task::{self, Task};
+use super::trace::Tracer;
+use super::types::{BaseType, Types};
+use crate::core::encoding::{self, SourceEncoding};
+use crate::core::equality::{typed_eq, HasShape, OrdValue, Shape};
+use crate::core::hints::{self, Hint, HintPattern};
+use crate::core::io::{self as core_io, Answer};
+use crate::core::number::{self, Number};
+use crate::core::table::{self, TableStyle};
+use crate::parser::ast::{AggField, AggFunc, AskType, CatchHandler, CmpOp, Expr, LogLevel, Param, Program, SchemaField, Stmt};
+use crate::stdlib::collections::{OrderedDict, PriorityQueue};
 use crate::stdlib::errors::{ErrorKind, PohError, StackFrame};
+#[cfg(feature = "web")]
+use crate::stdlib::event_stream::{self, EventStream};
+#[cfg(feature = "web")]
+use crate::stdlib::websocket::{self, WebSocket};
+#[cfg(feature = "web")]
+use crate::stdlib::http::{HttpResponse, RouteHooks};
+use crate::stdlib::log::LogFile;
 use anyhow::{anyhow, bail, Result};
-use std::collections::{HashMap, HashSet};
+use regex::Regex;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-
-fn enhance_error(msg: &str) -> String {
-    if msg.contains("out of range") {
-        format!(
-            "{}.\nHint: Check array bounds. Use negative indexing (-1) for last element",
-            msg
-        )
-    } else if msg.contains("not found") {
-        format!(
-            "{}.\nHint: Verify the key exists in the dictionary or check for typos",
-            msg
-        )
-    } else if msg.contains("division by zero") {
-        format!(
-            "{}.\nHint: Ensure denominator is not zero before dividing",
-            msg
-        )
-    } else if msg.contains("not defined") {
-        format!(
-            "{}.\nHint: Make sure the function is defined before calling it",
-            msg
-        )
-    } else {
-        msg.to_string()
-    }
-}
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
+/// Every variant is `Send + Sync`: shared state lives behind `Arc` (scopes in
+/// an `RwLock`, the web server and live-reload file times in a `Mutex`), so a
+/// value can be handed to another thread without copying what it points to.
 #[derive(Clone, Debug)]
 enum Value {
     Str(String),
+    Int(i64),
     Num(f64),
     Bool(bool),
     Null,
     Func(Func),
     List(Vec<Value>),
+    Tuple(Vec<Value>), // fixed-size and immutable
+    // `range(...)`: the numbers start, start + step, ... before end, made
+    // only when something needs them all
+    LazyRange { start: i64, end: i64, step: i64 },
     Dict(HashMap<String, Value>),
+    // `set containing ...`: numbers, text, booleans and nothing, unordered
+    Set(HashSet<OrdValue<Value>>),
     Error(PohError),
+    #[cfg(feature = "web")]
     WebServer(std::sync::Arc<std::sync::Mutex<crate::stdlib::http::WebServer>>),
-    HttpRequest(crate::stdlib::http::HttpRequest),
+    #[cfg(feature = "web")]
+    // Boxed: a request is several times the size of every other variant
+    HttpRequest(Box<crate::stdlib::http::HttpRequest>),
+    #[cfg(feature = "web")]
     HttpResponse(crate::stdlib::http::HttpResponse),
+    #[cfg(feature = "web")]
     LiveReloadTracker(crate::stdlib::livereload::LiveReloadTracker),
+    // Handles from the `collections` system module; copies share the contents
+    Stack(Arc<Mutex<Vec<Value>>>),
+    Queue(Arc<Mutex<VecDeque<Value>>>),
+    PriorityQueue(Arc<Mutex<PriorityQueue<Value>>>),
+    OrderedDict(Arc<Mutex<OrderedDict<Value>>>),
+    // A compiled `r"pattern"`; copies share it
+    Regex(Arc<Regex>),
+    // A `Start task named X:` body, running or finished; copies share it
+    Future(Arc<Task<Value>>),
+    // `make a channel`; copies share it
+    Channel(Arc<Channel<Value>>),
+    // The response of a handler that called `start event stream`
+    #[cfg(feature = "web")]
+    EventStream(EventStream),
+    // The connection of a websocket route, as `socket` in its handler
+    #[cfg(feature = "web")]
+    WebSocket(WebSocket),
+    // `Create event emitter X`; copies share the handlers
+    EventEmitter(EventEmitter<Value>),
+    // A program variable made by `Bind`: its value, and the block a `Set` of
+    // it runs. Only ever stored in a scope; reading the variable unwraps it.
+    Observed(Box<Value>, Arc<Program>),
 }
 
 #[derive(Clone, Debug)]
 struct Func {
     name: String,
     params: Vec<Param>,
-    body: Expr,
-    captured: Vec<HashMap<String, Value>>, // lexical chain from inner to outer
+    body: FuncBody,
+    captured: Env, // scope the function was defined in
 }
 
+/// What runs when a function is called: a single expression for inline
+/// definitions, a `Make ... End` block, or Rust code for native modules
+#[derive(Clone, Debug)]
+enum FuncBody {
+    Expr(Expr),
+    Block(Arc<FnBlock>),
+    Native(NativeFn),
+}
+
+type NativeFn = fn(&[Value]) -> Result<Value>;
+
 #[derive(Clone, Debug)]
 struct CallFrame {
     function_name: String,
@@ -65,8 +117,13 @@ struct CallFrame {
     line: usize,
 }
 
+/// The AST interpreter.
+///
+/// A `Vm` is `Send + Sync`, so it can be built on one thread and run on
+/// another. Route handlers rely on this: each request runs in its own `Vm`
+/// on a server worker thread, on top of the scope shared with the program.
 pub struct Vm {
-    globals: HashMap<String, Value>,
+    globals: Env,
     base_dir: PathBuf,
     loading_stack: Vec<String>,
     loaded_modules: HashSet<String>,
@@ -74,14 +131,267 @@ pub struct Vm {
     system_exports: HashMap<String, HashMap<String, Value>>,
     module_aliases: HashMap<String, String>,
     exposed_symbols: HashMap<String, String>,
-    call_stack: Vec<CallFrame>,
+    /// Functions being called, outermost first, each with the line it was called from
+    call_stack: Mutex<Vec<CallFrame>>,
     current_file: String,
+    source_encoding: SourceEncoding,
+    table_style: TableStyle,
+    /// Value of a top-level `Return` that is unwinding the program
+    returned: Option<Value>,
+    /// Set in route handler VMs: the first HTTP response the handler writes
+    #[cfg(feature = "web")]
+    handler_response: Option<Mutex<Option<crate::stdlib::http::HttpResponse>>>,
+    stats_enabled: bool,
+    counters: Arc<Counters>,
+    tracer: Tracer,
+    debugger: Option<Debugger>,
+    /// The call tree of `Benchmark` blocks, with `--bench-flamegraph`
+    profiler: Profiler,
+    /// With `--strict-types`, every `Set` of a declared variable is checked
+    strict_types: bool,
+    /// With `--memory-limit`, the most bytes, roughly, that any one list,
+    /// dictionary or text the program makes may take
+    memory_limit: Option<usize>,
+    /// Set in the copy a `Run in background` block runs in, which has no
+    /// input to answer `Ask for`
+    in_background: bool,
+    /// With `--strict`, undefined names, comparisons of things that are not
+    /// numbers and the like are errors instead of placeholder values
+    strict: bool,
+    types: RwLock<Types>,
+    /// Set by `Log to file`; shared with route handler VMs
+    log_file: Arc<Mutex<Option<LogFile>>>,
+    /// Whether `Restore checkpoint` reads saved checkpoints; off with `--no-resume`
+    resume: bool,
+    /// The `Add route group` blocks being run, innermost last
+    route_groups: Vec<RouteGroup>,
+    /// `Define schema` fields by schema name, their types resolved to base
+    /// types; shared with route handler VMs
+    schemas: Arc<RwLock<HashMap<String, Vec<SchemaField>>>>,
+    /// `Set constant` values, looked up before the globals. They are all set
+    /// before a program's first statement runs and never change, so route
+    /// handler VMs share them without a lock.
+    constants: Arc<HashMap<String, Value>>,
+    /// The template directory and the files read from it; shared with route
+    /// handler VMs
+    #[cfg(feature = "web")]
+    templates: Arc<crate::stdlib::template::TemplateCache>,
+}
+
+/// An `Add route group` block: its prefix after those of the groups around
+/// it, and the middleware of each of them, outermost first. The middleware
+/// is shared with the routes, so a block added after a route still runs for it.
+#[derive(Debug, Clone, Default)]
+struct RouteGroup {
+    prefix: String,
+    middleware: Vec<Arc<RwLock<Vec<Program>>>>,
+}
+
+/// Why a `Parallel` block or a wait for tasks failed: the error of each
+/// branch or task that did, in order. A `try` around it binds them as a list.
+#[derive(Debug)]
+struct BranchErrors {
+    errors: Vec<String>,
+    tasks: bool,
+}
+
+impl std::fmt::Display for BranchErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let n = self.errors.len();
+        if self.tasks {
+            write!(f, "[TaskError] {} task(s) failed", n)?;
+        } else {
+            write!(f, "[ParallelError] {} branch(es) failed", n)?;
+        }
+        for e in &self.errors {
+            write!(f, "\n  {}", e.replace('\n', "\n  "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BranchErrors {}
+
+/// A built-in or a function given the wrong number or kind of values. Made
+/// where the `Vm` is out of reach; the innermost `call_func_value`, or `run`
+/// at the top level, turns it into a `TypeError` with the call stack.
+#[derive(Debug)]
+struct WrongType(String);
+
+impl std::fmt::Display for WrongType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[TypeError] {}", self.0)
+    }
+}
+
+impl std::error::Error for WrongType {}
+
+fn wrong_type(message: impl Into<String>) -> anyhow::Error {
+    WrongType(message.into()).into()
+}
+
+/// A list, dictionary or text over the memory limit. `try` and `Retry` do
+/// not catch it, so the program ends.
+#[derive(Clone, Copy, Debug)]
+struct MemoryLimitExceeded {
+    size: usize,
+    limit: usize,
+}
+
+impl std::fmt::Display for MemoryLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[MemoryLimitExceeded] a value of about {} bytes is over the memory limit of {} bytes",
+            self.size, self.limit
+        )
+    }
+}
+
+impl std::error::Error for MemoryLimitExceeded {}
+
+/// Roughly how many bytes a value takes: a `Value` for it and each item,
+/// plus the bytes of its text and keys. The items of a stack, queue,
+/// priority queue or ordered dict count too, once however often the handle
+/// appears, and so do the values waiting in a channel.
+fn approx_size(v: &Value) -> usize {
+    approx_size_in(v, &mut Vec::new())
+}
+
+/// `approx_size`, with `seen` the handles already counted
+fn approx_size_in(v: &Value, seen: &mut Vec<usize>) -> usize {
+    let item = std::mem::size_of::<Value>();
+    item + match v {
+        Value::Str(s) => s.len(),
+        Value::List(xs) | Value::Tuple(xs) => xs.iter().map(|x| approx_size_in(x, seen)).sum(),
+        Value::Dict(m) => m.iter().map(|(k, v)| k.len() + approx_size_in(v, seen)).sum(),
+        Value::Set(s) => s.len() * item,
+        Value::Observed(v, _) => approx_size_in(v, seen),
+        Value::Stack(_) | Value::Queue(_) | Value::PriorityQueue(_) | Value::OrderedDict(_)
+            if !seen.contains(&shared_id(v)) =>
+        {
+            seen.push(shared_id(v));
+            if let Value::OrderedDict(od) = v {
+                // Copied out first: a dict that holds itself would lock twice
+                let items: Vec<(String, Value)> = od
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                items.iter().map(|(k, v)| k.len() + approx_size_in(v, seen)).sum()
+            } else {
+                collection_items(v).iter().map(|x| approx_size_in(x, seen)).sum()
+            }
+        }
+        Value::Channel(channel) => channel.queued_size(),
+        _ => 0,
+    }
+}
+
+/// The first handler of a `try this` that catches `err`, with the value its
+/// variable gets: the message without its `[Type]` marker, or the list of
+/// errors from a parallel block. A handler with a type matches the marker,
+/// or the message when it has none; one without a type catches anything.
+fn catch_handler<'a>(err: &anyhow::Error, handlers: &'a [CatchHandler]) -> Option<(&'a CatchHandler, Value)> {
+    if err.is::<MemoryLimitExceeded>() {
+        return None;
+    }
+    let msg = err.to_string();
+    let marker = match (msg.find('['), msg.find(']')) {
+        (Some(start), Some(end)) if start < end => Some(&msg[start + 1..end]),
+        _ => None,
+    };
+    let handler = handlers.iter().find(|h| match (&h.error_type, marker) {
+        (None, _) => true,
+        (Some(wanted), Some(marker)) => marker.eq_ignore_ascii_case(wanted),
+        (Some(wanted), None) => msg.to_lowercase().contains(&wanted.to_lowercase()),
+    })?;
+    let caught = match err.downcast_ref::<BranchErrors>() {
+        Some(branches) => Value::List(branches.errors.iter().cloned().map(Value::Str).collect()),
+        None => Value::Str(match msg.find(']') {
+            Some(end) => msg[end + 1..].trim().to_string(),
+            None => msg.clone(),
+        }),
+    };
+    Some((handler, caught))
+}
+
+/// What a web statement or expression fails with in a build without the
+/// `web` feature; `what` is its source
+#[cfg(not(feature = "web"))]
+fn web_disabled(what: String) -> anyhow::Error {
+    anyhow!("{}: web support not compiled in; build PohLang with the 'web' feature", what)
+}
+
+/// A value handed back to an embedder, such as the result of a top-level
+/// `Return`
+#[derive(Clone, Debug)]
+pub struct ProgramValue(Value);
+
+impl ProgramValue {
+    /// Whole numbers, including decimals with no fractional part
+    pub fn as_i64(&self) -> Option<i64> {
+        whole_number(&self.0)
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        number_of(&self.0).map(Number::as_f64)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.0 {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.0 {
+            Value::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self.0, Value::Null)
+    }
+}
+
+impl From<&str> for ProgramValue {
+    fn from(s: &str) -> Self {
+        ProgramValue(Value::Str(s.to_string()))
+    }
+}
+
+impl From<i64> for ProgramValue {
+    fn from(i: i64) -> Self {
+        ProgramValue(Value::Int(i))
+    }
+}
+
+impl From<f64> for ProgramValue {
+    fn from(n: f64) -> Self {
+        ProgramValue(Value::Num(n))
+    }
+}
+
+impl From<bool> for ProgramValue {
+    fn from(b: bool) -> Self {
+        ProgramValue(Value::Bool(b))
+    }
+}
+
+impl std::fmt::Display for ProgramValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&to_string(&self.0))
+    }
 }
 
 impl Default for Vm {
     fn default() -> Self {
         Vm {
-            globals: HashMap::new(),
+            globals: Scope::new_env(HashMap::new(), None),
             base_dir: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
             loading_stack: Vec::new(),
             loaded_modules: HashSet::new(),
@@ -89,8 +399,30 @@ impl Default for Vm {
             system_exports: HashMap::new(),
             module_aliases: HashMap::new(),
             exposed_symbols: HashMap::new(),
-            call_stack: Vec::new(),
+            call_stack: Mutex::new(Vec::new()),
             current_file: String::from("<main>"),
+            source_encoding: SourceEncoding::default(),
+            table_style: TableStyle::default(),
+            returned: None,
+            #[cfg(feature = "web")]
+            handler_response: None,
+            stats_enabled: false,
+            counters: Arc::default(),
+            tracer: Tracer::default(),
+            debugger: None,
+            profiler: Profiler::default(),
+            strict_types: false,
+            memory_limit: None,
+            in_background: false,
+            strict: false,
+            types: RwLock::new(Types::default()),
+            log_file: Arc::new(Mutex::new(None)),
+            resume: true,
+            route_groups: Vec::new(),
+            schemas: Arc::default(),
+            constants: Arc::default(),
+            #[cfg(feature = "web")]
+            templates: Arc::default(),
         }
     }
 }
@@ -98,7 +430,7 @@ impl Default for Vm {
 impl Vm {
     pub fn with_base_dir(base: PathBuf) -> Self {
         Vm {
-            globals: HashMap::new(),
+            globals: Scope::new_env(HashMap::new(), None),
             base_dir: base,
             loading_stack: Vec::new(),
             loaded_modules: HashSet::new(),
@@ -106,9 +438,69 @@ impl Vm {
             system_exports: HashMap::new(),
             module_aliases: HashMap::new(),
             exposed_symbols: HashMap::new(),
-            call_stack: Vec::new(),
+            call_stack: Mutex::new(Vec::new()),
             current_file: String::from("<main>"),
+            source_encoding: SourceEncoding::default(),
+            table_style: TableStyle::default(),
+            returned: None,
+            #[cfg(feature = "web")]
+            handler_response: None,
+            stats_enabled: false,
+            counters: Arc::default(),
+            tracer: Tracer::default(),
+            debugger: None,
+            profiler: Profiler::default(),
+            strict_types: false,
+            memory_limit: None,
+            in_background: false,
+            strict: false,
+            types: RwLock::new(Types::default()),
+            log_file: Arc::new(Mutex::new(None)),
+            resume: true,
+            route_groups: Vec::new(),
+            schemas: Arc::default(),
+            constants: Arc::default(),
+            #[cfg(feature = "web")]
+            templates: Arc::default(),
+        }
+    }
+}
+
+impl Clone for Vm {
+    /// A copy for a `Parallel` branch. The variables are copied into a scope
+    /// of its own, so nothing the copy sets reaches this `Vm`. The log file is
+    /// shared; the debugger and statistics are not.
+    fn clone(&self) -> Self {
+        let mut vm = Vm::with_base_dir(self.base_dir.clone());
+        vm.globals = Scope::new_env(flatten_env(&self.globals), None);
+        vm.loading_stack = self.loading_stack.clone();
+        vm.loaded_modules = self.loaded_modules.clone();
+        vm.loaded_system = self.loaded_system.clone();
+        vm.system_exports = self.system_exports.clone();
+        vm.module_aliases = self.module_aliases.clone();
+        vm.exposed_symbols = self.exposed_symbols.clone();
+        vm.call_stack = Mutex::new(self.call_stack.lock().unwrap().clone());
+        vm.current_file = self.current_file.clone();
+        vm.source_encoding = self.source_encoding;
+        vm.table_style = self.table_style;
+        if let Some(filter) = self.tracer.settings() {
+            vm.enable_trace(filter);
+        }
+        vm.log_file = Arc::clone(&self.log_file);
+        vm.strict_types = self.strict_types;
+        vm.memory_limit = self.memory_limit;
+        vm.in_background = self.in_background;
+        vm.strict = self.strict;
+        vm.types = RwLock::new(self.types.read().unwrap().clone());
+        vm.resume = self.resume;
+        vm.route_groups = self.route_groups.clone();
+        vm.schemas = Arc::clone(&self.schemas);
+        vm.constants = Arc::clone(&self.constants);
+        #[cfg(feature = "web")]
+        {
+            vm.templates = Arc::clone(&self.templates);
         }
+        vm
     }
 }
 
@@ -123,11 +515,97 @@ impl Vm {
         &self.current_file
     }
 
+    /// Set the encoding used to read imported local files
+    pub fn set_source_encoding(&mut self, encoding: SourceEncoding) {
+        self.source_encoding = encoding;
+    }
+
+    /// Set how `Print table` lays out its output
+    pub fn set_table_style(&mut self, style: TableStyle) {
+        self.table_style = style;
+    }
+
+    /// Start counting statements and calls for `stats`
+    pub fn enable_stats(&mut self) {
+        self.stats_enabled = true;
+        self.counters.enable();
+    }
+
+    /// Record the calls made inside `Benchmark` blocks and write their
+    /// flame graph to `path` after each block
+    pub fn enable_flamegraph(&mut self, path: PathBuf) {
+        self.profiler = Profiler::new(path);
+    }
+
+    /// Make a `Set` that breaks a `Set x as Type` declaration a `TypeError`,
+    /// and so are declarations of types that do not exist
+    pub fn enable_strict_types(&mut self) {
+        self.strict_types = true;
+    }
+
+    /// Turn soft failures into errors: an undefined name is a RuntimeError
+    /// instead of a `<name>` placeholder, and ordering things that are not
+    /// numbers, or repeating a block a non-number of times, is a TypeError.
+    /// Also enables strict types.
+    pub fn enable_strict(&mut self) {
+        self.strict = true;
+        self.strict_types = true;
+    }
+
+    /// End the program with a `MemoryLimitExceeded` error, which `try`
+    /// does not catch, when a list, dictionary or text it makes would take
+    /// more than about `bytes`, or a collection it adds to or a channel it
+    /// sends to would hold more. Sizes are estimated from the number of items
+    /// and the length of text, where values are made, so a copy counts again.
+    pub fn set_memory_limit(&mut self, bytes: usize) {
+        self.memory_limit = Some(bytes);
+    }
+
+    /// Make `Restore checkpoint` ignore saved checkpoints, so the program
+    /// starts over; `Checkpoint` still saves them
+    pub fn disable_resume(&mut self) {
+        self.resume = false;
+    }
+
+    /// Counters gathered so far; all zero unless `enable_stats` was called
+    pub fn stats(&self) -> ExecutionStats {
+        self.counters.snapshot()
+    }
+
+    /// Write each statement to stderr as it runs; with a filter, only inside
+    /// calls of that function
+    pub fn enable_trace(&mut self, filter: Option<String>) {
+        self.tracer = Tracer::new(filter);
+    }
+
+    /// Stop before the first statement and run `prompt` there and at every
+    /// later stop; see `debug`
+    pub fn enable_debug(&mut self, prompt: Prompt) {
+        self.debugger = Some(Debugger::new(prompt));
+    }
+
+    /// Tracing and debugging show statements as written, so they need line
+    /// markers and function bodies without slot resolution
+    fn shows_statements(&self) -> bool {
+        self.tracer.enabled() || self.debugger.is_some()
+    }
+
+    /// Parse an imported file, keeping line numbers when tracing or debugging
+    fn parse_source(&self, src: &str) -> Result<Program> {
+        if self.shows_statements() {
+            crate::parser::parse_with_lines(src)
+        } else {
+            crate::parser::parse(src)
+        }
+    }
+
     /// Enable hot reload with file watching
+    #[cfg(feature = "web")]
     pub fn enable_hot_reload(&mut self, watch_paths: Vec<PathBuf>) {
         let tracker = crate::stdlib::livereload::LiveReloadTracker::new(watch_paths);
-        self.globals.insert(
-            "__livereload".to_string(),
+        env_set(
+            &self.globals,
+            "__livereload",
             Value::LiveReloadTracker(tracker),
         );
     }
@@ -143,1031 +621,2313 @@ impl Vm {
     }
 
     pub fn execute(&mut self, prog: &Program) -> Result<()> {
-        for stmt in prog {
-            match stmt {
-                Stmt::Write(e) => {
-                    let v = match e {
-                        // If Write is given a bare function ident, attempt to call it with no args
-                        Expr::Ident(name) => {
-                            if let Some(Value::Func(f)) = self.globals.get(name) {
-                                self.call_func_value(f, &[])?
-                            } else {
-                                self.eval(e)?
-                            }
-                        }
-                        _ => self.eval(e)?,
-                    };
-                    core_io::write(&to_string(&v));
-                }
-                Stmt::AskFor { var_name } => {
-                    let input = core_io::ask("");
-                    // Try to parse as number, otherwise store as string
-                    let value = if let Ok(n) = input.parse::<f64>() {
-                        Value::Num(n)
-                    } else {
-                        Value::Str(input)
-                    };
-                    self.globals.insert(var_name.clone(), value);
-                }
-                Stmt::IfInline {
-                    cond,
-                    then_write,
-                    otherwise_write,
-                } => {
-                    let c = self.truthy(&self.eval(cond)?)?;
-                    if c {
-                        let v = self.eval(then_write)?;
-                        println!("{}", to_string(&v));
-                    } else if let Some(e) = otherwise_write {
-                        let v = self.eval(e)?;
-                        println!("{}", to_string(&v));
-                    }
-                }
-                Stmt::IfBlock {
-                    cond,
-                    then_body,
-                    otherwise_body,
-                } => {
-                    let c = self.truthy(&self.eval(cond)?)?;
-                    if c {
-                        self.execute(then_body)?;
-                    } else if let Some(eb) = otherwise_body {
-                        self.execute(eb)?;
-                    }
-                }
-                Stmt::FuncInline { name, params, body } => {
-                    let f = Func {
-                        name: name.clone(),
-                        params: params.clone(),
-                        body: body.clone(),
-                        captured: vec![self.globals.clone()],
-                    };
-                    self.globals.insert(name.clone(), Value::Func(f));
-                }
-                Stmt::FuncBlock { name, params, body } => {
-                    // Represent as a Func with a synthetic body: we will store a special marker by encoding the body as a call to an internal evaluator.
-                    // For simplicity, store block body in a separate map keyed by function name.
-                    let f = Func {
-                        name: name.clone(),
-                        params: params.clone(),
-                        body: Expr::Ident(format!("__fn_body__{}", name)),
-                        captured: vec![self.globals.clone()],
-                    };
-                    self.globals.insert(name.clone(), Value::Func(f));
-                    // Also record the body in globals under a special key as a Value::Func with no params meaning executable block
-                    // We'll store Program as a serialized form using a pointer-like trick via Box in a separate field.
-                    // To avoid a big refactor, store the block body as a special global value string key mapping to a boxed Program in an auxiliary table.
-                    // For now, we attach it to a static once cell (not ideal) - keep it minimal:
-                    FN_BLOCKS.with(|m| {
-                        m.borrow_mut()
-                            .insert(format!("__fn_body__{}", name), body.clone());
-                    });
-                }
-                Stmt::WhileBlock { cond, body } => {
-                    // Evaluate while the condition is truthy; prevent infinite tight loop by a simple iteration cap for safety (optional)
-                    let mut guard = 0usize;
-                    while self.truthy(&self.eval(cond)?)? {
-                        self.execute(body)?;
-                        guard += 1;
-                        if guard > 1_000_000 {
-                            break;
-                        }
-                    }
-                }
-                Stmt::RepeatBlock { count, body } => {
-                    let n = match self.eval(count)? {
-                        Value::Num(x) => x.max(0.0) as i64,
-                        v => {
-                            // Non-number: coerce via length-like string len or treat as 0; keep it simple: 0
-                            let _ = v;
-                            0
-                        }
-                    };
-                    for _ in 0..n {
-                        self.execute(body)?;
-                    }
-                }
-                Stmt::ImportLocal { path } => {
-                    self.import_local(path)?;
-                }
-                Stmt::ImportSystem {
-                    name,
-                    alias,
-                    exposing,
-                } => {
-                    self.import_system(name, alias.as_deref(), exposing)?;
-                }
-                Stmt::Use { name, args } => {
-                    let argv = args
-                        .iter()
-                        .map(|e| self.eval(e))
-                        .collect::<Result<Vec<_>>>()?;
-                    let ret = self.call_function(name, &argv)?;
-                    // Print return if not None/empty
-                    core_io::write(&to_string(&ret));
-                }
-                Stmt::Set { name, value } => {
-                    let v = self.eval(value)?;
-                    self.globals.insert(name.clone(), v);
-                }
-                Stmt::Return(_) => { /* top-level Return ignored */ }
-                Stmt::TryCatch {
-                    try_block,
-                    catch_handlers,
-                    finally_block,
-                } => {
-                    // Execute try block
-                    let try_result = self.execute(try_block);
+        self.run(prog).map(|_| ())
+    }
 
-                    // If try succeeded or no catch handlers, run finally and return
-                    if try_result.is_ok() || catch_handlers.is_empty() {
-                        if let Some(fin) = finally_block {
-                            self.execute(fin)?;
-                        }
-                        try_result?;
-                        continue;
-                    }
+    /// A variable of the program scope or a constant, as the next program
+    /// run on this `Vm` would see it
+    pub fn global(&self, name: &str) -> Option<ProgramValue> {
+        self.resolve_value(name).map(ProgramValue)
+    }
 
-                    // Try block failed - extract error message
-                    let err_msg = try_result.unwrap_err().to_string();
+    /// Set a variable in the program scope, as a top-level `Set` would
+    pub fn set_global(&mut self, name: &str, value: ProgramValue) -> Result<()> {
+        self.check_not_constant(name)?;
+        self.assign_global(name, value.0)
+    }
 
-                    // Extract error type from [TypeName] marker if present
-                    let error_type_from_msg = if let Some(start) = err_msg.find('[') {
-                        if let Some(end) = err_msg.find(']') {
-                            if start < end {
-                                Some(err_msg[start + 1..end].to_string())
-                            } else {
-                                None
-                            }
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    };
+    /// Add a hint to every error message, from the parser and from any `Vm`,
+    /// that `pattern` matches. Of the hints that match a message, the one
+    /// covering the most of it is shown; see `core::hints` for codes.
+    pub fn register_hint(pattern: impl Into<HintPattern>, hint: &str) {
+        hints::register(Hint::new(pattern, hint));
+    }
 
-                    // Try to match a catch handler
-                    let mut handled = false;
-                    for handler in catch_handlers {
-                        // Check if error type matches (if specified)
-                        let type_matches = if let Some(ref error_type) = handler.error_type {
-                            // Match against extracted type marker first, then fallback to message search
-                            if let Some(ref msg_type) = error_type_from_msg {
-                                msg_type.eq_ignore_ascii_case(error_type)
-                            } else {
-                                // Fallback: case-insensitive message search
-                                err_msg.to_lowercase().contains(&error_type.to_lowercase())
-                            }
-                        } else {
-                            true // No type specified = catch all
-                        };
-
-                        if type_matches {
-                            // Bind error message to variable if specified
-                            if let Some(ref var_name) = handler.var_name {
-                                // Remove [TypeName] marker before binding for cleaner user display
-                                let clean_msg = if let Some(end) = err_msg.find(']') {
-                                    err_msg[end + 1..].trim().to_string()
-                                } else {
-                                    err_msg.clone()
-                                };
-                                self.globals.insert(var_name.clone(), Value::Str(clean_msg));
-                            }
+    /// `value` as JSON text, as a JSON response would hold it
+    pub fn to_json(&self, value: &ProgramValue) -> Result<String> {
+        Ok(value_to_json(&value.0)?.to_string())
+    }
 
-                            // Execute catch block
-                            let catch_result = self.execute(&handler.block);
+    /// Run a program and hand back the value of a top-level `Return`, if one
+    /// ran. Imported modules go through here too, so a `Return` at the top of
+    /// a module only ends that module.
+    pub fn run(&mut self, prog: &Program) -> Result<Option<ProgramValue>> {
+        self.returned = None;
+        let result = self
+            .declare_constants(prog)
+            .and_then(|()| self.execute_stmts(prog))
+            .map_err(|e| self.with_call_stack(e))
+            .map_err(|e| match hints::add_to(&e.to_string()) {
+                Some(hinted) => anyhow!("{}", hinted),
+                None => e,
+            });
+        let returned = self.returned.take();
+        result?;
+        Ok(returned.map(ProgramValue))
+    }
 
-                            // Always execute finally block
-                            if let Some(fin) = finally_block {
-                                self.execute(fin)?;
-                            }
+    /// The `Set constant` statements of `prog`, all set before its first
+    /// statement runs so a constant has its value wherever it is used
+    fn declare_constants(&mut self, prog: &Program) -> Result<()> {
+        for stmt in prog {
+            let Stmt::GlobalConst { name, value } = stmt else {
+                continue;
+            };
+            if self.constants.contains_key(name) {
+                bail!("Set constant {}: {} is already a constant", name, name);
+            }
+            if env_get(&self.globals, name).is_some() {
+                bail!("Set constant {}: {} is already a variable", name, name);
+            }
+            let v = self.eval(value)?;
+            Arc::make_mut(&mut self.constants).insert(name.clone(), v);
+        }
+        Ok(())
+    }
 
-                            catch_result?;
-                            handled = true;
-                            break;
-                        }
-                    }
+    /// Every program write goes through here. Inside a route handler an HTTP
+    /// response is kept as the handler's result instead of being printed;
+    /// anywhere else there is nobody to send it to.
+    fn write_value(&self, v: &Value) -> Result<()> {
+        #[cfg(feature = "web")]
+        if let Value::HttpResponse(resp) = v {
+            let Some(slot) = &self.handler_response else {
+                bail!(
+                    "Write: response expressions only have effect inside 'Add route' handlers; \
+                     this response (status {}) was not sent. Write the data itself to print it",
+                    resp.status
+                );
+            };
+            let mut slot = slot.lock().unwrap();
+            if slot.is_none() {
+                *slot = Some(resp.clone());
+            }
+            return Ok(());
+        }
+        core_io::write(&to_string(v));
+        Ok(())
+    }
 
-                    // No matching catch handler - execute finally and re-raise
-                    if !handled {
-                        if let Some(fin) = finally_block {
-                            self.execute(fin)?;
-                        }
-                        bail!("{}", err_msg);
-                    }
-                }
-                Stmt::Throw(expr) => {
-                    let val = self.eval(expr)?;
-                    let error_msg = match val {
-                        Value::Error(e) => e.format_with_trace(),
-                        _ => {
-                            let msg = to_string(&val);
-                            let error = self.create_error(ErrorKind::RuntimeError, msg);
-                            error.format_with_trace()
-                        }
-                    };
-                    return Err(self.error_with_location(error_msg));
-                }
-                Stmt::AddRoute {
-                    path,
-                    method,
-                    handler,
-                } => {
-                    // Get the server from globals (should be stored with key "server")
-                    let server_val = self.globals.get("server").cloned()
-                        .ok_or_else(|| anyhow!("No web server found. Create a server first with: Make server to Create web server on port <port>"))?;
+    /// `plus`, `minus`, `times` and `divided by`, with the terms evaluated by
+    /// `eval`. A whole chain is folded in a loop, since recursing into
+    /// a long generated sum would overflow the stack.
+    fn arithmetic(&self, e: &Expr, eval: impl Fn(&Expr) -> Result<Value>) -> Result<Value> {
+        let (left, terms) = e.arithmetic_chain();
+        let mut sa = eval(left)?;
+        for (op, right) in terms {
+            let sb = eval(right)?;
+            sa = match (number_of(&sa), number_of(&sb)) {
+                (Some(na), Some(nb)) => match op {
+                    Expr::Plus(..) => (na + nb).into(),
+                    Expr::Minus(..) => (na - nb).into(),
+                    Expr::Times(..) => (na * nb).into(),
+                    _ if nb.is_zero() => return Err(self.error_with_location("Division by zero")),
+                    _ => (na / nb).into(),
+                },
+                _ => match op {
+                    Expr::Plus(..) => Value::Str(format!("{}{}", to_string(&sa), to_string(&sb))),
+                    Expr::Minus(..) => bail!("Cannot subtract non-numeric values"),
+                    Expr::Times(..) => bail!("Cannot multiply non-numeric values"),
+                    _ => bail!("Cannot divide non-numeric values"),
+                },
+            };
+        }
+        self.sized(sa)
+    }
 
-                    let path_val = self.eval(path)?;
-                    let method_val = self.eval(method)?;
+    /// `v`, or a `MemoryLimitExceeded` error when it is over the memory limit
+    fn sized(&self, v: Value) -> Result<Value> {
+        self.within_limit(approx_size(&v))?;
+        Ok(v)
+    }
 
-                    let path_str = match path_val {
-                        Value::Str(s) => s,
-                        _ => bail!("add route: path must be a string"),
-                    };
+    /// A `MemoryLimitExceeded` error when `size` bytes are over the memory limit
+    fn within_limit(&self, size: usize) -> Result<()> {
+        match self.memory_limit {
+            Some(limit) if size > limit => Err(MemoryLimitExceeded { size, limit }.into()),
+            _ => Ok(()),
+        }
+    }
 
-                    let method_str = match method_val {
-                        Value::Str(s) => s.to_uppercase(),
-                        _ => bail!("add route: method must be a string (GET, POST, PUT, DELETE)"),
-                    };
+    /// `Write a, b, c separated by sep ending with end`, each value as
+    /// `Write` would print it, evaluated in order with `eval`
+    fn multi_write(
+        &self,
+        exprs: &[Expr],
+        sep: Option<&Expr>,
+        end: Option<&Expr>,
+        eval: impl Fn(&Expr) -> Result<Value>,
+    ) -> Result<()> {
+        let parts = exprs.iter().map(|e| eval(e).map(|v| to_string(&v))).collect::<Result<Vec<_>>>()?;
+        let sep = sep.map_or(Ok(" ".to_string()), |e| eval(e).map(|v| to_string(&v)))?;
+        let end = end.map_or(Ok("\n".to_string()), |e| eval(e).map(|v| to_string(&v)))?;
+        core_io::write_ending(&parts.join(&sep), &end);
+        Ok(())
+    }
 
-                    // Clone the handler program to execute in the route
-                    let handler_program = handler.clone();
-                    let globals_snapshot = self.globals.clone();
-                    let base_dir_snapshot = self.base_dir.clone();
-
-                    // Create handler function that executes the PohLang code
-                    let handler_fn = std::sync::Arc::new(
-                        move |request: crate::stdlib::http::HttpRequest| {
-                            // Create a new VM instance for this request
-                            let mut vm = Vm::with_base_dir(base_dir_snapshot.clone());
-                            vm.globals = globals_snapshot.clone();
-                            
-                            // Store request in VM context for access by handler
-                            vm.globals.insert("__request".to_string(), Value::HttpRequest(request));
-
-                            // Execute each statement and check for response values
-                            for stmt in &handler_program {
-                                match stmt {
-                                    crate::parser::ast::Stmt::Write(expr) => {
-                                        // Evaluate the expression
-                                        match vm.eval(expr) {
-                                            Ok(Value::HttpResponse(resp)) => {
-                                                // Capture the response and return immediately
-                                                return Ok(resp);
-                                            }
-                                            Ok(val) => {
-                                                // Regular write - just evaluate it
-                                                let _ = val;
-                                            }
-                                            Err(e) => {
-                                                return Ok(crate::stdlib::http::error_response(
-                                                    500,
-                                                    format!("Handler error: {}", e),
-                                                ));
-                                            }
-                                        }
-                                    }
-                                    _ => {
-                                        // Execute other statements normally
-                                        if let Err(e) = vm.execute(&vec![stmt.clone()]) {
-                                            return Ok(crate::stdlib::http::error_response(
-                                                500,
-                                                format!("Handler error: {}", e),
-                                            ));
-                                        }
-                                    }
-                                }
-                            }
+    /// `Log to file`, or `Log to stderr` with no path, for the `Log` lines
+    /// that follow
+    fn set_log_file(
+        &self,
+        to_file: Option<&Expr>,
+        rotate_size: Option<&Expr>,
+        eval: impl Fn(&Expr) -> Result<Value>,
+    ) -> Result<()> {
+        let file = match to_file {
+            Some(path) => {
+                let path = match eval(path)? {
+                    Value::Str(s) => s,
+                    other => bail!("Log to file: the path must be text, got {}", to_string(&other)),
+                };
+                let rotate_at = match rotate_size {
+                    Some(size) => match whole_number(&eval(size)?) {
+                        Some(n) if n > 0 => Some(n as u64),
+                        _ => bail!("Log to file ... rotating at: the size must be a whole number of bytes above 0"),
+                    },
+                    None => None,
+                };
+                Some(LogFile::open(path, rotate_at)?)
+            }
+            None => None,
+        };
+        *self.log_file.lock().unwrap() = file;
+        Ok(())
+    }
 
-                            // If no response was captured, return a default message
-                            Ok(crate::stdlib::http::html_response(
-                            "<h1>Handler executed</h1><p>No response returned. Use 'Write html response with ...' or 'Write json response with ...'</p>".to_string()
-                        ))
-                        },
-                    );
+    /// A `Log` line goes to stderr, and to the log file if there is one
+    fn log(&self, level: LogLevel, message: &Value) -> Result<()> {
+        let line = crate::stdlib::log::format_line(
+            level.label(),
+            &to_string(message),
+            std::time::SystemTime::now(),
+        );
+        eprintln!("{}", line);
+        if let Some(file) = self.log_file.lock().unwrap().as_mut() {
+            file.write_line(&line)?;
+        }
+        Ok(())
+    }
+}
 
-                    // Add route to server
-                    match server_val {
-                        Value::WebServer(server_arc) => {
-                            let mut server = server_arc.lock().unwrap();
-                            server.add_route(path_str.clone(), method_str.clone(), handler_fn);
-                            core_io::write(&format!("✓ Route added: {} {}", method_str, path_str));
-                        }
-                        _ => bail!("server variable is not a web server"),
-                    }
-                }
-                Stmt::AddMiddleware {
-                    middleware_type,
-                    config,
-                } => {
-                    // Get the server from globals
-                    let server_val = self.globals.get("server").cloned()
-                        .ok_or_else(|| anyhow!("No web server found. Create a server first"))?;
+#[cfg(feature = "web")]
+impl Vm {
+    /// `Respond with`: the value as html, json or text, whichever the request
+    /// prefers
+    fn negotiated_response(&self, v: &Value) -> Result<crate::stdlib::http::HttpResponse> {
+        use crate::stdlib::http;
+        let req = self.current_request("respond with")?;
+        let mut response = match http::preferred_response_type(req.header("Accept")) {
+            "json" => http::json_response(value_to_json(v)?),
+            "text" => http::text_response(to_string(v)),
+            _ => http::html_response(to_string(v)),
+        };
+        // Caches must keep the answers for different Accept headers apart
+        response.headers.insert("Vary".to_string(), "Accept".to_string());
+        Ok(response)
+    }
 
-                    match server_val {
-                        Value::WebServer(server_arc) => {
-                            let server = server_arc.lock().unwrap();
-                            
-                            // Parse configuration
-                            let mut config_map = std::collections::HashMap::new();
-                            for (key, expr) in config {
-                                let val = self.eval(expr)?;
-                                if let Value::Str(s) = val {
-                                    config_map.insert(key.clone(), s);
-                                }
-                            }
-                            
-                            // Add middleware based on type
-                            match middleware_type.as_str() {
-                                "cors" => {
-                                    let origins = config_map.get("origins")
-                                        .map(|s| vec![s.clone()])
-                                        .unwrap_or_else(|| vec!["*".to_string()]);
-                                    let methods = config_map.get("methods")
-                                        .map(|s| vec![s.clone()])
-                                        .unwrap_or_else(|| vec!["GET".to_string(), "POST".to_string(), "PUT".to_string(), "DELETE".to_string()]);
-                                    let headers = config_map.get("headers")
-                                        .map(|s| vec![s.clone()])
-                                        .unwrap_or_else(|| vec!["Content-Type".to_string()]);
-                                    
-                                    let middleware = crate::stdlib::middleware::cors_middleware(origins, methods, headers);
-                                    server.add_response_middleware(middleware);
-                                    core_io::write("✓ CORS middleware added");
-                                }
-                                "logging" => {
-                                    let middleware = crate::stdlib::middleware::logging_middleware();
-                                    server.add_request_middleware(middleware);
-                                    core_io::write("✓ Logging middleware added");
-                                }
-                                "auth" => {
-                                    let token_name = config_map.get("token_name")
-                                        .cloned()
-                                        .unwrap_or_else(|| "Authorization".to_string());
-                                    let token = config_map.get("token")
-                                        .cloned()
-                                        .ok_or_else(|| anyhow!("auth middleware requires 'token' config"))?;
-                                    
-                                    let middleware = crate::stdlib::middleware::auth_middleware(token_name, token);
-                                    server.add_request_middleware(middleware);
-                                    core_io::write("✓ Auth middleware added");
-                                }
-                                "security" => {
-                                    let middleware = crate::stdlib::middleware::security_headers_middleware();
-                                    server.add_response_middleware(middleware);
-                                    core_io::write("✓ Security headers middleware added");
-                                }
-                                "timing" => {
-                                    let middleware = crate::stdlib::middleware::response_time_middleware();
-                                    server.add_response_middleware(middleware);
-                                    core_io::write("✓ Response timing middleware added");
-                                }
-                                _ => {
-                                    bail!("Unknown middleware type: {}. Available: cors, logging, auth, security, timing", middleware_type);
-                                }
-                            }
-                        }
-                        _ => bail!("server variable is not a web server"),
-                    }
-                }
-                Stmt::StartServer => {
-                    // Get the server from globals
-                    let server_val = self.globals.remove("server")
-                        .ok_or_else(|| anyhow!("No web server found. Create a server first with: Make server to Create web server on port <port>"))?;
+    /// The request being handled, for the request phrases
+    fn current_request(&self, phrase: &str) -> Result<crate::stdlib::http::HttpRequest> {
+        match env_get(&self.globals, "__request") {
+            Some(Value::HttpRequest(req)) => Ok(*req),
+            Some(_) => bail!("{}: invalid request context", phrase),
+            None => bail!("{}: no request context available", phrase),
+        }
+    }
 
-                    match server_val {
-                        Value::WebServer(server_arc) => {
-                            // Add hot reload route if LiveReloadTracker exists
-                            if let Some(Value::LiveReloadTracker(tracker)) =
-                                self.globals.get("__livereload")
-                            {
-                                let tracker_clone = tracker.clone();
+    /// Header names are case-insensitive; a missing header is None
+    fn request_header(&self, name: Value) -> Result<Value> {
+        let Value::Str(name) = name else {
+            bail!("get request header: header name must be a string");
+        };
+        let req = self.current_request("get request header")?;
+        Ok(req.header(&name).map_or(Value::Null, |v| Value::Str(v.to_string())))
+    }
 
-                                // Create the /__reload_check route handler
-                                let reload_handler = Arc::new(move |_req: crate::stdlib::http::HttpRequest| -> Result<crate::stdlib::http::HttpResponse> {
-                                    let changed = tracker_clone.check_for_changes();
-                                    let response_json = serde_json::json!({
-                                        "changed": changed,
-                                        "timestamp": std::time::SystemTime::now()
-                                            .duration_since(std::time::UNIX_EPOCH)
-                                            .unwrap()
-                                            .as_millis()
-                                    });
-                                    Ok(crate::stdlib::http::json_response(response_json))
-                                });
+    fn set_response_header(&self, name: Value, value: Value) -> Result<Value> {
+        let Value::Str(name) = name else {
+            bail!("set response header: header name must be a string");
+        };
+        self.current_request("set response header")?;
+        crate::stdlib::http::set_response_header(name, to_string(&value))
+            .map_err(|e| anyhow!("set response header: {}", e))?;
+        Ok(Value::Null)
+    }
 
-                                // Add the route
-                                let route = crate::stdlib::http::Route {
-                                    path: "/__reload_check".to_string(),
-                                    method: "GET".to_string(),
-                                    handler: reload_handler,
-                                };
+    fn take_handler_response(&self) -> Option<crate::stdlib::http::HttpResponse> {
+        self.handler_response.as_ref()?.lock().unwrap().take()
+    }
 
-                                server_arc.lock().unwrap().add_route_direct(route);
-                                eprintln!("🔄 Hot reload enabled at /__reload_check");
-                            }
+    /// Answer a request with a route handler and the route hooks around it.
+    /// A `Before each route` block that writes a response or sets
+    /// `__response` answers instead of the handler. Each `After each route`
+    /// block sees the answer as the `__response` dictionary and may change
+    /// it, or write a response to replace it.
+    fn handle_route(&mut self, hooks: &RouteHooks, group: &[&Program], handler: &Program) -> HttpResponse {
+        use crate::stdlib::http::{error_response, html_response};
+        let answer = match self.before_route(hooks, group) {
+            Ok(answer) => answer,
+            Err(failed) => return failed,
+        };
+        let mut response = match answer {
+            Some(resp) => resp,
+            None => match self.run_handler(handler) {
+                Ok(Some(resp)) => resp,
+                // If no response was captured, return a default message
+                Ok(None) => html_response(
+                    "<h1>Handler executed</h1><p>No response returned. Use 'Write html response with ...' or 'Return json response with ...'</p>".to_string()
+                ),
+                Err(e) => return error_response(500, format!("Handler error: {}", e)),
+            },
+        };
+        for hook in &hooks.after {
+            env_set(&self.globals, "__response", response_value(&response));
+            let changed = self.run_handler(hook).and_then(|written| match written {
+                Some(resp) => Ok(Some(resp)),
+                None => response_from_value(&env_get(&self.globals, "__response").unwrap_or(Value::Null)),
+            });
+            match changed {
+                Ok(Some(resp)) => response = resp,
+                Ok(None) => {}
+                Err(e) => return error_response(500, format!("After each route error: {}", e)),
+            }
+        }
+        response
+    }
 
-                            eprintln!("[DEBUG] About to call server.start()...");
+    /// The answer of the first `Before each route` block, then route group
+    /// middleware block, to give one; a block that fails is answered with a
+    /// 500 at once
+    fn before_route(&mut self, hooks: &RouteHooks, group: &[&Program]) -> Result<Option<HttpResponse>, HttpResponse> {
+        use crate::stdlib::http::error_response;
+        let blocks = hooks.before.iter().map(|hook| ("Before each route", hook));
+        for (what, hook) in blocks.chain(group.iter().map(|hook| ("Group middleware", *hook))) {
+            let mut answer = match self.run_handler(hook) {
+                Ok(answer) => answer,
+                Err(e) => return Err(error_response(500, format!("{} error: {}", what, e))),
+            };
+            match env_get(&self.globals, "__response").map(|v| response_from_value(&v)) {
+                Some(Ok(Some(resp))) => answer = answer.or(Some(resp)),
+                Some(Err(e)) => return Err(error_response(500, format!("{} error: {}", what, e))),
+                _ => {}
+            }
+            if answer.is_some() {
+                return Ok(answer);
+            }
+        }
+        Ok(None)
+    }
 
-                            // Use the special function that doesn't hold the lock
-                            crate::stdlib::http::start_server_from_arc(server_arc)?;
+    /// Talk to a client that asked for a websocket route. A `Before each
+    /// route` block that answers refuses the connection; otherwise it is
+    /// accepted and the handler runs with it as `socket`. When the handler
+    /// ends, so does the connection. No `After each route` block runs.
+    fn handle_websocket(&mut self, hooks: &RouteHooks, group: &[&Program], handler: &Program) -> HttpResponse {
+        use crate::stdlib::http::{error_response, take_response_headers};
+        match self.before_route(hooks, group) {
+            Ok(None) => {}
+            Ok(Some(refused)) | Err(refused) => return refused,
+        }
+        let socket = match WebSocket::accept(&take_response_headers()) {
+            Ok(socket) => socket,
+            Err(e) => return error_response(500, format!("Handler error: {}", e)),
+        };
+        env_set(&self.globals, "socket", Value::WebSocket(socket.clone()));
+        if let Err(e) = self.run_handler(handler) {
+            eprintln!("Websocket handler error: {}", e);
+        }
+        socket.close();
+        // Never sent: the connection stopped being HTTP when it was accepted
+        HttpResponse::default()
+    }
 
-                            eprintln!("[DEBUG] Server.start() returned (unexpected!)");
-                        }
-                        _ => bail!("server variable is not a web server"),
-                    }
-                }
+    /// Run a handler or hook statement by statement. The first response
+    /// written anywhere in it (a Write, an inline If, a block, a function)
+    /// ends it and is its answer. A `Return` ends it too, answering with the
+    /// value when that is a response.
+    fn run_handler(&mut self, body: &Program) -> Result<Option<HttpResponse>> {
+        for stmt in body {
+            let returned = self.run(&vec![stmt.clone()])?;
+            if let Some(resp) = self.take_handler_response() {
+                return Ok(Some(resp));
             }
+            match returned {
+                Some(ProgramValue(Value::HttpResponse(resp))) => return Ok(Some(resp)),
+                Some(_) => return Ok(None),
+                None => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Register a route handler on the web server in the variable `server_name`,
+    /// under the prefix of the route group being defined
+    fn add_route(&mut self, server_name: &str, path: &str, method_str: String, handler: &Program, line: usize) -> Result<()> {
+        let server_val = env_get(&self.globals, server_name).ok_or_else(|| {
+            anyhow!(
+                "No web server found in '{}'. Create a server first with: Make server to Create web server on port <port>",
+                server_name
+            )
+        })?;
+        let group = self.route_groups.last().cloned().unwrap_or_default();
+        let path_str = grouped_path(&group.prefix, path);
+        for warning in lint::unknown_path_parameters(&path_str, &method_str, line, handler) {
+            eprintln!("{}", warning);
+        }
+
+        // Clone the handler program to execute in the route
+        let handler_program = handler.clone();
+        let method = method_str.clone();
+        // Share the live program scope instead of snapshotting it, so functions
+        // defined after this route are still visible when a request arrives
+        let program_env = Arc::clone(&self.globals);
+        let base_dir_snapshot = self.base_dir.clone();
+        let source_encoding = self.source_encoding;
+        let table_style = self.table_style;
+        let trace = self.tracer.settings();
+        let log_file = Arc::clone(&self.log_file);
+        let counters = Arc::clone(&self.counters);
+        let memory_limit = self.memory_limit;
+        let schemas = Arc::clone(&self.schemas);
+        let constants = Arc::clone(&self.constants);
+        let templates = Arc::clone(&self.templates);
+        let hooks = match &server_val {
+            Value::WebServer(server_arc) => server_arc.lock().unwrap().route_hooks(),
+            _ => bail!("'{}' is not a web server", server_name),
+        };
+
+        // Create handler function that executes the PohLang code
+        let handler_fn = std::sync::Arc::new(
+            move |request: crate::stdlib::http::HttpRequest| {
+                // Each request gets its own small scope on top of the program scope;
+                // its writes (including __request) never reach the shared globals
+                let mut request_vars = HashMap::new();
+                request_vars.insert("request".to_string(), request_value(&request));
+                request_vars.insert("__request".to_string(), Value::HttpRequest(Box::new(request)));
+                request_vars.insert("__response".to_string(), Value::Null);
+                let mut vm = Vm::with_base_dir(base_dir_snapshot.clone());
+                vm.source_encoding = source_encoding;
+                vm.table_style = table_style;
+                if let Some(filter) = trace.clone() {
+                    vm.enable_trace(filter);
+                }
+                vm.log_file = Arc::clone(&log_file);
+                vm.counters = Arc::clone(&counters);
+                vm.stats_enabled = counters.enabled();
+                vm.memory_limit = memory_limit;
+                vm.schemas = Arc::clone(&schemas);
+                vm.constants = Arc::clone(&constants);
+                vm.templates = Arc::clone(&templates);
+                vm.globals = Scope::new_env(request_vars, Some(Arc::clone(&program_env)));
+                vm.handler_response = Some(Mutex::new(None));
+                let hooks = hooks.read().unwrap();
+                let middleware: Vec<_> = group.middleware.iter().map(|m| m.read().unwrap()).collect();
+                let middleware: Vec<&Program> = middleware.iter().flat_map(|m| m.iter()).collect();
+                if method == websocket::METHOD {
+                    return Ok(vm.handle_websocket(&hooks, &middleware, &handler_program));
+                }
+                Ok(vm.handle_route(&hooks, &middleware, &handler_program))
+            },
+        );
+
+        // Add route to server
+        match server_val {
+            Value::WebServer(server_arc) => {
+                let mut server = server_arc.lock().unwrap();
+                server.add_route(path_str.clone(), method_str.clone(), line, handler_fn)?;
+                core_io::write(&format!("✓ Route added: {} {}", method_str, path_str));
+            }
+            _ => bail!("'{}' is not a web server", server_name),
         }
         Ok(())
     }
 
-    pub fn execute_bytecode(&mut self, bc: &[u8]) -> Result<()> {
-        // Decode program into instruction vector
-        let mut prog: Vec<Instruction> = Vec::new();
-        for line in bc.split(|&b| b == b'\n') {
-            if line.is_empty() {
-                continue;
+    /// The `grouped by` function of a rate limit, called with the request on
+    /// the request thread like a route handler. Nothing or a failed call counts the
+    /// request under the client address.
+    fn rate_limit_key(&self, f: Func) -> crate::stdlib::rate_limit::KeyFn {
+        let program_env = Arc::clone(&self.globals);
+        let base_dir = self.base_dir.clone();
+        let source_encoding = self.source_encoding;
+        let table_style = self.table_style;
+        let log_file = Arc::clone(&self.log_file);
+        let constants = Arc::clone(&self.constants);
+        Arc::new(move |request: &crate::stdlib::http::HttpRequest| {
+            let mut vm = Vm::with_base_dir(base_dir.clone());
+            vm.source_encoding = source_encoding;
+            vm.table_style = table_style;
+            vm.log_file = Arc::clone(&log_file);
+            vm.constants = Arc::clone(&constants);
+            // Request phrases work in the function as they do in a handler
+            let mut request_vars = HashMap::new();
+            request_vars.insert("__request".to_string(), Value::HttpRequest(Box::new(request.clone())));
+            vm.globals = Scope::new_env(request_vars, Some(Arc::clone(&program_env)));
+            match vm.call_func_value(&f, &[Value::HttpRequest(Box::new(request.clone()))]) {
+                Ok(Value::Null) => None,
+                Ok(key) => Some(to_string(&key)),
+                Err(e) => {
+                    eprintln!("Error in rate limit key function {}: {}", f.name, e);
+                    None
+                }
             }
-            let s = String::from_utf8_lossy(line);
-            if let Some(instr) = Instruction::decode(&s) {
-                prog.push(instr);
+        })
+    }
+}
+
+impl Vm {
+    fn declare_type(&self, name: &str, type_name: &str) -> Result<()> {
+        let mut types = self.types.write().unwrap();
+        types.declare(name, type_name);
+        if self.strict_types && types.resolve(type_name).is_none() {
+            let msg = format!("'{}' is declared as {}, which is not a known type", name, type_name);
+            let error = self.create_error(ErrorKind::TypeError, msg);
+            return Err(self.error_with_location(error.format_with_trace()));
+        }
+        Ok(())
+    }
+
+    /// What a name that is not defined evaluates to: a `<name>` placeholder,
+    /// or with `--strict` a RuntimeError
+    fn undefined_variable(&self, name: &str) -> Result<Value> {
+        if !self.strict {
+            return Ok(Value::Str(format!("<{}>", name)));
+        }
+        let error = self.create_error(ErrorKind::RuntimeError, format!("Variable '{}' is not defined", name));
+        Err(self.error_with_location(error.format_with_trace()))
+    }
+
+    /// `1` or `0` for a comparison. Only numbers have an order; ordering
+    /// anything else is false, or with `--strict` a TypeError.
+    fn compare(&self, op: &CmpOp, lv: &Value, rv: &Value) -> Result<Value> {
+        let res = match op {
+            CmpOp::Eq => values_eq(lv, rv),
+            CmpOp::Ne => !values_eq(lv, rv),
+            CmpOp::Lt | CmpOp::Le | CmpOp::Gt | CmpOp::Ge => match (number_of(lv), number_of(rv)) {
+                (Some(a), Some(b)) => compare_numbers(op, a, b),
+                _ if self.strict => {
+                    return Err(wrong_type(format!(
+                        "Only numbers can be compared by size, got {} and {}",
+                        described(lv),
+                        described(rv)
+                    )))
+                }
+                _ => false,
+            },
+        };
+        Ok(Value::Int(res as i64))
+    }
+
+    /// How many times `Repeat` runs: negative counts run no times, and so
+    /// do non-numbers unless `--strict` makes them a TypeError
+    fn repeat_count(&self, v: Value) -> Result<i64> {
+        match v {
+            Value::Int(x) => Ok(x.max(0)),
+            Value::Num(x) => Ok(x.max(0.0) as i64),
+            v if self.strict => Err(wrong_type(format!("Repeat expects a number of times, got {}", described(&v)))),
+            _ => Ok(0),
+        }
+    }
+
+    /// A number for the compiled instructions. Text, booleans and nothing
+    /// are converted, except with `--strict`, where only numbers are taken.
+    fn coerce_num(&self, v: Value) -> Result<f64> {
+        if self.strict && number_of(&v).is_none() {
+            return Err(wrong_type(format!("Expected a number, got {}", described(&v))));
+        }
+        to_num(v)
+    }
+
+    /// Fail if `name` is a constant
+    fn check_not_constant(&self, name: &str) -> Result<()> {
+        if self.constants.contains_key(name) {
+            bail!("Cannot change {}: it was set with Set constant", name);
+        }
+        Ok(())
+    }
+
+    /// Fail if `name` is a constant or, with `--strict-types`, if `v` is not
+    /// of the type `name` was declared with. Values with no base type, such
+    /// as nothing, always pass.
+    fn check_declared(&self, name: &str, v: &Value) -> Result<()> {
+        self.check_not_constant(name)?;
+        if !self.strict_types {
+            return Ok(());
+        }
+        let Some(found) = base_type(v) else {
+            return Ok(());
+        };
+        match self.types.read().unwrap().mismatch(name, found) {
+            Some(msg) => {
+                let error = self.create_error(ErrorKind::TypeError, msg);
+                Err(self.error_with_location(error.format_with_trace()))
             }
+            None => Ok(()),
         }
-        let mut stack: Vec<Value> = Vec::new();
-        let mut ip: isize = 0;
-        while let Some(instr) = prog.get(ip as usize).cloned() {
-            match instr {
-                Instruction::PushBool(b) => stack.push(Value::Bool(b)),
-                Instruction::PushNull => stack.push(Value::Null),
-                Instruction::PushNum(n) => stack.push(Value::Num(n)),
-                Instruction::PushStr(s) => stack.push(Value::Str(s)),
-                Instruction::LoadVar(name) => {
-                    let v = self
-                        .globals
-                        .get(&name)
-                        .cloned()
-                        .unwrap_or(Value::Str(format!("<{}>", name)));
-                    stack.push(v);
-                }
-                Instruction::StoreVar(name) => {
-                    let v = stack.pop().unwrap_or(Value::Null);
-                    self.globals.insert(name, v);
-                }
-                Instruction::Add => {
-                    let b = stack.pop().unwrap_or(Value::Num(0.0));
-                    let a = stack.pop().unwrap_or(Value::Num(0.0));
-                    stack.push(Value::Num(to_num(a)? + to_num(b)?));
-                }
-                Instruction::Sub => {
-                    let b = stack.pop().unwrap_or(Value::Num(0.0));
-                    let a = stack.pop().unwrap_or(Value::Num(0.0));
-                    stack.push(Value::Num(to_num(a)? - to_num(b)?));
-                }
-                Instruction::Mul => {
-                    let b = stack.pop().unwrap_or(Value::Num(0.0));
-                    let a = stack.pop().unwrap_or(Value::Num(0.0));
-                    stack.push(Value::Num(to_num(a)? * to_num(b)?));
-                }
-                Instruction::Div => {
-                    let b = stack.pop().unwrap_or(Value::Num(1.0));
-                    let a = stack.pop().unwrap_or(Value::Num(0.0));
-                    let db = to_num(b)?;
-                    if db == 0.0 {
-                        return Err(self.error_with_location("Division by zero"));
-                    }
-                    stack.push(Value::Num(to_num(a)? / db));
-                }
-                Instruction::Eq => {
-                    let b = stack.pop().unwrap_or(Value::Null);
-                    let a = stack.pop().unwrap_or(Value::Null);
-                    stack.push(Value::Num((to_string(&a) == to_string(&b)) as i32 as f64));
-                }
-                Instruction::Ne => {
-                    let b = stack.pop().unwrap_or(Value::Null);
-                    let a = stack.pop().unwrap_or(Value::Null);
-                    stack.push(Value::Num((to_string(&a) != to_string(&b)) as i32 as f64));
-                }
-                Instruction::Lt => {
-                    let b = stack.pop().unwrap_or(Value::Num(0.0));
-                    let a = stack.pop().unwrap_or(Value::Num(0.0));
-                    stack.push(Value::Num((to_num(a)? < to_num(b)?) as i32 as f64));
-                }
-                Instruction::Le => {
-                    let b = stack.pop().unwrap_or(Value::Num(0.0));
-                    let a = stack.pop().unwrap_or(Value::Num(0.0));
-                    stack.push(Value::Num((to_num(a)? <= to_num(b)?) as i32 as f64));
-                }
-                Instruction::Gt => {
-                    let b = stack.pop().unwrap_or(Value::Num(0.0));
-                    let a = stack.pop().unwrap_or(Value::Num(0.0));
-                    stack.push(Value::Num((to_num(a)? > to_num(b)?) as i32 as f64));
+    }
+
+    /// The body of an `On event` block as a handler. Like a route handler it
+    /// runs on a `Vm` of its own against `scope`, the program's or that of
+    /// the function the block is in, with the emitted value as `data`, so
+    /// what it sets stays in the handler.
+    fn event_handler(&self, body: &Program, scope: &Env) -> Handler<Value> {
+        let body = body.clone();
+        let scope = Arc::clone(scope);
+        let base_dir = self.base_dir.clone();
+        let source_encoding = self.source_encoding;
+        let table_style = self.table_style;
+        let trace = self.tracer.settings();
+        let log_file = Arc::clone(&self.log_file);
+        let counters = Arc::clone(&self.counters);
+        let memory_limit = self.memory_limit;
+        let schemas = Arc::clone(&self.schemas);
+        let constants = Arc::clone(&self.constants);
+        #[cfg(feature = "web")]
+        let templates = Arc::clone(&self.templates);
+        Arc::new(move |data| {
+            let mut vm = Vm::with_base_dir(base_dir.clone());
+            vm.source_encoding = source_encoding;
+            vm.table_style = table_style;
+            if let Some(filter) = trace.clone() {
+                vm.enable_trace(filter);
+            }
+            vm.log_file = Arc::clone(&log_file);
+            vm.counters = Arc::clone(&counters);
+            vm.stats_enabled = counters.enabled();
+            vm.memory_limit = memory_limit;
+            vm.schemas = Arc::clone(&schemas);
+            vm.constants = Arc::clone(&constants);
+            #[cfg(feature = "web")]
+            {
+                vm.templates = Arc::clone(&templates);
+            }
+            let vars = HashMap::from([("data".to_string(), data)]);
+            vm.globals = Scope::new_env(vars, Some(Arc::clone(&scope)));
+            vm.execute(&body)
+        })
+    }
+
+    /// `Set template directory to`: relative to the program
+    #[cfg(feature = "web")]
+    fn set_template_dir(&self, dir: Value) -> Result<()> {
+        let Value::Str(dir) = dir else {
+            bail!("Set template directory to: the directory must be text, got {}", described(&dir));
+        };
+        let dir = self.base_dir.join(dir);
+        if !dir.is_dir() {
+            bail!("Set template directory to: {} is not a directory", dir.display());
+        }
+        self.templates.set_dir(dir);
+        Ok(())
+    }
+
+    /// `render template file`: read through the template cache, so a file
+    /// is only read again once it has changed
+    #[cfg(feature = "web")]
+    fn render_template_file(&self, name: Value, data: &Value) -> Result<Value> {
+        let Value::Str(name) = name else {
+            bail!("render template file: the file name must be text, got {}", described(&name));
+        };
+        let template = self.templates.load(&self.base_dir, &name)?;
+        let data = value_to_json(data)?;
+        crate::stdlib::template::render_full(&template, &data)
+            .map(Value::Str)
+            .map_err(|e| anyhow!("Failed to render template {}: {}", name, e))
+    }
+
+    /// `Define schema`: the field types are checked now, so a misspelt one is
+    /// reported on its line
+    fn define_schema(&self, name: &str, fields: &[SchemaField]) -> Result<()> {
+        let types = self.types.read().unwrap();
+        let mut resolved = Vec::with_capacity(fields.len());
+        for field in fields {
+            let Some(base) = types.resolve(&field.type_name) else {
+                bail!(
+                    "Define schema {}: {} is a {}, which is not a known type; use number, string, list, dict or boolean",
+                    name,
+                    field.name,
+                    field.type_name
+                );
+            };
+            resolved.push(SchemaField {
+                type_name: base.name().to_string(),
+                ..field.clone()
+            });
+        }
+        self.schemas.write().unwrap().insert(name.to_string(), resolved);
+        Ok(())
+    }
+
+    fn schema(&self, phrase: &str, name: &str) -> Result<Vec<SchemaField>> {
+        self.schemas.read().unwrap().get(name).cloned().ok_or_else(|| {
+            anyhow!("{} as {}: there is no schema named {}; define it with 'Define schema {} with ...'", phrase, name, name, name)
+        })
+    }
+
+    /// `validate x as Schema`: a message for each field `v` gets wrong; empty
+    /// if it is valid
+    fn validate(&self, v: &Value, schema: &str) -> Result<Value> {
+        let fields = self.schema("validate", schema)?;
+        let errors = schema_errors(schema_record("validate", schema, v)?, &fields);
+        Ok(Value::Dict(errors.into_iter().map(|(field, msg)| (field, Value::Str(msg))).collect()))
+    }
+
+    /// `coerce x as Schema`: only the schema's fields of `v`, with defaults for
+    /// the ones it leaves out. A ValidationError if any field is wrong.
+    fn coerce(&self, v: &Value, schema: &str) -> Result<Value> {
+        let fields = self.schema("coerce", schema)?;
+        let record = schema_record("coerce", schema, v)?;
+        let errors = schema_errors(record, &fields);
+        if !errors.is_empty() {
+            let problems: Vec<String> = errors.iter().map(|(field, msg)| format!("{} {}", field, msg)).collect();
+            let msg = format!("coerce as {}: {}", schema, problems.join("; "));
+            let error = self.create_error(ErrorKind::ValidationError, msg);
+            return Err(self.error_with_location(error.format_with_trace()));
+        }
+        let mut clean = HashMap::new();
+        for field in &fields {
+            let value = match (record.get(&field.name), &field.default) {
+                (Some(v), _) if !matches!(v, Value::Null) => v.clone(),
+                (_, Some(default)) => self.eval(default)?,
+                _ => continue,
+            };
+            clean.insert(field.name.clone(), value);
+        }
+        Ok(Value::Dict(clean))
+    }
+
+    /// `require x to match schema`: a ValidationError listing every problem
+    fn require_match(&self, v: &Value, schema: &Value) -> Result<()> {
+        let problems = shape_problems(v, schema).map_err(|e| anyhow!("require ... to match: {}", e))?;
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let msg = format!("does not match the schema: {}", problems.join("; "));
+        let error = self.create_error(ErrorKind::ValidationError, msg);
+        Err(self.error_with_location(error.format_with_trace()))
+    }
+
+    /// Set a program variable. One made by `Bind` keeps its block, which
+    /// then runs with `__old_value` and `__new_value`; the variable is not
+    /// bound while it runs, so setting it there does not run the block again.
+    fn assign_global(&mut self, name: &str, v: Value) -> Result<()> {
+        let bound = match self.globals.read().unwrap().vars.get(name) {
+            Some(Value::Observed(old, on_change)) => Some((old.as_ref().clone(), Arc::clone(on_change))),
+            _ => None,
+        };
+        let Some((old, on_change)) = bound else {
+            env_set(&self.globals, name, v);
+            return Ok(());
+        };
+        env_set(&self.globals, name, v.clone());
+        env_set(&self.globals, "__old_value", old);
+        env_set(&self.globals, "__new_value", v);
+        let result = self.execute_stmts(&on_change);
+        let current = env_get(&self.globals, name).unwrap_or(Value::Null);
+        env_set(&self.globals, name, Value::Observed(Box::new(current), on_change));
+        result
+    }
+
+    /// `Assert`: an AssertionError when the values differ, listing each
+    /// difference for `deep equals`
+    fn check_assert(&self, deep_equal: bool, actual: &Value, expected: &Value) -> Result<()> {
+        let problems = if deep_equal {
+            deep_diff(actual, expected, "")
+        } else if values_eq(actual, expected) {
+            Vec::new()
+        } else {
+            vec![format!("Expected {} but got {}", diff_value(expected), diff_value(actual))]
+        };
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let error = self.create_error(ErrorKind::Custom("AssertionError".to_string()), problems.join("; "));
+        Err(self.error_with_location(error.format_with_trace()))
+    }
+
+    /// Report how long a `Benchmark` block took, and write the flame graph
+    /// if one is being made
+    fn finish_benchmark(&self, name: &str, elapsed: Duration) -> Result<()> {
+        eprintln!("Benchmark \"{}\": {:.3} ms", name, elapsed.as_secs_f64() * 1000.0);
+        if let Some(path) = self.profiler.finish()? {
+            eprintln!("Flame graph written to {}", path.display());
+        }
+        Ok(())
+    }
+
+    /// What a `Send event` or `Send` sends: text as it is, anything else as JSON
+    #[cfg(feature = "web")]
+    fn event_data(&self, v: &Value) -> Result<String> {
+        match v {
+            Value::Str(s) => Ok(s.clone()),
+            other => Ok(value_to_json(other)?.to_string()),
+        }
+    }
+
+    /// `Send`: a copy of the value to a channel, or the value as text to a
+    /// websocket
+    fn send_to(&self, message: &Value, target: &Value) -> Result<()> {
+        match target {
+            Value::Channel(channel) => {
+                let message = sendable(message);
+                let size = approx_size(&message);
+                // What waits in the channel counts, not only this message
+                self.within_limit(channel.queued_size() + size)?;
+                channel.send(message, size)
+            }
+            #[cfg(feature = "web")]
+            _ => websocket_of(target)?.send(&self.event_data(message)?),
+            #[cfg(not(feature = "web"))]
+            other => bail!("Expected a channel to send to, got {}", described(other)),
+        }
+    }
+
+    /// `Checkpoint`: save the state as JSON beside the program
+    fn save_checkpoint(&self, name: &str, state: &Value) -> Result<()> {
+        let state = value_to_json(state)
+            .map_err(|e| self.error_with_location(format!("Checkpoint {}: {}", name, e)))?;
+        checkpoint::save(&self.base_dir, name, state)
+    }
+
+    /// `Restore checkpoint`: the state last saved, or nothing if none was
+    /// or with `--no-resume`
+    fn restore_checkpoint(&self, name: &str) -> Result<Value> {
+        if !self.resume {
+            return Ok(Value::Null);
+        }
+        match checkpoint::load(&self.base_dir, name)? {
+            Some(state) => json_to_value(&state),
+            None => Ok(Value::Null),
+        }
+    }
+
+    /// The tasks a `Wait for ...` names, in the order it names them
+    fn tasks(&self, names: &[String], get: impl Fn(&str) -> Option<Value>) -> Result<Vec<Arc<Task<Value>>>> {
+        names
+            .iter()
+            .map(|name| match get(name) {
+                Some(Value::Future(task)) => Ok(task),
+                Some(other) => Err(self.error_with_location(format!("'{}' is not a task, it is {}", name, described(&other)))),
+                None => Err(self.error_with_location(format!("No task named '{}' has been started", name))),
+            })
+            .collect()
+    }
+
+    /// A `finally` block still runs while a `Return` unwinds; a `Return`
+    /// inside it replaces the pending value
+    fn execute_finally(&mut self, fin: &Program) -> Result<()> {
+        let pending = self.returned.take();
+        self.execute_stmts(fin)?;
+        if self.returned.is_none() {
+            self.returned = pending;
+        }
+        Ok(())
+    }
+
+    fn execute_stmts(&mut self, prog: &Program) -> Result<()> {
+        let _nested = self.tracer.enabled().then(|| self.tracer.nested());
+        for stmt in prog {
+            // A top-level Return skips everything after it, block by block
+            if self.returned.is_some() {
+                return Ok(());
+            }
+            if let Stmt::Line(n) = stmt {
+                self.tracer.set_line(*n);
+                continue;
+            }
+            if self.stats_enabled {
+                self.counters.statement();
+            }
+            if let Some(debugger) = &self.debugger {
+                let scope = StopScope { vm: self, frame: None };
+                debugger.before(self.tracer.line(), || dump_stmt(stmt), &scope);
+            }
+            // A Set is traced once its value is known
+            if self.tracer.enabled() && !matches!(stmt, Stmt::Set { .. }) {
+                self.tracer.emit(&dump_stmt(stmt));
+            }
+            match stmt {
+                Stmt::Write(e) => {
+                    let v = match e {
+                        // If Write is given a bare function ident, attempt to call it with no args
+                        Expr::Ident(name) => {
+                            if let Some(Value::Func(f)) = env_get(&self.globals, name) {
+                                self.call_func_value(&f, &[])?
+                            } else {
+                                self.eval(e)?
+                            }
+                        }
+                        _ => self.eval(e)?,
+                    };
+                    self.write_value(&v)?;
                 }
-                Instruction::Ge => {
-                    let b = stack.pop().unwrap_or(Value::Num(0.0));
-                    let a = stack.pop().unwrap_or(Value::Num(0.0));
-                    stack.push(Value::Num((to_num(a)? >= to_num(b)?) as i32 as f64));
+                Stmt::MultiWrite { exprs, sep, end } => {
+                    self.multi_write(exprs, sep.as_ref(), end.as_ref(), |e| self.eval(e))?;
                 }
-                Instruction::Jump(tgt) => {
-                    ip = tgt as isize;
-                    continue;
+                Stmt::AskFor { var_name, expect } => {
+                    let value = self.ask_for(var_name, *expect)?;
+                    env_set(&self.globals, var_name, value);
                 }
-                Instruction::JumpIfFalse(tgt) => {
-                    let v = stack.pop().unwrap_or(Value::Num(0.0));
-                    let truthy = match v {
-                        Value::Num(n) => n != 0.0,
-                        Value::Bool(b) => b,
-                        Value::Str(s) => !s.is_empty(),
-                        Value::Null => false,
-                        _ => true,
-                    };
-                    if !truthy {
-                        ip = tgt as isize;
-                        continue;
+                Stmt::IfInline {
+                    cond,
+                    then_write,
+                    otherwise_write,
+                } => {
+                    let c = self.truthy(&self.eval(cond)?)?;
+                    if c {
+                        let v = self.eval(then_write)?;
+                        self.write_value(&v)?;
+                    } else if let Some(e) = otherwise_write {
+                        let v = self.eval(e)?;
+                        self.write_value(&v)?;
                     }
                 }
-                Instruction::WriteTop => {
-                    let v = stack.last().cloned().unwrap_or(Value::Null);
-                    core_io::write(&to_string(&v));
+                Stmt::IfBlock {
+                    cond,
+                    then_body,
+                    otherwise_body,
+                } => {
+                    let c = self.truthy(&self.eval(cond)?)?;
+                    if c {
+                        self.execute_stmts(then_body)?;
+                    } else if let Some(eb) = otherwise_body {
+                        self.execute_stmts(eb)?;
+                    }
                 }
-                Instruction::AskVar(name) => {
-                    let input = core_io::ask("");
-                    let value = if let Ok(n) = input.parse::<f64>() {
-                        Value::Num(n)
-                    } else {
-                        Value::Str(input)
+                Stmt::FuncInline { name, params, body } => {
+                    let f = Func {
+                        name: name.clone(),
+                        params: params.clone(),
+                        body: FuncBody::Expr(body.clone()),
+                        captured: Arc::clone(&self.globals),
                     };
-                    self.globals.insert(name, value);
+                    env_set(&self.globals, name, Value::Func(f));
                 }
-            }
-            ip += 1;
-        }
-        Ok(())
-    }
-
-    fn eval(&self, e: &Expr) -> Result<Value> {
-        match e {
-            Expr::Str(s) => Ok(Value::Str(s.clone())),
-            Expr::Num(n) => Ok(Value::Num(*n)),
-            Expr::Bool(b) => Ok(Value::Bool(*b)),
-            Expr::Null => Ok(Value::Null),
-            Expr::Ident(name) => {
-                if let Some(v) = self.resolve_value(name) {
-                    return Ok(v);
+                Stmt::FuncBlock { name, params, body } => {
+                    let f = Func {
+                        name: name.clone(),
+                        params: params.clone(),
+                        body: FuncBody::Block(FnBlock::new(params, body, !self.shows_statements())),
+                        captured: Arc::clone(&self.globals),
+                    };
+                    env_set(&self.globals, name, Value::Func(f));
                 }
-                Ok(Value::Str(format!("<{}>", name)))
-            }
-            Expr::Plus(a, b) => {
-                let sa = self.eval(a)?;
-                let sb = self.eval(b)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => Ok(Value::Num(na + nb)),
-                    (x, y) => Ok(Value::Str(format!("{}{}", to_string(&x), to_string(&y)))),
-                }
-            }
-            Expr::Minus(a, b) => {
-                let sa = self.eval(a)?;
-                let sb = self.eval(b)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => Ok(Value::Num(na - nb)),
-                    _ => Err(anyhow!("Cannot subtract non-numeric values")),
-                }
-            }
-            Expr::Times(a, b) => {
-                let sa = self.eval(a)?;
-                let sb = self.eval(b)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => Ok(Value::Num(na * nb)),
-                    _ => Err(anyhow!("Cannot multiply non-numeric values")),
-                }
-            }
-            Expr::DividedBy(a, b) => {
-                let sa = self.eval(a)?;
-                let sb = self.eval(b)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => {
-                        if nb == 0.0 {
-                            Err(self.error_with_location("Division by zero"))
-                        } else {
-                            Ok(Value::Num(na / nb))
+                Stmt::WhileBlock { cond, body } => {
+                    // Evaluate while the condition is truthy; prevent infinite tight loop by a simple iteration cap for safety (optional)
+                    let mut guard = 0usize;
+                    while self.truthy(&self.eval(cond)?)? {
+                        self.execute_stmts(body)?;
+                        if self.returned.is_some() {
+                            break;
+                        }
+                        guard += 1;
+                        if guard > 1_000_000 {
+                            break;
                         }
                     }
-                    _ => Err(anyhow!("Cannot divide non-numeric values")),
                 }
-            }
-            Expr::And(a, b) => {
-                let la = self.truthy(&self.eval(a)?)?;
-                if !la {
-                    return Ok(Value::Num(0.0));
-                }
-                let lb = self.truthy(&self.eval(b)?)?;
-                Ok(if lb { Value::Num(1.0) } else { Value::Num(0.0) })
-            }
-            Expr::Or(a, b) => {
-                let la = self.truthy(&self.eval(a)?)?;
-                if la {
-                    return Ok(Value::Num(1.0));
-                }
-                let lb = self.truthy(&self.eval(b)?)?;
-                Ok(if lb { Value::Num(1.0) } else { Value::Num(0.0) })
-            }
-            Expr::Not(a) => {
-                let la = self.truthy(&self.eval(a)?)?;
-                Ok(if !la {
-                    Value::Num(1.0)
-                } else {
-                    Value::Num(0.0)
-                })
-            }
-            Expr::Cmp(op, l, r) => {
-                let lv = self.eval(l)?;
-                let rv = self.eval(r)?;
-                let res = match op {
-                    CmpOp::Eq => to_string(&lv) == to_string(&rv),
-                    CmpOp::Ne => to_string(&lv) != to_string(&rv),
-                    CmpOp::Lt | CmpOp::Le | CmpOp::Gt | CmpOp::Ge => {
-                        let ln = match lv {
-                            Value::Num(n) => Some(n),
-                            _ => None,
-                        };
-                        let rn = match rv {
-                            Value::Num(n) => Some(n),
-                            _ => None,
-                        };
-                        if let (Some(a), Some(b)) = (ln, rn) {
-                            match op {
-                                CmpOp::Lt => a < b,
-                                CmpOp::Le => a <= b,
-                                CmpOp::Gt => a > b,
-                                CmpOp::Ge => a >= b,
-                                _ => unreachable!(),
-                            }
-                        } else {
-                            false
+                Stmt::RepeatUntil { cond, body } => {
+                    // The condition is checked before each pass, as in While
+                    let mut guard = 0usize;
+                    while !self.truthy(&self.eval(cond)?)? {
+                        self.execute_stmts(body)?;
+                        if self.returned.is_some() {
+                            break;
+                        }
+                        guard += 1;
+                        if guard > 1_000_000 {
+                            break;
                         }
                     }
-                };
-                Ok(if res {
-                    Value::Num(1.0)
-                } else {
-                    Value::Num(0.0)
-                })
-            }
-            Expr::Call { name, args } => {
-                let argv = args
-                    .iter()
-                    .map(|e| self.eval(e))
-                    .collect::<Result<Vec<_>>>()?;
-                self.call_function(name, &argv)
-            }
-            Expr::ListLit(items) => {
-                let mut out = Vec::new();
-                for it in items {
-                    out.push(self.eval(it)?);
                 }
-                Ok(Value::List(out))
-            }
-            Expr::DictLit(pairs) => {
-                let mut map = HashMap::new();
-                for (k, ve) in pairs {
-                    map.insert(k.clone(), self.eval(ve)?);
+                Stmt::RepeatBlock { count, body } => {
+                    let n = self.repeat_count(self.eval(count)?)?;
+                    for _ in 0..n {
+                        self.execute_stmts(body)?;
+                        if self.returned.is_some() {
+                            break;
+                        }
+                    }
                 }
-                Ok(Value::Dict(map))
-            }
-            Expr::Index(base, index) => {
-                let base_val = self.eval(base)?;
-                let index_val = self.eval(index)?;
-
-                match (&base_val, &index_val) {
-                    (Value::List(items), Value::Num(n)) => {
-                        let idx = *n as i32;
-                        let len = items.len() as i32;
-                        // Support negative indexing
-                        let actual_idx = if idx < 0 { len + idx } else { idx };
-
-                        if actual_idx < 0 || actual_idx >= len {
-                            let msg =
-                                format!("List index out of range: {} (list length: {})", idx, len);
-                            return Err(anyhow!("{}", enhance_error(&msg)));
+                Stmt::ReadLineLoop { var_name, body } => {
+                    while let Some(line) = core_io::read_raw_line() {
+                        env_set(&self.globals, var_name, Value::Str(line));
+                        self.execute_stmts(body)?;
+                        if self.returned.is_some() {
+                            break;
                         }
-                        Ok(items[actual_idx as usize].clone())
                     }
-                    (Value::Dict(map), Value::Str(key)) => map.get(key).cloned().ok_or_else(|| {
-                        let msg = format!("Key not found in dictionary: \"{}\"", key);
-                        anyhow!("{}", enhance_error(&msg))
-                    }),
-                    (Value::Str(s), Value::Num(n)) => {
-                        let idx = *n as i32;
-                        let chars: Vec<char> = s.chars().collect();
-                        let len = chars.len() as i32;
-                        let actual_idx = if idx < 0 { len + idx } else { idx };
-
-                        if actual_idx < 0 || actual_idx >= len {
-                            return Err(anyhow!(
-                                "String index out of range: {} (string length: {})",
-                                idx,
-                                len
-                            ));
+                }
+                Stmt::ForEach { var_name, items, body } => {
+                    for item in each_item(self.eval(items)?, "For each")? {
+                        env_set(&self.globals, var_name, item);
+                        self.execute_stmts(body)?;
+                        if self.returned.is_some() {
+                            break;
                         }
-                        Ok(Value::Str(chars[actual_idx as usize].to_string()))
                     }
-                    _ => Err(anyhow!("Cannot index {:?} with {:?}", base_val, index_val)),
                 }
-            }
-            // Phrasal built-in expressions
-            Expr::TotalOf(expr) => {
-                let val = self.eval(expr)?;
-                builtin_sum(&[val])
-            }
-            Expr::SmallestIn(expr) => {
-                let val = self.eval(expr)?;
-                builtin_min(&[val])
-            }
-            Expr::LargestIn(expr) => {
-                let val = self.eval(expr)?;
-                builtin_max(&[val])
-            }
-            Expr::AbsoluteValueOf(expr) => {
-                let val = self.eval(expr)?;
-                builtin_abs(&[val])
-            }
-            Expr::Round(expr) => {
-                let val = self.eval(expr)?;
-                builtin_round(&[val])
-            }
-            Expr::RoundDown(expr) => {
-                let val = self.eval(expr)?;
-                builtin_floor(&[val])
-            }
-            Expr::RoundUp(expr) => {
-                let val = self.eval(expr)?;
-                builtin_ceil(&[val])
-            }
-            Expr::MakeUppercase(expr) => {
-                let val = self.eval(expr)?;
-                builtin_uppercase(&[val])
-            }
-            Expr::MakeLowercase(expr) => {
-                let val = self.eval(expr)?;
-                builtin_lowercase(&[val])
-            }
-            Expr::TrimSpaces(expr) => {
-                let val = self.eval(expr)?;
-                builtin_trim(&[val])
-            }
-            Expr::FirstIn(expr) => {
-                let val = self.eval(expr)?;
-                builtin_first(&[val])
-            }
-            Expr::LastIn(expr) => {
-                let val = self.eval(expr)?;
-                builtin_last(&[val])
-            }
-            Expr::ReverseOf(expr) => {
-                let val = self.eval(expr)?;
-                builtin_reverse(&[val])
-            }
-            Expr::CountOf(expr) => {
-                let val = self.eval(expr)?;
-                builtin_length(&[val])
-            }
-            Expr::JoinWith(a, b) => {
-                let va = self.eval(a)?;
-                let vb = self.eval(b)?;
-                builtin_join(&[va, vb])
-            }
-            Expr::SplitBy(a, b) => {
-                let va = self.eval(a)?;
-                let vb = self.eval(b)?;
-                builtin_split(&[va, vb])
-            }
-            // Collection operations
-            Expr::Contains(item, collection) => {
-                let item_val = self.eval(item)?;
-                let coll_val = self.eval(collection)?;
-                builtin_contains(&[item_val, coll_val])
-            }
-            Expr::Remove(item, list) => {
-                let item_val = self.eval(item)?;
-                let list_val = self.eval(list)?;
-                builtin_remove(&[item_val, list_val])
-            }
-            Expr::Append(item, list) => {
-                let item_val = self.eval(item)?;
-                let list_val = self.eval(list)?;
-                builtin_append(&[item_val, list_val])
-            }
-            Expr::InsertAt(item, index, list) => {
-                let item_val = self.eval(item)?;
-                let index_val = self.eval(index)?;
-                let list_val = self.eval(list)?;
-                builtin_insert_at(&[item_val, index_val, list_val])
-            }
-            // File I/O operations
-            Expr::ReadFile(path_expr) => {
-                let path_val = self.eval(path_expr)?;
-                let path = match path_val {
-                    Value::Str(s) => s,
-                    _ => bail!("read file at: path must be a string"),
-                };
-                match crate::stdlib::file::read_file(&path) {
-                    Ok(content) => Ok(Value::Str(content)),
-                    Err(e) => bail!("Failed to read file '{}': {}", path, e),
+                Stmt::ImportLocal { path } => {
+                    self.import_local(path)?;
                 }
-            }
-            Expr::WriteFile(content_expr, path_expr) => {
-                let content_val = self.eval(content_expr)?;
-                let path_val = self.eval(path_expr)?;
-                let content = match content_val {
-                    Value::Str(s) => s,
-                    Value::Num(n) => n.to_string(),
-                    Value::Bool(b) => b.to_string(),
-                    _ => bail!("write to file: content must be string, number, or boolean"),
-                };
-                let path = match path_val {
-                    Value::Str(s) => s,
-                    _ => bail!("write to file at: path must be a string"),
-                };
-                match crate::stdlib::file::write_file(&path, &content) {
-                    Ok(_) => Ok(Value::Null),
-                    Err(e) => bail!("Failed to write file '{}': {}", path, e),
+                Stmt::ImportAs { path, alias } => {
+                    self.import_local_as(path, alias)?;
                 }
-            }
-            Expr::AppendFile(content_expr, path_expr) => {
-                let content_val = self.eval(content_expr)?;
-                let path_val = self.eval(path_expr)?;
-                let content = match content_val {
-                    Value::Str(s) => s,
-                    Value::Num(n) => n.to_string(),
-                    Value::Bool(b) => b.to_string(),
-                    _ => bail!("append to file: content must be string, number, or boolean"),
-                };
-                let path = match path_val {
-                    Value::Str(s) => s,
-                    _ => bail!("append to file at: path must be a string"),
-                };
-                match crate::stdlib::file::append_file(&path, &content) {
-                    Ok(_) => Ok(Value::Null),
-                    Err(e) => bail!("Failed to append to file '{}': {}", path, e),
+                Stmt::ImportItems { path, items } => {
+                    self.import_items(path, items)?;
                 }
-            }
-            Expr::FileExists(path_expr) => {
-                let path_val = self.eval(path_expr)?;
-                let path = match path_val {
-                    Value::Str(s) => s,
-                    _ => bail!("file exists at: path must be a string"),
-                };
-                Ok(Value::Bool(crate::stdlib::file::file_exists(&path)))
-            }
-            Expr::DeleteFile(path_expr) => {
-                let path_val = self.eval(path_expr)?;
-                let path = match path_val {
-                    Value::Str(s) => s,
-                    _ => bail!("delete file at: path must be a string"),
-                };
-                match crate::stdlib::file::delete_file(&path) {
-                    Ok(_) => Ok(Value::Null),
-                    Err(e) => bail!("Failed to delete file '{}': {}", path, e),
+                Stmt::ImportSystem {
+                    name,
+                    alias,
+                    exposing,
+                } => {
+                    self.import_system(name, alias.as_deref(), exposing)?;
                 }
-            }
-            Expr::CreateDir(path_expr) => {
-                let path_val = self.eval(path_expr)?;
-                let path = match path_val {
-                    Value::Str(s) => s,
-                    _ => bail!("create directory at: path must be a string"),
-                };
-                match crate::stdlib::file::create_directory(&path) {
-                    Ok(_) => Ok(Value::Null),
-                    Err(e) => bail!("Failed to create directory '{}': {}", path, e),
+                Stmt::Use { name, args, result_var } => {
+                    let argv = args
+                        .iter()
+                        .map(|e| self.eval(e))
+                        .collect::<Result<Vec<_>>>()?;
+                    // Use runs a function for its effects; Write prints a result
+                    let v = self.call_function(name, &argv)?;
+                    if let Some(var) = result_var {
+                        self.check_declared(var, &v)?;
+                        env_set(&self.globals, var, v);
+                    }
                 }
-            }
-            Expr::ListDir(path_expr) => {
-                let path_val = self.eval(path_expr)?;
-                let path = match path_val {
-                    Value::Str(s) => s,
-                    _ => bail!("list files in: path must be a string"),
-                };
-                match crate::stdlib::file::list_directory(&path) {
-                    Ok(files) => Ok(Value::List(files.into_iter().map(Value::Str).collect())),
-                    Err(e) => bail!("Failed to list directory '{}': {}", path, e),
+                Stmt::Set { name, value } => {
+                    let v = self.eval(value)?;
+                    if self.tracer.enabled() {
+                        self.tracer.emit(&format!("{} → {}", dump_stmt(stmt), trace_value(&v)));
+                    }
+                    self.check_declared(name, &v)?;
+                    self.assign_global(name, v)?;
+                }
+                Stmt::DataBinding { var, to, on_change } => {
+                    self.check_not_constant(var)?;
+                    let v = self.eval(to)?;
+                    self.check_declared(var, &v)?;
+                    env_set(&self.globals, var, Value::Observed(Box::new(v), Arc::new(on_change.clone())));
+                }
+                Stmt::TypeAlias { name, base_type } => self.types.write().unwrap().alias(name, base_type),
+                Stmt::Schema { name, fields } => self.define_schema(name, fields)?,
+                #[cfg(feature = "web")]
+                Stmt::TemplateDirectory(dir) => self.set_template_dir(self.eval(dir)?)?,
+                Stmt::DeclareType { name, type_name } => self.declare_type(name, type_name)?,
+                Stmt::GlobalConst { name, .. } => {
+                    // Set by declare_constants before the program started
+                    if !self.constants.contains_key(name) {
+                        bail!("Set constant {}: constants are set at the top level of a program", name);
+                    }
                 }
-            }
-            Expr::ReadLines(path_expr) => {
-                let path_val = self.eval(path_expr)?;
-                let path = match path_val {
-                    Value::Str(s) => s,
-                    _ => bail!("read lines from: path must be a string"),
-                };
-                match crate::stdlib::file::read_lines(&path) {
-                    Ok(lines) => Ok(Value::List(lines.into_iter().map(Value::Str).collect())),
-                    Err(e) => bail!("Failed to read lines from '{}': {}", path, e),
+                Stmt::SetItem { name, key, value } => {
+                    self.check_not_constant(name)?;
+                    let collection = self.eval(&Expr::Ident(name.clone()))?;
+                    let key = self.eval(key)?;
+                    let v = self.eval(value)?;
+                    let updated = self.sized(set_item(name, collection, key, v)?)?;
+                    self.assign_global(name, updated)?;
                 }
-            }
-            Expr::CopyFile(source_expr, dest_expr) => {
-                let source_val = self.eval(source_expr)?;
-                let dest_val = self.eval(dest_expr)?;
-                let source = match source_val {
-                    Value::Str(s) => s,
-                    _ => bail!("copy file from: source path must be a string"),
-                };
-                let dest = match dest_val {
-                    Value::Str(s) => s,
-                    _ => bail!("copy file to: destination path must be a string"),
-                };
-                match crate::stdlib::file::copy_file(&source, &dest) {
-                    Ok(_) => Ok(Value::Null),
-                    Err(e) => bail!("Failed to copy file from '{}' to '{}': {}", source, dest, e),
+                Stmt::Destructure { names, value } => {
+                    for name in names {
+                        self.check_not_constant(name)?;
+                    }
+                    let v = self.eval(value)?;
+                    for (name, item) in names.iter().zip(destructure(names, v)?) {
+                        env_set(&self.globals, name, item);
+                    }
                 }
-            }
-            Expr::MoveFile(source_expr, dest_expr) => {
-                let source_val = self.eval(source_expr)?;
-                let dest_val = self.eval(dest_expr)?;
-                let source = match source_val {
-                    Value::Str(s) => s,
-                    _ => bail!("move file from: source path must be a string"),
-                };
-                let dest = match dest_val {
-                    Value::Str(s) => s,
-                    _ => bail!("move file to: destination path must be a string"),
-                };
-                match crate::stdlib::file::move_file(&source, &dest) {
-                    Ok(_) => Ok(Value::Null),
-                    Err(e) => bail!("Failed to move file from '{}' to '{}': {}", source, dest, e),
+                Stmt::SetLocal { slot, .. } => {
+                    bail!("local slot {} assigned outside a function body", slot)
                 }
-            }
-            // JSON operations
-            Expr::ParseJson(json_str_expr) => {
-                let json_str_val = self.eval(json_str_expr)?;
-                let json_str = match json_str_val {
-                    Value::Str(s) => s,
-                    _ => bail!("parse json from: input must be a string"),
-                };
-                match crate::stdlib::network::parse_json(&json_str) {
-                    Ok(json_value) => {
-                        // Convert serde_json::Value to our Value
-                        self.json_to_value(&json_value)
+                Stmt::PutEnvInScope {
+                    prefix,
+                    strip_prefix,
+                } => {
+                    for (name, value) in env_vars_with_prefix(prefix, *strip_prefix) {
+                        env_set(&self.globals, &name, value);
                     }
-                    Err(e) => bail!("Failed to parse JSON: {}", e),
                 }
-            }
-            Expr::ToJson(value_expr) => {
-                let value = self.eval(value_expr)?;
-                let json_value = self.value_to_json(&value)?;
-                match crate::stdlib::network::json_stringify(&json_value) {
-                    Ok(json_str) => Ok(Value::Str(json_str)),
-                    Err(e) => bail!("Failed to convert to JSON: {}", e),
+                Stmt::PrintTable { headers, rows } => {
+                    let text = table_text(&self.eval(headers)?, &self.eval(rows)?, self.table_style)?;
+                    core_io::write(&text);
                 }
-            }
-            Expr::ToJsonPretty(value_expr) => {
-                let value = self.eval(value_expr)?;
-                let json_value = self.value_to_json(&value)?;
-                match crate::stdlib::network::json_stringify_pretty(&json_value) {
-                    Ok(json_str) => Ok(Value::Str(json_str)),
-                    Err(e) => bail!("Failed to convert to pretty JSON: {}", e),
+                Stmt::Return(e) => {
+                    let v = match e {
+                        Some(e) => self.eval(e)?,
+                        None => Value::Null,
+                    };
+                    self.returned = Some(v);
                 }
-            }
-            Expr::GetPathParam(param_name_expr) => {
-                let param_name_val = self.eval(param_name_expr)?;
-                let param_name = match param_name_val {
-                    Value::Str(s) => s,
-                    _ => bail!("get path parameter: parameter name must be a string"),
-                };
-                
-                // Get the request from VM context
-                let request_val = self.globals.get("__request")
-                    .ok_or_else(|| anyhow!("get path parameter: no request context available"))?;
-                    
-                match request_val {
-                    Value::HttpRequest(ref req) => {
-                        req.path_params.get(&param_name)
-                            .cloned()
-                            .map(Value::Str)
-                            .ok_or_else(|| anyhow!("Path parameter '{}' not found", param_name))
+                Stmt::TryCatch {
+                    try_block,
+                    catch_handlers,
+                    finally_block,
+                } => {
+                    let try_result = self.execute_stmts(try_block);
+                    let caught = try_result.as_ref().err().and_then(|err| catch_handler(err, catch_handlers));
+                    // An error no handler matches is raised again once finally has run
+                    let result = match caught {
+                        Some((handler, caught)) => {
+                            if let Some(var_name) = &handler.var_name {
+                                env_set(&self.globals, var_name, caught);
+                            }
+                            self.execute_stmts(&handler.block)
+                        }
+                        None => try_result,
+                    };
+                    if let Some(fin) = finally_block {
+                        self.execute_finally(fin)?;
                     }
-                    _ => bail!("get path parameter: invalid request context"),
+                    result?;
                 }
-            }
-            Expr::JsonGet(json_expr, key_expr) => {
-                let json_val = self.eval(json_expr)?;
-                let key_val = self.eval(key_expr)?;
-                let key = match key_val {
-                    Value::Str(s) => s,
-                    _ => bail!("get from json: key must be a string"),
-                };
-                match json_val {
-                    Value::Dict(ref map) => map
-                        .get(&key)
-                        .cloned()
-                        .ok_or_else(|| anyhow!("Key '{}' not found in JSON object", key)),
-                    _ => bail!("get from json: first argument must be a JSON object (dictionary)"),
+                Stmt::Throw(expr) => {
+                    let val = self.eval(expr)?;
+                    let error_msg = match val {
+                        Value::Error(e) => e.format_with_trace(),
+                        _ => {
+                            let msg = to_string(&val);
+                            let error = self.create_error(ErrorKind::RuntimeError, msg);
+                            error.format_with_trace()
+                        }
+                    };
+                    return Err(self.error_with_location(error_msg));
                 }
-            }
-            Expr::JsonSet(json_expr, key_expr, value_expr) => {
-                let json_val = self.eval(json_expr)?;
-                let key_val = self.eval(key_expr)?;
-                let value_val = self.eval(value_expr)?;
-                let key = match key_val {
-                    Value::Str(s) => s,
-                    _ => bail!("set in json: key must be a string"),
+                Stmt::Log {
+                    to_file,
+                    rotate_size,
+                } => self.set_log_file(to_file.as_ref(), rotate_size.as_ref(), |e| self.eval(e))?,
+                Stmt::LogMessage { level, message } => {
+                    let v = self.eval(message)?;
+                    self.log(*level, &v)?;
+                }
+                #[cfg(feature = "web")]
+                Stmt::AddRoute {
+                    path,
+                    method,
+                    handler,
+                    line,
+                } => {
+                    let path_str = match self.eval(path)? {
+                        Value::Str(s) => s,
+                        _ => bail!("add route: path must be a string"),
+                    };
+                    let method_str = match self.eval(method)? {
+                        Value::Str(s) => s.to_uppercase(),
+                        _ => bail!("add route: method must be a string (GET, POST, PUT, DELETE)"),
+                    };
+                    self.add_route("server", &path_str, method_str, handler, *line)?;
+                }
+                #[cfg(feature = "web")]
+                Stmt::Route {
+                    method,
+                    path,
+                    server,
+                    handler,
+                    line,
+                } => {
+                    self.add_route(server.as_deref().unwrap_or("server"), path, method.clone(), handler, *line)?;
+                }
+                #[cfg(feature = "web")]
+                Stmt::AddMiddleware {
+                    middleware_type,
+                    config,
+                } => {
+                    // Get the server from globals
+                    let server_val = env_get(&self.globals, "server")
+                        .ok_or_else(|| anyhow!("No web server found. Create a server first"))?;
+
+                    match server_val {
+                        Value::WebServer(server_arc) => {
+                            let server = server_arc.lock().unwrap();
+                            
+                            // Parse configuration
+                            let mut config_map = std::collections::HashMap::new();
+                            for (key, expr) in config {
+                                let val = self.eval(expr)?;
+                                if let Value::Str(s) = val {
+                                    config_map.insert(key.clone(), s);
+                                }
+                            }
+                            
+                            // Add middleware based on type
+                            match middleware_type.as_str() {
+                                "cors" => {
+                                    let origins = config_map.get("origins")
+                                        .map(|s| vec![s.clone()])
+                                        .unwrap_or_else(|| vec!["*".to_string()]);
+                                    let methods = config_map.get("methods")
+                                        .map(|s| vec![s.clone()])
+                                        .unwrap_or_else(|| vec!["GET".to_string(), "POST".to_string(), "PUT".to_string(), "DELETE".to_string()]);
+                                    let headers = config_map.get("headers")
+                                        .map(|s| vec![s.clone()])
+                                        .unwrap_or_else(|| vec!["Content-Type".to_string()]);
+                                    
+                                    let middleware = crate::stdlib::middleware::cors_middleware(origins, methods, headers);
+                                    server.add_response_middleware(middleware);
+                                    core_io::write("✓ CORS middleware added");
+                                }
+                                "logging" => {
+                                    let middleware = crate::stdlib::middleware::logging_middleware();
+                                    server.add_request_middleware(middleware);
+                                    core_io::write("✓ Logging middleware added");
+                                }
+                                "auth" => {
+                                    let token_name = config_map.get("token_name")
+                                        .cloned()
+                                        .unwrap_or_else(|| "Authorization".to_string());
+                                    let token = config_map.get("token")
+                                        .cloned()
+                                        .ok_or_else(|| anyhow!("auth middleware requires 'token' config"))?;
+                                    
+                                    let middleware = crate::stdlib::middleware::auth_middleware(token_name, token);
+                                    server.add_request_middleware(middleware);
+                                    core_io::write("✓ Auth middleware added");
+                                }
+                                "security" => {
+                                    let middleware = crate::stdlib::middleware::security_headers_middleware();
+                                    server.add_response_middleware(middleware);
+                                    core_io::write("✓ Security headers middleware added");
+                                }
+                                "timing" => {
+                                    let middleware = crate::stdlib::middleware::response_time_middleware();
+                                    server.add_response_middleware(middleware);
+                                    core_io::write("✓ Response timing middleware added");
+                                }
+                                _ => {
+                                    bail!("Unknown middleware type: {}. Available: cors, logging, auth, security, timing", middleware_type);
+                                }
+                            }
+                        }
+                        _ => bail!("server variable is not a web server"),
+                    }
+                }
+                #[cfg(feature = "web")]
+                Stmt::RateLimit {
+                    max_requests,
+                    per_seconds,
+                    key_by,
+                } => {
+                    let server_val = env_get(&self.globals, "server")
+                        .ok_or_else(|| anyhow!("No web server found. Create a server first"))?;
+                    let positive = |v: Value, what: &str| match whole_number(&v) {
+                        Some(n) if n > 0 => Ok(n as u64),
+                        _ => Err(anyhow!(
+                            "Limit to ... requests per ... seconds: {} must be a whole number above 0, got {}",
+                            what,
+                            to_string(&v)
+                        )),
+                    };
+                    let max = positive(self.eval(max_requests)?, "the number of requests")?;
+                    let secs = positive(self.eval(per_seconds)?, "the number of seconds")?;
+                    let key_fn = match key_by {
+                        Some(e) => match self.eval(e)? {
+                            Value::Func(f) => Some(self.rate_limit_key(f)),
+                            other => bail!(
+                                "Limit to ... grouped by needs a function that takes the request, got {}",
+                                to_string(&other)
+                            ),
+                        },
+                        None => None,
+                    };
+                    match server_val {
+                        Value::WebServer(server_arc) => {
+                            let limiter = crate::stdlib::rate_limit::RateLimiter::new(
+                                max,
+                                std::time::Duration::from_secs(secs),
+                                key_fn,
+                            );
+                            server_arc.lock().unwrap().set_rate_limit(limiter);
+                            core_io::write(&format!(
+                                "✓ Rate limit set: {} requests per {} seconds",
+                                max, secs
+                            ));
+                        }
+                        _ => bail!("server variable is not a web server"),
+                    }
+                }
+                #[cfg(feature = "web")]
+                Stmt::Respond(e) => {
+                    let v = self.eval(e)?;
+                    let response = self.negotiated_response(&v)?;
+                    self.write_value(&Value::HttpResponse(response))?;
+                }
+                #[cfg(feature = "web")]
+                Stmt::EnableMetrics { path, line } => {
+                    let server_val = env_get(&self.globals, "server")
+                        .ok_or_else(|| anyhow!("No web server found. Create a server first"))?;
+                    let Value::WebServer(server_arc) = server_val else {
+                        bail!("server variable is not a web server");
+                    };
+                    let path = match self.eval(path)? {
+                        Value::Str(s) if s.starts_with('/') => s,
+                        other => bail!(
+                            "Enable metrics on server at: the path must be text starting with '/', got {}",
+                            described(&other)
+                        ),
+                    };
+                    // Route handlers count statements and calls from now on
+                    self.enable_stats();
+                    let counters = Arc::clone(&self.counters);
+                    let mut server = server_arc.lock().unwrap();
+                    let metrics = server.metrics();
+                    let handler: crate::stdlib::http::RouteHandler =
+                        Arc::new(move |request| Ok(metrics.response(&request, &counters.snapshot())));
+                    server.add_route(path.clone(), "GET".to_string(), *line, handler)?;
+                    core_io::write(&format!("✓ Metrics enabled at {}", path));
+                }
+                #[cfg(feature = "web")]
+                Stmt::ServeStaticFiles(dir) => {
+                    let server_val = env_get(&self.globals, "server")
+                        .ok_or_else(|| anyhow!("No web server found. Create a server first"))?;
+                    let Value::WebServer(server_arc) = server_val else {
+                        bail!("server variable is not a web server");
+                    };
+                    let dir = match self.eval(dir)? {
+                        Value::Str(s) => self.base_dir.join(s),
+                        other => bail!("Serve static files from: the directory must be text, got {}", described(&other)),
+                    };
+                    if !dir.is_dir() {
+                        bail!("Serve static files from: {} is not a directory", dir.display());
+                    }
+                    core_io::write(&format!("✓ Serving static files from {}", dir.display()));
+                    server_arc.lock().unwrap().set_static_dir(dir);
+                }
+                Stmt::Evaluate(e) => {
+                    self.eval(e)?;
+                }
+                #[cfg(feature = "web")]
+                Stmt::StartServer => {
+                    // Get the server from globals
+                    let server_val = env_remove(&self.globals, "server")
+                        .ok_or_else(|| anyhow!("No web server found. Create a server first with: Make server to Create web server on port <port>"))?;
+
+                    match server_val {
+                        Value::WebServer(server_arc) => {
+                            // Add hot reload route if LiveReloadTracker exists
+                            if let Some(Value::LiveReloadTracker(tracker)) =
+                                env_get(&self.globals, "__livereload")
+                            {
+                                let tracker_clone = tracker.clone();
+                                let templates = Arc::clone(&self.templates);
+
+                                // Create the /__reload_check route handler
+                                let reload_handler = Arc::new(move |_req: crate::stdlib::http::HttpRequest| -> Result<crate::stdlib::http::HttpResponse> {
+                                    let changed = tracker_clone.check_for_changes();
+                                    if changed {
+                                        templates.clear();
+                                    }
+                                    let response_json = serde_json::json!({
+                                        "changed": changed,
+                                        "timestamp": std::time::SystemTime::now()
+                                            .duration_since(std::time::UNIX_EPOCH)
+                                            .unwrap()
+                                            .as_millis()
+                                    });
+                                    Ok(crate::stdlib::http::json_response(response_json))
+                                });
+
+                                // Add the route
+                                let route = crate::stdlib::http::Route {
+                                    path: "/__reload_check".to_string(),
+                                    method: "GET".to_string(),
+                                    handler: reload_handler,
+                                    line: 0,
+                                };
+
+                                server_arc.lock().unwrap().add_route_direct(route);
+                                eprintln!("🔄 Hot reload enabled at /__reload_check");
+                            }
+
+                            eprintln!("[DEBUG] About to call server.start()...");
+
+                            // Use the special function that doesn't hold the lock. A
+                            // port that cannot be bound is a NetworkError the program
+                            // can catch and try another port after
+                            if let Err(e) = crate::stdlib::http::start_server_from_arc(server_arc) {
+                                let Some(bind) = e.downcast_ref::<crate::stdlib::http::BindError>() else {
+                                    return Err(e);
+                                };
+                                let error = self.create_error(ErrorKind::NetworkError, bind.to_string());
+                                return Err(self.error_with_location(error.format_with_trace()));
+                            }
+
+                            eprintln!("[DEBUG] Server.start() returned (unexpected!)");
+                        }
+                        _ => bail!("server variable is not a web server"),
+                    }
+                }
+                #[cfg(not(feature = "web"))]
+                Stmt::AddRoute { .. }
+                | Stmt::Route { .. }
+                | Stmt::AddMiddleware { .. }
+                | Stmt::RateLimit { .. }
+                | Stmt::Respond(_)
+                | Stmt::EnableMetrics { .. }
+                | Stmt::ServeStaticFiles(_)
+                | Stmt::TemplateDirectory(_)
+                | Stmt::StartServer
+                | Stmt::BeforeEachRoute { .. }
+                | Stmt::AfterEachRoute { .. }
+                | Stmt::SendEvent { .. } => return Err(web_disabled(dump_stmt(stmt))),
+                // Line markers are read before the match
+                Stmt::Line(_) => {}
+                Stmt::Benchmark { name, body } => {
+                    let name = to_string(&self.eval(name)?);
+                    let start = Instant::now();
+                    let span = self.profiler.benchmark(&name);
+                    let result = self.execute_stmts(body);
+                    drop(span);
+                    self.finish_benchmark(&name, start.elapsed())?;
+                    result?;
+                }
+                Stmt::Measure { name, store_in, body } => {
+                    let name = to_string(&self.eval(name)?);
+                    let start = Instant::now();
+                    let result = self.execute_stmts(body);
+                    let ms = start.elapsed().as_secs_f64() * 1000.0;
+                    match store_in {
+                        Some(var) => {
+                            self.check_declared(var, &Value::Num(ms))?;
+                            env_set(&self.globals, var, Value::Num(ms));
+                        }
+                        None => eprintln!("{} took {:.1}ms", name, ms),
+                    }
+                    result?;
+                }
+                Stmt::Retry {
+                    max_attempts,
+                    delay,
+                    backoff,
+                    jitter,
+                    body,
+                    on_failure,
+                } => {
+                    let (attempts, wait) = retry_plan(max_attempts, delay, |e| self.eval(e))?;
+                    let outcome = retry(attempts, wait, *backoff, *jitter, || self.execute_stmts(body));
+                    match (outcome, on_failure) {
+                        (Ok(()), _) => {}
+                        (Err(e), None) => return Err(e),
+                        (Err(e), Some(_)) if e.is::<MemoryLimitExceeded>() => return Err(e),
+                        (Err(e), Some((var, block))) => {
+                            if let Some(var) = var {
+                                env_set(&self.globals, var, Value::Str(failure_text(&e)));
+                            }
+                            self.execute_stmts(block)?;
+                        }
+                    }
+                }
+                Stmt::Parallel { branches } => {
+                    // Each branch runs on its own thread with its own copy of the Vm
+                    run_branches(branches.iter().map(|branch| {
+                        let mut vm = self.clone();
+                        move || vm.execute(branch)
+                    }))?;
+                }
+                #[cfg(feature = "web")]
+                Stmt::SendEvent { data, stream } => {
+                    let data = self.event_data(&self.eval(data)?)?;
+                    event_stream_of(&self.eval(stream)?)?.send(&data)?;
+                }
+                Stmt::SendMessage { message, socket } => {
+                    let message = self.eval(message)?;
+                    self.send_to(&message, &self.eval(socket)?)?;
+                }
+                Stmt::CloseStream(stream) => close_connection(&self.eval(stream)?)?,
+                Stmt::Task { name, body } => {
+                    // Like a `Parallel` branch, but the program does not wait
+                    let mut vm = self.clone();
+                    let body = body.clone();
+                    let task = Task::spawn(name, move || Ok(vm.run(&body)?.map_or(Value::Null, |v| v.0)));
+                    env_set(&self.globals, name, Value::Future(task));
+                }
+                Stmt::Background { name, body } => {
+                    // Like a task, but the handle is optional; with no one to
+                    // wait for it, an error is reported on stderr instead
+                    let mut vm = self.clone();
+                    vm.in_background = true;
+                    let body = body.clone();
+                    let reported = name.is_none();
+                    let task = Task::spawn(name.as_deref().unwrap_or("background"), move || {
+                        let outcome = vm.run(&body);
+                        if let (true, Err(e)) = (reported, &outcome) {
+                            eprintln!("Run in background: {}", e);
+                        }
+                        Ok(outcome?.map_or(Value::Null, |v| v.0))
+                    });
+                    if let Some(name) = name {
+                        env_set(&self.globals, name, Value::Future(task));
+                    }
+                }
+                Stmt::AwaitAll { names } => {
+                    let tasks = self.tasks(names, |name| env_get(&self.globals, name))?;
+                    await_all(names, &tasks, |var, v| env_set(&self.globals, &var, v))?;
+                }
+                Stmt::AwaitAny { names } => {
+                    let tasks = self.tasks(names, |name| env_get(&self.globals, name))?;
+                    await_any(names, &tasks, |var, v| env_set(&self.globals, &var, v))?;
+                }
+                Stmt::Checkpoint { name, state } => {
+                    let state = self.eval(state)?;
+                    self.save_checkpoint(name, &state)?;
+                }
+                Stmt::RestoreCheckpoint { name, into_var } => {
+                    let state = self.restore_checkpoint(name)?;
+                    env_set(&self.globals, into_var, state);
+                }
+                Stmt::RouteGroup { prefix, body } => {
+                    let prefix = match self.eval(prefix)? {
+                        Value::Str(s) if s.starts_with('/') => s,
+                        other => bail!(
+                            "Add route group: the prefix must be text starting with '/', got {}",
+                            described(&other)
+                        ),
+                    };
+                    let mut group = self.route_groups.last().cloned().unwrap_or_default();
+                    group.prefix = grouped_path(&group.prefix, prefix.trim_end_matches('/'));
+                    group.middleware.push(Arc::new(RwLock::new(Vec::new())));
+                    self.route_groups.push(group);
+                    let result = self.execute_stmts(body);
+                    self.route_groups.pop();
+                    result?;
+                }
+                Stmt::GroupMiddleware { body } => {
+                    let Some(group) = self.route_groups.last() else {
+                        bail!("Add middleware to group: only works inside an 'Add route group' block");
+                    };
+                    if let Some(middleware) = group.middleware.last() {
+                        middleware.write().unwrap().push(body.clone());
+                    }
+                    core_io::write(&format!("✓ Middleware added to group {}", group.prefix));
+                }
+                #[cfg(feature = "web")]
+                Stmt::BeforeEachRoute { body } | Stmt::AfterEachRoute { body } => {
+                    let server_val = env_get(&self.globals, "server")
+                        .ok_or_else(|| anyhow!("No web server found. Create a server first with: Make server to Create web server on port <port>"))?;
+                    let Value::WebServer(server_arc) = server_val else {
+                        bail!("server variable is not a web server");
+                    };
+                    let hooks = server_arc.lock().unwrap().route_hooks();
+                    let mut hooks = hooks.write().unwrap();
+                    if matches!(stmt, Stmt::BeforeEachRoute { .. }) {
+                        hooks.before.push(body.clone());
+                        core_io::write("✓ Before each route hook added");
+                    } else {
+                        hooks.after.push(body.clone());
+                        core_io::write("✓ After each route hook added");
+                    }
+                }
+                Stmt::OnShutdown { body } => {
+                    // Runs on the shutdown thread against the live program scope,
+                    // like a route handler
+                    let body = body.clone();
+                    let program_env = Arc::clone(&self.globals);
+                    let base_dir = self.base_dir.clone();
+                    let source_encoding = self.source_encoding;
+                    let table_style = self.table_style;
+                    let trace = self.tracer.settings();
+                    let log_file = Arc::clone(&self.log_file);
+                    let constants = Arc::clone(&self.constants);
+                    crate::stdlib::shutdown::add_hook(Box::new(move || {
+                        let mut vm = Vm::with_base_dir(base_dir);
+                        vm.source_encoding = source_encoding;
+                        vm.table_style = table_style;
+                        if let Some(filter) = trace {
+                            vm.enable_trace(filter);
+                        }
+                        vm.log_file = log_file;
+                        vm.constants = constants;
+                        vm.globals = Scope::new_env(HashMap::new(), Some(program_env));
+                        if let Err(e) = vm.execute(&body) {
+                            eprintln!("Error in On shutdown block: {}", e);
+                        }
+                    }));
+                }
+                Stmt::EventEmitter { name } => {
+                    env_set(&self.globals, name, Value::EventEmitter(EventEmitter::default()));
+                }
+                Stmt::Require { value, schema } => self.require_match(&self.eval(value)?, &self.eval(schema)?)?,
+                Stmt::Assert { deep_equal, left, right } => {
+                    self.check_assert(*deep_equal, &self.eval(left)?, &self.eval(right)?)?
+                }
+                Stmt::On { emitter, event, body } => {
+                    let handler = self.event_handler(body, &self.globals);
+                    emitter_of(&self.eval(&Expr::Ident(emitter.clone()))?)?.on(event, handler);
+                }
+                Stmt::Emit { emitter, event, data } => {
+                    let data = self.eval(data)?;
+                    emitter_of(&self.eval(&Expr::Ident(emitter.clone()))?)?.emit(event, data)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn execute_bytecode(&mut self, bc: &[u8]) -> Result<()> {
+        // Decode program into instruction vector
+        let mut prog: Vec<Instruction> = Vec::new();
+        for line in bc.split(|&b| b == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let s = String::from_utf8_lossy(line);
+            if let Some(instr) = Instruction::decode(&s) {
+                prog.push(instr);
+            }
+        }
+        let mut stack: Vec<Value> = Vec::new();
+        let mut ip: isize = 0;
+        while let Some(instr) = prog.get(ip as usize).cloned() {
+            match instr {
+                Instruction::PushBool(b) => stack.push(Value::Bool(b)),
+                Instruction::PushNull => stack.push(Value::Null),
+                Instruction::PushNum(n) => stack.push(Value::Num(n)),
+                Instruction::PushStr(s) => stack.push(Value::Str(s)),
+                Instruction::LoadVar(name) => {
+                    let v = match env_get(&self.globals, &name) {
+                        Some(v) => v,
+                        None => self.undefined_variable(&name)?,
+                    };
+                    stack.push(v);
+                }
+                Instruction::StoreVar(name) => {
+                    let v = stack.pop().unwrap_or(Value::Null);
+                    env_set(&self.globals, &name, v);
+                }
+                Instruction::Add => {
+                    let b = stack.pop().unwrap_or(Value::Int(0));
+                    let a = stack.pop().unwrap_or(Value::Int(0));
+                    stack.push(Value::Num(self.coerce_num(a)? + self.coerce_num(b)?));
+                }
+                Instruction::Sub => {
+                    let b = stack.pop().unwrap_or(Value::Int(0));
+                    let a = stack.pop().unwrap_or(Value::Int(0));
+                    stack.push(Value::Num(self.coerce_num(a)? - self.coerce_num(b)?));
+                }
+                Instruction::Mul => {
+                    let b = stack.pop().unwrap_or(Value::Int(0));
+                    let a = stack.pop().unwrap_or(Value::Int(0));
+                    stack.push(Value::Num(self.coerce_num(a)? * self.coerce_num(b)?));
+                }
+                Instruction::Div => {
+                    let b = stack.pop().unwrap_or(Value::Int(1));
+                    let a = stack.pop().unwrap_or(Value::Int(0));
+                    let db = self.coerce_num(b)?;
+                    if db == 0.0 {
+                        return Err(self.error_with_location("Division by zero"));
+                    }
+                    stack.push(Value::Num(self.coerce_num(a)? / db));
+                }
+                Instruction::Eq => {
+                    let b = stack.pop().unwrap_or(Value::Null);
+                    let a = stack.pop().unwrap_or(Value::Null);
+                    stack.push(Value::Num((to_string(&a) == to_string(&b)) as i32 as f64));
+                }
+                Instruction::Ne => {
+                    let b = stack.pop().unwrap_or(Value::Null);
+                    let a = stack.pop().unwrap_or(Value::Null);
+                    stack.push(Value::Num((to_string(&a) != to_string(&b)) as i32 as f64));
+                }
+                Instruction::Lt => {
+                    let b = stack.pop().unwrap_or(Value::Int(0));
+                    let a = stack.pop().unwrap_or(Value::Int(0));
+                    stack.push(Value::Num((self.coerce_num(a)? < self.coerce_num(b)?) as i32 as f64));
+                }
+                Instruction::Le => {
+                    let b = stack.pop().unwrap_or(Value::Int(0));
+                    let a = stack.pop().unwrap_or(Value::Int(0));
+                    stack.push(Value::Num((self.coerce_num(a)? <= self.coerce_num(b)?) as i32 as f64));
+                }
+                Instruction::Gt => {
+                    let b = stack.pop().unwrap_or(Value::Int(0));
+                    let a = stack.pop().unwrap_or(Value::Int(0));
+                    stack.push(Value::Num((self.coerce_num(a)? > self.coerce_num(b)?) as i32 as f64));
+                }
+                Instruction::Ge => {
+                    let b = stack.pop().unwrap_or(Value::Int(0));
+                    let a = stack.pop().unwrap_or(Value::Int(0));
+                    stack.push(Value::Num((self.coerce_num(a)? >= self.coerce_num(b)?) as i32 as f64));
+                }
+                Instruction::Jump(tgt) => {
+                    ip = tgt as isize;
+                    continue;
+                }
+                Instruction::JumpIfFalse(tgt) => {
+                    let v = stack.pop().unwrap_or(Value::Int(0));
+                    let truthy = match v {
+                        Value::Int(i) => i != 0,
+                        Value::Num(n) => n != 0.0,
+                        Value::Bool(b) => b,
+                        Value::Str(s) => !s.is_empty(),
+                        Value::Null => false,
+                        _ => true,
+                    };
+                    if !truthy {
+                        ip = tgt as isize;
+                        continue;
+                    }
+                }
+                Instruction::WriteTop => {
+                    let v = stack.last().cloned().unwrap_or(Value::Null);
+                    core_io::write(&to_string(&v));
+                }
+                Instruction::AskVar(name, expect) => {
+                    let value = self.ask_for(&name, expect)?;
+                    env_set(&self.globals, &name, value);
+                }
+            }
+            ip += 1;
+        }
+        Ok(())
+    }
+
+    fn eval(&self, e: &Expr) -> Result<Value> {
+        self.eval_in(e, &TopLevel)
+    }
+
+    fn eval_in_frame(&self, e: &Expr, frame: &Frame) -> Result<Value> {
+        self.eval_in(e, frame)
+    }
+
+    fn eval_in_scope_with_capture(
+        &self,
+        e: &Expr,
+        locals: &HashMap<String, Value>,
+        captured: &Env,
+    ) -> Result<Value> {
+        self.eval_in(e, &Captured { locals, env: captured })
+    }
+
+    /// Evaluate `e` with names looked up in `scope` before the program's
+    /// globals, constants and built-ins. Every evaluator goes through here so
+    /// an expression means the same at the top level and in any function.
+    fn eval_in(&self, e: &Expr, scope: &dyn Lookup) -> Result<Value> {
+        match e {
+            Expr::Str(s) => Ok(Value::Str(s.clone())),
+            Expr::RegexLit(p) => Ok(Value::Regex(Arc::new(compile_pattern(p)?))),
+            Expr::Int(i) => Ok(Value::Int(*i)),
+            Expr::Num(n) => Ok(Value::Num(*n)),
+            Expr::Bool(b) => Ok(Value::Bool(*b)),
+            Expr::Null => Ok(Value::Null),
+            Expr::Ident(name) => {
+                if let Some(v) = scope.get(name).or_else(|| self.resolve_value(name)) {
+                    return Ok(v);
+                }
+                self.undefined_variable(name)
+            }
+            Expr::Global(name) => {
+                if let Some(v) = scope.get_outer(name).or_else(|| self.resolve_value(name)) {
+                    return Ok(v);
+                }
+                self.undefined_variable(name)
+            }
+            Expr::Local(slot) => {
+                let name = match scope.slot(*slot) {
+                    Some(Ok(v)) => return Ok(v),
+                    // Not assigned yet on this path; fall back to the enclosing scopes
+                    Some(Err(name)) => name,
+                    None => bail!("local slot {} used outside a function body", slot),
+                };
+                if let Some(v) = scope.get_outer(name).or_else(|| self.resolve_value(name)) {
+                    return Ok(v);
+                }
+                self.undefined_variable(name)
+            }
+            Expr::Plus(..) | Expr::Minus(..) | Expr::Times(..) | Expr::DividedBy(..) => {
+                self.arithmetic(e, |x| self.eval_in(x, scope))
+            }
+            Expr::And(a, b) => {
+                let la = self.truthy(&self.eval_in(a, scope)?)?;
+                if !la {
+                    return Ok(Value::Int(0));
+                }
+                let lb = self.truthy(&self.eval_in(b, scope)?)?;
+                Ok(if lb { Value::Int(1) } else { Value::Int(0) })
+            }
+            Expr::Conditional(cond, a, b) => {
+                let chosen = if self.truthy(&self.eval_in(cond, scope)?)? { a } else { b };
+                self.eval_in(chosen, scope)
+            }
+            Expr::Or(a, b) => {
+                let la = self.truthy(&self.eval_in(a, scope)?)?;
+                if la {
+                    return Ok(Value::Int(1));
+                }
+                let lb = self.truthy(&self.eval_in(b, scope)?)?;
+                Ok(if lb { Value::Int(1) } else { Value::Int(0) })
+            }
+            Expr::Not(a) => {
+                let la = self.truthy(&self.eval_in(a, scope)?)?;
+                Ok(if !la {
+                    Value::Int(1)
+                } else {
+                    Value::Int(0)
+                })
+            }
+            Expr::Cmp(op, l, r) => {
+                let lv = self.eval_in(l, scope)?;
+                let rv = self.eval_in(r, scope)?;
+                self.compare(op, &lv, &rv)
+            }
+            Expr::Call { name, args } => {
+                let argv = args
+                    .iter()
+                    .map(|e| self.eval_in(e, scope))
+                    .collect::<Result<Vec<_>>>()?;
+                // A function the scope holds comes before the program's own
+                if let Some(Value::Func(f)) = scope.get(name) {
+                    return self.call_func_value(&f, &argv).and_then(|v| self.sized(v));
+                }
+                self.call_function(name, &argv).and_then(|v| self.sized(v))
+            }
+            Expr::ListLit(items) => {
+                let mut out = Vec::new();
+                for it in items {
+                    out.push(self.eval_in(it, scope)?);
+                }
+                self.sized(Value::List(out))
+            }
+            Expr::TupleLit(items) => {
+                let mut out = Vec::new();
+                for it in items {
+                    out.push(self.eval_in(it, scope)?);
+                }
+                self.sized(Value::Tuple(out))
+            }
+            Expr::DictLit(pairs) => {
+                let mut map = HashMap::new();
+                for (k, ve) in pairs {
+                    map.insert(k.clone(), self.eval_in(ve, scope)?);
+                }
+                self.sized(Value::Dict(map))
+            }
+            Expr::Index(base, index) => {
+                let base_val = self.eval_in(base, scope)?;
+                let index_val = self.eval_in(index, scope)?;
+
+                match (&base_val, &index_val) {
+                    (Value::List(items) | Value::Tuple(items), Value::Int(_) | Value::Num(_)) => {
+                        let idx = index_of(&index_val);
+                        let len = items.len() as i32;
+                        // Support negative indexing
+                        let actual_idx = if idx < 0 { len + idx } else { idx };
+
+                        if actual_idx < 0 || actual_idx >= len {
+                            let msg =
+                                format!("List index out of range: {} (list length: {})", idx, len);
+                            return Err(anyhow!("{}", hints::with_hint(&msg)));
+                        }
+                        Ok(items[actual_idx as usize].clone())
+                    }
+                    (&Value::LazyRange { start, end, step }, Value::Int(_) | Value::Num(_)) => {
+                        range_index(start, end, step, index_of(&index_val))
+                    }
+                    (Value::Dict(map), Value::Str(key)) => map.get(key).cloned().ok_or_else(|| {
+                        let msg = format!("Key not found in dictionary: \"{}\"", key);
+                        anyhow!("{}", hints::with_hint(&msg))
+                    }),
+                    (Value::Str(s), Value::Int(_) | Value::Num(_)) => {
+                        let idx = index_of(&index_val);
+                        let chars: Vec<char> = s.chars().collect();
+                        let len = chars.len() as i32;
+                        let actual_idx = if idx < 0 { len + idx } else { idx };
+
+                        if actual_idx < 0 || actual_idx >= len {
+                            return Err(anyhow!(
+                                "String index out of range: {} (string length: {})",
+                                idx,
+                                len
+                            ));
+                        }
+                        Ok(Value::Str(chars[actual_idx as usize].to_string()))
+                    }
+                    (Value::Set(_), _) => bail!("Sets are not indexable; use 'set S contains X' or 'list of S'"),
+                    _ => Err(anyhow!("Cannot index {:?} with {:?}", base_val, index_val)),
+                }
+            }
+            Expr::Memoize(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_memoize(&[val])
+            }
+            Expr::TimedCall(name, args) => {
+                let argv = args
+                    .iter()
+                    .map(|e| self.eval_in(e, scope))
+                    .collect::<Result<Vec<_>>>()?;
+                let start = Instant::now();
+                let result = match scope.get(name) {
+                    Some(Value::Func(f)) => self.call_func_value(&f, &argv)?,
+                    _ => self.call_function(name, &argv)?,
+                };
+                Ok(timed_result(result, start))
+            }
+            Expr::ApplyInParallel(name, items, workers) => {
+                let items: Vec<Value> = each_item(self.eval_in(items, scope)?, "apply ... in parallel")?.collect();
+                let workers = match workers {
+                    Some(n) => match self.eval_in(n, scope)? {
+                        Value::Int(n) if n > 0 => n as usize,
+                        other => {
+                            return Err(wrong_type(format!(
+                                "apply ... in parallel expects a whole number of workers above 0, got {}",
+                                described(&other)
+                            )))
+                        }
+                    },
+                    None => std::thread::available_parallelism().map_or(1, |n| n.get()).min(MAX_WORKERS),
+                };
+                let func = match scope.get(name) {
+                    Some(Value::Func(f)) => Some(f),
+                    _ => None,
+                };
+                let results = self.apply_in_parallel(name, func.as_ref(), &items, workers)?;
+                self.sized(Value::List(results))
+            }
+            // Phrasal built-in expressions
+            Expr::TotalOf(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_sum(&[val])
+            }
+            Expr::SmallestIn(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_min(&[val])
+            }
+            Expr::LargestIn(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_max(&[val])
+            }
+            Expr::AverageOf(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_average(&[val])
+            }
+            Expr::MedianOf(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_median(&[val])
+            }
+            Expr::VarianceOf(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_variance(&[val])
+            }
+            Expr::StdDevOf(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_std_dev(&[val])
+            }
+            Expr::AbsoluteValueOf(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_abs(&[val])
+            }
+            Expr::Round(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_round(&[val])
+            }
+            Expr::RoundDown(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_floor(&[val])
+            }
+            Expr::RoundUp(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_ceil(&[val])
+            }
+            Expr::Lerp(a, b, t) => {
+                let args = [self.eval_in(a, scope)?, self.eval_in(b, scope)?, self.eval_in(t, scope)?];
+                builtin_lerp(&args)
+            }
+            #[cfg(feature = "web")]
+            Expr::ReceiveMessage { socket, timeout } => {
+                let timeout = timeout.as_ref().map(|t| self.eval_in(t, scope)).transpose()?;
+                receive_message(&self.eval_in(socket, scope)?, timeout)
+            }
+            Expr::MakeChannel => Ok(Value::Channel(Channel::new())),
+            Expr::ReceiveFrom { channel, timeout } => {
+                let timeout = timeout.as_ref().map(|t| self.eval_in(t, scope)).transpose()?;
+                receive_from(&self.eval_in(channel, scope)?, timeout)
+            }
+            Expr::Clamp(x, low, high) => {
+                let args = [self.eval_in(x, scope)?, self.eval_in(low, scope)?, self.eval_in(high, scope)?];
+                builtin_clamp(&args)
+            }
+            Expr::MapRange(x, a, b, c, d) => {
+                let args = [self.eval_in(x, scope)?, self.eval_in(a, scope)?, self.eval_in(b, scope)?, self.eval_in(c, scope)?, self.eval_in(d, scope)?];
+                builtin_map_range(&args)
+            }
+            Expr::MakeUppercase(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_uppercase(&[val])
+            }
+            Expr::MakeLowercase(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_lowercase(&[val])
+            }
+            Expr::TrimSpaces(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_trim(&[val])
+            }
+            Expr::FirstIn(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_first(&[val])
+            }
+            Expr::LastIn(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_last(&[val])
+            }
+            Expr::ReverseOf(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_reverse(&[val])
+            }
+            Expr::CountOf(expr) => {
+                let val = self.eval_in(expr, scope)?;
+                builtin_length(&[val])
+            }
+            Expr::TypeOf(expr) => Ok(Value::Str(type_name(&self.eval_in(expr, scope)?).to_string())),
+            Expr::WaitFor(expr) => match self.eval_in(expr, scope)? {
+                Value::Future(task) => task.wait().map_err(|e| tasks_failed(&[(task.name(), e)])),
+                other => Err(wrong_type(format!("wait for expects a task, got {}", described(&other)))),
+            },
+            Expr::JoinWith(a, b) => {
+                let va = self.eval_in(a, scope)?;
+                let vb = self.eval_in(b, scope)?;
+                self.sized(builtin_join(&[va, vb])?)
+            }
+            Expr::SplitBy(a, b) => {
+                let va = self.eval_in(a, scope)?;
+                let vb = self.eval_in(b, scope)?;
+                self.sized(builtin_split(&[va, vb])?)
+            }
+            // Collection operations
+            Expr::Contains(item, collection) => {
+                let item_val = self.eval_in(item, scope)?;
+                let coll_val = self.eval_in(collection, scope)?;
+                builtin_contains(&[item_val, coll_val])
+            }
+            Expr::Remove(item, list) => {
+                let item_val = self.eval_in(item, scope)?;
+                let list_val = self.eval_in(list, scope)?;
+                builtin_remove(&[item_val, list_val])
+            }
+            Expr::Append(item, list) => {
+                let item_val = self.eval_in(item, scope)?;
+                let list_val = self.eval_in(list, scope)?;
+                self.sized(builtin_append(&[item_val, list_val])?)
+            }
+            Expr::InsertAt(item, index, list) => {
+                let item_val = self.eval_in(item, scope)?;
+                let index_val = self.eval_in(index, scope)?;
+                let list_val = self.eval_in(list, scope)?;
+                self.sized(builtin_insert_at(&[item_val, index_val, list_val])?)
+            }
+            Expr::SetLit(items) => {
+                let items = items.iter().map(|it| self.eval_in(it, scope)).collect::<Result<_>>()?;
+                self.sized(set_of(items)?)
+            }
+            Expr::SetUnion(a, b) | Expr::SetIntersection(a, b) | Expr::SetDifference(a, b) | Expr::SetContains(a, b) => {
+                set_operation(e, self.eval_in(a, scope)?, self.eval_in(b, scope)?)
+            }
+            Expr::SetToList(a) | Expr::ListToSet(a) => convert_set(e, self.eval_in(a, scope)?),
+            // JSON operations
+            Expr::ParseUrl(a) | Expr::BuildUrl(a) | Expr::UrlEncode(a) | Expr::UrlDecode(a) => {
+                eval_url(e, self.eval_in(a, scope)?)
+            }
+            Expr::IndentBy(a, n) | Expr::WrapText(a, n) => eval_layout(e, self.eval_in(a, scope)?, Some(self.eval_in(n, scope)?)),
+            Expr::Dedent(a) => eval_layout(e, self.eval_in(a, scope)?, None),
+            Expr::JsonQuery(json, path) => {
+                Ok(Value::List(json_path_select("query json", &self.eval_in(json, scope)?, &self.eval_in(path, scope)?)?))
+            }
+            Expr::Pretty(a) => Ok(Value::Str(pretty(&self.eval_in(a, scope)?))),
+            Expr::Pick(a, keys) | Expr::Omit(a, keys) => select_fields(e, self.eval_in(a, scope)?, keys),
+            Expr::FindFirst(list, pred) | Expr::FindAll(list, pred) | Expr::FindIndex(list, pred) => {
+                self.find_items(e, self.eval_in(list, scope)?, self.eval_in(pred, scope)?)
+            }
+            Expr::GroupConcat(fields, list) | Expr::Pluck(fields, list) => {
+                collect_fields(e, self.eval_in(fields, scope)?, self.eval_in(list, scope)?)
+            }
+            Expr::Aggregate(list, fields) => {
+                let keys = fields.iter().map(|f| self.eval_in(&f.field, scope)).collect::<Result<_>>()?;
+                aggregate(self.eval_in(list, scope)?, fields, keys)
+            }
+            Expr::Validate(v, schema) => self.validate(&self.eval_in(v, scope)?, schema),
+            Expr::Coerce(v, schema) => self.coerce(&self.eval_in(v, scope)?, schema),
+            Expr::ValidateAgainst(v, schema) => validate_against(&self.eval_in(v, scope)?, &self.eval_in(schema, scope)?),
+            #[cfg(feature = "web")]
+            Expr::GetRequestHeader(name) => self.request_header(self.eval_in(name, scope)?),
+            #[cfg(feature = "web")]
+            Expr::SetResponseHeaderExpr(name, value) => {
+                self.set_response_header(self.eval_in(name, scope)?, self.eval_in(value, scope)?)
+            }
+            #[cfg(feature = "web")]
+            Expr::WithEtag(response) => with_etag(self.eval_in(response, scope)?),
+            #[cfg(feature = "web")]
+            Expr::RenderTemplateFile(name, data) => self.render_template_file(self.eval_in(name, scope)?, &self.eval_in(data, scope)?),
+            #[cfg(feature = "web")]
+            Expr::WithCache(response, secs) => with_cache(self.eval_in(response, scope)?, self.eval_in(secs, scope)?),
+            // Files, JSON, errors and the web server, in a function of their own
+            // so this one takes less stack for each call a program makes
+            Expr::ReadFile(_)
+            | Expr::ReadFromStdin
+            | Expr::WriteFile(_, _)
+            | Expr::AppendFile(_, _)
+            | Expr::FileExists(_)
+            | Expr::DeleteFile(_)
+            | Expr::CreateDir(_)
+            | Expr::ListDir(_)
+            | Expr::ReadLines(_)
+            | Expr::CopyFile(_, _)
+            | Expr::MoveFile(_, _)
+            | Expr::ParseJson(_)
+            | Expr::ToJson(_)
+            | Expr::ToJsonPretty(_)
+            | Expr::JsonGet(_, _)
+            | Expr::JsonSet(_, _, _)
+            | Expr::NewJsonObject
+            | Expr::NewJsonArray
+            | Expr::JsonPush(_, _)
+            | Expr::JsonLength(_)
+            | Expr::ErrorMessage(_)
+            | Expr::ErrorType(_)
+            | Expr::NewError { .. }
+            | Expr::GetPathParam(_)
+            | Expr::GetRequestMethod
+            | Expr::GetRequestPath
+            | Expr::GetRemoteAddr
+            | Expr::PreferredResponseType
+            | Expr::CreateWebServer { .. }
+            | Expr::StartEventStream { .. }
+            | Expr::HtmlResponse(_)
+            | Expr::JsonResponse(_)
+            | Expr::JsonResponseStatus(_, _)
+            | Expr::RenderTemplate(_, _)
+            | Expr::ErrorResponse(_, _)
+            | Expr::RequestField(_, _) => self.eval_io(e, scope),
+            #[cfg(not(feature = "web"))]
+            Expr::ReceiveMessage { .. }
+            | Expr::GetRequestHeader(_)
+            | Expr::SetResponseHeaderExpr(_, _)
+            | Expr::WithEtag(_)
+            | Expr::RenderTemplateFile(_, _)
+            | Expr::WithCache(_, _) => self.eval_io(e, scope),
+        }
+    }
+
+    /// The file, JSON, error and web server expressions for `eval_in`
+    #[inline(never)]
+    fn eval_io(&self, e: &Expr, scope: &dyn Lookup) -> Result<Value> {
+        match e {
+            // File I/O operations
+            Expr::ReadFile(path_expr) => {
+                let path_val = self.eval_in(path_expr, scope)?;
+                let path = match path_val {
+                    Value::Str(s) => s,
+                    _ => bail!("read file at: path must be a string"),
+                };
+                match crate::stdlib::file::read_file(&path) {
+                    Ok(content) => Ok(Value::Str(content)),
+                    Err(e) => bail!("Failed to read file '{}': {}", path, e),
+                }
+            }
+            Expr::WriteFile(content_expr, path_expr) => {
+                let content_val = self.eval_in(content_expr, scope)?;
+                let path_val = self.eval_in(path_expr, scope)?;
+                let content = match content_val {
+                    Value::Str(s) => s,
+                    v @ (Value::Int(_) | Value::Num(_)) => to_string(&v),
+                    Value::Bool(b) => b.to_string(),
+                    _ => bail!("write to file: content must be string, number, or boolean"),
+                };
+                let path = match path_val {
+                    Value::Str(s) => s,
+                    _ => bail!("write to file at: path must be a string"),
+                };
+                match crate::stdlib::file::write_file(&path, &content) {
+                    Ok(_) => Ok(Value::Null),
+                    Err(e) => bail!("Failed to write file '{}': {}", path, e),
+                }
+            }
+            Expr::AppendFile(content_expr, path_expr) => {
+                let content_val = self.eval_in(content_expr, scope)?;
+                let path_val = self.eval_in(path_expr, scope)?;
+                let content = match content_val {
+                    Value::Str(s) => s,
+                    v @ (Value::Int(_) | Value::Num(_)) => to_string(&v),
+                    Value::Bool(b) => b.to_string(),
+                    _ => bail!("append to file: content must be string, number, or boolean"),
+                };
+                let path = match path_val {
+                    Value::Str(s) => s,
+                    _ => bail!("append to file at: path must be a string"),
+                };
+                match crate::stdlib::file::append_file(&path, &content) {
+                    Ok(_) => Ok(Value::Null),
+                    Err(e) => bail!("Failed to append to file '{}': {}", path, e),
+                }
+            }
+            Expr::FileExists(path_expr) => {
+                let path_val = self.eval_in(path_expr, scope)?;
+                let path = match path_val {
+                    Value::Str(s) => s,
+                    _ => bail!("file exists at: path must be a string"),
+                };
+                Ok(Value::Bool(crate::stdlib::file::file_exists(&path)))
+            }
+            Expr::DeleteFile(path_expr) => {
+                let path_val = self.eval_in(path_expr, scope)?;
+                let path = match path_val {
+                    Value::Str(s) => s,
+                    _ => bail!("delete file at: path must be a string"),
+                };
+                match crate::stdlib::file::delete_file(&path) {
+                    Ok(_) => Ok(Value::Null),
+                    Err(e) => bail!("Failed to delete file '{}': {}", path, e),
+                }
+            }
+            Expr::CreateDir(path_expr) => {
+                let path_val = self.eval_in(path_expr, scope)?;
+                let path = match path_val {
+                    Value::Str(s) => s,
+                    _ => bail!("create directory at: path must be a string"),
+                };
+                match crate::stdlib::file::create_directory(&path) {
+                    Ok(_) => Ok(Value::Null),
+                    Err(e) => bail!("Failed to create directory '{}': {}", path, e),
+                }
+            }
+            Expr::ListDir(path_expr) => {
+                let path_val = self.eval_in(path_expr, scope)?;
+                let path = match path_val {
+                    Value::Str(s) => s,
+                    _ => bail!("list files in: path must be a string"),
+                };
+                match crate::stdlib::file::list_directory(&path) {
+                    Ok(files) => Ok(Value::List(files.into_iter().map(Value::Str).collect())),
+                    Err(e) => bail!("Failed to list directory '{}': {}", path, e),
+                }
+            }
+            Expr::ReadLines(path_expr) => {
+                let path_val = self.eval_in(path_expr, scope)?;
+                let path = match path_val {
+                    Value::Str(s) => s,
+                    _ => bail!("read lines from: path must be a string"),
+                };
+                match crate::stdlib::file::read_lines(&path) {
+                    Ok(lines) => Ok(Value::List(lines.into_iter().map(Value::Str).collect())),
+                    Err(e) => bail!("Failed to read lines from '{}': {}", path, e),
+                }
+            }
+            Expr::CopyFile(source_expr, dest_expr) => {
+                let source_val = self.eval_in(source_expr, scope)?;
+                let dest_val = self.eval_in(dest_expr, scope)?;
+                let source = match source_val {
+                    Value::Str(s) => s,
+                    _ => bail!("copy file from: source path must be a string"),
+                };
+                let dest = match dest_val {
+                    Value::Str(s) => s,
+                    _ => bail!("copy file to: destination path must be a string"),
+                };
+                match crate::stdlib::file::copy_file(&source, &dest) {
+                    Ok(_) => Ok(Value::Null),
+                    Err(e) => bail!("Failed to copy file from '{}' to '{}': {}", source, dest, e),
+                }
+            }
+            Expr::MoveFile(source_expr, dest_expr) => {
+                let source_val = self.eval_in(source_expr, scope)?;
+                let dest_val = self.eval_in(dest_expr, scope)?;
+                let source = match source_val {
+                    Value::Str(s) => s,
+                    _ => bail!("move file from: source path must be a string"),
+                };
+                let dest = match dest_val {
+                    Value::Str(s) => s,
+                    _ => bail!("move file to: destination path must be a string"),
+                };
+                match crate::stdlib::file::move_file(&source, &dest) {
+                    Ok(_) => Ok(Value::Null),
+                    Err(e) => bail!("Failed to move file from '{}' to '{}': {}", source, dest, e),
+                }
+            }
+            Expr::ReadFromStdin => Ok(Value::Str(core_io::read_to_end())),
+            Expr::ParseJson(json_str_expr) => {
+                let json_str_val = self.eval_in(json_str_expr, scope)?;
+                let json_str = match json_str_val {
+                    Value::Str(s) => s,
+                    _ => bail!("parse json from: input must be a string"),
+                };
+                match crate::stdlib::network::parse_json(&json_str) {
+                    Ok(json_value) => {
+                        // Convert serde_json::Value to our Value
+                        json_to_value(&json_value)
+                    }
+                    Err(e) => bail!("Failed to parse JSON: {}", e),
+                }
+            }
+            Expr::ToJson(value_expr) => {
+                let value = self.eval_in(value_expr, scope)?;
+                let json_value = value_to_json(&value)?;
+                match crate::stdlib::network::json_stringify(&json_value) {
+                    Ok(json_str) => Ok(Value::Str(json_str)),
+                    Err(e) => bail!("Failed to convert to JSON: {}", e),
+                }
+            }
+            Expr::ToJsonPretty(value_expr) => {
+                let value = self.eval_in(value_expr, scope)?;
+                let json_value = value_to_json(&value)?;
+                match crate::stdlib::network::json_stringify_pretty(&json_value) {
+                    Ok(json_str) => Ok(Value::Str(json_str)),
+                    Err(e) => bail!("Failed to convert to pretty JSON: {}", e),
+                }
+            }
+            #[cfg(feature = "web")]
+            Expr::GetPathParam(param_name_expr) => {
+                let param_name_val = self.eval_in(param_name_expr, scope)?;
+                let param_name = match param_name_val {
+                    Value::Str(s) => s,
+                    _ => bail!("get path parameter: parameter name must be a string"),
+                };
+                
+                // Get the request from VM context
+                let request_val = env_get(&self.globals, "__request")
+                    .ok_or_else(|| anyhow!("get path parameter: no request context available"))?;
+                    
+                match request_val {
+                    Value::HttpRequest(ref req) => {
+                        req.path_params.get(&param_name)
+                            .cloned()
+                            .map(Value::Str)
+                            .ok_or_else(|| anyhow!("Path parameter '{}' not found", param_name))
+                    }
+                    _ => bail!("get path parameter: invalid request context"),
+                }
+            }
+            #[cfg(feature = "web")]
+            Expr::GetRequestMethod => Ok(Value::Str(self.current_request("request method")?.method)),
+            #[cfg(feature = "web")]
+            Expr::GetRequestPath => Ok(Value::Str(self.current_request("request path")?.path)),
+            #[cfg(feature = "web")]
+            Expr::GetRemoteAddr => Ok(Value::Str(self.current_request("client address")?.remote_addr)),
+            #[cfg(feature = "web")]
+            Expr::PreferredResponseType => {
+                let req = self.current_request("preferred response type of request")?;
+                let preferred = crate::stdlib::http::preferred_response_type(req.header("Accept"));
+                Ok(Value::Str(preferred.to_string()))
+            }
+            Expr::JsonGet(json_expr, key_expr) => {
+                let json_val = self.eval_in(json_expr, scope)?;
+                let key_val = self.eval_in(key_expr, scope)?;
+                let key = match key_val {
+                    Value::Str(s) => s,
+                    _ => bail!("get from json: key must be a string"),
+                };
+                match json_val {
+                    Value::Dict(ref map) => map
+                        .get(&key)
+                        .cloned()
+                        .ok_or_else(|| anyhow!("Key '{}' not found in JSON object", key)),
+                    _ => bail!("get from json: first argument must be a JSON object (dictionary)"),
+                }
+            }
+            Expr::JsonSet(json_expr, key_expr, value_expr) => {
+                let json_val = self.eval_in(json_expr, scope)?;
+                let key_val = self.eval_in(key_expr, scope)?;
+                let value_val = self.eval_in(value_expr, scope)?;
+                let key = match key_val {
+                    Value::Str(s) => s,
+                    _ => bail!("set in json: key must be a string"),
                 };
                 match json_val {
                     Value::Dict(mut map) => {
@@ -1180,8 +2940,8 @@ impl Vm {
             Expr::NewJsonObject => Ok(Value::Dict(HashMap::new())),
             Expr::NewJsonArray => Ok(Value::List(Vec::new())),
             Expr::JsonPush(json_expr, item_expr) => {
-                let json_val = self.eval(json_expr)?;
-                let item_val = self.eval(item_expr)?;
+                let json_val = self.eval_in(json_expr, scope)?;
+                let item_val = self.eval_in(item_expr, scope)?;
                 match json_val {
                     Value::List(mut vec) => {
                         vec.push(item_val);
@@ -1191,22 +2951,22 @@ impl Vm {
                 }
             }
             Expr::JsonLength(json_expr) => {
-                let json_val = self.eval(json_expr)?;
+                let json_val = self.eval_in(json_expr, scope)?;
                 match json_val {
-                    Value::List(ref vec) => Ok(Value::Num(vec.len() as f64)),
-                    Value::Dict(ref map) => Ok(Value::Num(map.len() as f64)),
+                    Value::List(ref vec) | Value::Tuple(ref vec) => Ok(Value::Int(vec.len() as i64)),
+                    Value::Dict(ref map) => Ok(Value::Int(map.len() as i64)),
                     _ => bail!("json length of: argument must be a JSON array or object"),
                 }
             }
             Expr::ErrorMessage(err_expr) => {
-                let err_val = self.eval(err_expr)?;
+                let err_val = self.eval_in(err_expr, scope)?;
                 match err_val {
                     Value::Error(e) => Ok(Value::Str(e.message.clone())),
                     _ => bail!("error message of: argument must be an error value"),
                 }
             }
             Expr::ErrorType(err_expr) => {
-                let err_val = self.eval(err_expr)?;
+                let err_val = self.eval_in(err_expr, scope)?;
                 match err_val {
                     Value::Error(e) => Ok(Value::Str(e.type_string())),
                     _ => bail!("error type of: argument must be an error value"),
@@ -1216,78 +2976,143 @@ impl Vm {
                 error_type,
                 message,
             } => {
-                let msg_val = self.eval(message)?;
+                let msg_val = self.eval_in(message, scope)?;
                 let msg_str = to_string(&msg_val);
                 let kind = ErrorKind::from_string(error_type);
                 let error = self.create_error(kind, msg_str);
                 Ok(Value::Error(error))
             }
             // Web server operations
-            Expr::CreateWebServer(port_expr) => {
-                let port_val = self.eval(port_expr)?;
-                let port = match port_val {
-                    Value::Num(n) if n >= 0.0 && n <= 65535.0 => n as u16,
+            #[cfg(feature = "web")]
+            Expr::CreateWebServer {
+                port,
+                body_limit,
+                request_timeout,
+                tls,
+            } => {
+                let port_val = self.eval_in(port, scope)?;
+                let port = match number_of(&port_val).map(Number::as_f64) {
+                    Some(n) if (0.0..=65535.0).contains(&n) => n as u16,
                     _ => bail!(
                         "create web server on port: port must be a number between 0 and 65535"
                     ),
                 };
-                let server = crate::stdlib::http::WebServer::new(port);
+                let mut server = crate::stdlib::http::WebServer::new(port);
+                if let Some(bytes) = body_limit {
+                    match whole_number(&self.eval_in(bytes, scope)?) {
+                        Some(n) if n > 0 => server.set_body_limit(n as usize),
+                        _ => bail!("create web server: the body limit must be a whole number of bytes above 0"),
+                    }
+                }
+                if let Some(secs) = request_timeout {
+                    match number_of(&self.eval_in(secs, scope)?).map(Number::as_f64) {
+                        Some(n) if n > 0.0 && n.is_finite() => {
+                            server.set_request_timeout(Duration::from_secs_f64(n))
+                        }
+                        _ => bail!("create web server: the request timeout must be a number of seconds above 0"),
+                    }
+                }
+                if let Some((certificate, key)) = tls {
+                    let (Value::Str(certificate), Value::Str(key)) = (self.eval_in(certificate, scope)?, self.eval_in(key, scope)?) else {
+                        bail!("create secure web server: the certificate and key must be file names");
+                    };
+                    crate::stdlib::tls::TlsFiles::load(&self.base_dir.join(certificate), &self.base_dir.join(key))
+                        .and_then(|files| server.set_tls(files))
+                        .map_err(|e| anyhow!("create secure web server: {}", e))?;
+                }
                 Ok(Value::WebServer(std::sync::Arc::new(
                     std::sync::Mutex::new(server),
                 )))
             }
+            #[cfg(feature = "web")]
+            Expr::StartEventStream { heartbeat } => {
+                self.current_request("start event stream")?;
+                let heartbeat = match heartbeat {
+                    Some(secs) => match number_of(&self.eval_in(secs, scope)?).map(Number::as_f64) {
+                        Some(n) if n > 0.0 && n.is_finite() => Duration::from_secs_f64(n),
+                        _ => bail!("start event stream: the heartbeat must be a number of seconds above 0"),
+                    },
+                    None => event_stream::DEFAULT_HEARTBEAT,
+                };
+                let headers = crate::stdlib::http::take_response_headers();
+                Ok(Value::EventStream(EventStream::start(&headers, heartbeat)?))
+            }
+            #[cfg(feature = "web")]
             Expr::HtmlResponse(content_expr) => {
-                let content_val = self.eval(content_expr)?;
+                let content_val = self.eval_in(content_expr, scope)?;
                 let content = to_string(&content_val);
                 let response = crate::stdlib::http::html_response(content);
                 Ok(Value::HttpResponse(response))
             }
+            #[cfg(feature = "web")]
             Expr::JsonResponse(data_expr) => {
-                let data_val = self.eval(data_expr)?;
-                let json_value = self.value_to_json(&data_val)?;
+                let data_val = self.eval_in(data_expr, scope)?;
+                let json_value = value_to_json(&data_val)?;
                 let response = crate::stdlib::http::json_response(json_value);
                 Ok(Value::HttpResponse(response))
             }
+            #[cfg(feature = "web")]
             Expr::JsonResponseStatus(data_expr, status_expr) => {
-                let data_val = self.eval(data_expr)?;
-                let status_val = self.eval(status_expr)?;
-                let status = match status_val {
-                    Value::Num(n) if n >= 100.0 && n < 600.0 => n as u16,
+                let data_val = self.eval_in(data_expr, scope)?;
+                let status_val = self.eval_in(status_expr, scope)?;
+                let status = match number_of(&status_val).map(Number::as_f64) {
+                    Some(n) if (100.0..600.0).contains(&n) => n as u16,
                     _ => bail!(
                         "json response with status: status must be a number between 100 and 599"
                     ),
                 };
-                let json_value = self.value_to_json(&data_val)?;
+                let json_value = value_to_json(&data_val)?;
                 let response = crate::stdlib::http::json_response_with_status(json_value, status);
                 Ok(Value::HttpResponse(response))
             }
+            #[cfg(feature = "web")]
             Expr::RenderTemplate(template_expr, data_expr) => {
-                let template_val = self.eval(template_expr)?;
-                let data_val = self.eval(data_expr)?;
+                let template_val = self.eval_in(template_expr, scope)?;
+                let data_val = self.eval_in(data_expr, scope)?;
                 let template = match template_val {
                     Value::Str(s) => s,
                     _ => bail!("render template: template must be a string"),
                 };
-                let json_data = self.value_to_json(&data_val)?;
+                let json_data = value_to_json(&data_val)?;
                 match crate::stdlib::template::render_full(&template, &json_data) {
                     Ok(rendered) => Ok(Value::Str(rendered)),
                     Err(e) => bail!("Failed to render template: {}", e),
                 }
             }
+            #[cfg(feature = "web")]
             Expr::ErrorResponse(status_expr, message_expr) => {
-                let status_val = self.eval(status_expr)?;
-                let message_val = self.eval(message_expr)?;
-                let status = match status_val {
-                    Value::Num(n) if n >= 100.0 && n < 600.0 => n as u16,
+                let status_val = self.eval_in(status_expr, scope)?;
+                let message_val = self.eval_in(message_expr, scope)?;
+                let status = match number_of(&status_val).map(Number::as_f64) {
+                    Some(n) if (100.0..600.0).contains(&n) => n as u16,
                     _ => bail!("error response: status must be a number between 100 and 599"),
                 };
                 let message = to_string(&message_val);
                 let response = crate::stdlib::http::error_response(status, message);
                 Ok(Value::HttpResponse(response))
             }
+            #[cfg(not(feature = "web"))]
+            Expr::CreateWebServer { .. }
+            | Expr::StartEventStream { .. }
+            | Expr::ReceiveMessage { .. }
+            | Expr::HtmlResponse(_)
+            | Expr::JsonResponse(_)
+            | Expr::JsonResponseStatus(_, _)
+            | Expr::RenderTemplate(_, _)
+            | Expr::RenderTemplateFile(_, _)
+            | Expr::ErrorResponse(_, _)
+            | Expr::WithEtag(_)
+            | Expr::WithCache(_, _)
+            | Expr::GetPathParam(_)
+            | Expr::GetRequestHeader(_)
+            | Expr::SetResponseHeaderExpr(_, _)
+            | Expr::GetRequestMethod
+            | Expr::GetRequestPath
+            | Expr::GetRemoteAddr
+            | Expr::PreferredResponseType => Err(web_disabled(dump_expr(e))),
             Expr::RequestField(request_expr, field_expr) => {
-                let request_val = self.eval(request_expr)?;
-                let field_val = self.eval(field_expr)?;
+                let request_val = self.eval_in(request_expr, scope)?;
+                let field_val = self.eval_in(field_expr, scope)?;
                 let field = match field_val {
                     Value::Str(s) => s,
                     _ => bail!("request field: field name must be a string"),
@@ -1301,111 +3126,74 @@ impl Vm {
                     _ => bail!("request field: request must be a dictionary"),
                 }
             }
-        }
-    }
-
-    // Helper to convert serde_json::Value to our Value
-    #[allow(clippy::only_used_in_recursion)]
-    fn json_to_value(&self, json: &serde_json::Value) -> Result<Value> {
-        use serde_json::Value as JsonValue;
-        match json {
-            JsonValue::Null => Ok(Value::Null),
-            JsonValue::Bool(b) => Ok(Value::Bool(*b)),
-            JsonValue::Number(n) => {
-                if let Some(f) = n.as_f64() {
-                    Ok(Value::Num(f))
-                } else {
-                    bail!("JSON number out of range")
-                }
-            }
-            JsonValue::String(s) => Ok(Value::Str(s.clone())),
-            JsonValue::Array(arr) => {
-                let mut values = Vec::new();
-                for item in arr {
-                    values.push(self.json_to_value(item)?);
-                }
-                Ok(Value::List(values))
-            }
-            JsonValue::Object(obj) => {
-                let mut map = HashMap::new();
-                for (k, v) in obj {
-                    map.insert(k.clone(), self.json_to_value(v)?);
-                }
-                Ok(Value::Dict(map))
-            }
-        }
-    }
-
-    // Helper to convert our Value to serde_json::Value
-    #[allow(clippy::only_used_in_recursion)]
-    fn value_to_json(&self, value: &Value) -> Result<serde_json::Value> {
-        use serde_json::Value as JsonValue;
-        match value {
-            Value::Null => Ok(JsonValue::Null),
-            Value::Bool(b) => Ok(JsonValue::Bool(*b)),
-            Value::Num(n) => Ok(serde_json::Number::from_f64(*n)
-                .map(JsonValue::Number)
-                .unwrap_or(JsonValue::Null)),
-            Value::Str(s) => Ok(JsonValue::String(s.clone())),
-            Value::List(vec) => {
-                let mut arr = Vec::new();
-                for item in vec {
-                    arr.push(self.value_to_json(item)?);
-                }
-                Ok(JsonValue::Array(arr))
-            }
-            Value::Dict(map) => {
-                let mut obj = serde_json::Map::new();
-                for (k, v) in map {
-                    obj.insert(k.clone(), self.value_to_json(v)?);
-                }
-                Ok(JsonValue::Object(obj))
-            }
-            Value::Func(_) => bail!("Cannot convert function to JSON"),
-            Value::Error(e) => bail!("Cannot convert error to JSON: {}", e.message),
-            Value::WebServer(_) => bail!("Cannot convert web server to JSON"),
-            Value::HttpRequest(_) => bail!("Cannot convert HTTP request to JSON"),
-            Value::HttpResponse(_) => bail!("Cannot convert HTTP response to JSON"),
-            Value::LiveReloadTracker(_) => bail!("Cannot convert LiveReloadTracker to JSON"),
+            _ => unreachable!("eval_in evaluates {}", dump_expr(e)),
         }
     }
 
     fn truthy(&self, v: &Value) -> Result<bool> {
         match v {
+            Value::Int(i) => Ok(*i != 0),
             Value::Num(n) => Ok(*n != 0.0),
             Value::Str(s) => Ok(!s.is_empty()),
             Value::Bool(b) => Ok(*b),
             Value::Null => Ok(false),
             Value::Func(_) => Ok(true),
-            Value::List(v) => Ok(!v.is_empty()),
+            Value::List(v) | Value::Tuple(v) => Ok(!v.is_empty()),
+            Value::LazyRange { start, end, step } => Ok(range_len(*start, *end, *step) > 0),
             Value::Dict(m) => Ok(!m.is_empty()),
+            Value::Set(s) => Ok(!s.is_empty()),
             Value::Error(_) => Ok(true), // Errors are truthy (presence indicates something went wrong)
+            #[cfg(feature = "web")]
             Value::WebServer(_) => Ok(true), // Web servers are truthy
+            #[cfg(feature = "web")]
             Value::HttpRequest(_) => Ok(true), // HTTP requests are truthy
+            #[cfg(feature = "web")]
             Value::HttpResponse(_) => Ok(true), // HTTP responses are truthy
+            #[cfg(feature = "web")]
             Value::LiveReloadTracker(_) => Ok(true), // LiveReloadTracker is truthy
+            Value::Regex(_) | Value::Future(_) | Value::Channel(_) | Value::EventEmitter(_) => Ok(true),
+            Value::Observed(v, _) => self.truthy(v),
+            #[cfg(feature = "web")]
+            Value::EventStream(s) => Ok(s.is_open()),
+            #[cfg(feature = "web")]
+            Value::WebSocket(s) => Ok(s.is_open()),
+            Value::Stack(_) | Value::Queue(_) | Value::PriorityQueue(_) | Value::OrderedDict(_) => {
+                Ok(collection_len(v).unwrap_or(0) > 0)
+            }
         }
     }
 
     /// Push a new frame onto the call stack for error tracking
-    #[allow(dead_code)]
-    fn push_call_frame(&mut self, function_name: impl Into<String>) {
-        self.call_stack.push(CallFrame {
+    fn push_call_frame(&self, function_name: impl Into<String>) {
+        self.call_stack.lock().unwrap().push(CallFrame {
             function_name: function_name.into(),
             file: self.current_file.clone(),
-            line: 0, // Line tracking can be added later if needed
+            line: self.tracer.line(),
         });
     }
 
     /// Pop the topmost call frame when returning from a function
-    #[allow(dead_code)]
-    fn pop_call_frame(&mut self) {
-        self.call_stack.pop();
+    fn pop_call_frame(&self) {
+        self.call_stack.lock().unwrap().pop();
+    }
+
+    /// A `WrongType` error as a `TypeError` showing the calls that led to it;
+    /// other errors are left as they are
+    fn with_call_stack(&self, err: anyhow::Error) -> anyhow::Error {
+        match err.downcast::<WrongType>() {
+            Ok(WrongType(message)) => {
+                let error = self.create_error(ErrorKind::TypeError, message);
+                self.error_with_location(error.format_with_trace())
+            }
+            Err(err) => err,
+        }
     }
 
     /// Build a stack trace from the current call stack
     fn build_stack_trace(&self) -> Vec<StackFrame> {
         self.call_stack
+            .lock()
+            .unwrap()
             .iter()
             .map(|frame| {
                 StackFrame::new(frame.function_name.clone(), frame.file.clone(), frame.line)
@@ -1418,15 +3206,101 @@ impl Vm {
         PohError::with_stack_trace(kind, message, self.build_stack_trace())
     }
 
+    /// `Ask for`: the answer as a value, or a ValidationError when input
+    /// ends or keeps being of the wrong kind
+    fn ask_for(&self, name: &str, expect: AskType) -> Result<Value> {
+        if self.in_background {
+            bail!("Ask for {}: a 'Run in background' block cannot ask for input", name);
+        }
+        match core_io::ask_as(expect) {
+            Ok(Answer::Number(n)) => Ok(n.into()),
+            Ok(Answer::Bool(b)) => Ok(Value::Bool(b)),
+            Ok(Answer::Text(s)) => Ok(Value::Str(s)),
+            Err(msg) => {
+                let error = self.create_error(ErrorKind::ValidationError, format!("Ask for {}: {}", name, msg));
+                Err(self.error_with_location(error.format_with_trace()))
+            }
+        }
+    }
+
+    /// The results of calling `name` on each item, in the items' order. The
+    /// calls are shared out between `workers` threads, each running a copy
+    /// of this Vm, so what a call sets is gone when it returns. Once a call
+    /// fails no thread starts another, and the error of the earliest item
+    /// that failed is returned.
+    fn apply_in_parallel(&self, name: &str, func: Option<&Func>, items: &[Value], workers: usize) -> Result<Vec<Value>> {
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let results: Vec<Mutex<Option<Result<Value>>>> = items.iter().map(|_| Mutex::new(None)).collect();
+        let stopped = std::thread::scope(|s| {
+            let (next, failed, results) = (&next, &failed, &results);
+            let running: Vec<_> = (0..workers.min(items.len()))
+                .map(|_| {
+                    let vm = self.clone();
+                    s.spawn(move || {
+                        while !failed.load(atomic::Ordering::SeqCst) {
+                            let n = next.fetch_add(1, atomic::Ordering::SeqCst);
+                            let Some(item) = items.get(n) else { break };
+                            let args = std::slice::from_ref(item);
+                            let result = match func {
+                                Some(f) => vm.call_func_value(f, args),
+                                None => vm.call_function(name, args),
+                            };
+                            if result.is_err() {
+                                failed.store(true, atomic::Ordering::SeqCst);
+                            }
+                            *results[n].lock().unwrap() = Some(result);
+                        }
+                    })
+                })
+                .collect();
+            running.into_iter().map(|worker| worker.join()).filter(Result::is_err).count()
+        });
+        if stopped > 0 {
+            bail!("apply {} to each item in parallel: a worker stopped by an internal error", name);
+        }
+        let mut out = Vec::with_capacity(items.len());
+        for result in results {
+            match result.into_inner().unwrap() {
+                Some(Ok(v)) => out.push(v),
+                Some(Err(e)) => return Err(e),
+                // Never started because an earlier item failed
+                None => {}
+            }
+        }
+        Ok(out)
+    }
+
     fn call_function(&self, name: &str, args: &[Value]) -> Result<Value> {
         if name.contains("::") {
             return self.call_qualified_function(name, args);
         }
+        if self.stats_enabled && BUILTIN_FUNCTIONS.contains(&name) {
+            self.counters.builtin(name);
+        }
+        let _span = if self.profiler.recording() && BUILTIN_FUNCTIONS.contains(&name) {
+            self.profiler.call(name)
+        } else {
+            None
+        };
         // Built-ins
         match name {
             "now" if args.is_empty() => {
                 return Ok(Value::Str(iso_now()));
             }
+            // Not in BUILTIN_FUNCTIONS: it returns nothing, so `Use sleep with 0.5` is how it is called
+            "sleep" => {
+                let secs = match args {
+                    [Value::Int(i)] if *i >= 0 => Some(*i as f64),
+                    [Value::Num(n)] if *n >= 0.0 => Some(*n),
+                    _ => None,
+                };
+                let Some(secs) = secs else {
+                    bail!("sleep expects a number of seconds, 0 or more");
+                };
+                std::thread::sleep(Duration::from_secs_f64(secs));
+                return Ok(Value::Null);
+            }
             "range" => {
                 return builtin_range(args);
             }
@@ -1464,6 +3338,15 @@ impl Vm {
             "ceil" => {
                 return builtin_ceil(args);
             }
+            "lerp" => {
+                return builtin_lerp(args);
+            }
+            "clamp" => {
+                return builtin_clamp(args);
+            }
+            "map_range" => {
+                return builtin_map_range(args);
+            }
             // String functions
             "uppercase" => {
                 return builtin_uppercase(args);
@@ -1474,6 +3357,19 @@ impl Vm {
             "trim" => {
                 return builtin_trim(args);
             }
+            // Pattern functions
+            "match_pattern" => {
+                return builtin_match_pattern(args);
+            }
+            "find_all" => {
+                return builtin_find_all(args);
+            }
+            "replace_pattern" => {
+                return builtin_replace_pattern(args);
+            }
+            "split_by_pattern" => {
+                return builtin_split_by_pattern(args);
+            }
             // Collection functions
             "first" => {
                 return builtin_first(args);
@@ -1484,31 +3380,111 @@ impl Vm {
             "reverse" => {
                 return builtin_reverse(args);
             }
+            // Higher-order functions
+            "memoize" => {
+                return builtin_memoize(args);
+            }
             _ => {}
         }
         // User-defined
-        if let Some(Value::Func(f)) = self.globals.get(name) {
-            return self.call_func_value(f, args);
-        }
         if let Some(Value::Func(f)) = self.resolve_value(name) {
             return self.call_func_value(&f, args);
         }
         Err(anyhow!("Error: Function '{}' is not defined", name))
     }
 
+    /// `find first item in`, `find all items in` and `find index of first
+    /// item in`, given the list and the predicate after `where`. The first
+    /// and the index stop at the first item the predicate holds for.
+    fn find_items(&self, e: &Expr, list: Value, predicate: Value) -> Result<Value> {
+        let phrase = match e {
+            Expr::FindFirst(..) => "find first item",
+            Expr::FindAll(..) => "find all items",
+            _ => "find index of first item",
+        };
+        let Value::Func(f) = predicate else {
+            bail!("{}: expected a function after 'where', got {}", phrase, to_string(&predicate));
+        };
+        let items = match listed(&list).into_owned() {
+            Value::List(items) | Value::Tuple(items) => items,
+            other => bail!("{}: expected a list, got {}", phrase, to_string(&other)),
+        };
+        let mut found = Vec::new();
+        for (i, item) in items.into_iter().enumerate() {
+            if !self.truthy(&self.call_func_value(&f, std::slice::from_ref(&item))?)? {
+                continue;
+            }
+            match e {
+                Expr::FindFirst(..) => return Ok(item),
+                Expr::FindIndex(..) => return Ok(Value::Int(i as i64)),
+                _ => found.push(item),
+            }
+        }
+        Ok(match e {
+            Expr::FindFirst(..) => Value::Null,
+            Expr::FindIndex(..) => Value::Int(-1),
+            _ => Value::List(found),
+        })
+    }
+
     fn call_func_value(&self, f: &Func, args: &[Value]) -> Result<Value> {
+        if self.stats_enabled {
+            self.counters.call();
+        }
+        let _call = self.debugger.as_ref().map(Debugger::call);
+        let _span = self.profiler.call(&f.name);
+        if self.tracer.enabled() {
+            let shown: Vec<String> = args.iter().map(trace_value).collect();
+            let entry = if shown.is_empty() {
+                format!("call {}", f.name)
+            } else {
+                format!("call {} with {}", f.name, shown.join(", "))
+            };
+            return self
+                .tracer
+                .call(&f.name, entry, || self.invoke_in_frame(f, args), trace_value);
+        }
+        self.invoke_in_frame(f, args)
+    }
+
+    /// `invoke_func` with `f` on the call stack
+    fn invoke_in_frame(&self, f: &Func, args: &[Value]) -> Result<Value> {
+        self.push_call_frame(f.name.clone());
+        let result = self.invoke_func(f, args).map_err(|e| self.with_call_stack(e));
+        self.pop_call_frame();
+        result
+    }
+
+    fn invoke_func(&self, f: &Func, args: &[Value]) -> Result<Value> {
+        if let FuncBody::Native(native) = &f.body {
+            return native(args);
+        }
+        if let FuncBody::Expr(Expr::Memoize(target)) = &f.body {
+            return self.call_memoized(f, target, args);
+        }
         // Arity with defaults
         let required = f.params.iter().filter(|p| p.default.is_none()).count();
         if args.len() < required || args.len() > f.params.len() {
-            return Err(anyhow!(
-                "Function '{}' expects {}..{} args but got {}",
+            let expected = if required == f.params.len() {
+                format!("{}", required)
+            } else {
+                format!("{} to {}", required, f.params.len())
+            };
+            let given: Vec<String> = args.iter().map(described).collect();
+            let given = if given.is_empty() {
+                "nothing".to_string()
+            } else {
+                given.join(", ")
+            };
+            return Err(wrong_type(format!(
+                "Function '{}' expects {} argument(s) but got {}: {}",
                 f.name,
-                required,
-                f.params.len(),
-                args.len()
-            ));
+                expected,
+                args.len(),
+                given
+            )));
         }
-        // Locals map
+        // Locals map; the function's own scope sits on top of the one it was defined in
         let mut locals: HashMap<String, Value> = HashMap::new();
         for (i, p) in f.params.iter().enumerate() {
             if i < args.len() {
@@ -1518,74 +3494,141 @@ impl Vm {
                 locals.insert(p.name.clone(), v);
             }
         }
-        // If body is a synthetic Ident to a stored block, execute that block with a frame
-        if let Expr::Ident(synth) = &f.body {
-            if synth.starts_with("__fn_body__") {
-                if let Some(body_prog) = FN_BLOCKS.with(|m| m.borrow().get(synth).cloned()) {
-                    return self.execute_function_block(body_prog, &locals, &f.captured);
-                }
+        match &f.body {
+            FuncBody::Block(block) => self.execute_function_block(block, locals, &f.captured),
+            FuncBody::Expr(body) => self.eval_in_scope_with_capture(body, &locals, &f.captured),
+            FuncBody::Native(native) => native(args),
+        }
+    }
+
+    /// Call the function a `memoize` wrapper holds, reusing earlier results
+    fn call_memoized(&self, wrapper: &Func, target: &Expr, args: &[Value]) -> Result<Value> {
+        let key = memo_key(args);
+        if let Some(Value::Dict(cache)) = wrapper.captured.read().unwrap().vars.get(MEMO_CACHE) {
+            if let Some(hit) = cache.get(&key) {
+                return Ok(hit.clone());
             }
         }
-        self.eval_in_scope_with_capture(&f.body, &locals, &f.captured)
+        let inner = match self.eval_in_scope_with_capture(target, &HashMap::new(), &wrapper.captured)? {
+            Value::Func(f) => f,
+            other => return Err(anyhow!("memoize expects a function, got {}", to_string(&other))),
+        };
+        let result = self.call_func_value(&inner, args)?;
+        if let Some(Value::Dict(cache)) = wrapper.captured.write().unwrap().vars.get_mut(MEMO_CACHE) {
+            cache.insert(key, result.clone());
+        }
+        Ok(result)
     }
 
     fn execute_function_block(
         &self,
-        body: Program,
-        locals: &HashMap<String, Value>,
-        captured: &[HashMap<String, Value>],
+        block: &Arc<FnBlock>,
+        mut locals: HashMap<String, Value>,
+        captured: &Env,
     ) -> Result<Value> {
-        // Create a scope stack: locals (top), captured (next), globals (fallback)
+        // Create a scope chain: locals (top), then the defining scope and its parents
         // Execute statements until Return encountered; return its value or 'nothing' (empty string) if none.
+        let mut slots = Vec::new();
+        if let Some(layout) = &block.layout {
+            slots = vec![None; layout.len()];
+            for (slot, value) in slots.iter_mut().enumerate() {
+                *value = locals.remove(layout.name(slot as u16));
+            }
+        }
         let mut frame = Frame {
-            locals: locals.clone(),
-            captured: captured.to_owned(),
+            env: Scope::new_env(locals, Some(Arc::clone(captured))),
+            slots,
+            block: Arc::clone(block),
         };
-        match self.exec_block_with_frame(&body, &mut frame) {
+        match self.exec_block_with_frame(&block.body, &mut frame)? {
             ControlFlow::Return(v) => Ok(v.unwrap_or(Value::Str(String::new()))),
             ControlFlow::Continue => Ok(Value::Str(String::new())),
         }
     }
 
-    fn exec_block_with_frame(&self, body: &Program, frame: &mut Frame) -> ControlFlow {
+    fn exec_block_with_frame(&self, body: &Program, frame: &mut Frame) -> Result<ControlFlow> {
+        let _nested = self.tracer.enabled().then(|| self.tracer.nested());
         for stmt in body {
+            if let Stmt::Line(n) = stmt {
+                self.tracer.set_line(*n);
+                continue;
+            }
+            if self.stats_enabled {
+                self.counters.statement();
+            }
+            if let Some(debugger) = &self.debugger {
+                let scope = StopScope { vm: self, frame: Some(frame) };
+                debugger.before(self.tracer.line(), || dump_stmt(stmt), &scope);
+            }
+            if self.tracer.enabled() && !matches!(stmt, Stmt::Set { .. }) {
+                self.tracer.emit(&dump_stmt(stmt));
+            }
             match stmt {
-                Stmt::Write(e) => {
-                    if let Ok(v) = self.eval_in_frame(e, frame) {
-                        println!("{}", to_string(&v));
-                    }
-                }
-                Stmt::AskFor { var_name } => {
-                    let input = core_io::ask("");
-                    let value = if let Ok(n) = input.parse::<f64>() {
-                        Value::Num(n)
-                    } else {
-                        Value::Str(input)
-                    };
-                    frame.locals.insert(var_name.clone(), value);
+                Stmt::Write(e) => self.write_value(&self.eval_in_frame(e, frame)?)?,
+                Stmt::MultiWrite { exprs, sep, end } => {
+                    self.multi_write(exprs, sep.as_ref(), end.as_ref(), |e| self.eval_in_frame(e, frame))?;
                 }
+                Stmt::AskFor { var_name, expect } => frame.set(var_name, self.ask_for(var_name, *expect)?),
                 Stmt::Set { name, value } => {
-                    if let Ok(v) = self.eval_in_frame(value, frame) {
-                        frame.locals.insert(name.clone(), v);
+                    let v = self.eval_in_frame(value, frame)?;
+                    if self.tracer.enabled() {
+                        self.tracer.emit(&format!("{} → {}", dump_stmt(stmt), trace_value(&v)));
+                    }
+                    self.check_declared(name, &v)?;
+                    frame.set(name, v);
+                }
+                Stmt::SetLocal { slot, value } => {
+                    let v = self.eval_in_frame(value, frame)?;
+                    let name = frame.block.layout.as_ref().map_or("", |l| l.name(*slot));
+                    self.check_declared(name, &v)?;
+                    frame.slots[*slot as usize] = Some(v);
+                }
+                Stmt::TypeAlias { name, base_type } => self.types.write().unwrap().alias(name, base_type),
+                Stmt::Schema { name, fields } => self.define_schema(name, fields)?,
+                #[cfg(feature = "web")]
+                Stmt::TemplateDirectory(dir) => self.set_template_dir(self.eval_in_frame(dir, frame)?)?,
+                Stmt::DeclareType { name, type_name } => self.declare_type(name, type_name)?,
+                Stmt::GlobalConst { name, .. } => {
+                    bail!("Set constant {}: constants are set at the top level of a program, not in a function", name)
+                }
+                Stmt::SetItem { name, key, value } => {
+                    self.check_not_constant(name)?;
+                    let c = self.eval_in_frame(&Expr::Ident(name.clone()), frame)?;
+                    let key = self.eval_in_frame(key, frame)?;
+                    let collection = self.sized(set_item(name, c, key, self.eval_in_frame(value, frame)?)?)?;
+                    frame.assign(name, collection);
+                }
+                Stmt::Destructure { names, value } => {
+                    let v = self.eval_in_frame(value, frame)?;
+                    for name in names {
+                        self.check_not_constant(name)?;
+                    }
+                    for (name, item) in names.iter().zip(destructure(names, v)?) {
+                        frame.set(name, item);
+                    }
+                }
+                Stmt::PutEnvInScope {
+                    prefix,
+                    strip_prefix,
+                } => {
+                    for (name, value) in env_vars_with_prefix(prefix, *strip_prefix) {
+                        env_set(&self.globals, &name, value);
                     }
                 }
+                Stmt::PrintTable { headers, rows } => {
+                    let headers = self.eval_in_frame(headers, frame)?;
+                    let rows = self.eval_in_frame(rows, frame)?;
+                    core_io::write(&table_text(&headers, &rows, self.table_style)?);
+                }
                 Stmt::IfInline {
                     cond,
                     then_write,
                     otherwise_write,
                 } => {
-                    if let Ok(c) =
-                        self.truthy(&self.eval_in_frame(cond, frame).unwrap_or(Value::Num(0.0)))
-                    {
-                        if c {
-                            if let Ok(v) = self.eval_in_frame(then_write, frame) {
-                                core_io::write(&to_string(&v));
-                            }
-                        } else if let Some(e) = otherwise_write {
-                            if let Ok(v) = self.eval_in_frame(e, frame) {
-                                core_io::write(&to_string(&v));
-                            }
-                        }
+                    if self.truthy(&self.eval_in_frame(cond, frame)?)? {
+                        self.write_value(&self.eval_in_frame(then_write, frame)?)?;
+                    } else if let Some(e) = otherwise_write {
+                        self.write_value(&self.eval_in_frame(e, frame)?)?;
                     }
                 }
                 Stmt::IfBlock {
@@ -1593,30 +3636,36 @@ impl Vm {
                     then_body,
                     otherwise_body,
                 } => {
-                    if let Ok(c) =
-                        self.truthy(&self.eval_in_frame(cond, frame).unwrap_or(Value::Num(0.0)))
-                    {
-                        let cf = if c {
-                            self.exec_block_with_frame(then_body, frame)
-                        } else if let Some(eb) = otherwise_body {
-                            self.exec_block_with_frame(eb, frame)
-                        } else {
-                            ControlFlow::Continue
-                        };
+                    let cf = if self.truthy(&self.eval_in_frame(cond, frame)?)? {
+                        self.exec_block_with_frame(then_body, frame)?
+                    } else if let Some(eb) = otherwise_body {
+                        self.exec_block_with_frame(eb, frame)?
+                    } else {
+                        ControlFlow::Continue
+                    };
+                    if let ControlFlow::Return(_) = cf {
+                        return Ok(cf);
+                    }
+                }
+                Stmt::WhileBlock { cond, body } => {
+                    let mut guard = 0usize;
+                    while self.truthy(&self.eval_in_frame(cond, frame)?)? {
+                        let cf = self.exec_block_with_frame(body, frame)?;
                         if let ControlFlow::Return(_) = cf {
-                            return cf;
+                            return Ok(cf);
+                        }
+                        guard += 1;
+                        if guard > 1_000_000 {
+                            break;
                         }
                     }
                 }
-                Stmt::WhileBlock { cond, body } => {
+                Stmt::RepeatUntil { cond, body } => {
                     let mut guard = 0usize;
-                    while self
-                        .truthy(&self.eval_in_frame(cond, frame).unwrap_or(Value::Num(0.0)))
-                        .unwrap_or(false)
-                    {
-                        let cf = self.exec_block_with_frame(body, frame);
+                    while !self.truthy(&self.eval_in_frame(cond, frame)?)? {
+                        let cf = self.exec_block_with_frame(body, frame)?;
                         if let ControlFlow::Return(_) = cf {
-                            return cf;
+                            return Ok(cf);
                         }
                         guard += 1;
                         if guard > 1_000_000 {
@@ -1625,18 +3674,33 @@ impl Vm {
                     }
                 }
                 Stmt::RepeatBlock { count, body } => {
-                    let n = match self.eval_in_frame(count, frame).ok() {
-                        Some(Value::Num(x)) => x.max(0.0) as i64,
-                        _ => 0,
-                    };
+                    let n = self.repeat_count(self.eval_in_frame(count, frame)?)?;
                     for _ in 0..n {
-                        let cf = self.exec_block_with_frame(body, frame);
+                        let cf = self.exec_block_with_frame(body, frame)?;
+                        if let ControlFlow::Return(_) = cf {
+                            return Ok(cf);
+                        }
+                    }
+                }
+                Stmt::ReadLineLoop { var_name, body } => {
+                    while let Some(line) = core_io::read_raw_line() {
+                        frame.set(var_name, Value::Str(line));
+                        let cf = self.exec_block_with_frame(body, frame)?;
                         if let ControlFlow::Return(_) = cf {
-                            return cf;
+                            return Ok(cf);
                         }
                     }
                 }
-                Stmt::ImportLocal { .. } => {
+                Stmt::ForEach { var_name, items, body } => {
+                    for item in each_item(self.eval_in_frame(items, frame)?, "For each")? {
+                        frame.set(var_name, item);
+                        let cf = self.exec_block_with_frame(body, frame)?;
+                        if let ControlFlow::Return(_) = cf {
+                            return Ok(cf);
+                        }
+                    }
+                }
+                Stmt::ImportLocal { .. } | Stmt::ImportAs { .. } | Stmt::ImportItems { .. } => {
                     // Imports inside function frames are ignored at runtime-frame level;
                     // they should be handled at module load/top-level execution.
                 }
@@ -1644,2054 +3708,4187 @@ impl Vm {
                     // Same as above: ignore within function frames.
                 }
                 Stmt::FuncInline { name, params, body } => {
-                    let mut captured = frame.captured.clone();
-                    // capture chain + current locals as the first (innermost) env
-                    captured.insert(0, frame.locals.clone());
+                    // capture a handle to the current scope; later changes stay visible
                     let f = Func {
                         name: name.clone(),
                         params: params.clone(),
-                        body: body.clone(),
-                        captured,
+                        body: FuncBody::Expr(body.clone()),
+                        captured: Arc::clone(&frame.env),
                     };
-                    frame.locals.insert(name.clone(), Value::Func(f));
+                    env_set(&frame.env, name, Value::Func(f));
                 }
                 Stmt::FuncBlock {
                     name,
                     params,
                     body: b,
                 } => {
-                    let mut captured = frame.captured.clone();
-                    captured.insert(0, frame.locals.clone());
+                    let block = frame
+                        .block
+                        .nested
+                        .get(&block_id(b))
+                        .cloned()
+                        .unwrap_or_else(|| FnBlock::new(params, b, !self.shows_statements()));
                     let f = Func {
                         name: name.clone(),
                         params: params.clone(),
-                        body: Expr::Ident(format!("__fn_body__{}", name)),
-                        captured,
+                        body: FuncBody::Block(block),
+                        captured: Arc::clone(&frame.env),
                     };
-                    FN_BLOCKS.with(|m| {
-                        m.borrow_mut()
-                            .insert(format!("__fn_body__{}", name), b.clone());
-                    });
-                    frame.locals.insert(name.clone(), Value::Func(f));
+                    env_set(&frame.env, name, Value::Func(f));
                 }
-                Stmt::Use { name, args } => {
+                Stmt::Use { name, args, result_var } => {
                     let argv = args
                         .iter()
-                        .filter_map(|e| self.eval_in_frame(e, frame).ok())
-                        .collect::<Vec<_>>();
-                    // Resolve function from current frame first, then captured, then globals
-                    if let Some(Value::Func(f)) = frame.locals.get(name) {
-                        if let Ok(v) = self.call_func_value(f, &argv) {
-                            core_io::write(&to_string(&v));
-                        }
-                    } else if let Some(Value::Func(f)) = self.globals.get(name) {
-                        if let Ok(v) = self.call_func_value(f, &argv) {
-                            core_io::write(&to_string(&v));
-                        }
+                        .map(|e| self.eval_in_frame(e, frame))
+                        .collect::<Result<Vec<_>>>()?;
+                    // Resolve function through the frame's scope chain first, then globals
+                    let v = if let Some(Value::Func(f)) = frame.get(name) {
+                        self.call_func_value(&f, &argv)?
                     } else {
-                        // search in captured chain
-                        let mut done = false;
-                        for env in frame.captured.iter() {
-                            if let Some(Value::Func(f)) = env.get(name) {
-                                if let Ok(v) = self.call_func_value(f, &argv) {
-                                    core_io::write(&to_string(&v));
-                                }
-                                done = true;
-                                break;
-                            }
-                        }
-                        if !done {
-                            if let Ok(v) = self.call_function(name, &argv) {
-                                core_io::write(&to_string(&v));
-                            }
-                        }
+                        self.call_function(name, &argv)?
+                    };
+                    if let Some(var) = result_var {
+                        self.check_declared(var, &v)?;
+                        frame.set(var, v);
                     }
                 }
                 Stmt::Return(expr) => {
                     let v = match expr {
-                        Some(e) => self.eval_in_frame(e, frame).ok(),
+                        Some(e) => Some(self.eval_in_frame(e, frame)?),
                         None => None,
                     };
-                    return ControlFlow::Return(v);
-                }
-                Stmt::TryCatch { .. } => {
-                    // TODO: Implement proper try/catch in function context
-                    // For now, just continue execution
-                    // Full implementation requires propagating errors through ControlFlow
-                    return ControlFlow::Continue;
+                    return Ok(ControlFlow::Return(v));
                 }
-                Stmt::Throw(expr) => {
-                    // Evaluate throw expression in function context
-                    if let Ok(val) = self.eval_in_frame(expr, frame) {
-                        match val {
-                            Value::Error(e) => {
-                                eprintln!("{}", e.format_with_trace());
-                            }
-                            _ => {
-                                let msg = to_string(&val);
-                                let error = self.create_error(ErrorKind::RuntimeError, msg);
-                                eprintln!("{}", error.format_with_trace());
+                Stmt::TryCatch {
+                    try_block,
+                    catch_handlers,
+                    finally_block,
+                } => {
+                    let mut outcome = self.exec_block_with_frame(try_block, frame);
+                    if let Err(err) = &outcome {
+                        if let Some((handler, caught)) = catch_handler(err, catch_handlers) {
+                            if let Some(var_name) = &handler.var_name {
+                                frame.set(var_name, caught);
                             }
+                            outcome = self.exec_block_with_frame(&handler.block, frame);
+                        }
+                    }
+                    // A Return in the finally block wins over the try's outcome
+                    if let Some(fin) = finally_block {
+                        if let ControlFlow::Return(v) = self.exec_block_with_frame(fin, frame)? {
+                            return Ok(ControlFlow::Return(v));
                         }
                     }
-                    return ControlFlow::Return(None);
+                    if let ControlFlow::Return(v) = outcome? {
+                        return Ok(ControlFlow::Return(v));
+                    }
+                }
+                Stmt::Throw(expr) => {
+                    let error_msg = match self.eval_in_frame(expr, frame)? {
+                        Value::Error(e) => e.format_with_trace(),
+                        val => self.create_error(ErrorKind::RuntimeError, to_string(&val)).format_with_trace(),
+                    };
+                    return Err(self.error_with_location(error_msg));
                 }
-                Stmt::AddRoute { .. } => {
+                Stmt::Log {
+                    to_file,
+                    rotate_size,
+                } => self.set_log_file(to_file.as_ref(), rotate_size.as_ref(), |e| self.eval_in_frame(e, frame))?,
+                Stmt::LogMessage { level, message } => self.log(*level, &self.eval_in_frame(message, frame)?)?,
+                Stmt::AddRoute { .. } | Stmt::Route { .. } => {
                     // Web server routes cannot be defined inside functions
                     // They must be defined at module level
                     eprintln!("Warning: AddRoute statement inside function is not supported");
-                    return ControlFlow::Continue;
+                    return Ok(ControlFlow::Continue);
                 }
                 Stmt::AddMiddleware { .. } => {
                     // Middleware cannot be added inside functions
                     eprintln!("Warning: AddMiddleware statement inside function is not supported");
-                    return ControlFlow::Continue;
-                }
-                Stmt::StartServer => {
-                    // Start server cannot be called inside functions
-                    eprintln!("Warning: StartServer statement inside function is not supported");
-                    return ControlFlow::Continue;
-                }
-            }
-        }
-        ControlFlow::Continue
-    }
-
-    fn eval_in_frame(&self, e: &Expr, frame: &Frame) -> Result<Value> {
-        match e {
-            Expr::Ident(n) => {
-                if let Some(v) = frame.locals.get(n) {
-                    return Ok(v.clone());
+                    return Ok(ControlFlow::Continue);
                 }
-                for env in frame.captured.iter() {
-                    if let Some(v) = env.get(n) {
-                        return Ok(v.clone());
-                    }
+                Stmt::RateLimit { .. } => {
+                    bail!("{}: the server is set up at the top level of a program, not in a function", dump_stmt(stmt))
                 }
-                if let Some(v) = self.resolve_value(n) {
-                    return Ok(v);
+                #[cfg(feature = "web")]
+                Stmt::Respond(e) => {
+                    let response = self.negotiated_response(&self.eval_in_frame(e, frame)?)?;
+                    self.write_value(&Value::HttpResponse(response))?;
                 }
-                Ok(Value::Str(format!("<{}>", n)))
-            }
-            Expr::Str(_) | Expr::Num(_) | Expr::Bool(_) | Expr::Null => self.eval(e),
-            Expr::Plus(a, b) => {
-                let sa = self.eval_in_frame(a, frame)?;
-                let sb = self.eval_in_frame(b, frame)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => Ok(Value::Num(na + nb)),
-                    (x, y) => Ok(Value::Str(format!("{}{}", to_string(&x), to_string(&y)))),
-                }
-            }
-            Expr::Minus(a, b) => {
-                let sa = self.eval_in_frame(a, frame)?;
-                let sb = self.eval_in_frame(b, frame)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => Ok(Value::Num(na - nb)),
-                    _ => Err(anyhow!("Cannot subtract non-numeric values")),
-                }
-            }
-            Expr::Times(a, b) => {
-                let sa = self.eval_in_frame(a, frame)?;
-                let sb = self.eval_in_frame(b, frame)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => Ok(Value::Num(na * nb)),
-                    _ => Err(anyhow!("Cannot multiply non-numeric values")),
-                }
-            }
-            Expr::DividedBy(a, b) => {
-                let sa = self.eval_in_frame(a, frame)?;
-                let sb = self.eval_in_frame(b, frame)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => {
-                        if nb == 0.0 {
-                            Err(self.error_with_location("Division by zero"))
-                        } else {
-                            Ok(Value::Num(na / nb))
-                        }
-                    }
-                    _ => Err(anyhow!("Cannot divide non-numeric values")),
+                Stmt::EnableMetrics { .. } => {
+                    bail!("{}: the server is set up at the top level of a program, not in a function", dump_stmt(stmt))
                 }
-            }
-            Expr::And(a, b) => {
-                let la = self.truthy(&self.eval_in_frame(a, frame).unwrap_or(Value::Num(0.0)))?;
-                if !la {
-                    return Ok(Value::Num(0.0));
+                Stmt::ServeStaticFiles(_) => {
+                    bail!("{}: the server is set up at the top level of a program, not in a function", dump_stmt(stmt))
                 }
-                let lb = self.truthy(&self.eval_in_frame(b, frame).unwrap_or(Value::Num(0.0)))?;
-                Ok(if lb { Value::Num(1.0) } else { Value::Num(0.0) })
-            }
-            Expr::Or(a, b) => {
-                let la = self.truthy(&self.eval_in_frame(a, frame).unwrap_or(Value::Num(0.0)))?;
-                if la {
-                    return Ok(Value::Num(1.0));
+                Stmt::Evaluate(e) => {
+                    self.eval_in_frame(e, frame)?;
                 }
-                let lb = self.truthy(&self.eval_in_frame(b, frame).unwrap_or(Value::Num(0.0)))?;
-                Ok(if lb { Value::Num(1.0) } else { Value::Num(0.0) })
-            }
-            Expr::Not(a) => {
-                let la = self.truthy(&self.eval_in_frame(a, frame).unwrap_or(Value::Num(0.0)))?;
-                Ok(if !la {
-                    Value::Num(1.0)
-                } else {
-                    Value::Num(0.0)
-                })
-            }
-            Expr::Cmp(op, l, r) => {
-                let lv = self.eval_in_frame(l, frame)?;
-                let rv = self.eval_in_frame(r, frame)?;
-                let res = match op {
-                    CmpOp::Eq => to_string(&lv) == to_string(&rv),
-                    CmpOp::Ne => to_string(&lv) != to_string(&rv),
-                    CmpOp::Lt | CmpOp::Le | CmpOp::Gt | CmpOp::Ge => {
-                        let ln = match lv {
-                            Value::Num(n) => Some(n),
-                            _ => None,
-                        };
-                        let rn = match rv {
-                            Value::Num(n) => Some(n),
-                            _ => None,
-                        };
-                        if let (Some(a), Some(b)) = (ln, rn) {
-                            match op {
-                                CmpOp::Lt => a < b,
-                                CmpOp::Le => a <= b,
-                                CmpOp::Gt => a > b,
-                                CmpOp::Ge => a >= b,
-                                _ => unreachable!(),
-                            }
-                        } else {
-                            false
-                        }
-                    }
-                };
-                Ok(if res {
-                    Value::Num(1.0)
-                } else {
-                    Value::Num(0.0)
-                })
-            }
-            Expr::Call { name, args } => {
-                let argv = args
-                    .iter()
-                    .map(|e| self.eval_in_frame(e, frame))
-                    .collect::<Result<Vec<_>>>()?;
-                if name == "now" && argv.is_empty() {
-                    return Ok(Value::Str(iso_now()));
+                Stmt::StartServer => {
+                    // Start server cannot be called inside functions
+                    eprintln!("Warning: StartServer statement inside function is not supported");
+                    return Ok(ControlFlow::Continue);
+                }
+                Stmt::Line(_) => {}
+                Stmt::OnShutdown { .. } => {
+                    bail!("On shutdown: blocks are added at the top level of a program, not in a function")
+                }
+                Stmt::EventEmitter { name } => frame.set(name, Value::EventEmitter(EventEmitter::default())),
+                Stmt::On { emitter, event, body } => {
+                    // The handler sees the function's variables, as a closure does
+                    let handler = self.event_handler(body, &frame.env);
+                    emitter_of(&self.eval_in_frame(&Expr::Ident(emitter.clone()), frame)?)?.on(event, handler);
+                }
+                Stmt::DataBinding { var, .. } => {
+                    bail!("Bind {}: program variables are bound at the top level of a program, not in a function", var)
+                }
+                Stmt::Require { value, schema } => {
+                    self.require_match(&self.eval_in_frame(value, frame)?, &self.eval_in_frame(schema, frame)?)?
+                }
+                Stmt::Assert { deep_equal, left, right } => {
+                    self.check_assert(*deep_equal, &self.eval_in_frame(left, frame)?, &self.eval_in_frame(right, frame)?)?
+                }
+                Stmt::Emit { emitter, event, data } => {
+                    let data = self.eval_in_frame(data, frame)?;
+                    emitter_of(&self.eval_in_frame(&Expr::Ident(emitter.clone()), frame)?)?.emit(event, data)?;
+                }
+                Stmt::BeforeEachRoute { .. } | Stmt::AfterEachRoute { .. } => {
+                    bail!("{} blocks are added at the top level of a program, not in a function", dump_stmt(stmt))
+                }
+                Stmt::RouteGroup { .. } | Stmt::GroupMiddleware { .. } => {
+                    bail!("{} blocks are added at the top level of a program, not in a function", dump_stmt(stmt))
+                }
+                Stmt::Benchmark { name, body } => {
+                    let name = to_string(&self.eval_in_frame(name, frame)?);
+                    let start = Instant::now();
+                    let span = self.profiler.benchmark(&name);
+                    let cf = self.exec_block_with_frame(body, frame);
+                    drop(span);
+                    self.finish_benchmark(&name, start.elapsed())?;
+                    if let ControlFlow::Return(v) = cf? {
+                        return Ok(ControlFlow::Return(v));
+                    }
                 }
-                // Try resolve function in local frame hierarchy first
-                if let Some(Value::Func(f)) = frame.locals.get(name) {
-                    return self.call_func_value(f, &argv);
+                Stmt::Measure { name, store_in, body } => {
+                    let name = to_string(&self.eval_in_frame(name, frame)?);
+                    let start = Instant::now();
+                    let cf = self.exec_block_with_frame(body, frame);
+                    let ms = start.elapsed().as_secs_f64() * 1000.0;
+                    match store_in {
+                        Some(var) => {
+                            self.check_declared(var, &Value::Num(ms))?;
+                            frame.set(var, Value::Num(ms));
+                        }
+                        None => eprintln!("{} took {:.1}ms", name, ms),
+                    }
+                    if let ControlFlow::Return(v) = cf? {
+                        return Ok(ControlFlow::Return(v));
+                    }
                 }
-                for env in frame.captured.iter() {
-                    if let Some(Value::Func(f)) = env.get(name) {
-                        return self.call_func_value(f, &argv);
+                Stmt::Parallel { branches } => {
+                    // The copy of the Vm gets a copy of the function's variables too
+                    run_branches(branches.iter().map(|branch| {
+                        let vm = self.clone();
+                        let mut frame = frame.copy();
+                        move || vm.exec_block_with_frame(branch, &mut frame).map(|_| ())
+                    }))?;
+                }
+                Stmt::Retry {
+                    max_attempts,
+                    delay,
+                    backoff,
+                    jitter,
+                    body,
+                    on_failure,
+                } => {
+                    let (attempts, wait) = retry_plan(max_attempts, delay, |e| self.eval_in_frame(e, frame))?;
+                    let outcome = retry(attempts, wait, *backoff, *jitter, || self.exec_block_with_frame(body, frame));
+                    let cf = match (outcome, on_failure) {
+                        (Ok(cf), _) => cf,
+                        (Err(e), None) => return Err(e),
+                        (Err(e), Some(_)) if e.is::<MemoryLimitExceeded>() => return Err(e),
+                        (Err(e), Some((var, block))) => {
+                            if let Some(var) = var {
+                                frame.set(var, Value::Str(failure_text(&e)));
+                            }
+                            self.exec_block_with_frame(block, frame)?
+                        }
+                    };
+                    if let ControlFlow::Return(v) = cf {
+                        return Ok(ControlFlow::Return(v));
                     }
                 }
-                if let Some(Value::Func(f)) = self.globals.get(name) {
-                    return self.call_func_value(f, &argv);
+                #[cfg(feature = "web")]
+                Stmt::SendEvent { data, stream } => {
+                    let data = self.event_data(&self.eval_in_frame(data, frame)?)?;
+                    event_stream_of(&self.eval_in_frame(stream, frame)?)?.send(&data)?;
+                }
+                Stmt::SendMessage { message, socket } => {
+                    let message = self.eval_in_frame(message, frame)?;
+                    self.send_to(&message, &self.eval_in_frame(socket, frame)?)?;
+                }
+                Stmt::CloseStream(stream) => close_connection(&self.eval_in_frame(stream, frame)?)?,
+                #[cfg(not(feature = "web"))]
+                Stmt::TemplateDirectory(_) | Stmt::Respond(_) | Stmt::SendEvent { .. } => {
+                    return Err(web_disabled(dump_stmt(stmt)))
+                }
+                Stmt::Task { name, body } => {
+                    // The task gets a copy of the function's variables, as a branch does
+                    let vm = self.clone();
+                    let mut copy = frame.copy();
+                    let body = body.clone();
+                    let task = Task::spawn(name, move || match vm.exec_block_with_frame(&body, &mut copy)? {
+                        ControlFlow::Return(v) => Ok(v.unwrap_or(Value::Null)),
+                        ControlFlow::Continue => Ok(Value::Null),
+                    });
+                    frame.set(name, Value::Future(task));
+                }
+                Stmt::Background { name, body } => {
+                    let mut vm = self.clone();
+                    vm.in_background = true;
+                    let mut copy = frame.copy();
+                    let body = body.clone();
+                    let reported = name.is_none();
+                    let task = Task::spawn(name.as_deref().unwrap_or("background"), move || {
+                        let outcome = vm.exec_block_with_frame(&body, &mut copy);
+                        if let (true, Err(e)) = (reported, &outcome) {
+                            eprintln!("Run in background: {}", e);
+                        }
+                        match outcome? {
+                            ControlFlow::Return(v) => Ok(v.unwrap_or(Value::Null)),
+                            ControlFlow::Continue => Ok(Value::Null),
+                        }
+                    });
+                    if let Some(name) = name {
+                        frame.set(name, Value::Future(task));
+                    }
                 }
-                self.call_function(name, &argv)
-            }
-            Expr::ListLit(items) => {
-                let mut out = Vec::new();
-                for it in items {
-                    out.push(self.eval_in_frame(it, frame)?);
+                Stmt::AwaitAll { names } => {
+                    let tasks = self.tasks(names, |name| frame.get(name))?;
+                    await_all(names, &tasks, |var, v| frame.set(&var, v))?;
                 }
-                Ok(Value::List(out))
-            }
-            Expr::DictLit(pairs) => {
-                let mut map = HashMap::new();
-                for (k, ve) in pairs {
-                    map.insert(k.clone(), self.eval_in_frame(ve, frame)?);
+                Stmt::AwaitAny { names } => {
+                    let tasks = self.tasks(names, |name| frame.get(name))?;
+                    await_any(names, &tasks, |var, v| frame.set(&var, v))?;
                 }
-                Ok(Value::Dict(map))
+                Stmt::Checkpoint { name, state } => self.save_checkpoint(name, &self.eval_in_frame(state, frame)?)?,
+                Stmt::RestoreCheckpoint { name, into_var } => frame.set(into_var, self.restore_checkpoint(name)?),
             }
-            Expr::Index(base, index) => {
-                let base_val = self.eval_in_frame(base, frame)?;
-                let index_val = self.eval_in_frame(index, frame)?;
-
-                match (&base_val, &index_val) {
-                    (Value::List(items), Value::Num(n)) => {
-                        let idx = *n as i32;
-                        let len = items.len() as i32;
-                        let actual_idx = if idx < 0 { len + idx } else { idx };
+        }
+        Ok(ControlFlow::Continue)
+    }
+}
 
-                        if actual_idx < 0 || actual_idx >= len {
-                            return Err(anyhow!(
-                                "List index out of range: {} (list length: {})",
-                                idx,
-                                len
-                            ));
-                        }
-                        Ok(items[actual_idx as usize].clone())
-                    }
-                    (Value::Dict(map), Value::Str(key)) => map
-                        .get(key)
-                        .cloned()
-                        .ok_or_else(|| anyhow!("Key not found in dictionary: \"{}\"", key)),
-                    (Value::Str(s), Value::Num(n)) => {
-                        let idx = *n as i32;
-                        let chars: Vec<char> = s.chars().collect();
-                        let len = chars.len() as i32;
-                        let actual_idx = if idx < 0 { len + idx } else { idx };
+thread_local! {
+    /// Set while `compile_strict` runs, collecting the expressions that
+    /// `compile` could only turn into their text
+    static UNCOMPILED: std::cell::RefCell<Option<Vec<String>>> = const { std::cell::RefCell::new(None) };
+}
 
-                        if actual_idx < 0 || actual_idx >= len {
-                            return Err(anyhow!(
-                                "String index out of range: {} (string length: {})",
-                                idx,
-                                len
-                            ));
-                        }
-                        Ok(Value::Str(chars[actual_idx as usize].to_string()))
-                    }
-                    _ => Err(anyhow!("Cannot index {:?} with {:?}", base_val, index_val)),
-                }
-            }
-            // Phrasal built-in expressions
-            Expr::TotalOf(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_sum(&[val])
-            }
-            Expr::SmallestIn(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_min(&[val])
-            }
-            Expr::LargestIn(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_max(&[val])
-            }
-            Expr::AbsoluteValueOf(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_abs(&[val])
-            }
-            Expr::Round(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_round(&[val])
-            }
-            Expr::RoundDown(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_floor(&[val])
-            }
-            Expr::RoundUp(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_ceil(&[val])
-            }
-            Expr::MakeUppercase(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_uppercase(&[val])
+/// Like `compile`, but an expression it would otherwise only have pushed as
+/// its text is an error
+pub fn compile_strict(prog: &Program) -> Result<Vec<u8>> {
+    UNCOMPILED.with(|u| *u.borrow_mut() = Some(Vec::new()));
+    let bytes = compile(prog);
+    let uncompiled = UNCOMPILED.with(|u| u.borrow_mut().take()).unwrap_or_default();
+    match uncompiled.first() {
+        Some(first) => bail!(
+            "Cannot compile '{}'{}",
+            first,
+            match uncompiled.len() {
+                1 => String::new(),
+                n => format!(" and {} other expression(s)", n - 1),
             }
-            Expr::MakeLowercase(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_lowercase(&[val])
-            }
-            Expr::TrimSpaces(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_trim(&[val])
-            }
-            Expr::FirstIn(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_first(&[val])
-            }
-            Expr::LastIn(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_last(&[val])
-            }
-            Expr::ReverseOf(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_reverse(&[val])
-            }
-            Expr::CountOf(expr) => {
-                let val = self.eval_in_frame(expr, frame)?;
-                builtin_length(&[val])
+        ),
+        None => Ok(bytes),
+    }
+}
+
+pub fn compile(prog: &Program) -> Vec<u8> {
+    let mut out: Vec<String> = Vec::new();
+    for stmt in prog {
+        match stmt {
+            Stmt::Write(e) => {
+                compile_expr(e, &mut out);
+                out.push(Instruction::WriteTop.encode());
             }
-            Expr::JoinWith(a, b) => {
-                let va = self.eval_in_frame(a, frame)?;
-                let vb = self.eval_in_frame(b, frame)?;
-                builtin_join(&[va, vb])
+            Stmt::AskFor { var_name, expect } => {
+                out.push(Instruction::AskVar(var_name.clone(), *expect).encode());
             }
-            Expr::SplitBy(a, b) => {
-                let va = self.eval_in_frame(a, frame)?;
-                let vb = self.eval_in_frame(b, frame)?;
-                builtin_split(&[va, vb])
+            Stmt::Set { name, value } => {
+                compile_expr(value, &mut out);
+                out.push(Instruction::StoreVar(name.clone()).encode());
             }
-            Expr::Contains(item, collection) => {
-                let item_val = self.eval_in_frame(item, frame)?;
-                let coll_val = self.eval_in_frame(collection, frame)?;
-                builtin_contains(&[item_val, coll_val])
+            Stmt::IfBlock {
+                cond,
+                then_body,
+                otherwise_body,
+            } => {
+                // cond
+                compile_expr(cond, &mut out);
+                // placeholder for jump-if-false to else/end
+                let jmpf_index = out.len();
+                out.push(Instruction::JumpIfFalse(usize::MAX).encode());
+                // then body
+                for s in then_body {
+                    compile_stmt(s, &mut out);
+                }
+                // jump to end (skip else)
+                let jmp_end_index = out.len();
+                out.push(Instruction::Jump(usize::MAX).encode());
+                // patch JMPF to here (start of else)
+                let else_target = out.len();
+                patch_jump(&mut out[jmpf_index], else_target);
+                // else body if present
+                if let Some(eb) = otherwise_body {
+                    for s in eb {
+                        compile_stmt(s, &mut out);
+                    }
+                }
+                // patch end jump to here
+                let end_target = out.len();
+                patch_jump(&mut out[jmp_end_index], end_target);
             }
-            Expr::Remove(item, list) => {
-                let item_val = self.eval_in_frame(item, frame)?;
-                let list_val = self.eval_in_frame(list, frame)?;
-                builtin_remove(&[item_val, list_val])
+            Stmt::WhileBlock { cond, body } => {
+                let loop_start = out.len();
+                compile_expr(cond, &mut out);
+                let jmpf_index = out.len();
+                out.push(Instruction::JumpIfFalse(usize::MAX).encode());
+                for s in body {
+                    compile_stmt(s, &mut out);
+                }
+                // jump back to start
+                out.push(Instruction::Jump(loop_start).encode());
+                // patch false to loop end
+                let loop_end = out.len();
+                patch_jump(&mut out[jmpf_index], loop_end);
             }
-            Expr::Append(item, list) => {
-                let item_val = self.eval_in_frame(item, frame)?;
-                let list_val = self.eval_in_frame(list, frame)?;
-                builtin_append(&[item_val, list_val])
+            // Repeat until c is While not c
+            Stmt::RepeatUntil { cond, body } => compile_stmt(&negated_while(cond, body), &mut out),
+            Stmt::RepeatBlock { count, body } => {
+                // Desugar: Repeat n times -> i = 0; while i < n { body; i = i + 1 }
+                // We'll compile as: PUSH_NUM 0; STORE_VAR __i; <start> LOAD_VAR __i; <count>; LT; JMPF end; body; LOAD_VAR __i; PUSH_NUM 1; ADD; STORE_VAR __i; JMP start; end:
+                out.push(Instruction::PushNum(0.0).encode());
+                out.push(Instruction::StoreVar("__i".to_string()).encode());
+                let start = out.len();
+                out.push(Instruction::LoadVar("__i".to_string()).encode());
+                compile_expr(count, &mut out);
+                out.push(Instruction::Lt.encode());
+                let jmpf = out.len();
+                out.push(Instruction::JumpIfFalse(usize::MAX).encode());
+                for s in body {
+                    compile_stmt(s, &mut out);
+                }
+                out.push(Instruction::LoadVar("__i".to_string()).encode());
+                out.push(Instruction::PushNum(1.0).encode());
+                out.push(Instruction::Add.encode());
+                out.push(Instruction::StoreVar("__i".to_string()).encode());
+                out.push(Instruction::Jump(start).encode());
+                let end = out.len();
+                patch_jump(&mut out[jmpf], end);
             }
-            Expr::InsertAt(item, index, list) => {
-                let item_val = self.eval_in_frame(item, frame)?;
-                let index_val = self.eval_in_frame(index, frame)?;
-                let list_val = self.eval_in_frame(list, frame)?;
-                builtin_insert_at(&[item_val, index_val, list_val])
+            _ => {}
+        }
+    }
+    let text = out.join("\n") + "\n";
+    text.into_bytes()
+}
+
+fn compile_expr(e: &Expr, out: &mut Vec<String>) {
+    match e {
+        Expr::Int(i) => out.push(Instruction::PushNum(*i as f64).encode()),
+        Expr::Num(n) => out.push(Instruction::PushNum(*n).encode()),
+        Expr::Str(s) => out.push(Instruction::PushStr(s.clone()).encode()),
+        Expr::Ident(n) => out.push(Instruction::LoadVar(n.clone()).encode()),
+        Expr::Plus(a, b) => {
+            compile_expr(a, out);
+            compile_expr(b, out);
+            out.push(Instruction::Add.encode());
+        }
+        Expr::Minus(a, b) => {
+            compile_expr(a, out);
+            compile_expr(b, out);
+            out.push(Instruction::Sub.encode());
+        }
+        Expr::Times(a, b) => {
+            compile_expr(a, out);
+            compile_expr(b, out);
+            out.push(Instruction::Mul.encode());
+        }
+        Expr::DividedBy(a, b) => {
+            compile_expr(a, out);
+            compile_expr(b, out);
+            out.push(Instruction::Div.encode());
+        }
+        Expr::Cmp(op, l, r) => {
+            compile_expr(l, out);
+            compile_expr(r, out);
+            match op {
+                CmpOp::Eq => out.push(Instruction::Eq.encode()),
+                CmpOp::Ne => out.push(Instruction::Ne.encode()),
+                CmpOp::Lt => out.push(Instruction::Lt.encode()),
+                CmpOp::Le => out.push(Instruction::Le.encode()),
+                CmpOp::Gt => out.push(Instruction::Gt.encode()),
+                CmpOp::Ge => out.push(Instruction::Ge.encode()),
             }
-            // File I/O operations - delegate to eval since they don't use local scope
-            Expr::ReadFile(_)
-            | Expr::WriteFile(_, _)
-            | Expr::AppendFile(_, _)
-            | Expr::FileExists(_)
-            | Expr::DeleteFile(_)
-            | Expr::CreateDir(_)
-            | Expr::ListDir(_)
-            | Expr::ReadLines(_)
-            | Expr::CopyFile(_, _)
-            | Expr::MoveFile(_, _)
-            // JSON operations - also delegate to eval
-            | Expr::ParseJson(_)
-            | Expr::ToJson(_)
-            | Expr::ToJsonPretty(_)
-            | Expr::GetPathParam(_)
-            | Expr::JsonGet(_, _)
-            | Expr::JsonSet(_, _, _)
-            | Expr::NewJsonObject
-            | Expr::NewJsonArray
-            | Expr::JsonPush(_, _)
-            | Expr::JsonLength(_)
-            // Error operations - also delegate to eval
-            | Expr::ErrorMessage(_)
-            | Expr::ErrorType(_)
-            | Expr::NewError { .. }
-            // Web server operations - also delegate to eval
-            | Expr::CreateWebServer(_)
-            | Expr::HtmlResponse(_)
-            | Expr::JsonResponse(_)
-            | Expr::JsonResponseStatus(_, _)
-            | Expr::RenderTemplate(_, _)
-            | Expr::ErrorResponse(_, _)
-            | Expr::RequestField(_, _) => self.eval(e),
+        }
+        Expr::And(a, b) => {
+            // Boolean AND producing 1 or 0
+            compile_expr(a, out);
+            let jmp_false = out.len();
+            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
+            compile_expr(b, out);
+            let jmp_false2 = out.len();
+            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
+            // both truthy -> 1
+            out.push(Instruction::PushNum(1.0).encode());
+            let jmp_end = out.len();
+            out.push(Instruction::Jump(usize::MAX).encode());
+            // patch falses to push 0
+            let falsy_tgt = out.len();
+            patch_jump(&mut out[jmp_false], falsy_tgt);
+            patch_jump(&mut out[jmp_false2], falsy_tgt);
+            out.push(Instruction::PushNum(0.0).encode());
+            let end = out.len();
+            patch_jump(&mut out[jmp_end], end);
+        }
+        Expr::Or(a, b) => {
+            // Boolean OR producing 1 or 0
+            compile_expr(a, out);
+            let jmp_false = out.len();
+            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
+            // a truthy -> 1
+            out.push(Instruction::PushNum(1.0).encode());
+            let jmp_end = out.len();
+            out.push(Instruction::Jump(usize::MAX).encode());
+            // a falsy -> evaluate b
+            let else_tgt = out.len();
+            patch_jump(&mut out[jmp_false], else_tgt);
+            compile_expr(b, out);
+            let jmp_false_b = out.len();
+            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
+            out.push(Instruction::PushNum(1.0).encode());
+            let jmp_end2 = out.len();
+            out.push(Instruction::Jump(usize::MAX).encode());
+            let falsy_tgt = out.len();
+            patch_jump(&mut out[jmp_false_b], falsy_tgt);
+            out.push(Instruction::PushNum(0.0).encode());
+            let end = out.len();
+            patch_jump(&mut out[jmp_end], end);
+            patch_jump(&mut out[jmp_end2], end);
+        }
+        Expr::Not(a) => {
+            // Not a -> if a is falsy push 1 else 0
+            compile_expr(a, out);
+            let jmp_false = out.len();
+            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
+            // a truthy
+            out.push(Instruction::PushNum(0.0).encode());
+            let jmp_end = out.len();
+            out.push(Instruction::Jump(usize::MAX).encode());
+            // a falsy
+            let t = out.len();
+            patch_jump(&mut out[jmp_false], t);
+            out.push(Instruction::PushNum(1.0).encode());
+            let end = out.len();
+            patch_jump(&mut out[jmp_end], end);
+        }
+        other => {
+            // Fallback to string dump for complex expressions for now
+            let text = dump_expr(other);
+            UNCOMPILED.with(|u| {
+                if let Some(uncompiled) = u.borrow_mut().as_mut() {
+                    uncompiled.push(text.clone());
+                }
+            });
+            out.push(Instruction::PushStr(text).encode());
         }
     }
+}
 
-    fn eval_in_scope(&self, e: &Expr, locals: &HashMap<String, Value>) -> Result<Value> {
-        match e {
-            Expr::Ident(n) => {
-                if let Some(v) = locals.get(n) {
-                    return Ok(v.clone());
-                }
-                if let Some(v) = self.resolve_value(n) {
-                    return Ok(v);
-                }
-                Ok(Value::Str(format!("<{}>", n)))
-            }
-            Expr::Str(_) | Expr::Num(_) | Expr::Bool(_) | Expr::Null => self.eval(e),
-            Expr::Plus(a, b) => {
-                let sa = self.eval_in_scope(a, locals)?;
-                let sb = self.eval_in_scope(b, locals)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => Ok(Value::Num(na + nb)),
-                    (x, y) => Ok(Value::Str(format!("{}{}", to_string(&x), to_string(&y)))),
-                }
-            }
-            Expr::Minus(a, b) => {
-                let sa = self.eval_in_scope(a, locals)?;
-                let sb = self.eval_in_scope(b, locals)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => Ok(Value::Num(na - nb)),
-                    _ => Err(anyhow!("Cannot subtract non-numeric values")),
-                }
-            }
-            Expr::Times(a, b) => {
-                let sa = self.eval_in_scope(a, locals)?;
-                let sb = self.eval_in_scope(b, locals)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => Ok(Value::Num(na * nb)),
-                    _ => Err(anyhow!("Cannot multiply non-numeric values")),
-                }
-            }
-            Expr::DividedBy(a, b) => {
-                let sa = self.eval_in_scope(a, locals)?;
-                let sb = self.eval_in_scope(b, locals)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => {
-                        if nb == 0.0 {
-                            Err(self.error_with_location("Division by zero"))
-                        } else {
-                            Ok(Value::Num(na / nb))
-                        }
-                    }
-                    _ => Err(anyhow!("Cannot divide non-numeric values")),
-                }
-            }
-            Expr::And(a, b) => {
-                let la = self.truthy(&self.eval_in_scope(a, locals).unwrap_or(Value::Num(0.0)))?;
-                if !la {
-                    return Ok(Value::Num(0.0));
-                }
-                let lb = self.truthy(&self.eval_in_scope(b, locals).unwrap_or(Value::Num(0.0)))?;
-                Ok(if lb { Value::Num(1.0) } else { Value::Num(0.0) })
-            }
-            Expr::Or(a, b) => {
-                let la = self.truthy(&self.eval_in_scope(a, locals).unwrap_or(Value::Num(0.0)))?;
-                if la {
-                    return Ok(Value::Num(1.0));
-                }
-                let lb = self.truthy(&self.eval_in_scope(b, locals).unwrap_or(Value::Num(0.0)))?;
-                Ok(if lb { Value::Num(1.0) } else { Value::Num(0.0) })
-            }
-            Expr::Not(a) => {
-                let la = self.truthy(&self.eval_in_scope(a, locals).unwrap_or(Value::Num(0.0)))?;
-                Ok(if !la {
-                    Value::Num(1.0)
-                } else {
-                    Value::Num(0.0)
-                })
-            }
-            Expr::Cmp(op, l, r) => {
-                let lv = self.eval_in_scope(l, locals)?;
-                let rv = self.eval_in_scope(r, locals)?;
-                let res = match op {
-                    CmpOp::Eq => to_string(&lv) == to_string(&rv),
-                    CmpOp::Ne => to_string(&lv) != to_string(&rv),
-                    CmpOp::Lt | CmpOp::Le | CmpOp::Gt | CmpOp::Ge => {
-                        let ln = match lv {
-                            Value::Num(n) => Some(n),
-                            _ => None,
-                        };
-                        let rn = match rv {
-                            Value::Num(n) => Some(n),
-                            _ => None,
-                        };
-                        if let (Some(a), Some(b)) = (ln, rn) {
-                            match op {
-                                CmpOp::Lt => a < b,
-                                CmpOp::Le => a <= b,
-                                CmpOp::Gt => a > b,
-                                CmpOp::Ge => a >= b,
-                                _ => unreachable!(),
-                            }
-                        } else {
-                            false
-                        }
-                    }
-                };
-                Ok(if res {
-                    Value::Num(1.0)
-                } else {
-                    Value::Num(0.0)
-                })
+/// `Repeat until cond` as the `While Not cond` it stands for
+fn negated_while(cond: &Expr, body: &Program) -> Stmt {
+    Stmt::WhileBlock {
+        cond: Expr::Not(Box::new(cond.clone())),
+        body: body.clone(),
+    }
+}
+
+fn compile_stmt(s: &Stmt, out: &mut Vec<String>) {
+    match s {
+        Stmt::Write(e) => {
+            compile_expr(e, out);
+            out.push(Instruction::WriteTop.encode());
+        }
+        Stmt::Set { name, value } => {
+            compile_expr(value, out);
+            out.push(Instruction::StoreVar(name.clone()).encode());
+        }
+        Stmt::AskFor { var_name, expect } => {
+            out.push(Instruction::AskVar(var_name.clone(), *expect).encode());
+        }
+        Stmt::IfBlock {
+            cond,
+            then_body,
+            otherwise_body,
+        } => {
+            // Recursively handled by compile() loop, but support here for nested calls
+            // cond
+            compile_expr(cond, out);
+            let jmpf_index = out.len();
+            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
+            for st in then_body.iter() {
+                compile_stmt(st, out);
             }
-            Expr::Call { name, args } => {
-                let argv = args
-                    .iter()
-                    .map(|e| self.eval_in_scope(e, locals))
-                    .collect::<Result<Vec<_>>>()?;
-                if name == "now" && argv.is_empty() {
-                    return Ok(Value::Str(iso_now()));
+            let jmp_end_index = out.len();
+            out.push(Instruction::Jump(usize::MAX).encode());
+            let else_target = out.len();
+            patch_jump(&mut out[jmpf_index], else_target);
+            if let Some(eb) = otherwise_body {
+                for st in eb.iter() {
+                    compile_stmt(st, out);
                 }
-                self.call_function(name, &argv)
             }
-            Expr::ListLit(items) => {
-                let mut out = Vec::new();
-                for it in items {
-                    out.push(self.eval_in_scope(it, locals)?);
-                }
-                Ok(Value::List(out))
+            let end_target = out.len();
+            patch_jump(&mut out[jmp_end_index], end_target);
+        }
+        Stmt::WhileBlock { cond, body } => {
+            let loop_start = out.len();
+            compile_expr(cond, out);
+            let jmpf_index = out.len();
+            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
+            for st in body.clone() {
+                compile_stmt(&st, out);
             }
-            Expr::DictLit(pairs) => {
-                let mut map = HashMap::new();
-                for (k, ve) in pairs {
-                    map.insert(k.clone(), self.eval_in_scope(ve, locals)?);
-                }
-                Ok(Value::Dict(map))
+            out.push(Instruction::Jump(loop_start).encode());
+            let loop_end = out.len();
+            patch_jump(&mut out[jmpf_index], loop_end);
+        }
+        Stmt::RepeatUntil { cond, body } => compile_stmt(&negated_while(cond, body), out),
+        Stmt::RepeatBlock { count, body } => {
+            // Fallback to top-level logic using dedicated comparisons
+            out.push(Instruction::PushNum(0.0).encode());
+            out.push(Instruction::StoreVar("__i".to_string()).encode());
+            let start = out.len();
+            out.push(Instruction::LoadVar("__i".to_string()).encode());
+            compile_expr(count, out);
+            out.push(Instruction::Lt.encode());
+            let jmpf = out.len();
+            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
+            for st in body {
+                compile_stmt(st, out);
             }
-            Expr::Index(base, index) => {
-                let base_val = self.eval_in_scope(base, locals)?;
-                let index_val = self.eval_in_scope(index, locals)?;
+            out.push(Instruction::LoadVar("__i".to_string()).encode());
+            out.push(Instruction::PushNum(1.0).encode());
+            out.push(Instruction::Add.encode());
+            out.push(Instruction::StoreVar("__i".to_string()).encode());
+            out.push(Instruction::Jump(start).encode());
+            let end = out.len();
+            patch_jump(&mut out[jmpf], end);
+        }
+        _ => {}
+    }
+}
 
-                match (&base_val, &index_val) {
-                    (Value::List(items), Value::Num(n)) => {
-                        let idx = *n as i32;
-                        let len = items.len() as i32;
-                        let actual_idx = if idx < 0 { len + idx } else { idx };
+fn patch_jump(encoded: &mut String, target: usize) {
+    // Replace the placeholder usize::MAX with real target in textual encoding
+    if encoded.starts_with("JMP\t") || encoded.starts_with("JMPF\t") {
+        let parts: Vec<&str> = encoded.splitn(2, '\t').collect();
+        *encoded = format!("{}\t{}", parts[0], target);
+    }
+}
 
-                        if actual_idx < 0 || actual_idx >= len {
-                            return Err(anyhow!(
-                                "List index out of range: {} (list length: {})",
-                                idx,
-                                len
-                            ));
-                        }
-                        Ok(items[actual_idx as usize].clone())
-                    }
-                    (Value::Dict(map), Value::Str(key)) => map
-                        .get(key)
-                        .cloned()
-                        .ok_or_else(|| anyhow!("Key not found in dictionary: \"{}\"", key)),
-                    (Value::Str(s), Value::Num(n)) => {
-                        let idx = *n as i32;
-                        let chars: Vec<char> = s.chars().collect();
-                        let len = chars.len() as i32;
-                        let actual_idx = if idx < 0 { len + idx } else { idx };
+// How tightly an expression binds when dumped, so `dump_expr` knows which
+// operands need parentheses to read back the same. These follow the
+// binding powers of the expression parser.
+const BIND_TOP: u8 = 0;
+const BIND_OR: u8 = 1;
+const BIND_AND: u8 = 2;
+const BIND_CMP: u8 = 3;
+const BIND_ADD: u8 = 4;
+const BIND_MUL: u8 = 5;
+const BIND_PHRASE: u8 = 6;
+const BIND_ATOM: u8 = 7;
 
-                        if actual_idx < 0 || actual_idx >= len {
-                            return Err(anyhow!(
-                                "String index out of range: {} (string length: {})",
-                                idx,
-                                len
-                            ));
-                        }
-                        Ok(Value::Str(chars[actual_idx as usize].to_string()))
-                    }
-                    _ => Err(anyhow!("Cannot index {:?} with {:?}", base_val, index_val)),
-                }
-            }
-            // Phrasal built-in expressions
-            Expr::TotalOf(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_sum(&[val])
-            }
-            Expr::SmallestIn(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_min(&[val])
-            }
-            Expr::LargestIn(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_max(&[val])
-            }
-            Expr::AbsoluteValueOf(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_abs(&[val])
-            }
-            Expr::Round(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_round(&[val])
-            }
-            Expr::RoundDown(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_floor(&[val])
-            }
-            Expr::RoundUp(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_ceil(&[val])
-            }
-            Expr::MakeUppercase(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_uppercase(&[val])
-            }
-            Expr::MakeLowercase(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_lowercase(&[val])
-            }
-            Expr::TrimSpaces(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_trim(&[val])
-            }
-            Expr::FirstIn(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_first(&[val])
-            }
-            Expr::LastIn(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_last(&[val])
-            }
-            Expr::ReverseOf(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_reverse(&[val])
-            }
-            Expr::CountOf(expr) => {
-                let val = self.eval_in_scope(expr, locals)?;
-                builtin_length(&[val])
-            }
-            Expr::JoinWith(a, b) => {
-                let va = self.eval_in_scope(a, locals)?;
-                let vb = self.eval_in_scope(b, locals)?;
-                builtin_join(&[va, vb])
-            }
-            Expr::SplitBy(a, b) => {
-                let va = self.eval_in_scope(a, locals)?;
-                let vb = self.eval_in_scope(b, locals)?;
-                builtin_split(&[va, vb])
-            }
-            Expr::Contains(item, collection) => {
-                let item_val = self.eval_in_scope(item, locals)?;
-                let coll_val = self.eval_in_scope(collection, locals)?;
-                builtin_contains(&[item_val, coll_val])
+fn binding(e: &Expr) -> u8 {
+    match e {
+        Expr::Or(..) => BIND_OR,
+        Expr::And(..) | Expr::Not(_) => BIND_AND,
+        Expr::Cmp(..) => BIND_CMP,
+        Expr::Plus(..) | Expr::Minus(..) => BIND_ADD,
+        Expr::Times(..) | Expr::DividedBy(..) => BIND_MUL,
+        // These run on to the end of the expression: a list takes every
+        // item after it, and the rest only come at the top
+        Expr::Conditional(..)
+        | Expr::ListLit(_)
+        | Expr::TupleLit(_)
+        | Expr::DictLit(_)
+        | Expr::Aggregate(..)
+        | Expr::Pretty(_)
+        | Expr::WithEtag(_)
+        | Expr::WithCache(..) => BIND_TOP,
+        Expr::SetLit(items) if !items.is_empty() => BIND_TOP,
+        Expr::Str(_)
+        | Expr::RegexLit(_)
+        | Expr::Int(_)
+        | Expr::Num(_)
+        | Expr::Local(_)
+        | Expr::Global(_)
+        | Expr::Bool(_)
+        | Expr::Null
+        | Expr::Ident(_)
+        | Expr::Call { .. }
+        | Expr::Index(..)
+        | Expr::SetLit(_)
+        | Expr::RequestField(..)
+        | Expr::ReadFromStdin
+        | Expr::MakeChannel
+        | Expr::GetRequestMethod
+        | Expr::GetRequestPath
+        | Expr::GetRemoteAddr
+        | Expr::PreferredResponseType
+        | Expr::NewJsonObject
+        | Expr::NewJsonArray => BIND_ATOM,
+        _ => BIND_PHRASE,
+    }
+}
+
+/// `e` dumped where the parser reads an operand binding at least `min`
+fn operand(e: &Expr, min: u8) -> String {
+    if binding(e) < min {
+        format!("({})", dump_expr(e))
+    } else {
+        dump_expr(e)
+    }
+}
+
+/// An operand a phrase's next keyword follows, read up to comparisons
+fn lead(e: &Expr) -> String {
+    operand(e, BIND_CMP)
+}
+
+/// The operand that ends a phrase, which stops at the first operator
+fn tail(e: &Expr) -> String {
+    operand(e, BIND_PHRASE)
+}
+
+/// `a, b and c`, as list items and `Use` arguments are written
+pub(crate) fn dump_items(items: &[Expr]) -> String {
+    let items: Vec<String> = items.iter().map(|e| operand(e, BIND_CMP)).collect();
+    match items.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+        _ => items.join(""),
+    }
+}
+
+/// PohLang source for `e`, parenthesised where needed so that parsing it
+/// gives `e` back
+pub(crate) fn dump_expr(e: &Expr) -> String {
+    let binary = |word: &str, a: &Expr, b: &Expr| {
+        let bind = binding(e);
+        format!("{} {} {}", operand(a, bind), word, operand(b, bind + 1))
+    };
+    match e {
+        Expr::Str(s) => quoted(s),
+        Expr::RegexLit(p) => format!("r\"{}\"", p),
+        Expr::Int(i) => i.to_string(),
+        // Always with a decimal point, so it reads back as a float
+        Expr::Num(n) if n.is_finite() && n.fract() == 0.0 => format!("{:.1}", n),
+        Expr::Num(n) => n.to_string(),
+        Expr::Local(slot) => format!("local#{}", slot),
+        Expr::Global(name) => name.clone(),
+        Expr::Bool(b) => {
+            if *b {
+                "True".to_string()
+            } else {
+                "False".to_string()
             }
-            Expr::Remove(item, list) => {
-                let item_val = self.eval_in_scope(item, locals)?;
-                let list_val = self.eval_in_scope(list, locals)?;
-                builtin_remove(&[item_val, list_val])
+        }
+        Expr::Null => "None".to_string(),
+        Expr::Ident(x) => x.clone(),
+        Expr::Plus(..) | Expr::Minus(..) | Expr::Times(..) | Expr::DividedBy(..) => {
+            let (left, terms) = e.arithmetic_chain();
+            let mut text = dump_expr(left);
+            let mut bind = binding(left);
+            for (op, right) in terms {
+                let word = match op {
+                    Expr::Plus(..) => "plus",
+                    Expr::Minus(..) => "minus",
+                    Expr::Times(..) => "times",
+                    _ => "divided by",
+                };
+                let own = binding(op);
+                if bind < own {
+                    text = format!("({})", text);
+                }
+                text = format!("{} {} {}", text, word, operand(right, own + 1));
+                bind = own;
             }
-            Expr::Append(item, list) => {
-                let item_val = self.eval_in_scope(item, locals)?;
-                let list_val = self.eval_in_scope(list, locals)?;
-                builtin_append(&[item_val, list_val])
+            text
+        }
+        Expr::And(a, b) => binary("And", a, b),
+        Expr::Or(a, b) => binary("Or", a, b),
+        Expr::Conditional(cond, a, b) => format!(
+            "{} if {} else {}",
+            operand(a, BIND_OR),
+            operand(cond, BIND_OR),
+            operand(b, BIND_OR)
+        ),
+        Expr::Not(a) => format!("Not {}", operand(a, BIND_CMP)),
+        Expr::Cmp(op, l, r) => {
+            let sym = match op {
+                CmpOp::Lt => "<",
+                CmpOp::Le => "<=",
+                CmpOp::Gt => ">",
+                CmpOp::Ge => ">=",
+                CmpOp::Eq => "=",
+                CmpOp::Ne => "!=",
+            };
+            // Both sides tighter, or `a < b < c` would read as a chain
+            format!("{} {} {}", operand(l, BIND_CMP + 1), sym, operand(r, BIND_CMP + 1))
+        }
+        Expr::Call { name, args } => format!(
+            "{}({})",
+            name,
+            args.iter().map(|a| operand(a, BIND_OR)).collect::<Vec<_>>().join(", ")
+        ),
+        Expr::ListLit(items) => format!("Make a list of {}", dump_items(items)).trim_end().to_string(),
+        Expr::TupleLit(items) => format!("tuple of {}", dump_items(items)),
+        Expr::DictLit(pairs) => {
+            let pairs: Vec<String> = pairs
+                .iter()
+                .map(|(k, v)| format!("{} as {}", quoted(k), operand(v, BIND_CMP)))
+                .collect();
+            format!("Make a dictionary with {}", pairs.join(", ")).trim_end().to_string()
+        }
+        Expr::Index(base, idx) => format!("{}[{}]", operand(base, BIND_ATOM), dump_expr(idx)),
+        Expr::Memoize(expr) => format!("memoize with {}", dump_expr(expr)),
+        Expr::TimedCall(name, args) if args.is_empty() => format!("time calling {}", name),
+        Expr::TimedCall(name, args) => format!("time calling {} with {}", name, dump_items(args)),
+        Expr::ApplyInParallel(name, items, None) => {
+            format!("apply {} to each item in {} in parallel", name, lead(items))
+        }
+        Expr::ApplyInParallel(name, items, Some(n)) => {
+            format!("apply {} to each item in {} in parallel with {} workers", name, lead(items), tail(n))
+        }
+        // Phrasal built-in expressions
+        Expr::TotalOf(expr) => format!("total of {}", tail(expr)),
+        Expr::SmallestIn(expr) => format!("smallest in {}", tail(expr)),
+        Expr::AverageOf(expr) => format!("average of {}", tail(expr)),
+        Expr::MedianOf(expr) => format!("median of {}", tail(expr)),
+        Expr::VarianceOf(expr) => format!("variance of {}", tail(expr)),
+        Expr::StdDevOf(expr) => format!("standard deviation of {}", tail(expr)),
+        Expr::LargestIn(expr) => format!("largest in {}", tail(expr)),
+        Expr::AbsoluteValueOf(expr) => format!("absolute value of {}", tail(expr)),
+        Expr::Round(expr) => format!("round {}", tail(expr)),
+        Expr::RoundDown(expr) => format!("round down {}", tail(expr)),
+        Expr::RoundUp(expr) => format!("round up {}", tail(expr)),
+        Expr::Lerp(a, b, t) => format!("interpolate from {} to {} at {}", lead(a), lead(b), tail(t)),
+        Expr::Clamp(x, low, high) => {
+            format!("clamp {} between {} and {}", lead(x), lead(low), tail(high))
+        }
+        Expr::MapRange(x, a, b, c, d) => format!(
+            "map {} from range {} to {} into range {} to {}",
+            lead(x),
+            lead(a),
+            lead(b),
+            lead(c),
+            tail(d)
+        ),
+        Expr::MakeUppercase(expr) => format!("make uppercase {}", tail(expr)),
+        Expr::MakeLowercase(expr) => format!("make lowercase {}", tail(expr)),
+        Expr::TrimSpaces(expr) => format!("trim spaces from {}", tail(expr)),
+        Expr::FirstIn(expr) => format!("first in {}", tail(expr)),
+        Expr::LastIn(expr) => format!("last in {}", tail(expr)),
+        Expr::ReverseOf(expr) => format!("reverse of {}", tail(expr)),
+        Expr::CountOf(expr) => format!("count of {}", tail(expr)),
+        Expr::TypeOf(expr) => format!("type of {}", tail(expr)),
+        Expr::WaitFor(expr) => format!("wait for {}", tail(expr)),
+        Expr::JoinWith(a, b) => format!("join {} with {}", lead(a), tail(b)),
+        Expr::SplitBy(a, b) => format!("split {} by {}", lead(a), tail(b)),
+        Expr::Contains(item, collection) => {
+            format!("contains {} in {}", lead(item), tail(collection))
+        }
+        Expr::Remove(item, list) => format!("remove {} from {}", lead(item), tail(list)),
+        Expr::Append(item, list) => format!("append {} to {}", lead(item), tail(list)),
+        Expr::InsertAt(item, index, list) => format!(
+            "insert {} at {} in {}",
+            lead(item),
+            lead(index),
+            tail(list)
+        ),
+        Expr::SetLit(items) if items.is_empty() => "empty set".to_string(),
+        Expr::SetLit(items) => format!("set containing {}", dump_items(items)),
+        Expr::SetUnion(a, b) => format!("union of {} and {}", lead(a), tail(b)),
+        Expr::SetIntersection(a, b) => format!("intersection of {} and {}", lead(a), tail(b)),
+        Expr::SetDifference(a, b) => format!("difference of {} and {}", lead(a), tail(b)),
+        Expr::SetContains(set, item) => format!("set {} contains {}", lead(set), tail(item)),
+        Expr::SetToList(a) => format!("list of {}", tail(a)),
+        Expr::ListToSet(a) => format!("set of {}", tail(a)),
+        // File I/O operations
+        Expr::ReadFile(path) => format!("read file at {}", tail(path)),
+        Expr::WriteFile(content, path) => {
+            format!("write {} into file at {}", lead(content), tail(path))
+        }
+        Expr::AppendFile(content, path) => {
+            format!("append {} into file at {}", lead(content), tail(path))
+        }
+        Expr::FileExists(path) => format!("file exists at {}", tail(path)),
+        Expr::DeleteFile(path) => format!("delete file at {}", tail(path)),
+        Expr::CreateDir(path) => format!("create directory at {}", tail(path)),
+        Expr::ListDir(path) => format!("list files in {}", tail(path)),
+        Expr::ReadLines(path) => format!("read lines from {}", tail(path)),
+        Expr::CopyFile(source, dest) => {
+            format!("copy file from {} to {}", lead(source), tail(dest))
+        }
+        Expr::MoveFile(source, dest) => {
+            format!("move file from {} to {}", lead(source), tail(dest))
+        }
+        // JSON operations
+        Expr::ParseJson(s) => format!("parse json from {}", tail(s)),
+        Expr::ParseUrl(s) => format!("parse url {}", tail(s)),
+        Expr::BuildUrl(d) => format!("build url from {}", tail(d)),
+        Expr::UrlEncode(s) => format!("url encode {}", tail(s)),
+        Expr::UrlDecode(s) => format!("url decode {}", tail(s)),
+        Expr::IndentBy(s, n) => format!("indent {} by {} spaces", lead(s), tail(n)),
+        Expr::Dedent(s) => format!("remove common indent from {}", tail(s)),
+        Expr::WrapText(s, n) => format!("wrap {} at {} characters", lead(s), tail(n)),
+        Expr::Pretty(v) => format!("{} nicely", dump_expr(v)),
+        Expr::Pick(v, keys) | Expr::Omit(v, keys) => {
+            let phrase = if matches!(e, Expr::Pick(..)) { "pick" } else { "omit" };
+            let keys: Vec<String> = keys.iter().map(|k| quoted(k)).collect();
+            format!("{} {} from {}", phrase, keys.join(", "), tail(v))
+        }
+        Expr::FindFirst(l, p) => format!("find first item in {} where {}", lead(l), tail(p)),
+        Expr::FindAll(l, p) => format!("find all items in {} where {}", lead(l), tail(p)),
+        Expr::FindIndex(l, p) => {
+            format!("find index of first item in {} where {}", lead(l), tail(p))
+        }
+        Expr::GroupConcat(f, l) => format!("collect field {} from each item in {}", lead(f), tail(l)),
+        // Several keys are parsed into a list literal
+        Expr::Pluck(f, l) => match &**f {
+            Expr::ListLit(keys) if keys.len() > 1 => {
+                format!("collect fields {} from each item in {}", dump_items(keys), tail(l))
+            }
+            f => format!("collect fields {} from each item in {}", lead(f), tail(l)),
+        },
+        Expr::Validate(v, schema) => format!("validate {} as {}", lead(v), schema),
+        Expr::Coerce(v, schema) => format!("coerce {} as {}", lead(v), schema),
+        Expr::ValidateAgainst(v, schema) => format!("validate {} against {}", lead(v), tail(schema)),
+        Expr::ReadFromStdin => "read from stdin".to_string(),
+        Expr::Aggregate(l, fields) => format!(
+            "aggregate list {} computing {}",
+            lead(l),
+            fields
+                .iter()
+                .map(|f| format!(
+                    "{} as {} of {}",
+                    quoted(&f.alias),
+                    f.function.words()[0],
+                    operand(&f.field, BIND_CMP)
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Expr::ToJson(v) => format!("convert to json {}", tail(v)),
+        Expr::ToJsonPretty(v) => format!("convert to pretty json {}", tail(v)),
+        Expr::GetPathParam(name) => format!("get path parameter {}", tail(name)),
+        Expr::GetRequestHeader(name) => format!("get request header {}", tail(name)),
+        Expr::SetResponseHeaderExpr(name, value) => {
+            format!("set response header {} to {}", lead(name), tail(value))
+        }
+        Expr::WithEtag(response) => format!("{} with etag", operand(response, BIND_OR)),
+        Expr::WithCache(response, secs) => {
+            format!("{} with cache for {} seconds", operand(response, BIND_OR), tail(secs))
+        }
+        Expr::GetRequestMethod => "request method".to_string(),
+        Expr::GetRequestPath => "request path".to_string(),
+        Expr::GetRemoteAddr => "client address".to_string(),
+        Expr::PreferredResponseType => "preferred response type of request".to_string(),
+        Expr::JsonGet(json, key) => format!("get {} from json {}", lead(key), tail(json)),
+        Expr::JsonQuery(json, path) => format!("query json {} with path {}", lead(json), tail(path)),
+        Expr::JsonSet(json, key, val) => {
+            format!("set {} in json {} to {}", lead(key), lead(json), tail(val))
+        }
+        Expr::NewJsonObject => "new json object".to_string(),
+        Expr::NewJsonArray => "new json array".to_string(),
+        Expr::JsonPush(json, item) => {
+            format!("push {} to json {}", lead(item), tail(json))
+        }
+        Expr::JsonLength(json) => format!("json length of {}", tail(json)),
+        // Error operations
+        Expr::ErrorMessage(e) => format!("error message of {}", tail(e)),
+        Expr::ErrorType(e) => format!("error type of {}", tail(e)),
+        Expr::NewError {
+            error_type,
+            message,
+        } => {
+            format!("error of type {} with message {}", quoted(error_type), tail(message))
+        }
+        // Web server operations
+        Expr::CreateWebServer {
+            port,
+            body_limit,
+            request_timeout,
+            tls,
+        } => {
+            let mut s = match tls {
+                Some((certificate, key)) => format!(
+                    "create secure web server on port {} with certificate {} and key {}",
+                    tail(port),
+                    tail(certificate),
+                    tail(key)
+                ),
+                None => format!("create web server on port {}", tail(port)),
+            };
+            if let Some(bytes) = body_limit {
+                s.push_str(&format!(" with body limit {} bytes", tail(bytes)));
             }
-            Expr::InsertAt(item, index, list) => {
-                let item_val = self.eval_in_scope(item, locals)?;
-                let index_val = self.eval_in_scope(index, locals)?;
-                let list_val = self.eval_in_scope(list, locals)?;
-                builtin_insert_at(&[item_val, index_val, list_val])
+            if let Some(secs) = request_timeout {
+                s.push_str(&format!(" with request timeout {} seconds", tail(secs)));
             }
-            // File I/O operations - delegate to eval since they don't use local scope
-            Expr::ReadFile(_)
-            | Expr::WriteFile(_, _)
-            | Expr::AppendFile(_, _)
-            | Expr::FileExists(_)
-            | Expr::DeleteFile(_)
-            | Expr::CreateDir(_)
-            | Expr::ListDir(_)
-            | Expr::ReadLines(_)
-            | Expr::CopyFile(_, _)
-            | Expr::MoveFile(_, _)
-            // JSON operations - also delegate to eval
-            | Expr::ParseJson(_)
-            | Expr::ToJson(_)
-            | Expr::ToJsonPretty(_)
-            | Expr::GetPathParam(_)
-            | Expr::JsonGet(_, _)
-            | Expr::JsonSet(_, _, _)
-            | Expr::NewJsonObject
-            | Expr::NewJsonArray
-            | Expr::JsonPush(_, _)
-            | Expr::JsonLength(_)
-            // Error operations - also delegate to eval
-            | Expr::ErrorMessage(_)
-            | Expr::ErrorType(_)
-            | Expr::NewError { .. }
-            // Web server operations - also delegate to eval
-            | Expr::CreateWebServer(_)
-            | Expr::HtmlResponse(_)
-            | Expr::JsonResponse(_)
-            | Expr::JsonResponseStatus(_, _)
-            | Expr::RenderTemplate(_, _)
-            | Expr::ErrorResponse(_, _)
-            | Expr::RequestField(_, _) => self.eval(e),
+            s
+        }
+        Expr::StartEventStream { heartbeat: None } => "start event stream".to_string(),
+        Expr::ReceiveMessage { socket, timeout: None } => format!("receive message from {}", tail(socket)),
+        Expr::ReceiveMessage { socket, timeout: Some(secs) } => {
+            format!("receive message from {} with timeout {} seconds", tail(socket), tail(secs))
+        }
+        Expr::MakeChannel => "make a channel".to_string(),
+        Expr::ReceiveFrom { channel, timeout: None } => format!("receive from {}", tail(channel)),
+        Expr::ReceiveFrom { channel, timeout: Some(secs) } => {
+            format!("receive from {} within {} seconds", tail(channel), tail(secs))
+        }
+        Expr::StartEventStream { heartbeat: Some(secs) } => {
+            format!("start event stream with heartbeat every {} seconds", tail(secs))
+        }
+        Expr::HtmlResponse(content) => format!("html response with {}", tail(content)),
+        Expr::JsonResponse(data) => format!("json response with {}", tail(data)),
+        Expr::JsonResponseStatus(data, status) => {
+            format!("json response with {} and status {}", tail(data), tail(status))
+        }
+        Expr::RenderTemplateFile(name, data) => {
+            format!("render template file {} with {}", lead(name), tail(data))
+        }
+        Expr::RenderTemplate(template, data) => {
+            format!("render template {} with {}", lead(template), tail(data))
+        }
+        Expr::ErrorResponse(status, message) => {
+            format!("error response with status {} and message {}", lead(status), tail(message))
+        }
+        Expr::RequestField(request, field) => {
+            format!("{}[{}]", operand(request, BIND_ATOM), dump_expr(field))
         }
     }
+}
 
-    fn eval_in_scope_with_capture(
-        &self,
-        e: &Expr,
-        locals: &HashMap<String, Value>,
-        captured: &Vec<HashMap<String, Value>>,
-    ) -> Result<Value> {
-        if captured.is_empty() {
-            return self.eval_in_scope(e, locals);
+/// One line of source-like text for `--trace`; blocks show only their header
+pub(crate) fn dump_stmt(s: &Stmt) -> String {
+    // Separated by commas, each value kept whole
+    let listed = |xs: &[Expr]| xs.iter().map(|x| operand(x, BIND_OR)).collect::<Vec<_>>().join(", ");
+    let params = |params: &[Param]| {
+        params
+            .iter()
+            .map(|p| match &p.default {
+                Some(d) => format!("{} set to {}", p.name, operand(d, BIND_OR)),
+                None => p.name.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let checkpoint = |name: &str| {
+        if name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            name.to_string()
+        } else {
+            quoted(name)
         }
-        match e {
-            Expr::Ident(n) => {
-                if let Some(v) = locals.get(n) {
-                    return Ok(v.clone());
-                }
-                for env in captured.iter() {
-                    if let Some(v) = env.get(n) {
-                        return Ok(v.clone());
-                    }
-                }
-                if let Some(v) = self.resolve_value(n) {
-                    return Ok(v);
-                }
-                Ok(Value::Str(format!("<{}>", n)))
-            }
-            Expr::Str(_) | Expr::Num(_) | Expr::Bool(_) | Expr::Null => self.eval(e),
-            Expr::Plus(a, b) => {
-                let sa = self.eval_in_scope_with_capture(a, locals, captured)?;
-                let sb = self.eval_in_scope_with_capture(b, locals, captured)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => Ok(Value::Num(na + nb)),
-                    (x, y) => Ok(Value::Str(format!("{}{}", to_string(&x), to_string(&y)))),
-                }
-            }
-            Expr::Minus(a, b) => {
-                let sa = self.eval_in_scope_with_capture(a, locals, captured)?;
-                let sb = self.eval_in_scope_with_capture(b, locals, captured)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => Ok(Value::Num(na - nb)),
-                    _ => Err(anyhow!("Cannot subtract non-numeric values")),
-                }
-            }
-            Expr::Times(a, b) => {
-                let sa = self.eval_in_scope_with_capture(a, locals, captured)?;
-                let sb = self.eval_in_scope_with_capture(b, locals, captured)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => Ok(Value::Num(na * nb)),
-                    _ => Err(anyhow!("Cannot multiply non-numeric values")),
-                }
-            }
-            Expr::DividedBy(a, b) => {
-                let sa = self.eval_in_scope_with_capture(a, locals, captured)?;
-                let sb = self.eval_in_scope_with_capture(b, locals, captured)?;
-                match (sa, sb) {
-                    (Value::Num(na), Value::Num(nb)) => {
-                        if nb == 0.0 {
-                            Err(self.error_with_location("Division by zero"))
-                        } else {
-                            Ok(Value::Num(na / nb))
-                        }
-                    }
-                    _ => Err(anyhow!("Cannot divide non-numeric values")),
-                }
+    };
+    match s {
+        Stmt::Write(Expr::WriteFile(content, path)) => {
+            format!("Write {} to file at {}", dump_expr(content), dump_expr(path))
+        }
+        Stmt::Write(Expr::AppendFile(content, path)) => {
+            format!("Append {} to file at {}", dump_expr(content), dump_expr(path))
+        }
+        Stmt::Write(Expr::DeleteFile(path)) => format!("Delete file at {}", dump_expr(path)),
+        Stmt::Write(e) => format!("Write {}", dump_expr(e)),
+        Stmt::MultiWrite { exprs, sep, end } => {
+            let mut text = format!("Write {}", listed(exprs));
+            if let Some(sep) = sep {
+                text.push_str(&format!(" separated by {}", dump_expr(sep)));
             }
-            Expr::And(a, b) => {
-                let la = self.truthy(
-                    &self
-                        .eval_in_scope_with_capture(a, locals, captured)
-                        .unwrap_or(Value::Num(0.0)),
-                )?;
-                if !la {
-                    return Ok(Value::Num(0.0));
-                }
-                let lb = self.truthy(
-                    &self
-                        .eval_in_scope_with_capture(b, locals, captured)
-                        .unwrap_or(Value::Num(0.0)),
-                )?;
-                Ok(if lb { Value::Num(1.0) } else { Value::Num(0.0) })
+            if let Some(end) = end {
+                text.push_str(&format!(" ending with {}", dump_expr(end)));
             }
-            Expr::Or(a, b) => {
-                let la = self.truthy(
-                    &self
-                        .eval_in_scope_with_capture(a, locals, captured)
-                        .unwrap_or(Value::Num(0.0)),
-                )?;
-                if la {
-                    return Ok(Value::Num(1.0));
-                }
-                let lb = self.truthy(
-                    &self
-                        .eval_in_scope_with_capture(b, locals, captured)
-                        .unwrap_or(Value::Num(0.0)),
-                )?;
-                Ok(if lb { Value::Num(1.0) } else { Value::Num(0.0) })
+            text
+        }
+        Stmt::AskFor {
+            var_name,
+            expect: AskType::Any,
+        } => format!("Ask for {}", var_name),
+        Stmt::AskFor { var_name, expect } => format!("Ask for {} as {}", var_name, expect.phrase()),
+        Stmt::IfInline {
+            cond,
+            then_write,
+            otherwise_write,
+        } => match otherwise_write {
+            Some(o) => format!(
+                "If {} Write {} Otherwise Write {}",
+                dump_expr(cond),
+                dump_expr(then_write),
+                dump_expr(o)
+            ),
+            None => format!("If {} Write {}", dump_expr(cond), dump_expr(then_write)),
+        },
+        Stmt::IfBlock { cond, .. } => format!("If {}", dump_expr(cond)),
+        Stmt::FuncInline { name, params: p, body } => {
+            format!("Make {} with {} Write {}", name, params(p), dump_expr(body))
+        }
+        Stmt::FuncBlock { name, params: p, .. } if p.is_empty() => format!("Make {}", name),
+        Stmt::FuncBlock { name, params: p, .. } => format!("Make {} with {}", name, params(p)),
+        Stmt::WhileBlock { cond, .. } => format!("While {}", dump_expr(cond)),
+        Stmt::RepeatUntil { cond, .. } => format!("Repeat until {}", dump_expr(cond)),
+        Stmt::RepeatBlock { count, .. } => format!("Repeat {} times", dump_expr(count)),
+        Stmt::ImportLocal { path } => format!("Import \"{}\"", path),
+        Stmt::ImportAs { path, alias } => format!("Import \"{}\" as {}", path, alias),
+        Stmt::ImportItems { path, items } => format!("Import {} from \"{}\"", items.join(", "), path),
+        Stmt::ImportSystem { name, alias, exposing } => {
+            let mut text = format!("Import system \"{}\"", name);
+            if let Some(alias) = alias {
+                text.push_str(&format!(" as {}", alias));
+            }
+            if !exposing.is_empty() {
+                text.push_str(&format!(" exposing {}", exposing.join(", ")));
+            }
+            text
+        }
+        Stmt::Use { name, args, result_var } => {
+            let call = if args.is_empty() { name.clone() } else { format!("{} with {}", name, dump_items(args)) };
+            match result_var {
+                Some(var) => format!("Use {} storing result in {}", call, var),
+                None => format!("Use {}", call),
             }
-            Expr::Not(a) => {
-                let la = self.truthy(
-                    &self
-                        .eval_in_scope_with_capture(a, locals, captured)
-                        .unwrap_or(Value::Num(0.0)),
-                )?;
-                Ok(if !la {
-                    Value::Num(1.0)
-                } else {
-                    Value::Num(0.0)
-                })
+        }
+        Stmt::Set { name, value } => format!("Set {} to {}", name, dump_expr(value)),
+        Stmt::GlobalConst { name, value } => format!("Set constant {} to {}", name, dump_expr(value)),
+        Stmt::SetLocal { slot, value } => format!("Set local#{} to {}", slot, dump_expr(value)),
+        Stmt::SetItem { name, key, value } => {
+            format!("Set {}[{}] to {}", name, dump_expr(key), dump_expr(value))
+        }
+        Stmt::Destructure { names, value } => {
+            format!("Set {} to {}", names.join(", "), dump_expr(value))
+        }
+        Stmt::PutEnvInScope { prefix, strip_prefix } => format!(
+            "Load env vars starting with {}{}",
+            quoted(prefix),
+            if *strip_prefix { " stripping prefix" } else { "" }
+        ),
+        Stmt::PrintTable { headers, rows } => format!(
+            "Print table with headers {} and rows {}",
+            dump_expr(headers),
+            dump_expr(rows)
+        ),
+        Stmt::Return(Some(e)) => format!("Return {}", dump_expr(e)),
+        Stmt::Return(None) => "Return".to_string(),
+        Stmt::TryCatch { .. } => "Try this:".to_string(),
+        Stmt::Throw(e) => format!("Throw {}", dump_expr(e)),
+        Stmt::Log {
+            to_file: Some(path),
+            rotate_size,
+        } => match rotate_size {
+            Some(size) => format!("Log to file {} rotating at {}", dump_expr(path), dump_expr(size)),
+            None => format!("Log to file {}", dump_expr(path)),
+        },
+        Stmt::Log { to_file: None, .. } => "Log to stderr".to_string(),
+        Stmt::LogMessage { level, message } => {
+            format!("Log {} {}", level.label().to_lowercase(), dump_expr(message))
+        }
+        Stmt::AddRoute { path, method: Expr::Str(m), .. } if m == crate::parser::ast::WEBSOCKET_METHOD => {
+            format!("Add websocket route {} to server:", dump_expr(path))
+        }
+        Stmt::AddRoute { path, method, .. } => format!(
+            "Add route {} with method {} to server:",
+            lead(path),
+            dump_expr(method)
+        ),
+        Stmt::Route { method, path, server, .. } => match server {
+            Some(server) => format!("Route {} {} on server {}:", method, quoted(path), server),
+            None => format!("Route {} {}:", method, quoted(path)),
+        },
+        Stmt::AddMiddleware {
+            middleware_type,
+            config,
+        } => {
+            let mut text = format!("Add middleware {}", quoted(middleware_type));
+            if !config.is_empty() {
+                let pairs: Vec<String> = config.iter().map(|(k, v)| format!("{} {}", k, lead(v))).collect();
+                text.push_str(&format!(" with {}", pairs.join(" and ")));
             }
-            Expr::Cmp(op, l, r) => {
-                let lv = self.eval_in_scope_with_capture(l, locals, captured)?;
-                let rv = self.eval_in_scope_with_capture(r, locals, captured)?;
-                let res = match op {
-                    CmpOp::Eq => to_string(&lv) == to_string(&rv),
-                    CmpOp::Ne => to_string(&lv) != to_string(&rv),
-                    CmpOp::Lt | CmpOp::Le | CmpOp::Gt | CmpOp::Ge => {
-                        let ln = match lv {
-                            Value::Num(n) => Some(n),
-                            _ => None,
-                        };
-                        let rn = match rv {
-                            Value::Num(n) => Some(n),
-                            _ => None,
-                        };
-                        if let (Some(a), Some(b)) = (ln, rn) {
-                            match op {
-                                CmpOp::Lt => a < b,
-                                CmpOp::Le => a <= b,
-                                CmpOp::Gt => a > b,
-                                CmpOp::Ge => a >= b,
-                                _ => unreachable!(),
-                            }
-                        } else {
-                            false
-                        }
-                    }
-                };
-                Ok(if res {
-                    Value::Num(1.0)
-                } else {
-                    Value::Num(0.0)
+            text.push_str(" to server");
+            text
+        }
+        Stmt::RateLimit {
+            max_requests,
+            per_seconds,
+            key_by,
+        } => {
+            let mut text = format!(
+                "Limit to {} requests per {} seconds",
+                dump_expr(max_requests),
+                dump_expr(per_seconds)
+            );
+            if let Some(k) = key_by {
+                text.push_str(&format!(" grouped by {}", dump_expr(k)));
+            }
+            text
+        }
+        Stmt::Respond(e) => format!("Respond with {}", dump_expr(e)),
+        Stmt::EnableMetrics { path, .. } => format!("Enable metrics on server at {}", dump_expr(path)),
+        Stmt::ServeStaticFiles(dir) => format!("Serve static files from {}", dump_expr(dir)),
+        Stmt::TemplateDirectory(dir) => format!("Set template directory to {}", dump_expr(dir)),
+        Stmt::StartServer => "Start server".to_string(),
+        Stmt::Evaluate(e) => dump_expr(e),
+        Stmt::OnShutdown { .. } => "On shutdown:".to_string(),
+        Stmt::EventEmitter { name } => format!("Create event emitter {}", name),
+        Stmt::Require { value, schema } => format!("require {} to match {}", dump_expr(value), dump_expr(schema)),
+        Stmt::Assert { deep_equal, left, right } => format!(
+            "Assert {} {}equals {}",
+            dump_expr(left),
+            if *deep_equal { "deep " } else { "" },
+            dump_expr(right)
+        ),
+        Stmt::ReadLineLoop { var_name, .. } => format!("For each line in stdin as {}:", var_name),
+        Stmt::ForEach { var_name, items, .. } => format!("For each {} in {}:", var_name, dump_expr(items)),
+        Stmt::On { emitter, event, .. } => format!("On event \"{}\" from emitter {}:", event, emitter),
+        Stmt::DataBinding { var, to, .. } => format!("Bind {} to {} and on change run:", var, dump_expr(to)),
+        Stmt::Emit { emitter, event, data } => {
+            format!("Emit \"{}\" from {} with data {}", event, emitter, dump_expr(data))
+        }
+        Stmt::BeforeEachRoute { .. } => "Before each route:".to_string(),
+        Stmt::RouteGroup { prefix, .. } => format!("Add route group {} to server:", dump_expr(prefix)),
+        Stmt::GroupMiddleware { .. } => "Add middleware to group:".to_string(),
+        Stmt::AfterEachRoute { .. } => "After each route:".to_string(),
+        Stmt::Parallel { .. } => "Parallel:".to_string(),
+        Stmt::SendEvent { data, stream } => format!("Send event {} to {}", dump_expr(data), dump_expr(stream)),
+        Stmt::SendMessage { message, socket } => format!("Send {} to {}", dump_expr(message), dump_expr(socket)),
+        Stmt::CloseStream(stream) => format!("Close {}", dump_expr(stream)),
+        Stmt::Task { name, .. } => format!("Start task named {}:", name),
+        Stmt::Background { name: None, .. } => "Run in background:".to_string(),
+        Stmt::Background { name: Some(name), .. } => format!("Run in background as {}:", name),
+        Stmt::AwaitAll { names } => format!("Wait for all tasks {}", names.join(", ")),
+        Stmt::AwaitAny { names } => format!("Wait for any task {}", names.join(" or ")),
+        Stmt::Checkpoint { name, state } => {
+            format!("Checkpoint {} with state {}", checkpoint(name), dump_expr(state))
+        }
+        Stmt::RestoreCheckpoint { name, into_var } => {
+            format!("Restore checkpoint {} into variable {}", checkpoint(name), into_var)
+        }
+        Stmt::TypeAlias { name, base_type } => format!("Type {} is {}", name, base_type),
+        Stmt::Schema { name, fields } => format!(
+            "Define schema {} with {}",
+            name,
+            fields
+                .iter()
+                .map(|f| match &f.default {
+                    Some(d) => format!("{} as {} defaulting to {}", f.name, f.type_name, dump_expr(d)),
+                    None if f.required => format!("{} as required {}", f.name, f.type_name),
+                    None => format!("{} as optional {}", f.name, f.type_name),
                 })
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Stmt::DeclareType { name, type_name } => format!("Declare {} as {}", name, type_name),
+        Stmt::Benchmark { name, .. } => format!("Benchmark {}:", dump_expr(name)),
+        Stmt::Measure { name, store_in, .. } => match store_in {
+            Some(var) => format!("Measure time of {} storing result in {}:", dump_expr(name), var),
+            None => format!("Measure time of {}:", dump_expr(name)),
+        },
+        Stmt::Retry {
+            max_attempts,
+            delay,
+            backoff,
+            jitter,
+            ..
+        } => format!(
+            "Retry {} times with delay {} seconds{}{}:",
+            dump_expr(max_attempts),
+            dump_expr(delay),
+            if *backoff { " with backoff" } else { "" },
+            if *jitter { " with jitter" } else { "" }
+        ),
+        Stmt::Line(n) => format!("(line {})", n),
+    }
+}
+
+/// The program as PohLang again, one statement a line and blocks indented,
+/// for `--emit normalized`. Shorthands come out in the form they were
+/// desugared to, and the text parses back to the same program.
+pub fn dump_program(prog: &Program) -> String {
+    let mut out = String::from("Start Program\n");
+    dump_block(prog, 1, &mut out);
+    out.push_str("End Program\n");
+    out
+}
+
+fn dump_block(body: &[Stmt], depth: usize, out: &mut String) {
+    let line = |out: &mut String, depth: usize, text: &str| {
+        out.push_str(&"    ".repeat(depth));
+        out.push_str(text);
+        out.push('\n');
+    };
+    let mut stmts = body.iter().filter(|s| !matches!(s, Stmt::Line(_))).peekable();
+    while let Some(s) = stmts.next() {
+        // `Set age as Age to 30` is parsed as the declaration and then the Set
+        if let (Stmt::DeclareType { name, type_name }, Some(Stmt::Set { name: set, value })) = (s, stmts.peek()) {
+            if set == name {
+                line(out, depth, &format!("Set {} as {} to {}", name, type_name, dump_expr(value)));
+                stmts.next();
+                continue;
             }
-            Expr::Call { name, args } => {
-                let argv = args
-                    .iter()
-                    .map(|e| self.eval_in_scope_with_capture(e, locals, captured))
-                    .collect::<Result<Vec<_>>>()?;
-                if name == "now" && argv.is_empty() {
-                    return Ok(Value::Str(iso_now()));
+        }
+        line(out, depth, &dump_stmt(s));
+        let inner = depth + 1;
+        let closing = match s {
+            Stmt::IfBlock {
+                then_body,
+                otherwise_body,
+                ..
+            } => {
+                dump_block(then_body, inner, out);
+                if let Some(otherwise) = otherwise_body {
+                    line(out, depth, "Otherwise");
+                    dump_block(otherwise, inner, out);
+                }
+                "End"
+            }
+            Stmt::FuncBlock { body, .. }
+            | Stmt::WhileBlock { body, .. }
+            | Stmt::RepeatUntil { body, .. }
+            | Stmt::RepeatBlock { body, .. } => {
+                dump_block(body, inner, out);
+                "End"
+            }
+            Stmt::TryCatch {
+                try_block,
+                catch_handlers,
+                finally_block,
+            } => {
+                dump_block(try_block, inner, out);
+                for handler in catch_handlers {
+                    let mut header = String::from("if error");
+                    if let Some(t) = &handler.error_type {
+                        header.push_str(&format!(" of type {}", quoted(t)));
+                    }
+                    if let Some(v) = &handler.var_name {
+                        header.push_str(&format!(" as {}", v));
+                    }
+                    line(out, depth, &header);
+                    dump_block(&handler.block, inner, out);
                 }
-                self.call_function(name, &argv)
-            }
-            Expr::ListLit(items) => {
-                let mut out = Vec::new();
-                for it in items {
-                    out.push(self.eval_in_scope_with_capture(it, locals, captured)?);
+                if let Some(finally) = finally_block {
+                    line(out, depth, "finally:");
+                    dump_block(finally, inner, out);
                 }
-                Ok(Value::List(out))
+                "end try"
             }
-            Expr::DictLit(pairs) => {
-                let mut map = HashMap::new();
-                for (k, ve) in pairs {
-                    map.insert(
-                        k.clone(),
-                        self.eval_in_scope_with_capture(ve, locals, captured)?,
-                    );
+            Stmt::Parallel { branches } => {
+                for branch in branches {
+                    line(out, inner, "Branch:");
+                    dump_block(branch, inner + 1, out);
                 }
-                Ok(Value::Dict(map))
+                "End Parallel"
             }
-            Expr::Index(base, index) => {
-                let base_val = self.eval_in_scope_with_capture(base, locals, captured)?;
-                let index_val = self.eval_in_scope_with_capture(index, locals, captured)?;
-
-                match (&base_val, &index_val) {
-                    (Value::List(items), Value::Num(n)) => {
-                        let idx = *n as i32;
-                        let len = items.len() as i32;
-                        let actual_idx = if idx < 0 { len + idx } else { idx };
-
-                        if actual_idx < 0 || actual_idx >= len {
-                            return Err(anyhow!(
-                                "List index out of range: {} (list length: {})",
-                                idx,
-                                len
-                            ));
-                        }
-                        Ok(items[actual_idx as usize].clone())
+            Stmt::Retry { body, on_failure, .. } => {
+                dump_block(body, inner, out);
+                match on_failure {
+                    Some((Some(var), failed)) => {
+                        line(out, depth, &format!("On all failures as {}:", var));
+                        dump_block(failed, inner, out);
                     }
-                    (Value::Dict(map), Value::Str(key)) => map
-                        .get(key)
-                        .cloned()
-                        .ok_or_else(|| anyhow!("Key not found in dictionary: \"{}\"", key)),
-                    (Value::Str(s), Value::Num(n)) => {
-                        let idx = *n as i32;
-                        let chars: Vec<char> = s.chars().collect();
-                        let len = chars.len() as i32;
-                        let actual_idx = if idx < 0 { len + idx } else { idx };
-
-                        if actual_idx < 0 || actual_idx >= len {
-                            return Err(anyhow!(
-                                "String index out of range: {} (string length: {})",
-                                idx,
-                                len
-                            ));
-                        }
-                        Ok(Value::Str(chars[actual_idx as usize].to_string()))
+                    Some((None, failed)) => {
+                        line(out, depth, "On all failures:");
+                        dump_block(failed, inner, out);
                     }
-                    _ => Err(anyhow!("Cannot index {:?} with {:?}", base_val, index_val)),
+                    None => {}
                 }
+                "End Retry"
             }
-            // Phrasal built-in expressions
-            Expr::TotalOf(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_sum(&[val])
-            }
-            Expr::SmallestIn(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_min(&[val])
-            }
-            Expr::LargestIn(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_max(&[val])
-            }
-            Expr::AbsoluteValueOf(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_abs(&[val])
-            }
-            Expr::Round(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_round(&[val])
+            // The handler is whatever is indented under the route
+            Stmt::AddRoute { handler, .. } => {
+                dump_block(handler, inner, out);
+                continue;
             }
-            Expr::RoundDown(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_floor(&[val])
+            Stmt::Route { handler, .. } => {
+                dump_block(handler, inner, out);
+                "End Route"
             }
-            Expr::RoundUp(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_ceil(&[val])
+            Stmt::OnShutdown { body } => {
+                dump_block(body, inner, out);
+                "End On Shutdown"
             }
-            Expr::MakeUppercase(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_uppercase(&[val])
+            Stmt::ReadLineLoop { body, .. } | Stmt::ForEach { body, .. } => {
+                dump_block(body, inner, out);
+                "End For"
             }
-            Expr::MakeLowercase(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_lowercase(&[val])
+            Stmt::On { body, .. } => {
+                dump_block(body, inner, out);
+                "End On"
             }
-            Expr::TrimSpaces(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_trim(&[val])
+            Stmt::BeforeEachRoute { body } => {
+                dump_block(body, inner, out);
+                "End Before"
             }
-            Expr::FirstIn(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_first(&[val])
+            Stmt::AfterEachRoute { body } => {
+                dump_block(body, inner, out);
+                "End After"
             }
-            Expr::LastIn(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_last(&[val])
+            Stmt::RouteGroup { body, .. } => {
+                dump_block(body, inner, out);
+                "End group"
             }
-            Expr::ReverseOf(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_reverse(&[val])
+            Stmt::GroupMiddleware { body } => {
+                dump_block(body, inner, out);
+                "End middleware"
             }
-            Expr::CountOf(expr) => {
-                let val = self.eval_in_scope_with_capture(expr, locals, captured)?;
-                builtin_length(&[val])
+            Stmt::Task { body, .. } => {
+                dump_block(body, inner, out);
+                "End Task"
             }
-            Expr::JoinWith(a, b) => {
-                let va = self.eval_in_scope_with_capture(a, locals, captured)?;
-                let vb = self.eval_in_scope_with_capture(b, locals, captured)?;
-                builtin_join(&[va, vb])
+            Stmt::Background { body, .. } => {
+                dump_block(body, inner, out);
+                "End"
             }
-            Expr::SplitBy(a, b) => {
-                let va = self.eval_in_scope_with_capture(a, locals, captured)?;
-                let vb = self.eval_in_scope_with_capture(b, locals, captured)?;
-                builtin_split(&[va, vb])
+            Stmt::Benchmark { body, .. } => {
+                dump_block(body, inner, out);
+                "End Benchmark"
             }
-            Expr::Contains(item, collection) => {
-                let item_val = self.eval_in_scope_with_capture(item, locals, captured)?;
-                let coll_val = self.eval_in_scope_with_capture(collection, locals, captured)?;
-                builtin_contains(&[item_val, coll_val])
+            Stmt::Measure { body, .. } => {
+                dump_block(body, inner, out);
+                "End Measure"
             }
-            Expr::Remove(item, list) => {
-                let item_val = self.eval_in_scope_with_capture(item, locals, captured)?;
-                let list_val = self.eval_in_scope_with_capture(list, locals, captured)?;
-                builtin_remove(&[item_val, list_val])
+            Stmt::DataBinding { on_change, .. } => {
+                dump_block(on_change, inner, out);
+                "End Bind"
             }
-            Expr::Append(item, list) => {
-                let item_val = self.eval_in_scope_with_capture(item, locals, captured)?;
-                let list_val = self.eval_in_scope_with_capture(list, locals, captured)?;
-                builtin_append(&[item_val, list_val])
+            _ => continue,
+        };
+        line(out, depth, closing);
+    }
+}
+
+/// Text is quoted so it reads apart from numbers and names
+fn trace_value(v: &Value) -> String {
+    match v {
+        Value::Str(s) => format!("\"{}\"", s),
+        other => to_string(other),
+    }
+}
+
+fn iso_now() -> String {
+    // Avoid adding a chrono dependency; simple ISO-ish string
+    use std::time::{SystemTime, UNIX_EPOCH};
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}", now.as_secs())
+}
+
+/// The kind of a value, as error messages name it
+fn type_name(v: &Value) -> &'static str {
+    match v {
+        Value::Str(_) => "string",
+        Value::Int(_) | Value::Num(_) => "number",
+        Value::Bool(_) => "boolean",
+        Value::Null => "nothing",
+        Value::Func(_) => "function",
+        Value::List(_) | Value::LazyRange { .. } => "list",
+        Value::Tuple(_) => "tuple",
+        Value::Dict(_) => "dictionary",
+        Value::Set(_) => "set",
+        Value::Error(_) => "error",
+        #[cfg(feature = "web")]
+        Value::WebServer(_) => "web server",
+        #[cfg(feature = "web")]
+        Value::HttpRequest(_) => "request",
+        #[cfg(feature = "web")]
+        Value::HttpResponse(_) => "response",
+        #[cfg(feature = "web")]
+        Value::LiveReloadTracker(_) => "live reload tracker",
+        Value::Stack(_) => "stack",
+        Value::Queue(_) => "queue",
+        Value::PriorityQueue(_) => "priority queue",
+        Value::OrderedDict(_) => "ordered dictionary",
+        Value::Regex(_) => "pattern",
+        Value::Future(_) => "task",
+        Value::Channel(_) => "channel",
+        #[cfg(feature = "web")]
+        Value::EventStream(_) => "event stream",
+        #[cfg(feature = "web")]
+        Value::WebSocket(_) => "websocket",
+        Value::EventEmitter(_) => "event emitter",
+        Value::Observed(v, _) => type_name(v),
+    }
+}
+
+/// The emitter an `On event` or `Emit` names
+fn emitter_of(v: &Value) -> Result<&EventEmitter<Value>> {
+    match v {
+        Value::EventEmitter(e) => Ok(e),
+        other => bail!("Expected an event emitter from 'Create event emitter', got {}", described(other)),
+    }
+}
+
+/// The stream a `Send event` or `Close` is given
+#[cfg(feature = "web")]
+fn event_stream_of(v: &Value) -> Result<&EventStream> {
+    match v {
+        Value::EventStream(s) => Ok(s),
+        other => bail!("Expected an event stream from 'start event stream', got {}", described(other)),
+    }
+}
+
+/// The socket a `Send` or `receive message from` is given
+#[cfg(feature = "web")]
+fn websocket_of(v: &Value) -> Result<&WebSocket> {
+    match v {
+        Value::WebSocket(s) => Ok(s),
+        other => bail!("Expected the socket of a websocket route, got {}", described(other)),
+    }
+}
+
+/// `receive message from`: the message, or nothing once the timeout has
+/// passed or the client has closed the connection
+#[cfg(feature = "web")]
+fn receive_message(socket: &Value, timeout: Option<Value>) -> Result<Value> {
+    let timeout = match timeout {
+        Some(secs) => match number_of(&secs).map(Number::as_f64) {
+            Some(n) if n >= 0.0 && n.is_finite() => Some(Duration::from_secs_f64(n)),
+            _ => bail!("receive message: the timeout must be a number of seconds, 0 or more"),
+        },
+        None => None,
+    };
+    Ok(websocket_of(socket)?.receive(timeout)?.map_or(Value::Null, Value::Str))
+}
+
+/// `receive from`: the next value sent, or nothing once the timeout has
+/// passed or the channel is closed and empty
+fn receive_from(channel: &Value, timeout: Option<Value>) -> Result<Value> {
+    let timeout = match timeout {
+        Some(secs) => match number_of(&secs).map(Number::as_f64) {
+            Some(n) if n >= 0.0 && n.is_finite() => Some(Duration::from_secs_f64(n)),
+            _ => bail!("receive from: the timeout must be a number of seconds, 0 or more"),
+        },
+        None => None,
+    };
+    match channel {
+        Value::Channel(channel) => Ok(channel.receive(timeout).unwrap_or(Value::Null)),
+        other => Err(wrong_type(format!("Expected a channel from 'make a channel', got {}", described(other)))),
+    }
+}
+
+/// A copy of a value to send over a channel. Collection handles, which
+/// copies otherwise share, get contents of their own, so neither side can
+/// change what the other sees.
+fn sendable(v: &Value) -> Value {
+    match v {
+        Value::List(xs) => Value::List(xs.iter().map(sendable).collect()),
+        Value::Tuple(xs) => Value::Tuple(xs.iter().map(sendable).collect()),
+        Value::Dict(m) => Value::Dict(m.iter().map(|(k, v)| (k.clone(), sendable(v))).collect()),
+        Value::Stack(s) => Value::Stack(Arc::new(Mutex::new(s.lock().unwrap().iter().map(sendable).collect()))),
+        Value::Queue(q) => Value::Queue(Arc::new(Mutex::new(q.lock().unwrap().iter().map(sendable).collect()))),
+        Value::PriorityQueue(q) => Value::PriorityQueue(Arc::new(Mutex::new(q.lock().unwrap().map(sendable)))),
+        Value::OrderedDict(d) => Value::OrderedDict(Arc::new(Mutex::new(d.lock().unwrap().map(sendable)))),
+        Value::Observed(v, _) => sendable(v),
+        other => other.clone(),
+    }
+}
+
+/// `Close`: a channel, an event stream or a websocket
+fn close_connection(v: &Value) -> Result<()> {
+    match v {
+        Value::Channel(c) => c.close(),
+        #[cfg(feature = "web")]
+        Value::EventStream(s) => s.close(),
+        #[cfg(feature = "web")]
+        Value::WebSocket(s) => s.close(),
+        #[cfg(feature = "web")]
+        other => bail!("Expected a channel, an event stream or a websocket to close, got {}", described(other)),
+        #[cfg(not(feature = "web"))]
+        other => bail!("Expected a channel to close, got {}", described(other)),
+    }
+    Ok(())
+}
+
+/// A route's path inside a route group with this prefix; `/` is the
+/// group's own path
+fn grouped_path(prefix: &str, path: &str) -> String {
+    match path {
+        _ if prefix.is_empty() => path.to_string(),
+        "/" | "" => prefix.to_string(),
+        _ if path.starts_with('/') => format!("{}{}", prefix, path),
+        _ => format!("{}/{}", prefix, path),
+    }
+}
+
+/// Run `Parallel` branches on threads of their own and wait for all of them
+fn run_branches<F>(branches: impl Iterator<Item = F>) -> Result<()>
+where
+    F: FnOnce() -> Result<()> + Send,
+{
+    let mut failed: Vec<(usize, anyhow::Error)> = std::thread::scope(|s| {
+        let running: Vec<_> = branches.map(|branch| s.spawn(branch)).collect();
+        running
+            .into_iter()
+            .enumerate()
+            .filter_map(|(n, branch)| match branch.join() {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some((n, e)),
+                Err(_) => Some((n, anyhow!("stopped by an internal error"))),
+            })
+            .collect()
+    });
+    // Running out of memory ends the program, as it would outside the block
+    if let Some(n) = failed.iter().position(|(_, e)| e.is::<MemoryLimitExceeded>()) {
+        return Err(failed.swap_remove(n).1);
+    }
+    if !failed.is_empty() {
+        let errors = failed.iter().map(|(n, e)| format!("Branch {}: {}", n + 1, e)).collect();
+        return Err(BranchErrors { errors, tasks: false }.into());
+    }
+    Ok(())
+}
+
+/// `Wait for all tasks`: each result is set as `<name>_result`; the tasks
+/// that failed fail the wait together
+fn await_all(names: &[String], tasks: &[Arc<Task<Value>>], mut set: impl FnMut(String, Value)) -> Result<()> {
+    let mut failed = Vec::new();
+    for (name, task) in names.iter().zip(tasks) {
+        match task.wait() {
+            Ok(v) => set(format!("{}_result", name), v),
+            Err(e) => failed.push((name.as_str(), e)),
+        }
+    }
+    if !failed.is_empty() {
+        return Err(tasks_failed(&failed));
+    }
+    Ok(())
+}
+
+/// `Wait for any task`: the result of whichever finishes first
+fn await_any(names: &[String], tasks: &[Arc<Task<Value>>], mut set: impl FnMut(String, Value)) -> Result<()> {
+    let (n, outcome) = task::first_finished(tasks);
+    match outcome {
+        Ok(v) => set(format!("{}_result", names[n]), v),
+        Err(e) => return Err(tasks_failed(&[(names[n].as_str(), e)])),
+    }
+    Ok(())
+}
+
+/// What a wait raises for the tasks that failed: a TaskError listing them,
+/// unless one ran out of memory, which ends the program as it would have
+/// outside a task
+fn tasks_failed(failed: &[(&str, Arc<anyhow::Error>)]) -> anyhow::Error {
+    if let Some(over) = failed.iter().find_map(|(_, e)| e.downcast_ref::<MemoryLimitExceeded>()) {
+        return (*over).into();
+    }
+    let errors = failed.iter().map(|(name, e)| format!("Task {}: {}", name, e)).collect();
+    BranchErrors { errors, tasks: true }.into()
+}
+
+/// `Retry ... times with delay ...`: the number of attempts and the first
+/// delay in seconds
+fn retry_plan(max_attempts: &Expr, delay: &Expr, eval: impl Fn(&Expr) -> Result<Value>) -> Result<(i64, f64)> {
+    let attempts = eval(max_attempts)?;
+    let attempts = match whole_number(&attempts) {
+        Some(n) if n > 0 => n,
+        _ => bail!(
+            "Retry ... times: the number of attempts must be a whole number above 0, got {}",
+            described(&attempts)
+        ),
+    };
+    let delay = eval(delay)?;
+    let wait = match number_of(&delay).map(Number::as_f64) {
+        Some(secs) if secs >= 0.0 => secs,
+        _ => bail!(
+            "Retry ... with delay: the delay must be a number of seconds, got {}",
+            described(&delay)
+        ),
+    };
+    Ok((attempts, wait))
+}
+
+/// Run a `Retry` body until it succeeds or the attempts run out, giving the
+/// last error. Running out of memory is not tried again.
+fn retry<T>(
+    attempts: i64,
+    mut wait: f64,
+    backoff: bool,
+    jitter: bool,
+    mut body: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let mut attempt = 1;
+    loop {
+        match body() {
+            Ok(v) => return Ok(v),
+            Err(e) if attempt == attempts || e.is::<MemoryLimitExceeded>() => return Err(e),
+            Err(_) => {}
+        }
+        let secs = if jitter { wait * jitter_factor() } else { wait };
+        std::thread::sleep(Duration::from_secs_f64(secs));
+        if backoff {
+            wait *= 2.0;
+        }
+        attempt += 1;
+    }
+}
+
+/// What `On all failures as e` binds: the message without its [TypeName]
+/// marker, as `if error as e` binds it
+fn failure_text(e: &anyhow::Error) -> String {
+    let msg = e.to_string();
+    match msg.find(']') {
+        Some(end) if msg.starts_with('[') => msg[end + 1..].trim().to_string(),
+        _ => msg,
+    }
+}
+
+/// A random factor between 0.5 and 1.5 for `Retry ... with jitter`
+fn jitter_factor() -> f64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    // Each RandomState is seeded afresh, which is all the randomness a delay needs
+    let bits = RandomState::new().build_hasher().finish();
+    0.5 + (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// What `time calling` gives: the call's result and how long it took
+fn timed_result(result: Value, start: Instant) -> Value {
+    Value::Dict(HashMap::from([
+        ("result".to_string(), result),
+        ("milliseconds".to_string(), Value::Num(start.elapsed().as_secs_f64() * 1000.0)),
+    ]))
+}
+
+/// A value and its kind for error messages, such as `abc (string)`
+fn described(v: &Value) -> String {
+    format!("{} ({})", to_string(v), type_name(v))
+}
+
+fn to_string(v: &Value) -> String {
+    match v {
+        Value::Str(s) => s.clone(),
+        Value::Int(i) => i.to_string(),
+        Value::Num(n) => number::format_float(*n),
+        Value::Bool(b) => {
+            if *b {
+                "True".to_string()
+            } else {
+                "False".to_string()
             }
-            Expr::InsertAt(item, index, list) => {
-                let item_val = self.eval_in_scope_with_capture(item, locals, captured)?;
-                let index_val = self.eval_in_scope_with_capture(index, locals, captured)?;
-                let list_val = self.eval_in_scope_with_capture(list, locals, captured)?;
-                builtin_insert_at(&[item_val, index_val, list_val])
+        }
+        Value::Null => "None".to_string(),
+        Value::Func(f) => format!("<function {}>", f.name),
+        Value::List(xs) => format!(
+            "[{}]",
+            xs.iter().map(to_string).collect::<Vec<_>>().join(", ")
+        ),
+        Value::Tuple(xs) => format!(
+            "({})",
+            xs.iter().map(to_string).collect::<Vec<_>>().join(", ")
+        ),
+        Value::LazyRange { .. } => to_string(&listed(v)),
+        Value::Dict(m) => {
+            // HashMap order changes from run to run, so print keys sorted
+            let mut entries: Vec<(&String, &Value)> = m.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let parts: Vec<String> = entries
+                .into_iter()
+                .map(|(k, v)| format!("\"{}\": {}", k, to_string(v)))
+                .collect();
+            format!("{{{}}}", parts.join(", "))
+        }
+        Value::Set(s) => format!(
+            "{{{}}}",
+            sorted_members(s).iter().map(to_string).collect::<Vec<_>>().join(", ")
+        ),
+        Value::Error(e) => {
+            // Natural format when printing error values
+            if matches!(e.kind, ErrorKind::Custom(_)) {
+                format!("{} occurred: {}", e.type_string(), e.message)
+            } else {
+                format!("Error occurred: {} - {}", e.type_description(), e.message)
             }
-            // File I/O operations - delegate to eval since they don't use local scope
-            Expr::ReadFile(_)
-            | Expr::WriteFile(_, _)
-            | Expr::AppendFile(_, _)
-            | Expr::FileExists(_)
-            | Expr::DeleteFile(_)
-            | Expr::CreateDir(_)
-            | Expr::ListDir(_)
-            | Expr::ReadLines(_)
-            | Expr::CopyFile(_, _)
-            | Expr::MoveFile(_, _)
-            // JSON operations - also delegate to eval
-            | Expr::ParseJson(_)
-            | Expr::ToJson(_)
-            | Expr::ToJsonPretty(_)
-            | Expr::GetPathParam(_)
-            | Expr::JsonGet(_, _)
-            | Expr::JsonSet(_, _, _)
-            | Expr::NewJsonObject
-            | Expr::NewJsonArray
-            | Expr::JsonPush(_, _)
-            | Expr::JsonLength(_)
-            // Error operations - also delegate to eval
-            | Expr::ErrorMessage(_)
-            | Expr::ErrorType(_)
-            | Expr::NewError { .. }
-            // Web server operations - also delegate to eval
-            | Expr::CreateWebServer(_)
-            | Expr::HtmlResponse(_)
-            | Expr::JsonResponse(_)
-            | Expr::JsonResponseStatus(_, _)
-            | Expr::RenderTemplate(_, _)
-            | Expr::ErrorResponse(_, _)
-            | Expr::RequestField(_, _) => self.eval(e),
         }
+        #[cfg(feature = "web")]
+        Value::WebServer(_) => "<WebServer>".to_string(),
+        #[cfg(feature = "web")]
+        Value::HttpRequest(_) => "<HttpRequest>".to_string(),
+        #[cfg(feature = "web")]
+        Value::HttpResponse(r) => format!("<HttpResponse status={}>", r.status),
+        #[cfg(feature = "web")]
+        Value::LiveReloadTracker(_) => "<LiveReloadTracker>".to_string(),
+        Value::Stack(_) | Value::Queue(_) | Value::PriorityQueue(_) => {
+            let kind = match v {
+                Value::Stack(_) => "Stack",
+                Value::Queue(_) => "Queue",
+                _ => "PriorityQueue",
+            };
+            let items = collection_items(v);
+            format!(
+                "{}[{}]",
+                kind,
+                items.iter().map(to_string).collect::<Vec<_>>().join(", ")
+            )
+        }
+        Value::OrderedDict(od) => {
+            let parts: Vec<String> = od
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| format!("\"{}\": {}", k, to_string(v)))
+                .collect();
+            format!("{{{}}}", parts.join(", "))
+        }
+        Value::Regex(r) => format!("r\"{}\"", r.as_str()),
+        Value::Future(t) => format!("<task {}>", t.name()),
+        Value::Channel(_) => "<channel>".to_string(),
+        #[cfg(feature = "web")]
+        Value::EventStream(s) => format!("<EventStream {}>", if s.is_open() { "open" } else { "closed" }),
+        #[cfg(feature = "web")]
+        Value::WebSocket(s) => format!("<WebSocket {}>", if s.is_open() { "open" } else { "closed" }),
+        Value::EventEmitter(_) => "<EventEmitter>".to_string(),
+        Value::Observed(v, _) => to_string(v),
+    }
+}
+
+/// `Write ... nicely`: lists, tuples and dictionaries with one entry per
+/// line, indented two spaces a level, dictionary keys sorted and text
+/// quoted. Stacks, queues and ordered dictionaries are shared handles, so
+/// one can hold itself; where it repeats inside itself it shows `<cycle>`.
+fn pretty(v: &Value) -> String {
+    let mut out = String::new();
+    pretty_into(v, 0, &mut Vec::new(), &mut out);
+    out
+}
+
+/// `open` holds the shared collections being printed around this value
+fn pretty_into(v: &Value, depth: usize, open: &mut Vec<usize>, out: &mut String) {
+    match v {
+        Value::Str(s) => out.push_str(&quoted(s)),
+        Value::List(xs) => {
+            let entries: Vec<_> = xs.iter().map(|x| (None, x)).collect();
+            pretty_entries(("[", "]"), &entries, depth, open, out);
+        }
+        Value::Tuple(xs) => {
+            let entries: Vec<_> = xs.iter().map(|x| (None, x)).collect();
+            pretty_entries(("(", ")"), &entries, depth, open, out);
+        }
+        Value::LazyRange { .. } => pretty_into(&listed(v), depth, open, out),
+        Value::Dict(m) => {
+            let mut entries: Vec<_> = m.iter().map(|(k, v)| (Some(k.as_str()), v)).collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            pretty_entries(("{", "}"), &entries, depth, open, out);
+        }
+        Value::Stack(_) | Value::Queue(_) | Value::PriorityQueue(_) | Value::OrderedDict(_) => {
+            let id = shared_id(v);
+            if open.contains(&id) {
+                out.push_str("<cycle>");
+                return;
+            }
+            open.push(id);
+            if let Value::OrderedDict(od) = v {
+                // Copied out first: the lock must not be held while nested values print
+                let items: Vec<(String, Value)> = od
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                let entries: Vec<_> = items.iter().map(|(k, v)| (Some(k.as_str()), v)).collect();
+                pretty_entries(("{", "}"), &entries, depth, open, out);
+            } else {
+                let kind = match v {
+                    Value::Stack(_) => "Stack[",
+                    Value::Queue(_) => "Queue[",
+                    _ => "PriorityQueue[",
+                };
+                let items = collection_items(v);
+                let entries: Vec<_> = items.iter().map(|x| (None, x)).collect();
+                pretty_entries((kind, "]"), &entries, depth, open, out);
+            }
+            open.pop();
+        }
+        other => out.push_str(&to_string(other)),
+    }
+}
+
+fn pretty_entries(
+    (start, end): (&str, &str),
+    entries: &[(Option<&str>, &Value)],
+    depth: usize,
+    open: &mut Vec<usize>,
+    out: &mut String,
+) {
+    out.push_str(start);
+    if entries.is_empty() {
+        out.push_str(end);
+        return;
+    }
+    for (i, (key, value)) in entries.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth + 1));
+        if let Some(key) = key {
+            out.push_str(&quoted(key));
+            out.push_str(": ");
+        }
+        pretty_into(value, depth + 1, open, out);
+    }
+    out.push('\n');
+    out.push_str(&"  ".repeat(depth));
+    out.push_str(end);
+}
+
+/// Address of the contents a shared collection handle points to
+fn shared_id(v: &Value) -> usize {
+    match v {
+        Value::Stack(a) => Arc::as_ptr(a) as *const () as usize,
+        Value::Queue(a) => Arc::as_ptr(a) as *const () as usize,
+        Value::PriorityQueue(a) => Arc::as_ptr(a) as *const () as usize,
+        Value::OrderedDict(a) => Arc::as_ptr(a) as *const () as usize,
+        _ => 0,
+    }
+}
+
+/// Text in double quotes, with quotes, backslashes and line breaks escaped
+fn quoted(s: &str) -> String {
+    let mut q = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => q.push_str("\\\""),
+            '\\' => q.push_str("\\\\"),
+            '\n' => q.push_str("\\n"),
+            '\t' => q.push_str("\\t"),
+            '\r' => q.push_str("\\r"),
+            c => q.push(c),
+        }
+    }
+    q.push('"');
+    q
+}
+
+fn to_num(v: Value) -> Result<f64> {
+    match v {
+        Value::Int(i) => Ok(i as f64),
+        Value::Num(n) => Ok(n),
+        Value::Str(s) => s
+            .parse::<f64>()
+            .map_err(|_| anyhow!("Cannot convert string to number: {}", s)),
+        Value::Bool(b) => Ok(if b { 1.0 } else { 0.0 }),
+        Value::Null => Ok(0.0),
+        Value::Error(e) => Err(anyhow!("Cannot convert error to number: {}", e.message)),
+        _ => Err(anyhow!("Cannot convert value to number: {}", to_string(&v))),
+    }
+}
+
+/// The number held by `v`, if it is one
+fn number_of(v: &Value) -> Option<Number> {
+    match v {
+        Value::Int(i) => Some(Number::Int(*i)),
+        Value::Num(n) => Some(Number::Float(*n)),
+        _ => None,
+    }
+}
+
+/// Whole numbers, including decimals with no fractional part
+fn whole_number(v: &Value) -> Option<i64> {
+    match number_of(v)? {
+        Number::Int(i) => Some(i),
+        Number::Float(f) => match Number::from_whole(f) {
+            Number::Int(i) => Some(i),
+            Number::Float(_) => None,
+        },
+    }
+}
+
+impl HasShape for Value {
+    fn shape(&self) -> Shape<'_, Self> {
+        match self {
+            Value::Int(i) => Shape::Number(Number::Int(*i)),
+            Value::Num(n) => Shape::Number(Number::Float(*n)),
+            Value::Str(s) => Shape::Str(s),
+            Value::Bool(b) => Shape::Bool(*b),
+            Value::Null => Shape::Null,
+            Value::List(xs) => Shape::List(xs),
+            Value::Tuple(xs) => Shape::Tuple(xs),
+            Value::Dict(m) => Shape::Dict(m),
+            Value::Set(s) => Shape::Set(s),
+            _ => Shape::Other,
+        }
+    }
+}
+
+impl From<Number> for Value {
+    fn from(n: Number) -> Self {
+        match n {
+            Number::Int(i) => Value::Int(i),
+            Number::Float(f) => Value::Num(f),
+        }
+    }
+}
+
+fn compare_numbers(op: &CmpOp, a: Number, b: Number) -> bool {
+    let Some(ord) = a.compare(b) else {
+        return false;
+    };
+    match op {
+        CmpOp::Lt => ord.is_lt(),
+        CmpOp::Le => ord.is_le(),
+        CmpOp::Gt => ord.is_gt(),
+        CmpOp::Ge => ord.is_ge(),
+        CmpOp::Eq => ord.is_eq(),
+        CmpOp::Ne => ord.is_ne(),
+    }
+}
+
+/// A list or string position; floats are truncated and huge values clamped
+fn index_of(v: &Value) -> i32 {
+    match v {
+        Value::Int(i) => (*i).clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+        Value::Num(n) => *n as i32,
+        _ => 0,
+    }
+}
+
+/// A `Make ... End` body, slot-resolved unless it has to stay dynamic
+struct FnBlock {
+    body: Program,
+    layout: Option<Arc<SlotLayout>>,
+    // Blocks defined inside `body`, resolved once up front so calling this
+    // function again does not redo the work. Keyed by `block_id` of the
+    // definition, which is stable because `body` never changes.
+    nested: HashMap<usize, Arc<FnBlock>>,
+}
+
+impl FnBlock {
+    /// Tracing and debugging pass `resolve: false` so statements keep their
+    /// variable names when they are shown
+    fn new(params: &[Param], body: &Program, resolve: bool) -> Arc<FnBlock> {
+        let resolved = if resolve { resolve_body(params, body) } else { None };
+        let (body, layout) = match resolved {
+            Some((body, layout)) => (body, Some(Arc::new(layout))),
+            None => (body.clone(), None),
+        };
+        let mut nested = HashMap::new();
+        collect_nested_blocks(&body, &mut nested, resolve);
+        Arc::new(FnBlock {
+            body,
+            layout,
+            nested,
+        })
+    }
+}
+
+impl std::fmt::Debug for FnBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FnBlock")
+            .field("statements", &self.body.len())
+            .finish_non_exhaustive()
     }
 }
 
-pub fn compile(prog: &Program) -> Vec<u8> {
-    let mut out: Vec<String> = Vec::new();
-    for stmt in prog {
+/// Identity of a nested block definition within its enclosing `FnBlock`
+fn block_id(body: &Program) -> usize {
+    body as *const Program as usize
+}
+
+fn collect_nested_blocks(body: &Program, out: &mut HashMap<usize, Arc<FnBlock>>, resolve: bool) {
+    for stmt in body {
         match stmt {
-            Stmt::Write(e) => {
-                compile_expr(e, &mut out);
-                out.push(Instruction::WriteTop.encode());
-            }
-            Stmt::AskFor { var_name } => {
-                out.push(Instruction::AskVar(var_name.clone()).encode());
-            }
-            Stmt::Set { name, value } => {
-                compile_expr(value, &mut out);
-                out.push(Instruction::StoreVar(name.clone()).encode());
+            Stmt::FuncBlock { params, body, .. } => {
+                out.insert(block_id(body), FnBlock::new(params, body, resolve));
             }
             Stmt::IfBlock {
-                cond,
                 then_body,
                 otherwise_body,
+                ..
             } => {
-                // cond
-                compile_expr(cond, &mut out);
-                // placeholder for jump-if-false to else/end
-                let jmpf_index = out.len();
-                out.push(Instruction::JumpIfFalse(usize::MAX).encode());
-                // then body
-                for s in then_body {
-                    compile_stmt(s, &mut out);
-                }
-                // jump to end (skip else)
-                let jmp_end_index = out.len();
-                out.push(Instruction::Jump(usize::MAX).encode());
-                // patch JMPF to here (start of else)
-                let else_target = out.len();
-                patch_jump(&mut out[jmpf_index], else_target);
-                // else body if present
+                collect_nested_blocks(then_body, out, resolve);
                 if let Some(eb) = otherwise_body {
-                    for s in eb {
-                        compile_stmt(s, &mut out);
-                    }
-                }
-                // patch end jump to here
-                let end_target = out.len();
-                patch_jump(&mut out[jmp_end_index], end_target);
-            }
-            Stmt::WhileBlock { cond, body } => {
-                let loop_start = out.len();
-                compile_expr(cond, &mut out);
-                let jmpf_index = out.len();
-                out.push(Instruction::JumpIfFalse(usize::MAX).encode());
-                for s in body {
-                    compile_stmt(s, &mut out);
-                }
-                // jump back to start
-                out.push(Instruction::Jump(loop_start).encode());
-                // patch false to loop end
-                let loop_end = out.len();
-                patch_jump(&mut out[jmpf_index], loop_end);
-            }
-            Stmt::RepeatBlock { count, body } => {
-                // Desugar: Repeat n times -> i = 0; while i < n { body; i = i + 1 }
-                // We'll compile as: PUSH_NUM 0; STORE_VAR __i; <start> LOAD_VAR __i; <count>; LT; JMPF end; body; LOAD_VAR __i; PUSH_NUM 1; ADD; STORE_VAR __i; JMP start; end:
-                out.push(Instruction::PushNum(0.0).encode());
-                out.push(Instruction::StoreVar("__i".to_string()).encode());
-                let start = out.len();
-                out.push(Instruction::LoadVar("__i".to_string()).encode());
-                compile_expr(count, &mut out);
-                out.push(Instruction::Lt.encode());
-                let jmpf = out.len();
-                out.push(Instruction::JumpIfFalse(usize::MAX).encode());
-                for s in body {
-                    compile_stmt(s, &mut out);
+                    collect_nested_blocks(eb, out, resolve);
                 }
-                out.push(Instruction::LoadVar("__i".to_string()).encode());
-                out.push(Instruction::PushNum(1.0).encode());
-                out.push(Instruction::Add.encode());
-                out.push(Instruction::StoreVar("__i".to_string()).encode());
-                out.push(Instruction::Jump(start).encode());
-                let end = out.len();
-                patch_jump(&mut out[jmpf], end);
             }
+            Stmt::WhileBlock { body, .. }
+            | Stmt::RepeatBlock { body, .. }
+            | Stmt::RepeatUntil { body, .. }
+            | Stmt::ReadLineLoop { body, .. }
+            | Stmt::ForEach { body, .. } => collect_nested_blocks(body, out, resolve),
             _ => {}
         }
     }
-    let text = out.join("\n") + "\n";
-    text.into_bytes()
 }
 
-fn compile_expr(e: &Expr, out: &mut Vec<String>) {
-    match e {
-        Expr::Num(n) => out.push(Instruction::PushNum(*n).encode()),
-        Expr::Str(s) => out.push(Instruction::PushStr(s.clone()).encode()),
-        Expr::Ident(n) => out.push(Instruction::LoadVar(n.clone()).encode()),
-        Expr::Plus(a, b) => {
-            compile_expr(a, out);
-            compile_expr(b, out);
-            out.push(Instruction::Add.encode());
-        }
-        Expr::Minus(a, b) => {
-            compile_expr(a, out);
-            compile_expr(b, out);
-            out.push(Instruction::Sub.encode());
+/// A lexical scope. Functions keep a handle to the scope they were defined in,
+/// so capturing is O(1) and names are resolved when the function runs.
+struct Scope {
+    vars: HashMap<String, Value>,
+    parent: Option<Env>,
+}
+
+type Env = Arc<RwLock<Scope>>;
+
+impl Scope {
+    fn new_env(vars: HashMap<String, Value>, parent: Option<Env>) -> Env {
+        Arc::new(RwLock::new(Scope { vars, parent }))
+    }
+}
+
+/// Every variable visible from `env`, inner scopes hiding outer ones
+fn flatten_env(env: &Env) -> HashMap<String, Value> {
+    let scope = env.read().unwrap();
+    let mut vars = scope.parent.as_ref().map(flatten_env).unwrap_or_default();
+    vars.extend(scope.vars.iter().map(|(k, v)| (k.clone(), unobserved(v))));
+    vars
+}
+
+impl std::fmt::Debug for Scope {
+    // Scopes can be reachable from the functions they hold; print names only
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scope")
+            .field("vars", &self.vars.keys().collect::<Vec<_>>())
+            .field("has_parent", &self.parent.is_some())
+            .finish()
+    }
+}
+
+/// Look a name up through the scope chain, innermost first
+fn env_get(env: &Env, name: &str) -> Option<Value> {
+    let scope = env.read().unwrap();
+    if let Some(v) = scope.vars.get(name) {
+        return Some(unobserved(v));
+    }
+    scope.parent.as_ref().and_then(|p| env_get(p, name))
+}
+
+/// A variable's value, without the `Bind` block of a bound one
+fn unobserved(v: &Value) -> Value {
+    match v {
+        Value::Observed(v, _) => v.as_ref().clone(),
+        v => v.clone(),
+    }
+}
+
+/// Bind a name in the innermost scope
+fn env_set(env: &Env, name: &str, value: Value) {
+    env.write().unwrap().vars.insert(name.to_string(), value);
+}
+
+/// Update a name in the innermost scope that has it, or bind it there
+fn env_assign(env: &Env, name: &str, value: Value) {
+    let mut scope = env.write().unwrap();
+    if !scope.vars.contains_key(name) {
+        if let Some(parent) = scope.parent.as_ref().filter(|p| env_get(p, name).is_some()) {
+            return env_assign(parent, name, value);
         }
-        Expr::Times(a, b) => {
-            compile_expr(a, out);
-            compile_expr(b, out);
-            out.push(Instruction::Mul.encode());
+    }
+    scope.vars.insert(name.to_string(), value);
+}
+
+#[cfg(feature = "web")]
+fn env_remove(env: &Env, name: &str) -> Option<Value> {
+    env.write().unwrap().vars.remove(name)
+}
+
+#[derive(Debug)]
+struct Frame {
+    env: Env,
+    // Slot-resolved locals, `None` until assigned; empty for dynamic bodies
+    slots: Vec<Option<Value>>,
+    block: Arc<FnBlock>,
+}
+
+impl Frame {
+    fn get(&self, name: &str) -> Option<Value> {
+        if let Some(slot) = self.block.layout.as_ref().and_then(|l| l.slot(name)) {
+            if let Some(v) = &self.slots[slot as usize] {
+                return Some(v.clone());
+            }
         }
-        Expr::DividedBy(a, b) => {
-            compile_expr(a, out);
-            compile_expr(b, out);
-            out.push(Instruction::Div.encode());
+        env_get(&self.env, name)
+    }
+
+    fn set(&mut self, name: &str, value: Value) {
+        match self.block.layout.as_ref().and_then(|l| l.slot(name)) {
+            Some(slot) => self.slots[slot as usize] = Some(value),
+            None => env_set(&self.env, name, value),
         }
-        Expr::Cmp(op, l, r) => {
-            compile_expr(l, out);
-            compile_expr(r, out);
-            match op {
-                CmpOp::Eq => out.push(Instruction::Eq.encode()),
-                CmpOp::Ne => out.push(Instruction::Ne.encode()),
-                CmpOp::Lt => out.push(Instruction::Lt.encode()),
-                CmpOp::Le => out.push(Instruction::Le.encode()),
-                CmpOp::Gt => out.push(Instruction::Gt.encode()),
-                CmpOp::Ge => out.push(Instruction::Ge.encode()),
-            }
+    }
+
+    /// A copy for a `Parallel` branch or a task, like `Vm::clone`: nothing
+    /// the copy sets reaches this frame
+    fn copy(&self) -> Frame {
+        Frame {
+            env: Scope::new_env(flatten_env(&self.env), None),
+            slots: self.slots.clone(),
+            block: Arc::clone(&self.block),
         }
-        Expr::And(a, b) => {
-            // Boolean AND producing 1 or 0
-            compile_expr(a, out);
-            let jmp_false = out.len();
-            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
-            compile_expr(b, out);
-            let jmp_false2 = out.len();
-            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
-            // both truthy -> 1
-            out.push(Instruction::PushNum(1.0).encode());
-            let jmp_end = out.len();
-            out.push(Instruction::Jump(usize::MAX).encode());
-            // patch falses to push 0
-            let falsy_tgt = out.len();
-            patch_jump(&mut out[jmp_false], falsy_tgt);
-            patch_jump(&mut out[jmp_false2], falsy_tgt);
-            out.push(Instruction::PushNum(0.0).encode());
-            let end = out.len();
-            patch_jump(&mut out[jmp_end], end);
+    }
+
+    /// Like `set`, but a name from an enclosing scope is updated there, so
+    /// changing an item of a program-level list changes that list
+    fn assign(&mut self, name: &str, value: Value) {
+        match self.block.layout.as_ref().and_then(|l| l.slot(name)) {
+            Some(slot) => self.slots[slot as usize] = Some(value),
+            None => env_assign(&self.env, name, value),
         }
-        Expr::Or(a, b) => {
-            // Boolean OR producing 1 or 0
-            compile_expr(a, out);
-            let jmp_false = out.len();
-            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
-            // a truthy -> 1
-            out.push(Instruction::PushNum(1.0).encode());
-            let jmp_end = out.len();
-            out.push(Instruction::Jump(usize::MAX).encode());
-            // a falsy -> evaluate b
-            let else_tgt = out.len();
-            patch_jump(&mut out[jmp_false], else_tgt);
-            compile_expr(b, out);
-            let jmp_false_b = out.len();
-            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
-            out.push(Instruction::PushNum(1.0).encode());
-            let jmp_end2 = out.len();
-            out.push(Instruction::Jump(usize::MAX).encode());
-            let falsy_tgt = out.len();
-            patch_jump(&mut out[jmp_false_b], falsy_tgt);
-            out.push(Instruction::PushNum(0.0).encode());
-            let end = out.len();
-            patch_jump(&mut out[jmp_end], end);
-            patch_jump(&mut out[jmp_end2], end);
+    }
+}
+
+/// Where `eval_in` finds the names an expression uses before it looks at
+/// the program's globals
+trait Lookup {
+    fn get(&self, name: &str) -> Option<Value>;
+
+    /// A name the resolver found in an enclosing scope, skipping the locals
+    fn get_outer(&self, name: &str) -> Option<Value> {
+        self.get(name)
+    }
+
+    /// A resolved local: its value, or its name when it is not assigned yet.
+    /// `None` outside a function body.
+    fn slot(&self, _slot: u16) -> Option<Result<Value, &str>> {
+        None
+    }
+}
+
+/// The program scope, which `resolve_value` already covers
+struct TopLevel;
+
+impl Lookup for TopLevel {
+    fn get(&self, _name: &str) -> Option<Value> {
+        None
+    }
+}
+
+impl Lookup for Frame {
+    fn get(&self, name: &str) -> Option<Value> {
+        Frame::get(self, name)
+    }
+
+    fn get_outer(&self, name: &str) -> Option<Value> {
+        env_get(&self.env, name)
+    }
+
+    fn slot(&self, slot: u16) -> Option<Result<Value, &str>> {
+        Some(match &self.slots[slot as usize] {
+            Some(v) => Ok(v.clone()),
+            None => Err(self.block.layout.as_ref().map_or("", |l| l.name(slot))),
+        })
+    }
+}
+
+/// A one-line function's parameters, then the scope it was made in
+struct Captured<'a> {
+    locals: &'a HashMap<String, Value>,
+    env: &'a Env,
+}
+
+impl Lookup for Captured<'_> {
+    fn get(&self, name: &str) -> Option<Value> {
+        self.locals.get(name).cloned().or_else(|| env_get(self.env, name))
+    }
+}
+
+/// What a debugger stop sees: the program scope, and the frame of the
+/// function running, if any
+struct StopScope<'a> {
+    vm: &'a Vm,
+    frame: Option<&'a Frame>,
+}
+
+impl Inspect for StopScope<'_> {
+    fn eval(&self, src: &str) -> Result<String> {
+        let e = crate::parser::parser::parse_expr(src)?;
+        let v = match self.frame {
+            Some(frame) => self.vm.eval_in_frame(&e, frame)?,
+            None => self.vm.eval(&e)?,
+        };
+        Ok(pretty(&v))
+    }
+
+    fn locals(&self) -> Vec<(String, String)> {
+        let Some(frame) = self.frame else {
+            return Vec::new();
+        };
+        let mut vars = shown_vars(&frame.env.read().unwrap().vars);
+        if let Some(layout) = &frame.block.layout {
+            for (slot, value) in frame.slots.iter().enumerate() {
+                if let Some(v) = value {
+                    vars.push((layout.name(slot as u16).to_string(), trace_value(v)));
+                }
+            }
+            vars.sort();
         }
-        Expr::Not(a) => {
-            // Not a -> if a is falsy push 1 else 0
-            compile_expr(a, out);
-            let jmp_false = out.len();
-            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
-            // a truthy
-            out.push(Instruction::PushNum(0.0).encode());
-            let jmp_end = out.len();
-            out.push(Instruction::Jump(usize::MAX).encode());
-            // a falsy
-            let t = out.len();
-            patch_jump(&mut out[jmp_false], t);
-            out.push(Instruction::PushNum(1.0).encode());
-            let end = out.len();
-            patch_jump(&mut out[jmp_end], end);
+        vars
+    }
+
+    fn globals(&self) -> Vec<(String, String)> {
+        shown_vars(&self.vm.globals.read().unwrap().vars)
+    }
+}
+
+/// Sorted by name, without internal `__` names and function definitions
+fn shown_vars(vars: &HashMap<String, Value>) -> Vec<(String, String)> {
+    let mut shown: Vec<(String, String)> = vars
+        .iter()
+        .filter(|(name, v)| !name.starts_with("__") && !matches!(v, Value::Func(_)))
+        .map(|(name, v)| (name.clone(), trace_value(v)))
+        .collect();
+    shown.sort();
+    shown
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+enum ControlFlow {
+    Return(Option<Value>),
+    Continue,
+}
+
+// ------------------------ BUILT-INS ------------------------
+
+/// Functions `Vm::call_function` provides itself. Every one of them returns
+/// a value, so a bare `Use` of one is almost always a missing `Write`.
+pub(crate) const BUILTIN_FUNCTIONS: &[&str] = &[
+    "now", "range", "join", "split", "length", "len", "sum", "min", "max", "abs", "round",
+    "floor", "ceil", "lerp", "clamp", "map_range", "uppercase", "lowercase", "trim", "match_pattern", "find_all", "replace_pattern",
+    "split_by_pattern", "first", "last", "reverse", "memoize",
+];
+
+fn builtin_range(args: &[Value]) -> Result<Value> {
+    let bounds: Vec<i64> = args
+        .iter()
+        .map_while(|v| match v {
+            Value::Int(i) => Some(*i),
+            Value::Num(n) => Some(*n as i64),
+            _ => None,
+        })
+        .collect();
+    if bounds.len() != args.len() {
+        return Ok(Value::List(Vec::new()));
+    }
+    let (start, end, step) = match bounds[..] {
+        [n] => (0, n, 1),
+        [a, b] => (a, b, 1),
+        [a, b, step] => (a, b, step),
+        _ => return Ok(Value::List(Vec::new())),
+    };
+    Ok(Value::LazyRange { start, end, step })
+}
+
+/// How many numbers a range holds; none for a zero step
+fn range_len(start: i64, end: i64, step: i64) -> i64 {
+    let span = (end as i128 - start as i128) * step.signum() as i128;
+    if step == 0 || span <= 0 {
+        return 0;
+    }
+    ((span - 1) / (step as i128).abs() + 1).min(i64::MAX as i128) as i64
+}
+
+/// The first and last numbers of a range, unless it is empty
+fn range_ends(start: i64, end: i64, step: i64) -> Option<(i64, i64)> {
+    let len = range_len(start, end, step);
+    (len > 0).then(|| (start, start + (len - 1) * step))
+}
+
+/// The numbers of a range, made one at a time
+fn range_iter(start: i64, end: i64, step: i64) -> impl Iterator<Item = i64> {
+    (0..range_len(start, end, step)).map(move |k| start + k * step)
+}
+
+/// Threads `apply ... in parallel` uses without a `with <n> workers`, at most
+/// one for each CPU
+const MAX_WORKERS: usize = 8;
+
+/// The items `For each` walks, in order. A range's numbers are made one at
+/// a time, so a loop over `range(1000000000)` holds only the current one.
+/// `phrase` names what wanted them in the error for other values.
+fn each_item(v: Value, phrase: &str) -> Result<Box<dyn Iterator<Item = Value>>> {
+    Ok(match v {
+        Value::LazyRange { start, end, step } => Box::new(range_iter(start, end, step).map(Value::Int)),
+        Value::List(items) | Value::Tuple(items) => Box::new(items.into_iter()),
+        Value::Set(s) => Box::new(sorted_members(&s).into_iter()),
+        v => return Err(wrong_type(format!("{} expects a list, got {}", phrase, described(&v)))),
+    })
+}
+
+/// A range as the list it stands for; other values as they are. For the
+/// operations that need every item at once.
+fn listed(v: &Value) -> Cow<'_, Value> {
+    match *v {
+        Value::LazyRange { start, end, step } => {
+            Cow::Owned(Value::List(range_iter(start, end, step).map(Value::Int).collect()))
         }
-        other => {
-            // Fallback to string dump for complex expressions for now
-            out.push(Instruction::PushStr(dump_expr(other)).encode());
+        _ => Cow::Borrowed(v),
+    }
+}
+
+/// `nums[i]` on a range, negative `i` counting from the end as for lists
+fn range_index(start: i64, end: i64, step: i64, idx: i32) -> Result<Value> {
+    let len = range_len(start, end, step);
+    let actual_idx = if idx < 0 { len + idx as i64 } else { idx as i64 };
+    if actual_idx < 0 || actual_idx >= len {
+        let msg = format!("List index out of range: {} (list length: {})", idx, len);
+        return Err(anyhow!("{}", hints::with_hint(&msg)));
+    }
+    Ok(Value::Int(start + actual_idx * step))
+}
+
+/// Equality that sees a range as the list it stands for
+fn values_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::LazyRange { .. }, _) | (_, Value::LazyRange { .. }) => {
+            typed_eq(listed(a).as_ref(), listed(b).as_ref())
         }
+        _ => typed_eq(a, b),
     }
 }
 
-fn compile_stmt(s: &Stmt, out: &mut Vec<String>) {
-    match s {
-        Stmt::Write(e) => {
-            compile_expr(e, out);
-            out.push(Instruction::WriteTop.encode());
+/// Where `actual` differs from `expected`, one message per key or index,
+/// such as `Expected 'user.name' to be 'Alice' but got 'Bob'`. `path` is
+/// where the two values sit, empty at the top.
+fn deep_diff(actual: &Value, expected: &Value, path: &str) -> Vec<String> {
+    let (actual, expected) = (listed(actual), listed(expected));
+    let at = |step: &str| if path.is_empty() { step.to_string() } else { format!("{}.{}", path, step) };
+    let mut out = Vec::new();
+    match (actual.as_ref(), expected.as_ref()) {
+        (Value::Dict(got), Value::Dict(want)) => {
+            let mut keys: Vec<&String> = got.keys().chain(want.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                match (got.get(key), want.get(key)) {
+                    (Some(g), Some(w)) => out.extend(deep_diff(g, w, &at(key))),
+                    (None, Some(w)) => out.push(format!("Expected '{}' to be {} but it is missing", at(key), diff_value(w))),
+                    (Some(g), None) => out.push(format!("Expected no '{}' but got {}", at(key), diff_value(g))),
+                    (None, None) => {}
+                }
+            }
         }
-        Stmt::Set { name, value } => {
-            compile_expr(value, out);
-            out.push(Instruction::StoreVar(name.clone()).encode());
+        (Value::List(got), Value::List(want)) | (Value::Tuple(got), Value::Tuple(want)) => {
+            if got.len() != want.len() {
+                let whole = if path.is_empty() { String::new() } else { format!("'{}' ", path) };
+                out.push(format!("Expected {}to have length {} but got {}", whole, want.len(), got.len()));
+            }
+            for (i, (g, w)) in got.iter().zip(want).enumerate() {
+                out.extend(deep_diff(g, w, &format!("{}[{}]", path, i)));
+            }
         }
-        Stmt::AskFor { var_name } => {
-            out.push(Instruction::AskVar(var_name.clone()).encode());
+        (got, want) if !values_eq(got, want) => out.push(if path.is_empty() {
+            format!("Expected {} but got {}", diff_value(want), diff_value(got))
+        } else {
+            format!("Expected '{}' to be {} but got {}", path, diff_value(want), diff_value(got))
+        }),
+        _ => {}
+    }
+    out
+}
+
+/// A value as an assertion message shows it, text in quotes
+fn diff_value(v: &Value) -> String {
+    match v {
+        Value::Str(s) => format!("'{}'", s),
+        other => to_string(other),
+    }
+}
+
+fn builtin_join(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Ok(Value::Str(String::new()));
+    }
+    let sep = if args.len() >= 2 {
+        to_string(&args[1])
+    } else {
+        String::new()
+    };
+    match listed(&args[0]).as_ref() {
+        Value::List(xs) | Value::Tuple(xs) => Ok(Value::Str(
+            xs.iter().map(to_string).collect::<Vec<_>>().join(&sep),
+        )),
+        other => Ok(Value::Str(to_string(other))),
+    }
+}
+
+fn builtin_split(args: &[Value]) -> Result<Value> {
+    if args.len() < 2 {
+        return Ok(Value::List(Vec::new()));
+    }
+    let s = to_string(&args[0]);
+    let sep = to_string(&args[1]);
+    let parts = if sep.is_empty() {
+        s.chars().map(|c| Value::Str(c.to_string())).collect()
+    } else {
+        s.split(&sep).map(|p| Value::Str(p.to_string())).collect()
+    };
+    Ok(Value::List(parts))
+}
+
+fn builtin_length(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Ok(Value::Int(0));
+    }
+    match &args[0] {
+        Value::List(xs) | Value::Tuple(xs) => Ok(Value::Int(xs.len() as i64)),
+        Value::LazyRange { start, end, step } => Ok(Value::Int(range_len(*start, *end, *step))),
+        Value::Str(s) => Ok(Value::Int(s.chars().count() as i64)),
+        Value::Dict(m) => Ok(Value::Int(m.len() as i64)),
+        Value::Set(s) => Ok(Value::Int(s.len() as i64)),
+        other => Ok(Value::Int(collection_len(other).unwrap_or(0) as i64)),
+    }
+}
+
+/// The numbers in several arguments to `sum`, `min` or `max`, with lists
+/// among them flattened one level
+fn numbers_in(phrase: &str, args: &[Value]) -> Result<Vec<Number>> {
+    let mut items = Vec::new();
+    for arg in args {
+        match listed(arg).into_owned() {
+            Value::List(xs) | Value::Tuple(xs) => items.extend(xs),
+            other => items.push(other),
         }
-        Stmt::IfBlock {
-            cond,
-            then_body,
-            otherwise_body,
-        } => {
-            // Recursively handled by compile() loop, but support here for nested calls
-            // cond
-            compile_expr(cond, out);
-            let jmpf_index = out.len();
-            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
-            for st in then_body.iter() {
-                compile_stmt(st, out);
+    }
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            number_of(v).ok_or_else(|| wrong_type(format!("{} expects numbers; item {} was {}", phrase, i, described(v))))
+        })
+        .collect()
+}
+
+fn builtin_sum(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Ok(Value::Int(0));
+    }
+    if args.len() > 1 {
+        let total = numbers_in("total of", args)?
+            .into_iter()
+            .fold(Number::Int(0), |total, n| total + n);
+        return Ok(total.into());
+    }
+    match &args[0] {
+        Value::List(xs) => {
+            if xs.is_empty() {
+                return Ok(Value::Int(0));
             }
-            let jmp_end_index = out.len();
-            out.push(Instruction::Jump(usize::MAX).encode());
-            let else_target = out.len();
-            patch_jump(&mut out[jmpf_index], else_target);
-            if let Some(eb) = otherwise_body {
-                for st in eb.iter() {
-                    compile_stmt(st, out);
+            let mut total = Number::Int(0);
+            for (i, v) in xs.iter().enumerate() {
+                match number_of(v) {
+                    Some(n) => total = total + n,
+                    None => {
+                        return Err(wrong_type(format!(
+                            "total of expects numbers; item {} was {}",
+                            i,
+                            described(v)
+                        )))
+                    }
                 }
             }
-            let end_target = out.len();
-            patch_jump(&mut out[jmp_end_index], end_target);
+            Ok(total.into())
         }
-        Stmt::WhileBlock { cond, body } => {
-            let loop_start = out.len();
-            compile_expr(cond, out);
-            let jmpf_index = out.len();
-            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
-            for st in body.clone() {
-                compile_stmt(&st, out);
+        // n items from first to last add up to n * (first + last) / 2
+        &Value::LazyRange { start, end, step } => {
+            let total = range_ends(start, end, step).map_or(0, |(first, last)| {
+                range_len(start, end, step) as i128 * (first as i128 + last as i128) / 2
+            });
+            Ok(i64::try_from(total).map_or(Value::Num(total as f64), Value::Int))
+        }
+        n @ (Value::Int(_) | Value::Num(_)) => Ok(n.clone()),
+        other => Err(wrong_type(format!(
+            "total of expects a list or a number, got {}",
+            described(other)
+        ))),
+    }
+}
+
+fn builtin_min(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Err(anyhow!("smallest in requires at least one argument"));
+    }
+    if args.len() > 1 {
+        let smallest = numbers_in("smallest in", args)?
+            .into_iter()
+            .reduce(|m, n| if m.compare(n) != Some(Ordering::Less) { n } else { m });
+        return smallest
+            .map(Value::from)
+            .ok_or_else(|| anyhow!("smallest in an empty list is undefined"));
+    }
+    match &args[0] {
+        Value::List(xs) => {
+            if xs.is_empty() {
+                return Err(anyhow!("smallest in an empty list is undefined"));
             }
-            out.push(Instruction::Jump(loop_start).encode());
-            let loop_end = out.len();
-            patch_jump(&mut out[jmpf_index], loop_end);
+            let mut min_val = None::<Number>;
+            for (i, v) in xs.iter().enumerate() {
+                match number_of(v) {
+                    Some(n) => {
+                        min_val = Some(match min_val {
+                            Some(m) if m.compare(n) != Some(Ordering::Less) => n,
+                            Some(m) => m,
+                            None => n,
+                        });
+                    }
+                    None => {
+                        return Err(wrong_type(format!(
+                            "smallest in expects numbers; item {} was {}",
+                            i,
+                            described(v)
+                        )))
+                    }
+                }
+            }
+            Ok(min_val.unwrap().into())
         }
-        Stmt::RepeatBlock { count, body } => {
-            // Fallback to top-level logic using dedicated comparisons
-            out.push(Instruction::PushNum(0.0).encode());
-            out.push(Instruction::StoreVar("__i".to_string()).encode());
-            let start = out.len();
-            out.push(Instruction::LoadVar("__i".to_string()).encode());
-            compile_expr(count, out);
-            out.push(Instruction::Lt.encode());
-            let jmpf = out.len();
-            out.push(Instruction::JumpIfFalse(usize::MAX).encode());
-            for st in body {
-                compile_stmt(st, out);
+        Value::LazyRange { start, end, step } => match range_ends(*start, *end, *step) {
+            Some((first, last)) => Ok(Value::Int(first.min(last))),
+            None => Err(anyhow!("smallest in an empty list is undefined")),
+        },
+        n @ (Value::Int(_) | Value::Num(_)) => Ok(n.clone()),
+        other => Err(wrong_type(format!(
+            "smallest in expects a list or a number, got {}",
+            described(other)
+        ))),
+    }
+}
+
+fn builtin_max(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Err(anyhow!("largest in requires at least one argument"));
+    }
+    if args.len() > 1 {
+        let largest = numbers_in("largest in", args)?
+            .into_iter()
+            .reduce(|m, n| if m.compare(n) != Some(Ordering::Greater) { n } else { m });
+        return largest
+            .map(Value::from)
+            .ok_or_else(|| anyhow!("largest in an empty list is undefined"));
+    }
+    match &args[0] {
+        Value::List(xs) => {
+            if xs.is_empty() {
+                return Err(anyhow!("largest in an empty list is undefined"));
             }
-            out.push(Instruction::LoadVar("__i".to_string()).encode());
-            out.push(Instruction::PushNum(1.0).encode());
-            out.push(Instruction::Add.encode());
-            out.push(Instruction::StoreVar("__i".to_string()).encode());
-            out.push(Instruction::Jump(start).encode());
-            let end = out.len();
-            patch_jump(&mut out[jmpf], end);
+            let mut max_val = None::<Number>;
+            for (i, v) in xs.iter().enumerate() {
+                match number_of(v) {
+                    Some(n) => {
+                        max_val = Some(match max_val {
+                            Some(m) if m.compare(n) != Some(Ordering::Greater) => n,
+                            Some(m) => m,
+                            None => n,
+                        });
+                    }
+                    None => {
+                        return Err(wrong_type(format!(
+                            "largest in expects numbers; item {} was {}",
+                            i,
+                            described(v)
+                        )))
+                    }
+                }
+            }
+            Ok(max_val.unwrap().into())
         }
-        _ => {}
+        Value::LazyRange { start, end, step } => match range_ends(*start, *end, *step) {
+            Some((first, last)) => Ok(Value::Int(first.max(last))),
+            None => Err(anyhow!("largest in an empty list is undefined")),
+        },
+        n @ (Value::Int(_) | Value::Num(_)) => Ok(n.clone()),
+        other => Err(wrong_type(format!(
+            "largest in expects a list or a number, got {}",
+            described(other)
+        ))),
+    }
+}
+
+/// Collects a non-empty list of numbers for the statistical phrases
+fn numeric_list(phrase: &str, args: &[Value]) -> Result<Vec<f64>> {
+    match args.first().map(listed).as_deref() {
+        Some(Value::List(xs)) => {
+            if xs.is_empty() {
+                return Err(anyhow!("{} an empty list is undefined", phrase));
+            }
+            xs.iter()
+                .enumerate()
+                .map(|(i, v)| match number_of(v) {
+                    Some(n) => Ok(n.as_f64()),
+                    None => Err(wrong_type(format!(
+                        "{} expects numbers; item {} was {}",
+                        phrase,
+                        i,
+                        described(v)
+                    ))),
+                })
+                .collect()
+        }
+        Some(other) => Err(wrong_type(format!("{} expects a list, got {}", phrase, described(other)))),
+        None => Err(anyhow!("{} requires a list", phrase)),
+    }
+}
+
+fn mean(xs: &[f64]) -> f64 {
+    xs.iter().sum::<f64>() / xs.len() as f64
+}
+
+fn builtin_average(args: &[Value]) -> Result<Value> {
+    let xs = numeric_list("average of", args)?;
+    Ok(Value::Num(mean(&xs)))
+}
+
+fn builtin_median(args: &[Value]) -> Result<Value> {
+    // numeric_list returns a fresh Vec, so sorting never touches the caller's list
+    let mut xs = numeric_list("median of", args)?;
+    xs.sort_by(|a, b| a.total_cmp(b));
+    let mid = xs.len() / 2;
+    if xs.len() % 2 == 0 {
+        Ok(Value::Num((xs[mid - 1] + xs[mid]) / 2.0))
+    } else {
+        Ok(Value::Num(xs[mid]))
+    }
+}
+
+/// Population variance (divides by the number of items)
+fn builtin_variance(args: &[Value]) -> Result<Value> {
+    let xs = numeric_list("variance of", args)?;
+    let m = mean(&xs);
+    let var = xs.iter().map(|x| (x - m).powi(2)).sum::<f64>() / xs.len() as f64;
+    Ok(Value::Num(var))
+}
+
+fn builtin_std_dev(args: &[Value]) -> Result<Value> {
+    let xs = numeric_list("standard deviation of", args)?;
+    let m = mean(&xs);
+    let var = xs.iter().map(|x| (x - m).powi(2)).sum::<f64>() / xs.len() as f64;
+    Ok(Value::Num(var.sqrt()))
+}
+
+fn builtin_abs(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Err(anyhow!("abs requires one argument"));
+    }
+    match number_of(&args[0]) {
+        Some(n) => Ok(n.abs().into()),
+        None => Err(wrong_type(format!("abs requires a number, got {}", described(&args[0])))),
+    }
+}
+
+fn builtin_round(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Err(anyhow!("round requires one argument"));
+    }
+    match number_of(&args[0]) {
+        Some(n) => Ok(n.round().into()),
+        None => Err(wrong_type(format!("round requires a number, got {}", described(&args[0])))),
+    }
+}
+
+fn builtin_floor(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Err(anyhow!("floor requires one argument"));
+    }
+    match number_of(&args[0]) {
+        Some(n) => Ok(n.floor().into()),
+        None => Err(wrong_type(format!("floor requires a number, got {}", described(&args[0])))),
+    }
+}
+
+fn builtin_ceil(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Err(anyhow!("ceil requires one argument"));
+    }
+    match number_of(&args[0]) {
+        Some(n) => Ok(n.ceil().into()),
+        None => Err(wrong_type(format!("ceil requires a number, got {}", described(&args[0])))),
+    }
+}
+
+/// The `count` numbers a math built-in needs
+fn numbers_for(name: &str, args: &[Value], count: usize) -> Result<Vec<Number>> {
+    if args.len() < count {
+        bail!("{} requires {} numbers", name, count);
+    }
+    args[..count]
+        .iter()
+        .map(|v| number_of(v).ok_or_else(|| wrong_type(format!("{} requires numbers, got {}", name, described(v)))))
+        .collect()
+}
+
+/// `a + (b - a) * t`: `a` at 0, `b` at 1, and beyond them outside 0 to 1
+fn builtin_lerp(args: &[Value]) -> Result<Value> {
+    let n = numbers_for("lerp", args, 3)?;
+    let (a, b, t) = (n[0], n[1], n[2]);
+    Ok((a + (b - a) * t).into())
+}
+
+fn builtin_clamp(args: &[Value]) -> Result<Value> {
+    let n = numbers_for("clamp", args, 3)?;
+    let (x, low, high) = (n[0], n[1], n[2]);
+    if low.compare(high) == Some(Ordering::Greater) {
+        bail!("clamp: the low end {} is above the high end {}", low, high);
+    }
+    let clamped = if x.compare(low) == Some(Ordering::Less) {
+        low
+    } else if x.compare(high) == Some(Ordering::Greater) {
+        high
+    } else {
+        x
+    };
+    Ok(clamped.into())
+}
+
+/// Where `x` falls in `a` to `b`, at the same place in `c` to `d`. Like
+/// Arduino's `map()` it does not clamp, but it does not round either.
+fn builtin_map_range(args: &[Value]) -> Result<Value> {
+    let n = numbers_for("map_range", args, 5)?;
+    let (x, a, b, c, d) = (n[0], n[1], n[2], n[3], n[4]);
+    if (b - a).is_zero() {
+        bail!("map_range: the range {} to {} is empty", a, b);
+    }
+    Ok((c + (x - a) * (d - c) / (b - a)).into())
+}
+
+fn builtin_uppercase(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Ok(Value::Str(String::new()));
+    }
+    Ok(Value::Str(to_string(&args[0]).to_uppercase()))
+}
+
+fn builtin_lowercase(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Ok(Value::Str(String::new()));
     }
+    Ok(Value::Str(to_string(&args[0]).to_lowercase()))
 }
 
-fn patch_jump(encoded: &mut String, target: usize) {
-    // Replace the placeholder usize::MAX with real target in textual encoding
-    if encoded.starts_with("JMP\t") || encoded.starts_with("JMPF\t") {
-        let parts: Vec<&str> = encoded.splitn(2, '\t').collect();
-        *encoded = format!("{}\t{}", parts[0], target);
+fn builtin_trim(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Ok(Value::Str(String::new()));
     }
+    Ok(Value::Str(to_string(&args[0]).trim().to_string()))
 }
 
-fn dump_expr(e: &Expr) -> String {
-    match e {
-        Expr::Str(s) => s.clone(),
-        Expr::Num(n) => format_number(*n),
-        Expr::Bool(b) => {
-            if *b {
-                "True".to_string()
+fn compile_pattern(pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).map_err(|e| anyhow!("Invalid pattern r\"{}\": {}", pattern, e))
+}
+
+/// The text and pattern a pattern function was called with. The pattern can
+/// be an `r"..."` value or a string, compiled for this call only.
+fn text_and_pattern(name: &str, args: &[Value], count: usize) -> Result<(String, Arc<Regex>)> {
+    if args.len() < count {
+        let expected = if count == 2 {
+            "a text and a pattern"
+        } else {
+            "a text, a pattern and a replacement"
+        };
+        bail!("{} requires {}", name, expected);
+    }
+    let pattern = match &args[1] {
+        Value::Regex(r) => Arc::clone(r),
+        Value::Str(s) => Arc::new(compile_pattern(s)?),
+        other => {
+            return Err(wrong_type(format!("{} requires a pattern, got {}", name, described(other))));
+        }
+    };
+    Ok((to_string(&args[0]), pattern))
+}
+
+fn builtin_match_pattern(args: &[Value]) -> Result<Value> {
+    let (text, pattern) = text_and_pattern("match_pattern", args, 2)?;
+    Ok(Value::Bool(pattern.is_match(&text)))
+}
+
+fn builtin_find_all(args: &[Value]) -> Result<Value> {
+    let (text, pattern) = text_and_pattern("find_all", args, 2)?;
+    let found = pattern
+        .find_iter(&text)
+        .map(|m| Value::Str(m.as_str().to_string()))
+        .collect();
+    Ok(Value::List(found))
+}
+
+/// Every match replaced; `$1` or `${name}` in the replacement stand for
+/// what a group matched
+fn builtin_replace_pattern(args: &[Value]) -> Result<Value> {
+    let (text, pattern) = text_and_pattern("replace_pattern", args, 3)?;
+    let replacement = to_string(&args[2]);
+    Ok(Value::Str(pattern.replace_all(&text, replacement.as_str()).into_owned()))
+}
+
+fn builtin_split_by_pattern(args: &[Value]) -> Result<Value> {
+    let (text, pattern) = text_and_pattern("split_by_pattern", args, 2)?;
+    let parts = pattern.split(&text).map(|p| Value::Str(p.to_string())).collect();
+    Ok(Value::List(parts))
+}
+
+fn builtin_first(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Err(anyhow!("first requires one argument"));
+    }
+    match &args[0] {
+        Value::List(xs) | Value::Tuple(xs) => {
+            if xs.is_empty() {
+                Err(anyhow!("first called on empty list"))
             } else {
-                "False".to_string()
+                Ok(xs[0].clone())
             }
         }
-        Expr::Null => "None".to_string(),
-        Expr::Ident(x) => x.clone(),
-        Expr::Plus(a, b) => format!("{} plus {}", dump_expr(a), dump_expr(b)),
-        Expr::Minus(a, b) => format!("{} minus {}", dump_expr(a), dump_expr(b)),
-        Expr::Times(a, b) => format!("{} times {}", dump_expr(a), dump_expr(b)),
-        Expr::DividedBy(a, b) => format!("{} divided by {}", dump_expr(a), dump_expr(b)),
-        Expr::And(a, b) => format!("{} And {}", dump_expr(a), dump_expr(b)),
-        Expr::Or(a, b) => format!("{} Or {}", dump_expr(a), dump_expr(b)),
-        Expr::Not(a) => format!("Not {}", dump_expr(a)),
-        Expr::Cmp(op, l, r) => {
-            let sym = match op {
-                CmpOp::Lt => "<",
-                CmpOp::Le => "<=",
-                CmpOp::Gt => ">",
-                CmpOp::Ge => ">=",
-                CmpOp::Eq => "=",
-                CmpOp::Ne => "!=",
-            };
-            format!("{} {} {}", dump_expr(l), sym, dump_expr(r))
-        }
-        Expr::Call { name, args } => {
-            if args.is_empty() {
-                name.clone()
+        Value::Str(s) => {
+            if s.is_empty() {
+                Err(anyhow!("first called on empty string"))
             } else {
-                format!(
-                    "{} with {}",
-                    name,
-                    args.iter().map(dump_expr).collect::<Vec<_>>().join(", ")
-                )
+                Ok(Value::Str(s.chars().next().unwrap().to_string()))
             }
         }
-        Expr::ListLit(items) => format!(
-            "List contains {}",
-            items.iter().map(dump_expr).collect::<Vec<_>>().join(", ")
-        ),
-        Expr::DictLit(pairs) => pairs
-            .iter()
-            .map(|(k, v)| format!("\"{}\" set to {}", k, dump_expr(v)))
-            .collect::<Vec<_>>()
-            .join(", "),
-        Expr::Index(base, idx) => format!("{}[{}]", dump_expr(base), dump_expr(idx)),
-        // Phrasal built-in expressions
-        Expr::TotalOf(expr) => format!("total of {}", dump_expr(expr)),
-        Expr::SmallestIn(expr) => format!("smallest in {}", dump_expr(expr)),
-        Expr::LargestIn(expr) => format!("largest in {}", dump_expr(expr)),
-        Expr::AbsoluteValueOf(expr) => format!("absolute value of {}", dump_expr(expr)),
-        Expr::Round(expr) => format!("round {}", dump_expr(expr)),
-        Expr::RoundDown(expr) => format!("round down {}", dump_expr(expr)),
-        Expr::RoundUp(expr) => format!("round up {}", dump_expr(expr)),
-        Expr::MakeUppercase(expr) => format!("make uppercase {}", dump_expr(expr)),
-        Expr::MakeLowercase(expr) => format!("make lowercase {}", dump_expr(expr)),
-        Expr::TrimSpaces(expr) => format!("trim spaces from {}", dump_expr(expr)),
-        Expr::FirstIn(expr) => format!("first in {}", dump_expr(expr)),
-        Expr::LastIn(expr) => format!("last in {}", dump_expr(expr)),
-        Expr::ReverseOf(expr) => format!("reverse of {}", dump_expr(expr)),
-        Expr::CountOf(expr) => format!("count of {}", dump_expr(expr)),
-        Expr::JoinWith(a, b) => format!("join {} with {}", dump_expr(a), dump_expr(b)),
-        Expr::SplitBy(a, b) => format!("split {} by {}", dump_expr(a), dump_expr(b)),
-        Expr::Contains(item, collection) => {
-            format!("contains {} in {}", dump_expr(item), dump_expr(collection))
-        }
-        Expr::Remove(item, list) => format!("remove {} from {}", dump_expr(item), dump_expr(list)),
-        Expr::Append(item, list) => format!("append {} to {}", dump_expr(item), dump_expr(list)),
-        Expr::InsertAt(item, index, list) => format!(
-            "insert {} at {} in {}",
-            dump_expr(item),
-            dump_expr(index),
-            dump_expr(list)
-        ),
-        // File I/O operations
-        Expr::ReadFile(path) => format!("read file at {}", dump_expr(path)),
-        Expr::WriteFile(content, path) => {
-            format!(
-                "write {} to file at {}",
-                dump_expr(content),
-                dump_expr(path)
-            )
+        Value::LazyRange { start, end, step } => match range_ends(*start, *end, *step) {
+            Some((first, _)) => Ok(Value::Int(first)),
+            None => Err(anyhow!("first called on empty list")),
+        },
+        other => Err(wrong_type(format!("first requires a list or string, got {}", described(other)))),
+    }
+}
+
+fn builtin_last(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Err(anyhow!("last requires one argument"));
+    }
+    match &args[0] {
+        Value::List(xs) | Value::Tuple(xs) => {
+            if xs.is_empty() {
+                Err(anyhow!("last called on empty list"))
+            } else {
+                Ok(xs[xs.len() - 1].clone())
+            }
         }
-        Expr::AppendFile(content, path) => {
-            format!(
-                "append {} to file at {}",
-                dump_expr(content),
-                dump_expr(path)
-            )
+        Value::Str(s) => {
+            if s.is_empty() {
+                Err(anyhow!("last called on empty string"))
+            } else {
+                Ok(Value::Str(s.chars().last().unwrap().to_string()))
+            }
         }
-        Expr::FileExists(path) => format!("file exists at {}", dump_expr(path)),
-        Expr::DeleteFile(path) => format!("delete file at {}", dump_expr(path)),
-        Expr::CreateDir(path) => format!("create directory at {}", dump_expr(path)),
-        Expr::ListDir(path) => format!("list files in {}", dump_expr(path)),
-        Expr::ReadLines(path) => format!("read lines from {}", dump_expr(path)),
-        Expr::CopyFile(source, dest) => {
-            format!(
-                "copy file from {} to {}",
-                dump_expr(source),
-                dump_expr(dest)
-            )
+        Value::LazyRange { start, end, step } => match range_ends(*start, *end, *step) {
+            Some((_, last)) => Ok(Value::Int(last)),
+            None => Err(anyhow!("last called on empty list")),
+        },
+        other => Err(wrong_type(format!("last requires a list or string, got {}", described(other)))),
+    }
+}
+
+fn builtin_reverse(args: &[Value]) -> Result<Value> {
+    if args.is_empty() {
+        return Err(anyhow!("reverse requires one argument"));
+    }
+    match listed(&args[0]).as_ref() {
+        Value::List(xs) => {
+            let mut rev = xs.clone();
+            rev.reverse();
+            Ok(Value::List(rev))
         }
-        Expr::MoveFile(source, dest) => {
-            format!(
-                "move file from {} to {}",
-                dump_expr(source),
-                dump_expr(dest)
-            )
+        Value::Str(s) => Ok(Value::Str(s.chars().rev().collect())),
+        other => Err(wrong_type(format!("reverse requires a list or string, got {}", described(other)))),
+    }
+}
+
+/// A value as a set member. Lists, dictionaries and other sets can change
+/// or hold things that do not hash, so only numbers, text, booleans and
+/// nothing can be members; NaN cannot, as it equals nothing.
+fn set_member(v: Value) -> Result<OrdValue<Value>> {
+    match v {
+        Value::Num(n) if n.is_nan() => Err(wrong_type("NaN cannot be a member of a set")),
+        Value::Int(_) | Value::Num(_) | Value::Str(_) | Value::Bool(_) | Value::Null => Ok(OrdValue(v)),
+        other => Err(wrong_type(format!(
+            "{} cannot be a member of a set; sets hold numbers, text, booleans and nothing",
+            described(&other)
+        ))),
+    }
+}
+
+fn set_of(items: Vec<Value>) -> Result<Value> {
+    items.into_iter().map(set_member).collect::<Result<_>>().map(Value::Set)
+}
+
+/// The members of a set in one order for every run: nothing, booleans,
+/// numbers, then text
+// `Value` can hold functions and handles with locks inside, but a member is
+// always one of the plain values `set_member` lets through
+#[allow(clippy::mutable_key_type)]
+fn sorted_members(set: &HashSet<OrdValue<Value>>) -> Vec<Value> {
+    let rank = |v: &Value| match v {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Int(_) | Value::Num(_) => 2,
+        _ => 3,
+    };
+    let mut members: Vec<Value> = set.iter().map(|m| m.0.clone()).collect();
+    members.sort_by(|a, b| {
+        rank(a).cmp(&rank(b)).then_with(|| match (a, b) {
+            (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+            (Value::Str(x), Value::Str(y)) => x.cmp(y),
+            _ => match (number_of(a), number_of(b)) {
+                (Some(x), Some(y)) => x.compare(y).unwrap_or(Ordering::Equal),
+                _ => Ordering::Equal,
+            },
+        })
+    });
+    members
+}
+
+/// `union of`, `intersection of`, `difference of` and `set S contains X`
+#[allow(clippy::mutable_key_type)]
+fn set_operation(e: &Expr, a: Value, b: Value) -> Result<Value> {
+    let phrase = match e {
+        Expr::SetUnion(..) => "union of",
+        Expr::SetIntersection(..) => "intersection of",
+        Expr::SetDifference(..) => "difference of",
+        _ => "set ... contains",
+    };
+    if matches!(e, Expr::SetContains(..)) {
+        return match a {
+            Value::Set(_) => builtin_contains(&[b, a]),
+            other => Err(wrong_type(format!("{}: expected a set, got {}", phrase, described(&other)))),
+        };
+    }
+    let (Value::Set(a), Value::Set(b)) = (&a, &b) else {
+        return Err(wrong_type(format!(
+            "{} expects two sets, got {} and {}",
+            phrase,
+            described(&a),
+            described(&b)
+        )));
+    };
+    let members = match e {
+        Expr::SetUnion(..) => a.union(b).cloned().collect(),
+        Expr::SetIntersection(..) => a.intersection(b).cloned().collect(),
+        _ => a.difference(b).cloned().collect(),
+    };
+    Ok(Value::Set(members))
+}
+
+/// `list of S` gives the members sorted; `set of L` drops repeated items
+fn convert_set(e: &Expr, v: Value) -> Result<Value> {
+    match (e, listed(&v).into_owned()) {
+        (Expr::SetToList(_), Value::Set(s)) => Ok(Value::List(sorted_members(&s))),
+        (Expr::SetToList(_), other) => Err(wrong_type(format!("list of: expected a set, got {}", described(&other)))),
+        (_, Value::List(items) | Value::Tuple(items)) => set_of(items),
+        (_, set @ Value::Set(_)) => Ok(set),
+        (_, other) => Err(wrong_type(format!("set of: expected a list, got {}", described(&other)))),
+    }
+}
+
+fn builtin_contains(args: &[Value]) -> Result<Value> {
+    if args.len() < 2 {
+        return Err(anyhow!(
+            "contains requires two arguments: item and collection"
+        ));
+    }
+    let item = &args[0];
+    match &args[1] {
+        Value::List(xs) | Value::Tuple(xs) => {
+            // Check if item exists in list (using value equality)
+            for v in xs {
+                if typed_eq(item, v) {
+                    return Ok(Value::Bool(true));
+                }
+            }
+            Ok(Value::Bool(false))
         }
-        // JSON operations
-        Expr::ParseJson(s) => format!("parse json from {}", dump_expr(s)),
-        Expr::ToJson(v) => format!("convert to json {}", dump_expr(v)),
-        Expr::ToJsonPretty(v) => format!("convert to pretty json {}", dump_expr(v)),
-        Expr::GetPathParam(name) => format!("get path parameter {}", dump_expr(name)),
-        Expr::JsonGet(json, key) => format!("get {} from json {}", dump_expr(key), dump_expr(json)),
-        Expr::JsonSet(json, key, val) => {
-            format!(
-                "set {} in json {} to {}",
-                dump_expr(key),
-                dump_expr(json),
-                dump_expr(val)
-            )
+        &Value::LazyRange { start, end, step } => {
+            // On the range and a whole number of steps from its start
+            let found = step != 0
+                && whole_number(item).is_some_and(|n| {
+                    let offset = n as i128 - start as i128;
+                    let k = offset / step as i128;
+                    offset % step as i128 == 0 && k >= 0 && k < range_len(start, end, step) as i128
+                });
+            Ok(Value::Bool(found))
         }
-        Expr::NewJsonObject => "new json object".to_string(),
-        Expr::NewJsonArray => "new json array".to_string(),
-        Expr::JsonPush(json, item) => {
-            format!("push {} to json {}", dump_expr(item), dump_expr(json))
+        Value::Set(s) => Ok(Value::Bool(set_member(item.clone()).is_ok_and(|m| s.contains(&m)))),
+        Value::Str(s) => {
+            // Check if string contains substring
+            let needle = to_string(item);
+            Ok(Value::Bool(s.contains(&needle)))
         }
-        Expr::JsonLength(json) => format!("json length of {}", dump_expr(json)),
-        // Error operations
-        Expr::ErrorMessage(e) => format!("error message of {}", dump_expr(e)),
-        Expr::ErrorType(e) => format!("error type of {}", dump_expr(e)),
-        Expr::NewError {
-            error_type,
-            message,
-        } => {
-            format!(
-                "error of type {} with message {}",
-                error_type,
-                dump_expr(message)
-            )
+        Value::Dict(m) => {
+            // Check if key exists in dictionary
+            let key = to_string(item);
+            Ok(Value::Bool(m.contains_key(&key)))
         }
-        // Web server operations
-        Expr::CreateWebServer(port) => format!("create web server on port {}", dump_expr(port)),
-        Expr::HtmlResponse(content) => format!("html response with {}", dump_expr(content)),
-        Expr::JsonResponse(data) => format!("json response with {}", dump_expr(data)),
-        Expr::JsonResponseStatus(data, status) => {
-            format!(
-                "json response with {} and status {}",
-                dump_expr(data),
-                dump_expr(status)
-            )
+        other => Err(wrong_type(format!(
+            "contains expects a list, string, or dictionary as second argument, got {}",
+            described(other)
+        ))),
+    }
+}
+
+fn builtin_remove(args: &[Value]) -> Result<Value> {
+    if args.len() < 2 {
+        return Err(anyhow!("remove requires two arguments: item and list"));
+    }
+    let item = &args[0];
+    match listed(&args[1]).as_ref() {
+        Value::List(xs) => {
+            // Remove first occurrence of item from list
+            let mut result = Vec::new();
+            let mut removed = false;
+            for v in xs {
+                if !removed && typed_eq(item, v) {
+                    removed = true;
+                } else {
+                    result.push(v.clone());
+                }
+            }
+            Ok(Value::List(result))
         }
-        Expr::RenderTemplate(template, data) => {
-            format!(
-                "render template {} with {}",
-                dump_expr(template),
-                dump_expr(data)
-            )
+        Value::Tuple(_) => Err(anyhow!("remove: tuples cannot be changed")),
+        other => Err(wrong_type(format!("remove expects a list as second argument, got {}", described(other)))),
+    }
+}
+
+fn builtin_append(args: &[Value]) -> Result<Value> {
+    if args.len() < 2 {
+        return Err(anyhow!("append requires two arguments: item and list"));
+    }
+    let item = &args[0];
+    match listed(&args[1]).as_ref() {
+        Value::List(xs) => {
+            // Append item to end of list
+            let mut result = xs.clone();
+            result.push(item.clone());
+            Ok(Value::List(result))
         }
-        Expr::ErrorResponse(status, message) => {
-            format!(
-                "error response with status {} and message {}",
-                dump_expr(status),
-                dump_expr(message)
-            )
+        Value::Tuple(_) => Err(anyhow!("append: tuples cannot be changed")),
+        other => Err(wrong_type(format!("append expects a list as second argument, got {}", described(other)))),
+    }
+}
+
+fn builtin_insert_at(args: &[Value]) -> Result<Value> {
+    if args.len() < 3 {
+        return Err(anyhow!(
+            "insert at requires three arguments: item, index, and list"
+        ));
+    }
+    let item = &args[0];
+    let index = match &args[1] {
+        v @ (Value::Int(_) | Value::Num(_)) => index_of(v) as isize,
+        other => return Err(wrong_type(format!("insert at expects a number as index, got {}", described(other)))),
+    };
+    match listed(&args[2]).as_ref() {
+        Value::List(xs) => {
+            let mut result = xs.clone();
+            let len = result.len() as isize;
+            // Handle negative indices (from end)
+            let idx = if index < 0 {
+                (len + index).max(0) as usize
+            } else {
+                index.min(len) as usize
+            };
+            result.insert(idx, item.clone());
+            Ok(Value::List(result))
         }
-        Expr::RequestField(request, field) => {
-            format!("{}[{}]", dump_expr(request), dump_expr(field))
+        Value::Tuple(_) => Err(anyhow!("insert at: tuples cannot be changed")),
+        other => Err(wrong_type(format!("insert at expects a list as third argument, got {}", described(other)))),
+    }
+}
+
+// Names bound in the scope captured by a `memoize` wrapper
+const MEMO_TARGET: &str = "__memo_target__";
+const MEMO_CACHE: &str = "__memo_cache__";
+
+/// Wrap a function so repeated calls with the same arguments reuse the first result
+fn builtin_memoize(args: &[Value]) -> Result<Value> {
+    let target = match args {
+        [Value::Func(f)] => f,
+        [other] => return Err(wrong_type(format!("memoize expects a function, got {}", described(other)))),
+        _ => return Err(anyhow!("memoize requires exactly one argument: a function")),
+    };
+    let mut vars = HashMap::new();
+    vars.insert(MEMO_TARGET.to_string(), Value::Func(target.clone()));
+    vars.insert(MEMO_CACHE.to_string(), Value::Dict(HashMap::new()));
+    Ok(Value::Func(Func {
+        name: target.name.clone(),
+        params: target.params.clone(),
+        body: FuncBody::Expr(Expr::Memoize(Box::new(Expr::Ident(
+            MEMO_TARGET.to_string(),
+        )))),
+        captured: Scope::new_env(vars, Some(Arc::clone(&target.captured))),
+    }))
+}
+
+/// Cache key for a call: the arguments' debug forms, so `1` and `"1"` differ
+fn memo_key(args: &[Value]) -> String {
+    args.iter()
+        .map(|a| format!("{:?}", a))
+        .collect::<Vec<_>>()
+        .join("\u{1f}")
+}
+
+/// The `request` a handler sees: its method, path, query, headers, path
+/// parameters and body as a dictionary, so `request["method"]` works
+#[cfg(feature = "web")]
+fn request_value(req: &crate::stdlib::http::HttpRequest) -> Value {
+    let strings = |map: &HashMap<String, String>| {
+        Value::Dict(map.iter().map(|(k, v)| (k.clone(), Value::Str(v.clone()))).collect())
+    };
+    Value::Dict(HashMap::from([
+        ("method".to_string(), Value::Str(req.method.clone())),
+        ("path".to_string(), Value::Str(req.path.clone())),
+        ("query".to_string(), strings(&req.query)),
+        ("headers".to_string(), strings(&req.headers)),
+        ("params".to_string(), strings(&req.path_params)),
+        ("body".to_string(), Value::Str(req.body.clone())),
+    ]))
+}
+
+/// A response as the `__response` dictionary `After each route` blocks see
+#[cfg(feature = "web")]
+fn response_value(resp: &HttpResponse) -> Value {
+    let headers = resp
+        .headers
+        .iter()
+        .map(|(k, v)| (k.clone(), Value::Str(v.clone())))
+        .collect();
+    Value::Dict(HashMap::from([
+        ("status".to_string(), Value::Int(resp.status as i64)),
+        ("headers".to_string(), Value::Dict(headers)),
+        ("body".to_string(), Value::Str(resp.body.clone())),
+    ]))
+}
+
+/// `<response> with etag`: a weak ETag from the body, so a client that sends
+/// it back in `If-None-Match` gets 304 Not Modified
+#[cfg(feature = "web")]
+fn with_etag(v: Value) -> Result<Value> {
+    let Value::HttpResponse(mut response) = v else {
+        bail!("with etag: expected a response, got {}", described(&v));
+    };
+    let etag = crate::stdlib::http::weak_etag(response.body.as_bytes());
+    set_header(&mut response, "ETag", etag);
+    Ok(Value::HttpResponse(response))
+}
+
+/// `<response> with cache for <n> seconds`: `Cache-Control: max-age=<n>`
+#[cfg(feature = "web")]
+fn with_cache(v: Value, secs: Value) -> Result<Value> {
+    let Value::HttpResponse(mut response) = v else {
+        bail!("with cache for: expected a response, got {}", described(&v));
+    };
+    let secs = match secs {
+        Value::Int(n) if n >= 0 => n,
+        other => bail!("with cache for: the time must be a whole number of seconds, got {}", described(&other)),
+    };
+    set_header(&mut response, "Cache-Control", format!("max-age={}", secs));
+    Ok(Value::HttpResponse(response))
+}
+
+/// Replace the header `name`, whatever case it was set in
+#[cfg(feature = "web")]
+fn set_header(response: &mut HttpResponse, name: &str, value: String) {
+    response.headers.retain(|k, _| !k.eq_ignore_ascii_case(name));
+    response.headers.insert(name.to_string(), value);
+}
+
+/// What a route hook left in `__response`: nothing, a response, or a
+/// dictionary like the one `response_value` makes
+#[cfg(feature = "web")]
+fn response_from_value(v: &Value) -> Result<Option<HttpResponse>> {
+    let fields = match v {
+        Value::Null => return Ok(None),
+        Value::HttpResponse(resp) => return Ok(Some(resp.clone())),
+        Value::Dict(fields) => fields,
+        other => bail!("__response must be a response or a dictionary, got {}", to_string(other)),
+    };
+    let status = match fields.get("status") {
+        None => 200,
+        Some(Value::Int(n)) if (100..=599).contains(n) => *n as u16,
+        Some(other) => bail!("__response status must be a number from 100 to 599, got {}", to_string(other)),
+    };
+    let headers = match fields.get("headers") {
+        None => HashMap::new(),
+        Some(Value::Dict(h)) => h.iter().map(|(k, v)| (k.clone(), to_string(v))).collect(),
+        Some(other) => bail!("__response headers must be a dictionary, got {}", to_string(other)),
+    };
+    let body = fields.get("body").map(to_string).unwrap_or_default();
+    Ok(Some(HttpResponse { status, headers, body }))
+}
+
+/// `pick` keeps only the named keys of a dictionary, `omit` drops them.
+/// Keys the dictionary does not have are skipped.
+fn select_fields(e: &Expr, dict: Value, keys: &[String]) -> Result<Value> {
+    let picking = matches!(e, Expr::Pick(..));
+    let Value::Dict(mut map) = dict else {
+        let phrase = if picking { "pick" } else { "omit" };
+        bail!("{}: expected a dictionary, got {}", phrase, to_string(&dict));
+    };
+    if picking {
+        map.retain(|k, _| keys.contains(k));
+    } else {
+        for key in keys {
+            map.remove(key);
         }
     }
+    Ok(Value::Dict(map))
 }
 
-fn format_number(n: f64) -> String {
-    if (n.fract()).abs() < f64::EPSILON {
-        format!("{}", n as i64)
-    } else {
-        format!("{}", n)
+/// `collect field` takes one key's value from each dictionary in a list,
+/// `None` where it is missing. `collect fields` keeps the named keys of each
+/// dictionary, as `pick` does.
+/// `aggregate list`: one pass over the dictionaries, keeping what each
+/// field needs. Missing fields and `None` are skipped except by `first` and
+/// `last`, which give the value in the first and last item.
+/// The base type a value is of; none for nothing, functions and handles
+fn base_type(v: &Value) -> Option<BaseType> {
+    match v {
+        Value::Int(_) | Value::Num(_) => Some(BaseType::Number),
+        Value::Str(_) => Some(BaseType::String),
+        Value::Bool(_) => Some(BaseType::Boolean),
+        Value::List(_) | Value::Tuple(_) | Value::LazyRange { .. } => Some(BaseType::List),
+        Value::Dict(_) | Value::OrderedDict(_) => Some(BaseType::Dict),
+        _ => None,
     }
 }
 
-fn iso_now() -> String {
-    // Avoid adding a chrono dependency; simple ISO-ish string
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default();
-    format!("{}", now.as_secs())
+/// What `validate` and `coerce` check: a dictionary
+fn schema_record<'a>(phrase: &str, schema: &str, v: &'a Value) -> Result<&'a HashMap<String, Value>> {
+    match v {
+        Value::Dict(d) => Ok(d),
+        other => Err(wrong_type(format!(
+            "{} as {}: expected a dictionary, got {}",
+            phrase,
+            schema,
+            described(other)
+        ))),
+    }
 }
 
-fn to_string(v: &Value) -> String {
+/// A message for each field of a schema that `record` gets wrong, in the
+/// order the fields were defined. A field set to nothing counts as missing.
+fn schema_errors(record: &HashMap<String, Value>, fields: &[SchemaField]) -> Vec<(String, String)> {
+    let mut errors = Vec::new();
+    for field in fields {
+        let message = match record.get(&field.name) {
+            None | Some(Value::Null) => field.required.then(|| "is required".to_string()),
+            Some(v) => (base_type(v) != BaseType::from_name(&field.type_name))
+                .then(|| format!("must be a {}, got {}", field.type_name, described(v))),
+        };
+        errors.extend(message.map(|msg| (field.name.clone(), msg)));
+    }
+    errors
+}
+
+/// `validate x against schema`: nothing if `v` matches, or its problems
+fn validate_against(v: &Value, schema: &Value) -> Result<Value> {
+    let problems = shape_problems(v, schema).map_err(|e| anyhow!("validate ... against: {}", e))?;
+    if problems.is_empty() {
+        return Ok(Value::Null);
+    }
+    Ok(Value::List(problems.into_iter().map(Value::Str).collect()))
+}
+
+/// How `v` differs from a schema written as a dictionary of field names
+/// and type names: text, number, boolean, list, dictionary or any, each
+/// maybe `optional` and `list of` another type. A dictionary in place of a
+/// type name is a schema for that field. Problems come sorted by field;
+/// fields the schema does not name are left alone.
+fn shape_problems(v: &Value, schema: &Value) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
     match v {
-        Value::Str(s) => s.clone(),
-        Value::Num(n) => format_number(*n),
-        Value::Bool(b) => {
-            if *b {
-                "True".to_string()
-            } else {
-                "False".to_string()
+        Value::Dict(record) => record_problems("", record, schema, &mut problems)?,
+        other => problems.push(format!("expected a dictionary, got {}", shape_name(other))),
+    }
+    Ok(problems)
+}
+
+fn record_problems(path: &str, record: &HashMap<String, Value>, schema: &Value, problems: &mut Vec<String>) -> Result<()> {
+    let Value::Dict(fields) = schema else {
+        bail!("a schema must be a dictionary of field names and types, got {}", described(schema));
+    };
+    let mut names: Vec<&String> = fields.keys().collect();
+    names.sort();
+    for name in names {
+        let field = if path.is_empty() { name.clone() } else { format!("{}.{}", path, name) };
+        let value = record.get(name).filter(|v| !matches!(v, Value::Null));
+        match (&fields[name], value) {
+            (Value::Dict(_), None) => problems.push(format!("{} is required", field)),
+            (nested @ Value::Dict(_), Some(Value::Dict(inner))) => record_problems(&field, inner, nested, problems)?,
+            (Value::Dict(_), Some(other)) => {
+                problems.push(format!("{} must be a dictionary, got {}", field, shape_name(other)))
+            }
+            (Value::Str(spec), value) => {
+                let (optional, type_name) = match spec.strip_prefix("optional ") {
+                    Some(rest) => (true, rest.trim()),
+                    None => (false, spec.trim()),
+                };
+                match value {
+                    Some(v) => type_problems(&field, v, type_name, problems)?,
+                    None => {
+                        schema_shape(&field, type_name)?;
+                        if !optional {
+                            problems.push(format!("{} is required", field));
+                        }
+                    }
+                }
             }
+            (other, _) => bail!("the type of {} must be a type name or a schema, got {}", field, described(other)),
         }
-        Value::Null => "None".to_string(),
-        Value::Func(f) => format!("<function {}>", f.name),
-        Value::List(xs) => format!(
-            "[{}]",
-            xs.iter().map(to_string).collect::<Vec<_>>().join(", ")
-        ),
-        Value::Dict(m) => {
-            let mut parts: Vec<String> = Vec::new();
-            for (k, v) in m.iter() {
-                parts.push(format!("\"{}\": {}", k, to_string(v)));
+    }
+    Ok(())
+}
+
+fn type_problems(field: &str, v: &Value, type_name: &str, problems: &mut Vec<String>) -> Result<()> {
+    if let Some(item_type) = type_name.strip_prefix("list of ") {
+        let item_type = item_type.trim();
+        schema_shape(field, item_type)?;
+        match v {
+            Value::List(items) | Value::Tuple(items) => {
+                for (n, item) in items.iter().enumerate() {
+                    type_problems(&format!("{} item {}", field, n + 1), item, item_type, problems)?;
+                }
             }
-            format!("{{{}}}", parts.join(", "))
+            other => problems.push(format!("{} must be a list of {}, got {}", field, item_type, shape_name(other))),
         }
-        Value::Error(e) => {
-            // Natural format when printing error values
-            if matches!(e.kind, ErrorKind::Custom(_)) {
-                format!("{} occurred: {}", e.type_string(), e.message)
-            } else {
-                format!("Error occurred: {} - {}", e.type_description(), e.message)
-            }
+        return Ok(());
+    }
+    match schema_shape(field, type_name)? {
+        Some(shape) if shape != shape_name(v) => {
+            problems.push(format!("{} must be {}, got {}", field, with_article(type_name), shape_name(v)))
         }
-        Value::WebServer(_) => "<WebServer>".to_string(),
-        Value::HttpRequest(_) => "<HttpRequest>".to_string(),
-        Value::HttpResponse(r) => format!("<HttpResponse status={}>", r.status),
-        Value::LiveReloadTracker(_) => "<LiveReloadTracker>".to_string(),
+        _ => {}
     }
+    Ok(())
 }
 
-fn to_num(v: Value) -> Result<f64> {
-    match v {
-        Value::Num(n) => Ok(n),
-        Value::Str(s) => s
-            .parse::<f64>()
-            .map_err(|_| anyhow!("Cannot convert string to number: {}", s)),
-        Value::Bool(b) => Ok(if b { 1.0 } else { 0.0 }),
-        Value::Null => Ok(0.0),
-        Value::Error(e) => Err(anyhow!("Cannot convert error to number: {}", e.message)),
-        _ => Err(anyhow!("Cannot convert value to number: {}", to_string(&v))),
+/// The `shape_name` a schema's type name allows, none for `any`; an error
+/// if it is not a type name
+fn schema_shape(field: &str, type_name: &str) -> Result<Option<&'static str>> {
+    if let Some(item_type) = type_name.strip_prefix("list of ") {
+        return schema_shape(field, item_type.trim()).map(|_| Some("list"));
     }
+    Ok(match type_name {
+        "text" | "string" => Some("text"),
+        "number" => Some("number"),
+        "boolean" => Some("boolean"),
+        "list" => Some("list"),
+        "dictionary" | "dict" => Some("dictionary"),
+        "any" => None,
+        _ => bail!(
+            "{} has the type \"{}\"; use text, number, boolean, list, dictionary, any or list of <type>",
+            field,
+            type_name
+        ),
+    })
 }
 
-thread_local! {
-    static FN_BLOCKS: std::cell::RefCell<HashMap<String, Program>> = std::cell::RefCell::new(HashMap::new());
+/// A value's kind in the words a schema uses
+fn shape_name(v: &Value) -> &'static str {
+    match v {
+        Value::Str(_) => "text",
+        Value::List(_) | Value::Tuple(_) | Value::LazyRange { .. } => "list",
+        Value::Dict(_) | Value::OrderedDict(_) => "dictionary",
+        other => type_name(other),
+    }
 }
 
-#[derive(Clone, Debug)]
-struct Frame {
-    locals: HashMap<String, Value>,
-    captured: Vec<HashMap<String, Value>>,
+fn with_article(type_name: &str) -> String {
+    match type_name {
+        "text" | "string" => "text".to_string(),
+        t if t.starts_with(['a', 'e', 'i', 'o', 'u']) => format!("an {}", t),
+        t => format!("a {}", t),
+    }
 }
 
-#[derive(Clone, Debug)]
-enum ControlFlow {
-    Return(Option<Value>),
-    Continue,
+fn aggregate(list: Value, fields: &[AggField], keys: Vec<Value>) -> Result<Value> {
+    let keys = keys
+        .into_iter()
+        .zip(fields)
+        .map(|(key, f)| match key {
+            Value::Str(key) => Ok(key),
+            other => Err(wrong_type(format!(
+                "aggregate list: the field of \"{}\" must be text, got {}",
+                f.alias,
+                described(&other)
+            ))),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let items = match listed(&list).into_owned() {
+        Value::List(items) | Value::Tuple(items) => items,
+        other => return Err(wrong_type(format!("aggregate list expects a list, got {}", described(&other)))),
+    };
+    // How many values were counted, and their total, smallest or largest
+    let mut seen = vec![(0i64, None::<Number>, Value::Null); fields.len()];
+    for (i, item) in items.iter().enumerate() {
+        let Value::Dict(map) = item else {
+            return Err(wrong_type(format!(
+                "aggregate list expects dictionaries; item {} was {}",
+                i + 1,
+                described(item)
+            )));
+        };
+        for ((field, key), (count, number, value)) in fields.iter().zip(&keys).zip(&mut seen) {
+            let v = map.get(key).unwrap_or(&Value::Null);
+            match field.function {
+                AggFunc::First if i == 0 => *value = v.clone(),
+                AggFunc::First => {}
+                AggFunc::Last => *value = v.clone(),
+                _ if matches!(v, Value::Null) => {}
+                AggFunc::Count => *count += 1,
+                function => {
+                    let Some(n) = number_of(v) else {
+                        return Err(wrong_type(format!(
+                            "aggregate list: {} of \"{}\" expects numbers; item {} was {}",
+                            function.words()[0],
+                            key,
+                            i + 1,
+                            described(v)
+                        )));
+                    };
+                    *count += 1;
+                    *number = Some(match (function, *number) {
+                        (_, None) => n,
+                        (AggFunc::Min, Some(m)) if m.compare(n) == Some(Ordering::Greater) => n,
+                        (AggFunc::Max, Some(m)) if m.compare(n) == Some(Ordering::Less) => n,
+                        (AggFunc::Sum | AggFunc::Avg, Some(m)) => m + n,
+                        (_, Some(m)) => m,
+                    });
+                }
+            }
+        }
+    }
+    let result = fields
+        .iter()
+        .zip(seen)
+        .map(|(field, (count, number, value))| {
+            let v = match field.function {
+                AggFunc::Sum => number.map_or(Value::Int(0), Value::from),
+                AggFunc::Count => Value::Int(count),
+                AggFunc::Min | AggFunc::Max => number.map_or(Value::Null, Value::from),
+                AggFunc::Avg => number.map_or(Value::Null, |total| Value::Num(total.as_f64() / count as f64)),
+                AggFunc::First | AggFunc::Last => value,
+            };
+            (field.alias.clone(), v)
+        })
+        .collect();
+    Ok(Value::Dict(result))
 }
 
-// ------------------------ BUILT-INS ------------------------
+fn collect_fields(e: &Expr, fields: Value, list: Value) -> Result<Value> {
+    let phrase = if matches!(e, Expr::Pluck(..)) { "collect fields" } else { "collect field" };
+    let keys = match fields {
+        Value::Str(key) => vec![key],
+        Value::List(keys) | Value::Tuple(keys) if matches!(e, Expr::Pluck(..)) => keys
+            .into_iter()
+            .map(|k| match k {
+                Value::Str(key) => Ok(key),
+                other => Err(wrong_type(format!("{} expects text keys, got {}", phrase, described(&other)))),
+            })
+            .collect::<Result<_>>()?,
+        other => return Err(wrong_type(format!("{} expects text keys, got {}", phrase, described(&other)))),
+    };
+    let items = match listed(&list).into_owned() {
+        Value::List(items) | Value::Tuple(items) => items,
+        other => return Err(wrong_type(format!("{} expects a list, got {}", phrase, described(&other)))),
+    };
+    let collected = items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let Value::Dict(mut map) = item else {
+                return Err(wrong_type(format!(
+                    "{} expects dictionaries; item {} was {}",
+                    phrase,
+                    i + 1,
+                    described(&item)
+                )));
+            };
+            Ok(match e {
+                Expr::Pluck(..) => {
+                    map.retain(|k, _| keys.contains(k));
+                    Value::Dict(map)
+                }
+                _ => map.remove(&keys[0]).unwrap_or(Value::Null),
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(Value::List(collected))
+}
 
-fn builtin_range(args: &[Value]) -> Result<Value> {
-    let nums: Vec<f64> = match args.len() {
-        1 => {
-            if let Value::Num(n) = args[0] {
-                (0..n.max(0.0) as i64).map(|i| i as f64).collect()
-            } else {
-                vec![]
-            }
-        }
-        2 => {
-            if let (Value::Num(a), Value::Num(b)) = (&args[0], &args[1]) {
-                let (a, b) = (*a as i64, *b as i64);
-                (a..b).map(|i| i as f64).collect()
-            } else {
-                vec![]
-            }
+/// `parse url`, `build url from`, `url encode` and `url decode`, given the
+/// phrase and its evaluated operand
+fn eval_url(e: &Expr, arg: Value) -> Result<Value> {
+    use crate::stdlib::url;
+    match e {
+        Expr::ParseUrl(_) => {
+            let Value::Str(text) = arg else {
+                bail!("parse url: expected text, got {}", to_string(&arg));
+            };
+            let parts = url::parse_url(&text).map_err(|err| anyhow!("parse url: {}", err))?;
+            let text_or_null = |part: Option<String>| part.map_or(Value::Null, Value::Str);
+            let dict = HashMap::from([
+                ("scheme".to_string(), Value::Str(parts.scheme)),
+                ("host".to_string(), text_or_null(parts.host)),
+                (
+                    "port".to_string(),
+                    parts.port.map_or(Value::Null, |p| Value::Int(p.into())),
+                ),
+                ("path".to_string(), Value::Str(parts.path)),
+                ("query".to_string(), text_or_null(parts.query)),
+                ("fragment".to_string(), text_or_null(parts.fragment)),
+            ]);
+            Ok(Value::Dict(dict))
         }
-        3 => {
-            if let (Value::Num(a), Value::Num(b), Value::Num(s)) = (&args[0], &args[1], &args[2]) {
-                let (mut i, end, step) = (*a as i64, *b as i64, *s as i64);
-                if step == 0 {
-                    return Ok(Value::List(Vec::new()));
-                }
-                let mut out = Vec::new();
-                if step > 0 {
-                    while i < end {
-                        out.push(i as f64);
-                        i += step;
-                    }
-                } else {
-                    while i > end {
-                        out.push(i as f64);
-                        i += step;
-                    }
+        Expr::BuildUrl(_) => {
+            let Value::Dict(dict) = arg else {
+                bail!("build url from: expected a dictionary like the one parse url returns");
+            };
+            let text = |key: &str| -> Result<Option<String>> {
+                match dict.get(key) {
+                    None | Some(Value::Null) => Ok(None),
+                    Some(Value::Str(s)) => Ok(Some(s.clone())),
+                    Some(other) => bail!("build url from: \"{}\" must be text, got {}", key, to_string(other)),
                 }
-                out
-            } else {
-                vec![]
-            }
+            };
+            let port = match dict.get("port") {
+                None | Some(Value::Null) => None,
+                Some(v) => match whole_number(v).map(u16::try_from) {
+                    Some(Ok(p)) => Some(p),
+                    _ => bail!(
+                        "build url from: \"port\" must be a whole number from 0 to 65535, got {}",
+                        to_string(v)
+                    ),
+                },
+            };
+            let parts = url::UrlParts {
+                scheme: text("scheme")?
+                    .ok_or_else(|| anyhow!("build url from: the dictionary has no \"scheme\""))?,
+                host: text("host")?,
+                port,
+                path: text("path")?.unwrap_or_default(),
+                query: text("query")?,
+                fragment: text("fragment")?,
+            };
+            let built = url::build_url(&parts).map_err(|err| anyhow!("build url from: {}", err))?;
+            Ok(Value::Str(built))
         }
-        _ => vec![],
-    };
-    Ok(Value::List(nums.into_iter().map(Value::Num).collect()))
+        Expr::UrlEncode(_) => Ok(Value::Str(url::url_encode(&to_string(&arg)))),
+        Expr::UrlDecode(_) => {
+            let decoded = url::url_decode(&to_string(&arg)).map_err(|err| anyhow!("url decode: {}", err))?;
+            Ok(Value::Str(decoded))
+        }
+        _ => unreachable!("eval_url called with {:?}", e),
+    }
 }
 
-fn builtin_join(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Ok(Value::Str(String::new()));
-    }
-    let sep = if args.len() >= 2 {
-        to_string(&args[1])
-    } else {
-        String::new()
+/// `indent ... by`, `remove common indent from` and `wrap ... at`
+fn eval_layout(e: &Expr, text: Value, width: Option<Value>) -> Result<Value> {
+    use crate::stdlib::text;
+    let text = to_string(&text);
+    let count = |least: i64| match width.as_ref().and_then(whole_number) {
+        Some(n) if n >= least => Ok(n as usize),
+        _ => Err(width.as_ref().map_or_else(String::new, to_string)),
     };
-    match &args[0] {
-        Value::List(xs) => Ok(Value::Str(
-            xs.iter().map(to_string).collect::<Vec<_>>().join(&sep),
-        )),
-        other => Ok(Value::Str(to_string(other))),
+    match e {
+        Expr::IndentBy(..) => match count(0) {
+            Ok(spaces) => Ok(Value::Str(text::indent(&text, spaces))),
+            Err(got) => bail!("indent: the number of spaces must be a whole number, 0 or more, got {}", got),
+        },
+        Expr::WrapText(..) => match count(1) {
+            Ok(width) => Ok(Value::Str(text::wrap(&text, width))),
+            Err(got) => bail!("wrap: the width must be a whole number of characters, 1 or more, got {}", got),
+        },
+        Expr::Dedent(_) => Ok(Value::Str(text::dedent(&text))),
+        _ => unreachable!("eval_layout called with {:?}", e),
     }
 }
 
-fn builtin_split(args: &[Value]) -> Result<Value> {
-    if args.len() < 2 {
-        return Ok(Value::List(Vec::new()));
+/// `collection` with the item at `key` replaced, for `Set name[key] to ...`.
+/// List positions count from the end when negative; a dictionary key is
+/// added if it is new.
+fn set_item(name: &str, collection: Value, key: Value, value: Value) -> Result<Value> {
+    match (listed(&collection).into_owned(), key) {
+        (Value::List(mut items), key @ (Value::Int(_) | Value::Num(_))) => {
+            let idx = index_of(&key);
+            let len = items.len() as i32;
+            let actual_idx = if idx < 0 { len + idx } else { idx };
+            if actual_idx < 0 || actual_idx >= len {
+                let msg = format!("List index out of range: {} (list length: {})", idx, len);
+                return Err(anyhow!("{}", hints::with_hint(&msg)));
+            }
+            items[actual_idx as usize] = value;
+            Ok(Value::List(items))
+        }
+        (Value::Dict(mut map), Value::Str(key)) => {
+            map.insert(key, value);
+            Ok(Value::Dict(map))
+        }
+        (Value::Tuple(_), _) => bail!("Cannot set an item of {}: tuples cannot be changed", name),
+        (Value::List(_), key) => bail!("Cannot set an item of list {} at {}: positions are numbers", name, trace_value(&key)),
+        (Value::Dict(_), key) => bail!("Cannot set an item of dictionary {} at {}: keys are text", name, trace_value(&key)),
+        (other, _) => bail!("Cannot set an item of {}: it is {}, not a list or dictionary", name, trace_value(&other)),
     }
-    let s = to_string(&args[0]);
-    let sep = to_string(&args[1]);
-    let parts = if sep.is_empty() {
-        s.chars().map(|c| Value::Str(c.to_string())).collect()
-    } else {
-        s.split(&sep).map(|p| Value::Str(p.to_string())).collect()
-    };
-    Ok(Value::List(parts))
 }
 
-fn builtin_length(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Ok(Value::Num(0.0));
-    }
-    match &args[0] {
-        Value::List(xs) => Ok(Value::Num(xs.len() as f64)),
-        Value::Str(s) => Ok(Value::Num(s.chars().count() as f64)),
-        Value::Dict(m) => Ok(Value::Num(m.len() as f64)),
-        _ => Ok(Value::Num(0.0)),
+// Helper function for value equality comparison
+/// Values for the names of a destructuring Set, one per name
+fn destructure(names: &[String], value: Value) -> Result<Vec<Value>> {
+    let (kind, items) = match value {
+        Value::Tuple(items) => ("tuple", items),
+        Value::List(items) => ("list", items),
+        Value::LazyRange { .. } => return destructure(names, listed(&value).into_owned()),
+        other => bail!(
+            "Cannot destructure {} into {}: expected a tuple or list",
+            to_string(&other),
+            names.join(", ")
+        ),
+    };
+    if items.len() != names.len() {
+        bail!(
+            "Cannot destructure a {} of {} items into {} names ({})",
+            kind,
+            items.len(),
+            names.len(),
+            names.join(", ")
+        );
     }
+    Ok(items)
 }
 
-fn builtin_sum(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Ok(Value::Num(0.0));
-    }
-    match &args[0] {
-        Value::List(xs) => {
-            if xs.is_empty() {
-                return Ok(Value::Num(0.0));
-            }
-            let mut total = 0.0;
-            for (i, v) in xs.iter().enumerate() {
-                match v {
-                    Value::Num(n) => total += n,
-                    other => {
-                        return Err(anyhow!(
-                            "total of expects numbers; item {} was {}",
-                            i,
-                            to_string(other)
-                        ))
-                    }
-                }
-            }
-            Ok(Value::Num(total))
+/// Environment variables whose names start with `prefix`, as program
+/// globals. With `strip`, the prefix is removed and the rest lowercased, so
+/// `APP_PORT` becomes `port`. Numeric values become numbers.
+fn env_vars_with_prefix(prefix: &str, strip: bool) -> Vec<(String, Value)> {
+    let mut vars: Vec<(String, Value)> = std::env::vars()
+        .filter_map(|(key, raw)| {
+            let rest = key.strip_prefix(prefix)?;
+            let name = if strip { rest.to_lowercase() } else { key.clone() };
+            if name.is_empty() {
+                return None;
+            }
+            let value = match number::parse_number(&raw) {
+                Some(n) => n.into(),
+                None => Value::Str(raw),
+            };
+            Some((name, value))
+        })
+        .collect();
+    // Names that collide after lowercasing resolve the same way every run
+    vars.sort_by(|a, b| a.0.cmp(&b.0));
+    vars
+}
+
+/// Check and stringify the operands of `Print table`, then lay them out
+fn table_text(headers: &Value, rows: &Value, style: TableStyle) -> Result<String> {
+    let Value::List(headers) = headers else {
+        bail!("Print table: headers must be a list of strings");
+    };
+    let headers = headers
+        .iter()
+        .map(|h| match h {
+            Value::Str(s) => Ok(s.clone()),
+            other => Err(anyhow!(
+                "Print table: headers must be a list of strings, found {}",
+                to_string(other)
+            )),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let Value::List(rows) = rows else {
+        bail!("Print table: rows must be a list of lists");
+    };
+    let mut cells = Vec::with_capacity(rows.len());
+    for (i, row) in rows.iter().enumerate() {
+        let (Value::List(items) | Value::Tuple(items)) = row else {
+            bail!("Print table: row {} is not a list", i + 1);
+        };
+        if items.len() > headers.len() {
+            bail!(
+                "Print table: row {} has {} cells but there are only {} headers",
+                i + 1,
+                items.len(),
+                headers.len()
+            );
         }
-        Value::Num(n) => Ok(Value::Num(*n)),
-        other => Err(anyhow!(
-            "total of expects a list or a number, got {}",
-            to_string(other)
-        )),
+        cells.push(items.iter().map(to_string).collect());
     }
+    Ok(table::format_table(&headers, &cells, style))
 }
 
-fn builtin_min(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Err(anyhow!("smallest in requires at least one argument"));
-    }
-    match &args[0] {
-        Value::List(xs) => {
-            if xs.is_empty() {
-                return Err(anyhow!("smallest in an empty list is undefined"));
+// ------------------------ COLLECTIONS MODULE ------------------------
+
+/// A parsed JSON value as a PohLang value
+fn json_to_value(json: &serde_json::Value) -> Result<Value> {
+    use serde_json::Value as JsonValue;
+    match json {
+        JsonValue::Null => Ok(Value::Null),
+        JsonValue::Bool(b) => Ok(Value::Bool(*b)),
+        JsonValue::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Ok(Value::Int(i))
+            } else if let Some(f) = n.as_f64() {
+                Ok(Value::Num(f))
+            } else {
+                bail!("JSON number out of range")
             }
-            let mut min_val = None::<f64>;
-            for (i, v) in xs.iter().enumerate() {
-                match v {
-                    Value::Num(n) => {
-                        min_val = Some(match min_val {
-                            Some(m) => m.min(*n),
-                            None => *n,
-                        });
-                    }
-                    other => {
-                        return Err(anyhow!(
-                            "smallest in expects numbers; item {} was {}",
-                            i,
-                            to_string(other)
-                        ))
-                    }
-                }
+        }
+        JsonValue::String(s) => Ok(Value::Str(s.clone())),
+        JsonValue::Array(arr) => {
+            let mut values = Vec::new();
+            for item in arr {
+                values.push(json_to_value(item)?);
             }
-            Ok(Value::Num(min_val.unwrap()))
+            Ok(Value::List(values))
+        }
+        JsonValue::Object(obj) => {
+            let mut map = HashMap::new();
+            for (k, v) in obj {
+                map.insert(k.clone(), json_to_value(v)?);
+            }
+            Ok(Value::Dict(map))
         }
-        Value::Num(n) => Ok(Value::Num(*n)),
-        other => Err(anyhow!(
-            "smallest in expects a list or a number, got {}",
-            to_string(other)
-        )),
     }
 }
 
-fn builtin_max(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Err(anyhow!("largest in requires at least one argument"));
-    }
-    match &args[0] {
-        Value::List(xs) => {
-            if xs.is_empty() {
-                return Err(anyhow!("largest in an empty list is undefined"));
-            }
-            let mut max_val = None::<f64>;
-            for (i, v) in xs.iter().enumerate() {
-                match v {
-                    Value::Num(n) => {
-                        max_val = Some(match max_val {
-                            Some(m) => m.max(*n),
-                            None => *n,
-                        });
-                    }
-                    other => {
-                        return Err(anyhow!(
-                            "largest in expects numbers; item {} was {}",
-                            i,
-                            to_string(other)
-                        ))
-                    }
-                }
+/// A PohLang value as JSON, for `convert to json` and the json phrases
+fn value_to_json(value: &Value) -> Result<serde_json::Value> {
+    use serde_json::Value as JsonValue;
+    match value {
+        Value::Null => Ok(JsonValue::Null),
+        Value::Bool(b) => Ok(JsonValue::Bool(*b)),
+        Value::Int(i) => Ok(JsonValue::Number((*i).into())),
+        Value::Num(n) => Ok(serde_json::Number::from_f64(*n)
+            .map(JsonValue::Number)
+            .unwrap_or(JsonValue::Null)),
+        Value::Str(s) => Ok(JsonValue::String(s.clone())),
+        Value::List(vec) | Value::Tuple(vec) => {
+            let mut arr = Vec::new();
+            for item in vec {
+                arr.push(value_to_json(item)?);
+            }
+            Ok(JsonValue::Array(arr))
+        }
+        Value::LazyRange { .. } => value_to_json(&listed(value)),
+        Value::Dict(map) => {
+            // serde_json's Map is sorted by key unless its preserve_order
+            // feature is on; sorting here keeps the output stable either way
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let mut obj = serde_json::Map::new();
+            for (k, v) in entries {
+                obj.insert(k.clone(), value_to_json(v)?);
+            }
+            Ok(JsonValue::Object(obj))
+        }
+        Value::Set(s) => value_to_json(&Value::List(sorted_members(s))),
+        Value::Func(_) => bail!("Cannot convert function to JSON"),
+        Value::Error(e) => bail!("Cannot convert error to JSON: {}", e.message),
+        #[cfg(feature = "web")]
+        Value::WebServer(_) => bail!("Cannot convert web server to JSON"),
+        #[cfg(feature = "web")]
+        Value::HttpRequest(_) => bail!("Cannot convert HTTP request to JSON"),
+        #[cfg(feature = "web")]
+        Value::HttpResponse(_) => bail!("Cannot convert HTTP response to JSON"),
+        #[cfg(feature = "web")]
+        Value::LiveReloadTracker(_) => bail!("Cannot convert LiveReloadTracker to JSON"),
+        Value::Stack(_) | Value::Queue(_) | Value::PriorityQueue(_) => {
+            let arr = collection_items(value)
+                .iter()
+                .map(value_to_json)
+                .collect::<Result<Vec<_>>>()?;
+            Ok(JsonValue::Array(arr))
+        }
+        Value::OrderedDict(od) => {
+            let mut obj = serde_json::Map::new();
+            for (k, v) in od.lock().unwrap().iter() {
+                obj.insert(k.clone(), value_to_json(v)?);
             }
-            Ok(Value::Num(max_val.unwrap()))
+            Ok(JsonValue::Object(obj))
         }
-        Value::Num(n) => Ok(Value::Num(*n)),
-        other => Err(anyhow!(
-            "largest in expects a list or a number, got {}",
-            to_string(other)
-        )),
+        Value::Regex(r) => bail!("Cannot convert pattern r\"{}\" to JSON", r.as_str()),
+        #[cfg(feature = "web")]
+        Value::EventStream(_) => bail!("Cannot convert an event stream to JSON"),
+        #[cfg(feature = "web")]
+        Value::WebSocket(_) => bail!("Cannot convert a websocket to JSON"),
+        Value::EventEmitter(_) => bail!("Cannot convert an event emitter to JSON"),
+        Value::Observed(v, _) => value_to_json(v),
+        Value::Future(t) => bail!("Cannot convert task {} to JSON; wait for it and use {}_result", t.name(), t.name()),
+        Value::Channel(_) => bail!("Cannot convert a channel to JSON; receive from it and convert what arrives"),
     }
 }
 
-fn builtin_abs(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Err(anyhow!("abs requires one argument"));
+/// Exports of `Import system "collections"`, implemented natively
+fn collections_module() -> HashMap<String, Value> {
+    let natives: &[(&str, NativeFn)] = &[
+        ("new_stack", collections_new_stack),
+        ("new_queue", collections_new_queue),
+        ("new_priority_queue", collections_new_priority_queue),
+        ("ordered_dict", collections_ordered_dict),
+        ("push", collections_push),
+        ("pop", collections_pop),
+        ("peek", collections_peek),
+        ("is_empty", collections_is_empty),
+        ("size", collections_size),
+        ("enqueue", collections_enqueue),
+        ("dequeue", collections_dequeue),
+        ("pop_highest", collections_pop_highest),
+        ("set", collections_set),
+        ("get", collections_get),
+        ("remove", collections_remove),
+        ("keys", collections_keys),
+        ("values", collections_values),
+    ];
+    native_module("collections", natives)
+}
+
+/// Exports of `Import system "json_path"`: JSONPath queries such as
+/// `$.users[*].name` over parsed JSON or JSON text
+fn json_path_module() -> HashMap<String, Value> {
+    let natives: &[(&str, NativeFn)] = &[
+        ("query", json_path_query),
+        ("query_first", json_path_query_first),
+        ("query_all", json_path_query_all),
+    ];
+    native_module("json_path", natives)
+}
+
+/// A system module's native functions, named `module::name` in messages
+fn native_module(module: &str, natives: &[(&str, NativeFn)]) -> HashMap<String, Value> {
+    natives
+        .iter()
+        .map(|&(name, native)| {
+            let f = Func {
+                name: format!("{}::{}", module, name),
+                params: Vec::new(),
+                body: FuncBody::Native(native),
+                captured: Scope::new_env(HashMap::new(), None),
+            };
+            (name.to_string(), Value::Func(f))
+        })
+        .collect()
+}
+
+/// Every value `path` matches in `json`, in document order. `json` is a
+/// parsed value such as a dictionary, or text that is parsed first.
+fn json_path_select(what: &str, json: &Value, path: &Value) -> Result<Vec<Value>> {
+    let Value::Str(path) = path else {
+        bail!("{}: the path must be text such as \"$.users[*].name\", got {}", what, described(path));
+    };
+    let json = match json {
+        Value::Str(text) => serde_json::from_str(text)
+            .map_err(|e| anyhow!("{}: the text is not valid JSON: {}", what, e))?,
+        other => value_to_json(other).map_err(|e| anyhow!("{}: {}", what, e))?,
+    };
+    let found = jsonpath_lib::select(&json, path)
+        .map_err(|e| anyhow!("{}: '{}' is not a valid JSONPath: {}", what, path, e.to_string().trim_end()))?;
+    found.into_iter().map(json_to_value).collect()
+}
+
+fn json_path_query(args: &[Value]) -> Result<Value> {
+    match args {
+        [json, path] => Ok(Value::List(json_path_select("json_path::query", json, path)?)),
+        _ => bail!("json_path::query expects the JSON and a path"),
+    }
+}
+
+fn json_path_query_all(args: &[Value]) -> Result<Value> {
+    match args {
+        [json, path] => Ok(Value::List(json_path_select("json_path::query_all", json, path)?)),
+        _ => bail!("json_path::query_all expects the JSON and a path"),
     }
-    match &args[0] {
-        Value::Num(n) => Ok(Value::Num(n.abs())),
-        _ => Err(anyhow!("abs requires a number")),
+}
+
+fn json_path_query_first(args: &[Value]) -> Result<Value> {
+    match args {
+        [json, path] => Ok(json_path_select("json_path::query_first", json, path)?
+            .into_iter()
+            .next()
+            .unwrap_or(Value::Null)),
+        _ => bail!("json_path::query_first expects the JSON and a path"),
     }
 }
 
-fn builtin_round(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Err(anyhow!("round requires one argument"));
+/// Number of items in a collections-module value
+fn collection_len(v: &Value) -> Option<usize> {
+    match v {
+        Value::Stack(s) => Some(s.lock().unwrap().len()),
+        Value::Queue(q) => Some(q.lock().unwrap().len()),
+        Value::PriorityQueue(pq) => Some(pq.lock().unwrap().len()),
+        Value::OrderedDict(od) => Some(od.lock().unwrap().len()),
+        _ => None,
     }
-    match &args[0] {
-        Value::Num(n) => Ok(Value::Num(n.round())),
-        _ => Err(anyhow!("round requires a number")),
+}
+
+/// Items of a stack (bottom to top), queue (front to back) or priority queue
+/// (highest first)
+fn collection_items(v: &Value) -> Vec<Value> {
+    match v {
+        Value::Stack(s) => s.lock().unwrap().clone(),
+        Value::Queue(q) => q.lock().unwrap().iter().cloned().collect(),
+        Value::PriorityQueue(pq) => pq
+            .lock()
+            .unwrap()
+            .items_by_priority()
+            .into_iter()
+            .cloned()
+            .collect(),
+        _ => Vec::new(),
     }
 }
 
-fn builtin_floor(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Err(anyhow!("floor requires one argument"));
+fn no_args(name: &str, args: &[Value]) -> Result<()> {
+    if !args.is_empty() {
+        bail!("collections::{} takes no arguments", name);
     }
-    match &args[0] {
-        Value::Num(n) => Ok(Value::Num(n.floor())),
-        _ => Err(anyhow!("floor requires a number")),
+    Ok(())
+}
+
+fn collections_new_stack(args: &[Value]) -> Result<Value> {
+    no_args("new_stack", args)?;
+    Ok(Value::Stack(Arc::new(Mutex::new(Vec::new()))))
+}
+
+fn collections_new_queue(args: &[Value]) -> Result<Value> {
+    no_args("new_queue", args)?;
+    Ok(Value::Queue(Arc::new(Mutex::new(VecDeque::new()))))
+}
+
+fn collections_new_priority_queue(args: &[Value]) -> Result<Value> {
+    no_args("new_priority_queue", args)?;
+    Ok(Value::PriorityQueue(Arc::new(Mutex::new(PriorityQueue::new()))))
+}
+
+fn collections_ordered_dict(args: &[Value]) -> Result<Value> {
+    no_args("ordered_dict", args)?;
+    Ok(Value::OrderedDict(Arc::new(Mutex::new(OrderedDict::new()))))
+}
+
+/// `push(stack, item)` or `push(priority_queue, item, priority)`; returns the collection
+fn collections_push(args: &[Value]) -> Result<Value> {
+    match args {
+        [Value::Stack(s), item] => s.lock().unwrap().push(item.clone()),
+        [Value::PriorityQueue(pq), item, priority @ (Value::Int(_) | Value::Num(_))] => {
+            pq.lock().unwrap().push(item.clone(), to_num(priority.clone())?)
+        }
+        [Value::PriorityQueue(_), _, other] => bail!(
+            "collections::push expects a number as priority, got {}",
+            to_string(other)
+        ),
+        [Value::PriorityQueue(_), ..] => {
+            bail!("collections::push on a priority queue needs an item and a priority")
+        }
+        _ => bail!("collections::push expects a stack and an item"),
     }
+    Ok(args[0].clone())
 }
 
-fn builtin_ceil(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Err(anyhow!("ceil requires one argument"));
+fn collections_pop(args: &[Value]) -> Result<Value> {
+    match args {
+        [Value::Stack(s)] => s
+            .lock()
+            .unwrap()
+            .pop()
+            .ok_or_else(|| anyhow!("collections::pop called on an empty stack")),
+        _ => bail!("collections::pop expects a stack"),
     }
-    match &args[0] {
-        Value::Num(n) => Ok(Value::Num(n.ceil())),
-        _ => Err(anyhow!("ceil requires a number")),
+}
+
+fn collections_peek(args: &[Value]) -> Result<Value> {
+    let item = match args {
+        [Value::Stack(s)] => s.lock().unwrap().last().cloned(),
+        [Value::Queue(q)] => q.lock().unwrap().front().cloned(),
+        [Value::PriorityQueue(pq)] => pq.lock().unwrap().peek().cloned(),
+        _ => bail!("collections::peek expects a stack, queue or priority queue"),
+    };
+    item.ok_or_else(|| anyhow!("collections::peek called on an empty collection"))
+}
+
+fn collections_is_empty(args: &[Value]) -> Result<Value> {
+    match args {
+        [c] => match collection_len(c) {
+            Some(len) => Ok(Value::Bool(len == 0)),
+            None => bail!("collections::is_empty expects a collections value, got {}", to_string(c)),
+        },
+        _ => bail!("collections::is_empty expects one argument"),
     }
 }
 
-fn builtin_uppercase(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Ok(Value::Str(String::new()));
+fn collections_size(args: &[Value]) -> Result<Value> {
+    match args {
+        [c] => match collection_len(c) {
+            Some(len) => Ok(Value::Int(len as i64)),
+            None => bail!("collections::size expects a collections value, got {}", to_string(c)),
+        },
+        _ => bail!("collections::size expects one argument"),
     }
-    Ok(Value::Str(to_string(&args[0]).to_uppercase()))
 }
 
-fn builtin_lowercase(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Ok(Value::Str(String::new()));
+/// `enqueue(queue, item)`; returns the queue
+fn collections_enqueue(args: &[Value]) -> Result<Value> {
+    match args {
+        [Value::Queue(q), item] => q.lock().unwrap().push_back(item.clone()),
+        _ => bail!("collections::enqueue expects a queue and an item"),
     }
-    Ok(Value::Str(to_string(&args[0]).to_lowercase()))
+    Ok(args[0].clone())
 }
 
-fn builtin_trim(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Ok(Value::Str(String::new()));
+fn collections_dequeue(args: &[Value]) -> Result<Value> {
+    match args {
+        [Value::Queue(q)] => q
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| anyhow!("collections::dequeue called on an empty queue")),
+        _ => bail!("collections::dequeue expects a queue"),
     }
-    Ok(Value::Str(to_string(&args[0]).trim().to_string()))
 }
 
-fn builtin_first(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Err(anyhow!("first requires one argument"));
+fn collections_pop_highest(args: &[Value]) -> Result<Value> {
+    match args {
+        [Value::PriorityQueue(pq)] => pq
+            .lock()
+            .unwrap()
+            .pop_highest()
+            .ok_or_else(|| anyhow!("collections::pop_highest called on an empty priority queue")),
+        _ => bail!("collections::pop_highest expects a priority queue"),
     }
-    match &args[0] {
-        Value::List(xs) => {
-            if xs.is_empty() {
-                Err(anyhow!("first called on empty list"))
-            } else {
-                Ok(xs[0].clone())
-            }
-        }
-        Value::Str(s) => {
-            if s.is_empty() {
-                Err(anyhow!("first called on empty string"))
-            } else {
-                Ok(Value::Str(s.chars().next().unwrap().to_string()))
-            }
+}
+
+/// `set(ordered_dict, key, value)`; returns the dictionary
+fn collections_set(args: &[Value]) -> Result<Value> {
+    match args {
+        [Value::OrderedDict(od), key, value] => {
+            od.lock().unwrap().insert(to_string(key), value.clone())
         }
-        _ => Err(anyhow!("first requires a list or string")),
+        _ => bail!("collections::set expects an ordered dict, a key and a value"),
     }
+    Ok(args[0].clone())
 }
 
-fn builtin_last(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Err(anyhow!("last requires one argument"));
-    }
-    match &args[0] {
-        Value::List(xs) => {
-            if xs.is_empty() {
-                Err(anyhow!("last called on empty list"))
-            } else {
-                Ok(xs[xs.len() - 1].clone())
-            }
+fn collections_get(args: &[Value]) -> Result<Value> {
+    match args {
+        [Value::OrderedDict(od), key] => {
+            let key = to_string(key);
+            od.lock()
+                .unwrap()
+                .get(&key)
+                .cloned()
+                .ok_or_else(|| anyhow!("Key not found in ordered dict: \"{}\"", key))
         }
-        Value::Str(s) => {
-            if s.is_empty() {
-                Err(anyhow!("last called on empty string"))
-            } else {
-                Ok(Value::Str(s.chars().last().unwrap().to_string()))
-            }
-        }
-        _ => Err(anyhow!("last requires a list or string")),
+        _ => bail!("collections::get expects an ordered dict and a key"),
     }
 }
 
-fn builtin_reverse(args: &[Value]) -> Result<Value> {
-    if args.is_empty() {
-        return Err(anyhow!("reverse requires one argument"));
-    }
-    match &args[0] {
-        Value::List(xs) => {
-            let mut rev = xs.clone();
-            rev.reverse();
-            Ok(Value::List(rev))
+fn collections_remove(args: &[Value]) -> Result<Value> {
+    match args {
+        [Value::OrderedDict(od), key] => {
+            let key = to_string(key);
+            od.lock()
+                .unwrap()
+                .remove(&key)
+                .ok_or_else(|| anyhow!("Key not found in ordered dict: \"{}\"", key))
         }
-        Value::Str(s) => Ok(Value::Str(s.chars().rev().collect())),
-        _ => Err(anyhow!("reverse requires a list or string")),
+        _ => bail!("collections::remove expects an ordered dict and a key"),
     }
 }
 
-fn builtin_contains(args: &[Value]) -> Result<Value> {
-    if args.len() < 2 {
-        return Err(anyhow!(
-            "contains requires two arguments: item and collection"
-        ));
-    }
-    let item = &args[0];
-    match &args[1] {
-        Value::List(xs) => {
-            // Check if item exists in list (using value equality)
-            for v in xs {
-                if values_equal(item, v) {
-                    return Ok(Value::Bool(true));
-                }
-            }
-            Ok(Value::Bool(false))
-        }
-        Value::Str(s) => {
-            // Check if string contains substring
-            let needle = to_string(item);
-            Ok(Value::Bool(s.contains(&needle)))
-        }
-        Value::Dict(m) => {
-            // Check if key exists in dictionary
-            let key = to_string(item);
-            Ok(Value::Bool(m.contains_key(&key)))
-        }
-        _ => Err(anyhow!(
-            "contains expects a list, string, or dictionary as second argument"
+fn collections_keys(args: &[Value]) -> Result<Value> {
+    match args {
+        [Value::OrderedDict(od)] => Ok(Value::List(
+            od.lock()
+                .unwrap()
+                .iter()
+                .map(|(k, _)| Value::Str(k.clone()))
+                .collect(),
         )),
+        _ => bail!("collections::keys expects an ordered dict"),
     }
 }
 
-fn builtin_remove(args: &[Value]) -> Result<Value> {
-    if args.len() < 2 {
-        return Err(anyhow!("remove requires two arguments: item and list"));
-    }
-    let item = &args[0];
-    match &args[1] {
-        Value::List(xs) => {
-            // Remove first occurrence of item from list
-            let mut result = Vec::new();
-            let mut removed = false;
-            for v in xs {
-                if !removed && values_equal(item, v) {
-                    removed = true;
-                } else {
-                    result.push(v.clone());
-                }
-            }
-            Ok(Value::List(result))
-        }
-        _ => Err(anyhow!("remove expects a list as second argument")),
+fn collections_values(args: &[Value]) -> Result<Value> {
+    match args {
+        [Value::OrderedDict(od)] => Ok(Value::List(
+            od.lock().unwrap().iter().map(|(_, v)| v.clone()).collect(),
+        )),
+        _ => bail!("collections::values expects an ordered dict"),
     }
 }
 
-fn builtin_append(args: &[Value]) -> Result<Value> {
-    if args.len() < 2 {
-        return Err(anyhow!("append requires two arguments: item and list"));
-    }
-    let item = &args[0];
-    match &args[1] {
-        Value::List(xs) => {
-            // Append item to end of list
-            let mut result = xs.clone();
-            result.push(item.clone());
-            Ok(Value::List(result))
-        }
-        _ => Err(anyhow!("append expects a list as second argument")),
+// Keep the interpreter usable from worker threads; a non-thread-safe field or
+// value (an `Rc`, a `RefCell`, a thread-local handle) fails to compile here.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Vm>();
+    assert_send_sync::<Value>();
+};
+
+// ------------------------ IMPORTS ------------------------
+
+/// The file `Import "rel"` names from a file in `base_dir`, `.poh` added
+/// when it has no extension
+pub(super) fn local_module_file(base_dir: &Path, rel: &str) -> PathBuf {
+    let mut path = base_dir.join(rel);
+    if path.extension().is_none() {
+        path.set_extension("poh");
     }
+    path
 }
 
-fn builtin_insert_at(args: &[Value]) -> Result<Value> {
-    if args.len() < 3 {
-        return Err(anyhow!(
-            "insert at requires three arguments: item, index, and list"
-        ));
+/// Where the `.poh` file of a system module is: under `POHLANG_STDLIB`,
+/// then `Interpreter/stdlib` in `base_dir` or a directory above it, then in
+/// the current directory
+pub(super) fn stdlib_module_file(base_dir: &Path, file_name: &str) -> Option<PathBuf> {
+    // 1) Env override
+    if let Ok(root) = std::env::var("POHLANG_STDLIB") {
+        let p = PathBuf::from(root).join(file_name);
+        if p.exists() {
+            return Some(p);
+        }
     }
-    let item = &args[0];
-    let index = match &args[1] {
-        Value::Num(n) => *n as isize,
-        _ => return Err(anyhow!("insert at expects a number as index")),
-    };
-    match &args[2] {
-        Value::List(xs) => {
-            let mut result = xs.clone();
-            let len = result.len() as isize;
-            // Handle negative indices (from end)
-            let idx = if index < 0 {
-                (len + index).max(0) as usize
-            } else {
-                index.min(len) as usize
-            };
-            result.insert(idx, item.clone());
-            Ok(Value::List(result))
+    // 2) Search from base_dir upwards for Interpreter/stdlib/<file>
+    let mut cur: Option<&Path> = Some(base_dir);
+    while let Some(dir) = cur {
+        let cand = dir.join("Interpreter").join("stdlib").join(file_name);
+        if cand.exists() {
+            return Some(cand);
         }
-        _ => Err(anyhow!("insert at expects a list as third argument")),
+        cur = dir.parent();
     }
-}
-
-// Helper function for value equality comparison
-fn values_equal(a: &Value, b: &Value) -> bool {
-    match (a, b) {
-        (Value::Num(x), Value::Num(y)) => (x - y).abs() < f64::EPSILON,
-        (Value::Str(x), Value::Str(y)) => x == y,
-        (Value::Bool(x), Value::Bool(y)) => x == y,
-        (Value::Null, Value::Null) => true,
-        _ => false,
+    // 3) Try CWD fallback
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let cand = cwd.join("Interpreter").join("stdlib").join(file_name);
+    if cand.exists() {
+        return Some(cand);
     }
+    None
 }
 
-// ------------------------ IMPORTS ------------------------
-
 impl Vm {
-    fn import_local(&mut self, rel: &str) -> Result<()> {
-        let mut path = self.base_dir.join(rel);
-        if path.extension().is_none() {
-            path.set_extension("poh");
-        }
+    /// The file a local import names and the key it is kept under once loaded
+    fn module_path(&self, rel: &str) -> (PathBuf, String) {
+        let path = local_module_file(&self.base_dir, rel);
         let canon = fs::canonicalize(&path).unwrap_or(path.clone());
         let key = canon.to_string_lossy().to_string();
+        (canon, key)
+    }
+
+    fn import_local(&mut self, rel: &str) -> Result<()> {
+        let (canon, key) = self.module_path(rel);
         if self.loaded_modules.contains(&key) {
             return Ok(());
         }
         if self.loading_stack.contains(&key) {
             return Err(anyhow!("Circular import detected: {}", key));
         }
-        let src = fs::read_to_string(&canon)?;
-        let program = crate::parser::parse(&src)?;
+        let src = encoding::read_source(&canon, self.source_encoding)?;
+        let program = self.parse_source(&src)?;
         // push base dir
         let prev_base = self.base_dir.clone();
         self.base_dir = canon.parent().unwrap_or(Path::new(".")).to_path_buf();
@@ -3703,6 +7900,76 @@ impl Vm {
         Ok(())
     }
 
+    /// Run a local file once, in a fresh scope so nothing leaks into the
+    /// caller's globals, and keep the functions and variables it defines at
+    /// its top level as its exports
+    fn load_module_exports(&mut self, canon: &Path, key: &str) -> Result<()> {
+        if self.system_exports.contains_key(key) {
+            return Ok(());
+        }
+        if self.loading_stack.iter().any(|k| k == key) {
+            return Err(anyhow!("Circular import detected: {}", key));
+        }
+        let src = encoding::read_source(canon, self.source_encoding)?;
+        let program = self.parse_source(&src)?;
+
+        let prev_base = self.base_dir.clone();
+        let prev_globals = std::mem::replace(&mut self.globals, Scope::new_env(HashMap::new(), None));
+        self.base_dir = canon.parent().unwrap_or(Path::new(".")).to_path_buf();
+        self.loading_stack.push(key.to_string());
+        let exec_res = self.execute(&program);
+        self.loading_stack.pop();
+        self.base_dir = prev_base;
+        let module_globals = std::mem::replace(&mut self.globals, prev_globals);
+        exec_res?;
+
+        let exports = module_globals.read().unwrap().vars.clone();
+        self.system_exports.insert(key.to_string(), exports);
+        Ok(())
+    }
+
+    /// Import a local file under an alias; its exports are reachable only as `alias::name`
+    fn import_local_as(&mut self, rel: &str, alias: &str) -> Result<()> {
+        let (canon, key) = self.module_path(rel);
+        if let Some(existing) = self.module_aliases.get(alias) {
+            if existing != &key {
+                return Err(anyhow!(
+                    "Alias '{}' already bound to module '{}'",
+                    alias,
+                    existing
+                ));
+            }
+        }
+        self.load_module_exports(&canon, &key)?;
+        self.module_aliases.insert(alias.to_string(), key);
+        Ok(())
+    }
+
+    /// `Import a, b from "file"`: only the named exports, under their own
+    /// names. Nothing is imported unless the file exports all of them.
+    fn import_items(&mut self, rel: &str, items: &[String]) -> Result<()> {
+        let (canon, key) = self.module_path(rel);
+        self.load_module_exports(&canon, &key)?;
+        let exports = &self.system_exports[&key];
+        let mut shown = PathBuf::from(rel);
+        if shown.extension().is_none() {
+            shown.set_extension("poh");
+        }
+        let values = items
+            .iter()
+            .map(|name| {
+                exports
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Module '{}' does not export '{}'", shown.display(), name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (name, value) in items.iter().zip(values) {
+            env_set(&self.globals, name, value);
+        }
+        Ok(())
+    }
+
     fn import_system(
         &mut self,
         name: &str,
@@ -3746,7 +8013,7 @@ impl Vm {
                         )));
                     }
                 }
-                self.globals.insert(sym.clone(), value.clone());
+                env_set(&self.globals, sym, value.clone());
                 self.exposed_symbols.insert(sym.clone(), name.to_string());
             }
         }
@@ -3754,31 +8021,6 @@ impl Vm {
         Ok(())
     }
 
-    fn find_stdlib_module(&self, file_name: &str) -> Option<PathBuf> {
-        // 1) Env override
-        if let Ok(root) = std::env::var("POHLANG_STDLIB") {
-            let p = PathBuf::from(root).join(file_name);
-            if p.exists() {
-                return Some(p);
-            }
-        }
-        // 2) Search from base_dir upwards for Interpreter/stdlib/<file>
-        let mut cur: Option<&Path> = Some(self.base_dir.as_path());
-        while let Some(dir) = cur {
-            let cand = dir.join("Interpreter").join("stdlib").join(file_name);
-            if cand.exists() {
-                return Some(cand);
-            }
-            cur = dir.parent();
-        }
-        // 3) Try CWD fallback
-        let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
-        let cand = cwd.join("Interpreter").join("stdlib").join(file_name);
-        if cand.exists() {
-            return Some(cand);
-        }
-        None
-    }
 }
 
 impl Vm {
@@ -3796,14 +8038,23 @@ impl Vm {
             return Ok(existing.clone());
         }
 
+        // Native exports come first; a stdlib .poh of the same name can add to them
+        let native = match name {
+            "collections" => collections_module(),
+            "json_path" => json_path_module(),
+            _ => HashMap::new(),
+        };
+
         let file_name = format!("{}.poh", name);
-        if let Some(path) = self.find_stdlib_module(&file_name) {
+        if let Some(path) = stdlib_module_file(&self.base_dir, &file_name) {
             let canon = fs::canonicalize(&path).unwrap_or(path.clone());
             let src = fs::read_to_string(&canon)?;
-            let program = crate::parser::parse(&src)?;
+            let program = self.parse_source(&src)?;
 
+            // Run the module in its own scope so its definitions become the exports
             let prev_base = self.base_dir.clone();
-            let prev_globals = self.globals.clone();
+            let prev_globals = Arc::clone(&self.globals);
+            self.globals = Scope::new_env(HashMap::new(), Some(Arc::clone(&prev_globals)));
             self.base_dir = canon.parent().unwrap_or(Path::new(".")).to_path_buf();
             self.loading_stack.push(format!("<system:{}>", name));
             let exec_res = self.execute(&program);
@@ -3812,12 +8063,8 @@ impl Vm {
 
             match exec_res {
                 Ok(()) => {
-                    let mut exports = HashMap::new();
-                    for (k, v) in self.globals.iter() {
-                        if !prev_globals.contains_key(k) {
-                            exports.insert(k.clone(), v.clone());
-                        }
-                    }
+                    let mut exports = native;
+                    exports.extend(self.globals.read().unwrap().vars.clone());
                     self.globals = prev_globals;
                     self.loaded_system.insert(name.to_string());
                     Ok(exports)
@@ -3829,14 +8076,17 @@ impl Vm {
             }
         } else {
             self.loaded_system.insert(name.to_string());
-            Ok(HashMap::new())
+            Ok(native)
         }
     }
 
     fn resolve_value(&self, name: &str) -> Option<Value> {
-        if let Some(v) = self.globals.get(name) {
+        if let Some(v) = self.constants.get(name) {
             return Some(v.clone());
         }
+        if let Some(v) = env_get(&self.globals, name) {
+            return Some(v);
+        }
         if let Some((alias, symbol)) = split_qualified(name) {
             if let Some(module_name) = self.module_aliases.get(alias) {
                 if let Some(exports) = self.system_exports.get(module_name) {
@@ -3878,3 +8128,150 @@ fn split_qualified(name: &str) -> Option<(&str, &str)> {
     }
     Some((alias, rest))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dict(entries: &[(&str, Value)]) -> Value {
+        Value::Dict(entries.iter().map(|(k, v)| (k.to_string(), v.clone())).collect())
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+
+    fn problems(v: &Value, schema: &Value) -> Vec<String> {
+        shape_problems(v, schema).unwrap()
+    }
+
+    #[test]
+    fn test_matching_values_have_no_problems() {
+        let schema = dict(&[
+            ("name", text("text")),
+            ("age", text("number")),
+            ("admin", text("boolean")),
+            ("tags", text("list")),
+            ("prefs", text("dictionary")),
+            ("anything", text("any")),
+            ("email", text("optional text")),
+        ]);
+        let user = dict(&[
+            ("name", text("Ada")),
+            ("age", Value::Num(36.5)),
+            ("admin", Value::Bool(false)),
+            ("tags", Value::Tuple(vec![])),
+            ("prefs", dict(&[])),
+            ("anything", Value::Int(0)),
+            ("extra", text("ignored")),
+        ]);
+        assert_eq!(problems(&user, &schema), Vec::<String>::new());
+        assert!(matches!(validate_against(&user, &schema).unwrap(), Value::Null));
+    }
+
+    #[test]
+    fn test_problems_are_sorted_by_field() {
+        let schema = dict(&[
+            ("name", text("text")),
+            ("age", text("number")),
+            ("email", text("optional text")),
+            ("anything", text("any")),
+        ]);
+        let user = dict(&[("age", text("old")), ("email", Value::Int(5)), ("name", Value::Null)]);
+        assert_eq!(
+            problems(&user, &schema),
+            [
+                "age must be a number, got text",
+                "anything is required",
+                "email must be text, got number",
+                "name is required",
+            ]
+        );
+        let Value::List(listed) = validate_against(&user, &schema).unwrap() else {
+            panic!("expected a list of problems");
+        };
+        assert_eq!(listed.len(), 4);
+    }
+
+    #[test]
+    fn test_nested_schemas_and_lists_of_a_type() {
+        let schema = dict(&[
+            ("address", dict(&[("city", text("text")), ("zip", text("optional number"))])),
+            ("scores", text("list of number")),
+            ("matrix", text("list of list of number")),
+            ("owner", dict(&[("id", text("number"))])),
+        ]);
+        let record = dict(&[
+            ("address", dict(&[("zip", text("12345"))])),
+            ("scores", Value::List(vec![Value::Int(1), text("two"), Value::Num(3.0)])),
+            ("matrix", Value::List(vec![Value::List(vec![Value::Int(1)]), Value::Int(2)])),
+            ("owner", text("me")),
+        ]);
+        assert_eq!(
+            problems(&record, &schema),
+            [
+                "address.city is required",
+                "address.zip must be a number, got text",
+                "matrix item 2 must be a list of number, got number",
+                "owner must be a dictionary, got text",
+                "scores item 2 must be a number, got text",
+            ]
+        );
+        let record = dict(&[("scores", Value::Int(1))]);
+        assert!(problems(&record, &schema).contains(&"scores must be a list of number, got number".to_string()));
+        assert!(problems(&record, &schema).contains(&"owner is required".to_string()));
+    }
+
+    #[test]
+    fn test_a_value_that_is_not_a_dictionary_is_one_problem() {
+        let schema = dict(&[("name", text("text"))]);
+        assert_eq!(problems(&Value::List(vec![]), &schema), ["expected a dictionary, got list"]);
+        assert_eq!(problems(&Value::Null, &schema), ["expected a dictionary, got nothing"]);
+    }
+
+    #[test]
+    fn test_bad_schemas_are_errors() {
+        let error = |schema: Value| shape_problems(&dict(&[]), &schema).unwrap_err().to_string();
+        assert!(error(text("text")).contains("a schema must be a dictionary"));
+        assert!(error(dict(&[("age", text("decimal"))])).contains("age has the type \"decimal\""));
+        assert!(error(dict(&[("age", text("optional decimal"))])).contains("\"decimal\""));
+        assert!(error(dict(&[("tags", text("list of thing"))])).contains("tags has the type \"thing\""));
+        assert!(error(dict(&[("age", Value::Int(3))])).contains("the type of age must be a type name"));
+        // Checked even when no item needs it
+        let err = shape_problems(&dict(&[("tags", Value::List(vec![]))]), &dict(&[("tags", text("list of thing"))]));
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_deep_diff_names_each_nested_difference() {
+        let want = dict(&[
+            ("user", dict(&[("name", text("Alice")), ("age", Value::Int(36))])),
+            ("tags", Value::List(vec![text("a"), dict(&[("id", Value::Int(1))])])),
+            ("city", text("Paris")),
+        ]);
+        let got = dict(&[
+            ("user", dict(&[("name", text("Bob")), ("age", Value::Num(36.0))])),
+            ("tags", Value::List(vec![text("a"), dict(&[("id", Value::Int(2))]), Value::Null])),
+            ("extra", Value::Bool(true)),
+        ]);
+        assert_eq!(
+            deep_diff(&got, &want, ""),
+            [
+                "Expected 'city' to be 'Paris' but it is missing",
+                "Expected no 'extra' but got True",
+                "Expected 'tags' to have length 2 but got 3",
+                "Expected 'tags[1].id' to be 1 but got 2",
+                "Expected 'user.name' to be 'Alice' but got 'Bob'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deep_diff_of_equal_values_is_empty() {
+        let range = Value::LazyRange { start: 1, end: 4, step: 1 };
+        let nested = dict(&[("xs", range.clone())]);
+        assert!(deep_diff(&nested, &dict(&[("xs", Value::List(vec![Value::Int(1), Value::Int(2), Value::Int(3)]))]), "").is_empty());
+        assert_eq!(deep_diff(&text("a"), &Value::Int(1), ""), ["Expected 1 but got 'a'"]);
+        assert_eq!(deep_diff(&Value::List(vec![]), &range, ""), ["Expected to have length 3 but got 0"]);
+    }
+}
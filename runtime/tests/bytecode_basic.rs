@@ -19,7 +19,7 @@ fn test_constant_pool_basic() {
     assert_eq!(idx1, 0);
     assert_eq!(idx2, 1);
     assert_eq!(idx1, idx3); // deduplication works
-    assert_eq!(pool.constants.len(), 2);
+    assert_eq!(pool.len(), 2);
     println!("✓ Constant pool basic test passed");
 }
 
@@ -98,7 +98,7 @@ fn test_all_instruction_variants_have_size() {
 
 #[test]
 fn test_constant_types() {
-    let num = Constant::Number(3.14);
+    let num = Constant::Number(2.5);
     let string = Constant::String("test".to_string());
     let boolean = Constant::Boolean(true);
     let null = Constant::Null;
@@ -110,6 +110,6 @@ fn test_constant_types() {
     pool.add_constant(boolean);
     pool.add_constant(null);
 
-    assert_eq!(pool.constants.len(), 4);
+    assert_eq!(pool.len(), 4);
     println!("✓ All constant types work");
 }
@@ -1,5 +1,5 @@
-/// Integration tests for the complete bytecode pipeline
-/// Tests: AST → Compiler → VM → Result
+//! Integration tests for the complete bytecode pipeline
+//! Tests: AST → Compiler → VM → Result
 
 #[cfg(test)]
 mod tests {
@@ -114,7 +114,7 @@ mod tests {
         ];
 
         let (_, output) = compile_and_run_with_output(program).unwrap();
-        assert_eq!(output, vec!["true", "true", "true"]);
+        assert_eq!(output, vec!["1", "1", "1"]);
     }
 
     #[test]
@@ -132,7 +132,7 @@ mod tests {
         ];
 
         let (_, output) = compile_and_run_with_output(program).unwrap();
-        assert_eq!(output, vec!["false", "true", "true"]);
+        assert_eq!(output, vec!["0", "1", "1"]);
     }
 
     #[test]
@@ -261,7 +261,7 @@ mod tests {
         let program = vec![Stmt::Write(Expr::Null)];
 
         let (_, output) = compile_and_run_with_output(program).unwrap();
-        assert_eq!(output, vec!["null"]);
+        assert_eq!(output, vec!["None"]);
     }
 
     #[test]
@@ -272,7 +272,7 @@ mod tests {
         ];
 
         let (_, output) = compile_and_run_with_output(program).unwrap();
-        assert_eq!(output, vec!["true", "false"]);
+        assert_eq!(output, vec!["True", "False"]);
     }
 
     #[test]
@@ -326,4 +326,28 @@ mod tests {
         let (_, output) = compile_and_run_with_output(program).unwrap();
         assert_eq!(output, vec!["10", "20"]);
     }
+
+    #[test]
+    fn test_repeat_until_runs_while_the_condition_is_false() {
+        let counter = || Box::new(Expr::Ident("counter".to_string()));
+        let program = vec![
+            Stmt::Set {
+                name: "counter".to_string(),
+                value: Expr::Num(0.0),
+            },
+            Stmt::RepeatUntil {
+                cond: Expr::Cmp(CmpOp::Eq, counter(), Box::new(Expr::Num(3.0))),
+                body: vec![
+                    Stmt::Write(*counter()),
+                    Stmt::Set {
+                        name: "counter".to_string(),
+                        value: Expr::Plus(counter(), Box::new(Expr::Num(1.0))),
+                    },
+                ],
+            },
+        ];
+
+        let (_, output) = compile_and_run_with_output(program).unwrap();
+        assert_eq!(output, vec!["0", "1", "2"]);
+    }
 }
@@ -0,0 +1,67 @@
+use pohlang::capi::*;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::ptr;
+
+fn c(s: &str) -> CString {
+    CString::new(s).unwrap()
+}
+
+/// Take a string the library handed back
+unsafe fn take(s: *mut c_char) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    let text = CStr::from_ptr(s).to_str().unwrap().to_string();
+    poh_string_free(s);
+    Some(text)
+}
+
+extern "C" fn collect(line: *const c_char, user_data: *mut c_void) {
+    let lines = unsafe { &mut *(user_data as *mut Vec<String>) };
+    lines.push(unsafe { CStr::from_ptr(line) }.to_str().unwrap().to_string());
+}
+
+#[test]
+fn programs_run_through_the_c_api_share_globals() {
+    unsafe {
+        let vm = poh_vm_new();
+        assert!(!vm.is_null());
+        let mut lines: Vec<String> = Vec::new();
+        let user_data = &mut lines as *mut Vec<String> as *mut c_void;
+        assert_eq!(poh_vm_set_output_callback(vm, Some(collect), user_data), 0);
+
+        assert_eq!(poh_vm_set_global_string(vm, c("name").as_ptr(), c("Ada").as_ptr()), 0);
+        assert_eq!(poh_vm_set_global_number(vm, c("count").as_ptr(), 3.0), 0);
+        let source = c("Start Program\nWrite \"Hello \" plus name\nSet total to count times 2\nSet tags to Make a list of \"a\" and \"b\"\nEnd Program\n");
+        let mut error = ptr::null_mut();
+        let status = poh_vm_execute(vm, source.as_ptr(), &mut error);
+        assert_eq!((status, take(error)), (0, None));
+        assert_eq!(lines, vec!["Hello Ada"]);
+
+        assert_eq!(take(poh_vm_get_global_as_json(vm, c("total").as_ptr())).as_deref(), Some("6"));
+        assert_eq!(take(poh_vm_get_global_as_json(vm, c("tags").as_ptr())).as_deref(), Some(r#"["a","b"]"#));
+        assert_eq!(take(poh_vm_get_global_as_json(vm, c("missing").as_ptr())), None);
+        poh_vm_free(vm);
+    }
+}
+
+#[test]
+fn failures_come_back_as_status_and_message() {
+    unsafe {
+        let vm = poh_vm_new();
+        let mut error = ptr::null_mut();
+        let source = c("Start Program\nThrow \"stop here\"\nEnd Program\n");
+        assert_eq!(poh_vm_execute(vm, source.as_ptr(), &mut error), 1);
+        assert!(take(error).unwrap().contains("stop here"));
+
+        let source = c("Start Program\nSet constant limit to 5\nEnd Program\n");
+        assert_eq!(poh_vm_execute(vm, source.as_ptr(), ptr::null_mut()), 0);
+        assert_eq!(poh_vm_set_global_number(vm, c("limit").as_ptr(), 6.0), 1);
+
+        assert_eq!(poh_vm_execute(vm, ptr::null(), &mut error), 1);
+        assert!(take(error).unwrap().contains("NULL"));
+        assert_eq!(poh_vm_execute(ptr::null_mut(), source.as_ptr(), ptr::null_mut()), 1);
+        poh_vm_free(vm);
+        poh_vm_free(ptr::null_mut());
+    }
+}
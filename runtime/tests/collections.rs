@@ -0,0 +1,119 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn run(lines: &[&str]) -> Command {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let path = std::env::temp_dir().join(format!("pohlang_collections_test_{}.poh", ts));
+    let mut contents = String::from("Start Program\nImport system \"collections\"\n");
+    for l in lines {
+        contents.push_str(l);
+        contents.push('\n');
+    }
+    contents.push_str("End Program\n");
+    fs::write(&path, contents).unwrap();
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd
+}
+
+#[test]
+fn stack_is_last_in_first_out() {
+    let mut cmd = run(&[
+        "Set s to collections::new_stack()",
+        "Set s to collections::push(s, 1)",
+        "Set s to collections::push(s, 2)",
+        "Write s",
+        "Write collections::pop(s)",
+        "Write collections::peek(s)",
+        "Write count of s",
+        "Write collections::is_empty(s)",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout("Stack[1, 2]\n2\n1\n1\nFalse\n");
+}
+
+#[test]
+fn queue_is_first_in_first_out() {
+    let mut cmd = run(&[
+        "Set q to collections::new_queue()",
+        "Set q to collections::enqueue(q, \"a\")",
+        "Set q to collections::enqueue(q, \"b\")",
+        "Write collections::peek(q)",
+        "Write collections::dequeue(q)",
+        "Write collections::dequeue(q)",
+        "Write collections::is_empty(q)",
+    ]);
+    cmd.assert().success().stdout("a\na\nb\nTrue\n");
+}
+
+#[test]
+fn priority_queue_pops_highest_first() {
+    let mut cmd = run(&[
+        "Set pq to collections::new_priority_queue()",
+        "Set pq to collections::push(pq, \"low\", 1)",
+        "Set pq to collections::push(pq, \"high\", 9)",
+        "Set pq to collections::push(pq, \"also high\", 9)",
+        "Set pq to collections::push(pq, \"mid\", 5)",
+        "Write collections::pop_highest(pq)",
+        "Write collections::pop_highest(pq)",
+        "Write collections::pop_highest(pq)",
+        "Write collections::size(pq)",
+    ]);
+    cmd.assert().success().stdout("high\nalso high\nmid\n1\n");
+}
+
+#[test]
+fn ordered_dict_keeps_insertion_order() {
+    let mut cmd = run(&[
+        "Set od to collections::ordered_dict()",
+        "Set od to collections::set(od, \"zebra\", 1)",
+        "Set od to collections::set(od, \"apple\", 2)",
+        "Set od to collections::set(od, \"mango\", 3)",
+        "Set od to collections::set(od, \"zebra\", 10)",
+        "Write collections::keys(od)",
+        "Write collections::values(od)",
+        "Write collections::remove(od, \"apple\")",
+        "Write od",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout("[zebra, apple, mango]\n[10, 2, 3]\n2\n{\"zebra\": 10, \"mango\": 3}\n");
+}
+
+#[test]
+fn collections_work_through_an_alias_and_inside_functions() {
+    let mut cmd = run(&[
+        "Import system \"collections\" as coll",
+        "Make drain with st",
+        "    Set total to 0",
+        "    While coll::is_empty(st) is False",
+        "        Set total to total plus coll::pop(st)",
+        "    End",
+        "    Return total",
+        "End",
+        "Set s to coll::push(coll::push(coll::new_stack(), 3), 4)",
+        "Write drain(s)",
+        "Write coll::size(s)",
+    ]);
+    cmd.assert().success().stdout("7\n0\n");
+}
+
+#[test]
+fn empty_and_mismatched_collections_are_errors() {
+    let mut cmd = run(&["Write collections::pop(collections::new_stack())"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("empty stack"));
+
+    let mut cmd = run(&["Write collections::dequeue(collections::new_stack())"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("expects a queue"));
+}
@@ -1,6 +1,6 @@
-/// Bytecode Compiler Test Suite
-///
-/// Comprehensive tests for the bytecode compiler
+//! Bytecode Compiler Test Suite
+//!
+//! Comprehensive tests for the bytecode compiler
 
 #[cfg(test)]
 mod tests {
@@ -238,10 +238,11 @@ mod tests {
             Box::new(Expr::Bool(false)),
         ))];
         let chunk = compiler.compile(program).unwrap();
+        // `and` and `or` jump past their right side instead of always running it
         assert!(chunk
             .code
             .iter()
-            .any(|inst| matches!(inst, Instruction::And)));
+            .any(|inst| matches!(inst, Instruction::JumpIfFalse(_))));
 
         let compiler = Compiler::new();
         let program = vec![Stmt::Write(Expr::Or(
@@ -252,7 +253,7 @@ mod tests {
         assert!(chunk
             .code
             .iter()
-            .any(|inst| matches!(inst, Instruction::Or)));
+            .any(|inst| matches!(inst, Instruction::JumpIfFalse(_))));
 
         let compiler = Compiler::new();
         let program = vec![Stmt::Write(Expr::Not(Box::new(Expr::Bool(true))))];
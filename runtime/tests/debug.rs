@@ -0,0 +1,75 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PROGRAM: &str = "Start Program
+Make double with n
+    Set result to n times 2
+    Return result
+End
+Set x to 5
+Set y to double(x)
+Set z to double(y)
+Write z
+End Program
+";
+
+fn debug_command() -> Command {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let path = std::env::temp_dir().join(format!("pohlang_debug_test_{}.poh", ts));
+    fs::write(&path, PROGRAM).unwrap();
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg("--debug").arg(path.to_str().unwrap());
+    cmd
+}
+
+#[test]
+fn scripted_session_steps_prints_and_stops_at_breakpoints() {
+    let output = assert_cmd::Command::from_std(debug_command())
+        .env("POHLANG_DEBUG_STDIN", "1")
+        .write_stdin("step\nstep\nstep\nvars\nprint n plus 1\nbreak 8\ncontinue\nnext\nprint z\ncontinue\n")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "debugger failed: {}", stderr);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "20\n");
+
+    let stops: Vec<&str> = stderr
+        .lines()
+        .filter_map(|l| l.split("[debug] ").nth(1))
+        .collect();
+    assert_eq!(
+        stops,
+        [
+            "line 2: Make double with n",
+            "line 6: Set x to 5",
+            "line 7: Set y to double(x)",
+            // step goes into the call
+            "line 3: Set result to n times 2",
+            // the breakpoint, then next steps over the second call
+            "line 8: Set z to double(y)",
+            "line 9: Write z",
+        ]
+        .map(|s| s.to_string()),
+        "unexpected session: {}",
+        stderr
+    );
+    assert!(stderr.contains("locals:\n  n = 5\nglobals:\n  x = 5\n"), "unexpected session: {}", stderr);
+    assert!(stderr.contains("(debug) 6\n"), "unexpected session: {}", stderr);
+    assert!(stderr.contains("Breakpoint at line 8"), "unexpected session: {}", stderr);
+    assert!(stderr.contains("(debug) 20\n"), "unexpected session: {}", stderr);
+}
+
+#[test]
+fn debug_without_a_terminal_is_refused() {
+    debug_command()
+        .env_remove("POHLANG_DEBUG_STDIN")
+        .stdin(Stdio::null())
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("stdin is not one"));
+}
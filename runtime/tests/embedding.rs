@@ -0,0 +1,151 @@
+use pohlang::parser::parse;
+use pohlang::vm::{compile, compile_strict, Vm};
+use std::thread;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn vm_types_are_send_and_sync() {
+    assert_send_sync::<Vm>();
+    assert_send_sync::<pohlang::parser::Program>();
+}
+
+#[test]
+fn vm_moves_to_another_thread_with_its_functions() {
+    let mut vm = Vm::default();
+    let setup = parse(
+        "Start Program\nMake fib with n\n    If n is less than 2\n        Return n\n    End\n    Return fib(n minus 1) plus fib(n minus 2)\nEnd\nSet base to 10\nEnd Program\n",
+    )
+    .unwrap();
+    vm.execute(&setup).unwrap();
+
+    // Functions and variables defined on this thread are still callable there
+    let check = parse(
+        "Start Program\nIf fib(base) is not 55\n    Throw \"fib lost its body\"\nEnd\nEnd Program\n",
+    )
+    .unwrap();
+    thread::spawn(move || vm.execute(&check))
+        .join()
+        .unwrap()
+        .unwrap();
+}
+
+#[test]
+fn independent_vms_run_in_parallel() {
+    let program = parse(
+        "Start Program\nMake square with x\n    Return x times x\nEnd\nSet total to 0\nRepeat 100 times\n    Set total to total plus square(3)\nEnd\nIf total is not 900\n    Throw \"wrong total\"\nEnd\nEnd Program\n",
+    )
+    .unwrap();
+
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let program = program.clone();
+            thread::spawn(move || Vm::default().execute(&program))
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap().unwrap();
+    }
+}
+
+#[test]
+fn execute_program_hands_back_the_top_level_return() {
+    let program = parse(
+        "Start Program\nSet total to 40 plus 2\nReturn total\nThrow \"never reached\"\nEnd Program\n",
+    )
+    .unwrap();
+    let value = pohlang::execute_program(&program).unwrap().unwrap();
+    assert_eq!(value.as_i64(), Some(42));
+    assert_eq!(value.to_string(), "42");
+
+    let greeting = parse("Start Program\nReturn \"hi\"\nEnd Program\n").unwrap();
+    let value = pohlang::execute_program(&greeting).unwrap().unwrap();
+    assert_eq!(value.as_str(), Some("hi"));
+
+    let silent = parse("Start Program\nSet x to 1\nEnd Program\n").unwrap();
+    assert!(pohlang::execute_program(&silent).unwrap().is_none());
+}
+
+#[test]
+fn a_vm_starts_fresh_after_a_return() {
+    let mut vm = Vm::default();
+    let first = parse("Start Program\nReturn 1\nEnd Program\n").unwrap();
+    assert_eq!(vm.run(&first).unwrap().unwrap().as_i64(), Some(1));
+    let second = parse("Start Program\nSet x to 2\nReturn x times 2\nEnd Program\n").unwrap();
+    assert_eq!(vm.run(&second).unwrap().unwrap().as_i64(), Some(4));
+}
+
+#[test]
+fn inline_and_block_ifs_write_through_the_output_sink() {
+    let program = parse(
+        "Start Program\n\
+         Set n to 3\n\
+         If n is greater than 2 Write \"inline then\" Otherwise \"inline else\"\n\
+         If n is less than 2 Write \"skipped\" Otherwise Write \"inline otherwise\"\n\
+         If n is equal to 3\n\
+         \x20   Write \"block then\"\n\
+         End\n\
+         Make check with x\n\
+         \x20   If x is greater than 1 Write \"function inline\"\n\
+         \x20   If x is greater than 1\n\
+         \x20       Write \"function block\"\n\
+         \x20   End\n\
+         \x20   Return x\n\
+         End\n\
+         Use check with n\n\
+         End Program\n",
+    )
+    .unwrap();
+    let (result, output) = pohlang::core::io::capture_output(|| Vm::default().run(&program));
+    result.unwrap();
+    assert_eq!(
+        output,
+        "inline then\ninline otherwise\nblock then\nfunction inline\nfunction block\n"
+    );
+}
+
+#[test]
+fn stats_count_statements_and_calls_when_enabled() {
+    let program = parse(
+        "Start Program\nMake square with x Write x times x\nSet total to 0\nRepeat 3 times\n    Set total to total plus square(2)\nEnd\nSet n to length(range(4))\nSet m to length(\"abc\")\nEnd Program\n",
+    )
+    .unwrap();
+
+    let mut vm = Vm::default();
+    vm.execute(&program).unwrap();
+    assert_eq!(vm.stats(), pohlang::vm::ExecutionStats::default());
+
+    let mut vm = Vm::default();
+    vm.enable_stats();
+    vm.execute(&program).unwrap();
+    let stats = vm.stats();
+    // Make, Set, Repeat, three passes through its body, then two Sets
+    assert_eq!(stats.statements_executed, 8);
+    assert_eq!(stats.function_calls, 3);
+    assert_eq!(stats.top_builtins(10), vec![("length", 2), ("range", 1)]);
+    assert_eq!(pohlang::vm::stats::parsed_counts(&program), (6, 1));
+}
+
+#[test]
+fn strict_compile_rejects_expressions_it_can_only_store_as_text() {
+    let program = parse("Start Program\nSet x to 1 plus 2\nWrite x\nEnd Program\n").unwrap();
+    assert_eq!(compile_strict(&program).unwrap(), compile(&program));
+
+    let program = parse("Start Program\nWrite total of nums\nWrite reverse of nums\nEnd Program\n").unwrap();
+    let err = compile_strict(&program).unwrap_err().to_string();
+    assert!(err.contains("Cannot compile 'total of nums' and 1 other expression(s)"), "{}", err);
+    // Without strict the text is compiled in their place
+    assert!(!compile(&program).is_empty());
+}
+
+#[test]
+fn registered_hints_are_added_to_parse_and_runtime_errors() {
+    Vm::register_hint(regex::Regex::new(r"no table named \w+").unwrap(), "Create the table before querying it");
+
+    let program = parse("Start Program\nThrow \"no table named users\"\nEnd Program\n").unwrap();
+    let err = Vm::default().execute(&program).unwrap_err().to_string();
+    assert!(err.contains("no table named users.\nHint: Create the table before querying it\n"), "{}", err);
+
+    let err = parse("Start Program\nWrite (1 plus\nEnd Program\n").unwrap_err().to_string();
+    assert!(err.contains("Hint: Check for unmatched brackets"), "{}", err);
+}
@@ -0,0 +1,101 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn run(lines: &[&str], bytecode: bool) -> Command {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let path = std::env::temp_dir().join(format!("pohlang_equality_test_{}.poh", ts));
+    let mut contents = String::from("Start Program\n");
+    for l in lines {
+        contents.push_str(l);
+        contents.push('\n');
+    }
+    contents.push_str("End Program\n");
+    fs::write(&path, contents).unwrap();
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run");
+    if bytecode {
+        cmd.arg("--bytecode");
+    }
+    cmd.arg(path.to_str().unwrap());
+    cmd
+}
+
+/// Each comparison and whether it holds, for both VMs
+const SCALAR_MATRIX: &[(&str, bool)] = &[
+    ("1 is equal to 1.0", true),
+    ("1 is equal to 2", false),
+    ("0.1 plus 0.2 is equal to 0.3", false),
+    ("0.5 plus 0.25 is equal to 0.75", true),
+    ("\"1\" is equal to 1", false),
+    ("\"abc\" is equal to \"abc\"", true),
+    ("\"abc\" is equal to \"ABC\"", false),
+    ("True is equal to 1", false),
+    ("True is equal to True", true),
+    ("\"True\" is equal to True", false),
+    ("9007199254740993 is equal to 9007199254740992", false),
+    ("\"1\" is not equal to 1", true),
+    ("1 is not equal to 1.0", false),
+];
+
+fn matrix_program() -> Vec<String> {
+    SCALAR_MATRIX
+        .iter()
+        .map(|(cmp, _)| format!("If {}\n    Write \"yes\"\nOtherwise\n    Write \"no\"\nEnd", cmp))
+        .collect()
+}
+
+fn matrix_output() -> String {
+    SCALAR_MATRIX
+        .iter()
+        .map(|(_, holds)| if *holds { "yes\n" } else { "no\n" })
+        .collect()
+}
+
+#[test]
+fn scalar_equality_matrix() {
+    let program = matrix_program();
+    let lines: Vec<&str> = program.iter().map(String::as_str).collect();
+    run(&lines, false)
+        .assert()
+        .success()
+        .stdout(matrix_output());
+}
+
+#[test]
+fn bytecode_vm_uses_the_same_equality() {
+    let program = matrix_program();
+    let lines: Vec<&str> = program.iter().map(String::as_str).collect();
+    run(&lines, true)
+        .assert()
+        .success()
+        .stdout(matrix_output());
+}
+
+#[test]
+fn collections_compare_deeply() {
+    run(
+        &[
+            "Set a to Make a list of 1, \"x\", Make a list of 2, 3",
+            "Set b to Make a list of 1.0, \"x\", Make a list of 2, 3.0",
+            "Set c to Make a list of 1, \"x\"",
+            "Set t to tuple of 1, \"x\"",
+            "Set d to Make a dictionary with \"k\" as 1",
+            "Set e to Make a dictionary with \"k\" as 1.0",
+            "Write a is equal to b",
+            "Write a is equal to c",
+            "Write c is equal to t",
+            "Write d is equal to e",
+            "Write contains \"1\" in Make a list of 1, 2",
+            "Write contains 2.0 in Make a list of 1, 2",
+        ],
+        false,
+    )
+    .assert()
+    .success()
+    .stdout("1\n0\n0\n1\nFalse\nTrue\n");
+}
@@ -0,0 +1,169 @@
+//! The same expression must give the same value, or the same error, however
+//! it is evaluated: at the top level, in a function with a block body, in a
+//! one-line function, and, for what it supports, in the bytecode VM.
+
+use pohlang::bytecode::{self, BytecodeVM};
+use pohlang::parser::parse;
+use pohlang::vm::Vm;
+use proptest::prelude::*;
+
+/// The variables every expression can use, with the values they hold
+const VARS: &[(&str, &str)] = &[("a", "7"), ("b", "2.5"), ("s", "\"hi\""), ("t", "True"), ("n", "None")];
+
+/// Lists an expression can use, for the tree-walking paths only
+const LIST_VARS: &[(&str, &str)] = &[("xs", "(Make a list of 3, 1 and 2)"), ("ws", "(Make a list of \"b\" and \"a\")")];
+
+fn scalar_leaf() -> impl Strategy<Value = String> {
+    prop_oneof![
+        (-3i64..12).prop_map(|i| i.to_string()),
+        prop::sample::select(vec!["0.5", "2.25", "-1.5", "0.0"]).prop_map(str::to_string),
+        prop::sample::select(vec!["\"\"", "\"ab\"", "\"7\"", "\"x y\""]).prop_map(str::to_string),
+        prop::sample::select(vec!["True", "False", "None"]).prop_map(str::to_string),
+        prop::sample::select(VARS.iter().map(|(name, _)| *name).collect::<Vec<_>>()).prop_map(str::to_string),
+    ]
+}
+
+const BINARY: &[&str] = &[
+    "plus",
+    "minus",
+    "times",
+    "divided by",
+    "and",
+    "or",
+    "is greater than",
+    "is less than or equal to",
+    "is equal to",
+    "is not",
+];
+
+/// Expressions the bytecode compiler also handles: literals, variables,
+/// arithmetic, logic and comparisons
+fn scalar_expr() -> impl Strategy<Value = String> {
+    scalar_leaf().prop_recursive(4, 24, 2, |inner| {
+        prop_oneof![
+            (inner.clone(), prop::sample::select(BINARY), inner.clone())
+                .prop_map(|(l, op, r)| format!("({} {} {})", l, op, r)),
+            inner.prop_map(|e| format!("(not {})", e)),
+        ]
+    })
+}
+
+/// Scalar expressions plus lists, indexing and the built-in phrases
+fn rich_expr() -> impl Strategy<Value = String> {
+    let leaf = prop_oneof![
+        4 => scalar_leaf(),
+        1 => prop::sample::select(LIST_VARS.iter().map(|(name, _)| *name).collect::<Vec<_>>()).prop_map(str::to_string),
+    ];
+    leaf.prop_recursive(4, 24, 3, |inner| {
+        prop_oneof![
+            (inner.clone(), prop::sample::select(BINARY), inner.clone())
+                .prop_map(|(l, op, r)| format!("({} {} {})", l, op, r)),
+            inner.clone().prop_map(|e| format!("(not {})", e)),
+            prop::collection::vec(inner.clone(), 0..3).prop_map(|items| match items.len() {
+                0 => "(Make a list of)".to_string(),
+                1 => format!("(Make a list of {})", items[0]),
+                _ => format!("(Make a list of {})", items.join(", ")),
+            }),
+            (inner.clone(), inner.clone()).prop_map(|(l, i)| format!("{}[{}]", l, i)),
+            (
+                prop::sample::select(vec![
+                    "total of",
+                    "count of",
+                    "reverse of",
+                    "first in",
+                    "make uppercase",
+                    "absolute value of",
+                    "round",
+                    "type of",
+                ]),
+                inner.clone()
+            )
+                .prop_map(|(phrase, e)| format!("({} {})", phrase, e)),
+            (inner.clone(), inner.clone()).prop_map(|(x, c)| format!("(contains {} in {})", x, c)),
+            (inner.clone(), inner.clone()).prop_map(|(c, sep)| format!("(join {} with {})", c, sep)),
+            (inner.clone(), inner.clone(), inner).prop_map(|(a, c, b)| format!("({} if {} else {})", a, c, b)),
+        ]
+    })
+}
+
+/// The outcome of running a program: the value it returned, or the first
+/// line of its error
+fn outcome(src: &str) -> String {
+    let program = match parse(src) {
+        Ok(program) => program,
+        Err(e) => return format!("parse error: {}", e),
+    };
+    match Vm::default().run(&program) {
+        Ok(Some(value)) => format!("{:?}", value),
+        Ok(None) => "nothing".to_string(),
+        Err(e) => format!("error: {}", e.to_string().lines().next().unwrap_or("")),
+    }
+}
+
+fn program(lines: &[String]) -> String {
+    format!("Start Program\n{}\nEnd Program\n", lines.join("\n"))
+}
+
+/// The expression's outcome at the top level, in a block function and in a
+/// one-line function, with the variables as globals and then as parameters
+fn tree_outcomes(expr: &str, vars: &[(&str, &str)]) -> [String; 3] {
+    let names: Vec<&str> = vars.iter().map(|(name, _)| *name).collect();
+    let values: Vec<&str> = vars.iter().map(|(_, value)| *value).collect();
+    let params = names.join(", ");
+    let args = values.join(", ");
+    let mut top: Vec<String> = vars.iter().map(|(name, value)| format!("Set {} to {}", name, value)).collect();
+    top.push(format!("Return {}", expr));
+    let block = vec![
+        format!("Make check with {}", params),
+        format!("    Return {}", expr),
+        "End".to_string(),
+        format!("Return check({})", args),
+    ];
+    let inline = vec![format!("Make check with {} Write {}", params, expr), format!("Return check({})", args)];
+    [outcome(&program(&top)), outcome(&program(&block)), outcome(&program(&inline))]
+}
+
+/// The expression's outcome in the bytecode VM, in the tree-walker's terms,
+/// or `None` where the compiler does not support something in it
+fn bytecode_outcome(expr: &str) -> Option<String> {
+    let mut lines: Vec<String> = VARS.iter().map(|(name, value)| format!("Set {} to {}", name, value)).collect();
+    lines.push(format!("Return {}", expr));
+    let chunk = bytecode::Compiler::new().compile(parse(&program(&lines)).ok()?).ok()?;
+    let mut vm = BytecodeVM::new();
+    vm.load(chunk);
+    Some(match vm.run() {
+        Ok(bytecode::Value::Integer(i)) => format!("ProgramValue(Int({}))", i),
+        Ok(bytecode::Value::Number(n)) => format!("ProgramValue(Num({:?}))", n),
+        Ok(bytecode::Value::String(s)) => format!("ProgramValue(Str({:?}))", s),
+        Ok(bytecode::Value::Boolean(b)) => format!("ProgramValue(Bool({}))", b),
+        Ok(bytecode::Value::Null) => "ProgramValue(Null)".to_string(),
+        Err(_) => "error".to_string(),
+    })
+}
+
+fn same_everywhere(expr: &str, vars: &[(&str, &str)]) -> Result<(), TestCaseError> {
+    let [top, block, inline] = tree_outcomes(expr, vars);
+    prop_assert_eq!(&top, &block, "top level and block function differ on {}", expr);
+    prop_assert_eq!(&top, &inline, "top level and one-line function differ on {}", expr);
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig { cases: 256, ..ProptestConfig::default() })]
+
+    #[test]
+    fn scalar_expressions_agree_on_every_path(expr in scalar_expr()) {
+        same_everywhere(&expr, VARS)?;
+        if let Some(bytecode) = bytecode_outcome(&expr) {
+            let top = &tree_outcomes(&expr, VARS)[0];
+            let top = if top.starts_with("error") { "error" } else { top.as_str() };
+            prop_assert_eq!(bytecode.as_str(), top, "bytecode differs on {}", expr);
+        }
+    }
+
+    #[test]
+    fn list_and_phrase_expressions_agree_on_every_tree_path(expr in rich_expr()) {
+        let vars: Vec<(&str, &str)> = VARS.iter().chain(LIST_VARS).copied().collect();
+        same_everywhere(&expr, &vars)?;
+    }
+}
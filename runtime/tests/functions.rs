@@ -60,13 +60,116 @@ fn closure_captures_variable_define_form() {
 
 #[test]
 fn call_alias_works() {
+    let mut cmd = run(&[
+        "Make bump with x",
+        "    Write \"bumped \" plus x",
+        "    Return x plus x",
+        "End",
+        "Call bump with \"Ha\"",
+        "Use bump with \"Ho\"",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout("bumped Ha\nbumped Ho\n");
+}
+
+#[test]
+fn use_discards_the_return_value() {
     let mut cmd = run(&[
         "Define function shout with parameter x as x plus x",
-        "Call shout with \"Ha\"",
+        "Use shout with \"Ha\"",
+        "Write shout with \"Ho\"",
+    ]);
+    cmd.assert().success().stdout("HoHo\n");
+}
+
+#[test]
+fn bare_use_of_a_builtin_warns() {
+    let mut cmd = run(&[
+        "Set xs to Make a list of 1, 2",
+        "If True",
+        "    Use length with xs",
+        "End",
     ]);
     cmd.assert()
         .success()
-        .stdout(predicate::str::contains("HaHa"));
+        .stdout("")
+        .stderr(predicate::str::contains("'Use length' discards the value"))
+        .stderr(predicate::str::contains("Write length with"));
+}
+
+#[test]
+fn declared_types_warn_and_fail_under_strict_types() {
+    let program = [
+        "Type Age is number",
+        "Set age as Age to \"old\"",
+        "Write age",
+    ];
+    run(&program)
+        .assert()
+        .success()
+        .stdout("old\n")
+        .stderr(predicate::str::contains(
+            "'age' is declared as Age (number) but is set to a string",
+        ));
+
+    let mut cmd = run(&program);
+    cmd.arg("--strict-types");
+    cmd.assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("TypeError"));
+}
+
+#[test]
+fn strict_mode_makes_soft_failures_errors() {
+    run(&["Write nope"]).assert().success().stdout("<nope>\n");
+    let mut cmd = run(&["Write nope"]);
+    cmd.arg("--strict");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("[RuntimeError]"))
+        .stderr(predicate::str::contains("Variable 'nope' is not defined"));
+
+    let program = ["If \"abc\" is greater than 1", "    Write \"bigger\"", "End", "Write \"done\""];
+    run(&program).assert().success().stdout("done\n");
+    let mut cmd = run(&program);
+    cmd.arg("--strict");
+    cmd.assert().failure().stdout("").stderr(predicate::str::contains(
+        "Only numbers can be compared by size, got abc (string) and 1 (number)",
+    ));
+
+    let mut cmd = run(&["Repeat \"twice\" times", "    Write 1", "End"]);
+    cmd.arg("--strict");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Repeat expects a number of times, got twice (string)"));
+
+    // Strict types come with it
+    let mut cmd = run(&["Type Age is number", "Set age as Age to \"old\""]);
+    cmd.arg("--strict");
+    cmd.assert().failure().stderr(predicate::str::contains("TypeError"));
+
+    // And every lint check, including the hints at constants
+    let mut cmd = run(&["Set retries to 3", "Write retries"]);
+    cmd.arg("--strict");
+    cmd.assert().success().stdout("3\n").stderr(predicate::str::contains(
+        "Hint: retries is set once and never changed; 'Set constant retries to 3' saves looking it up at each use",
+    ));
+}
+
+#[test]
+fn strict_bytecode_refuses_what_the_compiler_would_leave_out() {
+    let program = ["Make twice with x", "    Return x times 2", "End", "Write 1"];
+    let mut cmd = run(&program);
+    cmd.arg("--bytecode");
+    cmd.assert().success().stdout("1\n");
+    let mut cmd = run(&program);
+    cmd.args(["--bytecode", "--strict"]);
+    cmd.assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("Function definitions not yet supported; it would be left out"));
 }
 
 #[test]
@@ -80,6 +183,30 @@ fn wrong_arity_reports_error() {
         .stderr(predicate::str::contains("expects"));
 }
 
+#[test]
+fn type_errors_show_the_value_received_and_the_calls_leading_there() {
+    let mut cmd = run(&["Write round(\"abc\")"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("[TypeError]"))
+        .stderr(predicate::str::contains("round requires a number, got abc (string)"));
+
+    let mut cmd = run(&[
+        "Define function pair with parameters a, b as a plus b",
+        "Define function middle with parameter x as pair(x)",
+        "Define function outer with parameter x as middle(x)",
+        "Write outer(1)",
+    ]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Function 'pair' expects 2 argument(s) but got 1: 1 (number)",
+        ))
+        .stderr(predicate::str::contains("Call stack:\n  in outer at"))
+        .stderr(predicate::str::contains("\n  in middle at"))
+        .stderr(predicate::str::contains("\n  in pair at"));
+}
+
 #[test]
 fn unknown_function_reports_error() {
     let mut cmd = run(&["Write nope with 1"]);
@@ -87,3 +214,191 @@ fn unknown_function_reports_error() {
         .failure()
         .stderr(predicate::str::contains("Function 'nope' is not defined"));
 }
+
+#[test]
+fn mutual_recursion_between_make_blocks() {
+    let mut cmd = run(&[
+        "Make is_even with n",
+        "    If n is equal to 0",
+        "        Return \"yes\"",
+        "    End",
+        "    Return is_odd(n minus 1)",
+        "End",
+        "Make is_odd with n",
+        "    If n is equal to 0",
+        "        Return \"no\"",
+        "    End",
+        "    Return is_even(n minus 1)",
+        "End",
+        "Write is_even(10)",
+        "Write is_even(7)",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("yes\nno"));
+}
+
+#[test]
+fn closure_sees_later_global_updates() {
+    let mut cmd = run(&[
+        "Set greeting to \"Hello\"",
+        "Make greet with name Write greeting plus \" \" plus name",
+        "Set greeting to \"Hi\"",
+        "Write greet(\"Ada\")",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Hi Ada"));
+}
+
+#[test]
+fn closure_over_loop_variable() {
+    let mut cmd = run(&[
+        "Make count_up with limit",
+        "    Set i to 0",
+        "    Set out to \"\"",
+        "    While i is less than limit",
+        "        Make show with prefix Write prefix plus i",
+        "        Set out to out plus show(\"#\")",
+        "        Set i to i plus 1",
+        "    End",
+        "    Return out",
+        "End",
+        "Write count_up(3)",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("#0#1#2"));
+}
+
+const FIB_AND_SUM: &[&str] = &[
+    "Make fib with n",
+    "    If n is less than 2",
+    "        Return n",
+    "    End",
+    "    Return fib(n minus 1) plus fib(n minus 2)",
+    "End",
+    "Make sum_to with limit",
+    "    Set i to 0",
+    "    Set acc to 0",
+    "    While i is less than limit",
+    "        Set acc to acc plus i",
+    "        Set i to i plus 1",
+    "    End",
+    "    Return acc",
+    "End",
+];
+
+/// The same programs with a nested definition, which keeps bodies on the name-based path
+fn dynamic_fib_and_sum() -> Vec<&'static str> {
+    let mut lines = FIB_AND_SUM.to_vec();
+    lines.insert(1, "    Make unused with x Write x");
+    lines.insert(9, "    Make unused with x Write x");
+    lines
+}
+
+fn program_output(lines: &[&str]) -> String {
+    let out = run(lines).output().unwrap();
+    assert!(out.status.success());
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn slot_resolved_and_dynamic_bodies_agree() {
+    let calls = ["Write fib(15)", "Write sum_to(1000)"];
+    let resolved: Vec<&str> = FIB_AND_SUM.iter().chain(&calls).copied().collect();
+    let dynamic: Vec<&str> = dynamic_fib_and_sum().into_iter().chain(calls).collect();
+    let expected = program_output(&resolved);
+    assert!(expected.contains("610\n499500"));
+    assert_eq!(expected, program_output(&dynamic));
+}
+
+#[test]
+fn local_assigned_in_untaken_branch_falls_back_to_global() {
+    let mut cmd = run(&[
+        "Set label to \"global\"",
+        "Make pick with flag",
+        "    If flag is equal to 1",
+        "        Set label to \"local\"",
+        "    End",
+        "    Return label",
+        "End",
+        "Write pick(0)",
+        "Write pick(1)",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("global\nlocal"));
+}
+
+#[test]
+fn memoize_caches_repeated_calls() {
+    // The slow body recurses through the memoized wrapper, so each n runs once
+    let out = program_output(&[
+        "Make fib_slow with n",
+        "    Write \"call\"",
+        "    If n is less than 2",
+        "        Return n",
+        "    End",
+        "    Return fib(n minus 1) plus fib(n minus 2)",
+        "End",
+        "Set fib to memoize with fib_slow",
+        "Write fib(30)",
+        "Write fib(30)",
+    ]);
+    assert_eq!(out.matches("call\n").count(), 31);
+    assert_eq!(out.matches("832040\n").count(), 2);
+}
+
+#[test]
+fn memoize_rejects_non_functions() {
+    run(&["Set f to memoize with 3"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("memoize expects a function"));
+}
+
+// Timing-sensitive micro-benchmark; run with `cargo test --release -- --ignored`
+#[test]
+#[ignore]
+fn slot_resolution_speeds_up_tight_loops() {
+    use std::time::Instant;
+    let calls = ["Write fib(20)", "Write sum_to(100000)"];
+    let resolved: Vec<&str> = FIB_AND_SUM.iter().chain(&calls).copied().collect();
+    let dynamic: Vec<&str> = dynamic_fib_and_sum().into_iter().chain(calls).collect();
+
+    let start = Instant::now();
+    let fast = program_output(&resolved);
+    let fast_time = start.elapsed();
+    let start = Instant::now();
+    let slow = program_output(&dynamic);
+    let slow_time = start.elapsed();
+
+    println!("slot-resolved: {:?}, name-based: {:?}", fast_time, slow_time);
+    assert_eq!(fast, slow);
+    assert!(fast_time < slow_time);
+}
+
+#[test]
+fn repeated_runs_in_one_process_do_not_share_function_bodies() {
+    // Both programs define `helper` on this thread; the second run must only
+    // ever see its own definition
+    let first = pohlang::parser::parse(
+        "Start Program\nMake helper with x\n    Return x plus 1\nEnd\nIf helper(1) is not 2\n    Throw \"first run saw the wrong helper\"\nEnd\nEnd Program\n",
+    )
+    .unwrap();
+    let second = pohlang::parser::parse(
+        "Start Program\nMake helper with x\n    Return x times 10\nEnd\nIf helper(1) is not 10\n    Throw \"second run saw the wrong helper\"\nEnd\nEnd Program\n",
+    )
+    .unwrap();
+    for _ in 0..2 {
+        pohlang::execute_program(&first).unwrap();
+        pohlang::execute_program(&second).unwrap();
+    }
+
+    let failing = pohlang::parser::parse(
+        "Start Program\nMake helper with x\n    Return x\nEnd\nIf helper(1) is not 10\n    Throw \"wrong helper\"\nEnd\nEnd Program\n",
+    )
+    .unwrap();
+    assert!(pohlang::execute_program(&failing).is_err());
+}
@@ -0,0 +1,105 @@
+use pohlang::bytecode::{BytecodeVM, Compiler};
+use pohlang::core::io::{capture_output, with_input};
+use pohlang::parser::parse;
+use pohlang::vm::{ProgramValue, Vm};
+
+/// Run `src` answering its input from `lines`, returning what it wrote
+fn run_with_input(src: &str, lines: &[&str]) -> (anyhow::Result<Option<ProgramValue>>, String) {
+    let program = parse(src).unwrap();
+    with_input(lines.iter().copied(), || capture_output(|| Vm::default().run(&program)))
+}
+
+#[test]
+fn ask_as_number_asks_again_until_it_gets_one() {
+    let (result, output) = run_with_input(
+        "Start Program\nAsk for age as number\nWrite age plus 1\nEnd Program\n",
+        &["abc", "", "41"],
+    );
+    result.unwrap();
+    assert_eq!(
+        output,
+        "'abc' is not a number. Please try again:\n\
+         '' is not a number. Please try again:\n\
+         42\n"
+    );
+}
+
+#[test]
+fn ask_as_yes_or_no_and_as_text() {
+    let (result, output) = run_with_input(
+        "Start Program\n\
+         Ask for confirmed as yes or no\n\
+         If confirmed\n    Write \"confirmed\"\nEnd\n\
+         Ask for code as text\n\
+         Write code plus 1\n\
+         End Program\n",
+        &["maybe", "Y", "42"],
+    );
+    result.unwrap();
+    // Text keeps digits as they were typed
+    assert_eq!(output, "'maybe' is not yes or no. Please try again:\nconfirmed\n421\n");
+}
+
+#[test]
+fn ask_as_number_is_a_validation_error_when_input_ends() {
+    let (result, output) = run_with_input(
+        "Start Program\n\
+         try this:\n    Ask for age as number\n\
+         if error of type \"ValidationError\" as e\n    Write \"no age\"\n\
+         end try\n\
+         End Program\n",
+        &["abc"],
+    );
+    result.unwrap();
+    assert_eq!(output, "'abc' is not a number. Please try again:\nno age\n");
+
+    let (result, output) = run_with_input(
+        "Start Program\nAsk for age as number\nEnd Program\n",
+        &["abc", "x", "y", "z"],
+    );
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("ValidationError"), "{}", err);
+    assert!(err.contains("no valid answer after 4 tries"), "{}", err);
+    assert_eq!(output.matches("Please try again").count(), 3);
+}
+
+#[test]
+fn bytecode_input_reads_typed_answers() {
+    let program = parse("Start Program\nAsk for age as number\nWrite age plus 1\nEnd Program\n").unwrap();
+    let chunk = Compiler::new().compile(program).unwrap();
+    let mut vm = BytecodeVM::new();
+    vm.load(chunk);
+    with_input(["abc", "41"], || vm.run()).unwrap();
+    assert_eq!(vm.get_output(), ["42"]);
+
+    let program = parse("Start Program\nAsk for age as number\nEnd Program\n").unwrap();
+    let mut vm = BytecodeVM::new();
+    vm.load(Compiler::new().compile(program).unwrap());
+    let err = with_input(Vec::<String>::new(), || vm.run()).unwrap_err();
+    assert!(err.to_string().contains("ValidationError"), "{}", err);
+}
+
+#[test]
+fn stdin_lines_are_read_one_by_one_keeping_empty_ones() {
+    let (result, output) = run_with_input(
+        "Start Program\n\
+         Make numbered with prefix\n    Set n to 0\n    For each line in stdin as line:\n        Set n to n plus 1\n        If line is \"stop\" then Return n\n        Write prefix plus n plus \" [\" plus line plus \"]\"\n    End For\n    Return n\nEnd\n\
+         Write \"stopped at \" plus numbered(\"#\")\n\
+         For each line in stdin as line:\n    Write \"[\" plus line plus \"]\"\nEnd For\n\
+         Write \"rest [\" plus read from stdin plus \"]\"\n\
+         End Program\n",
+        &["a", "stop", "", "  spaced  "],
+    );
+    result.unwrap();
+    assert_eq!(output, "#1 [a]\nstopped at 2\n[]\n[  spaced  ]\nrest []\n");
+}
+
+#[test]
+fn read_from_stdin_takes_the_rest_of_the_input() {
+    let (result, output) = run_with_input(
+        "Start Program\nAsk for name\nSet text to read from stdin\nWrite name plus \": \" plus text\nEnd Program\n",
+        &["Ada", "line one", "", "line three"],
+    );
+    result.unwrap();
+    assert_eq!(output, "Ada: line one\n\nline three\n\n");
+}
@@ -12,15 +12,18 @@ fn test_parse_json_object() {
     fs::write(
         &test_file,
         r#"
-Set json_string to "{\"name\":\"Alice\",\"age\":30}"
+Start Program
+Set json_string to '{"name":"Alice","age":30}'
 Set parsed to parse json from json_string
 Write "Parsed JSON successfully"
+End Program
 "#,
     )
     .unwrap();
 
     Command::cargo_bin("pohlang")
         .unwrap()
+        .arg("--run")
         .arg(test_file.to_str().unwrap())
         .assert()
         .success()
@@ -35,16 +38,19 @@ fn test_json_get() {
     fs::write(
         &test_file,
         r#"
-Set json_string to "{\"name\":\"Bob\",\"age\":25}"
+Start Program
+Set json_string to '{"name":"Bob","age":25}'
 Set parsed to parse json from json_string
 Set name to get "name" from json parsed
 Write name
+End Program
 "#,
     )
     .unwrap();
 
     Command::cargo_bin("pohlang")
         .unwrap()
+        .arg("--run")
         .arg(test_file.to_str().unwrap())
         .assert()
         .success()
@@ -59,17 +65,20 @@ fn test_json_set() {
     fs::write(
         &test_file,
         r#"
-Set json_string to "{\"name\":\"Carol\"}"
+Start Program
+Set json_string to '{"name":"Carol"}'
 Set parsed to parse json from json_string
 Set updated to set "age" in json parsed to 35
 Set json_str to convert to json updated
 Write json_str
+End Program
 "#,
     )
     .unwrap();
 
     Command::cargo_bin("pohlang")
         .unwrap()
+        .arg("--run")
         .arg(test_file.to_str().unwrap())
         .assert()
         .success()
@@ -85,16 +94,19 @@ fn test_new_json_object() {
     fs::write(
         &test_file,
         r#"
+Start Program
 Set obj to new json object
 Set obj2 to set "status" in json obj to "ok"
 Set json_str to convert to json obj2
 Write json_str
+End Program
 "#,
     )
     .unwrap();
 
     Command::cargo_bin("pohlang")
         .unwrap()
+        .arg("--run")
         .arg(test_file.to_str().unwrap())
         .assert()
         .success()
@@ -110,18 +122,21 @@ fn test_new_json_array() {
     fs::write(
         &test_file,
         r#"
+Start Program
 Set arr to new json array
 Set arr2 to push 1 to json arr
 Set arr3 to push 2 to json arr2
 Set arr4 to push 3 to json arr3
 Set json_str to convert to json arr4
 Write json_str
+End Program
 "#,
     )
     .unwrap();
 
     Command::cargo_bin("pohlang")
         .unwrap()
+        .arg("--run")
         .arg(test_file.to_str().unwrap())
         .assert()
         .success()
@@ -138,16 +153,19 @@ fn test_json_length_array() {
     fs::write(
         &test_file,
         r#"
+Start Program
 Set json_str to "[1,2,3,4,5]"
 Set arr to parse json from json_str
 Set len to json length of arr
 Write len
+End Program
 "#,
     )
     .unwrap();
 
     Command::cargo_bin("pohlang")
         .unwrap()
+        .arg("--run")
         .arg(test_file.to_str().unwrap())
         .assert()
         .success()
@@ -162,16 +180,19 @@ fn test_json_length_object() {
     fs::write(
         &test_file,
         r#"
-Set json_str to "{\"a\":1,\"b\":2,\"c\":3}"
+Start Program
+Set json_str to '{"a":1,"b":2,"c":3}'
 Set obj to parse json from json_str
 Set len to json length of obj
 Write len
+End Program
 "#,
     )
     .unwrap();
 
     Command::cargo_bin("pohlang")
         .unwrap()
+        .arg("--run")
         .arg(test_file.to_str().unwrap())
         .assert()
         .success()
@@ -186,16 +207,19 @@ fn test_json_pretty() {
     fs::write(
         &test_file,
         r#"
-Set json_str to "{\"name\":\"Dave\",\"age\":40}"
+Start Program
+Set json_str to '{"name":"Dave","age":40}'
 Set parsed to parse json from json_str
 Set pretty to convert to pretty json parsed
 Write pretty
+End Program
 "#,
     )
     .unwrap();
 
     Command::cargo_bin("pohlang")
         .unwrap()
+        .arg("--run")
         .arg(test_file.to_str().unwrap())
         .assert()
         .success()
@@ -211,17 +235,20 @@ fn test_json_roundtrip() {
     fs::write(
         &test_file,
         r#"
-Set original to "{\"items\":[1,2,3],\"status\":\"ok\"}"
+Start Program
+Set original to '{"items":[1,2,3],"status":"ok"}'
 Set parsed to parse json from original
 Set stringified to convert to json parsed
 Set reparsed to parse json from stringified
 Write "Roundtrip successful"
+End Program
 "#,
     )
     .unwrap();
 
     Command::cargo_bin("pohlang")
         .unwrap()
+        .arg("--run")
         .arg(test_file.to_str().unwrap())
         .assert()
         .success()
@@ -238,13 +265,15 @@ fn test_json_with_file() {
         &test_file,
         format!(
             r#"
+Start Program
 Set obj to new json object
 Set obj2 to set "message" in json obj to "Hello from PohLang"
 Set obj3 to set "version" in json obj2 to 1
 Set json_str to convert to pretty json obj3
-Write json_str into file at "{}"
+Set r to write json_str into file at "{}"
 Set content to read file at "{}"
 Write content
+End Program
 "#,
             json_file.to_str().unwrap().replace("\\", "\\\\"),
             json_file.to_str().unwrap().replace("\\", "\\\\")
@@ -254,6 +283,7 @@ Write content
 
     Command::cargo_bin("pohlang")
         .unwrap()
+        .arg("--run")
         .arg(test_file.to_str().unwrap())
         .assert()
         .success()
@@ -263,3 +293,53 @@ Write content
     // Verify the file was actually created
     assert!(json_file.exists());
 }
+
+#[test]
+fn test_json_path_queries() {
+    let temp_dir = TempDir::new().unwrap();
+    let test_file = temp_dir.path().join("test.poh");
+
+    fs::write(
+        &test_file,
+        r#"
+Start Program
+Import system "json_path"
+Set data to parse json from '{"users": [{"name": "Ada", "address": {"city": "London"}}, {"name": "Bo", "address": {"city": "Oslo"}}]}'
+Write json_path::query(data, "$.users[*].name")
+Write json_path::query_first(data, "$..city")
+Write json_path::query_first(data, "$.nobody")
+Write json_path::query_all('[1, [2, 3]]', "$..*")
+Write query json data with path "$.users[1].address.city"
+End Program
+"#,
+    )
+    .unwrap();
+
+    Command::cargo_bin("pohlang")
+        .unwrap()
+        .arg("--run")
+        .arg(test_file.to_str().unwrap())
+        .assert()
+        .success()
+        .stdout("[Ada, Bo]\nLondon\nNone\n[1, [2, 3], 2, 3]\n[Oslo]\n");
+}
+
+#[test]
+fn test_json_path_rejects_an_invalid_path() {
+    let temp_dir = TempDir::new().unwrap();
+    let test_file = temp_dir.path().join("test.poh");
+
+    fs::write(
+        &test_file,
+        "Start Program\nWrite query json '{\"a\": 1}' with path \"$[\"\nEnd Program\n",
+    )
+    .unwrap();
+
+    Command::cargo_bin("pohlang")
+        .unwrap()
+        .arg("--run")
+        .arg(test_file.to_str().unwrap())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("query json: '$[' is not a valid JSONPath"));
+}
@@ -0,0 +1,100 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::process::Command;
+use tempfile::TempDir;
+
+fn run_in(dir: &TempDir, program: &str) -> std::process::Output {
+    let path = dir.path().join("main.poh");
+    fs::write(&path, program).unwrap();
+    Command::cargo_bin("pohlang")
+        .unwrap()
+        .current_dir(dir.path())
+        .arg("--run")
+        .arg(&path)
+        .output()
+        .unwrap()
+}
+
+/// Log lines without their leading timestamp
+fn messages(text: &str) -> Vec<&str> {
+    text.lines().filter_map(|l| l.split_once(' ').map(|(_, m)| m)).collect()
+}
+
+#[test]
+fn log_lines_go_to_stderr_and_the_log_file() {
+    let dir = TempDir::new().unwrap();
+    let output = run_in(
+        &dir,
+        "Start Program
+Log info \"starting\"
+Log to file \"app.log\"
+Make check with n
+    Log warning \"checking \" plus n
+    Return n
+End
+Use check with 7
+Log error \"stopped\"
+End Program
+",
+    );
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(messages(&stderr), ["INFO starting", "WARN checking 7", "ERROR stopped"]);
+
+    // Only what was logged after Log to file is in the file
+    let file = fs::read_to_string(dir.path().join("app.log")).unwrap();
+    assert_eq!(messages(&file), ["WARN checking 7", "ERROR stopped"]);
+}
+
+#[test]
+fn log_file_rotates_at_the_size_limit() {
+    let dir = TempDir::new().unwrap();
+    let output = run_in(
+        &dir,
+        "Start Program
+Log to file \"app.log\" rotating at 1KB
+Set i to 0
+Repeat 200 times
+    Set i to i plus 1
+    Log info \"message number \" plus i
+End
+End Program
+",
+    );
+    assert!(output.status.success());
+
+    let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+    for name in ["app.log", "app.log.1", "app.log.5"] {
+        assert!(read(name).len() <= 1024, "{} is over the limit", name);
+    }
+    assert!(!dir.path().join("app.log.6").exists());
+    assert_eq!(messages(&read("app.log")).last(), Some(&"INFO message number 200"));
+    // Older lines were dropped with the sixth rotated file
+    assert!(!read("app.log.5").contains("message number 1\n"));
+}
+
+#[test]
+fn functions_can_switch_the_log_file() {
+    let dir = TempDir::new().unwrap();
+    let output = run_in(
+        &dir,
+        "Start Program
+Make log_to with name
+    Log to file name
+    Log info \"now logging to \" plus name
+End
+Make stop_logging with reason
+    Log to stderr
+End
+Use log_to with \"first.log\"
+Use log_to with \"second.log\"
+Use stop_logging with \"done\"
+Log info \"stderr only\"
+End Program
+",
+    );
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+    assert_eq!(messages(&read("first.log")), ["INFO now logging to first.log"]);
+    assert_eq!(messages(&read("second.log")), ["INFO now logging to second.log"]);
+}
@@ -0,0 +1,70 @@
+use assert_cmd::prelude::*;
+use pohlang::parser::parse;
+use pohlang::vm::vm::dump_program;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn poh_files(dir: &Path, found: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            poh_files(&path, found);
+        } else if path.extension().is_some_and(|e| e == "poh") {
+            found.push(path);
+        }
+    }
+}
+
+/// The parsed program, leaving out the line numbers kept for error messages
+fn shape(src: &str) -> Option<String> {
+    let debug = format!("{:?}", parse(src).ok()?);
+    let mut out = String::new();
+    let mut rest = debug.as_str();
+    while let Some(at) = rest.find("line: ") {
+        out.push_str(&rest[..at]);
+        rest = rest[at + "line: ".len()..].trim_start_matches(|c: char| c.is_ascii_digit());
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+#[test]
+fn examples_parse_back_to_the_same_program() {
+    let mut files = Vec::new();
+    poh_files(&Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples"), &mut files);
+    files.sort();
+    let mut checked = 0;
+    for path in files {
+        let src = fs::read_to_string(&path).unwrap();
+        // Examples of syntax still to come are left to the parser's own tests
+        let Some(first) = shape(&src) else { continue };
+        let printed = dump_program(&parse(&src).unwrap());
+        assert_eq!(
+            shape(&printed).as_ref(),
+            Some(&first),
+            "{} does not parse back the same from:\n{}",
+            path.display(),
+            printed
+        );
+        checked += 1;
+    }
+    assert!(checked > 0);
+}
+
+#[test]
+fn emit_normalized_writes_out_shorthands() {
+    let path = std::env::temp_dir().join("pohlang_normalized.poh");
+    fs::write(
+        &path,
+        "Start Program\nSet n to 1\nIncrease n by 2\nRepeat 3 times\n    Write n\nEnd\nEnd Program\n",
+    )
+    .unwrap();
+    Command::cargo_bin("pohlang")
+        .unwrap()
+        .args(["--emit", "normalized"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout("Start Program\n    Set n to 1\n    Set n to n plus 2\n    Repeat 3 times\n        Write n\n    End\nEnd Program\n");
+}
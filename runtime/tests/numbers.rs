@@ -0,0 +1,154 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn run(lines: &[&str], bytecode: bool) -> Command {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let path = std::env::temp_dir().join(format!("pohlang_number_test_{}.poh", ts));
+    let mut contents = String::from("Start Program\n");
+    for l in lines {
+        contents.push_str(l);
+        contents.push('\n');
+    }
+    contents.push_str("End Program\n");
+    fs::write(&path, contents).unwrap();
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run");
+    if bytecode {
+        cmd.arg("--bytecode");
+    }
+    cmd.arg(path.to_str().unwrap());
+    cmd
+}
+
+const ARITHMETIC: &[&str] = &[
+    "Write 0.1 plus 0.2",
+    "Write 9007199254740993",
+    "Write 9007199254740993 plus 1",
+    "Write 6 divided by 3",
+    "Write 7 divided by 2",
+    "Write 2 plus 0.5",
+    "Write 3 times 1.5",
+    "Write 9223372036854775807 plus 1 is greater than 9223372036854775807",
+    "Write -4 minus 3",
+];
+
+const ARITHMETIC_OUT: &str = "0.3\n9007199254740993\n9007199254740994\n2\n3.5\n2.5\n4.5\n";
+
+#[test]
+fn integer_and_decimal_arithmetic() {
+    run(ARITHMETIC, false)
+        .assert()
+        .success()
+        .stdout(format!("{}1\n-7\n", ARITHMETIC_OUT));
+}
+
+#[test]
+fn bytecode_vm_matches_integer_semantics() {
+    run(ARITHMETIC, true)
+        .assert()
+        .success()
+        .stdout(format!("{}1\n-7\n", ARITHMETIC_OUT));
+}
+
+#[test]
+fn integers_and_decimals_compare_by_value() {
+    run(
+        &[
+            "Write 2 is equal to 2.0",
+            "Write 9007199254740993 is greater than 9007199254740992.0",
+            "Write 2 is less than 2.5",
+            "Set n to count of Make a list of 1, 2",
+            "Write n is equal to 2.0",
+        ],
+        false,
+    )
+    .assert()
+    .success()
+    .stdout("1\n1\n1\n1\n");
+}
+
+#[test]
+fn rounding_gives_integers() {
+    run(
+        &[
+            "Write round 2.5",
+            "Write round down 7",
+            "Write round up 3.1",
+            "Write convert to json Make a list of round 2.6, round down 9007199254740993",
+            "Write absolute value of -3",
+        ],
+        false,
+    )
+    .assert()
+    .success()
+    .stdout("3\n7\n4\n[3,9007199254740993]\n3\n");
+}
+
+#[test]
+fn list_builtins_keep_integers() {
+    run(
+        &[
+            "Set xs to Make a list of 1, 2, 3",
+            "Write convert to json Make a list of total of xs, largest in xs, smallest in xs",
+            "Write total of Make a list of 1, 2.5",
+            "Write convert to json range(3)",
+            "Write xs[1 plus 0.0]",
+        ],
+        false,
+    )
+    .assert()
+    .success()
+    .stdout("[6,3,1]\n3.5\n[0,1,2]\n2\n");
+}
+
+#[test]
+fn min_max_and_sum_take_several_arguments() {
+    run(
+        &[
+            "Set xs to Make a list of 4, 9",
+            "Write smallest of 3, 7 and 2",
+            "Write largest of xs, 12 and 1",
+            "Write smallest of 3 plus 5, 2 times 5",
+            "Write max(3, 7, 2)",
+            "Write min(xs, range(5, 8), 6)",
+            "Write sum(1, 2.5, xs)",
+            "Write convert to json Make a list of sum(xs), min(xs), max(9)",
+        ],
+        false,
+    )
+    .assert()
+    .success()
+    .stdout("2\n12\n8\n7\n4\n16.5\n[13,4,9]\n");
+
+    run(&["Write min(Make a list of, Make a list of)"], false)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("smallest in an empty list is undefined"));
+    run(&["Write largest of 1, \"two\""], false)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("largest in expects numbers; item 1 was two"));
+}
+
+#[test]
+fn json_round_trips_integers_and_decimals() {
+    run(
+        &[
+            r#"Set data to parse json from '{"big": 9007199254740993, "half": 0.5, "two": 2.0}'"#,
+            "Write data[\"big\"] plus 1",
+            "Write data[\"half\"] plus data[\"half\"]",
+            "Write convert to json data[\"big\"]",
+            "Write convert to json data[\"two\"]",
+            "Write convert to json Make a list of 1, 2.5, 7 divided by 2, 8 divided by 2",
+        ],
+        false,
+    )
+    .assert()
+    .success()
+    .stdout("9007199254740994\n1\n9007199254740993\n2.0\n[1,2.5,3.5,4]\n");
+}
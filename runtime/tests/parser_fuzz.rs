@@ -0,0 +1,112 @@
+use pohlang::parser::parse;
+use pohlang::parser::parser::parse_expr;
+use proptest::prelude::*;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Longest any one input may take to parse before it counts as a hang
+const BUDGET: Duration = Duration::from_secs(5);
+
+/// Every phrase the parser looks for, read from phrases.rs so new phrases are
+/// fuzzed without touching this file, plus the statement keywords and the
+/// characters that sit at the edges of them. The non-ASCII ones change byte
+/// length when their case changes or take more than one byte.
+fn fragments() -> Vec<String> {
+    let mut out: Vec<String> = include_str!("../src/parser/phrases.rs")
+        .lines()
+        .filter_map(|line| line.split_once(": &str = \"")?.1.split_once("\";"))
+        .map(|(phrase, _)| phrase.to_string())
+        .collect();
+    for extra in [
+        "Start Program\n", "End Program\n", "Write ", "Set ", " to ", "If ", "Otherwise\n", "End\n",
+        "Repeat ", " times\n", "While ", "Make ", " with ", "Return ", "Use ", "Increase ", " by ",
+        "Ask for ", "Import ", "try this:\n", "if error\n", "end try\n", "\"", "\"\"", "[", "]",
+        "(", ")", "{", "}", ",", ":", "::", ".", "-", "0", "1.5", "x", "\n", "    ", "\t", "é",
+        "İ", "ß", "K", "ǅ", "🙂", "\u{301}", "\u{a0}", "\u{2028}",
+    ] {
+        out.push(extra.to_string());
+    }
+    out
+}
+
+fn phrase_soup() -> impl Strategy<Value = String> {
+    prop::collection::vec(prop::sample::select(fragments()), 0..24).prop_map(|parts| parts.concat())
+}
+
+/// Parse on a thread of its own so a hang fails the test instead of stalling
+/// it, with the main thread's larger stack for the nesting limit
+fn parses_without_panicking(src: String) -> Result<(), TestCaseError> {
+    let (done, finished) = mpsc::channel();
+    let input = src.clone();
+    thread::Builder::new()
+        .stack_size(8 << 20)
+        .spawn(move || {
+            let program = std::panic::catch_unwind(|| drop(parse(&input))).is_ok();
+            let expr = std::panic::catch_unwind(|| drop(parse_expr(&input))).is_ok();
+            let _ = done.send(program && expr);
+        })
+        .unwrap();
+    match finished.recv_timeout(BUDGET) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(TestCaseError::fail(format!("the parser panicked on {:?}", src))),
+        Err(_) => Err(TestCaseError::fail(format!("the parser took longer than {:?} on {:?}", BUDGET, src))),
+    }
+}
+
+/// Lines of the example programs, so mutations start from text that parses
+fn example_lines() -> Vec<String> {
+    fn walk(dir: &std::path::Path, out: &mut Vec<String>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                walk(&path, out);
+            } else if path.extension().is_some_and(|e| e == "poh") {
+                let src = std::fs::read_to_string(&path).unwrap();
+                out.extend(src.lines().filter(|l| !l.trim().is_empty()).map(str::to_string));
+            }
+        }
+    }
+    let mut out = Vec::new();
+    walk(&std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples"), &mut out);
+    out
+}
+
+/// An example line with fragments spliced in at character boundaries
+fn mutated_line() -> impl Strategy<Value = String> {
+    let edits = prop::collection::vec((any::<prop::sample::Index>(), prop::sample::select(fragments())), 1..4);
+    (prop::sample::select(example_lines()), edits).prop_map(|(line, edits)| {
+        let mut line = line;
+        for (at, fragment) in edits {
+            let bounds: Vec<usize> = line.char_indices().map(|(i, _)| i).chain([line.len()]).collect();
+            line.insert_str(bounds[at.index(bounds.len())], &fragment);
+        }
+        line
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig { cases: 512, ..ProptestConfig::default() })]
+
+    #[test]
+    fn any_text_parses_or_errors(src in any::<String>()) {
+        parses_without_panicking(src)?;
+    }
+
+    #[test]
+    fn phrases_and_multi_byte_text_parse_or_error(src in phrase_soup()) {
+        parses_without_panicking(src)?;
+    }
+
+    #[test]
+    fn programs_of_phrases_parse_or_error(body in prop::collection::vec(phrase_soup(), 0..8)) {
+        let src = format!("Start Program\n{}\nEnd Program\n", body.join("\n"));
+        parses_without_panicking(src)?;
+    }
+
+    #[test]
+    fn example_lines_with_text_spliced_in_parse_or_error(lines in prop::collection::vec(mutated_line(), 1..6)) {
+        parses_without_panicking(lines.join("\n"))?;
+        parses_without_panicking(format!("Start Program\n{}\nEnd Program\n", lines.join("\n")))?;
+    }
+}
@@ -59,7 +59,7 @@ fn split_by_separator() {
 #[test]
 fn contains_in_list_and_string() {
     let mut cmd = run(&[
-        "Set nums to [1, 2, 3, 4]",
+        "Set nums to Make a list of 1, 2, 3, 4",
         "Write contains 3 in nums",
         "Write contains 5 in nums",
         "Set text to \"hello world\"",
@@ -75,7 +75,7 @@ fn contains_in_list_and_string() {
 #[test]
 fn remove_from_list() {
     let mut cmd = run(&[
-        "Set nums to [1, 2, 3, 2, 4]",
+        "Set nums to Make a list of 1, 2, 3, 2, 4",
         "Set result to remove 2 from nums",
         "Write count of result",
         "Write first in result",
@@ -89,7 +89,7 @@ fn remove_from_list() {
 #[test]
 fn append_to_list() {
     let mut cmd = run(&[
-        "Set nums to [1, 2, 3]",
+        "Set nums to Make a list of 1, 2, 3",
         "Set result to append 4 to nums",
         "Write count of result",
         "Write last in result",
@@ -103,7 +103,7 @@ fn append_to_list() {
 #[test]
 fn insert_at_index_in_list() {
     let mut cmd = run(&[
-        "Set nums to [1, 2, 4]",
+        "Set nums to Make a list of 1, 2, 4",
         "Set result to insert 3 at 2 in nums",
         "Write count of result",
         "Write result[2]",
@@ -113,3 +113,93 @@ fn insert_at_index_in_list() {
         .stdout(predicate::str::contains("4"))
         .stdout(predicate::str::contains("3"));
 }
+
+#[test]
+fn average_and_median_of_list() {
+    let mut cmd = run(&[
+        "Set nums to Make a list of 4, 1, 3, 2",
+        "Write average of nums",
+        "Write median of nums",
+        "Write median of Make a list of 9, 2, 5",
+        "Write nums[0]",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("2.5\n2.5\n5\n4"));
+}
+
+#[test]
+fn variance_and_standard_deviation_of_list() {
+    let mut cmd = run(&[
+        "Set nums to Make a list of 2, 4, 4, 4, 5, 5, 7, 9",
+        "Write variance of nums",
+        "Write standard deviation of nums",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("4\n2"));
+}
+
+#[test]
+fn average_of_empty_list_is_an_error() {
+    let mut cmd = run(&["Write average of Make a list of"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("empty list"));
+}
+
+#[test]
+fn median_rejects_non_numbers() {
+    let mut cmd = run(&["Write median of Make a list of 1, \"two\""]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("expects numbers"));
+}
+
+#[test]
+fn interpolate_clamp_and_map_between_ranges() {
+    let mut cmd = run(&[
+        "Write interpolate from 0 to 10 at 0.25",
+        "Write clamp 15 between 0 and 10",
+        "Write clamp -3 between 0 and 10",
+        "Write map 5 from range 0 to 10 into range 100 to 200",
+        "Write lerp(10, 20, 1)",
+        "Write clamp(2.5, 0, 10)",
+        "Write map_range(50, 0, 100, 0, 1)",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("2.5\n10\n0\n150\n20\n2.5\n0.5"));
+}
+
+#[test]
+fn map_from_an_empty_range_is_an_error() {
+    let mut cmd = run(&["Write map 1 from range 2 to 2 into range 0 to 1"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("the range 2 to 2 is empty"));
+}
+
+#[test]
+fn indent_dedent_and_wrap_text() {
+    let mut cmd = run(&[
+        "Set code to \"if x:\\n    y\\n\\nz\"",
+        "Set block to indent code by 4 spaces",
+        "Write block",
+        "Write remove common indent from block",
+        "Write wrap \"the quick brown fox jumps\" at 10 characters",
+        "Set indent to 2",
+        "Write indent plus 1",
+    ]);
+    cmd.assert().success().stdout(
+        "    if x:\n        y\n\n    z\nif x:\n    y\n\nz\nthe quick\nbrown fox\njumps\n3\n",
+    );
+}
+
+#[test]
+fn wrap_needs_a_positive_width() {
+    let mut cmd = run(&["Write wrap \"a b\" at 0 characters"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("the width must be a whole number of characters, 1 or more, got 0"));
+}
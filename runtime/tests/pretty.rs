@@ -0,0 +1,90 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn run(lines: &[&str]) -> Command {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let path = std::env::temp_dir().join(format!("pohlang_pretty_test_{}.poh", ts));
+    let mut contents = String::from("Start Program\nImport system \"collections\"\n");
+    for l in lines {
+        contents.push_str(l);
+        contents.push('\n');
+    }
+    contents.push_str("End Program\n");
+    fs::write(&path, contents).unwrap();
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd
+}
+
+/// A config-like value: dictionaries in lists in dictionaries, with text
+/// that needs escaping and empty collections
+const FIXTURE: &[&str] = &[
+    "Set none to Make a list of",
+    "Set point to tuple of 3 and 4",
+    "Set alice to Make a dictionary with \"name\" as \"Alice\", \"roles\" as none",
+    "Set bob to Make a dictionary with \"name\" as \"Bob \\\"B\\\" Smith\", \"at\" as point",
+    "Set users to Make a list of alice, bob",
+    "Set config to Make a dictionary with \"version\" as 2, \"users\" as users, \"debug\" as False",
+];
+
+#[test]
+fn nested_values_print_one_entry_per_line() {
+    let mut lines = FIXTURE.to_vec();
+    lines.push("Write config nicely");
+    run(&lines).assert().success().stdout(
+        r#"{
+  "debug": False,
+  "users": [
+    {
+      "name": "Alice",
+      "roles": []
+    },
+    {
+      "at": (
+        3,
+        4
+      ),
+      "name": "Bob \"B\" Smith"
+    }
+  ],
+  "version": 2
+}
+"#,
+    );
+}
+
+#[test]
+fn nicely_works_inside_functions_and_on_plain_values() {
+    let mut lines = FIXTURE.to_vec();
+    lines.extend([
+        "Make show with value",
+        "    Write value nicely",
+        "End",
+        "Use show with users",
+        "Write 42 nicely",
+        "Write \"text\" nicely",
+    ]);
+    let output = run(&lines).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    assert!(stdout.starts_with("[\n  {\n    \"name\": \"Alice\",\n"), "unexpected output: {}", stdout);
+    assert!(stdout.ends_with("\n]\n42\n\"text\"\n"), "unexpected output: {}", stdout);
+}
+
+#[test]
+fn a_collection_holding_itself_prints_cycle() {
+    run(&[
+        "Set s to collections::new_stack()",
+        "Set s to collections::push(s, 1)",
+        "Set s to collections::push(s, s)",
+        "Write s nicely",
+    ])
+    .assert()
+    .success()
+    .stdout("Stack[\n  1,\n  <cycle>\n]\n");
+}
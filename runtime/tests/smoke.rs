@@ -147,6 +147,48 @@ fn run_while_block_counts() {
         .stdout(predicates::str::contains("3"));
 }
 
+#[test]
+fn repeat_until_checks_its_condition_before_each_pass() {
+    let path = write_program(&[
+        "Set n to 0",
+        "Repeat until n is greater than 2",
+        "    Write n",
+        "    Set n to n plus 1",
+        "End",
+        "Repeat until true",
+        "    Write \"never\"",
+        "End",
+        "Make countdown with k",
+        "    Repeat until k is 0",
+        "        Write k",
+        "        Set k to k minus 1",
+        "    End",
+        "    Return \"done\"",
+        "End",
+        "Write countdown(2)",
+    ]);
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().success().stdout("0\n1\n2\n2\n1\ndone\n");
+}
+
+#[test]
+fn negative_numbers_and_unary_minus() {
+    let path = write_program(&[
+        "Set x to -5",
+        "Write x",
+        "Write -x",
+        "Write -(1 plus 2)",
+        "Write 3 - -2",
+        "Write Make a list of -1, -x and -2.5",
+    ]);
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().success().stdout("-5\n5\n-3\n5\n[-1, 5, -2.5]\n");
+}
+
 #[test]
 fn import_local_file_and_call() {
     let dir = tempdir().unwrap();
@@ -174,6 +216,117 @@ fn import_local_file_and_call() {
         .stdout(predicates::str::contains("Hi Alice"));
 }
 
+#[test]
+fn return_in_an_imported_module_ends_only_that_module() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join("lib.poh"),
+        "Start Program\nMake hello with who Write \"Hi \" plus who\nReturn 1\nWrite \"unreachable\"\nEnd Program\n",
+    )
+    .unwrap();
+
+    let main_path = dir.path().join("main.poh");
+    fs::write(
+        &main_path,
+        "Start Program\nImport \"lib\"\nWrite hello(\"Alice\")\nEnd Program\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(main_path.to_str().unwrap());
+    cmd.assert().success().stdout("Hi Alice\n");
+}
+
+#[test]
+fn import_local_files_with_aliases_keep_names_apart() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join("english.poh"),
+        "Start Program\nMake greet with who\n    Return \"Hello \" plus who\nEnd\nEnd Program\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("french.poh"),
+        "Start Program\nMake greet with who\n    Return \"Bonjour \" plus who\nEnd\nEnd Program\n",
+    )
+    .unwrap();
+
+    let main_path = dir.path().join("main.poh");
+    fs::write(
+        &main_path,
+        "Start Program\nImport \"english\" as En\nImport \"french.poh\" as Fr\nWrite En::greet(\"Ada\")\nWrite Fr::greet(\"Ada\")\nWrite greet(\"Ada\")\nEnd Program\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(main_path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout(predicates::str::contains("Hello Ada\nBonjour Ada"))
+        .stderr(predicates::str::contains("Function 'greet' is not defined"));
+}
+
+#[test]
+fn modules_with_same_named_helpers_keep_their_own_bodies() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join("add.poh"),
+        "Start Program\nMake helper with x\n    Return x plus 1\nEnd\nMake run with x\n    Return helper(x)\nEnd\nEnd Program\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("scale.poh"),
+        "Start Program\nMake helper with x\n    Return x times 10\nEnd\nMake run with x\n    Return helper(x)\nEnd\nEnd Program\n",
+    )
+    .unwrap();
+
+    let main_path = dir.path().join("main.poh");
+    fs::write(
+        &main_path,
+        "Start Program\nImport \"add\" as A\nImport \"scale\" as S\nWrite A::run(4)\nWrite S::run(4)\nWrite A::helper(4)\nEnd Program\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(main_path.to_str().unwrap());
+    cmd.assert().success().stdout("5\n40\n5\n");
+}
+
+#[test]
+fn import_items_from_a_file_brings_in_only_those_names() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join("lib.poh"),
+        "Start Program\nSet rate to 3\nMake helper with x\n    Return x plus 1\nEnd\nMake triple with x\n    Return helper(x) times rate\nEnd\nEnd Program\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("missing.poh"),
+        "Start Program\nImport triple, quadruple from \"lib\"\nEnd Program\n",
+    )
+    .unwrap();
+
+    let main_path = dir.path().join("main.poh");
+    fs::write(
+        &main_path,
+        "Start Program\nImport triple and rate from \"lib.poh\"\nWrite triple(2)\nWrite rate\nWrite helper(2)\nEnd Program\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(main_path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout("9\n3\n")
+        .stderr(predicates::str::contains("Function 'helper' is not defined"));
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(dir.path().join("missing.poh").to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("Module 'lib.poh' does not export 'quadruple'"));
+}
+
 #[test]
 fn system_import_stub_noop() {
     let path = write_program(&["Import system \"collections\"", "Write \"OK\""]);
@@ -197,7 +350,7 @@ fn system_import_with_alias_and_exposing() {
 
     let program = write_program(&[
         "Import system \"collections\" as coll exposing head",
-        "Set nums to [1, 2, 3]",
+        "Set nums to Make a list of 1, 2, 3",
         "Write head(nums)",
         "Write coll::head(nums)",
     ]);
@@ -233,7 +386,7 @@ fn ask_for_parses_correctly() {
 #[test]
 fn list_indexing_works() {
     let path = write_program(&[
-        "Set nums to [10, 20, 30, 40]",
+        "Set nums to Make a list of 10, 20, 30, 40",
         "Write nums[0]",
         "Write nums[2]",
         "Write nums[-1]",
@@ -251,7 +404,7 @@ fn list_indexing_works() {
 #[test]
 fn dict_indexing_works() {
     let path = write_program(&[
-        "Set person to {name: \"Bob\", age: 25}",
+        "Set person to Make a dictionary with \"name\" as \"Bob\", \"age\" as 25",
         "Write person[\"name\"]",
         "Write person[\"age\"]",
     ]);
@@ -267,7 +420,9 @@ fn dict_indexing_works() {
 #[test]
 fn nested_indexing_works() {
     let path = write_program(&[
-        "Set matrix to [[1, 2], [3, 4]]",
+        "Set row1 to Make a list of 1, 2",
+        "Set row2 to Make a list of 3, 4",
+        "Set matrix to Make a list of row1, row2",
         "Write matrix[0][1]",
         "Write matrix[1][0]",
     ]);
@@ -294,7 +449,7 @@ fn string_indexing_works() {
 
 #[test]
 fn index_out_of_bounds_error() {
-    let path = write_program(&["Set nums to [1, 2, 3]", "Write nums[10]"]);
+    let path = write_program(&["Set nums to Make a list of 1, 2, 3", "Write nums[10]"]);
 
     let mut cmd = Command::cargo_bin("pohlang").unwrap();
     cmd.arg("--run").arg(path.to_str().unwrap());
@@ -305,7 +460,7 @@ fn index_out_of_bounds_error() {
 
 #[test]
 fn dict_key_not_found_error() {
-    let path = write_program(&["Set data to {x: 1}", "Write data[\"missing\"]"]);
+    let path = write_program(&["Set data to Make a dictionary with \"x\" as 1", "Write data[\"missing\"]"]);
 
     let mut cmd = Command::cargo_bin("pohlang").unwrap();
     cmd.arg("--run").arg(path.to_str().unwrap());
@@ -317,11 +472,13 @@ fn dict_key_not_found_error() {
 #[test]
 fn modern_list_syntax_works() {
     let path = write_program(&[
-        "Set nums to [1, 2, 3]",
+        "Set nums to Make a list of 1, 2, 3",
         "Write nums",
-        "Set empty to []",
+        "Set empty to Make a list of",
         "Write empty",
-        "Set nested to [[1, 2], [3, 4]]",
+        "Set row1 to Make a list of 1, 2",
+        "Set row2 to Make a list of 3, 4",
+        "Set nested to Make a list of row1, row2",
         "Write nested",
     ]);
 
@@ -337,11 +494,11 @@ fn modern_list_syntax_works() {
 #[test]
 fn modern_dict_syntax_works() {
     let path = write_program(&[
-        "Set person to {name: \"Alice\", age: 30}",
+        "Set person to Make a dictionary with \"name\" as \"Alice\", \"age\" as 30",
         "Write person",
-        "Set config to {\"host\": \"localhost\", \"port\": 8080}",
+        "Set config to Make a dictionary with \"host\" as \"localhost\", \"port\" as 8080",
         "Write config",
-        "Set empty to {}",
+        "Set empty to Make a dictionary with",
         "Write empty",
     ]);
 
@@ -354,12 +511,33 @@ fn modern_dict_syntax_works() {
         .stdout(predicates::str::contains("{}"));
 }
 
+#[test]
+fn dict_output_is_sorted_by_key() {
+    let path = write_program(&[
+        "Set a to Make a dictionary with \"e\" as 5, \"b\" as 2, \"d\" as 4, \"a\" as 1, \"c\" as 3",
+        "Set b to Make a dictionary with \"c\" as 3, \"a\" as 1, \"e\" as 5, \"d\" as 4, \"b\" as 2",
+        r#"Set c to parse json from '{"d": 4, "c": 3, "b": 2, "e": 5, "a": 1}'"#,
+        "Write a",
+        "Write b",
+        "Write c",
+        "Write convert to json b",
+    ]);
+
+    let sorted = "{\"a\": 1, \"b\": 2, \"c\": 3, \"d\": 4, \"e\": 5}\n";
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().success().stdout(format!(
+        "{}{}{}{{\"a\":1,\"b\":2,\"c\":3,\"d\":4,\"e\":5}}\n",
+        sorted, sorted, sorted
+    ));
+}
+
 #[test]
 fn collections_with_expressions() {
     let path = write_program(&[
-        "Set calculated to [5 plus 5, 10 times 2, 30 divided by 3]",
+        "Set calculated to Make a list of (5 plus 5), (10 times 2), (30 divided by 3)",
         "Write calculated",
-        "Set math to {sum: 10 plus 5, product: 10 times 5}",
+        "Set math to Make a dictionary with \"sum\" as (10 plus 5), \"product\" as (10 times 5)",
         "Write math",
     ]);
 
@@ -372,20 +550,14 @@ fn collections_with_expressions() {
 }
 
 #[test]
-fn legacy_collection_syntax_still_works() {
-    let path = write_program(&[
-        "Set nums to List contains 1, 2, 3",
-        "Write nums",
-        "Set dict to Dictionary contains \"x\" set to 5, \"y\" set to 10",
-        "Write dict",
-    ]);
+fn legacy_collection_syntax_is_rejected() {
+    let path = write_program(&["Set nums to List contains 1, 2, 3", "Write nums"]);
 
     let mut cmd = Command::cargo_bin("pohlang").unwrap();
     cmd.arg("--run").arg(path.to_str().unwrap());
     cmd.assert()
-        .success()
-        .stdout(predicates::str::contains("[1, 2, 3]"))
-        .stdout(predicates::str::contains("5"));
+        .failure()
+        .stderr(predicates::str::contains("Make a list of"));
 }
 
 #[test]
@@ -412,6 +584,76 @@ fn increase_decrease_desugar_works() {
     cmd.assert().success();
 }
 
+#[test]
+fn increase_and_decrease_default_to_one() {
+    let path = write_program(&[
+        "Set counter to 10",
+        "Increase counter",
+        "Write counter",
+        "Increase counter by 5",
+        "Write counter",
+        "Decrease counter",
+        "Decrease counter",
+        "Write counter",
+    ]);
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().success().stdout("11\n16\n14\n");
+}
+
+#[test]
+fn increase_with_something_other_than_by_suggests_by() {
+    let path = write_program(&["Set counter to 1", "Increase counter with 2"]);
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "Expected 'by <amount>' after 'Increase counter', got: with 2.\nHint: Give the amount with 'by': Increase counter by 2",
+    ));
+}
+
+#[test]
+fn increase_and_set_reach_list_items_and_dictionary_values() {
+    let path = write_program(&[
+        "Set scores to Make a list of 1, 2, 3",
+        "Set stats to Make a dictionary with \"wins\" set to 0",
+        "Increase scores[0] by 10",
+        "Decrease scores[-1] by 1",
+        "Set scores[1] to 20",
+        "Increase \"wins\" in stats by 2",
+        "Set key to \"wins\"",
+        "Increase key in stats by 1",
+        "Set i to 0",
+        "Repeat 3 times",
+        "    Increase scores[i] by 1",
+        "    Increase i by 1",
+        "End",
+        "Make bump with k",
+        "    Increase k in stats by 5",
+        "    Increase scores[0] by 100",
+        "End",
+        "Use bump with \"wins\"",
+        "Write scores",
+        "Write stats",
+        "Increase scores[7] by 1",
+    ]);
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout("[112, 21, 3]\n{\"wins\": 8}\n")
+        .stderr(predicates::str::contains("List index out of range: 7 (list length: 3)"));
+
+    let path = write_program(&["Set stats to Make a dictionary with \"a\" set to 1", "Increase \"b\" in stats by 1"]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("Key not found in dictionary: \"b\""));
+}
+
 #[test]
 fn arithmetic_operators_work() {
     let path = write_program(&[
@@ -550,11 +792,11 @@ fn complex_logical_expressions() {
 #[test]
 fn test_length_builtin() {
     let path = write_program(&[
-        "Set nums to [1, 2, 3, 4, 5]",
+        "Set nums to Make a list of 1, 2, 3, 4, 5",
         "Write length(nums)",
         "Set text to \"hello\"",
         "Write length(text)",
-        "Set empty to []",
+        "Set empty to Make a list of",
         "Write length(empty)",
     ]);
 
@@ -620,12 +862,71 @@ fn test_range_builtin_comprehensive() {
         .stdout(predicates::str::contains("3"));
 }
 
+#[test]
+fn huge_ranges_answer_without_making_their_items() {
+    let path = write_program(&[
+        "Set big to range(1, 3000000001)",
+        "Write count of big",
+        "Write total of big",
+        "Write first in big",
+        "Write last in big",
+        "Write big[-2]",
+        "Write contains 2999999999 in big",
+        "Write contains 0 in big",
+        "Set odd to range(9, 0, -2)",
+        "Write odd",
+        "Write total of odd",
+        "Write contains 5 in odd",
+        "Write contains 4 in odd",
+        "Write reverse of odd",
+        "Write range(3) is Make a list of 0, 1, 2",
+        "Write count of range(5, 5)",
+    ]);
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().success().stdout(
+        "3000000000\n4500000001500000000\n1\n3000000000\n2999999999\nTrue\nFalse\n\
+         [9, 7, 5, 3, 1]\n25\nTrue\nFalse\n[1, 3, 5, 7, 9]\n1\n0\n",
+    );
+}
+
+#[test]
+fn for_each_walks_lists_and_huge_ranges_one_item_at_a_time() {
+    let path = write_program(&[
+        "Set total to 0",
+        "For each n in range(1, 5):",
+        "    Set total to total plus n",
+        "End For",
+        "Write total",
+        "Make squares with xs",
+        "    Set out to Make a list of",
+        "    For each x in xs:",
+        "        Set out to Append x times x to out",
+        "    End For",
+        "    Return out",
+        "End",
+        "Write squares(range(4))",
+        "Write squares(Make a list of 5, 6)",
+        "For each n in range(0, 3000000000):",
+        "    If n is 2",
+        "        Write \"stopped at \" plus n",
+        "        Return n",
+        "    End",
+        "End For",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    // A three-billion-item list would be far over the limit
+    cmd.args(["--run", "--memory-limit", "100000"]).arg(path.to_str().unwrap());
+    cmd.assert().success().stdout("10\n[0, 1, 4, 9]\n[25, 36]\nstopped at 2\n");
+}
+
 #[test]
 fn test_join_builtin_comprehensive() {
     let path = write_program(&[
-        "Set words to [\"Hello\", \"World\", \"PohLang\"]",
+        "Set words to Make a list of \"Hello\", \"World\", \"PohLang\"",
         "Write join(words, \" \")",
-        "Set nums to [1, 2, 3]",
+        "Set nums to Make a list of 1, 2, 3",
         "Write join(nums, \"-\")",
     ]);
 
@@ -636,3 +937,1591 @@ fn test_join_builtin_comprehensive() {
         .stdout(predicates::str::contains("Hello World PohLang"))
         .stdout(predicates::str::contains("1-2-3"));
 }
+
+#[test]
+fn bench_mode_runs_program_repeatedly() {
+    let path = write_program(&["Write \"tick\""]);
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--bench").arg("3").arg(path.to_str().unwrap());
+    cmd.assert()
+        .success()
+        .stdout("tick\ntick\ntick\n")
+        .stderr(predicates::str::contains("3 runs: min"))
+        .stderr(predicates::str::contains("avg"))
+        .stderr(predicates::str::contains("max"));
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--bench").arg("0").arg(path.to_str().unwrap());
+    cmd.assert().failure();
+}
+
+fn run_encoded(bytes: &[u8], encoding: Option<&str>) -> assert_cmd::assert::Assert {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("main.poh");
+    fs::write(&path, bytes).unwrap();
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    if let Some(encoding) = encoding {
+        cmd.arg("--input-encoding").arg(encoding);
+    }
+    cmd.arg("--run").arg(&path).assert()
+}
+
+#[test]
+fn input_encoding_latin1_applies_to_imports() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join("greetings.poh"),
+        b"Start Program\nMake greet with who\n    Return \"Ol\xE1 \" plus who\nEnd\nEnd Program\n",
+    )
+    .unwrap();
+    let main_path = dir.path().join("main.poh");
+    fs::write(
+        &main_path,
+        b"Start Program\nImport \"greetings\"\nWrite greet(\"Jos\xE9\")\nEnd Program\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--input-encoding").arg("latin-1");
+    cmd.arg("--run").arg(&main_path);
+    cmd.assert().success().stdout("Olá José\n");
+}
+
+#[test]
+fn input_encoding_windows_1252() {
+    run_encoded(
+        b"Start Program\nWrite \"\x93price\x94 \x96 5\x80\"\nEnd Program\n",
+        Some("windows-1252"),
+    )
+    .success()
+    .stdout("\u{201c}price\u{201d} \u{2013} 5\u{20ac}\n");
+}
+
+#[test]
+fn input_encoding_utf8_strips_bom() {
+    run_encoded(
+        "\u{feff}Start Program\nWrite \"naïve\"\nEnd Program\n".as_bytes(),
+        None,
+    )
+    .success()
+    .stdout("naïve\n");
+}
+
+#[test]
+fn input_encoding_rejects_invalid_utf8_and_unknown_names() {
+    run_encoded(b"Start Program\nWrite \"caf\xE9\"\nEnd Program\n", None)
+        .failure()
+        .stderr(predicates::str::contains("--input-encoding latin-1"));
+    run_encoded(b"Start Program\nEnd Program\n", Some("shift-jis"))
+        .failure()
+        .stderr(predicates::str::contains("unsupported encoding"));
+}
+
+#[test]
+fn load_env_vars_with_prefix() {
+    let path = write_program(&[
+        "Load env vars starting with \"POHTEST_\" stripping prefix",
+        "Write port plus 1",
+        "Write db_host",
+        "Load env vars starting with \"POHTEST_\"",
+        "Write POHTEST_DB_HOST",
+        "Make show with label",
+        "    Load env vars starting with \"POHTEST_RATIO_\" stripping prefix",
+        "    Return \"loaded\"",
+        "End",
+        "Write show(\"x\")",
+        "Write x times 2",
+    ]);
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.env("POHTEST_PORT", "8080")
+        .env("POHTEST_DB_HOST", "localhost")
+        .env("POHTEST_RATIO_X", "0.25")
+        .arg("--run")
+        .arg(path.to_str().unwrap());
+    cmd.assert()
+        .success()
+        .stdout("8081\nlocalhost\nlocalhost\nloaded\n0.5\n");
+}
+
+#[test]
+fn load_env_vars_requires_a_quoted_prefix() {
+    let path = write_program(&["Load env vars starting with APP_"]);
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("Expected a quoted prefix"));
+}
+
+#[test]
+fn top_level_return_ends_the_program() {
+    let path = write_program(&[
+        "Write \"start\"",
+        "Repeat 3 times",
+        "    Write \"loop\"",
+        "    If True",
+        "        Return 3 plus 4",
+        "    End",
+        "End",
+        "Write \"after\"",
+    ]);
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().success().stdout("start\nloop\n");
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run")
+        .arg("--exit-with-return")
+        .arg(path.to_str().unwrap());
+    cmd.assert().code(7).stdout("start\nloop\n");
+}
+
+#[test]
+fn finally_runs_while_a_return_unwinds() {
+    let path = write_program(&[
+        "try this:",
+        "    Return 2",
+        "finally:",
+        "    Write \"cleanup\"",
+        "end try",
+        "Write \"after\"",
+    ]);
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run")
+        .arg("--exit-with-return")
+        .arg(path.to_str().unwrap());
+    cmd.assert().code(2).stdout("cleanup\n");
+}
+
+// Needs the panic hook that only debug builds of pohlang have
+#[cfg(debug_assertions)]
+#[test]
+fn reformat_errors_reports_internal_panics_as_bugs() {
+    use predicates::prelude::PredicateBooleanExt;
+    let path = write_program(&["Write 1"]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg("--reformat-errors").arg(path.to_str().unwrap());
+    cmd.env("POHLANG_TEST_PANIC", "called unwrap on None")
+        .env("RUST_BACKTRACE", "0")
+        .assert()
+        .code(101)
+        .stderr(predicates::str::starts_with(
+            "Oops! PohLang encountered an internal error. Please report this bug.\n  called unwrap on None (at src/main.rs:",
+        ))
+        .stderr(predicates::str::contains("Please open an issue"))
+        .stderr(predicates::str::contains("panicked at").not())
+        .stderr(predicates::str::contains("0: ").not());
+
+    // The backtrace is still there when asked for
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg("--reformat-errors").arg(path.to_str().unwrap());
+    cmd.env("POHLANG_TEST_PANIC", "boom")
+        .env("RUST_BACKTRACE", "1")
+        .assert()
+        .code(101)
+        .stderr(predicates::str::contains("0: "));
+}
+
+#[test]
+fn parallel_branches_run_on_their_own_copies() {
+    let path = write_program(&[
+        "Set x to 1",
+        "Parallel:",
+        "    Branch:",
+        "        Set x to 10",
+        "        Write \"a \" plus x",
+        "    Branch:",
+        "        Write \"b \" plus x",
+        "End Parallel",
+        "Write x",
+        "try this:",
+        "    Parallel:",
+        "        Branch:",
+        "            Throw \"first\"",
+        "        Branch:",
+        "            Set x to 2",
+        "        Branch:",
+        "            Write 1 divided by 0",
+        "    End Parallel",
+        "if error of type \"ParallelError\" as errors",
+        "    Write length(errors)",
+        "    Write first(errors)",
+        "end try",
+    ]);
+    let output = Command::cargo_bin("pohlang")
+        .unwrap()
+        .arg("--run")
+        .arg(path.to_str().unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    // The branches may write in either order
+    lines[..2].sort();
+    assert_eq!(lines[..5], ["a 10", "b 1", "1", "2", "Branch 1: [RuntimeError] Error occurred: a runtime error - first"]);
+}
+
+#[test]
+fn parallel_branches_in_a_function_see_its_variables() {
+    let path = write_program(&[
+        "Make spread with n",
+        "    Set total to n times 2",
+        "    Parallel:",
+        "        Branch:",
+        "            Set total to 0",
+        "            Write \"a \" plus total",
+        "        Branch:",
+        "            Write \"b \" plus (total plus n)",
+        "    End Parallel",
+        "    Parallel:",
+        "        Branch:",
+        "            Throw \"inside\"",
+        "    End Parallel",
+        "    Return total",
+        "End",
+        "try this:",
+        "    Use spread with 5",
+        "if error of type \"ParallelError\" as errors",
+        "    Write first(errors)",
+        "end try",
+    ]);
+    let output = Command::cargo_bin("pohlang")
+        .unwrap()
+        .arg("--run")
+        .arg(path.to_str().unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines[..2].sort();
+    assert_eq!(lines[..2], ["a 0", "b 15"]);
+    assert!(lines[2].starts_with("Branch 1: ") && lines[2].ends_with("inside"), "{}", stdout);
+}
+
+#[test]
+fn background_blocks_are_joined_with_wait_for() {
+    let path = write_program(&[
+        "Set base to 10",
+        "Run in background as job:",
+        "    Use sleep with 0.1",
+        "    Set base to 99",
+        "    Return base plus 1",
+        "End",
+        "Write wait for job",
+        "Write base",
+        "Write wait for job",
+        "Run in background as bad:",
+        "    Throw \"webhook failed\"",
+        "End",
+        "try this:",
+        "    Write wait for bad",
+        "if error of type \"TaskError\" as errors",
+        "    Write contains \"webhook failed\" in errors[0]",
+        "end try",
+        "Run in background as asks:",
+        "    Ask for name",
+        "End",
+        "Wait for asks",
+    ]);
+    let output = Command::cargo_bin("pohlang").unwrap().arg("--run").arg(&path).output().unwrap();
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "100\n10\n100\nTrue\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Ask for name: a 'Run in background' block cannot ask for input"), "{}", stderr);
+}
+
+#[test]
+fn functions_run_blocks_in_the_background() {
+    let path = write_program(&[
+        "Make later with n",
+        "    Set label to \"job \"",
+        "    Run in background as job:",
+        "        Use sleep with 0.2",
+        "        Set label to \"changed \"",
+        "        Return label plus n",
+        "    End",
+        "    Run in background:",
+        "        Throw \"unwatched \" plus n",
+        "    End",
+        "    Write wait for job",
+        "    Write label",
+        "    Run in background as asks:",
+        "        Ask for name",
+        "    End",
+        "    Wait for asks",
+        "End",
+        "Use later with 7",
+    ]);
+    let output = Command::cargo_bin("pohlang").unwrap().arg("--run").arg(&path).output().unwrap();
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "changed 7\njob \n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Run in background: ") && stderr.contains("unwatched 7"), "{}", stderr);
+    assert!(stderr.contains("Ask for name: a 'Run in background' block cannot ask for input"), "{}", stderr);
+}
+
+#[test]
+fn apply_in_parallel_keeps_the_order_and_overlaps_slow_calls() {
+    let run = |workers: usize| {
+        let call = format!("Write apply slow to each item in range(4) in parallel with {} workers", workers);
+        let path = write_program(&["Make slow with x", "    Use sleep with 0.25", "    Return x times 10", "End", &call]);
+        let started = std::time::Instant::now();
+        let output = Command::cargo_bin("pohlang").unwrap().arg("--run").arg(&path).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "[0, 10, 20, 30]\n");
+        started.elapsed()
+    };
+    let one = run(1);
+    let four = run(4);
+    assert!(one.as_secs_f64() >= 1.0, "one worker took {:?}", one);
+    assert!(four.as_secs_f64() < 0.75, "four workers took {:?}, one took {:?}", four, one);
+}
+
+#[test]
+fn apply_in_parallel_stops_at_the_first_error() {
+    let path = write_program(&[
+        "Make check with x",
+        "    Write \"checking \" plus x",
+        "    If x is 2",
+        "        Throw \"bad item \" plus x",
+        "    End",
+        "    Return x",
+        "End",
+        "try this:",
+        "    Write apply check to each item in range(5) in parallel with 1 worker",
+        "if error as e",
+        "    Write \"caught\"",
+        "end try",
+        "Write apply uppercase to each item in Make a list of \"a\", \"b\" in parallel",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().success().stdout("checking 0\nchecking 1\nchecking 2\ncaught\n[A, B]\n");
+}
+
+#[test]
+fn tasks_run_in_the_background_until_waited_for() {
+    let path = write_program(&[
+        "Set base to 10",
+        "Start task named slow:",
+        "    Set n to 0",
+        "    Repeat 100000 times",
+        "        Increase n by 1",
+        "    End",
+        "    Return n",
+        "End Task",
+        "Start task named quick:",
+        "    Return base plus 5",
+        "End Task",
+        "Write \"started\"",
+        "Wait for any task slow or quick",
+        "Write quick_result",
+        "Wait for all tasks slow and quick",
+        "Write slow_result",
+        "Start task named bad:",
+        "    Throw \"oops\"",
+        "End Task",
+        "try this:",
+        "    Wait for all tasks quick, bad",
+        "if error of type \"TaskError\" as errors",
+        "    Write length(errors)",
+        "end try",
+        "Wait for all tasks missing",
+    ]);
+    let output = Command::cargo_bin("pohlang")
+        .unwrap()
+        .arg("--run")
+        .arg(path.to_str().unwrap())
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), ["started", "15", "100000", "1"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("No task named 'missing' has been started"), "{}", stderr);
+}
+
+#[test]
+fn functions_start_and_wait_for_tasks() {
+    let path = write_program(&[
+        "Start task named outer:",
+        "    Return \"from the program\"",
+        "End Task",
+        "Make both with n",
+        "    Set offset to 100",
+        "    Start task named low:",
+        "        Return n plus 1",
+        "    End Task",
+        "    Start task named high:",
+        "        Return n plus offset",
+        "    End Task",
+        "    Wait for all tasks low and high",
+        "    Wait for any task outer",
+        "    Write outer_result",
+        "    Return low_result plus high_result",
+        "End",
+        "Write both(5)",
+        "Make failing with n",
+        "    Start task named bad:",
+        "        Throw \"oops \" plus n",
+        "    End Task",
+        "    Wait for all tasks bad",
+        "    Write \"not reached\"",
+        "End",
+        "try this:",
+        "    Use failing with 3",
+        "if error of type \"TaskError\" as errors",
+        "    Write first(errors)",
+        "end try",
+    ]);
+    let output = Command::cargo_bin("pohlang")
+        .unwrap()
+        .arg("--run")
+        .arg(path.to_str().unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines[..2], ["from the program", "111"]);
+    assert!(lines[2].starts_with("Task bad: ") && lines[2].ends_with("oops 3"), "{}", stdout);
+}
+
+#[test]
+fn benchmark_blocks_report_their_time_and_draw_a_flame_graph() {
+    let dir = tempdir().unwrap();
+    let path = write_program(&[
+        "Make square with n",
+        "    Return n times n",
+        "End",
+        "Benchmark \"hot loop\":",
+        "    Set total to 0",
+        "    Repeat 50 times",
+        "        Set total to total plus square(3)",
+        "    End",
+        "End Benchmark",
+        "Write total",
+    ]);
+    let output = Command::cargo_bin("pohlang")
+        .unwrap()
+        .current_dir(dir.path())
+        .arg("--run")
+        .arg("--bench-flamegraph")
+        .arg(path.to_str().unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "450\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Benchmark \"hot loop\": "), "{}", stderr);
+    assert!(stderr.contains("Flame graph written to flamegraph.svg"), "{}", stderr);
+
+    let svg = fs::read_to_string(dir.path().join("flamegraph.svg")).unwrap();
+    for frame in ["<title>all ", "<title>hot loop ", "<title>square "] {
+        assert!(svg.contains(frame), "missing {}: {}", frame, svg);
+    }
+
+    // Without the flag the block is only timed
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.current_dir(dir.path()).arg("--run").arg(path.to_str().unwrap());
+    fs::remove_file(dir.path().join("flamegraph.svg")).unwrap();
+    cmd.assert().success().stderr(predicates::str::starts_with("Benchmark \"hot loop\": "));
+    assert!(!dir.path().join("flamegraph.svg").exists());
+}
+
+#[test]
+fn measure_blocks_and_timed_calls_report_milliseconds() {
+    let path = write_program(&[
+        "Make count_to with n",
+        "    Set total to 0",
+        "    Repeat n times",
+        "        Increase total by 1",
+        "    End",
+        "    Return total",
+        "End",
+        "Measure time of \"outer\":",
+        "    Measure time of \"inner\" storing result in elapsed:",
+        "        Set x to count_to(100)",
+        "    End Measure",
+        "    Measure time of \"second\":",
+        "        Set y to count_to(10)",
+        "    End Measure",
+        "    Write elapsed is greater than -1",
+        "End Measure",
+        "Set timed to time calling count_to with 50",
+        "Write timed[\"result\"]",
+        "Write timed[\"milliseconds\"] is greater than -1",
+        "Make doubled with n",
+        "    Measure time of \"doubling\" storing result in ms:",
+        "        Set r to time calling count_to with n",
+        "    End Measure",
+        "    Return r[\"result\"] times 2",
+        "End",
+        "Write doubled(4)",
+    ]);
+    let output = Command::cargo_bin("pohlang")
+        .unwrap()
+        .arg("--run")
+        .arg(path.to_str().unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n50\n1\n8\n");
+    // Each printing block reports on its own line, the inner ones first
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let names: Vec<&str> = stderr
+        .lines()
+        .map(|line| {
+            let (name, ms) = line.split_once(" took ").unwrap();
+            assert!(ms.strip_suffix("ms").unwrap().parse::<f64>().is_ok(), "{}", line);
+            name
+        })
+        .collect();
+    assert_eq!(names, ["second", "outer"]);
+}
+
+#[test]
+fn pick_and_omit_shape_a_dictionary() {
+    let path = write_program(&[
+        "Set user to Make a dictionary with \"name\" as \"Ada\", \"age\" as 36 and \"password\" as \"x\"",
+        "Write pick \"name\", \"age\" and \"email\" from user nicely",
+        "Write omit \"password\", \"secret\" from user nicely",
+        "Make public with u",
+        "    Return pick \"name\" from u",
+        "End",
+        "Write public(user)",
+        "Write count of user",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    // Missing keys are left out, and the original keeps all its keys
+    cmd.assert().success().stdout(
+        "{\n  \"age\": 36,\n  \"name\": \"Ada\"\n}\n{\n  \"age\": 36,\n  \"name\": \"Ada\"\n}\n{\"name\": Ada}\n3\n",
+    );
+
+    let path = write_program(&["Write pick \"a\" from 5"]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("pick: expected a dictionary, got 5"));
+}
+
+#[test]
+fn comparison_chains_check_every_pair() {
+    let path = write_program(&[
+        "Set x to 5",
+        "If 1 < x < 10",
+        "    Write \"inside\"",
+        "End",
+        "If 1 < 20 < 10",
+        "    Write \"wrong\"",
+        "Otherwise",
+        "    Write \"outside\"",
+        "End",
+        "If 0 is less than x is less than or equal to 5 is less than 6",
+        "    Write \"all three\"",
+        "End",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().success().stdout("inside\noutside\nall three\n");
+}
+
+#[test]
+fn find_items_in_a_list_with_a_predicate() {
+    let path = write_program(&[
+        "Define function big with parameter n as n is greater than 10",
+        "Make seen with n",
+        "    Write \"checking \" plus n",
+        "    Return n is greater than 10",
+        "End",
+        "Set nums to Make a list of 3, 12, 5, 20",
+        "Write find first item in nums where seen",
+        "Write find all items in nums where big",
+        "Write find index of first item in nums where big plus 1",
+        "Write find first item in nums where Make a list of 1",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    // The first match stops the search
+    cmd.assert()
+        .failure()
+        .stdout("checking 3\nchecking 12\n12\n[12, 20]\n2\n")
+        .stderr(predicates::str::contains(
+            "find first item: expected a function after 'where', got [1]",
+        ));
+
+    let path = write_program(&[
+        "Define function big with parameter n as n is greater than 10",
+        "Make none_big with xs",
+        "    Return find index of first item in xs where big",
+        "End",
+        "Write none_big(Make a list of 1, 2)",
+        "Write find first item in range(1, 5) where big",
+        "Write find all items in Make a list of 1 where big",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().success().stdout("-1\nNone\n[]\n");
+}
+
+#[test]
+fn collect_fields_from_each_dictionary_in_a_list() {
+    let path = write_program(&[
+        "Set ada to Make a dictionary with \"name\" as \"Ada\", \"email\" as \"ada@example.com\"",
+        "Set alan to Make a dictionary with \"name\" as \"Alan\", \"age\" as 41",
+        "Set people to Make a list of ada, alan",
+        "Write join collect field \"name\" from each item in people with \", \"",
+        "Write collect field \"email\" from each item in people",
+        "Write collect fields \"name\" and \"age\" from each item in people",
+        "Set keys to Make a list of \"email\"",
+        "Write collect fields keys from each item in people",
+        "Write collect field \"name\" from each item in Make a list of ada, 7",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout(
+            "Ada, Alan\n[ada@example.com, None]\n\
+             [{\"name\": Ada}, {\"age\": 41, \"name\": Alan}]\n\
+             [{\"email\": ada@example.com}, {}]\n",
+        )
+        .stderr(predicates::str::contains(
+            "collect field expects dictionaries; item 2 was 7 (number)",
+        ));
+}
+
+#[test]
+fn aggregate_list_computes_every_field_in_one_pass() {
+    let path = write_program(&[
+        "Set a to Make a dictionary with \"price\" as 10, \"id\" as 1",
+        "Set b to Make a dictionary with \"price\" as 2.5, \"id\" as 2",
+        "Set c to Make a dictionary with \"id\" as 3",
+        "Set orders to Make a list of a, b, c",
+        "Set summary to aggregate list orders computing \"total\" as sum of \"price\", \"count\" as count of \"id\", \"average\" as average of \"price\"",
+        "Write summary",
+        "Write aggregate list orders computing \"low\" as min of \"price\" and \"high\" as max of \"price\"",
+        "Write aggregate list orders computing \"first\" as first of \"id\", \"last\" as last of \"price\"",
+        "Set d to Make a dictionary with \"price\" as \"free\"",
+        "Write aggregate list Make a list of a, d computing \"total\" as sum of \"price\"",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout(
+            "{\"average\": 6.25, \"count\": 3, \"total\": 12.5}\n\
+             {\"high\": 10, \"low\": 2.5}\n\
+             {\"first\": 1, \"last\": None}\n",
+        )
+        .stderr(predicates::str::contains(
+            "aggregate list: sum of \"price\" expects numbers; item 2 was free (string)",
+        ));
+}
+
+#[test]
+fn schemas_validate_and_coerce_dictionaries() {
+    let path = write_program(&[
+        "Type Name is string",
+        "Define schema User with name as required Name, age as optional number, role as string defaulting to \"user\", tags as list defaulting to Make a list of \"a\", \"b\"",
+        "Set good to Make a dictionary with \"name\" as \"Ada\", \"age\" as 36, \"extra\" as 1",
+        "Set bad to Make a dictionary with \"age\" as \"old\", \"role\" as 5",
+        "Write validate good as User",
+        "Write validate bad as User",
+        "Write coerce good as User",
+        "try this:",
+        "    Write coerce bad as User",
+        "if error of type \"ValidationError\" as e",
+        "    Write e",
+        "end try",
+        "Write validate 5 as User",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout(predicates::str::starts_with(
+            "{}\n\
+             {\"age\": must be a number, got old (string), \"name\": is required, \"role\": must be a string, got 5 (number)}\n\
+             {\"age\": 36, \"name\": Ada, \"role\": user, \"tags\": [a, b]}\n",
+        ))
+        .stdout(predicates::str::contains(
+            "a validation error - coerce as User: name is required; age must be a number, got old (string); role must be a string, got 5 (number)",
+        ))
+        .stderr(predicates::str::contains(
+            "validate as User: expected a dictionary, got 5 (number)",
+        ));
+
+    let path = write_program(&["Define schema Point with x as number, y as decimal"]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "Define schema Point: y is a decimal, which is not a known type",
+    ));
+}
+
+#[test]
+fn pattern_literals_and_functions() {
+    let path = write_program(&[
+        "Set digits to r\"\\d+\"",
+        "Write digits",
+        "Write match_pattern(\"order 66\", digits)",
+        "Write find_all(\"a1 b22 c333\", digits)",
+        "Write replace_pattern(\"2024-01-05\", r\"(\\d+)-(\\d+)-(\\d+)\", \"$3/$2/$1\")",
+        "Write split_by_pattern(\"a, b;c\", \"[,;] *\")",
+        "Write match_pattern(\"abc\", r\"(\")",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout("r\"\\d+\"\nTrue\n[1, 22, 333]\n05/01/2024\n[a, b, c]\n")
+        .stderr(predicates::str::contains("Invalid pattern r\"(\": regex parse error"));
+}
+
+#[test]
+fn emit_tokens_lists_the_source_without_running_it() {
+    let path = write_program(&["# greet", "Write \"hi\" plus 1", "Write \"open"]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--emit").arg("tokens").arg(path.to_str().unwrap());
+    cmd.assert().success().stdout(
+        "0..13 keyword \"Start Program\"\n\
+         13..14 whitespace \"\\n\"\n\
+         14..21 comment \"# greet\"\n\
+         21..22 whitespace \"\\n\"\n\
+         22..27 keyword \"Write\"\n\
+         27..28 whitespace \" \"\n\
+         28..32 string \"\\\"hi\\\"\"\n\
+         32..33 whitespace \" \"\n\
+         33..37 keyword \"plus\"\n\
+         37..38 whitespace \" \"\n\
+         38..39 number \"1\"\n\
+         39..40 whitespace \"\\n\"\n\
+         40..45 keyword \"Write\"\n\
+         45..46 whitespace \" \"\n\
+         46..51 error \"\\\"open\"\n\
+         51..52 whitespace \"\\n\"\n\
+         52..63 keyword \"End Program\"\n\
+         63..64 whitespace \"\\n\"\n",
+    );
+}
+
+#[test]
+fn tree_draws_the_imports_without_running_them() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join("main.poh"),
+        "Start Program\nWrite \"ran\"\nImport \"utils\"\nImport system \"collections\"\nEnd Program\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("utils.poh"), "Start Program\nImport \"main\"\nEnd Program\n").unwrap();
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.current_dir(dir.path()).arg("--tree").arg("main.poh");
+    cmd.assert().success().stdout(
+        "main.poh\n\
+         ├── utils.poh\n\
+         │   └── main.poh [circular]\n\
+         └── [system] collections\n",
+    );
+}
+
+#[test]
+fn checkpoints_are_restored_on_the_next_run() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("import.poh");
+    fs::write(
+        &path,
+        "Start Program\n\
+         Restore checkpoint import into variable row\n\
+         If row is nothing\n\
+         \x20   Set row to 0\n\
+         End\n\
+         Increase row by 1\n\
+         Checkpoint import with state row\n\
+         Checkpoint \"summary\" with state Make a dictionary with \"rows\" as row\n\
+         Write row\n\
+         End Program\n",
+    )
+    .unwrap();
+    let run = |extra: &[&str]| {
+        let output = Command::cargo_bin("pohlang")
+            .unwrap()
+            .arg("--run")
+            .args(extra)
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(run(&[]), "1\n");
+    assert_eq!(run(&[]), "2\n");
+    assert_eq!(run(&["--no-resume"]), "1\n");
+    let saved: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dir.path().join(".poh_checkpoint")).unwrap()).unwrap();
+    assert_eq!(saved, serde_json::json!({"import": 1, "summary": {"rows": 1}}));
+}
+
+#[test]
+#[cfg(feature = "web")]
+fn secure_web_servers_check_their_certificate_and_key() {
+    let dir = tempdir().unwrap();
+    let pem = |label: &str| format!("-----BEGIN {0}-----\naGVsbG8=\n-----END {0}-----\n", label);
+    fs::write(dir.path().join("cert.pem"), pem("CERTIFICATE")).unwrap();
+    fs::write(dir.path().join("key.pem"), pem("PRIVATE KEY")).unwrap();
+    let run = |line: &str| {
+        let path = dir.path().join("server.poh");
+        fs::write(&path, format!("Start Program\n{}\nWrite \"created\"\nStart server\nEnd Program\n", line)).unwrap();
+        let output = Command::cargo_bin("pohlang").unwrap().arg("--run").arg(&path).output().unwrap();
+        assert!(!output.status.success());
+        (String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap())
+    };
+
+    let (_, stderr) = run("Set server to create secure web server on port 48199 with certificate \"key.pem\" and key \"cert.pem\"");
+    assert!(stderr.contains("the wrong way round"), "{}", stderr);
+    let (_, stderr) = run("Set server to create secure web server on port 48199 with certificate \"none.pem\" and key \"key.pem\"");
+    assert!(stderr.contains("Cannot read certificate file"), "{}", stderr);
+    let (stdout, stderr) = run("Set server to create secure web server on port 48199 with certificate \"cert.pem\" and key \"key.pem\"");
+    assert!(!stdout.contains("created"), "{}", stdout);
+    if cfg!(feature = "tls") {
+        assert!(stderr.contains("cannot be served with"), "{}", stderr);
+    } else {
+        assert!(stderr.contains("build PohLang with the 'tls' feature"), "{}", stderr);
+    }
+}
+
+#[test]
+#[cfg(feature = "tls")]
+fn secure_web_servers_refuse_a_key_from_another_certificate() {
+    let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls");
+    let path = write_program(&[&format!(
+        "Set server to create secure web server on port 48199 with certificate \"{}\" and key \"{}\"",
+        fixtures.join("cert.pem").display(),
+        fixtures.join("other-key.pem").display()
+    )]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("create secure web server: the certificate and key cannot be served with"));
+}
+
+#[test]
+fn retry_runs_the_block_again_until_it_succeeds() {
+    use predicates::prelude::PredicateBooleanExt;
+    let path = write_program(&[
+        "Set tries to 0",
+        "Retry 3 times with delay 0.01 seconds with backoff with jitter:",
+        "    Increase tries by 1",
+        "    If tries is less than 3",
+        "        Throw \"flaky\"",
+        "    End",
+        "End Retry",
+        "Write \"done after \" plus tries",
+        "Retry 2 times with delay 0 seconds:",
+        "    Throw \"always\"",
+        "On all failures as e:",
+        "    Write \"gave up: \" plus e",
+        "End Retry",
+        "Retry 2 times with delay 0 seconds:",
+        "    Write \"trying\"",
+        "    Throw \"boom\"",
+        "End Retry",
+        "Write \"not reached\"",
+    ]);
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout(predicates::str::contains("done after 3\ngave up: "))
+        .stdout(predicates::str::contains("always"))
+        .stdout(predicates::str::contains("trying\ntrying\n"))
+        .stdout(predicates::str::contains("not reached").not())
+        .stderr(predicates::str::contains("boom"));
+}
+
+#[test]
+fn retry_blocks_run_inside_functions() {
+    use predicates::prelude::PredicateBooleanExt;
+    let path = write_program(&[
+        "Make fetch with needed",
+        "    Set tries to 0",
+        "    Retry 3 times with delay 0 seconds:",
+        "        Increase tries by 1",
+        "        If tries is less than needed",
+        "            Throw \"flaky\"",
+        "        End",
+        "        Return \"done after \" plus tries",
+        "    On all failures as e:",
+        "        Return \"gave up after \" plus tries plus \": \" plus e",
+        "    End Retry",
+        "    Write \"not reached\"",
+        "End",
+        "Write fetch(2)",
+        "Write fetch(5)",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::starts_with("done after 2\ngave up after 3: "))
+        .stdout(predicates::str::contains("flaky\n"))
+        .stdout(predicates::str::contains("not reached").not());
+}
+
+#[test]
+fn one_line_if_then_else_and_conditional_expressions() {
+    let path = write_program(&[
+        "Set score to 72",
+        "If score is greater than 50 then Set grade to \"pass\" else Set grade to \"fail\"",
+        "Write grade",
+        "If score is less than 50 then Write \"low\"",
+        "Write \"big\" if score is greater than 100 else \"small\"",
+        "Make describe with n",
+        "    If n is greater than 0 then Return \"positive\" else Return \"not positive\"",
+        "End",
+        "Write describe(3) plus \" \" plus describe(-1)",
+        "Define function sign with parameter n as \"+\" if n is greater than 0 else \"-\" if n is less than 0 else \"0\"",
+        "Write sign(5) plus sign(-5) plus sign(0)",
+        "If score is greater than 50 then Set x to 1 if score is greater than 70 else 2 else Set x to 3",
+        "Write x",
+        "If \"a then b\" is \"a then b\" then Write \"quoted\"",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .success()
+        .stdout("pass\nsmall\npositive not positive\n+-0\n1\nquoted\n");
+}
+
+#[test]
+#[cfg(feature = "web")]
+fn template_files_render_from_the_template_directory() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("templates")).unwrap();
+    fs::write(
+        dir.path().join("templates").join("users.html"),
+        "<ul>{{#each users}}<li>{{name}}</li>{{/each}}</ul>",
+    )
+    .unwrap();
+    fs::write(dir.path().join("templates").join("broken.html"), "<h1>\n{{#if admin}}\n</h1>").unwrap();
+    let main_path = dir.path().join("main.poh");
+    fs::write(
+        &main_path,
+        "Start Program\n\
+         Set template directory to \"templates\"\n\
+         Set data to Make a dictionary with \"users\" as Make a list of (Make a dictionary with \"name\" as \"Ada\")\n\
+         Make page with d\n    Return render template file \"users.html\" with d\nEnd\n\
+         Write page(data)\n\
+         Write render template file \"users.html\" with data\n\
+         Write render template file \"broken.html\" with data\n\
+         End Program\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(main_path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout("<ul><li>Ada</li></ul>\n<ul><li>Ada</li></ul>\n")
+        .stderr(predicates::str::contains("broken.html line 2: {{#if admin}} has no {{/if}}"));
+}
+
+#[test]
+fn event_emitters_call_their_handlers_in_order() {
+    let path = write_program(&[
+        "Create event emitter bus",
+        "On event \"click\" from emitter bus:",
+        "    Write \"clicked \" plus data",
+        "End On",
+        "On event \"click\" from emitter bus:",
+        "    Emit \"double\" from bus with data data times 2",
+        "End On",
+        "On event \"double\" from emitter bus:",
+        "    Write \"doubled \" plus data",
+        "End On",
+        "Make press with n",
+        "    Emit \"click\" from bus with data n",
+        "End",
+        "Emit \"click\" from bus with data 5",
+        "Use press with 7",
+        "Emit \"unheard\" from bus",
+        "Write \"done\"",
+        "Set other to 3",
+        "Emit \"click\" from other with data 1",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout("clicked 5\ndoubled 10\nclicked 7\ndoubled 14\ndone\n")
+        .stderr(predicates::str::contains("Expected an event emitter"));
+}
+
+#[test]
+fn functions_make_event_emitters_and_handlers() {
+    let path = write_program(&[
+        "Create event emitter bus",
+        "Make listen with prefix",
+        "    Set suffix to \"!\"",
+        "    On event \"click\" from emitter bus:",
+        "        Write prefix plus data plus suffix",
+        "    End On",
+        "End",
+        "Make counter with start",
+        "    Create event emitter ticks",
+        "    On event \"tick\" from emitter ticks:",
+        "        Write start plus data",
+        "    End On",
+        "    Emit \"tick\" from ticks with data 1",
+        "    Return ticks",
+        "End",
+        "Use listen with \"heard \"",
+        "Emit \"click\" from bus with data 5",
+        "Set ticks to counter(10)",
+        "Emit \"tick\" from ticks with data 2",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().success().stdout("heard 5!\n11\n12\n");
+}
+
+#[test]
+fn dictionaries_validate_against_schema_dictionaries() {
+    let path = write_program(&[
+        "Set address to Make a dictionary with \"city\" as \"text\"",
+        "Set schema to Make a dictionary with \"name\" as \"text\" and \"age\" as \"number\" and \"email\" as \"optional text\" and \"scores\" as \"list of number\" and \"address\" as address",
+        "Set good to Make a dictionary with \"name\" as \"Ada\" and \"age\" as 36 and \"scores\" as Make a list of 1, 2 and \"address\" as Make a dictionary with \"city\" as \"London\"",
+        "Set bad to Make a dictionary with \"age\" as \"old\" and \"scores\" as Make a list of 1, \"two\"",
+        "Write validate good against schema",
+        "Write validate bad against schema",
+        "require good to match schema",
+        "try this:",
+        "    require bad to match schema",
+        "if error of type \"ValidationError\" as e",
+        "    Write e",
+        "end try",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::starts_with(
+            "None\n\
+             [address is required, age must be a number, got text, name is required, scores item 2 must be a number, got text]\n",
+        ))
+        .stdout(predicates::str::contains(
+            "does not match the schema: address is required; age must be a number, got text; name is required; scores item 2 must be a number, got text",
+        ));
+}
+
+#[test]
+fn deep_equals_assertions_name_each_difference() {
+    use predicates::prelude::PredicateBooleanExt;
+    let path = write_program(&[
+        "Set got to Make a dictionary with \"user\" as (Make a dictionary with \"name\" as \"Bob\") and \"tags\" as Make a list of 1, 2",
+        "Set want to Make a dictionary with \"user\" as (Make a dictionary with \"name\" as \"Alice\") and \"tags\" as Make a list of 1, 3",
+        "Assert got deep equals got",
+        "Assert range(1, 3) equals Make a list of 1, 2",
+        "try this:",
+        "    Assert got deep equals want",
+        "if error of type \"AssertionError\" as e",
+        "    Write e",
+        "end try",
+        "Assert 1 equals 2",
+        "Write \"not reached\"",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout(predicates::str::contains(
+            "Expected 'tags[1]' to be 3 but got 2; Expected 'user.name' to be 'Alice' but got 'Bob'",
+        ))
+        .stdout(predicates::str::contains("not reached").not())
+        .stderr(predicates::str::contains("Expected 2 but got 1"));
+}
+
+#[test]
+fn bound_variables_run_their_block_when_set() {
+    let path = write_program(&[
+        "Bind total to 1 plus 1 and on change run:",
+        "    Write \"total: \" plus __old_value plus \" -> \" plus __new_value",
+        "    Set total to total times 10",
+        "End Bind",
+        "Write total",
+        "Set total to 5",
+        "Increase total",
+        "Write total",
+        "Bind tags to Make a list of \"a\" and on change run:",
+        "    Write join __new_value with \",\"",
+        "End Bind",
+        "Set tags[0] to \"b\"",
+        "Set copy to tags",
+        "Set copy to 0",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .success()
+        .stdout("2\ntotal: 2 -> 5\ntotal: 50 -> 51\n510\nb\n");
+}
+
+#[test]
+fn on_shutdown_inside_a_function_is_an_error() {
+    let path = write_program(&[
+        "Make stop with reason",
+        "    On shutdown:",
+        "        Write reason",
+        "    End On Shutdown",
+        "    Write \"not reached\"",
+        "End",
+        "Use stop with \"done\"",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().failure().stdout("").stderr(predicates::str::contains(
+        "On shutdown: blocks are added at the top level of a program, not in a function",
+    ));
+}
+
+#[test]
+fn rate_limit_inside_a_function_is_an_error() {
+    let path = write_program(&[
+        "Make setup with n",
+        "    Limit to 10 requests per 60 seconds",
+        "    Write \"not reached\"",
+        "End",
+        "Use setup with 1",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().failure().stdout("").stderr(predicates::str::contains(
+        "Limit to 10 requests per 60 seconds: the server is set up at the top level of a program, not in a function",
+    ));
+}
+
+#[test]
+fn route_hooks_inside_a_function_are_an_error() {
+    let path = write_program(&[
+        "Make setup with n",
+        "    After each route:",
+        "        Write n",
+        "    End After",
+        "    Write \"not reached\"",
+        "End",
+        "Use setup with 1",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().failure().stdout("").stderr(predicates::str::contains(
+        "After each route: blocks are added at the top level of a program, not in a function",
+    ));
+}
+
+#[test]
+fn route_groups_inside_a_function_are_an_error() {
+    let path = write_program(&[
+        "Make setup with n",
+        "    Add route group \"/api\" to server:",
+        "    End group",
+        "    Write \"not reached\"",
+        "End",
+        "Use setup with 1",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().failure().stdout("").stderr(predicates::str::contains(
+        "Add route group \"/api\" to server: blocks are added at the top level of a program, not in a function",
+    ));
+}
+
+#[test]
+fn metrics_inside_a_function_are_an_error() {
+    let path = write_program(&[
+        "Make setup with n",
+        "    Enable metrics on server at \"/metrics\"",
+        "    Write \"not reached\"",
+        "End",
+        "Use setup with 1",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().failure().stdout("").stderr(predicates::str::contains(
+        "Enable metrics on server at \"/metrics\": the server is set up at the top level of a program, not in a function",
+    ));
+}
+
+#[test]
+fn static_files_inside_a_function_are_an_error() {
+    let path = write_program(&[
+        "Make setup with n",
+        "    Serve static files from \"public\"",
+        "    Write \"not reached\"",
+        "End",
+        "Use setup with 1",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().failure().stdout("").stderr(predicates::str::contains(
+        "Serve static files from \"public\": the server is set up at the top level of a program, not in a function",
+    ));
+}
+
+#[test]
+fn bind_inside_a_function_is_an_error() {
+    let path = write_program(&[
+        "Make watch with start",
+        "    Bind total to start and on change run:",
+        "        Write \"changed\"",
+        "    End Bind",
+        "    Write \"not reached\"",
+        "End",
+        "Use watch with 1",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().failure().stdout("").stderr(predicates::str::contains(
+        "Bind total: program variables are bound at the top level of a program, not in a function",
+    ));
+}
+
+#[test]
+fn memory_limit_stops_growing_values_past_try() {
+    let bombs: [&[&str]; 2] = [
+        &["    Set xs to Make a list of \"aaaaaaaaaa\"", "    While true", "        Set xs to Append xs[0] to xs", "    End"],
+        &["    Make grow with s", "        While true", "            Set s to s plus s", "        End", "    End", "    Write grow(\"a\")"],
+    ];
+    for bomb in bombs {
+        let lines: Vec<&str> = ["try this:"]
+            .iter()
+            .chain(bomb)
+            .chain(&["if error as e", "    Write \"caught\"", "end try", "Write \"after\""])
+            .copied()
+            .collect();
+        let path = write_program(&lines);
+        let mut cmd = Command::cargo_bin("pohlang").unwrap();
+        cmd.args(["--run", "--memory-limit", "100000"]).arg(path.to_str().unwrap());
+        cmd.assert()
+            .failure()
+            .stdout("")
+            .stderr(predicates::str::contains("is over the memory limit of 100000 bytes"));
+    }
+}
+
+#[test]
+fn memory_limit_is_not_caught_from_tasks_and_branches() {
+    let bomb = ["        Set xs to Make a list of \"aaaaaaaaaa\"", "        While true", "            Set xs to Append xs[0] to xs", "        End"];
+    let wrappers: [(&str, &str, &[&str]); 4] = [
+        ("    Run in background as job:", "    End", &["    Write wait for job"]),
+        ("    Run in background as job:", "    End", &["    Wait for job"]),
+        ("    Start task named job:", "    End Task", &["    Wait for any task job"]),
+        ("    Parallel:\n    Branch:", "    End Parallel", &[]),
+    ];
+    for (start, end, wait) in wrappers {
+        let mut lines = vec!["try this:", start];
+        lines.extend(bomb);
+        lines.push(end);
+        lines.extend(wait);
+        lines.extend(["if error as e", "    Write \"caught\"", "end try", "Write \"after\""]);
+        let path = write_program(&lines);
+        let mut cmd = Command::cargo_bin("pohlang").unwrap();
+        cmd.args(["--run", "--memory-limit", "100000"]).arg(path.to_str().unwrap());
+        cmd.assert()
+            .failure()
+            .stdout("")
+            .stderr(predicates::str::contains("is over the memory limit of 100000 bytes"));
+    }
+}
+
+#[test]
+fn memory_limit_counts_what_collections_and_channels_hold() {
+    let growing = [
+        ("collections::new_stack()", "Set s to collections::push(s, \"aaaaaaaaaa\")"),
+        ("collections::new_queue()", "Set s to collections::enqueue(s, \"aaaaaaaaaa\")"),
+        ("collections::new_priority_queue()", "Set s to collections::push(s, \"aaaaaaaaaa\", 1)"),
+        ("collections::ordered_dict()", "Set s to collections::set(s, collections::size(s), \"aaaaaaaaaa\")"),
+        ("make a channel", "Send \"aaaaaaaaaa\" to s"),
+    ];
+    for (make, grow) in growing {
+        let path = write_program(&[
+            "Import system \"collections\"",
+            &format!("Set s to {}", make),
+            "try this:",
+            "    While true",
+            &format!("        {}", grow),
+            "    End",
+            "if error as e",
+            "    Write \"caught\"",
+            "end try",
+            "Write \"after\"",
+        ]);
+        let mut cmd = Command::cargo_bin("pohlang").unwrap();
+        cmd.args(["--run", "--memory-limit", "100000"]).arg(path.to_str().unwrap());
+        cmd.assert()
+            .failure()
+            .stdout("")
+            .stderr(predicates::str::contains("is over the memory limit of 100000 bytes"));
+    }
+}
+
+#[test]
+fn programs_filter_piped_stdin() {
+    let path = write_program(&[
+        "For each line in stdin as line:",
+        "    Write \"<\" plus line plus \">\"",
+        "End For",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    assert_cmd::Command::from_std(cmd)
+        .write_stdin("one\r\n\n  two\nlast")
+        .assert()
+        .success()
+        .stdout("<one>\n<>\n<  two>\n<last>\n");
+}
+
+#[test]
+#[cfg(feature = "web")]
+fn responses_written_outside_a_route_are_errors() {
+    use predicates::prelude::PredicateBooleanExt;
+    let path = write_program(&[
+        "try this:",
+        "    Write html response with \"<p>hi</p>\"",
+        "if error as e",
+        "    Write \"caught\"",
+        "end try",
+        "Make gone with message",
+        "    Write error response with status 404 and message message",
+        "    Write \"not reached\"",
+        "End",
+        "try this:",
+        "    Use gone with \"missing\"",
+        "if error as e",
+        "    Write e",
+        "end try",
+        "Write \"after\"",
+        "Write json response with 1 and status 201",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout(predicates::str::starts_with("caught\n"))
+        .stdout(predicates::str::contains(
+            "response expressions only have effect inside 'Add route' handlers; this response (status 404) was not sent",
+        ))
+        .stdout(predicates::str::ends_with("after\n"))
+        .stdout(predicates::str::contains("not reached").not())
+        .stderr(predicates::str::contains("this response (status 201) was not sent"));
+}
+
+#[test]
+fn sets_hold_unique_members() {
+    let path = write_program(&[
+        "Set seen to set containing 3, 1, \"b\" and 1",
+        "Set more to set of Make a list of 2, 2.0, 3, True",
+        "Write seen",
+        "Write count of more",
+        "Write union of seen and more",
+        "Write intersection of seen and more",
+        "Write difference of seen and more",
+        "Write set seen contains 1.0",
+        "Write list of seen",
+        "Write empty set",
+        "Write set containing Make a list of 1",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout("{1, 3, b}\n3\n{True, 1, 2, 3, b}\n{3}\n{1, b}\nTrue\n[1, 3, b]\n{}\n")
+        .stderr(predicates::str::contains("[1] (list) cannot be a member of a set"));
+}
+
+#[test]
+fn constants_are_set_before_the_program_and_never_change() {
+    let path = write_program(&[
+        "Make show with n",
+        "    Write limit plus n",
+        "End",
+        "Use show with 1",
+        "Set constant limit to 100",
+        "Set constant tags to Make a list of \"a\", -2 and 3.5",
+        "Set retries to 3",
+        "Set count to 0",
+        "Increase count by 1",
+        "Write tags",
+        "try this:",
+        "    Set limit to 5",
+        "if error as e",
+        "    Write e",
+        "end try",
+        "Write limit",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg("--report-const-candidates").arg(path.to_str().unwrap());
+    cmd.assert()
+        .success()
+        .stdout("101\n[a, -2, 3.5]\nCannot change limit: it was set with Set constant\n100\n")
+        .stderr(
+            "Hint: retries is set once and never changed; 'Set constant retries to 3' saves looking it up at each use\n",
+        );
+
+    let path = write_program(&["If True", "    Set constant limit to 1", "End"]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("constants are set at the top level of a program"));
+
+    let path = write_program(&["Set constant total to 1 plus 2"]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("the value must be a number, text"));
+}
+
+#[test]
+fn use_storing_result_in_keeps_the_returned_value() {
+    let path = write_program(&[
+        "Make greet with who",
+        "    Return \"Hello \" plus who",
+        "End",
+        "Make shout with who",
+        "    Use greet with who storing result in line",
+        "    Return line plus \"!\"",
+        "End",
+        "Use greet with \"Ada\" storing result in greeting",
+        "Write greeting",
+        "Call shout with \"Bo storing result in nothing\" storing result in loud",
+        "Write loud",
+        "Use greet with \"Cy\"",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .success()
+        .stdout("Hello Ada\nHello Bo storing result in nothing!\n");
+}
+
+#[test]
+fn write_puts_several_values_on_one_line() {
+    let path = write_program(&[
+        "Set name to \"Ada\"",
+        "Write \"name:\", name, 3 plus 4",
+        "Write Make a list of 1, 2 and 3",
+        "Write 1, \"a, b\", 2 separated by \" | \"",
+        "Write \"no \", \"newline\" separated by \"\" ending with \"\"",
+        "Write \"!\" ending with \"?\"",
+        "Make show with a, b",
+        "    Write a, b separated by \"-\"",
+        "End",
+        "Use show with \"x\", 2",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .success()
+        .stdout("name: Ada 7\n[1, 2, 3]\n1 | a, b | 2\nno newline!?x-2\n");
+}
+
+#[test]
+#[cfg(not(feature = "web"))]
+fn web_statements_fail_without_the_web_feature() {
+    let path = write_program(&[
+        "Write \"before\"",
+        "Set server to create web server on port 8080",
+        "Write \"not reached\"",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .failure()
+        .stdout("before\n")
+        .stderr(predicates::str::contains("web support not compiled in"));
+}
+
+#[test]
+fn deeply_nested_expressions_are_an_error_not_a_crash() {
+    let deep = [
+        format!("Write {}1{}", "(".repeat(10_000), ")".repeat(10_000)),
+        format!("Write {}\"a\"", "count of ".repeat(10_000)),
+        format!("Write {}True", "not ".repeat(10_000)),
+        format!("Write {}0{}", "1 plus (".repeat(10_000), ")".repeat(10_000)),
+    ];
+    for line in &deep {
+        let path = write_program(&[line]);
+        let mut cmd = Command::cargo_bin("pohlang").unwrap();
+        cmd.arg("--run").arg(path.to_str().unwrap());
+        cmd.assert()
+            .failure()
+            .stderr(predicates::str::contains("expression too deeply nested (limit 256)"));
+    }
+}
+
+#[test]
+fn long_operator_chains_run_without_recursing() {
+    let sum = format!("1{}", " plus 1".repeat(10_000));
+    let mixed = format!("2{}", " times 1 minus 1 plus 1 divided by 1".repeat(3_000));
+    let path = write_program(&[
+        &format!("Write {}", sum),
+        "Make f with x",
+        &format!("    Return x{}", " plus 1".repeat(10_000)),
+        "End",
+        "Write f(5)",
+        &format!("Write {}", mixed),
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert().success().stdout("10001\n10005\n2\n");
+}
+
+#[test]
+fn channels_carry_values_from_background_blocks_until_closed() {
+    let path = write_program(&[
+        "Set results to make a channel",
+        "Set counts to Make a list of 1",
+        "Run in background as producer:",
+        "    Send \"first\" to results",
+        "    Send counts to results",
+        "    Use sleep with 0.1",
+        "    Send 3 to results",
+        "    Close results",
+        "End",
+        "Write receive from results",
+        "Write receive from results",
+        "Write receive from results",
+        "Write receive from results",
+        "Wait for producer",
+        "Set quiet to make a channel",
+        "Write receive from quiet within 0.1 seconds",
+        "Send 5 to quiet",
+        "Write receive from quiet within 0.1 seconds",
+        "Close quiet",
+        "Write receive from quiet",
+        "try this:",
+        "    Send 6 to quiet",
+        "if error as e",
+        "    Write e",
+        "end try",
+    ]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd.assert()
+        .success()
+        .stdout("first\n[1]\n3\nNone\nNone\n5\nNone\nCannot send to a channel that has been closed\n");
+}
@@ -0,0 +1,98 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn run(lines: &[&str], style: Option<&str>) -> Command {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let path = std::env::temp_dir().join(format!("pohlang_table_test_{}.poh", ts));
+    let mut contents = String::from("Start Program\n");
+    for l in lines {
+        contents.push_str(l);
+        contents.push('\n');
+    }
+    contents.push_str("End Program\n");
+    fs::write(&path, contents).unwrap();
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run");
+    if let Some(style) = style {
+        cmd.arg("--table-style").arg(style);
+    }
+    cmd.arg(path.to_str().unwrap());
+    cmd
+}
+
+const SCHEDULE: &[&str] = &[
+    "Set headers to Make a list of \"Day\", \"Room\", \"Topic\"",
+    "Set mon to Make a list of \"Mon\", 101, \"Intro\\nand setup\"",
+    "Set tue to Make a list of \"Tuesday\", 7",
+    "Set rows to Make a list of mon, tue",
+    "Print table with headers headers and rows rows",
+];
+
+#[test]
+fn box_table_is_the_default() {
+    run(SCHEDULE, None).assert().success().stdout(
+        "┌─────────┬──────┬───────────┐\n\
+         │ Day     │ Room │ Topic     │\n\
+         ├─────────┼──────┼───────────┤\n\
+         │ Mon     │ 101  │ Intro     │\n\
+         │         │      │ and setup │\n\
+         │ Tuesday │ 7    │           │\n\
+         └─────────┴──────┴───────────┘\n",
+    );
+}
+
+#[test]
+fn plain_and_csv_table_styles() {
+    let plain = [
+        "Day      Room  Topic",
+        "-------  ----  ---------",
+        "Mon      101   Intro",
+        "               and setup",
+        "Tuesday  7",
+    ];
+    run(SCHEDULE, Some("plain"))
+        .assert()
+        .success()
+        .stdout(format!("{}\n", plain.join("\n")));
+    run(SCHEDULE, Some("csv"))
+        .assert()
+        .success()
+        .stdout("Day,Room,Topic\nMon,101,\"Intro\nand setup\"\nTuesday,7,\n");
+}
+
+#[test]
+fn table_inside_a_function() {
+    run(
+        &[
+            "Make show with label",
+            "    Set row to Make a list of label",
+            "    Print table with headers Make a list of \"Label\" and rows Make a list of row",
+            "End",
+            "Use show with \"hi\"",
+        ],
+        Some("csv"),
+    )
+    .assert()
+    .success()
+    .stdout("Label\nhi\n");
+}
+
+#[test]
+fn table_rejects_rows_wider_than_headers() {
+    run(
+        &[
+            "Set row to Make a list of 1, 2",
+            "Print table with headers Make a list of \"Only\" and rows Make a list of row",
+        ],
+        None,
+    )
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("row 1 has 2 cells but there are only 1 headers"));
+}
@@ -0,0 +1,66 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PROGRAM: &str = "Start Program
+Make square with x
+    Set y to x times x
+    Return y
+End
+Set total to 0
+Repeat 2 times
+    Set total to total plus square(3)
+End
+If total is greater than 10
+    Write \"big\"
+End
+End Program
+";
+
+fn run(flags: &[&str]) -> Command {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let path = std::env::temp_dir().join(format!("pohlang_trace_test_{}.poh", ts));
+    fs::write(&path, PROGRAM).unwrap();
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").args(flags).arg(path.to_str().unwrap());
+    cmd
+}
+
+#[test]
+fn trace_shows_each_statement_with_its_line_and_depth() {
+    run(&["--trace"]).assert().success().stdout("big\n").stderr(
+        [
+            "[trace]    2  Make square with x",
+            "[trace]    6  Set total to 0 → 0",
+            "[trace]    7  Repeat 2 times",
+            "[trace]    8      call square with 3",
+            "[trace]    3        Set y to x times x → 9",
+            "[trace]    4        Return y",
+            "[trace]    4      square returned 9",
+            "[trace]    8    Set total to total plus square(3) → 9",
+            "[trace]    8      call square with 3",
+            "[trace]    3        Set y to x times x → 9",
+            "[trace]    4        Return y",
+            "[trace]    4      square returned 9",
+            "[trace]    8    Set total to total plus square(3) → 18",
+            "[trace]   10  If total > 10",
+            "[trace]   11    Write \"big\"",
+            "",
+        ]
+        .join("\n"),
+    );
+}
+
+#[test]
+fn trace_filter_limits_output_to_one_function() {
+    let output = run(&["--trace-filter", "square"]).output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success());
+    assert_eq!(stderr.lines().count(), 8, "unexpected trace: {}", stderr);
+    assert!(stderr.lines().all(|l| !l.contains("Set total")), "unexpected trace: {}", stderr);
+    assert!(stderr.contains("call square with 3"), "unexpected trace: {}", stderr);
+}
@@ -0,0 +1,223 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn write_program(name: &str, lines: &[&str]) -> PathBuf {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let path = std::env::temp_dir().join(format!("pohlang_js_{}_{}.poh", name, ts));
+    let mut contents = String::from("Start Program\n");
+    for l in lines {
+        contents.push_str(l);
+        contents.push('\n');
+    }
+    contents.push_str("End Program\n");
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn stdout_of(mut cmd: Command, input: &str) -> String {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?} failed", cmd);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Run the program natively and as JavaScript under node, and check both
+/// print the same. Skipped where node is not installed.
+fn same_under_node(name: &str, lines: &[&str], input: &str) {
+    if Command::new("node").arg("--version").output().is_err() {
+        eprintln!("node not found; skipping {}", name);
+        return;
+    }
+    let path = write_program(name, lines);
+    let js = path.with_extension("js");
+    Command::cargo_bin("pohlang")
+        .unwrap()
+        .args(["--transpile", "js", "-o"])
+        .arg(&js)
+        .arg(&path)
+        .assert()
+        .success();
+
+    let mut native = Command::cargo_bin("pohlang").unwrap();
+    native.arg("--run").arg(&path);
+    let mut node = Command::new("node");
+    node.arg(&js);
+    let expected = stdout_of(native, input);
+    assert!(!expected.is_empty());
+    assert_eq!(stdout_of(node, input), expected);
+}
+
+#[test]
+fn values_print_the_same_in_javascript() {
+    same_under_node(
+        "values",
+        &[
+            "Set constant limit to 3",
+            "Set xs to Make a list of 3, 1.5 and -2",
+            "Set d to Make a dictionary with \"b\" as 2, \"a\" as xs",
+            "Set d[\"c\"] to True",
+            "Set copy to xs",
+            "Set copy[0] to 9",
+            "Write xs",
+            "Write copy",
+            "Write d",
+            "Write tuple of 1 and \"a\"",
+            "Write 10 divided by 4",
+            "Write 10 divided by 5",
+            "Write 0.1 plus 0.2",
+            "Write 1 plus True",
+            "Write \"n = \" plus None",
+            "Write 3 is greater than 2",
+            "Write True and False",
+            "Write total of xs",
+            "Write average of xs",
+            "Write round 2.5",
+            "Write round -2.5",
+            "Write reverse of \"abc\"",
+            "Write join xs with \"-\"",
+            "Write split \"a,b\" by \",\"",
+            "Write count of d",
+            "Write length(\"héllo\")",
+            "Write range(limit)",
+            "Write xs[-1]",
+            "Write make uppercase \"shout\"",
+            "Write contains 1.5 in xs",
+            "Write append 4 to xs",
+            "Write xs is equal to copy",
+            "Write \"xs:\", xs, count of d separated by \" | \"",
+            "Write \"open\" ending with \"\"",
+            "Write \" line\"",
+        ],
+        "",
+    );
+}
+
+#[test]
+fn functions_and_loops_run_the_same_in_javascript() {
+    same_under_node(
+        "functions",
+        &[
+            "Make fact with n",
+            "    If n is less than 2",
+            "        Return 1",
+            "    End",
+            "    Return n times fact(n minus 1)",
+            "End",
+            "Make greet with who, greeting set to \"Hello\"",
+            "    Set line to greeting plus \" \" plus who",
+            "    Return line",
+            "End",
+            "Make square with x Write x times x",
+            "Write fact(10)",
+            "Write greet(\"Ada\")",
+            "Write greet(\"Bo\", \"Hi\")",
+            "Use greet with \"Cy\" storing result in kept",
+            "Write kept",
+            "Write square(7)",
+            "Set total to 0",
+            "Repeat 3 times",
+            "    Repeat 2 times",
+            "        Increase total by 1",
+            "    End",
+            "End",
+            "Write total",
+            "Set a, b to Make a list of 5 and 6",
+            "While a is less than 8",
+            "    Increase a by 1",
+            "End",
+            "Write a plus b",
+            "If a is 7",
+            "    Write \"seven\"",
+            "Otherwise",
+            "    If a is 8",
+            "        Write \"eight\"",
+            "    Otherwise",
+            "        Write \"other\"",
+            "    End",
+            "End",
+        ],
+        "",
+    );
+}
+
+#[test]
+fn errors_are_thrown_and_caught_the_same_in_javascript() {
+    same_under_node(
+        "errors",
+        &[
+            "try this:",
+            "    Throw \"bad\"",
+            "    Write \"not reached\"",
+            "if error as e",
+            "    Write \"caught\"",
+            "finally:",
+            "    Write \"finally\"",
+            "end try",
+            "try this:",
+            "    Throw error of type \"ValidationError\" with message \"nope\"",
+            "if error of type \"NotFound\"",
+            "    Write \"wrong handler\"",
+            "if error of type \"ValidationError\"",
+            "    Write \"validation\"",
+            "end try",
+            "try this:",
+            "    Write 1 divided by 0",
+            "if error",
+            "    Write \"division\"",
+            "end try",
+        ],
+        "",
+    );
+}
+
+#[test]
+fn ask_for_reads_the_same_input_in_javascript() {
+    same_under_node(
+        "ask",
+        &[
+            "Ask for name as text",
+            "Ask for age as number",
+            "Ask for ok as yes or no",
+            "Write \"Hi \" plus name",
+            "Write age plus 1",
+            "Write ok",
+        ],
+        "Ada\nold\n41\ny\n",
+    );
+}
+
+#[test]
+fn web_server_statements_are_not_transpiled() {
+    let path = write_program(
+        "web",
+        &[
+            "Write \"starting\"",
+            "Set server to create web server on port 8080",
+            "Start server",
+        ],
+    );
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.args(["--transpile", "js"]).arg(&path);
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "line 3: 'create web server on port 8080' is a web server expression, not supported in the JS target",
+    ));
+
+    let path = write_program("web", &["Start server"]);
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.args(["--transpile", "js"]).arg(&path);
+    cmd.assert().failure().stderr(predicates::str::contains(
+        "'Start server' is a web server statement, not supported in the JS target",
+    ));
+}
@@ -0,0 +1,75 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn run(lines: &[&str]) -> Command {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let path = std::env::temp_dir().join(format!("pohlang_tuple_test_{}.poh", ts));
+    let mut contents = String::from("Start Program\n");
+    for l in lines {
+        contents.push_str(l);
+        contents.push('\n');
+    }
+    contents.push_str("End Program\n");
+    fs::write(&path, contents).unwrap();
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd
+}
+
+#[test]
+fn tuple_literal_and_accessors() {
+    let mut cmd = run(&[
+        "Set t to tuple of 1, \"two\" and 3",
+        "Write t",
+        "Write count of t",
+        "Write first in t",
+        "Write last in t",
+        "Write t[1]",
+        "Write convert to json t",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout("(1, two, 3)\n3\n1\n3\ntwo\n[1,\"two\",3]\n");
+}
+
+#[test]
+fn destructure_tuple_and_list() {
+    let mut cmd = run(&[
+        "Make bounds with xs",
+        "    Return tuple of smallest in xs, largest in xs",
+        "End",
+        "Make swap with pair",
+        "    Set a, b to pair",
+        "    Return tuple of b, a",
+        "End",
+        "Set low, high to bounds(Make a list of 4, 9, 2)",
+        "Write low",
+        "Write high",
+        "Set [x, y] to Make a list of 5, 6",
+        "Write x plus y",
+        "Write swap(tuple of 1, 2)",
+    ]);
+    cmd.assert().success().stdout("2\n9\n11\n(2, 1)\n");
+}
+
+#[test]
+fn destructure_arity_mismatch_is_an_error() {
+    let mut cmd = run(&["Set a, b, c to tuple of 1, 2"]);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Cannot destructure a tuple of 2 items into 3 names",
+    ));
+}
+
+#[test]
+fn tuples_are_immutable() {
+    let mut cmd = run(&["Set t to tuple of 1, 2", "Write append 3 to t"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("tuples cannot be changed"));
+}
@@ -0,0 +1,66 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn run(lines: &[&str]) -> Command {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let path = std::env::temp_dir().join(format!("pohlang_url_test_{}.poh", ts));
+    let mut contents = String::from("Start Program\n");
+    for l in lines {
+        contents.push_str(l);
+        contents.push('\n');
+    }
+    contents.push_str("End Program\n");
+    fs::write(&path, contents).unwrap();
+    let mut cmd = Command::cargo_bin("pohlang").unwrap();
+    cmd.arg("--run").arg(path.to_str().unwrap());
+    cmd
+}
+
+#[test]
+fn parse_url_splits_a_url_into_a_dictionary() {
+    run(&[
+        "Set parts to parse url \"https://API.example.com:8443/hooks?id=7#top\"",
+        "Write parts",
+        "Write parse url \"http://example.com\"",
+    ])
+    .assert()
+    .success()
+    .stdout(
+        "{\"fragment\": top, \"host\": api.example.com, \"path\": /hooks, \"port\": 8443, \"query\": id=7, \"scheme\": https}\n\
+         {\"fragment\": None, \"host\": example.com, \"path\": /, \"port\": None, \"query\": None, \"scheme\": http}\n",
+    );
+}
+
+#[test]
+fn build_url_reverses_parse_url_inside_functions_too() {
+    run(&[
+        "Make rebuild with u Write build url from parse url u",
+        "Write rebuild(\"https://example.com:8443/a/b?x=1#frag\")",
+        "Set parts to Make a dictionary with \"scheme\" as \"http\", \"host\" as \"localhost\", \"port\" as 3000, \"path\" as \"api\", \"query\" as \"q=\" plus url encode \"a b&c\"",
+        "Write build url from parts",
+        "Write url decode \"a%20b%26c\"",
+    ])
+    .assert()
+    .success()
+    .stdout("https://example.com:8443/a/b?x=1#frag\nhttp://localhost:3000/api?q=a%20b%26c\na b&c\n");
+}
+
+#[test]
+fn invalid_urls_are_reported() {
+    run(&["Write parse url \"example.com/path\""])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "parse url: 'example.com/path' is not a valid URL: missing scheme",
+        ));
+    run(&["Write build url from Make a dictionary with \"host\" as \"example.com\""])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("build url from: the dictionary has no \"scheme\""));
+}
@@ -0,0 +1,1005 @@
+// Integration tests for route handlers served by a running PohLang program
+
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Kills the server process when the test finishes, even on panic
+struct ServerGuard(Child);
+
+impl Drop for ServerGuard {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn spawn_server(lines: &[&str], temp_dir: &TempDir) -> ServerGuard {
+    let script_path = temp_dir.path().join("server.poh");
+    let mut content = String::from("Start Program\n");
+    for line in lines {
+        content.push_str(line);
+        content.push('\n');
+    }
+    content.push_str("End Program\n");
+    fs::write(&script_path, content).unwrap();
+
+    let child = Command::cargo_bin("pohlang")
+        .unwrap()
+        .arg("--run")
+        .arg(&script_path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    ServerGuard(child)
+}
+
+fn get(port: u16, path: &str) -> String {
+    get_with_headers(port, path, "")
+}
+
+/// `headers` is sent as is, each header ending in CRLF
+fn get_with_headers(port: u16, path: &str, headers: &str) -> String {
+    for _ in 0..100 {
+        if let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)) {
+            write!(
+                stream,
+                "GET {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n",
+                path, headers
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            return response;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("server on port {} never came up", port);
+}
+
+#[test]
+fn handler_calls_function_defined_after_route() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48131",
+            "Add route \"/hello\" with method \"GET\" to server:",
+            "    Write html response with greet(\"Ada\")",
+            "Make greet with name Write \"Hello \" plus name",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let response = get(48131, "/hello");
+    assert!(response.contains("200"), "unexpected response: {}", response);
+    assert!(response.contains("Hello Ada"), "unexpected response: {}", response);
+}
+
+#[test]
+fn responses_from_inline_and_block_ifs_are_sent() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48133",
+            "Set ready to True",
+            "Add route \"/inline\" with method \"GET\" to server:",
+            "    If ready Write html response with \"inline ok\" Otherwise html response with \"no\"",
+            "Add route \"/block\" with method \"GET\" to server:",
+            "    If ready",
+            "        Write json response with Make a dictionary with \"block\" as \"ok\"",
+            "    End",
+            "    Write html response with \"fell through\"",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let response = get(48133, "/inline");
+    assert!(response.contains("inline ok"), "unexpected response: {}", response);
+    let response = get(48133, "/block");
+    assert!(response.contains("\"block\":\"ok\""), "unexpected response: {}", response);
+}
+
+#[test]
+fn handlers_read_request_details_and_set_response_headers() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48134",
+            "Add route \"/echo\" with method \"GET\" to server:",
+            "    Set response header \"X-Echo\" to get request header \"x-token\"",
+            "    Set missing to get request header \"X-Missing\"",
+            "    Set details to request method plus \" \" plus request path plus \" \" plus client address plus \" \" plus missing",
+            "    Write html response with details",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let response = get_with_headers(48134, "/echo?q=1", "X-Token: abc123\r\n");
+    assert!(response.contains("X-Echo: abc123"), "unexpected response: {}", response);
+    assert!(
+        response.contains("GET /echo 127.0.0.1 None"),
+        "unexpected response: {}",
+        response
+    );
+}
+
+#[test]
+fn burst_over_the_rate_limit_gets_429_with_retry_after() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48135",
+            "Limit to 3 requests per 60 seconds",
+            "Add route \"/\" with method \"GET\" to server:",
+            "    Write html response with \"ok\"",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let statuses: Vec<String> = (0..5)
+        .map(|_| get(48135, "/"))
+        .map(|response| response.lines().next().unwrap_or("").to_string())
+        .collect();
+    assert!(statuses[..3].iter().all(|s| s.contains("200")), "{:?}", statuses);
+    assert!(statuses[3..].iter().all(|s| s.contains("429")), "{:?}", statuses);
+
+    let response = get(48135, "/");
+    assert!(response.contains("Retry-After: "), "unexpected response: {}", response);
+    assert!(response.contains("Too Many Requests"), "unexpected response: {}", response);
+}
+
+#[test]
+fn rate_limit_grouped_by_a_function_counts_each_key_apart() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48136",
+            "Make api_key with request Write get request header \"X-Api-Key\"",
+            "Limit to 2 requests per 60 seconds grouped by api_key",
+            "Add route \"/\" with method \"GET\" to server:",
+            "    Write html response with \"ok\"",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let status = |key: &str| {
+        let response = get_with_headers(48136, "/", &format!("X-Api-Key: {}\r\n", key));
+        response.lines().next().unwrap_or("").to_string()
+    };
+    for _ in 0..2 {
+        assert!(status("alpha").contains("200"));
+    }
+    assert!(status("alpha").contains("429"));
+    // Another key has a window of its own
+    assert!(status("beta").contains("200"));
+}
+
+#[test]
+fn route_hooks_run_around_every_matched_handler() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48137",
+            "Add route \"/\" with method \"GET\" to server:",
+            "    Set greeting to \"hello \" plus user",
+            "    Write html response with greeting",
+            "Before each route:",
+            "    Set user to get request header \"X-User\"",
+            "End Before",
+            "Before each route:",
+            "    If user is None",
+            "        Write error response with status 401 and message \"who are you?\"",
+            "    End",
+            "End Before",
+            "After each route:",
+            "    Set response header \"X-Seen-By\" to \"after hook\"",
+            "    Set __response[\"body\"] to \"<main>\" plus __response[\"body\"] plus \"</main>\"",
+            "End After",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let response = get_with_headers(48137, "/", "X-User: ada\r\n");
+    assert!(response.contains("200"), "unexpected response: {}", response);
+    assert!(response.contains("<main>hello ada</main>"), "unexpected response: {}", response);
+    assert!(response.contains("X-Seen-By: after hook"), "unexpected response: {}", response);
+
+    // The second before hook answers instead of the handler, and the after
+    // hook still runs
+    let response = get(48137, "/");
+    assert!(response.contains("401"), "unexpected response: {}", response);
+    assert!(response.contains("who are you?"), "unexpected response: {}", response);
+    assert!(response.ends_with("</main>"), "unexpected response: {}", response);
+
+    // Unmatched paths do not run the hooks
+    let response = get(48137, "/missing");
+    assert!(response.contains("404"), "unexpected response: {}", response);
+    assert!(!response.contains("X-Seen-By"), "unexpected response: {}", response);
+}
+
+#[test]
+fn request_phrases_outside_a_handler_are_errors() {
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join("main.poh");
+    fs::write(&script_path, "Start Program\nWrite request method\nEnd Program\n").unwrap();
+    Command::cargo_bin("pohlang")
+        .unwrap()
+        .arg("--run")
+        .arg(&script_path)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("request method: no request context available"));
+}
+
+#[test]
+fn routes_are_checked_when_they_are_added() {
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join("main.poh");
+    // The server is never started, so no port is bound
+    fs::write(
+        &script_path,
+        "Start Program
+Set server to create web server on port 48138
+Add route \"/users/:id\" with method \"GET\" to server:
+    Set id to get path parameter \"userid\"
+    Write json response with get path parameter \"id\"
+Add route \"/users\" with method \"get\" to server:
+    Write html response with get path parameter \"id\"
+Add route \"/users\" with method \"POST\" to server:
+    Write html response with \"created\"
+Add route \"/users\" with method \"GET\" to server:
+    Write html response with \"again\"
+Write \"not reached\"
+End Program
+",
+    )
+    .unwrap();
+    Command::cargo_bin("pohlang")
+        .unwrap()
+        .arg("--run")
+        .arg(&script_path)
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains("not reached").not())
+        .stderr(predicates::str::contains(
+            "Warning: line 3: the handler for GET /users/:id asks for path parameter 'userid', but the path only has 'id'",
+        ))
+        .stderr(predicates::str::contains(
+            "Warning: line 6: the handler for GET /users asks for path parameter 'id', but the path has none",
+        ))
+        .stderr(predicates::str::contains(
+            "add route: GET /users on line 10 is already handled by the route on line 6",
+        ));
+}
+
+/// Send `request` as is once the server is up, and read what comes back
+/// until the server closes the connection
+fn send_raw(port: u16, request: &str) -> String {
+    get(port, "/up");
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn oversized_bodies_and_slow_requests_are_refused() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48139 with body limit 16 bytes with request timeout 1 second",
+            "Add route \"/up\" with method \"GET\" to server:",
+            "    Write html response with \"up\"",
+            "Add route \"/echo\" with method \"POST\" to server:",
+            "    Write html response with \"posted\"",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let response = send_raw(
+        48139,
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 17\r\nConnection: close\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large"), "unexpected response: {}", response);
+
+    let response = send_raw(
+        48139,
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 16\r\nConnection: close\r\n\r\n0123456789abcdef",
+    );
+    assert!(response.contains("posted"), "unexpected response: {}", response);
+
+    // The headers never end, so the request is given up on after a second
+    let started = std::time::Instant::now();
+    let response = send_raw(48139, "GET /up HTTP/1.1\r\nHost: local");
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout"), "unexpected response: {}", response);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn event_streams_send_events_as_the_handler_runs() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48140",
+            "Add route \"/up\" with method \"GET\" to server:",
+            "    Write html response with \"up\"",
+            "Add route \"/events\" with method \"GET\" to server:",
+            "    set response header \"X-Feed\" to \"prices\"",
+            "    Set stream to start event stream with heartbeat every 5 seconds",
+            "    Send event \"hello\" to stream",
+            "    Send event Make a dictionary with \"price\" as 42 to stream",
+            "    Close stream",
+            "    Send event \"never sent\" to stream",
+            "Add route \"/forever\" with method \"GET\" to server:",
+            "    Set stream to start event stream",
+            "    Set n to 0",
+            "    While True",
+            "        Increase n by 1",
+            "        Send event n to stream",
+            "    End",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let response = send_raw(48140, "GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "unexpected response: {}", response);
+    assert!(head.contains("Content-Type: text/event-stream"), "unexpected response: {}", response);
+    assert!(head.contains("X-Feed: prices"), "unexpected response: {}", response);
+    assert_eq!(body, "data: hello\n\ndata: {\"price\":42}\n\n");
+
+    // Hanging up ends the handler's loop; the server goes on serving
+    let mut stream = TcpStream::connect(("127.0.0.1", 48140)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream.write_all(b"GET /forever HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut received = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !String::from_utf8_lossy(&received).contains("data: 2\n\n") {
+        let n = stream.read(&mut buf).unwrap();
+        assert!(n > 0, "stream ended early: {}", String::from_utf8_lossy(&received));
+        received.extend_from_slice(&buf[..n]);
+    }
+    assert!(String::from_utf8_lossy(&received).contains("data: 1\n\n"));
+    drop(stream);
+    assert!(get(48140, "/up").contains("up"));
+}
+
+/// A GET that fails, rather than hanging the test, if no answer comes
+fn get_within_seconds(port: u16, path: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).expect("no answer from the server");
+    response
+}
+
+#[test]
+fn other_routes_answer_while_an_event_stream_is_open() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48150",
+            "Add route \"/up\" with method \"GET\" to server:",
+            "    Write html response with \"up\"",
+            "Add route \"/forever\" with method \"GET\" to server:",
+            "    Set stream to start event stream",
+            "    While True",
+            "        Send event \"tick\" to stream",
+            "    End",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    assert!(get(48150, "/up").contains("up"));
+    let mut stream = TcpStream::connect(("127.0.0.1", 48150)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream.write_all(b"GET /forever HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut received = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !String::from_utf8_lossy(&received).contains("data: tick\n\n") {
+        let n = stream.read(&mut buf).unwrap();
+        assert!(n > 0, "stream ended early: {}", String::from_utf8_lossy(&received));
+        received.extend_from_slice(&buf[..n]);
+    }
+    // The stream's handler is still running
+    assert!(get_within_seconds(48150, "/up").contains("up"));
+    assert!(stream.read(&mut buf).unwrap() > 0);
+}
+
+/// A frame as a client sends it, masked; `payload` is under 126 bytes
+fn client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(n, b)| b ^ mask[n % 4]));
+    frame
+}
+
+/// The opcode and payload of the next frame from the server
+fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head).unwrap();
+    let len = match head[1] {
+        126 => {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).unwrap();
+            u16::from_be_bytes(len) as usize
+        }
+        n => n as usize,
+    };
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).unwrap();
+    (head[0] & 0x0F, payload)
+}
+
+#[test]
+fn websocket_routes_upgrade_and_echo_messages() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48141",
+            "Add route \"/up\" with method \"GET\" to server:",
+            "    Write html response with \"up\"",
+            "Add websocket route \"/echo\" to server:",
+            "    Send \"welcome\" to socket",
+            "    Set message to receive message from socket with timeout 5 seconds",
+            "    While socket",
+            "        Send Make a dictionary with \"echo\" as message to socket",
+            "        Set message to receive message from socket with timeout 5 seconds",
+            "    End",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    // Without the upgrade headers, or for another path, it is plain HTTP
+    assert!(get(48141, "/echo").starts_with("HTTP/1.1 404"));
+    let upgrade = |stream: &mut TcpStream, path: &str| {
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            path
+        )
+        .unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        String::from_utf8(head).unwrap()
+    };
+    let mut stream = TcpStream::connect(("127.0.0.1", 48141)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let head = upgrade(&mut stream, "/nowhere");
+    assert!(head.starts_with("HTTP/1.1 404"), "unexpected response: {}", head);
+
+    let mut stream = TcpStream::connect(("127.0.0.1", 48141)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let head = upgrade(&mut stream, "/echo");
+    assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"), "unexpected response: {}", head);
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"), "unexpected response: {}", head);
+
+    assert_eq!(read_frame(&mut stream), (0x1, b"welcome".to_vec()));
+    stream.write_all(&client_frame(0x1, b"hello")).unwrap();
+    assert_eq!(read_frame(&mut stream), (0x1, br#"{"echo":"hello"}"#.to_vec()));
+    stream.write_all(&client_frame(0x9, b"still there?")).unwrap();
+    assert_eq!(read_frame(&mut stream), (0xA, b"still there?".to_vec()));
+    stream.write_all(&client_frame(0x8, &1000u16.to_be_bytes())).unwrap();
+    assert_eq!(read_frame(&mut stream), (0x8, 1000u16.to_be_bytes().to_vec()));
+    assert!(get(48141, "/up").contains("up"));
+}
+
+#[test]
+fn other_routes_answer_while_a_websocket_is_open() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48151",
+            "Add route \"/up\" with method \"GET\" to server:",
+            "    Write html response with \"up\"",
+            "Add websocket route \"/echo\" to server:",
+            "    Send \"welcome\" to socket",
+            "    Set message to receive message from socket with timeout 10 seconds",
+            "    While socket",
+            "        Send message to socket",
+            "        Set message to receive message from socket with timeout 10 seconds",
+            "    End",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    assert!(get(48151, "/up").contains("up"));
+    let mut stream = TcpStream::connect(("127.0.0.1", 48151)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream
+        .write_all(
+            b"GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .unwrap();
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    assert!(head.starts_with(b"HTTP/1.1 101"), "unexpected response: {}", String::from_utf8_lossy(&head));
+    assert_eq!(read_frame(&mut stream), (0x1, b"welcome".to_vec()));
+
+    // The socket's handler is waiting for a message, and the server goes on serving
+    assert!(get_within_seconds(48151, "/up").contains("up"));
+    stream.write_all(&client_frame(0x1, b"still here")).unwrap();
+    assert_eq!(read_frame(&mut stream), (0x1, b"still here".to_vec()));
+}
+
+#[cfg(unix)]
+#[test]
+fn sigterm_runs_shutdown_hooks_and_exits_cleanly() {
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join("server.poh");
+    fs::write(
+        &script_path,
+        "Start Program\n\
+         Set server to create web server on port 48132\n\
+         Add route \"/ping\" with method \"GET\" to server:\n\
+         \x20   Write html response with \"pong\"\n\
+         On shutdown:\n\
+         \x20   Write \"cleaning up\"\n\
+         End On Shutdown\n\
+         Start server\n\
+         End Program\n",
+    )
+    .unwrap();
+    let child = Command::cargo_bin("pohlang")
+        .unwrap()
+        .arg("--run")
+        .arg(&script_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut server = ServerGuard(child);
+
+    assert!(get(48132, "/ping").contains("pong"));
+    let status = Command::new("kill")
+        .arg("-TERM")
+        .arg(server.0.id().to_string())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let status = server.0.wait().unwrap();
+    assert_eq!(status.code(), Some(0));
+    let mut stdout = String::new();
+    server.0.stdout.take().unwrap().read_to_string(&mut stdout).unwrap();
+    assert!(stdout.contains("Shutting down"), "unexpected stdout: {}", stdout);
+    assert!(stdout.ends_with("cleaning up\n"), "unexpected stdout: {}", stdout);
+}
+
+#[test]
+fn route_groups_prefix_their_routes_and_scope_their_middleware() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48142",
+            "Add route \"/health\" with method \"GET\" to server:",
+            "    Write html response with \"ok\"",
+            "Add route group \"/api/v1\" to server:",
+            "    Add middleware to group:",
+            "        If get request header \"Authorization\" is not \"Bearer secret\"",
+            "            Write error response with status 401 and message \"sign in first\"",
+            "        End",
+            "    End middleware",
+            "    Add route \"/users\" with method \"GET\" to server:",
+            "        Write json response with Make a list of \"ada\", \"alan\"",
+            "    Add route group \"/admin\" to server:",
+            "        Add route \"/stats\" with method \"GET\" to server:",
+            "            Write html response with \"stats\"",
+            "    End group",
+            "    Add route \"/posts\" with method \"GET\" to server:",
+            "        Write html response with \"posts\"",
+            "End group",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let auth = "Authorization: Bearer secret\r\n";
+    let response = get_with_headers(48142, "/api/v1/users", auth);
+    assert!(response.contains("200"), "unexpected response: {}", response);
+    assert!(response.contains("[\"ada\",\"alan\"]"), "unexpected response: {}", response);
+    let response = get_with_headers(48142, "/api/v1/posts", auth);
+    assert!(response.ends_with("posts"), "unexpected response: {}", response);
+    // Nested groups add their prefix and keep the outer group's middleware
+    let response = get_with_headers(48142, "/api/v1/admin/stats", auth);
+    assert!(response.ends_with("stats"), "unexpected response: {}", response);
+    let response = get(48142, "/api/v1/admin/stats");
+    assert!(response.contains("401"), "unexpected response: {}", response);
+
+    let response = get(48142, "/api/v1/users");
+    assert!(response.contains("401"), "unexpected response: {}", response);
+    assert!(response.contains("sign in first"), "unexpected response: {}", response);
+    assert!(get(48142, "/users").contains("404"));
+
+    // The group's middleware does not run for routes outside it
+    let response = get(48142, "/health");
+    assert!(response.contains("200"), "unexpected response: {}", response);
+    assert!(response.ends_with("ok"), "unexpected response: {}", response);
+}
+
+#[test]
+fn metrics_endpoint_counts_the_requests_served() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48143",
+            "Enable metrics on server at \"/metrics\"",
+            "Add route \"/hello\" with method \"GET\" to server:",
+            "    Set greeting to \"hi\"",
+            "    Write html response with greeting",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    assert!(get(48143, "/hello").ends_with("hi"));
+    assert!(get(48143, "/hello").ends_with("hi"));
+    assert!(get(48143, "/missing").contains("404"));
+
+    let response = get(48143, "/metrics");
+    let body = response.split("\r\n\r\n").nth(1).unwrap_or_default();
+    let metrics: serde_json::Value = serde_json::from_str(body).expect(&response);
+    assert_eq!(metrics["requests_total"], 3, "{}", body);
+    assert_eq!(metrics["requests_by_status"]["2xx"], 2, "{}", body);
+    assert_eq!(metrics["requests_by_status"]["4xx"], 1, "{}", body);
+    assert_eq!(metrics["active_connections"], 1, "{}", body);
+    assert!(metrics["execution"]["statements_executed"].as_u64().unwrap() >= 4, "{}", body);
+
+    // Prometheus asks for text; the JSON request above is counted by then
+    let response = get_with_headers(48143, "/metrics", "Accept: text/plain\r\n");
+    assert!(response.contains("text/plain; version=0.0.4"), "{}", response);
+    assert!(response.contains("pohlang_requests_total{class=\"2xx\"} 3\n"), "{}", response);
+    assert!(response.contains("pohlang_request_duration_seconds_count 4\n"), "{}", response);
+}
+
+#[test]
+fn route_blocks_are_served_like_added_routes() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48144",
+            "Set api to server",
+            "Route GET \"/up\":",
+            "    Write html response with \"up\"",
+            "End Route",
+            "Route get \"/users/:id\":",
+            "    Set id to get path parameter \"id\"",
+            "    If id is \"0\"",
+            "        Write error response with status 404 and message \"no such user\"",
+            "    End",
+            // The body ends at End Route, however it is indented
+            "Write json response with id",
+            "End Route",
+            "Route POST \"/users\" on server api:",
+            "    Write html response with \"created\"",
+            "End Route",
+            "Add route group \"/v2\" to server:",
+            "    Route GET \"/up\":",
+            "        Write html response with \"v2 up\"",
+            "    End Route",
+            "End group",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    assert!(get(48144, "/up").ends_with("up"));
+    let response = get(48144, "/users/7");
+    assert!(response.ends_with("\"7\""), "unexpected response: {}", response);
+    assert!(get(48144, "/users/0").contains("404"));
+    let response = send_raw(
+        48144,
+        "POST /users HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nConnection: close\r\n\r\nada",
+    );
+    assert!(response.ends_with("created"), "unexpected response: {}", response);
+    assert!(get(48144, "/v2/up").ends_with("v2 up"));
+}
+
+#[test]
+fn respond_with_answers_in_the_type_the_client_accepts() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48145",
+            "Add route \"/up\" with method \"GET\" to server:",
+            "    Write html response with preferred response type of request",
+            "Add route \"/users\" with method \"GET\" to server:",
+            "    Respond with Make a list of \"ada\", \"alan\"",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let response = get_with_headers(48145, "/users", "Accept: application/json\r\n");
+    assert!(response.contains("Content-Type: application/json"), "unexpected response: {}", response);
+    assert!(response.contains("Vary: Accept"), "unexpected response: {}", response);
+    assert!(response.ends_with("[\"ada\",\"alan\"]"), "unexpected response: {}", response);
+    let response = get_with_headers(48145, "/users", "Accept: text/plain;q=0.9, text/html;q=0.5\r\n");
+    assert!(response.contains("Content-Type: text/plain"), "unexpected response: {}", response);
+    let response = get(48145, "/users");
+    assert!(response.contains("Content-Type: text/html"), "unexpected response: {}", response);
+
+    assert!(get(48145, "/up").ends_with("html"));
+    assert!(get_with_headers(48145, "/up", "accept: */*;q=0.1, application/json\r\n").ends_with("json"));
+}
+
+/// The ETag header of a raw response
+fn etag_of(response: &str) -> String {
+    response
+        .lines()
+        .find_map(|line| line.strip_prefix("ETag: "))
+        .unwrap_or_else(|| panic!("no ETag in: {}", response))
+        .to_string()
+}
+
+#[test]
+fn static_files_and_responses_answer_304_while_unchanged() {
+    let temp_dir = TempDir::new().unwrap();
+    let public = temp_dir.path().join("public");
+    fs::create_dir(&public).unwrap();
+    fs::write(public.join("app.css"), "body { color: red }").unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48146",
+            "Serve static files from \"public\"",
+            "Add route \"/up\" with method \"GET\" to server:",
+            "    Write html response with \"ok\" with etag with cache for 60 seconds",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let response = get(48146, "/app.css");
+    assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+    assert!(response.ends_with("body { color: red }"), "unexpected response: {}", response);
+    let etag = etag_of(&response);
+    assert!(etag.starts_with("W/\""), "unexpected ETag: {}", etag);
+
+    let conditional = format!("If-None-Match: \"other\", {}\r\n", etag);
+    let response = get_with_headers(48146, "/app.css", &conditional);
+    assert!(response.starts_with("HTTP/1.1 304"), "unexpected response: {}", response);
+    assert!(response.ends_with("\r\n\r\n"), "a 304 has no body: {}", response);
+    assert_eq!(etag_of(&response), etag);
+    let response = get_with_headers(48146, "/app.css", "If-None-Match: *\r\n");
+    assert!(response.starts_with("HTTP/1.1 304"), "unexpected response: {}", response);
+
+    fs::write(public.join("app.css"), "body { color: blue }").unwrap();
+    let response = get_with_headers(48146, "/app.css", &conditional);
+    assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+    assert_ne!(etag_of(&response), etag);
+
+    let response = get(48146, "/up");
+    assert!(response.contains("Cache-Control: max-age=60"), "unexpected response: {}", response);
+    let conditional = format!("If-None-Match: {}\r\n", etag_of(&response));
+    assert!(get_with_headers(48146, "/up", &conditional).starts_with("HTTP/1.1 304"));
+    assert!(get(48146, "/missing.css").starts_with("HTTP/1.1 404"));
+}
+
+#[test]
+fn a_port_in_use_is_a_network_error_the_program_can_catch() {
+    let _holder = std::net::TcpListener::bind(("0.0.0.0", 48147)).unwrap();
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join("server.poh");
+    fs::write(
+        &script_path,
+        "Start Program\n\
+         try this:\n    Set server to create web server on port 48147\n    Start server\n\
+         if error of type \"NetworkError\" as e\n    Write e\nend try\n\
+         Set server to create web server on port 48147\n\
+         Start server\n\
+         End Program\n",
+    )
+    .unwrap();
+    Command::cargo_bin("pohlang")
+        .unwrap()
+        .arg("--run")
+        .arg(&script_path)
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "a network error - port 48147 is already in use — choose a different port or stop the other process",
+        ))
+        .stderr(predicate::str::contains("[NetworkError]"));
+}
+
+#[test]
+fn port_0_listens_on_a_free_port_and_says_which() {
+    use std::io::BufRead;
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join("server.poh");
+    fs::write(
+        &script_path,
+        "Start Program\n\
+         Set server to create web server on port 0\n\
+         Add route \"/up\" with method \"GET\" to server:\n    Write html response with \"up\"\n\
+         Start server\n\
+         End Program\n",
+    )
+    .unwrap();
+    let mut server = ServerGuard(
+        Command::cargo_bin("pohlang")
+            .unwrap()
+            .arg("--run")
+            .arg(&script_path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let stdout = std::io::BufReader::new(server.0.stdout.take().unwrap());
+    let port: u16 = stdout
+        .lines()
+        .map(Result::unwrap)
+        .find_map(|line| line.split_once("listening on http://0.0.0.0:").map(|(_, port)| port.parse().unwrap()))
+        .expect("the server says where it listens");
+    assert_ne!(port, 0);
+    assert!(get(port, "/up").ends_with("up"));
+}
+
+#[test]
+fn handlers_answer_with_return_or_write_and_see_request() {
+    let temp_dir = TempDir::new().unwrap();
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48148",
+            "Add route \"/returned/:id\" with method \"GET\" to server:",
+            "    If contains \"loud\" in request[\"query\"]",
+            "        Set loud to \"LOUD \" plus request[\"params\"][\"id\"]",
+            "        Return html response with loud",
+            "    End",
+            "    Return json response with Make a dictionary with \"method\" as request[\"method\"] and \"path\" as request[\"path\"]",
+            "    Write html response with \"not reached\"",
+            "Add route \"/written\" with method \"GET\" to server:",
+            "    Write html response with \"written\"",
+            "Add route \"/silent\" with method \"GET\" to server:",
+            "    Set x to 1",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let response = get(48148, "/returned/7");
+    assert!(response.contains("{\"method\":\"GET\",\"path\":\"/returned/7\"}"), "unexpected response: {}", response);
+    let response = get(48148, "/returned/7?loud=yes");
+    assert!(response.contains("LOUD 7"), "unexpected response: {}", response);
+    let response = get(48148, "/written");
+    assert!(response.contains("written"), "unexpected response: {}", response);
+    let response = get(48148, "/silent");
+    assert!(response.contains("No response returned"), "unexpected response: {}", response);
+}
+
+#[test]
+fn handlers_respond_before_their_background_work_finishes() {
+    let temp_dir = TempDir::new().unwrap();
+    let done = temp_dir.path().join("webhook.txt");
+    let write_done = format!("        Set sent to write \"sent \" plus id into file at \"{}\"", done.display());
+    let _server = spawn_server(
+        &[
+            "Set server to create web server on port 48149",
+            "Add route \"/order/:id\" with method \"GET\" to server:",
+            "    Set id to request[\"params\"][\"id\"]",
+            "    Run in background:",
+            "        Use sleep with 1",
+            &write_done,
+            "    End",
+            "    Set reply to \"accepted \" plus id",
+            "    Write html response with reply",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let response = get(48149, "/order/7");
+    assert!(response.contains("accepted 7"), "unexpected response: {}", response);
+    assert!(!done.exists(), "the handler waited for its background work");
+    for _ in 0..100 {
+        if let Ok(text) = fs::read_to_string(&done) {
+            assert_eq!(text, "sent 7");
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("the background work never finished");
+}
+
+/// A file of the self-signed certificate for `localhost` the TLS tests use
+#[cfg(feature = "tls")]
+fn tls_fixture(name: &str) -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls").join(name)
+}
+
+/// A GET over TLS from a client that trusts only the test certificate
+#[cfg(feature = "tls")]
+fn get_over_tls(port: u16, path: &str) -> String {
+    use rustls::pki_types::{pem::PemObject, CertificateDer};
+    use std::sync::Arc;
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(CertificateDer::from_pem_file(tls_fixture("cert.pem")).unwrap()).unwrap();
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    for _ in 0..100 {
+        if let Ok(tcp) = TcpStream::connect(("127.0.0.1", port)) {
+            tcp.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            let session = rustls::ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+            let mut stream = rustls::StreamOwned::new(session, tcp);
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            return response;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("server on port {} never came up", port);
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn secure_servers_answer_over_tls() {
+    let temp_dir = TempDir::new().unwrap();
+    for name in ["cert.pem", "key.pem"] {
+        fs::copy(tls_fixture(name), temp_dir.path().join(name)).unwrap();
+    }
+    let _server = spawn_server(
+        &[
+            "Set server to create secure web server on port 48152 with certificate \"cert.pem\" and key \"key.pem\"",
+            "Add route \"/hello\" with method \"GET\" to server:",
+            "    Write html response with \"hello over tls\"",
+            "Add route \"/events\" with method \"GET\" to server:",
+            "    Set stream to start event stream",
+            "    Send event \"one\" to stream",
+            "    Send event \"two\" to stream",
+            "Start server",
+        ],
+        &temp_dir,
+    );
+
+    let response = get_over_tls(48152, "/hello");
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "unexpected response: {}", response);
+    assert!(response.contains("hello over tls"), "unexpected response: {}", response);
+    let response = get_over_tls(48152, "/events");
+    assert!(response.contains("Content-Type: text/event-stream"), "unexpected response: {}", response);
+    assert!(response.ends_with("data: one\n\ndata: two\n\n"), "unexpected response: {}", response);
+
+    // Plain HTTP fails the handshake and is never answered
+    let mut stream = TcpStream::connect(("127.0.0.1", 48152)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream.write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut received = Vec::new();
+    let _ = stream.read_to_end(&mut received);
+    assert!(!String::from_utf8_lossy(&received).contains("hello over tls"));
+}